@@ -0,0 +1,100 @@
+//! Packs the `initramfs/` directory into an LZ4-compressed ustar archive embedded by
+//! `fs::initramfs`, along with its SHA-256 for the kernel to check at boot.
+
+#![feature(try_reserve)]
+
+extern crate alloc;
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+// the kernel's own, so both sides agree
+#[allow(dead_code)]
+#[path = "src/compress/lz4.rs"]
+mod lz4;
+#[allow(dead_code)]
+#[path = "src/crypto/sha256.rs"]
+mod sha256;
+
+const BLOCK: usize = 512;
+
+fn main() -> io::Result<()> {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("initramfs");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    println!("cargo:rerun-if-changed={}", root.display());
+    // embedded by boot::cmdline
+    println!("cargo:rerun-if-env-changed=KERNEL_CMDLINE");
+
+    let mut archive = Vec::new();
+    if root.is_dir() {
+        pack_dir(&root, &root, &mut archive)?;
+    }
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    let compressed = lz4::compress_frame(&archive);
+    fs::write(
+        out_dir.join("initramfs.sha256"),
+        sha256::digest(&compressed),
+    )?;
+    fs::File::create(out_dir.join("initramfs.tar.lz4"))?.write_all(&compressed)
+}
+
+fn pack_dir(root: &Path, dir: &Path, archive: &mut Vec<u8>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.path());
+
+    for entry in entries {
+        let path = entry.path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        let name = path
+            .strip_prefix(root)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            archive.extend_from_slice(&header(&format!("{}/", name), 0, b'5', ""));
+            pack_dir(root, &path, archive)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&path)?;
+            archive.extend_from_slice(&header(&name, 0, b'2', &target.to_string_lossy()));
+        } else {
+            let data = fs::read(&path)?;
+            archive.extend_from_slice(&header(&name, data.len(), b'0', ""));
+            archive.extend_from_slice(&data);
+            let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+            archive.resize(archive.len() + padding, 0);
+        }
+    }
+    Ok(())
+}
+
+fn header(name: &str, size: usize, typeflag: u8, link: &str) -> [u8; BLOCK] {
+    assert!(name.len() <= 100, "initramfs path too long: {}", name);
+    assert!(link.len() <= 100, "initramfs link target too long: {}", link);
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    let mode = if typeflag == b'5' {
+        "0000755"
+    } else {
+        "0000644"
+    };
+    header[100..107].copy_from_slice(mode.as_bytes());
+    header[108..115].copy_from_slice(b"0000000");
+    header[116..123].copy_from_slice(b"0000000");
+    header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+    header[136..147].copy_from_slice(b"00000000000");
+    header[156] = typeflag;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    header
+}
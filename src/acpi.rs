@@ -0,0 +1,270 @@
+//! ACPI table discovery. The bootloader does not pass the RSDP, so it is
+//! found the BIOS way, by scanning the EBDA and the read-only BIOS area for
+//! its signature. Tables are read in place through the physical memory
+//! mapping and parsed on demand by the submodules.
+
+use crate::{
+    memory,
+    shell::{self, ShellErr},
+    sync::Once,
+};
+use core::{fmt::Write, slice, str};
+use x86_64::PhysAddr;
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+pub mod sleep;
+
+pub use self::{fadt::Fadt, hpet::Hpet, madt::Madt, mcfg::Mcfg};
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Real-mode segment of the EBDA, stored in the BIOS data area.
+const EBDA_POINTER: u64 = 0x40e;
+const EBDA_SCAN_LEN: u64 = 1024;
+const BIOS_AREA: (u64, u64) = (0xe_0000, 0x10_0000);
+pub(crate) const HEADER_LEN: usize = 36;
+
+static ROOT: Once<Root> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Physical memory is not mapped yet.
+    NotReady,
+    NoRsdp,
+    BadChecksum([u8; 4]),
+    NotFound([u8; 4]),
+    /// Shorter than its fixed fields.
+    Truncated([u8; 4]),
+}
+
+/// The RSDT or XSDT everything else hangs off.
+#[derive(Debug, Clone, Copy)]
+struct Root {
+    rsdp: PhysAddr,
+    revision: u8,
+    table: PhysAddr,
+    /// Entries are 64-bit, as in the XSDT.
+    wide: bool,
+}
+
+/// Where an ACPI register lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    /// 0 system memory, 1 system I/O, 2 PCI configuration space.
+    pub space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+
+    pub(crate) fn parse(table: &[u8], offset: usize) -> Self {
+        GenericAddress {
+            space: read_u8(table, offset),
+            bit_width: read_u8(table, offset + 1),
+            bit_offset: read_u8(table, offset + 2),
+            access_size: read_u8(table, offset + 3),
+            address: read_u64(table, offset + 4),
+        }
+    }
+}
+
+/// Finds the RSDP and checks the root table. Needs the physical memory
+/// mapping, so run after `memory::install`.
+pub fn init() -> Result<(), Error> {
+    memory::phys_to_virt(PhysAddr::zero()).map_err(|_| Error::NotReady)?;
+    let rsdp = find_rsdp().ok_or(Error::NoRsdp)?;
+    let bytes = unsafe { phys_slice(rsdp, 36) };
+    let revision = bytes[15];
+    let root = if revision >= 2 && read_u64(bytes, 24) != 0 {
+        Root {
+            rsdp,
+            revision,
+            table: PhysAddr::new(read_u64(bytes, 24)),
+            wide: true,
+        }
+    } else {
+        Root {
+            rsdp,
+            revision,
+            table: PhysAddr::new(u64::from(read_u32(bytes, 16))),
+            wide: false,
+        }
+    };
+    let table = unsafe { map_table(root.table) };
+    check(table)?;
+    ROOT.call_once(|| root);
+    shell::register(
+        "acpi",
+        "acpi\nLists the ACPI tables the firmware provides.",
+        command,
+    );
+    info!(
+        "acpi: rsdp at {:#x}, revision {}, {} tables",
+        rsdp.as_u64(),
+        revision,
+        tables().count()
+    );
+    Ok(())
+}
+
+fn find_rsdp() -> Option<PhysAddr> {
+    let ebda_segment = unsafe { phys_slice(PhysAddr::new(EBDA_POINTER), 2) };
+    let ebda = u64::from(read_u16(ebda_segment, 0)) << 4;
+    let mut areas = [(ebda, ebda + EBDA_SCAN_LEN), BIOS_AREA];
+    if ebda == 0 {
+        areas[0] = (0, 0);
+    }
+    areas.iter().find_map(|&(start, end)| {
+        (start..end).step_by(16).map(PhysAddr::new).find(|&addr| {
+            let bytes = unsafe { phys_slice(addr, 20) };
+            &bytes[..8] == RSDP_SIGNATURE && checksum(bytes) == 0
+        })
+    })
+}
+
+/// Physical addresses of every table the root lists.
+pub fn tables() -> impl Iterator<Item = PhysAddr> {
+    let root = ROOT.get().copied();
+    let (table, width) = match root {
+        Some(root) => (
+            unsafe { map_table(root.table) },
+            if root.wide { 8 } else { 4 },
+        ),
+        None => (&[][..], 4),
+    };
+    let entries = table.get(HEADER_LEN..).unwrap_or(&[]);
+    entries.chunks_exact(width).map(move |entry| {
+        PhysAddr::new(match width {
+            8 => read_u64(entry, 0),
+            _ => u64::from(read_u32(entry, 0)),
+        })
+    })
+}
+
+/// The first table with `signature`, checksum verified, header included.
+pub fn find(signature: &[u8; 4]) -> Result<&'static [u8], Error> {
+    let table = tables()
+        .map(|addr| unsafe { map_table(addr) })
+        .find(|table| &table[..4] == signature)
+        .ok_or(Error::NotFound(*signature))?;
+    check(table)?;
+    Ok(table)
+}
+
+/// The table at `addr`, e.g. the DSDT the FADT points to.
+///
+/// # Safety
+/// `addr` must hold an ACPI table.
+pub unsafe fn table_at(addr: PhysAddr) -> Result<&'static [u8], Error> {
+    let table = map_table(addr);
+    check(table)?;
+    Ok(table)
+}
+
+/// Parsed MADT, for the APIC code.
+pub fn madt() -> Result<Madt, Error> {
+    Madt::parse(find(b"APIC")?)
+}
+
+pub fn hpet() -> Result<Hpet, Error> {
+    Hpet::parse(find(b"HPET")?)
+}
+
+pub fn mcfg() -> Result<Mcfg, Error> {
+    Mcfg::parse(find(b"MCFG")?)
+}
+
+pub fn fadt() -> Result<Fadt, Error> {
+    Fadt::parse(find(b"FACP")?)
+}
+
+fn signature(table: &[u8]) -> [u8; 4] {
+    let mut signature = [0; 4];
+    signature.copy_from_slice(&table[..4]);
+    signature
+}
+
+fn check(table: &[u8]) -> Result<(), Error> {
+    if checksum(table) == 0 {
+        Ok(())
+    } else {
+        Err(Error::BadChecksum(signature(table)))
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum: u8, &b| sum.wrapping_add(b))
+}
+
+/// Errors with `Truncated` unless `table` reaches `len` bytes.
+pub(crate) fn require(table: &[u8], len: usize) -> Result<(), Error> {
+    if table.len() >= len {
+        Ok(())
+    } else {
+        Err(Error::Truncated(signature(table)))
+    }
+}
+
+/// A whole table, sized by the length in its header.
+unsafe fn map_table(addr: PhysAddr) -> &'static [u8] {
+    let header = phys_slice(addr, HEADER_LEN);
+    let len = (read_u32(header, 4) as usize).max(HEADER_LEN);
+    phys_slice(addr, len)
+}
+
+/// Everything here runs after `init`, which checked for the mapping.
+unsafe fn phys_slice(addr: PhysAddr, len: usize) -> &'static [u8] {
+    let start = memory::phys_to_virt(addr).expect("ACPI read before the physical mapping");
+    slice::from_raw_parts(start.as_ptr(), len)
+}
+
+// Fields past the end read as zero, which is what older, shorter
+// revisions of a table mean by leaving them out.
+
+pub(crate) fn read_u8(table: &[u8], offset: usize) -> u8 {
+    table.get(offset).copied().unwrap_or(0)
+}
+
+pub(crate) fn read_u16(table: &[u8], offset: usize) -> u16 {
+    u16::from(read_u8(table, offset)) | u16::from(read_u8(table, offset + 1)) << 8
+}
+
+pub(crate) fn read_u32(table: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(table, offset)) | u32::from(read_u16(table, offset + 2)) << 16
+}
+
+pub(crate) fn read_u64(table: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(table, offset)) | u64::from(read_u32(table, offset + 4)) << 32
+}
+
+/// `acpi`
+fn command(_args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let root = ROOT.get().ok_or_else(|| ShellErr::new("no ACPI tables"))?;
+    writeln!(
+        out,
+        "rsdp {:#x} revision {}, {} {:#x}",
+        root.rsdp.as_u64(),
+        root.revision,
+        if root.wide { "xsdt" } else { "rsdt" },
+        root.table.as_u64()
+    )?;
+    for addr in tables() {
+        let table = unsafe { map_table(addr) };
+        writeln!(
+            out,
+            "{} {:#010x} {:>6} bytes  {}",
+            str::from_utf8(&table[..4]).unwrap_or("????"),
+            addr.as_u64(),
+            table.len(),
+            str::from_utf8(&table[10..16]).unwrap_or("").trim_end()
+        )?;
+    }
+    Ok(())
+}
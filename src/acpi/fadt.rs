@@ -0,0 +1,68 @@
+//! The FADT: fixed power management registers, the DSDT and how to reset.
+
+use super::{read_u16, read_u32, read_u64, read_u8, require, Error, GenericAddress};
+use x86_64::PhysAddr;
+
+/// Everything up to the flags, present since ACPI 1.0.
+const V1_LEN: usize = 116;
+const RESET_REG_SUPPORTED: u32 = 1 << 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub revision: u8,
+    pub dsdt: PhysAddr,
+    pub sci_interrupt: u16,
+    /// Port to write `acpi_enable` to, 0 if the firmware is always in ACPI mode.
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event: u32,
+    pub pm1b_event: u32,
+    pub pm1a_control: u32,
+    pub pm1b_control: u32,
+    pub pm_timer: u32,
+    pub pm1_event_len: u8,
+    pub pm1_control_len: u8,
+    /// RTC century register, 0 if absent.
+    pub century: u8,
+    pub boot_flags: u16,
+    pub flags: u32,
+    /// `reset_value` written here resets the machine, if `flags` says so.
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    pub(super) fn parse(table: &[u8]) -> Result<Self, Error> {
+        require(table, V1_LEN)?;
+        let flags = read_u32(table, 112);
+        let x_dsdt = read_u64(table, 140);
+        let dsdt = if x_dsdt != 0 {
+            x_dsdt
+        } else {
+            u64::from(read_u32(table, 40))
+        };
+        let reset_register = Some(GenericAddress::parse(table, 116))
+            .filter(|reg| flags & RESET_REG_SUPPORTED != 0 && reg.address != 0);
+        Ok(Fadt {
+            revision: read_u8(table, 8),
+            dsdt: PhysAddr::new(dsdt),
+            sci_interrupt: read_u16(table, 46),
+            smi_command: read_u32(table, 48),
+            acpi_enable: read_u8(table, 52),
+            acpi_disable: read_u8(table, 53),
+            pm1a_event: read_u32(table, 56),
+            pm1b_event: read_u32(table, 60),
+            pm1a_control: read_u32(table, 64),
+            pm1b_control: read_u32(table, 68),
+            pm_timer: read_u32(table, 76),
+            pm1_event_len: read_u8(table, 88),
+            pm1_control_len: read_u8(table, 89),
+            century: read_u8(table, 108),
+            boot_flags: read_u16(table, 109),
+            flags,
+            reset_register,
+            reset_value: read_u8(table, 128),
+        })
+    }
+}
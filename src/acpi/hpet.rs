@@ -0,0 +1,32 @@
+//! The HPET description: where its registers are and what it offers.
+
+use super::{read_u16, read_u32, read_u8, require, Error, GenericAddress, HEADER_LEN};
+use x86_64::PhysAddr;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub address: PhysAddr,
+    pub number: u8,
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    pub pci_vendor: u16,
+    /// Smallest periodic tick, in main counter clocks, that does not lose
+    /// interrupts.
+    pub min_tick: u16,
+}
+
+impl Hpet {
+    pub(super) fn parse(table: &[u8]) -> Result<Self, Error> {
+        require(table, HEADER_LEN + 20)?;
+        let id = read_u32(table, HEADER_LEN);
+        let base = GenericAddress::parse(table, HEADER_LEN + 4);
+        Ok(Hpet {
+            address: PhysAddr::new(base.address),
+            number: read_u8(table, HEADER_LEN + 16),
+            comparators: ((id >> 8) & 0x1f) as u8 + 1,
+            counter_64bit: id & (1 << 13) != 0,
+            pci_vendor: (id >> 16) as u16,
+            min_tick: read_u16(table, HEADER_LEN + 17),
+        })
+    }
+}
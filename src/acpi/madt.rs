@@ -0,0 +1,114 @@
+//! The MADT: local APICs, I/O APICs and how ISA IRQs map onto them.
+
+use super::{read_u16, read_u32, read_u64, read_u8, require, Error, HEADER_LEN};
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+const LOCAL_APIC: u8 = 0;
+const IO_APIC: u8 = 1;
+const INTERRUPT_OVERRIDE: u8 = 2;
+const LOCAL_APIC_NMI: u8 = 4;
+const LOCAL_APIC_ADDRESS: u8 = 5;
+const ENABLED: u32 = 1 << 0;
+const PCAT_COMPAT: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApic {
+    pub processor: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// First global system interrupt it serves.
+    pub gsi_base: u32,
+}
+
+/// ISA `source` IRQ arrives as `gsi` instead.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    /// MPS INTI flags: polarity in bits 0-1, trigger mode in bits 2-3.
+    pub flags: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApicNmi {
+    /// 0xff for every processor.
+    pub processor: u8,
+    pub flags: u16,
+    pub lint: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    /// Dual 8259 PICs are present and must be masked to use the APICs.
+    pub legacy_pics: bool,
+    pub local_apics: Vec<LocalApic>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    pub(super) fn parse(table: &[u8]) -> Result<Self, Error> {
+        require(table, HEADER_LEN + 8)?;
+        let mut madt = Madt {
+            local_apic_address: PhysAddr::new(u64::from(read_u32(table, HEADER_LEN))),
+            legacy_pics: read_u32(table, HEADER_LEN + 4) & PCAT_COMPAT != 0,
+            local_apics: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmis: Vec::new(),
+        };
+        let mut offset = HEADER_LEN + 8;
+        while offset + 2 <= table.len() {
+            let (kind, len) = (table[offset], table[offset + 1] as usize);
+            if len < 2 || offset + len > table.len() {
+                break;
+            }
+            let entry = &table[offset..offset + len];
+            match kind {
+                LOCAL_APIC => madt.local_apics.push(LocalApic {
+                    processor: read_u8(entry, 2),
+                    apic_id: read_u8(entry, 3),
+                    enabled: read_u32(entry, 4) & ENABLED != 0,
+                }),
+                IO_APIC => madt.io_apics.push(IoApic {
+                    id: read_u8(entry, 2),
+                    address: PhysAddr::new(u64::from(read_u32(entry, 4))),
+                    gsi_base: read_u32(entry, 8),
+                }),
+                INTERRUPT_OVERRIDE => madt.overrides.push(InterruptOverride {
+                    source: read_u8(entry, 3),
+                    gsi: read_u32(entry, 4),
+                    flags: read_u16(entry, 8),
+                }),
+                LOCAL_APIC_NMI => madt.nmis.push(LocalApicNmi {
+                    processor: read_u8(entry, 2),
+                    flags: read_u16(entry, 3),
+                    lint: read_u8(entry, 5),
+                }),
+                LOCAL_APIC_ADDRESS => {
+                    madt.local_apic_address = PhysAddr::new(read_u64(entry, 4));
+                }
+                _ => {}
+            }
+            offset += len;
+        }
+        Ok(madt)
+    }
+
+    /// The GSI ISA `irq` arrives on, after overrides.
+    pub fn isa_gsi(&self, irq: u8) -> u32 {
+        self.overrides
+            .iter()
+            .find(|o| o.source == irq)
+            .map_or(u32::from(irq), |o| o.gsi)
+    }
+}
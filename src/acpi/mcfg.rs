@@ -0,0 +1,51 @@
+//! The MCFG: where PCIe configuration space is memory-mapped (ECAM).
+
+use super::{read_u16, read_u64, read_u8, require, Error, HEADER_LEN};
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+const ENTRIES: usize = HEADER_LEN + 8;
+const ENTRY_LEN: usize = 16;
+
+/// One segment group's ECAM window.
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    pub base: PhysAddr,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl McfgEntry {
+    /// Configuration space of one function, if its bus is in this window.
+    pub fn function_address(&self, bus: u8, device: u8, function: u8) -> Option<PhysAddr> {
+        if bus < self.start_bus || bus > self.end_bus {
+            return None;
+        }
+        let offset = u64::from(bus - self.start_bus) << 20
+            | u64::from(device) << 15
+            | u64::from(function) << 12;
+        Some(self.base + offset)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+impl Mcfg {
+    pub(super) fn parse(table: &[u8]) -> Result<Self, Error> {
+        require(table, ENTRIES)?;
+        let entries = table[ENTRIES..]
+            .chunks_exact(ENTRY_LEN)
+            .map(|entry| McfgEntry {
+                base: PhysAddr::new(read_u64(entry, 0)),
+                segment: read_u16(entry, 8),
+                start_bus: read_u8(entry, 10),
+                end_bus: read_u8(entry, 11),
+            })
+            .collect();
+        Ok(Mcfg { entries })
+    }
+}
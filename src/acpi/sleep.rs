@@ -0,0 +1,72 @@
+//! Sleep state values from the `\_Sx` packages in the DSDT. There is no AML
+//! interpreter; the packages are simple enough to pick out of the byte code
+//! directly, which is what every small kernel does to power off.
+
+use super::{fadt, read_u8, table_at, tables, Error, HEADER_LEN};
+
+const NAME_OP: u8 = 0x08;
+const PACKAGE_OP: u8 = 0x12;
+const BYTE_PREFIX: u8 = 0x0a;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+
+/// The `SLP_TYP` values for PM1a and PM1b control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SleepTypes {
+    pub a: u8,
+    pub b: u8,
+}
+
+/// `SLP_TYP` for sleep state `S<state>`, from the DSDT or failing that an
+/// SSDT.
+pub fn sleep_types(state: u8) -> Result<SleepTypes, Error> {
+    let name = [b'_', b'S', b'0' + state, b'_'];
+    let dsdt = unsafe { table_at(fadt()?.dsdt)? };
+    if let Some(types) = scan(dsdt, &name) {
+        return Ok(types);
+    }
+    tables()
+        .filter_map(|addr| unsafe { table_at(addr) }.ok())
+        .filter(|table| &table[..4] == b"SSDT")
+        .find_map(|table| scan(table, &name))
+        .ok_or(Error::NotFound(name))
+}
+
+/// Finds `Name(\_Sx_, Package() { a, b, ... })` in `table`'s AML.
+fn scan(table: &[u8], name: &[u8; 4]) -> Option<SleepTypes> {
+    let aml = table.get(HEADER_LEN..)?;
+    (0..aml.len().saturating_sub(4))
+        .filter(|&i| &aml[i..i + 4] == name)
+        // preceded by NameOp, optionally with a root prefix
+        .filter(|&i| {
+            i >= 1
+                && (aml[i - 1] == NAME_OP
+                    || (i >= 2 && aml[i - 1] == b'\\' && aml[i - 2] == NAME_OP))
+        })
+        .find_map(|i| package(&aml[i + 4..]))
+}
+
+fn package(aml: &[u8]) -> Option<SleepTypes> {
+    if read_u8(aml, 0) != PACKAGE_OP {
+        return None;
+    }
+    // the top two bits of the lead byte count the extra length bytes
+    let length_bytes = usize::from(read_u8(aml, 1) >> 6);
+    let mut offset = 2 + length_bytes + 1; // past NumElements
+    let mut next = || {
+        let value = match read_u8(aml, offset) {
+            BYTE_PREFIX => {
+                offset += 1;
+                read_u8(aml, offset)
+            }
+            ZERO_OP => 0,
+            ONE_OP => 1,
+            _ => return None,
+        };
+        offset += 1;
+        Some(value)
+    };
+    let a = next()?;
+    let b = next()?;
+    Some(SleepTypes { a, b })
+}
@@ -1,3 +1,8 @@
+use crate::{
+    config, memory,
+    sync::{Mutex, MutexGuard},
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use fixed_size_block::FixedSizeBlockAllocator;
 use x86_64::{
     structures::paging::{
@@ -10,8 +15,29 @@ pub mod fixed_size_block;
 // pub mod slab;
 // pub mod linked_list;
 
-pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024;
+/// Lowest heap address; `init_heap` slides it up by a random amount.
+pub const HEAP_BASE: usize = 0x_4444_4444_0000;
+
+static HEAP_START: AtomicUsize = AtomicUsize::new(HEAP_BASE);
+static HEAP_LEN: AtomicUsize = AtomicUsize::new(config::heap::SIZE);
+
+/// Where the heap was placed.
+pub fn heap_start() -> usize {
+    HEAP_START.load(Ordering::Relaxed)
+}
+
+/// How big the heap was made.
+pub fn heap_size() -> usize {
+    HEAP_LEN.load(Ordering::Relaxed)
+}
+
+/// Heap bytes in use and free, or `None` while the allocator is locked, as
+/// when a panic struck inside it. Blocks cached for reuse count as used.
+pub fn try_usage() -> Option<(usize, usize)> {
+    ALLOCATOR
+        .try_lock()
+        .map(|allocator| (allocator.used(), allocator.free()))
+}
 
 #[global_allocator]
 static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
@@ -20,9 +46,13 @@ pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
+    let heap_start = memory::kaslr::slide(HEAP_BASE as u64) as usize;
+    let heap_size = crate::boot::cmdline::options().heap_size;
+    HEAP_START.store(heap_start, Ordering::Relaxed);
+    HEAP_LEN.store(heap_size, Ordering::Relaxed);
     let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let heap_start = VirtAddr::new(heap_start as u64);
+        let heap_end = heap_start + heap_size - 1u64;
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(heap_start_page, heap_end_page)
@@ -37,26 +67,30 @@ pub fn init_heap(
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(heap_start, heap_size);
     }
 
     Ok(())
 }
 
 pub struct Locked<A> {
-    inner: spin::Mutex<A>,
+    inner: Mutex<A>,
 }
 
 impl<A> Locked<A> {
     pub const fn new(inner: A) -> Self {
         Locked {
-            inner: spin::Mutex::new(inner),
+            inner: Mutex::new(inner),
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
+    pub fn lock(&self) -> MutexGuard<A> {
         self.inner.lock()
     }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<A>> {
+        self.inner.try_lock()
+    }
 }
 
 fn align_up(addr: usize, align: usize) -> usize {
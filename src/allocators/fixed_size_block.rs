@@ -1,4 +1,5 @@
 use super::Locked;
+use crate::debug::fault;
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::{
     mem,
@@ -34,6 +35,16 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
+    /// Bytes handed out by the fallback allocator, including the blocks
+    /// now waiting in the lists.
+    pub fn used(&self) -> usize {
+        self.fallback_allocator.used()
+    }
+
+    pub fn free(&self) -> usize {
+        self.fallback_allocator.free()
+    }
+
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
@@ -44,6 +55,9 @@ impl FixedSizeBlockAllocator {
 
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if fault::should_fail(fault::Site::Heap) {
+            return ptr::null_mut();
+        }
         let mut allocator = self.lock();
         match list_index(&layout) {
             Some(index) => match allocator.list_heads[index].take() {
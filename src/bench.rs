@@ -0,0 +1,151 @@
+//! Cycle-accurate micro-benchmarks.
+//!
+//! `bench!("name", iters, || work())` times every iteration with serialized
+//! RDTSC after a warmup and reports min/median/p99 in cycles. Building with
+//! `--features bench` runs `run_suite` at boot, so allocator, scheduler and
+//! IPC changes can come with numbers. Where the CPU has performance
+//! counters, instructions retired and LLC misses per iteration come along.
+
+use crate::cpu::pmu;
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt;
+
+/// Times `$body` (a closure) `$iters` times, returning a `Summary`.
+#[macro_export]
+macro_rules! bench {
+    ($name:expr, $iters:expr, $body:expr) => {
+        $crate::bench::run($name, $iters, $body)
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Summary {
+    pub name: &'static str,
+    pub iters: usize,
+    pub min: u64,
+    pub median: u64,
+    pub p99: u64,
+    pub mean: u64,
+    pub instructions: Option<u64>,
+    pub llc_misses: Option<u64>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<24} {:>7} iters  min {:>7}  median {:>7}  p99 {:>7}  mean {:>7} cycles",
+            self.name, self.iters, self.min, self.median, self.p99, self.mean
+        )?;
+        if let Some(instructions) = self.instructions {
+            write!(f, "  {:>7} instr", instructions)?;
+        }
+        if let Some(misses) = self.llc_misses {
+            write!(f, "  {:>5} llc miss", misses)?;
+        }
+        Ok(())
+    }
+}
+
+/// Waits for earlier instructions before reading the TSC.
+#[inline(always)]
+fn start() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe { asm!("lfence", "rdtsc", out("eax") low, out("edx") high, options(nomem, nostack)) };
+    (high as u64) << 32 | low as u64
+}
+
+/// `rdtscp` waits for the measured code; the `lfence` keeps later code out.
+#[inline(always)]
+fn stop() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!(
+            "rdtscp",
+            "lfence",
+            out("eax") low,
+            out("edx") high,
+            out("ecx") _,
+            options(nomem, nostack)
+        )
+    };
+    (high as u64) << 32 | low as u64
+}
+
+/// Keeps the optimizer from dropping work whose result is unused.
+pub fn black_box<T>(value: T) -> T {
+    unsafe {
+        let copy = core::ptr::read_volatile(&value);
+        core::mem::forget(value);
+        copy
+    }
+}
+
+/// Cost of an empty measurement, subtracted from every sample.
+fn overhead() -> u64 {
+    (0..64)
+        .map(|_| {
+            let begin = start();
+            stop() - begin
+        })
+        .min()
+        .unwrap_or(0)
+}
+
+pub fn run<R>(name: &'static str, iters: usize, mut body: impl FnMut() -> R) -> Summary {
+    let iters = iters.max(1);
+    for _ in 0..(iters / 10).max(1) {
+        black_box(body());
+    }
+    let overhead = overhead();
+    let mut samples = Vec::with_capacity(iters);
+    let misses = pmu::allocate(pmu::Event::LlcMisses).ok();
+    let instructions = pmu::instructions();
+    for _ in 0..iters {
+        let begin = start();
+        black_box(body());
+        samples.push((stop() - begin).saturating_sub(overhead));
+    }
+    let instructions = pmu::instructions()
+        .zip(instructions)
+        .map(|(end, begin)| (end - begin) / iters as u64);
+    let llc_misses = misses.map(|counter| counter.read() / iters as u64);
+    samples.sort_unstable();
+    Summary {
+        name,
+        iters,
+        min: samples[0],
+        median: samples[iters / 2],
+        p99: samples[(iters * 99 / 100).min(iters - 1)],
+        mean: samples.iter().sum::<u64>() / iters as u64,
+        instructions,
+        llc_misses,
+    }
+}
+
+/// The standard suite, reported over serial.
+pub fn run_suite() -> Vec<Summary> {
+    serial_println!("running benchmarks");
+    let queue = crate::sync::MpscQueue::with_capacity(16);
+    let was_tracing = crate::trace::is_enabled();
+    crate::trace::enable();
+    let results = alloc::vec![
+        bench!("heap alloc 64 B", 10_000, || Box::new([0u8; 64])),
+        bench!("heap alloc 4 KiB", 1_000, || Box::new([0u8; 4096])),
+        bench!("queue push+pop", 10_000, || {
+            let _ = queue.push(1u64);
+            queue.pop()
+        }),
+        bench!("trace_event", 10_000, || trace_event!(bench, value = 1u64)),
+        bench!("path normalize", 1_000, || crate::fs::path::normalize(
+            "/usr/./lib/../bin//sh"
+        )),
+    ];
+    if !was_tracing {
+        crate::trace::disable();
+    }
+    for summary in results.iter() {
+        serial_println!("{}", summary);
+    }
+    results
+}
@@ -0,0 +1,241 @@
+//! What the bootloader handed over, in one form whichever loader ran: the
+//! `bootloader` crate's `BootInfo` converts into it, and so does Multiboot2
+//! information from GRUB. Everything past `init` reads `boot::info()`.
+//!
+//! Regions and modules live in fixed arrays since this is filled in before
+//! the heap exists. Module memory is reserved as the modules are recorded;
+//! a module named `initramfs`, a tar archive or an LZ4 frame of one,
+//! replaces the embedded archive, and the rest wait in `info().modules()`
+//! for whoever loads them.
+
+use crate::{error::KernelError, memory, shell::ShellErr, sync::InitCell};
+use core::{fmt::Write, slice};
+use x86_64::PhysAddr;
+
+pub mod cmdline;
+pub mod multiboot2;
+
+pub const MAX_REGIONS: usize = 64;
+pub const MAX_MODULES: usize = 8;
+
+static INFO: InitCell<BootInfo> = InitCell::new("boot info");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loader {
+    /// The `bootloader` crate, which maps all physical memory at an offset.
+    Bootloader,
+    Multiboot2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Usable,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    BadMemory,
+    /// The kernel image and its boot stack.
+    Kernel,
+    /// Page tables and data the loader left for the kernel.
+    Bootloader,
+    /// A module the loader passed along.
+    Module,
+}
+
+/// Physical memory `[start, end)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+    pub kind: RegionKind,
+}
+
+impl Region {
+    const EMPTY: Region = Region {
+        start: 0,
+        end: 0,
+        kind: RegionKind::Reserved,
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    pub addr: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+}
+
+/// A file the loader put in memory at `[start, end)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Module {
+    pub start: u64,
+    pub end: u64,
+    /// The loader's string for it, usually a path and arguments.
+    pub name: &'static str,
+}
+
+impl Module {
+    const EMPTY: Module = Module {
+        start: 0,
+        end: 0,
+        name: "",
+    };
+
+    /// The module's contents; `NotReady` without the physical memory
+    /// mapping.
+    pub fn data(&self) -> Result<&'static [u8], KernelError> {
+        let start = memory::phys_to_virt(PhysAddr::new(self.start))?;
+        let len = (self.end - self.start) as usize;
+        Ok(unsafe { slice::from_raw_parts(start.as_ptr(), len) })
+    }
+
+    /// The first word of the name, which is the path the loader read.
+    pub fn path(&self) -> &'static str {
+        self.name.split_whitespace().next().unwrap_or("")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BootInfo {
+    pub loader: Loader,
+    /// Where physical memory is mapped; 0 for an identity mapping.
+    pub physical_memory_offset: u64,
+    pub cmdline: Option<&'static str>,
+    pub framebuffer: Option<Framebuffer>,
+    regions: [Region; MAX_REGIONS],
+    region_count: usize,
+    modules: [Module; MAX_MODULES],
+    module_count: usize,
+}
+
+impl BootInfo {
+    pub fn new(loader: Loader, physical_memory_offset: u64) -> Self {
+        BootInfo {
+            loader,
+            physical_memory_offset,
+            cmdline: None,
+            framebuffer: None,
+            regions: [Region::EMPTY; MAX_REGIONS],
+            region_count: 0,
+            modules: [Module::EMPTY; MAX_MODULES],
+            module_count: 0,
+        }
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions[..self.region_count]
+    }
+
+    pub fn modules(&self) -> &[Module] {
+        &self.modules[..self.module_count]
+    }
+
+    /// Records a region; past `MAX_REGIONS` the rest are dropped, which
+    /// only ever loses memory, never hands out memory that is in use.
+    pub fn push_region(&mut self, region: Region) {
+        if region.start >= region.end {
+            return;
+        }
+        if let Some(slot) = self.regions.get_mut(self.region_count) {
+            *slot = region;
+            self.region_count += 1;
+        }
+    }
+
+    /// Records a module and reserves its memory.
+    pub fn push_module(&mut self, module: Module) {
+        self.reserve(module.start, module.end, RegionKind::Module);
+        if let Some(slot) = self.modules.get_mut(self.module_count) {
+            *slot = module;
+            self.module_count += 1;
+        }
+    }
+
+    /// Carves `[start, end)` out of the usable regions as `kind`.
+    pub fn reserve(&mut self, start: u64, end: u64, kind: RegionKind) {
+        let old = self.regions;
+        let count = self.region_count;
+        self.region_count = 0;
+        for region in &old[..count] {
+            if region.kind != RegionKind::Usable || region.end <= start || region.start >= end {
+                self.push_region(*region);
+                continue;
+            }
+            self.push_region(Region {
+                end: start,
+                ..*region
+            });
+            self.push_region(Region {
+                start: region.start.max(start),
+                end: region.end.min(end),
+                kind,
+            });
+            self.push_region(Region {
+                start: end,
+                ..*region
+            });
+        }
+    }
+}
+
+impl From<&bootloader::BootInfo> for BootInfo {
+    fn from(boot_info: &bootloader::BootInfo) -> Self {
+        use bootloader::bootinfo::MemoryRegionType;
+
+        let mut info = BootInfo::new(Loader::Bootloader, boot_info.physical_memory_offset);
+        for region in boot_info.memory_map.iter() {
+            let kind = match region.region_type {
+                MemoryRegionType::Usable => RegionKind::Usable,
+                MemoryRegionType::AcpiReclaimable => RegionKind::AcpiReclaimable,
+                MemoryRegionType::AcpiNvs => RegionKind::AcpiNvs,
+                MemoryRegionType::BadMemory => RegionKind::BadMemory,
+                MemoryRegionType::Kernel | MemoryRegionType::KernelStack => RegionKind::Kernel,
+                MemoryRegionType::PageTable
+                | MemoryRegionType::Bootloader
+                | MemoryRegionType::BootInfo
+                | MemoryRegionType::Package => RegionKind::Bootloader,
+                _ => RegionKind::Reserved,
+            };
+            info.push_region(Region {
+                start: region.range.start_addr(),
+                end: region.range.end_addr(),
+                kind,
+            });
+        }
+        info
+    }
+}
+
+/// Keeps `info` for the rest of the kernel's life.
+pub fn install(info: BootInfo) -> &'static BootInfo {
+    INFO.init(info)
+}
+
+pub fn info() -> &'static BootInfo {
+    INFO.get()
+}
+
+/// The first module whose file name (the last path component) is `name`.
+pub fn find_module(name: &str) -> Option<&'static Module> {
+    info()
+        .modules()
+        .iter()
+        .find(|module| module.path().rsplit('/').next() == Some(name))
+}
+
+/// `modules`
+pub fn modules_command(_args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    for module in info().modules() {
+        writeln!(
+            out,
+            "{:#010x}-{:#010x} {:>8} bytes  {}",
+            module.start,
+            module.end,
+            module.end - module.start,
+            module.name
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,415 @@
+//! The kernel command line, from Multiboot2 when GRUB passes one. The
+//! `bootloader` crate cannot, so otherwise it is embedded at build time from
+//! `KERNEL_CMDLINE`:
+//!
+//! ```text
+//! KERNEL_CMDLINE="log=info,net=trace heap=4M scheduler=round-robin" cargo run
+//! ```
+//!
+//! Words are `key=value` or bare flags:
+//! - `log=<directives>`: log filter, as for `logs::parse_directives`
+//! - `scheduler=<priority|round-robin|budget>`
+//! - `budget=<high>,<medium>,<low>`: polls per round for each priority
+//!   under the budget scheduler
+//! - `heap=<bytes>[K|M]`: heap size, rounded up to whole pages
+//! - `idle=<latency|power>`: how deep the CPU sleeps when idle
+//! - `vga=<25|50>`: text rows on screen
+//! - `console=<vga|serial>`: where `print!` and the shell write; `serial`
+//!   runs headless, also keeping logs off the screen
+//! - `baud=<bps>`: COM1's speed, 9600 to 115200
+//! - `crtscts`: RTS/CTS flow control on COM1
+//! - `crashdump`: stream a checksummed crash dump over COM1 on panic, see
+//!   `debug::crash_dump`
+//! - `gdb`: let gdb break in over COM2, see `debug::gdb`; off unless given,
+//!   since whoever is on that port can then read and write all memory
+//! - `nokaslr`: keep the heap and MMIO window at fixed addresses, like the
+//!   `no-kaslr` feature
+//! - `initramfs_sha256=<64 hex digits>`: the digest a loader-provided
+//!   initramfs must have; the embedded one carries its own
+//! - `integrity=<warn|enforce>`: on a digest mismatch, log an error and
+//!   boot anyway, or panic
+//! - `panic=<halt|reboot[,<seconds>]|shutdown|monitor>`: what a panic ends
+//!   in once reported: halt for good, reboot after the delay unless a key
+//!   is pressed, power off (exiting QEMU with a failure), or enter
+//!   `debug::monitor`; without it the panic screen waits for a key
+//! - `panic=recover`: drop a task that panics instead of stopping the
+//!   kernel, when it is safe to, see `task::recover`; combines with the
+//!   above. Only `TrackedMutex` locks are checked: a task that panics
+//!   holding a plain `Mutex` is still dropped, and the next user of that
+//!   lock hangs, so this is for development rather than production
+//! - `report=<seconds>`: write scheduler, memory and interrupt statistics
+//!   as CSV to COM1 at this interval, see `serial::report`
+//! - `selftest`: check the core subsystems at boot, printing PASS/FAIL
+//! - `telnet`: serve the shell on TCP port 23, to anyone who connects;
+//!   off unless given
+//! - `script=<path>`: a file of shell commands to run after boot
+//! - `sh=<commands>`: shell commands to run after the script, separated by
+//!   `;`, with `,` for spaces
+//! - `ip=<address>/<prefix>`: the address to fall back on when DHCP gets
+//!   no lease
+//! - `gw=<address>`: the default gateway to go with `ip`
+//! - `bench`: run the benchmark suite at boot, like the `bench` feature
+//! - `test`: exit QEMU on panic
+//!
+//! Unknown or malformed words are reported and otherwise ignored.
+
+use crate::{
+    config, cpu::idle::Policy as IdlePolicy, serial::BaudRate, sync::Once,
+    vga_buffer::mode::TextMode,
+};
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+const EMBEDDED: Option<&str> = option_env!("KERNEL_CMDLINE");
+const PAGE_SIZE: usize = 4096;
+const MIN_HEAP_SIZE: usize = 64 * 1024;
+
+static OPTIONS: Once<Options> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulerKind {
+    Priority,
+    RoundRobin,
+    Budget,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleKind {
+    Vga,
+    Serial,
+}
+
+/// What a failed integrity check does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrity {
+    Warn,
+    Enforce,
+}
+
+/// How a panic ends once it has been reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    /// Wait for a key: M for the monitor, any other to reboot.
+    Prompt,
+    Halt,
+    /// Reboot after this many seconds.
+    Reboot(u64),
+    Shutdown,
+    Monitor,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub log: Option<&'static str>,
+    pub scheduler: SchedulerKind,
+    /// Indexed by priority, low first.
+    pub budgets: [usize; 3],
+    pub heap_size: usize,
+    pub idle: IdlePolicy,
+    pub text_mode: TextMode,
+    pub console: ConsoleKind,
+    pub baud: BaudRate,
+    pub flow_control: bool,
+    pub crash_dump: bool,
+    pub gdb: bool,
+    pub nokaslr: bool,
+    pub initramfs_sha256: Option<[u8; 32]>,
+    pub integrity: Integrity,
+    pub panic: PanicAction,
+    pub panic_recover: bool,
+    pub report_secs: Option<u64>,
+    pub selftest: bool,
+    pub telnet: bool,
+    pub ip: Option<Ipv4Cidr>,
+    pub gateway: Option<Ipv4Address>,
+    pub bench: bool,
+    pub test_mode: bool,
+    pub script: Option<&'static str>,
+    pub sh: Option<&'static str>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            log: None,
+            scheduler: config::scheduler::DEFAULT,
+            budgets: config::scheduler::BUDGETS,
+            heap_size: config::heap::SIZE,
+            idle: IdlePolicy::Latency,
+            text_mode: TextMode::Rows25,
+            console: ConsoleKind::Vga,
+            baud: BaudRate::Baud115200,
+            flow_control: false,
+            crash_dump: false,
+            gdb: false,
+            nokaslr: false,
+            initramfs_sha256: None,
+            integrity: Integrity::Warn,
+            panic: PanicAction::Prompt,
+            panic_recover: false,
+            report_secs: None,
+            selftest: false,
+            telnet: false,
+            ip: None,
+            gateway: None,
+            bench: false,
+            test_mode: false,
+            script: None,
+            sh: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    UnknownOption(&'static str),
+    InvalidValue(&'static str),
+}
+
+/// The command line as the loader passed it, or as embedded.
+pub fn raw() -> &'static str {
+    super::INFO
+        .get()
+        .and_then(|info| info.cmdline)
+        .or(EMBEDDED)
+        .unwrap_or("")
+}
+
+/// Parses the command line and applies the log filter. Run right after the
+/// logger comes up, before anything reads `options`.
+pub fn init() {
+    OPTIONS.call_once(|| parse(raw(), |err| warn!("cmdline: ignoring {:?}", err)));
+    let options = options();
+    if let Some(log) = options.log {
+        if let Err(err) = crate::logs::parse_directives(log) {
+            warn!("cmdline: bad log filter {:?}: {:?}", log, err);
+        }
+    }
+    info!("cmdline: {:?} -> {:?}", raw(), options);
+}
+
+/// The parsed options, parsed quietly on first use if `init` has not run.
+pub fn options() -> &'static Options {
+    OPTIONS.call_once(|| parse(raw(), |_| {}))
+}
+
+/// Parses `line`, passing each bad word to `report` and skipping it.
+pub fn parse(line: &'static str, mut report: impl FnMut(Error)) -> Options {
+    let mut options = Options::default();
+    for word in line.split_whitespace() {
+        let mut parts = word.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next();
+        let parsed = match (key, value) {
+            ("log", Some(spec)) => {
+                options.log = Some(spec);
+                Ok(())
+            }
+            ("scheduler", Some("priority")) => {
+                options.scheduler = SchedulerKind::Priority;
+                Ok(())
+            }
+            ("scheduler", Some("round-robin")) => {
+                options.scheduler = SchedulerKind::RoundRobin;
+                Ok(())
+            }
+            ("scheduler", Some("budget")) => {
+                options.scheduler = SchedulerKind::Budget;
+                Ok(())
+            }
+            ("budget", Some(budgets)) => parse_budgets(budgets)
+                .map(|budgets| options.budgets = budgets)
+                .ok_or(Error::InvalidValue(word)),
+            ("heap", Some(size)) => parse_size(size)
+                .filter(|&size| size >= MIN_HEAP_SIZE)
+                .map(|size| options.heap_size = round_up(size, PAGE_SIZE))
+                .ok_or(Error::InvalidValue(word)),
+            ("idle", Some("latency")) => {
+                options.idle = IdlePolicy::Latency;
+                Ok(())
+            }
+            ("idle", Some("power")) => {
+                options.idle = IdlePolicy::Power;
+                Ok(())
+            }
+            ("vga", Some("25")) => {
+                options.text_mode = TextMode::Rows25;
+                Ok(())
+            }
+            ("vga", Some("50")) => {
+                options.text_mode = TextMode::Rows50;
+                Ok(())
+            }
+            ("console", Some("vga")) => {
+                options.console = ConsoleKind::Vga;
+                Ok(())
+            }
+            ("console", Some("serial")) => {
+                options.console = ConsoleKind::Serial;
+                Ok(())
+            }
+            ("baud", Some(bps)) => bps
+                .parse()
+                .ok()
+                .and_then(BaudRate::from_bps)
+                .map(|baud| options.baud = baud)
+                .ok_or(Error::InvalidValue(word)),
+            ("crtscts", None) => {
+                options.flow_control = true;
+                Ok(())
+            }
+            ("crashdump", None) => {
+                options.crash_dump = true;
+                Ok(())
+            }
+            ("gdb", None) => {
+                options.gdb = true;
+                Ok(())
+            }
+            ("nokaslr", None) => {
+                options.nokaslr = true;
+                Ok(())
+            }
+            ("initramfs_sha256", Some(hex)) => parse_digest(hex)
+                .map(|digest| options.initramfs_sha256 = Some(digest))
+                .ok_or(Error::InvalidValue(word)),
+            ("integrity", Some("warn")) => {
+                options.integrity = Integrity::Warn;
+                Ok(())
+            }
+            ("integrity", Some("enforce")) => {
+                options.integrity = Integrity::Enforce;
+                Ok(())
+            }
+            ("panic", Some("recover")) => {
+                options.panic_recover = true;
+                Ok(())
+            }
+            ("panic", Some(action)) => parse_panic(action)
+                .map(|action| options.panic = action)
+                .ok_or(Error::InvalidValue(word)),
+            ("report", Some(secs)) => secs
+                .parse()
+                .ok()
+                // in milliseconds it must still fit a u64
+                .filter(|&secs: &u64| secs > 0 && secs.checked_mul(1000).is_some())
+                .map(|secs| options.report_secs = Some(secs))
+                .ok_or(Error::InvalidValue(word)),
+            ("selftest", None) => {
+                options.selftest = true;
+                Ok(())
+            }
+            ("telnet", None) => {
+                options.telnet = true;
+                Ok(())
+            }
+            ("ip", Some(cidr)) => cidr
+                .parse()
+                .ok()
+                .map(|cidr| options.ip = Some(cidr))
+                .ok_or(Error::InvalidValue(word)),
+            ("gw", Some(address)) => address
+                .parse()
+                .ok()
+                .map(|address| options.gateway = Some(address))
+                .ok_or(Error::InvalidValue(word)),
+            ("bench", None) => {
+                options.bench = true;
+                Ok(())
+            }
+            ("test", None) => {
+                options.test_mode = true;
+                Ok(())
+            }
+            ("script", Some(path)) => {
+                options.script = Some(path);
+                Ok(())
+            }
+            ("sh", Some(commands)) => {
+                options.sh = Some(commands);
+                Ok(())
+            }
+            ("log", None)
+            | ("scheduler", _)
+            | ("budget", None)
+            | ("heap", None)
+            | ("idle", _)
+            | ("vga", _)
+            | ("console", _)
+            | ("baud", None)
+            | ("crtscts", Some(_))
+            | ("crashdump", Some(_))
+            | ("gdb", Some(_))
+            | ("nokaslr", Some(_))
+            | ("initramfs_sha256", None)
+            | ("integrity", _)
+            | ("panic", None)
+            | ("report", None)
+            | ("selftest", Some(_))
+            | ("telnet", Some(_))
+            | ("ip", None)
+            | ("gw", None)
+            | ("bench", Some(_))
+            | ("test", Some(_))
+            | ("script", None)
+            | ("sh", None) => Err(Error::InvalidValue(word)),
+            _ => Err(Error::UnknownOption(word)),
+        };
+        if let Err(err) = parsed {
+            report(err);
+        }
+    }
+    options
+}
+
+/// `8,4,2`, high first, into budgets indexed by priority.
+fn parse_budgets(text: &str) -> Option<[usize; 3]> {
+    let mut budgets = [0; 3];
+    let mut parts = text.split(',');
+    for budget in budgets.iter_mut().rev() {
+        *budget = parts.next()?.parse().ok().filter(|&budget| budget > 0)?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(budgets),
+    }
+}
+
+/// `halt`, `reboot`, `reboot,<seconds>`, `shutdown` or `monitor`.
+fn parse_panic(text: &str) -> Option<PanicAction> {
+    let mut parts = text.splitn(2, ',');
+    let action = match (parts.next()?, parts.next()) {
+        ("halt", None) => PanicAction::Halt,
+        ("reboot", None) => PanicAction::Reboot(0),
+        ("reboot", Some(secs)) => PanicAction::Reboot(secs.parse().ok()?),
+        ("shutdown", None) => PanicAction::Shutdown,
+        ("monitor", None) => PanicAction::Monitor,
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// A SHA-256 digest as 64 hex digits.
+fn parse_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// `4096`, `64K` or `4M`.
+fn parse_size(text: &str) -> Option<usize> {
+    let (digits, unit) = match text.as_bytes().last()? {
+        b'K' | b'k' => (&text[..text.len() - 1], 1024),
+        b'M' | b'm' => (&text[..text.len() - 1], 1024 * 1024),
+        _ => (text, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) / align * align
+}
@@ -0,0 +1,225 @@
+//! Multiboot2 boot information, as GRUB passes it.
+//!
+//! Only the parser is here, and nothing calls it yet: booting from GRUB is
+//! deferred. GRUB enters in 32-bit protected mode without paging, and the
+//! Multiboot2 header and the stub that gets from there to long mode need a
+//! linker script and an image layout of their own, beside the `bootloader`
+//! crate's. Once the stub has identity-mapped low memory and switched to
+//! long mode it hands the information address to `parse` and continues
+//! with `microkernel::init_from`.
+
+use super::{BootInfo, Framebuffer, Loader, Module, Region, RegionKind};
+use core::{slice, str};
+
+/// What GRUB leaves in `eax`.
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+
+const MEMORY_AVAILABLE: u32 = 1;
+const MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+const MEMORY_NVS: u32 = 4;
+const MEMORY_BAD: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    BadMagic(u32),
+    Misaligned,
+    /// A tag runs past the end of the information.
+    Truncated,
+    /// A memory map entry runs past the top of the address space.
+    BadRegion,
+}
+
+/// Parses the information at `addr` and reserves it, along with the
+/// kernel image at `kernel`, so the frame allocator leaves both alone.
+///
+/// # Safety
+/// `addr` must point at Multiboot2 information that stays in place, in
+/// identity-mapped memory.
+pub unsafe fn parse(magic: u32, addr: u64, kernel: (u64, u64)) -> Result<BootInfo, Error> {
+    if magic != BOOTLOADER_MAGIC {
+        return Err(Error::BadMagic(magic));
+    }
+    if addr % 8 != 0 {
+        return Err(Error::Misaligned);
+    }
+    let total = *(addr as *const u32) as usize;
+    let mbi: &'static [u8] = slice::from_raw_parts(addr as *const u8, total);
+
+    let mut info = BootInfo::new(Loader::Multiboot2, 0);
+    // the memory map first, so later reservations have regions to carve
+    for (kind, tag) in tags(mbi) {
+        if kind == TAG_MEMORY_MAP {
+            memory_map(&mut info, tag)?;
+        }
+    }
+    for (kind, tag) in tags(mbi) {
+        match kind {
+            TAG_CMDLINE => info.cmdline = Some(c_str(tag.get(8..).unwrap_or(&[]))),
+            TAG_MODULE => info.push_module(Module {
+                start: u64::from(read_u32(tag, 8)?),
+                end: u64::from(read_u32(tag, 12)?),
+                name: c_str(tag.get(16..).unwrap_or(&[])),
+            }),
+            TAG_FRAMEBUFFER => {
+                info.framebuffer = Some(Framebuffer {
+                    addr: u64::from(read_u32(tag, 8)?) | u64::from(read_u32(tag, 12)?) << 32,
+                    pitch: read_u32(tag, 16)?,
+                    width: read_u32(tag, 20)?,
+                    height: read_u32(tag, 24)?,
+                    bpp: *tag.get(28).ok_or(Error::Truncated)?,
+                })
+            }
+            _ => {}
+        }
+    }
+    info.reserve(addr, addr + total as u64, RegionKind::Bootloader);
+    info.reserve(kernel.0, kernel.1, RegionKind::Kernel);
+    Ok(info)
+}
+
+/// `(type, tag)` for each tag, header included, up to the end tag.
+fn tags(mbi: &'static [u8]) -> impl Iterator<Item = (u32, &'static [u8])> {
+    let mut offset = 8;
+    core::iter::from_fn(move || {
+        let kind = read_u32(mbi, offset).ok()?;
+        let size = read_u32(mbi, offset + 4).ok()? as usize;
+        if kind == TAG_END || size < 8 {
+            return None;
+        }
+        let tag = mbi.get(offset..offset + size)?;
+        offset += (size + 7) & !7;
+        Some((kind, tag))
+    })
+}
+
+fn memory_map(info: &mut BootInfo, tag: &[u8]) -> Result<(), Error> {
+    let entry_size = read_u32(tag, 8)? as usize;
+    if entry_size < 24 {
+        return Err(Error::Truncated);
+    }
+    for entry in tag.get(16..).unwrap_or(&[]).chunks_exact(entry_size) {
+        let start = read_u64(entry, 0)?;
+        let len = read_u64(entry, 8)?;
+        let kind = match read_u32(entry, 16)? {
+            MEMORY_AVAILABLE => RegionKind::Usable,
+            MEMORY_ACPI_RECLAIMABLE => RegionKind::AcpiReclaimable,
+            MEMORY_NVS => RegionKind::AcpiNvs,
+            MEMORY_BAD => RegionKind::BadMemory,
+            _ => RegionKind::Reserved,
+        };
+        info.push_region(Region {
+            start,
+            end: start.checked_add(len).ok_or(Error::BadRegion)?,
+            kind,
+        });
+    }
+    Ok(())
+}
+
+fn c_str(bytes: &'static [u8]) -> &'static str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+    let mut value = [0; 4];
+    value.copy_from_slice(bytes.get(offset..offset + 4).ok_or(Error::Truncated)?);
+    Ok(u32::from_le_bytes(value))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, Error> {
+    Ok(u64::from(read_u32(bytes, offset)?) | u64::from(read_u32(bytes, offset + 4)?) << 32)
+}
+
+/// Builds information from `tags`, end tag added, somewhere it can stay.
+#[cfg(test)]
+fn build(tags: &[(u32, &[u8])]) -> u64 {
+    use alloc::vec::Vec;
+
+    let mut bytes = Vec::from(&[0u8; 8][..]);
+    for &(kind, body) in tags.iter().chain(&[(TAG_END, &[][..])]) {
+        bytes.extend_from_slice(&kind.to_le_bytes());
+        bytes.extend_from_slice(&(8 + body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes.resize((bytes.len() + 7) & !7, 0);
+    }
+    let total = bytes.len() as u32;
+    bytes[..4].copy_from_slice(&total.to_le_bytes());
+    // u64s, for the alignment
+    let mut words = alloc::vec![0u64; bytes.len() / 8];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(8)) {
+        let mut value = [0; 8];
+        value.copy_from_slice(chunk);
+        *word = u64::from_le_bytes(value);
+    }
+    Vec::leak(words).as_ptr() as u64
+}
+
+/// A memory map tag's body with `(start, len, type)` entries.
+#[cfg(test)]
+fn memory_map_body(entries: &[(u64, u64, u32)]) -> alloc::vec::Vec<u8> {
+    let mut body = alloc::vec::Vec::new();
+    body.extend_from_slice(&24u32.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    for &(start, len, kind) in entries {
+        body.extend_from_slice(&start.to_le_bytes());
+        body.extend_from_slice(&len.to_le_bytes());
+        body.extend_from_slice(&kind.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+    }
+    body
+}
+
+#[test_case]
+fn parses_cmdline_and_memory_map() {
+    let map = memory_map_body(&[
+        (0, 0x9f000, MEMORY_AVAILABLE),
+        (0xf0000, 0x10000, 2),
+        (0x10_0000, 0x7f0_0000, MEMORY_AVAILABLE),
+    ]);
+    let addr = build(&[(TAG_CMDLINE, &b"quiet\0"[..]), (TAG_MEMORY_MAP, &map[..])]);
+    let info = unsafe { parse(BOOTLOADER_MAGIC, addr, (0x10_0000, 0x20_0000)) }.unwrap();
+    assert_eq!(info.loader, Loader::Multiboot2);
+    assert_eq!(info.cmdline, Some("quiet"));
+    let regions: alloc::vec::Vec<_> = info
+        .regions()
+        .iter()
+        .map(|r| (r.start, r.end, r.kind))
+        .collect();
+    assert!(regions.contains(&(0, 0x9f000, RegionKind::Usable)));
+    assert!(regions.contains(&(0xf0000, 0x10_0000, RegionKind::Reserved)));
+    assert!(regions.contains(&(0x10_0000, 0x20_0000, RegionKind::Kernel)));
+    assert!(regions.contains(&(0x20_0000, 0x800_0000, RegionKind::Usable)));
+}
+
+#[test_case]
+fn rejects_bad_information() {
+    let addr = build(&[]);
+    assert_eq!(
+        unsafe { parse(0, addr, (0, 0)) }.unwrap_err(),
+        Error::BadMagic(0)
+    );
+    assert_eq!(
+        unsafe { parse(BOOTLOADER_MAGIC, addr + 4, (0, 0)) }.unwrap_err(),
+        Error::Misaligned
+    );
+    let wrapping = memory_map_body(&[(u64::MAX - 0xfff, 0x2000, MEMORY_AVAILABLE)]);
+    let addr = build(&[(TAG_MEMORY_MAP, &wrapping[..])]);
+    assert_eq!(
+        unsafe { parse(BOOTLOADER_MAGIC, addr, (0, 0)) }.unwrap_err(),
+        Error::BadRegion
+    );
+    let mut short = memory_map_body(&[]);
+    short[0] = 16;
+    let addr = build(&[(TAG_MEMORY_MAP, &short[..])]);
+    assert_eq!(
+        unsafe { parse(BOOTLOADER_MAGIC, addr, (0, 0)) }.unwrap_err(),
+        Error::Truncated
+    );
+}
@@ -0,0 +1,110 @@
+//! Compression, so boot images and crash dumps take less space and time
+//! to move around.
+//!
+//! - `lz4`: the LZ4 block and frame formats. Blocks compress into a
+//!   caller's buffer without allocating, so the panic path can use them;
+//!   frames are what the `lz4` tool writes, as for a compressed initramfs.
+
+pub mod lz4;
+
+// `build.rs` includes `lz4.rs` on its own, without the test framework, so
+// its tests live here.
+
+#[cfg(test)]
+fn repetitive() -> alloc::vec::Vec<u8> {
+    b"abcabcabd".iter().copied().cycle().take(1000).collect()
+}
+
+#[test_case]
+fn lz4_frame_round_trips() {
+    let repetitive = repetitive();
+    let inputs: [&[u8]; 3] = [b"", b"hello", &repetitive];
+    for &input in inputs.iter() {
+        let frame = lz4::compress_frame(input);
+        assert!(lz4::is_frame(&frame));
+        assert_eq!(
+            lz4::decompress_frame(&frame, usize::MAX).as_deref(),
+            Ok(input)
+        );
+    }
+    // repeats compress rather than being stored
+    assert!(lz4::compress_frame(&repetitive).len() < repetitive.len() / 4);
+}
+
+#[test_case]
+fn lz4_block_round_trips() {
+    let input = repetitive();
+    let mut block = [0; lz4::max_compressed_len(1000)];
+    let len = lz4::compress_block(&input, &mut block).unwrap();
+    let mut output = alloc::vec::Vec::new();
+    assert_eq!(
+        lz4::decompress_block(&block[..len], &mut output, usize::MAX),
+        Ok(())
+    );
+    assert_eq!(output, input);
+}
+
+#[test_case]
+fn lz4_rejects_truncated_frames() {
+    let frame = lz4::compress_frame(&repetitive());
+    for &len in [3, 6, 10, frame.len() - 5, frame.len() - 1].iter() {
+        assert!(
+            lz4::decompress_frame(&frame[..len], usize::MAX).is_err(),
+            "{}",
+            len
+        );
+    }
+}
+
+#[test_case]
+fn lz4_rejects_corrupt_input() {
+    let mut frame = lz4::compress_frame(b"hello");
+    let last = frame.len() - 1;
+    frame[last] ^= 1;
+    assert_eq!(
+        lz4::decompress_frame(&frame, usize::MAX),
+        Err(lz4::Error::Checksum)
+    );
+
+    let mut frame = lz4::compress_frame(b"hello");
+    frame[6] ^= 1;
+    assert_eq!(
+        lz4::decompress_frame(&frame, usize::MAX),
+        Err(lz4::Error::Checksum)
+    );
+
+    assert_eq!(
+        lz4::decompress_frame(b"not a frame", usize::MAX),
+        Err(lz4::Error::BadMagic)
+    );
+    // a match reaching back before the start of the output
+    let mut output = alloc::vec::Vec::new();
+    assert_eq!(
+        lz4::decompress_block(&[0x00, 0x01, 0x00], &mut output, usize::MAX),
+        Err(lz4::Error::Corrupt)
+    );
+}
+
+#[test_case]
+fn lz4_bounds_the_output() {
+    let input = repetitive();
+    let frame = lz4::compress_frame(&input);
+    assert_eq!(
+        lz4::decompress_frame(&frame, input.len()).as_deref(),
+        Ok(&input[..])
+    );
+    assert_eq!(
+        lz4::decompress_frame(&frame, input.len() - 1),
+        Err(lz4::Error::Corrupt)
+    );
+    // one literal, then a match of it claiming some 64 KiB
+    let mut block = alloc::vec![0x1f, b'a', 0x01, 0x00];
+    block.extend_from_slice(&[255; 256]);
+    block.push(0);
+    let mut output = alloc::vec::Vec::new();
+    assert_eq!(
+        lz4::decompress_block(&block, &mut output, 4096),
+        Err(lz4::Error::Corrupt)
+    );
+    assert!(output.len() <= 4096);
+}
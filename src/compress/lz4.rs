@@ -0,0 +1,375 @@
+//! LZ4 (https://github.com/lz4/lz4/tree/dev/doc): the block format, and
+//! the frame format around it that the `lz4` tool reads and writes.
+//!
+//! The compressor is greedy with a small hash table: fast and simple, at
+//! some cost in ratio against the reference one. It needs no heap, which
+//! the frame functions do. `build.rs` includes this file too, to compress
+//! the embedded initramfs, so it uses nothing beyond `core` and `alloc`.
+
+use alloc::{vec, vec::Vec};
+
+pub const FRAME_MAGIC: u32 = 0x184d_2204;
+
+/// Largest block a frame written here holds: 4 MiB.
+const BLOCK_MAX: usize = 4 << 20;
+/// Block descriptor byte for `BLOCK_MAX`.
+const BD_4M: u8 = 7 << 4;
+
+/// Frame flags: version 01, then which optional parts are present.
+const FLG_VERSION: u8 = 0b0100_0000;
+const FLG_BLOCK_INDEPENDENT: u8 = 1 << 5;
+const FLG_BLOCK_CHECKSUM: u8 = 1 << 4;
+const FLG_CONTENT_SIZE: u8 = 1 << 3;
+const FLG_CONTENT_CHECKSUM: u8 = 1 << 2;
+const FLG_DICT_ID: u8 = 1 << 0;
+
+/// Set in a block's size when it is stored uncompressed.
+const UNCOMPRESSED: u32 = 1 << 31;
+
+/// A match is at least this long.
+const MIN_MATCH: usize = 4;
+/// The last bytes of a block are always literals...
+const LAST_LITERALS: usize = 5;
+/// ...and the last match starts this far before the end.
+const MF_LIMIT: usize = 12;
+const MAX_OFFSET: usize = 65535;
+const HASH_LOG: u32 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Not an LZ4 frame.
+    BadMagic,
+    /// A feature this decoder lacks, such as dictionaries.
+    Unsupported,
+    /// The input ends early.
+    Truncated,
+    /// A sequence reaches outside the data.
+    Corrupt,
+    /// The header, a block or the content fails its checksum.
+    Checksum,
+    /// The heap cannot hold the output.
+    OutOfMemory,
+}
+
+/// The most `compress_block` can produce for `len` bytes.
+pub const fn max_compressed_len(len: usize) -> usize {
+    len + len / 255 + 16
+}
+
+/// Compresses `input` into `output` as one block, returning its length,
+/// or `None` if `output` is too small; `max_compressed_len` always fits.
+pub fn compress_block(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut out = Output {
+        buf: output,
+        pos: 0,
+    };
+    let mut table = [0u32; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut i = 0;
+    if input.len() > MF_LIMIT {
+        let match_end_limit = input.len() - LAST_LITERALS;
+        while i < input.len() - MF_LIMIT {
+            let sequence = read_u32(input, i);
+            let slot = hash(sequence);
+            let candidate = table[slot] as usize;
+            table[slot] = i as u32;
+            if candidate >= i
+                || i - candidate > MAX_OFFSET
+                || read_u32(input, candidate) != sequence
+            {
+                i += 1;
+                continue;
+            }
+            let mut len = MIN_MATCH;
+            while i + len < match_end_limit && input[candidate + len] == input[i + len] {
+                len += 1;
+            }
+            out.sequence(&input[anchor..i], Some((i - candidate, len)))?;
+            i += len;
+            anchor = i;
+        }
+    }
+    out.sequence(&input[anchor..], None)?;
+    Some(out.pos)
+}
+
+/// Decompresses the block `input`, appending to `output`. What `output`
+/// already holds serves as the window, as linked blocks in a frame need.
+/// A block that would take `output` past `limit` bytes is corrupt.
+pub fn decompress_block(input: &[u8], output: &mut Vec<u8>, limit: usize) -> Result<(), Error> {
+    let mut pos = 0;
+    loop {
+        let token = *input.get(pos).ok_or(Error::Truncated)?;
+        pos += 1;
+        let literals = read_len(input, &mut pos, (token >> 4) as usize)?;
+        let end = pos.checked_add(literals).ok_or(Error::Corrupt)?;
+        let literals = input.get(pos..end).ok_or(Error::Truncated)?;
+        reserve(output, literals.len(), limit)?;
+        output.extend_from_slice(literals);
+        pos = end;
+        if pos == input.len() {
+            return Ok(());
+        }
+        let offset = match input.get(pos..pos + 2) {
+            Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]) as usize,
+            None => return Err(Error::Truncated),
+        };
+        pos += 2;
+        if offset == 0 || offset > output.len() {
+            return Err(Error::Corrupt);
+        }
+        let len = read_len(input, &mut pos, (token & 0xf) as usize)? + MIN_MATCH;
+        reserve(output, len, limit)?;
+        // byte by byte: the match may overlap what it produces
+        for _ in 0..len {
+            let byte = output[output.len() - offset];
+            output.push(byte);
+        }
+    }
+}
+
+/// Compresses `input` as a frame the `lz4` tool can read, with a content
+/// checksum and independent blocks.
+pub fn compress_frame(input: &[u8]) -> Vec<u8> {
+    let flags = FLG_VERSION | FLG_BLOCK_INDEPENDENT | FLG_CONTENT_CHECKSUM;
+    let mut frame = Vec::with_capacity(max_compressed_len(input.len()) + 32);
+    frame.extend_from_slice(&FRAME_MAGIC.to_le_bytes());
+    frame.extend_from_slice(&[flags, BD_4M, header_checksum(&[flags, BD_4M])]);
+    let mut block = vec![0; max_compressed_len(BLOCK_MAX.min(input.len()))];
+    for chunk in input.chunks(BLOCK_MAX) {
+        match compress_block(chunk, &mut block) {
+            Some(len) if len < chunk.len() => {
+                frame.extend_from_slice(&(len as u32).to_le_bytes());
+                frame.extend_from_slice(&block[..len]);
+            }
+            _ => {
+                frame.extend_from_slice(&(chunk.len() as u32 | UNCOMPRESSED).to_le_bytes());
+                frame.extend_from_slice(chunk);
+            }
+        }
+    }
+    frame.extend_from_slice(&0u32.to_le_bytes());
+    frame.extend_from_slice(&xxh32(input, 0).to_le_bytes());
+    frame
+}
+
+/// Whether `data` starts like an LZ4 frame.
+pub fn is_frame(data: &[u8]) -> bool {
+    data.len() >= 4 && read_u32(data, 0) == FRAME_MAGIC
+}
+
+/// Makes room for `len` more bytes in `output`, which may hold at most
+/// `limit`, without panicking when the heap is short.
+fn reserve(output: &mut Vec<u8>, len: usize, limit: usize) -> Result<(), Error> {
+    if len > limit.saturating_sub(output.len()) {
+        return Err(Error::Corrupt);
+    }
+    output.try_reserve(len).map_err(|_| Error::OutOfMemory)
+}
+
+/// The most a block may decompress to, by the frame's block descriptor.
+fn block_max(bd: u8) -> Option<usize> {
+    match bd >> 4 & 0b111 {
+        4 => Some(64 << 10),
+        5 => Some(256 << 10),
+        6 => Some(1 << 20),
+        7 => Some(4 << 20),
+        _ => None,
+    }
+}
+
+/// Decompresses one frame of at most `limit` bytes, checking every
+/// checksum it carries. Blocks are held to the frame's block size, so
+/// corrupt input fails instead of exhausting the heap.
+pub fn decompress_frame(input: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    if !is_frame(input) {
+        return Err(Error::BadMagic);
+    }
+    let flags = *input.get(4).ok_or(Error::Truncated)?;
+    if flags & 0b1100_0000 != FLG_VERSION || flags & FLG_DICT_ID != 0 {
+        return Err(Error::Unsupported);
+    }
+    let block_max = block_max(*input.get(5).ok_or(Error::Truncated)?).ok_or(Error::Unsupported)?;
+    let mut pos = 6;
+    let mut output = Vec::new();
+    if flags & FLG_CONTENT_SIZE != 0 {
+        let size = input.get(pos..pos + 8).ok_or(Error::Truncated)?;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(size);
+        // only a hint, so a corrupt size cannot exhaust the heap
+        let hint = (u64::from_le_bytes(bytes) as usize).min(input.len().saturating_mul(4));
+        let _ = output.try_reserve(hint.min(limit));
+        pos += 8;
+    }
+    let checksum = *input.get(pos).ok_or(Error::Truncated)?;
+    if checksum != header_checksum(&input[4..pos]) {
+        return Err(Error::Checksum);
+    }
+    pos += 1;
+
+    loop {
+        let size = read_u32(input.get(pos..pos + 4).ok_or(Error::Truncated)?, 0);
+        pos += 4;
+        if size == 0 {
+            break;
+        }
+        let len = (size & !UNCOMPRESSED) as usize;
+        let block = input.get(pos..pos + len).ok_or(Error::Truncated)?;
+        pos += len;
+        let block_limit = limit.min(output.len().saturating_add(block_max));
+        if size & UNCOMPRESSED != 0 {
+            reserve(&mut output, block.len(), block_limit)?;
+            output.extend_from_slice(block);
+        } else {
+            decompress_block(block, &mut output, block_limit)?;
+        }
+        if flags & FLG_BLOCK_CHECKSUM != 0 {
+            let expected = read_u32(input.get(pos..pos + 4).ok_or(Error::Truncated)?, 0);
+            if expected != xxh32(block, 0) {
+                return Err(Error::Checksum);
+            }
+            pos += 4;
+        }
+    }
+    if flags & FLG_CONTENT_CHECKSUM != 0 {
+        let expected = read_u32(input.get(pos..pos + 4).ok_or(Error::Truncated)?, 0);
+        if expected != xxh32(&output, 0) {
+            return Err(Error::Checksum);
+        }
+    }
+    Ok(output)
+}
+
+/// Where `compress_block` writes, failing once `buf` is full.
+struct Output<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Output<'_> {
+    fn push(&mut self, byte: u8) -> Option<()> {
+        *self.buf.get_mut(self.pos)? = byte;
+        self.pos += 1;
+        Some(())
+    }
+
+    fn extend(&mut self, bytes: &[u8]) -> Option<()> {
+        self.buf
+            .get_mut(self.pos..self.pos + bytes.len())?
+            .copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Some(())
+    }
+
+    /// The part of a length beyond what fits in the token's nibble.
+    fn len(&mut self, mut len: usize) -> Option<()> {
+        while len >= 255 {
+            self.push(255)?;
+            len -= 255;
+        }
+        self.push(len as u8)
+    }
+
+    /// `literals`, then the match at `offset` back of `len` bytes; the last
+    /// sequence has no match.
+    fn sequence(&mut self, literals: &[u8], matched: Option<(usize, usize)>) -> Option<()> {
+        let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+        self.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8)?;
+        if literals.len() >= 15 {
+            self.len(literals.len() - 15)?;
+        }
+        self.extend(literals)?;
+        if let Some((offset, _)) = matched {
+            self.extend(&(offset as u16).to_le_bytes())?;
+            if match_len >= 15 {
+                self.len(match_len - 15)?;
+            }
+        }
+        Some(())
+    }
+}
+
+/// A length whose token nibble is `nibble`, with its extra bytes.
+fn read_len(input: &[u8], pos: &mut usize, nibble: usize) -> Result<usize, Error> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *input.get(*pos).ok_or(Error::Truncated)?;
+            *pos += 1;
+            len = len.checked_add(byte as usize).ok_or(Error::Corrupt)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+/// The frame descriptor's check byte.
+fn header_checksum(descriptor: &[u8]) -> u8 {
+    (xxh32(descriptor, 0) >> 8) as u8
+}
+
+const PRIME1: u32 = 2_654_435_761;
+const PRIME2: u32 = 2_246_822_519;
+const PRIME3: u32 = 3_266_489_917;
+const PRIME4: u32 = 668_265_263;
+const PRIME5: u32 = 374_761_393;
+
+/// xxHash32, which frames use for their checksums.
+fn xxh32(data: &[u8], seed: u32) -> u32 {
+    let round = |acc: u32, lane: u32| {
+        acc.wrapping_add(lane.wrapping_mul(PRIME2))
+            .rotate_left(13)
+            .wrapping_mul(PRIME1)
+    };
+    let mut at = 0;
+    let mut hash = if data.len() >= 16 {
+        let mut lanes = [
+            seed.wrapping_add(PRIME1).wrapping_add(PRIME2),
+            seed.wrapping_add(PRIME2),
+            seed,
+            seed.wrapping_sub(PRIME1),
+        ];
+        while at + 16 <= data.len() {
+            for lane in lanes.iter_mut() {
+                *lane = round(*lane, read_u32(data, at));
+                at += 4;
+            }
+        }
+        lanes[0]
+            .rotate_left(1)
+            .wrapping_add(lanes[1].rotate_left(7))
+            .wrapping_add(lanes[2].rotate_left(12))
+            .wrapping_add(lanes[3].rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME5)
+    };
+    hash = hash.wrapping_add(data.len() as u32);
+    while at + 4 <= data.len() {
+        hash = hash
+            .wrapping_add(read_u32(data, at).wrapping_mul(PRIME3))
+            .rotate_left(17)
+            .wrapping_mul(PRIME4);
+        at += 4;
+    }
+    for &byte in &data[at..] {
+        hash = hash
+            .wrapping_add((byte as u32).wrapping_mul(PRIME5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME1);
+    }
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME3);
+    hash ^ (hash >> 16)
+}
@@ -0,0 +1,102 @@
+//! Build-time kernel parameters, grouped per subsystem. A cargo feature
+//! picks the profile:
+//!
+//! - `minimal`: a small heap, a 100 Hz tick and shallow queues, for
+//!   machines with little memory
+//! - `debug`: a large heap and fixed addresses (`no-kaslr`), with fault
+//!   injection available
+//! - `full`: the defaults, also used when no profile is chosen
+//!
+//! Features are additive, so when several are on the smallest wins:
+//! `minimal` over `debug` over `full`. The command line can still override
+//! the heap size and the scheduler at boot.
+
+use crate::boot::cmdline::SchedulerKind;
+
+struct Profile {
+    name: &'static str,
+    heap_size: usize,
+    tick_rate: u32,
+    task_queue_depth: usize,
+    scancode_queue_depth: usize,
+    serial_queue_depth: usize,
+    scheduler: SchedulerKind,
+}
+
+#[allow(dead_code)]
+const MINIMAL: Profile = Profile {
+    name: "minimal",
+    heap_size: 256 * 1024,
+    tick_rate: 100,
+    task_queue_depth: 64,
+    scancode_queue_depth: 32,
+    serial_queue_depth: 64,
+    scheduler: SchedulerKind::RoundRobin,
+};
+
+#[allow(dead_code)]
+const DEBUG: Profile = Profile {
+    name: "debug",
+    heap_size: 4 * 1024 * 1024,
+    tick_rate: 1000,
+    task_queue_depth: 1024,
+    scancode_queue_depth: 100,
+    serial_queue_depth: 256,
+    scheduler: SchedulerKind::Priority,
+};
+
+#[allow(dead_code)]
+const FULL: Profile = Profile {
+    name: "full",
+    heap_size: 1024 * 1024,
+    tick_rate: 1000,
+    task_queue_depth: 1024,
+    scancode_queue_depth: 100,
+    serial_queue_depth: 256,
+    scheduler: SchedulerKind::Priority,
+};
+
+#[cfg(feature = "minimal")]
+const PROFILE: Profile = MINIMAL;
+#[cfg(all(feature = "debug", not(feature = "minimal")))]
+const PROFILE: Profile = DEBUG;
+#[cfg(not(any(feature = "minimal", feature = "debug")))]
+const PROFILE: Profile = FULL;
+
+/// The profile this kernel was built with.
+pub const PROFILE_NAME: &str = PROFILE.name;
+
+pub mod heap {
+    /// Default heap size; `heap=` on the command line overrides it.
+    pub const SIZE: usize = super::PROFILE.heap_size;
+}
+
+pub mod time {
+    /// Timer interrupts per second.
+    pub const TICK_RATE: u32 = super::PROFILE.tick_rate;
+}
+
+pub mod scheduler {
+    use crate::boot::cmdline::SchedulerKind;
+
+    /// Ready tasks each run queue holds.
+    pub const QUEUE_DEPTH: usize = super::PROFILE.task_queue_depth;
+    /// Used unless `scheduler=` is on the command line.
+    pub const DEFAULT: SchedulerKind = super::PROFILE.scheduler;
+    /// `BudgetScheduler`'s polls per round, low to high, unless `budget=`
+    /// is on the command line.
+    pub const BUDGETS: [usize; 3] = [2, 4, 8];
+}
+
+pub mod keyboard {
+    /// Scancodes buffered between the interrupt and the shell.
+    pub const QUEUE_DEPTH: usize = super::PROFILE.scancode_queue_depth;
+}
+
+pub mod serial {
+    /// Bytes buffered between the COM1 interrupt and its reader.
+    pub const QUEUE_DEPTH: usize = super::PROFILE.serial_queue_depth;
+    /// Bytes queued for COM1's transmit interrupt; about a second at
+    /// 115200 baud.
+    pub const TRANSMIT_BUFFER: usize = 16 * 1024;
+}
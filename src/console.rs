@@ -0,0 +1,6 @@
+//! What every text console shares, whether its characters come from the
+//! keyboard, a serial port, telnet or a virtio console.
+
+pub mod line_discipline;
+
+pub use self::line_discipline::{Input, LineDiscipline};
@@ -0,0 +1,125 @@
+//! Cooked input, as a TTY line discipline gives it: characters are
+//! collected into a line and echoed, backspace and Ctrl+U erase, and a line
+//! is only handed over once Enter ends it.
+//!
+//! Ctrl+C drops the line and reports an interrupt; Ctrl+D on an empty line
+//! reports the end of input, and is ignored otherwise. Terminals send Enter
+//! as CR LF or CR NUL, so whatever follows a CR is not another line end.
+
+use alloc::string::String;
+use core::{fmt::Write, mem};
+
+const INTERRUPT: char = '\x03';
+const END_OF_FILE: char = '\x04';
+const KILL_LINE: char = '\x15';
+
+/// What a character finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Line(String),
+    /// Ctrl+C; the line typed so far is gone.
+    Interrupt,
+    /// Ctrl+D at the start of a line.
+    EndOfFile,
+}
+
+pub struct LineDiscipline {
+    line: String,
+    echo: bool,
+    /// The last character was a CR.
+    after_cr: bool,
+}
+
+impl LineDiscipline {
+    pub fn new() -> Self {
+        LineDiscipline {
+            line: String::new(),
+            echo: true,
+            after_cr: false,
+        }
+    }
+
+    /// Whether typed characters are written back; control characters
+    /// still take effect without it.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    pub fn echo(&self) -> bool {
+        self.echo
+    }
+
+    /// The line typed so far.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Takes `c`, echoing it to `out`, and returns what it finished.
+    pub fn push(&mut self, c: char, out: &mut dyn Write) -> Option<Input> {
+        if mem::replace(&mut self.after_cr, c == '\r') && (c == '\n' || c == '\0') {
+            return None;
+        }
+        match c {
+            '\r' | '\n' => {
+                self.write(out, "\n");
+                Some(Input::Line(mem::replace(&mut self.line, String::new())))
+            }
+            INTERRUPT => {
+                self.write(out, "^C\n");
+                self.line.clear();
+                Some(Input::Interrupt)
+            }
+            END_OF_FILE if self.line.is_empty() => Some(Input::EndOfFile),
+            '\x08' | '\x7f' => {
+                if self.line.pop().is_some() {
+                    self.write(out, "\x08 \x08");
+                }
+                None
+            }
+            KILL_LINE => {
+                self.erase(out);
+                None
+            }
+            c if c.is_control() => None,
+            c => {
+                self.line.push(c);
+                if self.echo {
+                    let _ = out.write_char(c);
+                }
+                None
+            }
+        }
+    }
+
+    /// Appends `s` as if it had been typed, for completion.
+    pub fn insert_str(&mut self, s: &str, out: &mut dyn Write) {
+        self.line.push_str(s);
+        self.write(out, s);
+    }
+
+    /// Replaces the line typed so far with `line`, redrawn in place.
+    pub fn replace(&mut self, line: String, out: &mut dyn Write) {
+        self.erase(out);
+        self.write(out, &line);
+        self.line = line;
+    }
+
+    fn erase(&mut self, out: &mut dyn Write) {
+        for _ in self.line.chars() {
+            self.write(out, "\x08 \x08");
+        }
+        self.line.clear();
+    }
+
+    fn write(&self, out: &mut dyn Write, s: &str) {
+        if self.echo {
+            let _ = out.write_str(s);
+        }
+    }
+}
+
+impl Default for LineDiscipline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,21 @@
+pub mod features;
+pub mod idle;
+pub mod msr;
+pub mod pat;
+pub mod pmu;
+pub mod protection;
+pub mod random;
+pub mod thermal;
+
+pub use self::features::{features, Features};
+
+/// Detects what the boot CPU supports, run before anything that cares,
+/// and turns on the protections it offers.
+pub fn init() {
+    features::init();
+    protection::init();
+    pat::init();
+    pmu::init();
+    idle::init();
+    thermal::init();
+}
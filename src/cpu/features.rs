@@ -0,0 +1,233 @@
+//! What the CPU supports, read from CPUID once at boot so other code can
+//! check `cpu::features().nx` instead of assuming.
+
+use crate::sync::Once;
+use core::{
+    arch::x86_64::{__cpuid, __cpuid_count, CpuidResult},
+    fmt, str,
+};
+
+static FEATURES: Once<Features> = Once::new();
+
+#[derive(Debug, Clone)]
+pub struct Features {
+    /// "GenuineIntel", "AuthenticAMD", "TCGTCGTCGTCG"...
+    pub vendor: [u8; 12],
+    pub max_leaf: u32,
+    pub max_extended_leaf: u32,
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    /// Running under a hypervisor.
+    pub hypervisor: bool,
+
+    pub sse: bool,
+    pub sse2: bool,
+    pub sse3: bool,
+    pub ssse3: bool,
+    pub sse4_1: bool,
+    pub sse4_2: bool,
+    pub avx: bool,
+    pub avx2: bool,
+    pub avx512f: bool,
+    pub fxsr: bool,
+    pub xsave: bool,
+    pub popcnt: bool,
+
+    pub apic: bool,
+    pub x2apic: bool,
+    pub tsc: bool,
+    pub tsc_deadline: bool,
+    /// The TSC ticks at a constant rate across P-, C- and T-states.
+    pub invariant_tsc: bool,
+    pub rdtscp: bool,
+    pub msr: bool,
+    pub pat: bool,
+    pub monitor: bool,
+    /// Per-core digital thermal sensor, read through `IA32_THERM_STATUS`.
+    pub digital_thermal_sensor: bool,
+    pub package_thermal: bool,
+
+    pub rdrand: bool,
+    pub rdseed: bool,
+
+    /// No-execute page bit.
+    pub nx: bool,
+    pub pge: bool,
+    pub pcid: bool,
+    pub invpcid: bool,
+    pub huge_pages_1gib: bool,
+    pub la57: bool,
+    pub smep: bool,
+    pub smap: bool,
+    pub umip: bool,
+    pub fsgsbase: bool,
+    pub syscall: bool,
+
+    /// Architectural performance monitoring, 0 if absent.
+    pub pmu_version: u8,
+    pub pmu_counters: u8,
+    pub pmu_counter_width: u8,
+    pub pmu_fixed_counters: u8,
+    pub pmu_fixed_width: u8,
+}
+
+/// Reads CPUID; later calls keep the first result.
+pub fn init() {
+    info!("cpu: {}", features());
+}
+
+/// The boot CPU's features, detected on first use if `init` has not run.
+pub fn features() -> &'static Features {
+    FEATURES.call_once(detect)
+}
+
+fn bit(register: u32, bit: u32) -> bool {
+    register & (1 << bit) != 0
+}
+
+fn detect() -> Features {
+    let vendor_leaf = unsafe { __cpuid(0) };
+    let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    let leaf = |eax| {
+        if eax <= vendor_leaf.eax {
+            unsafe { __cpuid_count(eax, 0) }
+        } else {
+            CpuidResult {
+                eax: 0,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            }
+        }
+    };
+    let extended = |eax| {
+        if eax <= max_extended_leaf {
+            unsafe { __cpuid(eax) }
+        } else {
+            CpuidResult {
+                eax: 0,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            }
+        }
+    };
+
+    let mut vendor = [0; 12];
+    vendor[..4].copy_from_slice(&vendor_leaf.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&vendor_leaf.edx.to_le_bytes());
+    vendor[8..].copy_from_slice(&vendor_leaf.ecx.to_le_bytes());
+
+    let basic = leaf(1);
+    let structured = leaf(7);
+    let thermal = leaf(6);
+    let perfmon = leaf(0xa);
+    let ext = extended(0x8000_0001);
+    let power = extended(0x8000_0007);
+
+    let base_family = (basic.eax >> 8) & 0xf;
+    let base_model = (basic.eax >> 4) & 0xf;
+    let (family, model) = match base_family {
+        0xf => (
+            base_family + ((basic.eax >> 20) & 0xff),
+            base_model | ((basic.eax >> 12) & 0xf0),
+        ),
+        0x6 => (base_family, base_model | ((basic.eax >> 12) & 0xf0)),
+        _ => (base_family, base_model),
+    };
+
+    Features {
+        vendor,
+        max_leaf: vendor_leaf.eax,
+        max_extended_leaf,
+        family,
+        model,
+        stepping: basic.eax & 0xf,
+        hypervisor: bit(basic.ecx, 31),
+
+        sse: bit(basic.edx, 25),
+        sse2: bit(basic.edx, 26),
+        sse3: bit(basic.ecx, 0),
+        ssse3: bit(basic.ecx, 9),
+        sse4_1: bit(basic.ecx, 19),
+        sse4_2: bit(basic.ecx, 20),
+        avx: bit(basic.ecx, 28),
+        avx2: bit(structured.ebx, 5),
+        avx512f: bit(structured.ebx, 16),
+        fxsr: bit(basic.edx, 24),
+        xsave: bit(basic.ecx, 26),
+        popcnt: bit(basic.ecx, 23),
+
+        apic: bit(basic.edx, 9),
+        x2apic: bit(basic.ecx, 21),
+        tsc: bit(basic.edx, 4),
+        tsc_deadline: bit(basic.ecx, 24),
+        invariant_tsc: bit(power.edx, 8),
+        rdtscp: bit(ext.edx, 27),
+        msr: bit(basic.edx, 5),
+        pat: bit(basic.edx, 16),
+        monitor: bit(basic.ecx, 3),
+        digital_thermal_sensor: bit(thermal.eax, 0),
+        package_thermal: bit(thermal.eax, 6),
+
+        rdrand: bit(basic.ecx, 30),
+        rdseed: bit(structured.ebx, 18),
+
+        nx: bit(ext.edx, 20),
+        pge: bit(basic.edx, 13),
+        pcid: bit(basic.ecx, 17),
+        invpcid: bit(structured.ebx, 10),
+        huge_pages_1gib: bit(ext.edx, 26),
+        la57: bit(structured.ecx, 16),
+        smep: bit(structured.ebx, 7),
+        smap: bit(structured.ebx, 20),
+        umip: bit(structured.ecx, 2),
+        fsgsbase: bit(structured.ebx, 0),
+        syscall: bit(ext.edx, 11),
+
+        pmu_version: perfmon.eax as u8,
+        pmu_counters: (perfmon.eax >> 8) as u8,
+        pmu_counter_width: (perfmon.eax >> 16) as u8,
+        pmu_fixed_counters: (perfmon.edx & 0x1f) as u8,
+        pmu_fixed_width: (perfmon.edx >> 5) as u8,
+    }
+}
+
+impl Features {
+    pub fn vendor(&self) -> &str {
+        str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+}
+
+/// One line: vendor, family/model/stepping and the notable flags.
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} family {:#x} model {:#x} stepping {}",
+            self.vendor(),
+            self.family,
+            self.model,
+            self.stepping
+        )?;
+        let flags = [
+            ("sse4.2", self.sse4_2),
+            ("avx", self.avx),
+            ("avx2", self.avx2),
+            ("nx", self.nx),
+            ("x2apic", self.x2apic),
+            ("invtsc", self.invariant_tsc),
+            ("rdrand", self.rdrand),
+            ("1gb", self.huge_pages_1gib),
+            ("smep", self.smep),
+            ("smap", self.smap),
+            ("umip", self.umip),
+            ("hypervisor", self.hypervisor),
+        ];
+        for (name, _) in flags.iter().filter(|(_, present)| *present) {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! What the schedulers do when no task is ready. `hlt` is C1 everywhere;
+//! where CPUID advertises MONITOR/MWAIT with C-state sub-states, `wait`
+//! can ask for deeper states, which save power on laptops at the cost of
+//! a slower wake-up. The `Policy` picks between the two.
+//!
+//! Residency is measured with the TSC. Without an invariant TSC it may
+//! stop in deep states, so their residency undercounts.
+
+use super::features;
+use crate::{shell::ShellErr, sync::Mutex};
+use core::{
+    arch::x86_64::{__cpuid, _rdtsc},
+    fmt::Write,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
+
+const MWAIT_LEAF: u32 = 5;
+/// Leaf 5 ECX: the sub-state enumeration in EDX is valid.
+const MWAIT_EXTENSIONS: u32 = 1 << 0;
+/// C0 through C7, each with a 4-bit sub-state count in leaf 5 EDX.
+const C_STATES: usize = 8;
+
+static POLICY: AtomicU8 = AtomicU8::new(Policy::Latency as u8);
+/// The MWAIT hint for the deepest advertised C-state, or `NO_MWAIT`.
+static DEEPEST_HINT: AtomicU64 = AtomicU64::new(NO_MWAIT);
+const NO_MWAIT: u64 = u64::MAX;
+/// The line MONITOR arms. Nothing writes it: interrupts are the only
+/// wake-up, as with `hlt`.
+static MONITOR_LINE: AtomicU64 = AtomicU64::new(0);
+static RESIDENCY: Mutex<[Residency; C_STATES]> = Mutex::new([Residency::ZERO; C_STATES]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Policy {
+    /// `hlt` only, the quickest to wake from.
+    Latency = 0,
+    /// The deepest C-state MWAIT offers.
+    Power = 1,
+}
+
+/// Time spent in one C-state.
+#[derive(Debug, Clone, Copy)]
+pub struct Residency {
+    pub entries: u64,
+    pub cycles: u64,
+}
+
+impl Residency {
+    const ZERO: Residency = Residency {
+        entries: 0,
+        cycles: 0,
+    };
+}
+
+/// Looks for MWAIT C-states and applies the command line's policy.
+pub fn init() {
+    if let Some(hint) = detect_deepest_hint() {
+        DEEPEST_HINT.store(u64::from(hint), Ordering::Relaxed);
+    }
+    set_policy(crate::boot::cmdline::options().idle);
+    match deepest_state() {
+        Some(state) => info!("idle: mwait up to C{}, policy {:?}", state, policy()),
+        None => info!("idle: hlt only"),
+    }
+}
+
+/// `(C-state - 1) << 4 | sub-state` for the deepest C-state with any
+/// sub-states listed.
+fn detect_deepest_hint() -> Option<u32> {
+    let features = features();
+    if !features.monitor || features.max_leaf < MWAIT_LEAF {
+        return None;
+    }
+    let leaf = unsafe { __cpuid(MWAIT_LEAF) };
+    if leaf.ecx & MWAIT_EXTENSIONS == 0 {
+        return None;
+    }
+    (1..C_STATES as u32).rev().find_map(|state| {
+        let sub_states = (leaf.edx >> (state * 4)) & 0xf;
+        if sub_states == 0 {
+            None
+        } else {
+            Some((state - 1) << 4 | (sub_states - 1))
+        }
+    })
+}
+
+/// The deepest C-state MWAIT can enter, if it is usable at all.
+pub fn deepest_state() -> Option<usize> {
+    match DEEPEST_HINT.load(Ordering::Relaxed) {
+        NO_MWAIT => None,
+        hint => Some((hint >> 4) as usize + 1),
+    }
+}
+
+pub fn policy() -> Policy {
+    match POLICY.load(Ordering::Relaxed) {
+        1 => Policy::Power,
+        _ => Policy::Latency,
+    }
+}
+
+pub fn set_policy(policy: Policy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Sleeps until the next interrupt, then returns with interrupts enabled.
+/// Call with interrupts disabled, after checking there is nothing to run,
+/// so a wake-up between the check and the sleep is not lost.
+pub fn wait() {
+    let hint = match (policy(), DEEPEST_HINT.load(Ordering::Relaxed)) {
+        (Policy::Power, hint) if hint != NO_MWAIT => Some(hint as u32),
+        _ => None,
+    };
+    let state = hint.map_or(1, |hint| (hint >> 4) as usize + 1);
+    let start = unsafe { _rdtsc() };
+    match hint {
+        // sti holds interrupts off for one more instruction, so one arriving
+        // after the queues were checked still ends the mwait
+        Some(hint) => unsafe {
+            asm!(
+                "monitor",
+                in("rax") &MONITOR_LINE as *const AtomicU64 as u64,
+                in("ecx") 0,
+                in("edx") 0,
+                options(nostack)
+            );
+            asm!("sti; mwait", in("eax") hint, in("ecx") 0, options(nostack));
+        },
+        None => x86_64::instructions::interrupts::enable_and_hlt(),
+    }
+    let cycles = unsafe { _rdtsc() }.wrapping_sub(start);
+    let mut residency = RESIDENCY.lock();
+    residency[state].entries += 1;
+    residency[state].cycles += cycles;
+}
+
+/// Residency per C-state, indexed by state; C0 stays empty.
+pub fn residency() -> [Residency; C_STATES] {
+    *RESIDENCY.lock()
+}
+
+/// `idle [latency|power]`
+pub fn command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    match args.first() {
+        None => {}
+        Some(&"latency") => set_policy(Policy::Latency),
+        Some(&"power") => set_policy(Policy::Power),
+        Some(_) => return Err(ShellErr::new("usage: idle [latency|power]")),
+    }
+    match deepest_state() {
+        Some(state) => writeln!(out, "policy {:?}, mwait up to C{}", policy(), state)?,
+        None => writeln!(out, "policy {:?}, hlt only", policy())?,
+    }
+    for (state, residency) in residency().iter().enumerate() {
+        if residency.entries == 0 {
+            continue;
+        }
+        writeln!(
+            out,
+            "C{}: {:>10} entries {:>16} cycles",
+            state, residency.entries, residency.cycles
+        )?;
+    }
+    Ok(())
+}
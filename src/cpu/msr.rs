@@ -0,0 +1,259 @@
+//! Model-specific registers the kernel uses, behind typed accessors.
+//!
+//! Everything else goes through `read_checked`/`write_checked`, which only
+//! exist in debug builds and only touch registers on `ALLOWED`, so the
+//! `msr` shell command cannot fault the machine on a typo.
+
+use crate::shell::{parse_number, ShellErr};
+use core::fmt::Write;
+use x86_64::{
+    registers::model_specific::{EferFlags, Msr},
+    PhysAddr, VirtAddr,
+};
+
+pub const IA32_TSC: u32 = 0x10;
+pub const IA32_APIC_BASE: u32 = 0x1b;
+pub const IA32_PMC0: u32 = 0xc1;
+pub const IA32_PERFEVTSEL0: u32 = 0x186;
+pub const IA32_THERM_STATUS: u32 = 0x19c;
+pub const MSR_TEMPERATURE_TARGET: u32 = 0x1a2;
+pub const IA32_PACKAGE_THERM_STATUS: u32 = 0x1b1;
+pub const IA32_PAT: u32 = 0x277;
+pub const IA32_FIXED_CTR0: u32 = 0x309;
+pub const IA32_FIXED_CTR_CTRL: u32 = 0x38d;
+pub const IA32_PERF_GLOBAL_STATUS: u32 = 0x38e;
+pub const IA32_PERF_GLOBAL_CTRL: u32 = 0x38f;
+pub const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+pub const IA32_EFER: u32 = 0xc000_0080;
+pub const IA32_STAR: u32 = 0xc000_0081;
+pub const IA32_LSTAR: u32 = 0xc000_0082;
+pub const IA32_FMASK: u32 = 0xc000_0084;
+pub const IA32_FS_BASE: u32 = 0xc000_0100;
+pub const IA32_GS_BASE: u32 = 0xc000_0101;
+pub const IA32_KERNEL_GS_BASE: u32 = 0xc000_0102;
+
+/// Registers `read_checked` and `write_checked` accept.
+pub const ALLOWED: &[(u32, &str)] = &[
+    (IA32_TSC, "tsc"),
+    (IA32_APIC_BASE, "apic_base"),
+    (IA32_PMC0, "pmc0"),
+    (IA32_PERFEVTSEL0, "perfevtsel0"),
+    (IA32_THERM_STATUS, "therm_status"),
+    (IA32_PACKAGE_THERM_STATUS, "package_therm_status"),
+    (IA32_PAT, "pat"),
+    (IA32_FIXED_CTR0, "fixed_ctr0"),
+    (IA32_FIXED_CTR_CTRL, "fixed_ctr_ctrl"),
+    (IA32_PERF_GLOBAL_STATUS, "perf_global_status"),
+    (IA32_PERF_GLOBAL_CTRL, "perf_global_ctrl"),
+    (IA32_EFER, "efer"),
+    (IA32_STAR, "star"),
+    (IA32_LSTAR, "lstar"),
+    (IA32_FMASK, "fmask"),
+    (IA32_FS_BASE, "fs_base"),
+    (IA32_GS_BASE, "gs_base"),
+    (IA32_KERNEL_GS_BASE, "kernel_gs_base"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NotAllowed,
+    /// Raw access is compiled out of release builds.
+    Disabled,
+}
+
+/// Reads `msr`, which must exist on this CPU or the read raises #GP.
+///
+/// # Safety
+/// Some MSRs have side effects on read.
+pub unsafe fn read(msr: u32) -> u64 {
+    Msr::new(msr).read()
+}
+
+/// # Safety
+/// Writing MSRs can change paging, interrupt delivery or anything else.
+pub unsafe fn write(msr: u32, value: u64) {
+    Msr::new(msr).write(value)
+}
+
+pub fn efer() -> EferFlags {
+    EferFlags::from_bits_truncate(unsafe { read(IA32_EFER) })
+}
+
+/// # Safety
+/// Clearing `LONG_MODE_ENABLE` or `NO_EXECUTE_ENABLE` breaks the kernel.
+pub unsafe fn set_efer(flags: EferFlags) {
+    write(IA32_EFER, flags.bits())
+}
+
+/// The local APIC's base address and mode bits.
+#[derive(Debug, Clone, Copy)]
+pub struct ApicBase {
+    pub addr: PhysAddr,
+    pub bootstrap: bool,
+    pub x2apic: bool,
+    pub enabled: bool,
+}
+
+const APIC_BSP: u64 = 1 << 8;
+const APIC_X2APIC: u64 = 1 << 10;
+const APIC_ENABLE: u64 = 1 << 11;
+
+pub fn apic_base() -> ApicBase {
+    let value = unsafe { read(IA32_APIC_BASE) };
+    ApicBase {
+        addr: PhysAddr::new(value & 0xf_ffff_f000),
+        bootstrap: value & APIC_BSP != 0,
+        x2apic: value & APIC_X2APIC != 0,
+        enabled: value & APIC_ENABLE != 0,
+    }
+}
+
+/// # Safety
+/// Moving or disabling the APIC under its users breaks interrupts.
+pub unsafe fn set_apic_base(base: ApicBase) {
+    let mut value = base.addr.as_u64();
+    if base.bootstrap {
+        value |= APIC_BSP;
+    }
+    if base.x2apic {
+        value |= APIC_X2APIC;
+    }
+    if base.enabled {
+        value |= APIC_ENABLE;
+    }
+    write(IA32_APIC_BASE, value)
+}
+
+/// The eight PAT entries, entry 0 in the low byte.
+pub fn pat() -> u64 {
+    unsafe { read(IA32_PAT) }
+}
+
+/// # Safety
+/// Changes the caching of every mapping that selects a changed entry.
+pub unsafe fn set_pat(value: u64) {
+    write(IA32_PAT, value)
+}
+
+pub fn fs_base() -> VirtAddr {
+    VirtAddr::new_truncate(unsafe { read(IA32_FS_BASE) })
+}
+
+/// # Safety
+/// Code addressing through `fs` sees different memory afterwards.
+pub unsafe fn set_fs_base(addr: VirtAddr) {
+    write(IA32_FS_BASE, addr.as_u64())
+}
+
+pub fn gs_base() -> VirtAddr {
+    VirtAddr::new_truncate(unsafe { read(IA32_GS_BASE) })
+}
+
+/// # Safety
+/// Code addressing through `gs` sees different memory afterwards.
+pub unsafe fn set_gs_base(addr: VirtAddr) {
+    write(IA32_GS_BASE, addr.as_u64())
+}
+
+pub fn kernel_gs_base() -> VirtAddr {
+    VirtAddr::new_truncate(unsafe { read(IA32_KERNEL_GS_BASE) })
+}
+
+/// # Safety
+/// `swapgs` installs this as the `gs` base.
+pub unsafe fn set_kernel_gs_base(addr: VirtAddr) {
+    write(IA32_KERNEL_GS_BASE, addr.as_u64())
+}
+
+/// Segment selectors `syscall`/`sysret` load, as laid out in `STAR`.
+pub fn star() -> u64 {
+    unsafe { read(IA32_STAR) }
+}
+
+/// # Safety
+/// Wrong selectors fault on the next `syscall` or `sysret`.
+pub unsafe fn set_star(value: u64) {
+    write(IA32_STAR, value)
+}
+
+/// Where `syscall` jumps.
+pub fn lstar() -> VirtAddr {
+    VirtAddr::new_truncate(unsafe { read(IA32_LSTAR) })
+}
+
+/// # Safety
+/// The next `syscall` jumps to `entry` in ring 0.
+pub unsafe fn set_lstar(entry: VirtAddr) {
+    write(IA32_LSTAR, entry.as_u64())
+}
+
+/// RFLAGS bits `syscall` clears.
+pub fn fmask() -> u64 {
+    unsafe { read(IA32_FMASK) }
+}
+
+/// # Safety
+/// Leaving `IF` set lets interrupts in before the entry switched stacks.
+pub unsafe fn set_fmask(value: u64) {
+    write(IA32_FMASK, value)
+}
+
+fn check(msr: u32) -> Result<(), Error> {
+    if !cfg!(debug_assertions) {
+        return Err(Error::Disabled);
+    }
+    if ALLOWED.iter().any(|&(allowed, _)| allowed == msr) {
+        Ok(())
+    } else {
+        Err(Error::NotAllowed)
+    }
+}
+
+/// Reads any register on `ALLOWED`, in debug builds.
+pub fn read_checked(msr: u32) -> Result<u64, Error> {
+    check(msr)?;
+    Ok(unsafe { read(msr) })
+}
+
+/// Writes any register on `ALLOWED`, in debug builds.
+pub fn write_checked(msr: u32, value: u64) -> Result<(), Error> {
+    check(msr)?;
+    unsafe { write(msr, value) };
+    Ok(())
+}
+
+/// `msr [<name>|<index> [value]]`
+pub fn command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let msr = match args.first() {
+        None => {
+            for &(msr, name) in ALLOWED {
+                let value = read_checked(msr).map_err(shell_err)?;
+                writeln!(out, "{:<20}{:#010x} {:#018x}", name, msr, value)?;
+            }
+            return Ok(());
+        }
+        Some(arg) => ALLOWED
+            .iter()
+            .find(|&&(_, name)| name == *arg)
+            .map(|&(msr, _)| msr)
+            .or_else(|| parse_number(arg).ok().map(|msr| msr as u32))
+            .ok_or_else(|| ShellErr::new("unknown MSR"))?,
+    };
+    let value = match args.get(1) {
+        Some(value) => {
+            let value = parse_number(value)?;
+            write_checked(msr, value).map_err(shell_err)?;
+            value
+        }
+        None => read_checked(msr).map_err(shell_err)?,
+    };
+    writeln!(out, "{:#010x} = {:#018x}", msr, value)?;
+    Ok(())
+}
+
+fn shell_err(err: Error) -> ShellErr {
+    match err {
+        Error::NotAllowed => ShellErr::new("not on the allow-list"),
+        Error::Disabled => ShellErr::new("only available in debug builds"),
+    }
+}
@@ -0,0 +1,90 @@
+//! The page attribute table, which turns the PWT, PCD and PAT bits of a
+//! page table entry into a memory type. Entries 0-3 keep their power-on
+//! values, so existing mappings mean what they did; entry 4 becomes
+//! write-combining, which framebuffers need to not crawl.
+
+use super::{features, msr};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::{instructions::tlb, structures::paging::PageTableFlags};
+
+const UC: u64 = 0x00;
+const WC: u64 = 0x01;
+const WT: u64 = 0x04;
+const WB: u64 = 0x06;
+const UC_MINUS: u64 = 0x07;
+
+const LAYOUT: [u64; 8] = [WB, WT, UC_MINUS, UC, WC, WT, UC_MINUS, UC];
+
+/// Bit 7 of a 4 KiB page table entry; the same bit means huge page higher up.
+const PTE_PAT: PageTableFlags = PageTableFlags::HUGE_PAGE;
+
+static PROGRAMMED: AtomicBool = AtomicBool::new(false);
+
+/// How the CPU may cache accesses through a mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    WriteBack,
+    WriteThrough,
+    /// Writes are buffered and merged, reads are uncached. For framebuffers.
+    WriteCombining,
+    /// For device registers.
+    Uncacheable,
+}
+
+impl CacheMode {
+    /// The entry bits selecting this mode, for 4 KiB pages. Falls back to
+    /// uncacheable for write-combining if the PAT could not be programmed.
+    pub fn flags(self) -> PageTableFlags {
+        match self {
+            CacheMode::WriteBack => PageTableFlags::empty(),
+            CacheMode::WriteThrough => PageTableFlags::WRITE_THROUGH,
+            CacheMode::WriteCombining if PROGRAMMED.load(Ordering::Relaxed) => PTE_PAT,
+            CacheMode::WriteCombining | CacheMode::Uncacheable => {
+                PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH
+            }
+        }
+    }
+}
+
+/// Programs the PAT if the CPU has one. Run before anything is mapped
+/// write-combining.
+pub fn init() {
+    if !features().pat {
+        info!("pat: not supported, write-combining maps uncacheable");
+        return;
+    }
+    let value = LAYOUT
+        .iter()
+        .enumerate()
+        .fold(0, |value, (i, &kind)| value | kind << (i * 8));
+    unsafe {
+        asm!("wbinvd", options(nostack));
+        msr::set_pat(value);
+        asm!("wbinvd", options(nostack));
+    }
+    tlb::flush_all();
+    PROGRAMMED.store(true, Ordering::Relaxed);
+    info!("pat: {:#018x}", value);
+}
+
+#[test_case]
+fn maps_write_combining() {
+    use crate::memory::{self, MAPPER};
+    use x86_64::{
+        structures::paging::{mapper::TranslateResult, Translate},
+        PhysAddr,
+    };
+
+    // the VGA text buffer, there on every PC
+    let phys = PhysAddr::new(0xb8000);
+    let virt = memory::map_mmio_with(phys, 4096, CacheMode::WriteCombining).unwrap();
+    let flags = match MAPPER.try_get().unwrap().lock().translate(virt) {
+        TranslateResult::Mapped { flags, .. } => flags,
+        _ => panic!("not mapped"),
+    };
+    assert_eq!(flags.contains(PTE_PAT), PROGRAMMED.load(Ordering::Relaxed));
+    let direct = memory::phys_to_virt(phys).unwrap();
+    assert_eq!(unsafe { virt.as_ptr::<u16>().read_volatile() }, unsafe {
+        direct.as_ptr::<u16>().read_volatile()
+    });
+}
@@ -0,0 +1,291 @@
+//! Hardware performance counters. The fixed counters (instructions retired,
+//! core cycles, reference cycles) run free once `init` has enabled them;
+//! general counters are handed out one event at a time by `allocate`.
+//! General counter 0 belongs to the NMI watchdog and is never handed out.
+//!
+//! Overflow interrupts arrive as NMIs through the local APIC's performance
+//! LVT entry, the same path the watchdog uses, and handlers run in NMI
+//! context: no locks, no allocation.
+
+use super::{features, msr};
+use crate::{memory, shell::ShellErr};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// General counters this module tracks, at most.
+const MAX_GENERAL: usize = 8;
+const WATCHDOG_COUNTER: u32 = 1 << 0;
+
+const EVENT_USR: u64 = 1 << 16;
+const EVENT_OS: u64 = 1 << 17;
+const EVENT_INT: u64 = 1 << 20;
+const EVENT_EN: u64 = 1 << 22;
+/// Count in ring 0 and ring 3, per fixed counter.
+const FIXED_OS_USR: u64 = 0b11;
+const FIXED_GLOBAL_SHIFT: u32 = 32;
+
+const APIC_LVT_PERF: u64 = 0x340;
+const DELIVERY_NMI: u32 = 0b100 << 8;
+
+/// Counter writes only take 32 bits, sign-extended.
+const MAX_PERIOD: u64 = 0x7fff_ffff;
+
+/// General counters in use, one bit each.
+static IN_USE: AtomicU32 = AtomicU32::new(WATCHDOG_COUNTER);
+static FIXED_ENABLED: AtomicU32 = AtomicU32::new(0);
+static LAPIC: AtomicU64 = AtomicU64::new(0);
+/// Overflow handler per general counter, as `fn` pointers; 0 for none.
+static HANDLERS: [AtomicUsize; MAX_GENERAL] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+static PERIODS: [AtomicU64; MAX_GENERAL] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No architectural performance monitoring, or too old a version.
+    Unsupported,
+    /// Every general counter is taken.
+    Busy,
+    MapFailed,
+}
+
+/// The fixed-function counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixed {
+    InstructionsRetired = 0,
+    CoreCycles = 1,
+    ReferenceCycles = 2,
+}
+
+/// Events for general counters. The named ones are architectural, so they
+/// mean the same on every Intel CPU with a PMU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    LlcReferences,
+    LlcMisses,
+    BranchesRetired,
+    BranchMisses,
+    Raw { event: u8, umask: u8 },
+}
+
+impl Event {
+    fn select(self) -> u64 {
+        let (event, umask) = match self {
+            Event::LlcReferences => (0x2e, 0x4f),
+            Event::LlcMisses => (0x2e, 0x41),
+            Event::BranchesRetired => (0xc4, 0x00),
+            Event::BranchMisses => (0xc5, 0x00),
+            Event::Raw { event, umask } => (event, umask),
+        };
+        u64::from(event) | u64::from(umask) << 8
+    }
+}
+
+/// Starts the fixed counters. Needs version 2, which added the global
+/// control register.
+pub fn init() {
+    let features = features();
+    if features.pmu_version < 2 || features.pmu_fixed_counters == 0 {
+        info!("pmu: version {}, no fixed counters", features.pmu_version);
+        return;
+    }
+    let fixed = u32::from(features.pmu_fixed_counters.min(3));
+    let control = (0..fixed).fold(0, |control, i| control | FIXED_OS_USR << (i * 4));
+    let global = ((1 << fixed) - 1) << FIXED_GLOBAL_SHIFT;
+    unsafe {
+        msr::write(msr::IA32_FIXED_CTR_CTRL, control);
+        msr::write(
+            msr::IA32_PERF_GLOBAL_CTRL,
+            msr::read(msr::IA32_PERF_GLOBAL_CTRL) | global,
+        );
+    }
+    FIXED_ENABLED.store((1 << fixed) - 1, Ordering::SeqCst);
+    info!(
+        "pmu: version {}, {} general counters, {} fixed",
+        features.pmu_version, features.pmu_counters, fixed
+    );
+}
+
+/// The current value of a fixed counter, if it is running.
+pub fn read_fixed(counter: Fixed) -> Option<u64> {
+    let index = counter as u32;
+    if FIXED_ENABLED.load(Ordering::Relaxed) & 1 << index == 0 {
+        return None;
+    }
+    Some(unsafe { msr::read(msr::IA32_FIXED_CTR0 + index) })
+}
+
+/// Instructions retired so far, the usual reason to look at a counter.
+pub fn instructions() -> Option<u64> {
+    read_fixed(Fixed::InstructionsRetired)
+}
+
+/// A general counter programmed for one event, freed on drop.
+#[derive(Debug)]
+pub struct Counter {
+    index: u32,
+    event: Event,
+}
+
+/// Claims a free general counter and starts it counting `event`.
+pub fn allocate(event: Event) -> Result<Counter, Error> {
+    let features = features();
+    if features.pmu_version == 0 {
+        return Err(Error::Unsupported);
+    }
+    let count = u32::from(features.pmu_counters).min(MAX_GENERAL as u32);
+    let index = loop {
+        let in_use = IN_USE.load(Ordering::SeqCst);
+        let index = (0..count)
+            .find(|i| in_use & 1 << i == 0)
+            .ok_or(Error::Busy)?;
+        if IN_USE
+            .compare_exchange(
+                in_use,
+                in_use | 1 << index,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_ok()
+        {
+            break index;
+        }
+    };
+    let counter = Counter { index, event };
+    unsafe {
+        msr::write(msr::IA32_PMC0 + index, 0);
+        counter.select(0);
+    }
+    Ok(counter)
+}
+
+impl Counter {
+    pub fn event(&self) -> Event {
+        self.event
+    }
+
+    pub fn read(&self) -> u64 {
+        unsafe { msr::read(msr::IA32_PMC0 + self.index) }
+    }
+
+    pub fn reset(&self) {
+        unsafe { msr::write(msr::IA32_PMC0 + self.index, 0) };
+    }
+
+    /// Raises an NMI every `period` events and runs `handler` from it.
+    /// Needs version 2 to tell which counter overflowed.
+    pub fn on_overflow(&self, period: u64, handler: fn(&InterruptStackFrame)) -> Result<(), Error> {
+        if features().pmu_version < 2 {
+            return Err(Error::Unsupported);
+        }
+        if LAPIC.load(Ordering::SeqCst) == 0 {
+            let lapic =
+                memory::map_mmio(msr::apic_base().addr, 4096).map_err(|_| Error::MapFailed)?;
+            LAPIC.store(lapic.as_u64(), Ordering::SeqCst);
+        }
+        let period = period.max(1).min(MAX_PERIOD);
+        let slot = self.index as usize;
+        PERIODS[slot].store(period, Ordering::SeqCst);
+        HANDLERS[slot].store(handler as usize, Ordering::SeqCst);
+        unsafe {
+            msr::write(msr::IA32_PMC0 + self.index, period.wrapping_neg());
+            self.select(EVENT_INT);
+            write_apic(APIC_LVT_PERF, DELIVERY_NMI);
+        }
+        Ok(())
+    }
+
+    unsafe fn select(&self, extra: u64) {
+        msr::write(
+            msr::IA32_PERFEVTSEL0 + self.index,
+            self.event.select() | EVENT_USR | EVENT_OS | EVENT_EN | extra,
+        );
+    }
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        unsafe { msr::write(msr::IA32_PERFEVTSEL0 + self.index, 0) };
+        HANDLERS[self.index as usize].store(0, Ordering::SeqCst);
+        IN_USE.fetch_and(!(1 << self.index), Ordering::SeqCst);
+    }
+}
+
+/// Called by the NMI handler; `false` if no counter with a handler
+/// overflowed, so the NMI came from somewhere else.
+pub(crate) fn nmi(stack_frame: &InterruptStackFrame) -> bool {
+    if LAPIC.load(Ordering::SeqCst) == 0 {
+        return false;
+    }
+    let status = unsafe { msr::read(msr::IA32_PERF_GLOBAL_STATUS) };
+    let mut handled = 0;
+    for (slot, handler) in HANDLERS.iter().enumerate() {
+        let handler = handler.load(Ordering::SeqCst);
+        if handler == 0 || status & 1 << slot == 0 {
+            continue;
+        }
+        let period = PERIODS[slot].load(Ordering::SeqCst);
+        unsafe { msr::write(msr::IA32_PMC0 + slot as u32, period.wrapping_neg()) };
+        let handler: fn(&InterruptStackFrame) = unsafe { core::mem::transmute(handler) };
+        handler(stack_frame);
+        handled |= 1 << slot;
+    }
+    if handled == 0 {
+        return false;
+    }
+    unsafe {
+        msr::write(msr::IA32_PERF_GLOBAL_OVF_CTRL, handled);
+        // delivering the NMI masked the entry
+        write_apic(APIC_LVT_PERF, DELIVERY_NMI);
+    }
+    true
+}
+
+unsafe fn write_apic(offset: u64, value: u32) {
+    core::ptr::write_volatile((LAPIC.load(Ordering::SeqCst) + offset) as *mut u32, value)
+}
+
+/// `pmu`
+pub fn command(_args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let features = features();
+    if features.pmu_version == 0 {
+        return Err(ShellErr::new("no performance counters"));
+    }
+    writeln!(
+        out,
+        "version {}, {} general counters ({} in use)",
+        features.pmu_version,
+        features.pmu_counters,
+        IN_USE.load(Ordering::Relaxed).count_ones()
+    )?;
+    for &(name, counter) in &[
+        ("instructions", Fixed::InstructionsRetired),
+        ("core cycles", Fixed::CoreCycles),
+        ("ref cycles", Fixed::ReferenceCycles),
+    ] {
+        match read_fixed(counter) {
+            Some(value) => writeln!(out, "{:<14}{}", name, value)?,
+            None => writeln!(out, "{:<14}off", name)?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Supervisor protections: SMEP stops the kernel executing user pages,
+//! SMAP stops it touching them outside `user_access_begin`/`end`, and UMIP
+//! hides `sgdt`/`sidt`/`str` from user mode. Each is only turned on when
+//! `features` reports it.
+
+use super::features;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::registers::control::Cr4;
+
+const CR4_UMIP: u64 = 1 << 11;
+const CR4_SMEP: u64 = 1 << 20;
+const CR4_SMAP: u64 = 1 << 21;
+
+static SMAP: AtomicBool = AtomicBool::new(false);
+
+pub fn init() {
+    let features = features();
+    let mut enable = 0;
+    if features.smep {
+        enable |= CR4_SMEP;
+    }
+    if features.smap {
+        enable |= CR4_SMAP;
+    }
+    if features.umip {
+        enable |= CR4_UMIP;
+    }
+    unsafe { Cr4::write_raw(Cr4::read_raw() | enable) };
+    SMAP.store(features.smap, Ordering::Relaxed);
+    info!(
+        "cpu: smep {}, smap {}, umip {}",
+        features.smep, features.smap, features.umip
+    );
+}
+
+pub fn smap_enabled() -> bool {
+    SMAP.load(Ordering::Relaxed)
+}
+
+/// Allows kernel accesses to user pages until `user_access_end`. Keep the
+/// window to the copy itself; anything else in it defeats SMAP.
+///
+/// Neither this nor `user_access_end` is `nomem`: both must act as
+/// compiler barriers, or the user accesses could be moved out of the
+/// window.
+#[inline(always)]
+pub fn user_access_begin() {
+    if smap_enabled() {
+        unsafe { asm!("stac", options(nostack)) };
+    }
+}
+
+#[inline(always)]
+pub fn user_access_end() {
+    if smap_enabled() {
+        unsafe { asm!("clac", options(nostack)) };
+    }
+}
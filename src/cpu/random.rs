@@ -0,0 +1,50 @@
+//! Kernel random numbers: RDRAND when the CPU has it, otherwise a
+//! TSC-seeded xorshift64*. Fine for address randomization, not for keys.
+
+use super::features;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+pub fn next_u64() -> u64 {
+    if features().rdrand {
+        if let Some(value) = rdrand() {
+            return value;
+        }
+    }
+    xorshift()
+}
+
+pub fn fill(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = next_u64().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
+
+/// RDRAND may run dry under load, so it gets a few tries.
+fn rdrand() -> Option<u64> {
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack))
+        };
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn xorshift() -> u64 {
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = unsafe { core::arch::x86_64::_rdtsc() } | 1;
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    STATE.store(x, Ordering::Relaxed);
+    x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
@@ -0,0 +1,172 @@
+//! CPU temperature from Intel's digital thermal sensors, where CPUID leaf
+//! 6 has them; hypervisors usually do not, and then there is nothing to
+//! read.
+//!
+//! The sensors give degrees below TjMax, the temperature at which the CPU
+//! throttles itself. `thermal_task` samples them, counts the throttling
+//! episodes the sticky log bit records and warns once the CPU is within
+//! `WARN_MARGIN` degrees of TjMax.
+
+use super::{features, msr};
+use crate::{shell::ShellErr, time};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+};
+
+/// Bits of `IA32_THERM_STATUS` and `IA32_PACKAGE_THERM_STATUS`.
+const STATUS: u64 = 1 << 0;
+/// Sticky: throttled since last cleared; cleared by writing 0.
+const LOG: u64 = 1 << 1;
+const READOUT_SHIFT: u32 = 16;
+const READOUT_MASK: u64 = 0x7f;
+const VALID: u64 = 1 << 31;
+/// `MSR_TEMPERATURE_TARGET` bits 23:16.
+const TARGET_SHIFT: u32 = 16;
+
+/// Assumed where `MSR_TEMPERATURE_TARGET` does not exist.
+const DEFAULT_TJ_MAX: u8 = 100;
+/// The first model with `MSR_TEMPERATURE_TARGET` (Nehalem).
+const TARGET_MIN_MODEL: u32 = 0x1a;
+/// Degrees below TjMax at which `thermal_task` warns.
+pub const WARN_MARGIN: u8 = 10;
+const POLL_MS: u64 = 1000;
+
+static TJ_MAX: AtomicU8 = AtomicU8::new(DEFAULT_TJ_MAX);
+static THROTTLE_EVENTS: AtomicU64 = AtomicU64::new(0);
+static HOT: AtomicBool = AtomicBool::new(false);
+
+/// One sensor's state.
+#[derive(Debug, Clone, Copy)]
+pub struct Reading {
+    pub celsius: u8,
+    pub tj_max: u8,
+    /// Throttling right now.
+    pub throttling: bool,
+    /// Throttled at some point since `thermal_task` last cleared the log.
+    pub throttled: bool,
+}
+
+impl Reading {
+    fn parse(status: u64) -> Option<Reading> {
+        if status & VALID == 0 {
+            return None;
+        }
+        let tj_max = TJ_MAX.load(Ordering::Relaxed);
+        let below = ((status >> READOUT_SHIFT) & READOUT_MASK) as u8;
+        Some(Reading {
+            celsius: tj_max.saturating_sub(below),
+            tj_max,
+            throttling: status & STATUS != 0,
+            throttled: status & LOG != 0,
+        })
+    }
+}
+
+pub fn is_supported() -> bool {
+    let features = features();
+    features.msr && features.digital_thermal_sensor
+}
+
+/// Reads TjMax where the CPU says it.
+pub fn init() {
+    if !is_supported() {
+        return;
+    }
+    let features = features();
+    if features.vendor() == "GenuineIntel"
+        && features.family == 6
+        && features.model >= TARGET_MIN_MODEL
+    {
+        let target = unsafe { msr::read(msr::MSR_TEMPERATURE_TARGET) };
+        let tj_max = (target >> TARGET_SHIFT) as u8;
+        if tj_max != 0 {
+            TJ_MAX.store(tj_max, Ordering::Relaxed);
+        }
+    }
+    info!(
+        "cpu: thermal sensor, TjMax {} C",
+        TJ_MAX.load(Ordering::Relaxed)
+    );
+}
+
+/// The boot CPU's core sensor, leaving its log bit alone.
+pub fn core() -> Option<Reading> {
+    if !is_supported() {
+        return None;
+    }
+    Reading::parse(unsafe { msr::read(msr::IA32_THERM_STATUS) })
+}
+
+/// The package sensor, where there is one.
+pub fn package() -> Option<Reading> {
+    if !is_supported() || !features().package_thermal {
+        return None;
+    }
+    Reading::parse(unsafe { msr::read(msr::IA32_PACKAGE_THERM_STATUS) })
+}
+
+/// Throttling episodes `thermal_task` has seen since boot.
+pub fn throttle_events() -> u64 {
+    THROTTLE_EVENTS.load(Ordering::Relaxed)
+}
+
+/// Reads the core sensor and clears its log bit, counting a throttling
+/// episode if it was set.
+fn sample() -> Option<Reading> {
+    let reading = core()?;
+    if reading.throttled {
+        // the log bits are the only writable ones; 0 clears them all
+        unsafe { msr::write(msr::IA32_THERM_STATUS, 0) };
+        THROTTLE_EVENTS.fetch_add(1, Ordering::Relaxed);
+        warn!("cpu: throttled at {} C", reading.celsius);
+    }
+    Some(reading)
+}
+
+/// Samples the sensor every `POLL_MS`, warning when the CPU gets within
+/// `WARN_MARGIN` of TjMax and again once it has cooled down.
+pub async fn thermal_task() {
+    if !is_supported() {
+        return;
+    }
+    loop {
+        if let Some(reading) = sample() {
+            let hot = reading.celsius.saturating_add(WARN_MARGIN) >= reading.tj_max;
+            if hot != HOT.swap(hot, Ordering::Relaxed) {
+                if hot {
+                    warn!(
+                        "cpu: {} C, within {} C of TjMax",
+                        reading.celsius,
+                        reading.tj_max - reading.celsius
+                    );
+                } else {
+                    info!("cpu: cooled down to {} C", reading.celsius);
+                }
+            }
+        }
+        time::sleep(POLL_MS).await;
+    }
+}
+
+/// `thermal`
+pub fn command(_args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    if !is_supported() {
+        return Err(ShellErr::new("no digital thermal sensor"));
+    }
+    for &(name, reading) in &[("core", core()), ("package", package())] {
+        match reading {
+            Some(r) => writeln!(
+                out,
+                "{:<8}{} C (TjMax {} C){}",
+                name,
+                r.celsius,
+                r.tj_max,
+                if r.throttling { ", throttling" } else { "" }
+            )?,
+            None => writeln!(out, "{:<8}no reading", name)?,
+        }
+    }
+    writeln!(out, "throttle events {}", throttle_events())?;
+    Ok(())
+}
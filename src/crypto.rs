@@ -0,0 +1,46 @@
+//! Cryptographic primitives for integrity checks and, later, network
+//! authentication. Nothing here allocates, so it works before the heap
+//! and from the panic handler.
+//!
+//! - `sha256`: SHA-256, one-shot or streamed
+//! - `hmac`: HMAC-SHA-256
+//! - `ct_eq`: comparison of digests and tags that does not leak where
+//!   they differ
+
+pub mod hmac;
+pub mod sha256;
+
+pub use self::hmac::HmacSha256;
+pub use self::sha256::Sha256;
+
+/// Whether `a` and `b` hold the same bytes, in time that depends only on
+/// their lengths, which are not secret. Compare tags with this, never
+/// with `==`, which stops at the first difference.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b) {
+        diff |= x ^ y;
+    }
+    // keeps the compiler from turning the loop into an early exit
+    unsafe { core::ptr::read_volatile(&diff) == 0 }
+}
+
+/// A digest written out in hex, for the test vectors.
+#[cfg(test)]
+fn hex(text: &str) -> sha256::Digest {
+    let mut digest = [0; sha256::DIGEST_LEN];
+    for (byte, pair) in digest.iter_mut().zip(text.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(pair).unwrap(), 16).unwrap();
+    }
+    digest
+}
+
+#[test_case]
+fn ct_eq_compares_bytes_and_lengths() {
+    assert!(ct_eq(b"tag", b"tag"));
+    assert!(!ct_eq(b"tag", b"tab"));
+    assert!(!ct_eq(b"tag", b"tags"));
+}
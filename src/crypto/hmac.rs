@@ -0,0 +1,95 @@
+//! HMAC-SHA-256 (RFC 2104).
+
+use super::{
+    ct_eq,
+    sha256::{self, Digest, Sha256, BLOCK_LEN, DIGEST_LEN},
+};
+
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+/// A MAC in progress; feed it with `update`, then `finish` or `verify`.
+#[derive(Clone)]
+pub struct HmacSha256 {
+    inner: Sha256,
+    /// The key padded to a block and XORed with `OPAD`.
+    outer_key: [u8; BLOCK_LEN],
+}
+
+impl HmacSha256 {
+    /// Keys longer than a block are hashed first, as the RFC says.
+    pub fn new(key: &[u8]) -> Self {
+        let mut padded = [0; BLOCK_LEN];
+        if key.len() > BLOCK_LEN {
+            padded[..DIGEST_LEN].copy_from_slice(&sha256::digest(key));
+        } else {
+            padded[..key.len()].copy_from_slice(key);
+        }
+        let mut inner_key = padded;
+        let mut outer_key = padded;
+        for (inner, outer) in inner_key.iter_mut().zip(outer_key.iter_mut()) {
+            *inner ^= IPAD;
+            *outer ^= OPAD;
+        }
+        let mut inner = Sha256::new();
+        inner.update(&inner_key);
+        HmacSha256 { inner, outer_key }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finish(self) -> Digest {
+        let mut outer = Sha256::new();
+        outer.update(&self.outer_key);
+        outer.update(&self.inner.finish());
+        outer.finish()
+    }
+
+    /// Whether `tag` is the MAC of what was fed in, compared in constant
+    /// time.
+    pub fn verify(self, tag: &[u8]) -> bool {
+        ct_eq(&self.finish(), tag)
+    }
+}
+
+/// The HMAC-SHA-256 of `data` under `key`.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Digest {
+    let mut mac = HmacSha256::new(key);
+    mac.update(data);
+    mac.finish()
+}
+
+#[test_case]
+fn hmac_matches_rfc4231() {
+    use super::hex;
+    assert_eq!(
+        hmac_sha256(&[0x0b; 20], b"Hi There"),
+        hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+    );
+    assert_eq!(
+        hmac_sha256(b"Jefe", b"what do ya want for nothing?"),
+        hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+    );
+    // a key longer than a block is hashed first
+    assert_eq!(
+        hmac_sha256(
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First"
+        ),
+        hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+    );
+}
+
+#[test_case]
+fn verify_rejects_a_wrong_tag() {
+    let tag = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+    let mut mac = HmacSha256::new(b"Jefe");
+    mac.update(b"what do ya want ");
+    mac.update(b"for nothing?");
+    assert!(mac.clone().verify(&tag));
+    let mut wrong = tag;
+    wrong[31] ^= 1;
+    assert!(!mac.verify(&wrong));
+}
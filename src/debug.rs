@@ -0,0 +1,100 @@
+use core::{fmt::Write, panic::PanicInfo};
+use x86_64::instructions::port::Port;
+
+pub mod backtrace;
+pub mod canary;
+pub mod crash_dump;
+pub mod early_console;
+pub mod fault;
+pub mod gdb;
+pub mod lockdep;
+pub mod monitor;
+pub mod panic_screen;
+pub mod symbols;
+pub mod watchdog;
+
+/// Port of QEMU's `isa-debug-exit` device, see `package.metadata.bootimage`.
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// Values written to `isa-debug-exit`; QEMU exits with `(value << 1) | 1`,
+/// so 33 (`test-success-exit-code`) on success and 35 on failure.
+const EXIT_SUCCESS: u32 = 0x10;
+const EXIT_FAILURE: u32 = 0x11;
+
+/// Terminates QEMU with the success or failure exit code. Halts instead on
+/// machines without the device.
+pub fn exit_qemu(success: bool) -> ! {
+    try_exit_qemu(success);
+    crate::hlt_loop()
+}
+
+/// Terminates QEMU like `exit_qemu`, but returns on machines without the
+/// device.
+pub fn try_exit_qemu(success: bool) {
+    let code = if success { EXIT_SUCCESS } else { EXIT_FAILURE };
+    unsafe {
+        let mut port = Port::new(ISA_DEBUG_EXIT_PORT);
+        port.write(code);
+    }
+}
+
+/// A `#[test_case]` that reports its own name over serial.
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+/// Runs every `#[test_case]` and exits QEMU; a failing test panics instead.
+pub fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(true);
+}
+
+/// Reports the panicking test and exits QEMU with a failure.
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    backtrace::dump_to_serial();
+    exit_qemu(false);
+}
+
+/// Whether the formatted panic contains `needle`, for tests that expect a
+/// specific panic. Formats into a stack buffer since the heap may be gone.
+pub fn panic_message_contains(info: &PanicInfo, needle: &str) -> bool {
+    let mut message = Message {
+        bytes: [0; 256],
+        len: 0,
+    };
+    let _ = write!(message, "{}", info);
+    core::str::from_utf8(&message.bytes[..message.len]).map_or(false, |m| m.contains(needle))
+}
+
+struct Message {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+/// Keeps as many whole characters as fit.
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            let width = c.len_utf8();
+            if self.len + width > self.bytes.len() {
+                break;
+            }
+            c.encode_utf8(&mut self.bytes[self.len..]);
+            self.len += width;
+        }
+        Ok(())
+    }
+}
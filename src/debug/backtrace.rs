@@ -0,0 +1,73 @@
+//! Frame-pointer unwinding. Relies on every frame saving `rbp`, which
+//! `-C force-frame-pointers=yes` in `.cargo/config` guarantees. Names come
+//! from `symbols` once the table has been embedded.
+
+use super::symbols;
+use crate::{memory, serial::SERIAL1};
+use core::fmt::{self, Write};
+use x86_64::{instructions::interrupts::without_interrupts, VirtAddr};
+
+/// Frames printed before giving up on a (possibly corrupt) chain.
+pub const MAX_FRAMES: usize = 32;
+
+/// Calls `f` with the return address of every frame above the caller,
+/// innermost first. Stops at the first frame pointer that is null,
+/// unmapped or not above the previous one.
+#[inline(never)]
+pub fn walk(mut f: impl FnMut(u64)) {
+    let mut rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+    for _ in 0..MAX_FRAMES {
+        if rbp == 0 || rbp % 8 != 0 || !readable(rbp) || !readable(rbp + 8) {
+            break;
+        }
+        let (next, return_address) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        if return_address == 0 {
+            break;
+        }
+        f(return_address);
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+}
+
+fn readable(addr: u64) -> bool {
+    VirtAddr::try_new(addr).map_or(false, memory::is_mapped)
+}
+
+pub fn write(w: &mut impl Write) -> fmt::Result {
+    writeln!(w, "backtrace:")?;
+    let mut result = Ok(());
+    let mut depth = 0;
+    walk(|addr| {
+        if result.is_ok() {
+            result = write_frame(w, depth, addr);
+        }
+        depth += 1;
+    });
+    result
+}
+
+/// Writes one symbolized frame, as `write` does for each.
+pub fn write_frame(w: &mut impl Write, depth: usize, addr: u64) -> fmt::Result {
+    match symbols::symbolize(addr) {
+        Some(symbol) => writeln!(
+            w,
+            "  #{:<2} {:#018x} {}+{:#x}",
+            depth, addr, symbol.name, symbol.offset
+        ),
+        None => writeln!(w, "  #{:<2} {:#018x}", depth, addr),
+    }
+}
+
+/// Writes the current backtrace to the serial port. Only try-locks, so it
+/// is safe to call from the panic handler.
+pub fn dump_to_serial() {
+    without_interrupts(|| {
+        if let Some(mut serial) = SERIAL1.try_lock() {
+            let _ = write(&mut *serial);
+        }
+    })
+}
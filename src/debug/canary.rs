@@ -0,0 +1,115 @@
+//! Canary words at the bottom of every kernel stack, checked after each
+//! task poll and periodically from the timer. Guard pages only catch an
+//! overflow that touches them; a large frame can jump straight over one
+//! and land in whatever lies below, which the canary then reports.
+
+use crate::{cpu::random, memory, sync::Mutex, task::TaskId};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{instructions::interrupts::without_interrupts, VirtAddr};
+
+/// Words written at the bottom of each stack.
+const WORDS: usize = 4;
+const MAX_STACKS: usize = 8;
+/// Largest boot stack `register_boot_stack` looks for, in pages.
+const MAX_STACK_PAGES: u64 = 1024;
+/// Timer ticks between checks from the interrupt handler.
+pub const CHECK_INTERVAL: u64 = 64;
+
+static CANARY: AtomicU64 = AtomicU64::new(0);
+static STACKS: Mutex<[Option<Stack>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+#[derive(Clone, Copy)]
+struct Stack {
+    name: &'static str,
+    bottom: u64,
+}
+
+impl Stack {
+    fn words(&self) -> *mut u64 {
+        self.bottom as *mut u64
+    }
+
+    fn intact(&self, canary: u64) -> bool {
+        (0..WORDS).all(|i| unsafe { self.words().add(i).read_volatile() } == canary)
+    }
+}
+
+fn canary() -> u64 {
+    match CANARY.load(Ordering::Relaxed) {
+        0 => {
+            // keep a zero byte so string overruns stop at it
+            let value = random::next_u64() & !0xff | 1 << 8;
+            CANARY.store(value, Ordering::Relaxed);
+            value
+        }
+        value => value,
+    }
+}
+
+/// Writes the canary at `bottom`, the lowest address of a stack that grows
+/// down towards it, and checks it from now on.
+pub fn register(name: &'static str, bottom: VirtAddr) {
+    let stack = Stack {
+        name,
+        bottom: bottom.as_u64(),
+    };
+    let canary = canary();
+    for i in 0..WORDS {
+        unsafe { stack.words().add(i).write_volatile(canary) };
+    }
+    without_interrupts(|| {
+        let mut stacks = STACKS.lock();
+        match stacks.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(stack),
+            None => warn!("canary: no room for the {} stack", name),
+        }
+    });
+}
+
+/// Finds the bottom of the stack we are running on by walking down to the
+/// bootloader's guard page, and registers it.
+pub fn register_boot_stack() {
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)) };
+    let mut bottom = rsp & !0xfff;
+    for _ in 0..MAX_STACK_PAGES {
+        if !memory::is_mapped(VirtAddr::new(bottom - 4096)) {
+            register("kernel", VirtAddr::new(bottom));
+            return;
+        }
+        bottom -= 4096;
+    }
+    warn!("canary: no guard page below the kernel stack");
+}
+
+/// Checks every stack, blaming `task` for an overflow.
+pub fn check(task: Option<TaskId>) {
+    let canary = CANARY.load(Ordering::Relaxed);
+    if canary == 0 {
+        return;
+    }
+    let smashed = without_interrupts(|| {
+        STACKS
+            .try_lock()
+            .and_then(|stacks| stacks.iter().flatten().find(|s| !s.intact(canary)).copied())
+    });
+    if let Some(stack) = smashed {
+        match task {
+            Some(task) => panic!(
+                "stack canary of the {} stack overwritten by task {}",
+                stack.name, task
+            ),
+            None => panic!(
+                "stack canary of the {} stack overwritten outside any task",
+                stack.name
+            ),
+        }
+    }
+}
+
+/// Called from the timer interrupt; checks every `CHECK_INTERVAL` ticks.
+pub(crate) fn tick(ticks: u64) {
+    if ticks % CHECK_INTERVAL == 0 {
+        check(TaskId::current());
+    }
+}
@@ -0,0 +1,214 @@
+//! A crash dump streamed over COM1 on panic, with `crashdump` on the
+//! command line, so a post-mortem needs the serial log rather than a photo
+//! of the screen. There is no disk to keep it on.
+//!
+//! The dump is text, one item per line, `\n`-terminated:
+//!
+//! ```text
+//! -----BEGIN CRASH DUMP v1-----
+//! panic <message>
+//! uptime_ms <n>
+//! task <id>|none
+//! [registers]
+//! <name> <16 hex digits>          one per register, cr0 to cr4 included,
+//!                                 as the panic handler found them
+//! [backtrace]
+//! <16 hex digits> [<symbol>+<offset>]
+//! [log]
+//! <a line of the log ring>
+//! [tasks]
+//! <id> <high|medium|low|-> <state> <polls> <runtime_us>
+//! [heap]
+//! start <hex> size <n> used <n> free <n>
+//! -----END CRASH DUMP <length> <checksum>-----
+//! ```
+//!
+//! `length` is the decimal byte count and `checksum` the FNV-1a 64 hash, in
+//! 16 hex digits, of everything between the newline ending the BEGIN line
+//! and the start of the END line. Sections whose data was locked when the
+//! kernel panicked hold `locked` instead. The port turns `\n` into `\r\n`,
+//! which a reader drops before checking; `tools/crash_dump.py` does so.
+
+use super::{backtrace, panic_screen::Registers, symbols};
+use crate::{
+    allocators,
+    logs::ring::RING,
+    serial::{SerialPort, COM1, SERIAL1},
+    task::{scheduler, Priority, TaskId},
+    time,
+};
+use core::{
+    fmt::{self, Write},
+    panic::PanicInfo,
+};
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    registers::control::{Cr0, Cr2, Cr3, Cr4},
+};
+
+pub const VERSION: u32 = 1;
+pub const BEGIN: &str = "-----BEGIN CRASH DUMP v";
+pub const END: &str = "-----END CRASH DUMP ";
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+/// Counts and hashes what passes through to the port.
+struct Checksummed<W> {
+    inner: W,
+    len: u64,
+    hash: u64,
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.hash = (self.hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+        self.len += s.len() as u64;
+        self.inner.write_str(s)
+    }
+}
+
+/// Streams the dump for `info` over COM1. Only try-locks what it reads,
+/// so it is safe to call from the panic handler.
+pub fn stream(info: &PanicInfo, regs: &Registers) {
+    without_interrupts(|| match SERIAL1.try_lock() {
+        Some(mut serial) => {
+            let _ = write(&mut *serial, info, regs);
+        }
+        None => {
+            // whoever held the port is never coming back
+            let _ = write(&mut unsafe { SerialPort::new(COM1) }, info, regs);
+        }
+    })
+}
+
+fn write(w: &mut impl Write, info: &PanicInfo, regs: &Registers) -> fmt::Result {
+    writeln!(w, "{}{}-----", BEGIN, VERSION)?;
+    let mut body = Checksummed {
+        inner: &mut *w,
+        len: 0,
+        hash: FNV_OFFSET,
+    };
+    write_body(&mut body, info, regs)?;
+    let (len, hash) = (body.len, body.hash);
+    writeln!(w, "{}{} {:016x}-----", END, len, hash)
+}
+
+fn write_body(w: &mut impl Write, info: &PanicInfo, regs: &Registers) -> fmt::Result {
+    // the message on one line, so the format stays line-based
+    write!(w, "panic ")?;
+    write!(OneLine(&mut *w), "{}", info)?;
+    writeln!(w)?;
+    writeln!(w, "uptime_ms {}", time::uptime_ms())?;
+    match TaskId::current() {
+        Some(task) => writeln!(w, "task {}", task)?,
+        None => writeln!(w, "task none")?,
+    }
+
+    writeln!(w, "[registers]")?;
+    let registers = [
+        ("rax", regs.rax),
+        ("rbx", regs.rbx),
+        ("rcx", regs.rcx),
+        ("rdx", regs.rdx),
+        ("rsi", regs.rsi),
+        ("rdi", regs.rdi),
+        ("rbp", regs.rbp),
+        ("rsp", regs.rsp),
+        ("r8", regs.r8),
+        ("r9", regs.r9),
+        ("r10", regs.r10),
+        ("r11", regs.r11),
+        ("r12", regs.r12),
+        ("r13", regs.r13),
+        ("r14", regs.r14),
+        ("r15", regs.r15),
+        ("rflags", regs.rflags),
+        ("cr0", Cr0::read_raw()),
+        ("cr2", Cr2::read().as_u64()),
+        ("cr3", Cr3::read().0.start_address().as_u64()),
+        ("cr4", Cr4::read_raw()),
+    ];
+    for (name, value) in registers.iter() {
+        writeln!(w, "{} {:016x}", name, value)?;
+    }
+
+    writeln!(w, "[backtrace]")?;
+    let mut result = Ok(());
+    backtrace::walk(|addr| {
+        if result.is_ok() {
+            result = match symbols::symbolize(addr) {
+                Some(symbol) => writeln!(w, "{:016x} {}+{:#x}", addr, symbol.name, symbol.offset),
+                None => writeln!(w, "{:016x}", addr),
+            };
+        }
+    });
+    result?;
+
+    writeln!(w, "[log]")?;
+    match RING.try_lock() {
+        Some(ring) => {
+            for entry in ring.iter() {
+                write!(OneLine(&mut *w), "{}", entry)?;
+                writeln!(w)?;
+            }
+        }
+        None => writeln!(w, "locked")?,
+    }
+
+    writeln!(w, "[tasks]")?;
+    let mut result = Ok(());
+    let listed = scheduler::try_for_each_task(|task| {
+        if result.is_ok() {
+            let priority = match task.priority {
+                Some(Priority::High) => "high",
+                Some(Priority::Medium) => "medium",
+                Some(Priority::Low) => "low",
+                None => "-",
+            };
+            result = writeln!(
+                w,
+                "{} {} {} {} {}",
+                task.id.as_u64(),
+                priority,
+                task.state.name(),
+                task.polls,
+                task.runtime_us
+            );
+        }
+    });
+    result?;
+    if !listed {
+        writeln!(w, "locked")?;
+    }
+
+    writeln!(w, "[heap]")?;
+    match allocators::try_usage() {
+        Some((used, free)) => writeln!(
+            w,
+            "start {:#x} size {} used {} free {}",
+            allocators::heap_start(),
+            allocators::heap_size(),
+            used,
+            free
+        ),
+        None => writeln!(w, "locked"),
+    }
+}
+
+/// Writes through with newlines turned into spaces.
+struct OneLine<W>(W);
+
+impl<W: Write> Write for OneLine<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, part) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.0.write_char(' ')?;
+            }
+            self.0.write_str(part)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! Output for the first moments of boot, before `logs::init` has a logger
+//! up. Writes go straight to COM1, polling, and to the VGA text buffer,
+//! without locks or allocation, so they work from the first instruction of
+//! `kernel_main` and from a panic that strikes before the logger exists.
+//!
+//! `retire` hands the screen to the real logger, which carries on below
+//! the early lines instead of clearing them.
+
+use core::{
+    fmt::{self, Write},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use x86_64::instructions::port::Port;
+
+const VGA_BUFFER: usize = 0xb8000;
+const WIDTH: usize = 80;
+const HEIGHT: usize = 25;
+/// Light grey on black.
+const COLOR: u8 = 0x07;
+
+const COM1: u16 = crate::serial::COM1;
+const LINE_STATUS: u16 = COM1 + 5;
+const OUTPUT_EMPTY: u8 = 1 << 5;
+/// Polls of the line status before a byte is dropped, in case there is no
+/// UART to drain it.
+const SERIAL_POLLS: usize = 100_000;
+
+static ACTIVE: AtomicBool = AtomicBool::new(true);
+static SERIAL_READY: AtomicBool = AtomicBool::new(false);
+static SCREEN_CLEARED: AtomicBool = AtomicBool::new(false);
+/// Cell the next character goes to, row-major.
+static POSITION: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the real logger has not taken over yet.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Stops the early console and returns the first screen row it left free.
+pub fn retire() -> usize {
+    ACTIVE.store(false, Ordering::Relaxed);
+    let position = POSITION.load(Ordering::Relaxed);
+    if position == 0 {
+        0
+    } else {
+        (position + WIDTH - 1) / WIDTH
+    }
+}
+
+pub fn write_str(s: &str) {
+    if !is_active() {
+        return;
+    }
+    for byte in s.bytes() {
+        if byte == b'\n' {
+            serial_byte(b'\r');
+        }
+        serial_byte(byte);
+        vga_byte(byte);
+    }
+}
+
+/// 115200 baud, 8N1, FIFOs on and interrupts off, as `SerialPort::init`
+/// leaves it; the real driver programs the same again later.
+fn init_serial() {
+    unsafe {
+        Port::<u8>::new(COM1 + 1).write(0x00);
+        Port::<u8>::new(COM1 + 3).write(0x80);
+        Port::<u8>::new(COM1).write(0x01);
+        Port::<u8>::new(COM1 + 1).write(0x00);
+        Port::<u8>::new(COM1 + 3).write(0x03);
+        Port::<u8>::new(COM1 + 2).write(0xc7);
+        Port::<u8>::new(COM1 + 4).write(0x0b);
+    }
+}
+
+fn serial_byte(byte: u8) {
+    if !SERIAL_READY.swap(true, Ordering::Relaxed) {
+        init_serial();
+    }
+    let mut status = Port::<u8>::new(LINE_STATUS);
+    for _ in 0..SERIAL_POLLS {
+        if unsafe { status.read() } & OUTPUT_EMPTY != 0 {
+            unsafe { Port::<u8>::new(COM1).write(byte) };
+            return;
+        }
+        crate::interrupts::pause();
+    }
+}
+
+fn vga_byte(byte: u8) {
+    let buffer = VGA_BUFFER as *mut u16;
+    if !SCREEN_CLEARED.swap(true, Ordering::Relaxed) {
+        for cell in 0..WIDTH * HEIGHT {
+            unsafe { ptr::write_volatile(buffer.add(cell), u16::from(COLOR) << 8 | 0x20) };
+        }
+    }
+    let mut position = POSITION.load(Ordering::Relaxed);
+    // no scrolling: once the screen is full, start again at the top
+    if position >= WIDTH * HEIGHT {
+        position = 0;
+    }
+    match byte {
+        b'\n' => position = (position / WIDTH + 1) * WIDTH,
+        0x20..=0x7e => {
+            unsafe {
+                ptr::write_volatile(
+                    buffer.add(position),
+                    u16::from(COLOR) << 8 | u16::from(byte),
+                )
+            };
+            position += 1;
+        }
+        _ => {
+            unsafe { ptr::write_volatile(buffer.add(position), u16::from(COLOR) << 8 | 0xfe) };
+            position += 1;
+        }
+    }
+    POSITION.store(position, Ordering::Relaxed);
+}
+
+struct EarlyConsole;
+
+impl Write for EarlyConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self::write_str(s);
+        Ok(())
+    }
+}
+
+pub fn _print(args: fmt::Arguments) {
+    let _ = EarlyConsole.write_fmt(args);
+}
+
+/// Prints through the early console until the logger is up; does nothing
+/// after.
+#[macro_export]
+macro_rules! early_print {
+    ($($arg:tt)*) => {
+        $crate::debug::early_console::_print(format_args!($($arg)*));
+    };
+}
+
+#[macro_export]
+macro_rules! early_println {
+    () => ($crate::early_print!("\n"));
+    ($fmt:expr) => ($crate::early_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::early_print!(
+        concat!($fmt, "\n"), $($arg)*));
+}
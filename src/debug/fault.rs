@@ -0,0 +1,185 @@
+//! Fault injection: makes chosen allocation and I/O sites fail on purpose
+//! so the error paths behind them get run.
+//!
+//! Built only with the `fault-injection` feature; otherwise `should_fail`
+//! is a constant `false` and the hooks compile away. Sites start disabled
+//! and are armed at run time with `set` or the `fault` shell command.
+//!
+//! There is no block layer yet, so `Site::FileIo` fails reads and writes
+//! made through file descriptors instead.
+
+use crate::shell::ShellErr;
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Site {
+    /// `BootInfoFrameAllocator::allocate_frame` returns `None`.
+    FrameAlloc,
+    /// The global allocator returns a null pointer.
+    Heap,
+    /// `fd::read` and `fd::write` return `Error::Io`.
+    FileIo,
+}
+
+const SITES: [Site; 3] = [Site::FrameAlloc, Site::Heap, Site::FileIo];
+
+impl Site {
+    pub fn name(self) -> &'static str {
+        match self {
+            Site::FrameAlloc => "frame",
+            Site::Heap => "heap",
+            Site::FileIo => "io",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        SITES.iter().copied().find(|site| site.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Off,
+    /// Fails every Nth call made while the mode is set.
+    EveryNth(u32),
+    /// Fails each call with this chance, in thousandths.
+    Probability(u32),
+}
+
+const OFF: u8 = 0;
+const EVERY_NTH: u8 = 1;
+const PROBABILITY: u8 = 2;
+
+struct State {
+    kind: AtomicU8,
+    value: AtomicU32,
+    calls: AtomicU32,
+    injected: AtomicU64,
+}
+
+impl State {
+    const fn new() -> Self {
+        State {
+            kind: AtomicU8::new(OFF),
+            value: AtomicU32::new(0),
+            calls: AtomicU32::new(0),
+            injected: AtomicU64::new(0),
+        }
+    }
+}
+
+static STATES: [State; 3] = [State::new(), State::new(), State::new()];
+#[cfg(feature = "fault-injection")]
+static RNG: AtomicU64 = AtomicU64::new(0);
+
+fn state(site: Site) -> &'static State {
+    &STATES[site as usize]
+}
+
+/// Arms or disarms `site`, restarting its call count.
+pub fn set(site: Site, mode: Mode) {
+    let state = state(site);
+    let (kind, value) = match mode {
+        Mode::Off => (OFF, 0),
+        Mode::EveryNth(n) => (EVERY_NTH, n.max(1)),
+        Mode::Probability(permille) => (PROBABILITY, permille.min(1000)),
+    };
+    state.kind.store(OFF, Ordering::Relaxed);
+    state.value.store(value, Ordering::Relaxed);
+    state.calls.store(0, Ordering::Relaxed);
+    state.kind.store(kind, Ordering::Release);
+}
+
+pub fn mode(site: Site) -> Mode {
+    let state = state(site);
+    let value = state.value.load(Ordering::Relaxed);
+    match state.kind.load(Ordering::Acquire) {
+        EVERY_NTH => Mode::EveryNth(value),
+        PROBABILITY => Mode::Probability(value),
+        _ => Mode::Off,
+    }
+}
+
+/// How many failures `site` has injected since boot.
+pub fn injected(site: Site) -> u64 {
+    state(site).injected.load(Ordering::Relaxed)
+}
+
+/// Whether the caller at `site` should pretend to fail this time.
+///
+/// Called from the allocators, so it must not allocate or take locks.
+#[inline]
+pub fn should_fail(site: Site) -> bool {
+    #[cfg(feature = "fault-injection")]
+    {
+        let state = state(site);
+        let fail = match state.kind.load(Ordering::Acquire) {
+            EVERY_NTH => {
+                let n = state.value.load(Ordering::Relaxed);
+                (state.calls.fetch_add(1, Ordering::Relaxed) + 1) % n == 0
+            }
+            PROBABILITY => random() % 1000 < u64::from(state.value.load(Ordering::Relaxed)),
+            _ => false,
+        };
+        if fail {
+            state.injected.fetch_add(1, Ordering::Relaxed);
+        }
+        fail
+    }
+    #[cfg(not(feature = "fault-injection"))]
+    {
+        let _ = site;
+        false
+    }
+}
+
+/// xorshift64, seeded from the TSC on first use.
+#[cfg(feature = "fault-injection")]
+fn random() -> u64 {
+    let mut x = RNG.load(Ordering::Relaxed);
+    if x == 0 {
+        x = unsafe { core::arch::x86_64::_rdtsc() } | 1;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    RNG.store(x, Ordering::Relaxed);
+    x
+}
+
+/// `fault [<site> off|every <n>|prob <permille>]`
+pub fn command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    if !cfg!(feature = "fault-injection") {
+        return Err(ShellErr::new("built without the fault-injection feature"));
+    }
+    let (site, rest) = match args.split_first() {
+        None => {
+            for &site in SITES.iter() {
+                writeln!(
+                    out,
+                    "{:<6}{:?}, {} injected",
+                    site.name(),
+                    mode(site),
+                    injected(site)
+                )?;
+            }
+            return Ok(());
+        }
+        Some((site, rest)) => (
+            Site::from_name(site).ok_or_else(|| ShellErr::new("sites are frame, heap and io"))?,
+            rest,
+        ),
+    };
+    let parse = |n: &str| n.parse().map_err(|_| ShellErr::new("expected a number"));
+    let mode = match rest {
+        ["off"] => Mode::Off,
+        ["every", n] => Mode::EveryNth(parse(n)?),
+        ["prob", permille] => Mode::Probability(parse(permille)?),
+        _ => return Err(ShellErr::new("expected off, every <n> or prob <permille>")),
+    };
+    set(site, mode);
+    Ok(())
+}
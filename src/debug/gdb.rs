@@ -0,0 +1,549 @@
+//! GDB remote serial protocol stub on COM2.
+//!
+//! Point gdb at the port (`target remote /dev/ttyS1` on a null-modem cable,
+//! or QEMU's second `-serial`) and the kernel stops as soon as the first
+//! packet arrives; Ctrl-C stops it again later and `breakpoint()` stops it
+//! from code. Registers, memory, software breakpoints and single-stepping
+//! are supported; everything runs with interrupts off until gdb resumes.
+//!
+//! The kernel only listens with `gdb` on the command line. Without it a
+//! stray `int3` is just logged, but `breakpoint()` still waits for gdb.
+
+use crate::{
+    memory,
+    serial::{SerialPort, COM2, SERIAL2},
+    sync::Mutex,
+};
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use x86_64::{
+    registers::control::{Cr0, Cr0Flags},
+    structures::idt::HandlerFunc,
+    VirtAddr,
+};
+
+/// Largest packet accepted or sent, advertised to gdb in `qSupported`.
+const PACKET_SIZE: usize = 1024;
+const MAX_BREAKPOINTS: usize = 32;
+const INT3: u8 = 0xcc;
+const TRAP_FLAG: u64 = 1 << 8;
+/// Ctrl-C: gdb asking a running target to stop.
+const INTERRUPT: u8 = 0x03;
+const NO_BYTE: u16 = u16::MAX;
+
+/// gdb is connected and expects a stop reply whenever the kernel traps.
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+/// The next `int3` comes from `breakpoint()`, not stray code.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+/// A byte the COM2 interrupt read before handing over to the stub.
+static PENDING: AtomicU16 = AtomicU16::new(NO_BYTE);
+
+static BREAKPOINTS: Mutex<[Option<Breakpoint>; MAX_BREAKPOINTS]> =
+    Mutex::new([None; MAX_BREAKPOINTS]);
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: u64,
+    saved: u8,
+}
+
+/// Registers saved by the trap entry stubs, lowest address first.
+#[derive(Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Saves every general purpose register on top of the CPU's interrupt
+/// frame so gdb can read and change them, then calls `$trap` with it.
+macro_rules! trap_entry {
+    ($name:ident, $trap:ident) => {
+        #[naked]
+        unsafe extern "C" fn $name() -> ! {
+            asm!(
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rdi, rsp",
+                "call {trap}",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                "iretq",
+                trap = sym $trap,
+                options(noreturn)
+            )
+        }
+    };
+}
+
+trap_entry!(breakpoint_stub, breakpoint_trap);
+trap_entry!(debug_stub, debug_trap);
+
+/// IDT handler for `#BP`. Not a real `x86-interrupt` function: the IDT
+/// only stores the address, and the stub does its own save and `iretq`.
+pub fn breakpoint_entry() -> HandlerFunc {
+    unsafe { core::mem::transmute(breakpoint_stub as unsafe extern "C" fn() -> !) }
+}
+
+/// IDT handler for `#DB`, raised after a single step.
+pub fn debug_entry() -> HandlerFunc {
+    unsafe { core::mem::transmute(debug_stub as unsafe extern "C" fn() -> !) }
+}
+
+/// Lets gdb break in over COM2; the kernel calls this only with `gdb` on
+/// the command line.
+pub fn init() {
+    SERIAL2.lock().enable_receive_interrupt();
+    info!("gdb stub listening on COM2");
+}
+
+/// Stops in the stub, waiting for gdb if it is not connected yet.
+pub fn breakpoint() {
+    REQUESTED.store(true, Ordering::SeqCst);
+    x86_64::instructions::interrupts::int3();
+}
+
+/// Called from the COM2 interrupt; returns whether to stop in the stub.
+pub(crate) fn receive_interrupt() -> bool {
+    let byte = match port().try_receive() {
+        Some(byte) => byte,
+        None => return false,
+    };
+    match byte {
+        INTERRUPT => true,
+        // a new connection; keep the byte so the packet is not lost
+        b'$' | b'+' if !ATTACHED.load(Ordering::SeqCst) => {
+            PENDING.store(byte as u16, Ordering::SeqCst);
+            true
+        }
+        _ => false,
+    }
+}
+
+extern "C" fn breakpoint_trap(frame: &mut TrapFrame) {
+    let requested = REQUESTED.swap(false, Ordering::SeqCst);
+    // rip is past the int3; rewind onto an inserted breakpoint
+    if find_breakpoint(frame.rip.wrapping_sub(1)).is_some() {
+        frame.rip -= 1;
+    }
+    if !requested && !ATTACHED.load(Ordering::SeqCst) {
+        error!("EXCEPTION: BREAKPOINT at {:#x}", frame.rip);
+        return;
+    }
+    session(frame);
+}
+
+extern "C" fn debug_trap(frame: &mut TrapFrame) {
+    frame.rflags &= !TRAP_FLAG;
+    if !ATTACHED.load(Ordering::SeqCst) {
+        panic!("EXCEPTION: DEBUG at {:#x}", frame.rip);
+    }
+    session(frame);
+}
+
+enum Action {
+    Reply,
+    Resume,
+    Detach,
+}
+
+fn session(frame: &mut TrapFrame) {
+    let mut packet = [0; PACKET_SIZE];
+    let mut reply = Reply::new();
+    if ATTACHED.swap(true, Ordering::SeqCst) {
+        // gdb is waiting for the continue or step to finish
+        send_packet(b"S05");
+    }
+    loop {
+        let len = receive_packet(&mut packet);
+        reply.clear();
+        match handle(&packet[..len], frame, &mut reply) {
+            Action::Reply => send_packet(reply.as_bytes()),
+            Action::Resume => return,
+            Action::Detach => {
+                send_packet(b"OK");
+                remove_all_breakpoints();
+                ATTACHED.store(false, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+}
+
+fn handle(packet: &[u8], frame: &mut TrapFrame, reply: &mut Reply) -> Action {
+    let (&command, args) = match packet.split_first() {
+        Some(split) => split,
+        None => return Action::Reply,
+    };
+    match command {
+        b'?' => reply.push(b"S05"),
+        b'g' => read_registers(frame, reply),
+        b'G' => {
+            write_registers(frame, args);
+            reply.push(b"OK");
+        }
+        b'm' => match parse_range(args) {
+            Some((addr, len)) => read_memory(addr, len, reply),
+            None => reply.push(b"E01"),
+        },
+        b'M' => {
+            let ok = split_at(args, b':').and_then(|(range, data)| {
+                let (addr, len) = parse_range(range)?;
+                write_memory(addr, len, data)
+            });
+            if ok.is_some() {
+                reply.push(b"OK");
+            } else {
+                reply.push(b"E14");
+            }
+        }
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                frame.rip = addr;
+            }
+            if command == b's' {
+                frame.rflags |= TRAP_FLAG;
+            }
+            return Action::Resume;
+        }
+        b'Z' | b'z' => {
+            let addr = match args {
+                [b'0', b',', rest @ ..] => {
+                    split_at(rest, b',').and_then(|(addr, _)| parse_hex(addr))
+                }
+                // only software breakpoints; gdb falls back on its own
+                _ => return Action::Reply,
+            };
+            let ok = addr.and_then(|addr| {
+                if command == b'Z' {
+                    insert_breakpoint(addr)
+                } else {
+                    remove_breakpoint(addr)
+                }
+            });
+            if ok.is_some() {
+                reply.push(b"OK");
+            } else {
+                reply.push(b"E01");
+            }
+        }
+        b'D' => return Action::Detach,
+        b'k' => {
+            remove_all_breakpoints();
+            ATTACHED.store(false, Ordering::SeqCst);
+            return Action::Resume;
+        }
+        b'H' => reply.push(b"OK"),
+        b'q' if args.starts_with(b"Supported") => reply.push(b"PacketSize=400"),
+        b'q' if args.starts_with(b"Attached") => reply.push(b"1"),
+        // unsupported: the empty reply tells gdb so
+        _ => {}
+    }
+    Action::Reply
+}
+
+/// gdb's amd64 order: 17 64-bit registers ending in rip, then eflags and
+/// the six segment registers as 32-bit values.
+fn read_registers(frame: &TrapFrame, reply: &mut Reply) {
+    for &value in general_registers(frame).iter() {
+        reply.push_le(value, 8);
+    }
+    for &value in [frame.rflags, frame.cs, frame.ss, 0, 0, 0, 0].iter() {
+        reply.push_le(value, 4);
+    }
+}
+
+fn general_registers(frame: &TrapFrame) -> [u64; 17] {
+    [
+        frame.rax, frame.rbx, frame.rcx, frame.rdx, frame.rsi, frame.rdi, frame.rbp, frame.rsp,
+        frame.r8, frame.r9, frame.r10, frame.r11, frame.r12, frame.r13, frame.r14, frame.r15,
+        frame.rip,
+    ]
+}
+
+/// Segment registers are left alone; the kernel only has one of each.
+fn write_registers(frame: &mut TrapFrame, hex: &[u8]) {
+    let mut registers: [&mut u64; 18] = [
+        &mut frame.rax,
+        &mut frame.rbx,
+        &mut frame.rcx,
+        &mut frame.rdx,
+        &mut frame.rsi,
+        &mut frame.rdi,
+        &mut frame.rbp,
+        &mut frame.rsp,
+        &mut frame.r8,
+        &mut frame.r9,
+        &mut frame.r10,
+        &mut frame.r11,
+        &mut frame.r12,
+        &mut frame.r13,
+        &mut frame.r14,
+        &mut frame.r15,
+        &mut frame.rip,
+        &mut frame.rflags,
+    ];
+    let mut offset = 0;
+    for (i, register) in registers.iter_mut().enumerate() {
+        let width = if i < 17 { 8 } else { 4 };
+        match parse_le(hex.get(offset..offset + 2 * width)) {
+            Some(value) => **register = value,
+            None => return,
+        }
+        offset += 2 * width;
+    }
+}
+
+fn read_memory(addr: u64, len: usize, reply: &mut Reply) {
+    let len = len.min((PACKET_SIZE - 1) / 2);
+    if (addr..addr + len as u64).any(|byte| !mapped(byte)) {
+        return reply.push(b"E14");
+    }
+    for i in 0..len as u64 {
+        let byte = unsafe { core::ptr::read_volatile((addr + i) as *const u8) };
+        reply.push_le(byte as u64, 1);
+    }
+}
+
+fn write_memory(addr: u64, len: usize, hex: &[u8]) -> Option<()> {
+    if hex.len() != 2 * len {
+        return None;
+    }
+    for (i, pair) in hex.chunks(2).enumerate() {
+        poke(addr + i as u64, parse_le(Some(pair))? as u8)?;
+    }
+    Some(())
+}
+
+fn mapped(addr: u64) -> bool {
+    VirtAddr::try_new(addr).map_or(false, memory::is_mapped)
+}
+
+/// Writes one byte, even into read-only kernel text.
+fn poke(addr: u64, byte: u8) -> Option<()> {
+    if !mapped(addr) {
+        return None;
+    }
+    let flags = Cr0::read();
+    unsafe {
+        Cr0::write(flags - Cr0Flags::WRITE_PROTECT);
+        core::ptr::write_volatile(addr as *mut u8, byte);
+        Cr0::write(flags);
+    }
+    Some(())
+}
+
+fn find_breakpoint(addr: u64) -> Option<usize> {
+    BREAKPOINTS
+        .lock()
+        .iter()
+        .position(|bp| bp.map_or(false, |bp| bp.addr == addr))
+}
+
+fn insert_breakpoint(addr: u64) -> Option<()> {
+    if find_breakpoint(addr).is_some() {
+        return Some(());
+    }
+    if !mapped(addr) {
+        return None;
+    }
+    let mut breakpoints = BREAKPOINTS.lock();
+    let slot = breakpoints.iter_mut().find(|bp| bp.is_none())?;
+    let saved = unsafe { core::ptr::read_volatile(addr as *const u8) };
+    poke(addr, INT3)?;
+    *slot = Some(Breakpoint { addr, saved });
+    Some(())
+}
+
+fn remove_breakpoint(addr: u64) -> Option<()> {
+    let index = find_breakpoint(addr)?;
+    let breakpoint = BREAKPOINTS.lock()[index].take()?;
+    poke(breakpoint.addr, breakpoint.saved)
+}
+
+fn remove_all_breakpoints() {
+    for slot in BREAKPOINTS.lock().iter_mut() {
+        if let Some(breakpoint) = slot.take() {
+            poke(breakpoint.addr, breakpoint.saved);
+        }
+    }
+}
+
+/// The stub talks to the UART directly: it runs inside traps, where a
+/// holder of `SERIAL2` could never release it.
+fn port() -> SerialPort {
+    unsafe { SerialPort::new(COM2) }
+}
+
+fn read_byte() -> u8 {
+    match PENDING.swap(NO_BYTE, Ordering::SeqCst) {
+        NO_BYTE => port().receive(),
+        byte => byte as u8,
+    }
+}
+
+/// Waits for a `$data#xx` packet with a valid checksum, acknowledging it.
+fn receive_packet(buf: &mut [u8]) -> usize {
+    loop {
+        while read_byte() != b'$' {}
+        let mut len = 0;
+        let mut checksum = 0u8;
+        loop {
+            let byte = read_byte();
+            if byte == b'#' {
+                break;
+            }
+            checksum = checksum.wrapping_add(byte);
+            if len < buf.len() {
+                buf[len] = byte;
+                len += 1;
+            }
+        }
+        let expected = parse_le(Some(&[read_byte(), read_byte()][..]));
+        if expected == Some(checksum as u64) && len < buf.len() {
+            port().send_raw(b'+');
+            return len;
+        }
+        port().send_raw(b'-');
+    }
+}
+
+/// Sends `data` as a packet until gdb acknowledges it.
+fn send_packet(data: &[u8]) {
+    let mut port = port();
+    loop {
+        let checksum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        port.send_raw(b'$');
+        data.iter().for_each(|&byte| port.send_raw(byte));
+        port.send_raw(b'#');
+        port.send_raw(HEX[(checksum >> 4) as usize]);
+        port.send_raw(HEX[(checksum & 0xf) as usize]);
+        loop {
+            match read_byte() {
+                b'+' => return,
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Reply {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len < self.buf.len() {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            }
+        }
+    }
+
+    /// Appends the low `width` bytes of `value` as hex, least significant first.
+    fn push_le(&mut self, value: u64, width: usize) {
+        for i in 0..width {
+            let byte = (value >> (8 * i)) as u8;
+            self.push(&[HEX[(byte >> 4) as usize], HEX[(byte & 0xf) as usize]]);
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u64> {
+    (c as char).to_digit(16).map(u64::from)
+}
+
+/// A big-endian hex number, as gdb writes addresses and lengths.
+fn parse_hex(hex: &[u8]) -> Option<u64> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    hex.iter()
+        .try_fold(0, |value, &c| Some(value << 4 | hex_digit(c)?))
+}
+
+/// Hex bytes in target (little-endian) order, as in register dumps.
+fn parse_le(hex: Option<&[u8]>) -> Option<u64> {
+    let hex = hex?;
+    if hex.is_empty() || hex.len() % 2 != 0 || hex.len() > 16 {
+        return None;
+    }
+    hex.chunks(2).enumerate().try_fold(0, |value, (i, pair)| {
+        let byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        Some(value | byte << (8 * i))
+    })
+}
+
+/// `addr,len`
+fn parse_range(args: &[u8]) -> Option<(u64, usize)> {
+    let (addr, len) = split_at(args, b',')?;
+    Some((parse_hex(addr)?, parse_hex(len)? as usize))
+}
+
+fn split_at(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let index = bytes.iter().position(|&byte| byte == separator)?;
+    Some((&bytes[..index], &bytes[index + 1..]))
+}
@@ -0,0 +1,393 @@
+//! A spinlock that checks how it is used in debug builds.
+//!
+//! Every `Mutex` records who holds it and where it was taken, and every
+//! lock name forms a class whose acquisition order is remembered. Taking
+//! a lock the current context already holds (typically an interrupt
+//! handler spinning on a lock the interrupted code owns), or taking two
+//! classes in the reverse of an order seen before, panics with both
+//! backtraces instead of hanging. Release builds skip all of it.
+//!
+//! Waits lockdep cannot prove hopeless, such as a holder that never lets
+//! go, can use `try_lock_for`, which logs the holder when it times out.
+
+use super::backtrace;
+use crate::{interrupts, serial::SERIAL1, task::TaskId, time::Deadline};
+use core::{
+    fmt::{self, Write},
+    ops::{Deref, DerefMut},
+    sync::atomic::{spin_loop_hint, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+const ENABLED: bool = cfg!(debug_assertions);
+/// Distinct lock names tracked; later ones are only checked for self-deadlock.
+const MAX_CLASSES: usize = 64;
+/// Locks one CPU may hold at once before nesting stops being recorded.
+const MAX_HELD: usize = 16;
+/// Return addresses kept per acquisition.
+const FRAMES: usize = 8;
+const NO_CLASS: usize = 0;
+const FREE: u64 = u64::MAX;
+/// Holder for locks taken before the scheduler polls any task.
+const NO_TASK: u64 = u64::MAX - 1;
+
+static CLASS_NAMES: spin::Mutex<[&str; MAX_CLASSES]> = spin::Mutex::new([""; MAX_CLASSES]);
+static CLASS_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Bit `b` of `ORDER[a]` is set once class `b` was taken while holding `a`.
+static ORDER: [AtomicU64; MAX_CLASSES] = [ZERO; MAX_CLASSES];
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+/// Locks held by this CPU, innermost last.
+static HELD: spin::Mutex<Held> = spin::Mutex::new(Held {
+    entries: [(NO_CLASS, 0, [0; FRAMES]); MAX_HELD],
+    len: 0,
+});
+
+struct Held {
+    /// Class, interrupt depth and where it was taken.
+    entries: [(usize, usize, [u64; FRAMES]); MAX_HELD],
+    len: usize,
+}
+
+/// A `spin::Mutex` with owner tracking; see the module documentation.
+pub struct Mutex<T> {
+    inner: spin::Mutex<T>,
+    name: &'static str,
+    /// Class index plus one, assigned on first use.
+    class: AtomicUsize,
+    /// Task id of the holder, `NO_TASK`, or `FREE`.
+    owner: AtomicU64,
+    owner_depth: AtomicUsize,
+    owner_frames: [AtomicU64; FRAMES],
+}
+
+/// The context a lock is taken from: a task, or none before the scheduler
+/// runs, and how deep in interrupt handlers it is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Holder {
+    task: u64,
+    depth: usize,
+}
+
+impl Holder {
+    fn current() -> Self {
+        Holder {
+            task: TaskId::current().map_or(NO_TASK, TaskId::as_u64),
+            depth: interrupts::depth(),
+        }
+    }
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.task {
+            NO_TASK => write!(f, "boot code")?,
+            task => write!(f, "task {}", task)?,
+        }
+        write!(f, " at interrupt depth {}", self.depth)
+    }
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(name: &'static str, value: T) -> Self {
+        Mutex {
+            inner: spin::Mutex::new(value),
+            name,
+            class: AtomicUsize::new(NO_CLASS),
+            owner: AtomicU64::new(FREE),
+            owner_depth: AtomicUsize::new(0),
+            owner_frames: [ZERO; FRAMES],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn lock(&self) -> MutexGuard<T> {
+        if !ENABLED {
+            return self.guard(self.inner.lock());
+        }
+        let me = Holder::current();
+        let class = self.class();
+        check_order(self.name, class);
+        loop {
+            if let Some(inner) = self.inner.try_lock() {
+                return self.acquired(inner, me, class);
+            }
+            if let Some(holder) = self.holder() {
+                // One CPU: the holder can only run again once we return.
+                if holder.depth < me.depth || holder == me {
+                    self.report_deadlock();
+                }
+            }
+            spin_loop_hint();
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let inner = self.inner.try_lock()?;
+        if !ENABLED {
+            return Some(self.guard(inner));
+        }
+        Some(self.acquired(inner, Holder::current(), self.class()))
+    }
+
+    /// Like `lock`, but gives up after `timeout` and logs who holds the lock
+    /// and where they took it, so a lock that should only be held briefly
+    /// reports a hang instead of spinning forever. Owners are only recorded
+    /// in debug builds.
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<MutexGuard<T>> {
+        let deadline = Deadline::after(timeout);
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+            if deadline.expired() {
+                self.report_timeout(timeout);
+                return None;
+            }
+            spin_loop_hint();
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Releases the lock without a guard, for the panic path.
+    ///
+    /// # Safety
+    /// The holder must never touch the data again.
+    pub unsafe fn force_unlock(&self) {
+        self.owner.store(FREE, Ordering::Release);
+        self.inner.force_unlock();
+    }
+
+    fn guard<'a>(&'a self, inner: spin::MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        MutexGuard { lock: self, inner }
+    }
+
+    fn acquired<'a>(
+        &'a self,
+        inner: spin::MutexGuard<'a, T>,
+        me: Holder,
+        class: usize,
+    ) -> MutexGuard<'a, T> {
+        let mut frames = [0; FRAMES];
+        capture(&mut frames);
+        for (slot, &frame) in self.owner_frames.iter().zip(frames.iter()) {
+            slot.store(frame, Ordering::Relaxed);
+        }
+        self.owner_depth.store(me.depth, Ordering::Relaxed);
+        self.owner.store(me.task, Ordering::Release);
+        if class != NO_CLASS {
+            without_interrupts(|| HELD.lock().push(class, me.depth, frames));
+        }
+        self.guard(inner)
+    }
+
+    fn released(&self) {
+        self.owner.store(FREE, Ordering::Release);
+        let class = self.class.load(Ordering::Relaxed);
+        if class != NO_CLASS {
+            let depth = interrupts::depth();
+            without_interrupts(|| HELD.lock().remove(class, depth));
+        }
+    }
+
+    fn holder(&self) -> Option<Holder> {
+        match self.owner.load(Ordering::Acquire) {
+            FREE => None,
+            task => Some(Holder {
+                task,
+                depth: self.owner_depth.load(Ordering::Relaxed),
+            }),
+        }
+    }
+
+    fn class(&self) -> usize {
+        match self.class.load(Ordering::Relaxed) {
+            NO_CLASS => {
+                let class = register(self.name);
+                self.class.store(class, Ordering::Relaxed);
+                class
+            }
+            class => class,
+        }
+    }
+
+    fn frames(&self) -> [u64; FRAMES] {
+        let mut frames = [0; FRAMES];
+        for (frame, slot) in frames.iter_mut().zip(self.owner_frames.iter()) {
+            *frame = slot.load(Ordering::Relaxed);
+        }
+        frames
+    }
+
+    fn report_deadlock(&self) -> ! {
+        report(format_args!("{} was taken at", self.name), &self.frames());
+        panic!(
+            "lockdep: deadlock on {}, already held by this context",
+            self.name
+        );
+    }
+
+    fn report_timeout(&self, timeout: Duration) {
+        match self.holder().filter(|_| ENABLED) {
+            Some(holder) => {
+                let me = Holder::current();
+                let note = if holder == me || holder.depth < me.depth {
+                    ", which is waiting on it"
+                } else {
+                    ""
+                };
+                warn!(
+                    "lockdep: {} not acquired within {:?}, held by {}{}",
+                    self.name, timeout, holder, note
+                );
+                report(format_args!("{} was taken at", self.name), &self.frames());
+            }
+            None => warn!("lockdep: {} not acquired within {:?}", self.name, timeout),
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    lock: &'a Mutex<T>,
+    inner: spin::MutexGuard<'a, T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if ENABLED {
+            self.lock.released();
+        }
+    }
+}
+
+impl Held {
+    fn push(&mut self, class: usize, depth: usize, frames: [u64; FRAMES]) {
+        if self.len < MAX_HELD {
+            self.entries[self.len] = (class, depth, frames);
+            self.len += 1;
+        }
+    }
+
+    /// Guards may be dropped out of order, so removes the innermost match.
+    fn remove(&mut self, class: usize, depth: usize) {
+        let len = self.len;
+        if let Some(index) = self.entries[..len]
+            .iter()
+            .rposition(|&(c, d, _)| c == class && d == depth)
+        {
+            self.entries.copy_within(index + 1..len, index);
+            self.len -= 1;
+        }
+    }
+}
+
+/// How many tracked locks this CPU holds at interrupt depth `depth`, so
+/// the panic path can tell whether the code that panicked left one held.
+/// Release builds track nothing and always say 0; `None` if the table
+/// itself was held when the panic struck.
+pub fn held_at(depth: usize) -> Option<usize> {
+    without_interrupts(|| {
+        let held = HELD.try_lock()?;
+        Some(
+            held.entries[..held.len]
+                .iter()
+                .filter(|&&(_, d, _)| d == depth)
+                .count(),
+        )
+    })
+}
+
+/// Finds or creates the class for `name`; `NO_CLASS` once the table is full.
+fn register(name: &'static str) -> usize {
+    without_interrupts(|| {
+        let mut names = CLASS_NAMES.lock();
+        let count = CLASS_COUNT.load(Ordering::Relaxed);
+        if let Some(index) = names[..count].iter().position(|&n| n == name) {
+            return index + 1;
+        }
+        if count == MAX_CLASSES {
+            return NO_CLASS;
+        }
+        names[count] = name;
+        CLASS_COUNT.store(count + 1, Ordering::Relaxed);
+        count + 1
+    })
+}
+
+fn class_name(class: usize) -> &'static str {
+    without_interrupts(|| CLASS_NAMES.lock()[class - 1])
+}
+
+/// Records that `class` is taken after everything this context holds, and
+/// panics if one of those was ever taken after `class`.
+fn check_order(name: &'static str, class: usize) {
+    if class == NO_CLASS {
+        return;
+    }
+    let depth = interrupts::depth();
+    let inversion = without_interrupts(|| {
+        let held = HELD.lock();
+        for &(outer, outer_depth, frames) in held.entries[..held.len].iter() {
+            if outer_depth != depth || outer == class {
+                continue;
+            }
+            if ORDER[class - 1].load(Ordering::Relaxed) & 1 << (outer - 1) != 0 {
+                return Some((outer, frames));
+            }
+            ORDER[outer - 1].fetch_or(1 << (class - 1), Ordering::Relaxed);
+        }
+        None
+    });
+    if let Some((outer, frames)) = inversion {
+        let outer = class_name(outer);
+        report(format_args!("{} was taken at", outer), &frames);
+        panic!(
+            "lockdep: {} taken while holding {}, but the reverse order was seen before",
+            name, outer
+        );
+    }
+}
+
+fn capture(frames: &mut [u64; FRAMES]) {
+    let mut depth = 0;
+    backtrace::walk(|addr| {
+        if depth < FRAMES {
+            frames[depth] = addr;
+        }
+        depth += 1;
+    });
+}
+
+/// Prints the other side of a deadlock; the panic handler adds ours.
+fn report(what: fmt::Arguments, frames: &[u64]) {
+    without_interrupts(|| {
+        if let Some(mut serial) = SERIAL1.try_lock() {
+            let _ = writeln!(serial, "lockdep: {}:", what);
+            for (depth, &addr) in frames.iter().take_while(|&&a| a != 0).enumerate() {
+                let _ = backtrace::write_frame(&mut *serial, depth, addr);
+            }
+        }
+    })
+}
@@ -0,0 +1,269 @@
+//! A last-resort console for when the executor is wedged.
+//!
+//! Ctrl+Alt+M on the keyboard or Ctrl-] on COM1 enters it straight from the
+//! interrupt handler, and the panic screen offers it before rebooting. It
+//! polls the keyboard controller and COM1 with interrupts off, so nothing
+//! else runs until it returns: no scheduler, no heap, and no lock that the
+//! interrupted code might hold. Output goes to COM1 and, unless the
+//! interrupted code was using it, the screen.
+
+use super::{
+    backtrace,
+    panic_screen::{self, Registers},
+};
+use crate::{
+    device::{keyboard::Ps2Decoder, scancode::Decoder},
+    interrupts,
+    memory::inspect,
+    serial::{SerialPort, COM1},
+    shell::parse_number,
+    task::TaskId,
+    vga_buffer::WRITER,
+};
+use core::{
+    fmt::{self, Write},
+    ptr, str,
+};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use x86_64::{
+    instructions::{interrupts::disable, port::Port},
+    registers::control::{Cr0, Cr2, Cr3, Cr4},
+    structures::idt::InterruptStackFrame,
+    VirtAddr,
+};
+
+const PS2_DATA: u16 = 0x60;
+const PS2_STATUS: u16 = 0x64;
+const PS2_OUTPUT_FULL: u8 = 1;
+/// Ctrl-] on a terminal, as telnet uses it.
+pub const SERIAL_HOTKEY: u8 = 0x1d;
+const LINE: usize = 80;
+/// Longest `mem` dump, in bytes.
+const MAX_DUMP: u64 = 512;
+const HELP: &str = "\
+regs             registers at entry and control registers
+mem <addr> [len] dump up to 512 bytes; unmapped bytes show as ??
+bt               backtrace from the monitor
+reboot           reset the machine
+continue         return to the interrupted code (not after a panic)
+";
+
+/// How the kernel got into the monitor.
+pub enum Entry<'a> {
+    Panic(&'a Registers),
+    Hotkey(&'a InterruptStackFrame),
+}
+
+/// Runs the monitor after a panic; it can only end in a reboot.
+pub fn enter_from_panic(regs: &Registers) -> ! {
+    disable();
+    // whoever held the writer is never coming back
+    unsafe { WRITER.force_unlock() };
+    run(&Entry::Panic(regs));
+    panic_screen::reboot()
+}
+
+/// Runs the monitor from an interrupt handler until `continue`. Interrupts
+/// must be off, as they are in any handler.
+pub fn enter_from_interrupt(frame: &InterruptStackFrame) {
+    run(&Entry::Hotkey(frame));
+}
+
+fn run(entry: &Entry) {
+    let mut console = Console {
+        serial: unsafe { SerialPort::new(COM1) },
+        // a writer held by the interrupted code is left alone
+        screen: !WRITER.is_locked(),
+    };
+    let mut input = Input::new();
+    let _ = writeln!(console, "\n*** kernel monitor, `help` for commands ***");
+    let mut line = [0; LINE];
+    loop {
+        let _ = write!(console, "mon> ");
+        let len = input.read_line(&mut console, &mut line);
+        let line = str::from_utf8(&line[..len]).unwrap_or("");
+        let mut words = line.split_whitespace();
+        let _ = match words.next() {
+            None => Ok(()),
+            Some("help") => console.write_str(HELP),
+            Some("regs") => regs(&mut console, entry),
+            Some("mem") => mem(&mut console, words.next(), words.next()),
+            Some("bt") => backtrace::write(&mut console),
+            Some("reboot") => panic_screen::reboot(),
+            Some("continue") | Some("c") => match entry {
+                Entry::Hotkey(_) => {
+                    let _ = writeln!(console, "resuming");
+                    return;
+                }
+                Entry::Panic(_) => writeln!(console, "cannot continue after a panic"),
+            },
+            Some(other) => writeln!(console, "unknown command `{}`", other),
+        };
+    }
+}
+
+fn regs(w: &mut Console, entry: &Entry) -> fmt::Result {
+    match entry {
+        Entry::Panic(regs) => {
+            writeln!(w, "at panic handler:")?;
+            let values = [
+                ("rax", regs.rax),
+                ("rbx", regs.rbx),
+                ("rcx", regs.rcx),
+                ("rdx", regs.rdx),
+                ("rsi", regs.rsi),
+                ("rdi", regs.rdi),
+                ("rbp", regs.rbp),
+                ("rsp", regs.rsp),
+                ("r8 ", regs.r8),
+                ("r9 ", regs.r9),
+                ("r10", regs.r10),
+                ("r11", regs.r11),
+                ("r12", regs.r12),
+                ("r13", regs.r13),
+                ("r14", regs.r14),
+                ("r15", regs.r15),
+                ("rfl", regs.rflags),
+            ];
+            write_values(w, &values)?;
+        }
+        Entry::Hotkey(frame) => {
+            let values = [
+                ("rip", frame.instruction_pointer.as_u64()),
+                ("cs ", frame.code_segment),
+                ("rfl", frame.cpu_flags),
+                ("rsp", frame.stack_pointer.as_u64()),
+                ("ss ", frame.stack_segment),
+            ];
+            write_values(w, &values)?;
+        }
+    }
+    write_values(
+        w,
+        &[
+            ("cr0", Cr0::read_raw()),
+            ("cr2", Cr2::read().as_u64()),
+            ("cr3", Cr3::read().0.start_address().as_u64()),
+            ("cr4", Cr4::read_raw()),
+        ],
+    )?;
+    match TaskId::current() {
+        Some(task) => write!(w, "task {}", task)?,
+        None => write!(w, "no task")?,
+    }
+    writeln!(w, ", interrupt depth {}", interrupts::depth())
+}
+
+/// Three to a line, as on the panic screen.
+fn write_values(w: &mut Console, values: &[(&str, u64)]) -> fmt::Result {
+    for row in values.chunks(3) {
+        for (name, value) in row {
+            write!(w, "{} {:016x}   ", name, value)?;
+        }
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+fn mem(w: &mut Console, addr: Option<&str>, len: Option<&str>) -> fmt::Result {
+    let start = match addr.and_then(|addr| parse_number(addr).ok()) {
+        Some(start) => start,
+        None => return writeln!(w, "usage: mem <addr> [len]"),
+    };
+    let len = match len.map(parse_number) {
+        None => 64,
+        Some(Ok(len)) => len.min(MAX_DUMP),
+        Some(Err(_)) => return writeln!(w, "usage: mem <addr> [len]"),
+    };
+    let end = start.saturating_add(len);
+    let mut row = start;
+    while row < end {
+        write!(w, "{:016x}:", row)?;
+        for addr in row..end.min(row.saturating_add(16)) {
+            match VirtAddr::try_new(addr) {
+                Ok(virt) if inspect::is_mapped(virt) => write!(w, " {:02x}", unsafe {
+                    ptr::read_volatile(addr as *const u8)
+                })?,
+                _ => write!(w, " ??")?,
+            }
+        }
+        writeln!(w)?;
+        row = row.saturating_add(16);
+    }
+    Ok(())
+}
+
+/// Writes to COM1 through its ports, and to the screen if it may.
+struct Console {
+    serial: SerialPort,
+    screen: bool,
+}
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.serial.write_str(s);
+        if self.screen {
+            let _ = WRITER.lock().write_str(s);
+        }
+        Ok(())
+    }
+}
+
+/// Polls the keyboard controller and COM1 for characters.
+struct Input {
+    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
+    decoder: Ps2Decoder,
+    status: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Input {
+    fn new() -> Self {
+        Input {
+            keyboard: Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore),
+            decoder: Ps2Decoder::new(),
+            status: Port::new(PS2_STATUS),
+            data: Port::new(PS2_DATA),
+        }
+    }
+
+    fn read_char(&mut self, console: &mut Console) -> char {
+        loop {
+            if let Some(byte) = console.serial.try_receive() {
+                return byte as char;
+            }
+            if unsafe { self.status.read() } & PS2_OUTPUT_FULL != 0 {
+                let scancode = unsafe { self.data.read() };
+                if let Some(event) = self.decoder.feed(scancode) {
+                    if let Some(DecodedKey::Unicode(c)) = self.keyboard.process_keyevent(event) {
+                        return c;
+                    }
+                }
+            }
+            interrupts::pause();
+        }
+    }
+
+    /// Reads a line into `buf` with echo and backspace, returning its length.
+    fn read_line(&mut self, console: &mut Console, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        loop {
+            match self.read_char(console) {
+                '\r' | '\n' => {
+                    let _ = writeln!(console);
+                    return len;
+                }
+                '\x08' | '\x7f' if len > 0 => {
+                    len -= 1;
+                    let _ = console.write_str("\x08 \x08");
+                }
+                c if c.is_ascii() && !c.is_ascii_control() && len < buf.len() => {
+                    buf[len] = c as u8;
+                    len += 1;
+                    let _ = console.write_char(c);
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! The full-screen report shown when the kernel panics.
+
+use super::{backtrace, symbols};
+use crate::{
+    boot::cmdline::{self, PanicAction},
+    interrupts,
+    serial::{SerialPort, COM1},
+    task::TaskId,
+    time::Deadline,
+    vga_buffer::{Color, ColorCode, WRITER},
+};
+use core::{fmt::Write, panic::PanicInfo, time::Duration};
+use x86_64::{
+    instructions::{
+        interrupts::{are_enabled, disable},
+        port::Port,
+        tables::{lidt, DescriptorTablePointer},
+    },
+    registers::control::{Cr0, Cr2, Cr3, Cr4},
+    VirtAddr,
+};
+
+/// Backtrace frames that fit under the registers.
+const FRAMES_SHOWN: usize = 6;
+const PS2_DATA: u16 = 0x60;
+const PS2_STATUS: u16 = 0x64;
+const PS2_OUTPUT_FULL: u8 = 1;
+const SCANCODE_M: u8 = 0x32;
+/// Pulses the CPU reset line through the keyboard controller.
+const PS2_RESET: u8 = 0xfe;
+
+/// General purpose registers as the panic handler found them. These are
+/// the handler's own, not the panicking code's: by the time a panic
+/// reaches it, `panic!` and the formatting machinery have run, and an
+/// exception handler that panics has its own frame in between.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rflags: u64,
+}
+
+impl Registers {
+    /// Must be the first thing the panic handler does; `rdi` ends up
+    /// holding this struct's address.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let mut regs = Registers::default();
+        unsafe {
+            asm!(
+                "mov [rdi], rax",
+                "mov [rdi + 8], rbx",
+                "mov [rdi + 16], rcx",
+                "mov [rdi + 24], rdx",
+                "mov [rdi + 32], rsi",
+                "mov [rdi + 40], rdi",
+                "mov [rdi + 48], rbp",
+                "mov [rdi + 56], rsp",
+                "mov [rdi + 64], r8",
+                "mov [rdi + 72], r9",
+                "mov [rdi + 80], r10",
+                "mov [rdi + 88], r11",
+                "mov [rdi + 96], r12",
+                "mov [rdi + 104], r13",
+                "mov [rdi + 112], r14",
+                "mov [rdi + 120], r15",
+                "pushfq",
+                "pop qword ptr [rdi + 128]",
+                in("rdi") &mut regs,
+            )
+        }
+        regs
+    }
+}
+
+/// Replaces the screen with the panic report, then does what `panic=`
+/// says. By default that is to reboot on a keypress or, on M or any input
+/// from COM1, enter the monitor.
+pub fn show(info: &PanicInfo, regs: &Registers) -> ! {
+    let action = cmdline::options().panic;
+    let interrupts_were_enabled = are_enabled();
+    disable();
+    // whoever held the writer is never coming back
+    unsafe { WRITER.force_unlock() };
+    {
+        let mut writer = WRITER.lock();
+        writer.set_color(ColorCode::new(Color::White, Color::Blue));
+        writer.clear_screen();
+        writer.set_position(0, 0);
+        let _ = report(&mut *writer, info, regs, interrupts_were_enabled);
+        let _ = match action {
+            PanicAction::Prompt => {
+                write!(writer, "press M for the monitor, any other key to reboot")
+            }
+            PanicAction::Halt => write!(writer, "halted"),
+            PanicAction::Reboot(0) => write!(writer, "rebooting"),
+            PanicAction::Reboot(secs) => write!(
+                writer,
+                "rebooting in {} s; press M for the monitor, any other key to reboot now",
+                secs
+            ),
+            PanicAction::Shutdown => write!(writer, "shutting down"),
+            PanicAction::Monitor => Ok(()),
+        };
+    }
+    match action {
+        PanicAction::Prompt => {
+            if wait_for_key(None) == Some(Key::Monitor) {
+                super::monitor::enter_from_panic(regs);
+            }
+        }
+        PanicAction::Halt => crate::hlt_loop(),
+        PanicAction::Reboot(0) => {}
+        PanicAction::Reboot(secs) => {
+            let deadline = Deadline::after(Duration::from_secs(secs));
+            if wait_for_key(Some(deadline)) == Some(Key::Monitor) {
+                super::monitor::enter_from_panic(regs);
+            }
+        }
+        PanicAction::Shutdown => {
+            // a failure for CI, if QEMU has the exit device
+            super::try_exit_qemu(false);
+            crate::power::shutdown()
+        }
+        PanicAction::Monitor => super::monitor::enter_from_panic(regs),
+    }
+    reboot()
+}
+
+fn report(
+    w: &mut impl Write,
+    info: &PanicInfo,
+    regs: &Registers,
+    interrupts_were_enabled: bool,
+) -> core::fmt::Result {
+    writeln!(w, " *** KERNEL PANIC ***\n")?;
+    writeln!(w, "{}\n", info)?;
+    writeln!(w, "registers at panic handler:")?;
+    let rows = [
+        [("rax", regs.rax), ("rbx", regs.rbx), ("rcx", regs.rcx)],
+        [("rdx", regs.rdx), ("rsi", regs.rsi), ("rdi", regs.rdi)],
+        [("rbp", regs.rbp), ("rsp", regs.rsp), ("r8 ", regs.r8)],
+        [("r9 ", regs.r9), ("r10", regs.r10), ("r11", regs.r11)],
+        [("r12", regs.r12), ("r13", regs.r13), ("r14", regs.r14)],
+        [
+            ("r15", regs.r15),
+            ("rfl", regs.rflags),
+            ("cr0", Cr0::read_raw()),
+        ],
+        [
+            ("cr2", Cr2::read().as_u64()),
+            ("cr3", Cr3::read().0.start_address().as_u64()),
+            ("cr4", Cr4::read_raw()),
+        ],
+    ];
+    for row in rows.iter() {
+        for (name, value) in row.iter() {
+            write!(w, "{} {:016x}   ", name, value)?;
+        }
+        writeln!(w)?;
+    }
+    writeln!(w)?;
+    match TaskId::current() {
+        Some(task) => write!(w, "task {}", task)?,
+        None => write!(w, "no task")?,
+    }
+    writeln!(
+        w,
+        ", interrupt depth {}, interrupts {}",
+        interrupts::depth(),
+        if interrupts_were_enabled { "on" } else { "off" }
+    )?;
+    writeln!(w, "\nbacktrace:")?;
+    let mut result = Ok(());
+    let mut depth = 0;
+    backtrace::walk(|addr| {
+        if depth < FRAMES_SHOWN && result.is_ok() {
+            result = match symbols::symbolize(addr) {
+                Some(symbol) => writeln!(w, "  {:016x} {}+{:#x}", addr, symbol.name, symbol.offset),
+                None => writeln!(w, "  {:016x}", addr),
+            };
+        }
+        depth += 1;
+    });
+    result?;
+    writeln!(w)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Key {
+    Monitor,
+    Other,
+}
+
+/// Polls the PS/2 controller and COM1, since interrupts stay off from here
+/// on. `None` if `deadline` passes first.
+fn wait_for_key(deadline: Option<Deadline>) -> Option<Key> {
+    let mut status = Port::<u8>::new(PS2_STATUS);
+    let mut data = Port::<u8>::new(PS2_DATA);
+    let mut serial = unsafe { SerialPort::new(COM1) };
+    unsafe {
+        // drop whatever was typed before the panic
+        while status.read() & PS2_OUTPUT_FULL != 0 {
+            data.read();
+        }
+        while serial.try_receive().is_some() {}
+        loop {
+            if serial.try_receive().is_some() {
+                return Some(Key::Monitor);
+            }
+            if status.read() & PS2_OUTPUT_FULL != 0 {
+                match data.read() {
+                    SCANCODE_M => return Some(Key::Monitor),
+                    scancode if scancode < 0x80 => return Some(Key::Other),
+                    _ => {}
+                }
+            }
+            if deadline.map_or(false, |deadline| deadline.expired()) {
+                return None;
+            }
+            crate::interrupts::pause();
+        }
+    }
+}
+
+/// Resets through the keyboard controller, or failing that triple faults.
+pub(super) fn reboot() -> ! {
+    unsafe {
+        Port::<u8>::new(PS2_STATUS).write(PS2_RESET);
+        let empty = DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        };
+        lidt(&empty);
+        asm!("int3", options(noreturn));
+    }
+}
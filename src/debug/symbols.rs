@@ -0,0 +1,116 @@
+//! Address-to-name lookup for backtraces and profiling.
+//!
+//! The table cannot be generated before the kernel is linked, so it is
+//! reserved here and filled in afterwards by `tools/embed_symbols.py`, which
+//! patches the linked ELF in place; the cargo runner calls it before every
+//! boot. Until then `symbolize` finds nothing.
+//!
+//! Layout: `MAGIC`, a `u32` entry count, four bytes of padding, the entries
+//! sorted by address (`u64` address, `u32` size, `u32` name offset, `u32`
+//! name length, four bytes of padding), then the names, all little endian.
+
+/// Bytes reserved for the table; the tool refuses anything larger.
+pub const CAPACITY: usize = 192 * 1024;
+
+const MAGIC: [u8; 8] = *b"KSYMTAB\0";
+const HEADER_LEN: usize = 16;
+const ENTRY_LEN: usize = 24;
+
+/// Starts with the magic so it lands in `.data` and has bytes in the ELF
+/// to patch; `mut` so the compiler cannot fold reads of the empty table.
+#[no_mangle]
+#[used]
+static mut KERNEL_SYMBOL_TABLE: [u8; CAPACITY] = empty_table();
+
+const fn empty_table() -> [u8; CAPACITY] {
+    let mut table = [0; CAPACITY];
+    let mut i = 0;
+    while i < MAGIC.len() {
+        table[i] = MAGIC[i];
+        i += 1;
+    }
+    table
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name: &'static str,
+    /// Distance of the looked up address from the symbol's start.
+    pub offset: u64,
+}
+
+struct Entry {
+    address: u64,
+    size: u64,
+    name: &'static str,
+}
+
+fn table() -> &'static [u8] {
+    unsafe { &KERNEL_SYMBOL_TABLE }
+}
+
+fn len() -> usize {
+    let table = table();
+    if table[..MAGIC.len()] != MAGIC {
+        return 0;
+    }
+    let count = read_u32(table, 8) as usize;
+    count.min((CAPACITY - HEADER_LEN) / ENTRY_LEN)
+}
+
+fn entry(index: usize) -> Entry {
+    let table = table();
+    let base = HEADER_LEN + index * ENTRY_LEN;
+    let names = HEADER_LEN + len() * ENTRY_LEN;
+    let start = names + read_u32(table, base + 12) as usize;
+    let end = start + read_u32(table, base + 16) as usize;
+    let name = table
+        .get(start..end)
+        .and_then(|name| core::str::from_utf8(name).ok())
+        .unwrap_or("?");
+    Entry {
+        address: read_u64(table, base),
+        size: read_u32(table, base + 8) as u64,
+        name,
+    }
+}
+
+/// The function containing `addr`, if the table has been embedded.
+pub fn symbolize(addr: u64) -> Option<Symbol> {
+    // index of the last entry starting at or below `addr`
+    let (mut low, mut high) = (0, len());
+    while low < high {
+        let mid = (low + high) / 2;
+        if entry(mid).address <= addr {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let entry = entry(low.checked_sub(1)?);
+    let offset = addr - entry.address;
+    if entry.size != 0 && offset >= entry.size {
+        return None;
+    }
+    Some(Symbol {
+        name: entry.name,
+        offset,
+    })
+}
+
+/// Number of embedded symbols; zero when the tool has not been run.
+pub fn count() -> usize {
+    len()
+}
+
+fn read_u32(table: &[u8], at: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&table[at..at + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(table: &[u8], at: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&table[at..at + 8]);
+    u64::from_le_bytes(bytes)
+}
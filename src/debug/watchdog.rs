@@ -0,0 +1,157 @@
+//! NMI watchdog. A performance counter counting unhalted cycles overflows
+//! into an NMI, which checks that the timer tick still advances; a CPU
+//! spinning with interrupts off or on a deadlocked spinlock keeps counting
+//! but stops ticking. Idle CPUs sit in `hlt` and never trip it.
+
+use super::backtrace;
+use crate::{
+    cpu::{self, msr},
+    memory,
+    serial::{SerialPort, COM1},
+    time,
+};
+use core::{
+    arch::x86_64::_rdtsc,
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// How long the tick may stand still before the CPU counts as locked up.
+pub const TIMEOUT_MS: u64 = 5000;
+
+const APIC_SVR: u64 = 0xf0;
+const APIC_LVT_PERF: u64 = 0x340;
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const DELIVERY_NMI: u32 = 0b100 << 8;
+const UNHALTED_CORE_CYCLES: u64 = 0x3c;
+const EVENT_USR: u64 = 1 << 16;
+const EVENT_OS: u64 = 1 << 17;
+const EVENT_INT: u64 = 1 << 20;
+const EVENT_EN: u64 = 1 << 22;
+/// Counter writes only take 32 bits, sign-extended.
+const MAX_PERIOD: u64 = 0x7fff_ffff;
+/// Ticks timed when estimating the clock rate.
+const CALIBRATION_TICKS: u64 = 50;
+
+#[derive(Debug)]
+pub enum Error {
+    NoApic,
+    NoPerfCounters,
+    MapFailed,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LAPIC: AtomicU64 = AtomicU64::new(0);
+/// Cycles between NMIs.
+static PERIOD: AtomicU64 = AtomicU64::new(0);
+static CYCLES_PER_MS: AtomicU64 = AtomicU64::new(1);
+static COUNTER_WIDTH: AtomicU64 = AtomicU64::new(40);
+static LAST_TICK: AtomicU64 = AtomicU64::new(0);
+/// Cycles counted since the tick last moved.
+static STALLED: AtomicU64 = AtomicU64::new(0);
+
+/// Starts the watchdog; needs interrupts on to calibrate against the timer.
+pub fn init() -> Result<(), Error> {
+    let features = cpu::features();
+    if !features.apic {
+        return Err(Error::NoApic);
+    }
+    if features.pmu_version == 0 || features.pmu_counters == 0 {
+        return Err(Error::NoPerfCounters);
+    }
+
+    let lapic = memory::map_mmio(msr::apic_base().addr, 4096).map_err(|_| Error::MapFailed)?;
+    LAPIC.store(lapic.as_u64(), Ordering::SeqCst);
+
+    let cycles_per_ms = calibrate();
+    let period = (cycles_per_ms * 1000).min(MAX_PERIOD);
+    CYCLES_PER_MS.store(cycles_per_ms.max(1), Ordering::SeqCst);
+    PERIOD.store(period, Ordering::SeqCst);
+    COUNTER_WIDTH.store(u64::from(features.pmu_counter_width), Ordering::SeqCst);
+    LAST_TICK.store(time::ticks(), Ordering::SeqCst);
+    ENABLED.store(true, Ordering::SeqCst);
+
+    unsafe {
+        write_apic(APIC_SVR, read_apic(APIC_SVR) | APIC_SOFTWARE_ENABLE);
+        write_apic(APIC_LVT_PERF, DELIVERY_NMI);
+        msr::write(msr::IA32_PMC0, period.wrapping_neg());
+        msr::write(
+            msr::IA32_PERFEVTSEL0,
+            UNHALTED_CORE_CYCLES | EVENT_USR | EVENT_OS | EVENT_INT | EVENT_EN,
+        );
+    }
+    info!(
+        "NMI watchdog: {} ms timeout, NMI every {} cycles",
+        TIMEOUT_MS, period
+    );
+    Ok(())
+}
+
+/// Times the TSC over a few ticks and takes that as the core clock.
+fn calibrate() -> u64 {
+    let start = time::ticks();
+    while time::ticks() == start {
+        crate::interrupts::pause();
+    }
+    let (tick, tsc) = (time::ticks(), unsafe { _rdtsc() });
+    while time::ticks() < tick + CALIBRATION_TICKS {
+        crate::interrupts::pause();
+    }
+    let elapsed_ms = CALIBRATION_TICKS * 1000 / time::TICK_RATE as u64;
+    (unsafe { _rdtsc() } - tsc) / elapsed_ms
+}
+
+/// Called first thing by the NMI handler; `false` if the counter did not
+/// overflow, so the NMI came from somewhere else.
+pub(crate) fn nmi(stack_frame: &InterruptStackFrame) -> bool {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return false;
+    }
+    // armed, the counter sits just below overflow with its top bit set
+    let top_bit = 1 << (COUNTER_WIDTH.load(Ordering::SeqCst) - 1);
+    if unsafe { msr::read(msr::IA32_PMC0) } & top_bit != 0 {
+        return false;
+    }
+    let period = PERIOD.load(Ordering::SeqCst);
+    unsafe {
+        msr::write(msr::IA32_PMC0, period.wrapping_neg());
+        // delivering the NMI masked the entry
+        write_apic(APIC_LVT_PERF, DELIVERY_NMI);
+    }
+
+    let tick = time::ticks();
+    if LAST_TICK.swap(tick, Ordering::SeqCst) != tick {
+        STALLED.store(0, Ordering::SeqCst);
+        return true;
+    }
+    let stalled = STALLED.fetch_add(period, Ordering::SeqCst) + period;
+    let stalled_ms = stalled / CYCLES_PER_MS.load(Ordering::SeqCst);
+    if stalled_ms >= TIMEOUT_MS {
+        lockup(stack_frame, tick, stalled_ms);
+    }
+    true
+}
+
+/// Reports on a fresh handle to COM1, since the lockup may well be a
+/// spinlock around `SERIAL1`.
+fn lockup(stack_frame: &InterruptStackFrame, tick: u64, stalled_ms: u64) -> ! {
+    ENABLED.store(false, Ordering::SeqCst);
+    let rip = stack_frame.instruction_pointer.as_u64();
+    let mut serial = unsafe { SerialPort::new(COM1) };
+    let _ = writeln!(
+        serial,
+        "watchdog: no tick for {} ms (stuck at tick {}), rip {:#x}",
+        stalled_ms, tick, rip
+    );
+    let _ = backtrace::write(&mut serial);
+    panic!("watchdog: CPU locked up at {:#x}", rip);
+}
+
+unsafe fn read_apic(offset: u64) -> u32 {
+    core::ptr::read_volatile((LAPIC.load(Ordering::SeqCst) + offset) as *const u32)
+}
+
+unsafe fn write_apic(offset: u64, value: u32) {
+    core::ptr::write_volatile((LAPIC.load(Ordering::SeqCst) + offset) as *mut u32, value)
+}
@@ -0,0 +1,245 @@
+//! The 128 bytes of battery-backed CMOS RAM behind ports 0x70/0x71.
+//!
+//! Bit 7 of the index port masks NMIs, so every access says whether they
+//! stay masked; `set_nmi_masked` is the one place that changes it, and an
+//! access masks them while it runs so an NMI handler cannot move the index
+//! between the two port accesses.
+//!
+//! The RTC registers below `FIRST_NVRAM` are only read, by `datetime`.
+//! Bytes in the checksummed range are written with the checksum updated,
+//! so the firmware does not reset its setup on the next boot.
+
+use crate::{
+    acpi,
+    shell::{parse_number, ShellErr},
+    sync::Mutex,
+};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+const INDEX: u16 = 0x70;
+const DATA: u16 = 0x71;
+const NMI_MASK: u8 = 1 << 7;
+
+pub const LEN: u8 = 128;
+/// The RTC's date and time registers, in `DateTime` order.
+const RTC_REGS: [u8; 6] = [0x09, 0x08, 0x07, 0x04, 0x02, 0x00];
+const HOURS_PM: u8 = 1 << 7;
+const STATUS_A: u8 = 0x0a;
+const STATUS_A_UPDATING: u8 = 1 << 7;
+const STATUS_B: u8 = 0x0b;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Reads of status A `datetime` makes before giving up on an update
+/// finishing; one takes under 2 ms.
+const MAX_UPDATE_POLLS: u32 = 100_000;
+/// What the firmware does on the next reset, e.g. `SHUTDOWN_JUMP`.
+pub const SHUTDOWN_STATUS: u8 = 0x0f;
+/// Jump through the vector at 0040:0067 after a reset, without an EOI;
+/// how an AP trampoline or a warm restart skips the POST.
+pub const SHUTDOWN_JUMP: u8 = 0x0a;
+const FIRST_NVRAM: u8 = 0x0e;
+/// The bytes the standard checksum covers, and where it is kept, high
+/// byte first.
+const CHECKSUMMED: core::ops::RangeInclusive<u8> = 0x10..=0x2d;
+const CHECKSUM: u8 = 0x2e;
+/// Where the century is kept when the FADT does not say.
+const DEFAULT_CENTURY: u8 = 0x32;
+
+/// Orders the index and data accesses of concurrent callers.
+static LOCK: Mutex<()> = Mutex::new(());
+static NMI_MASKED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    OutOfRange(u8),
+    /// An RTC register, or the checksum itself.
+    Reserved(u8),
+}
+
+/// Runs `f` on the selected register with NMIs masked, then leaves the
+/// index on status register D with the NMI mask as set.
+fn access<R>(reg: u8, f: impl FnOnce(&mut Port<u8>) -> R) -> R {
+    without_interrupts(|| {
+        let _lock = LOCK.lock();
+        let mut index = Port::new(INDEX);
+        let mut data = Port::new(DATA);
+        let masked = if NMI_MASKED.load(Ordering::Relaxed) {
+            NMI_MASK
+        } else {
+            0
+        };
+        unsafe {
+            index.write(reg | NMI_MASK);
+            let result = f(&mut data);
+            index.write(0x0d | masked);
+            result
+        }
+    })
+}
+
+fn read_raw(reg: u8) -> u8 {
+    access(reg, |data| unsafe { data.read() })
+}
+
+fn write_raw(reg: u8, value: u8) {
+    access(reg, |data| unsafe { data.write(value) })
+}
+
+pub fn read(reg: u8) -> Result<u8, Error> {
+    if reg >= LEN {
+        return Err(Error::OutOfRange(reg));
+    }
+    Ok(read_raw(reg))
+}
+
+/// Writes an NVRAM byte, updating the checksum if it covers `reg`.
+pub fn write(reg: u8, value: u8) -> Result<(), Error> {
+    if reg >= LEN {
+        return Err(Error::OutOfRange(reg));
+    }
+    if reg < FIRST_NVRAM || reg == CHECKSUM || reg == CHECKSUM + 1 {
+        return Err(Error::Reserved(reg));
+    }
+    without_interrupts(|| {
+        write_raw(reg, value);
+        if CHECKSUMMED.contains(&reg) {
+            let sum = checksum();
+            write_raw(CHECKSUM, (sum >> 8) as u8);
+            write_raw(CHECKSUM + 1, sum as u8);
+        }
+    });
+    Ok(())
+}
+
+fn checksum() -> u16 {
+    CHECKSUMMED.map(|reg| read_raw(reg) as u16).sum()
+}
+
+pub fn checksum_ok() -> bool {
+    let stored = (read_raw(CHECKSUM) as u16) << 8 | read_raw(CHECKSUM + 1) as u16;
+    stored == checksum()
+}
+
+/// Masks or unmasks NMIs at the CMOS index port.
+pub fn set_nmi_masked(masked: bool) {
+    NMI_MASKED.store(masked, Ordering::Relaxed);
+    // any access writes the new mask on its way out
+    read_raw(0x0d);
+}
+
+pub fn set_shutdown_status(value: u8) {
+    write_raw(SHUTDOWN_STATUS, value);
+}
+
+/// The century, from the register the FADT names, or the usual one.
+pub fn century() -> u8 {
+    let reg = match acpi::fadt() {
+        Ok(fadt) if fadt.century != 0 => fadt.century,
+        _ => DEFAULT_CENTURY,
+    };
+    decode(read_raw(reg & (LEN - 1)), read_raw(STATUS_B))
+}
+
+/// A value as the RTC keeps it, BCD unless status B says binary.
+fn decode(value: u8, status_b: u8) -> u8 {
+    if status_b & STATUS_B_BINARY != 0 {
+        value
+    } else {
+        (value >> 4) * 10 + (value & 0xf)
+    }
+}
+
+/// The RTC's date and time, as the firmware keeps it; usually UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// Reads the RTC. The registers change during an update, so they are read
+/// until two reads outside one agree.
+pub fn datetime() -> DateTime {
+    let read_all = || {
+        for _ in 0..MAX_UPDATE_POLLS {
+            if read_raw(STATUS_A) & STATUS_A_UPDATING == 0 {
+                break;
+            }
+        }
+        let mut regs = [0; 6];
+        for (value, &reg) in regs.iter_mut().zip(RTC_REGS.iter()) {
+            *value = read_raw(reg);
+        }
+        regs
+    };
+    let mut regs = read_all();
+    loop {
+        let again = read_all();
+        if again == regs {
+            break;
+        }
+        regs = again;
+    }
+    let status_b = read_raw(STATUS_B);
+    let [year, month, day, hours, minute, second] = regs;
+    let mut hour = decode(hours & !HOURS_PM, status_b);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 is the first hour of either half
+        hour %= 12;
+        if hours & HOURS_PM != 0 {
+            hour += 12;
+        }
+    }
+    DateTime {
+        year: match century() {
+            // no century register; this century is the likely one
+            0 => 2000,
+            century => century as u16 * 100,
+        } + decode(year, status_b) as u16,
+        month: decode(month, status_b),
+        day: decode(day, status_b),
+        hour,
+        minute: decode(minute, status_b),
+        second: decode(second, status_b),
+    }
+}
+
+/// `cmos [<reg> [value]]`
+pub fn command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let reg = match args.first() {
+        None => {
+            for row in (0..LEN).step_by(16) {
+                write!(out, "{:02x}:", row)?;
+                for reg in row..row + 16 {
+                    write!(out, " {:02x}", read_raw(reg))?;
+                }
+                writeln!(out)?;
+            }
+            let verdict = if checksum_ok() { "good" } else { "bad" };
+            writeln!(out, "checksum {}, century {}", verdict, century())?;
+            return Ok(());
+        }
+        Some(reg) => parse_number(reg)? as u8,
+    };
+    if let Some(value) = args.get(1) {
+        write(reg, parse_number(value)? as u8).map_err(shell_err)?;
+    }
+    writeln!(out, "{:#04x} = {:#04x}", reg, read(reg).map_err(shell_err)?)?;
+    Ok(())
+}
+
+fn shell_err(err: Error) -> ShellErr {
+    match err {
+        Error::OutOfRange(_) => ShellErr::new("CMOS has 128 registers"),
+        Error::Reserved(_) => ShellErr::new("RTC and checksum registers are read-only here"),
+    }
+}
@@ -0,0 +1,224 @@
+//! The interface between the kernel and device drivers, and the registry
+//! that matches drivers to devices.
+//!
+//! A driver is a static implementing `Driver`, listed in `DRIVERS` behind
+//! its own cargo feature (`driver-<name>`), so a build carries only the
+//! drivers it asks for. `probe` walks the PCI bus once at boot and starts,
+//! for each function, the driver registered for its vendor and device id,
+//! or failing that for its class, as for controllers with a standard
+//! programming interface.
+//!
+//! Drivers state the `API_VERSION` they were written against and are
+//! skipped when it differs, so changing this trait set means bumping the
+//! version and revisiting every driver, not finding out at run time.
+
+use super::pci::PciDevice;
+use crate::{interrupts, power, sync::InitCell};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::fmt;
+
+/// The version of `Driver` this kernel calls.
+pub const API_VERSION: u32 = 2;
+
+/// Every driver built in.
+static DRIVERS: &[&dyn Driver] = &[
+    #[cfg(feature = "driver-e1000")]
+    &super::e1000::DRIVER,
+    #[cfg(feature = "driver-virtio-console")]
+    &super::virtio_console::DRIVER,
+    #[cfg(feature = "driver-virtio-9p")]
+    &super::virtio_9p::DRIVER,
+    #[cfg(feature = "driver-xhci")]
+    &super::xhci::DRIVER,
+];
+
+/// The devices a driver took over, fixed once `probe` is done so the
+/// interrupt path can read it without a lock.
+static BOUND: InitCell<Vec<(PciDevice, &'static dyn Driver)>> = InitCell::new("bound drivers");
+
+/// A PCI vendor and device id pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DeviceId {
+    pub vendor: u16,
+    pub device: u16,
+}
+
+impl DeviceId {
+    pub const fn new(vendor: u16, device: u16) -> Self {
+        DeviceId { vendor, device }
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor, self.device)
+    }
+}
+
+/// A PCI class, subclass and programming interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DeviceClass {
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+}
+
+impl DeviceClass {
+    pub const fn new(class: u8, subclass: u8, prog_if: u8) -> Self {
+        DeviceClass {
+            class,
+            subclass,
+            prog_if,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The driver recognised the id but not the hardware behind it.
+    Unsupported,
+    OutOfMemory,
+    /// Anything else, described by the driver.
+    Device(&'static str),
+}
+
+pub trait Driver: Sync {
+    fn name(&self) -> &'static str;
+
+    /// The `API_VERSION` the driver was written against.
+    fn api_version(&self) -> u32;
+
+    /// The devices the driver can run.
+    fn ids(&self) -> &'static [DeviceId];
+
+    /// The classes of device the driver can run, whatever their ids.
+    fn classes(&self) -> &'static [DeviceClass] {
+        &[]
+    }
+
+    /// Takes over `device` and hands it to the subsystem that uses it. On
+    /// an error the device is left unbound.
+    fn init(&self, device: PciDevice) -> Result<(), Error>;
+
+    /// Called on every interrupt from a line with a bound device, so it must
+    /// check that its own device raised it. Must not block or allocate.
+    fn interrupt(&self) {}
+
+    /// Stops the device raising interrupts and doing DMA, keeping its state.
+    fn suspend(&self) {}
+
+    /// Stops the device for good, before power goes.
+    fn remove(&self) {}
+}
+
+/// The built-in drivers by the ids and the classes they run.
+#[derive(Default)]
+struct Registry {
+    ids: BTreeMap<DeviceId, &'static dyn Driver>,
+    classes: BTreeMap<DeviceClass, &'static dyn Driver>,
+}
+
+impl Registry {
+    fn find(&self, device: &PciDevice) -> Option<&'static dyn Driver> {
+        let id = DeviceId::new(device.vendor_id, device.device_id);
+        let class = DeviceClass::new(device.class, device.subclass, device.prog_if);
+        self.ids
+            .get(&id)
+            .or_else(|| self.classes.get(&class))
+            .copied()
+    }
+}
+
+/// Skips drivers written against another `API_VERSION`.
+fn registry() -> Registry {
+    let mut registry = Registry::default();
+    for &driver in DRIVERS {
+        if driver.api_version() != API_VERSION {
+            warn!(
+                "driver {} is for interface version {}, not {}; skipped",
+                driver.name(),
+                driver.api_version(),
+                API_VERSION
+            );
+            continue;
+        }
+        for &id in driver.ids() {
+            if let Some(other) = registry.ids.insert(id, driver) {
+                warn!(
+                    "{} claimed by both {} and {}",
+                    id,
+                    other.name(),
+                    driver.name()
+                );
+            }
+        }
+        for &class in driver.classes() {
+            if let Some(other) = registry.classes.insert(class, driver) {
+                warn!(
+                    "class {:?} claimed by both {} and {}",
+                    class,
+                    other.name(),
+                    driver.name()
+                );
+            }
+        }
+    }
+    registry
+}
+
+/// Starts a driver for every PCI function one is registered for. Runs
+/// once, after the services are up.
+pub fn probe() {
+    let registry = registry();
+    let mut bound = Vec::new();
+    for device in super::pci::devices() {
+        let id = DeviceId::new(device.vendor_id, device.device_id);
+        let driver = match registry.find(&device) {
+            Some(driver) => driver,
+            None => continue,
+        };
+        match driver.init(device) {
+            Ok(()) => {
+                device.bind(driver.name());
+                bound.push((device, driver));
+            }
+            Err(err) => warn!("{}: {} at {}: {:?}", driver.name(), id, device, err),
+        }
+    }
+    let bound = BOUND.init(bound);
+    let mut lines: Vec<u8> = bound
+        .iter()
+        .map(|(device, _)| device.interrupt_line())
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+    for line in lines {
+        if let Err(err) = interrupts::register_irq(line, dispatch_interrupt) {
+            warn!("drivers: cannot use IRQ {}: {:?}", line, err);
+        }
+    }
+    if !bound.is_empty() {
+        power::register_hook("drivers", |_| remove_all());
+    }
+}
+
+/// The drivers running a device, with the device.
+pub fn bound() -> &'static [(PciDevice, &'static dyn Driver)] {
+    BOUND.try_get().map_or(&[], |bound| bound.as_slice())
+}
+
+/// A handler does not know its line, so every driver hears every driver
+/// interrupt and checks its own device, as it must on a shared line anyway.
+fn dispatch_interrupt() {
+    for (_, driver) in bound() {
+        driver.interrupt();
+    }
+}
+
+pub fn suspend_all() {
+    bound().iter().for_each(|(_, driver)| driver.suspend());
+}
+
+fn remove_all() {
+    bound().iter().rev().for_each(|(_, driver)| driver.remove());
+}
@@ -0,0 +1,396 @@
+use super::{
+    driver::{self, DeviceId, Driver},
+    pci::PciDevice,
+};
+use crate::{
+    kernel,
+    net::{self, device::MAX_FRAME_LEN, NetDevice},
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+const INTEL: u16 = 0x8086;
+
+/// 82540EM (QEMU `e1000`), 82545EM, 82574L (QEMU `e1000e`), I217-LM, 82577LM.
+const DEVICE_IDS: [DeviceId; 5] = [
+    DeviceId::new(INTEL, 0x100e),
+    DeviceId::new(INTEL, 0x100f),
+    DeviceId::new(INTEL, 0x10d3),
+    DeviceId::new(INTEL, 0x153a),
+    DeviceId::new(INTEL, 0x10ea),
+];
+
+const MMIO_SIZE: u64 = 0x20000;
+
+const CTRL: u32 = 0x0000;
+const STATUS: u32 = 0x0008;
+const ICR: u32 = 0x00c0;
+const IMS: u32 = 0x00d0;
+const IMC: u32 = 0x00d8;
+const RCTL: u32 = 0x0100;
+const TCTL: u32 = 0x0400;
+const TIPG: u32 = 0x0410;
+const RDBAL: u32 = 0x2800;
+const RDBAH: u32 = 0x2804;
+const RDLEN: u32 = 0x2808;
+const RDH: u32 = 0x2810;
+const RDT: u32 = 0x2818;
+const TDBAL: u32 = 0x3800;
+const TDBAH: u32 = 0x3804;
+const TDLEN: u32 = 0x3808;
+const TDH: u32 = 0x3810;
+const TDT: u32 = 0x3818;
+const MTA: u32 = 0x5200;
+const RAL: u32 = 0x5400;
+const RAH: u32 = 0x5404;
+
+const CTRL_ASDE: u32 = 1 << 5;
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+const RAH_AV: u32 = 1 << 31;
+
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+const RCTL_SECRC: u32 = 1 << 26;
+
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x0f << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+
+/// Recommended inter-packet gap for copper links.
+const TIPG_DEFAULT: u32 = 0x0060_200a;
+
+const ICR_LSC: u32 = 1 << 2;
+const ICR_RXDMT0: u32 = 1 << 4;
+const ICR_RXO: u32 = 1 << 6;
+const ICR_RXT0: u32 = 1 << 7;
+
+const DESC_DD: u8 = 1 << 0;
+const DESC_EOP: u8 = 1 << 1;
+const CMD_EOP: u8 = 1 << 0;
+const CMD_IFCS: u8 = 1 << 1;
+const CMD_RS: u8 = 1 << 3;
+
+const RX_RING_LEN: usize = 32;
+const TX_RING_LEN: usize = 32;
+const BUFFER_SIZE: usize = 2048;
+const BUFFERS_PER_FRAME: usize = 4096 / BUFFER_SIZE;
+
+#[repr(C)]
+struct RxDescriptor {
+    addr: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[repr(C)]
+struct TxDescriptor {
+    addr: u64,
+    length: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+/// Register base used by the interrupt handler.
+static IRQ_MMIO: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+pub enum Error {
+    NoMemoryBar,
+    OutOfMemory,
+    ResetTimeout,
+}
+
+pub struct E1000 {
+    mmio: VirtAddr,
+    mac: [u8; 6],
+    rx_ring: *mut RxDescriptor,
+    rx_buffers: [VirtAddr; RX_RING_LEN],
+    rx_next: usize,
+    tx_ring: *mut TxDescriptor,
+    tx_buffers: [VirtAddr; TX_RING_LEN],
+    tx_next: usize,
+}
+
+// The rings are only touched through `&mut self` once the NIC is attached.
+unsafe impl Send for E1000 {}
+
+fn read_reg(mmio: VirtAddr, reg: u32) -> u32 {
+    unsafe { ptr::read_volatile((mmio + reg as u64).as_ptr()) }
+}
+
+fn write_reg(mmio: VirtAddr, reg: u32, value: u32) {
+    unsafe { ptr::write_volatile((mmio + reg as u64).as_mut_ptr(), value) }
+}
+
+/// Allocates DMA buffers for a ring, filling in each descriptor's address.
+fn alloc_buffers(
+    count: usize,
+    mut set_addr: impl FnMut(usize, PhysAddr),
+) -> Result<Vec<VirtAddr>, Error> {
+    let mut buffers = Vec::with_capacity(count);
+    while buffers.len() < count {
+        let (phys, virt) = kernel::services()
+            .memory()
+            .alloc_dma_frame()
+            .ok_or(Error::OutOfMemory)?;
+        for i in 0..BUFFERS_PER_FRAME.min(count - buffers.len()) {
+            let offset = (i * BUFFER_SIZE) as u64;
+            set_addr(buffers.len(), phys + offset);
+            buffers.push(virt + offset);
+        }
+    }
+    Ok(buffers)
+}
+
+impl E1000 {
+    fn new(pci: PciDevice) -> Result<Self, Error> {
+        let bar = pci.memory_bar(0).ok_or(Error::NoMemoryBar)?;
+        pci.enable_bus_mastering();
+        let mmio = kernel::services()
+            .memory()
+            .map_mmio(PhysAddr::new(bar), MMIO_SIZE)
+            .map_err(|_| Error::OutOfMemory)?;
+
+        write_reg(mmio, IMC, u32::MAX);
+        write_reg(mmio, CTRL, read_reg(mmio, CTRL) | CTRL_RST);
+        let mut spins = 0;
+        while read_reg(mmio, CTRL) & CTRL_RST != 0 {
+            spins += 1;
+            if spins > 1_000_000 {
+                return Err(Error::ResetTimeout);
+            }
+            crate::interrupts::pause();
+        }
+        write_reg(mmio, IMC, u32::MAX);
+        read_reg(mmio, ICR);
+        write_reg(mmio, CTRL, read_reg(mmio, CTRL) | CTRL_SLU | CTRL_ASDE);
+
+        // Firmware (and QEMU) load the EEPROM's address into receive address 0.
+        let ral = read_reg(mmio, RAL);
+        let rah = read_reg(mmio, RAH);
+        let mac = [
+            ral as u8,
+            (ral >> 8) as u8,
+            (ral >> 16) as u8,
+            (ral >> 24) as u8,
+            rah as u8,
+            (rah >> 8) as u8,
+        ];
+        write_reg(mmio, RAH, rah | RAH_AV);
+        for i in 0..128 {
+            write_reg(mmio, MTA + i * 4, 0);
+        }
+
+        let (rx_phys, rx_virt) = kernel::services()
+            .memory()
+            .alloc_dma_frame()
+            .ok_or(Error::OutOfMemory)?;
+        let rx_ring: *mut RxDescriptor = rx_virt.as_mut_ptr();
+        let rx_buffers = alloc_buffers(RX_RING_LEN, |i, addr| unsafe {
+            (*rx_ring.add(i)).addr = addr.as_u64();
+        })?;
+        write_reg(mmio, RDBAL, rx_phys.as_u64() as u32);
+        write_reg(mmio, RDBAH, (rx_phys.as_u64() >> 32) as u32);
+        write_reg(mmio, RDLEN, (RX_RING_LEN * 16) as u32);
+        write_reg(mmio, RDH, 0);
+        write_reg(mmio, RDT, RX_RING_LEN as u32 - 1);
+        write_reg(mmio, RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+
+        let (tx_phys, tx_virt) = kernel::services()
+            .memory()
+            .alloc_dma_frame()
+            .ok_or(Error::OutOfMemory)?;
+        let tx_ring: *mut TxDescriptor = tx_virt.as_mut_ptr();
+        let tx_buffers = alloc_buffers(TX_RING_LEN, |i, addr| unsafe {
+            (*tx_ring.add(i)).addr = addr.as_u64();
+            (*tx_ring.add(i)).status = DESC_DD;
+        })?;
+        write_reg(mmio, TDBAL, tx_phys.as_u64() as u32);
+        write_reg(mmio, TDBAH, (tx_phys.as_u64() >> 32) as u32);
+        write_reg(mmio, TDLEN, (TX_RING_LEN * 16) as u32);
+        write_reg(mmio, TDH, 0);
+        write_reg(mmio, TDT, 0);
+        write_reg(mmio, TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+        write_reg(mmio, TIPG, TIPG_DEFAULT);
+
+        let mut e1000 = E1000 {
+            mmio,
+            mac,
+            rx_ring,
+            rx_buffers: [VirtAddr::zero(); RX_RING_LEN],
+            rx_next: 0,
+            tx_ring,
+            tx_buffers: [VirtAddr::zero(); TX_RING_LEN],
+            tx_next: 0,
+        };
+        e1000.rx_buffers.copy_from_slice(&rx_buffers);
+        e1000.tx_buffers.copy_from_slice(&tx_buffers);
+        Ok(e1000)
+    }
+
+    fn enable_interrupts(&self) {
+        write_reg(self.mmio, IMS, ICR_RXT0 | ICR_LSC | ICR_RXDMT0 | ICR_RXO);
+        read_reg(self.mmio, ICR);
+    }
+}
+
+impl NetDevice for E1000 {
+    fn name(&self) -> &'static str {
+        "e1000"
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn link_up(&self) -> bool {
+        read_reg(self.mmio, STATUS) & STATUS_LU != 0
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let index = self.rx_next;
+            let descriptor = unsafe { &mut *self.rx_ring.add(index) };
+            let status = unsafe { ptr::read_volatile(&descriptor.status) };
+            if status & DESC_DD == 0 {
+                return None;
+            }
+            let length = unsafe { ptr::read_volatile(&descriptor.length) } as usize;
+            let frame = if status & DESC_EOP != 0 && length <= MAX_FRAME_LEN {
+                let buffer = self.rx_buffers[index].as_ptr::<u8>();
+                Some(unsafe { core::slice::from_raw_parts(buffer, length) }.to_vec())
+            } else {
+                // Frames spanning several buffers are larger than we accept.
+                None
+            };
+            unsafe { ptr::write_volatile(&mut descriptor.status, 0) };
+            self.rx_next = (index + 1) % RX_RING_LEN;
+            write_reg(self.mmio, RDT, index as u32);
+            if frame.is_some() {
+                return frame;
+            }
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> bool {
+        self.transmit_vectored(&[frame])
+    }
+
+    fn transmit_vectored(&mut self, parts: &[&[u8]]) -> bool {
+        let index = self.tx_next;
+        let descriptor = unsafe { &mut *self.tx_ring.add(index) };
+        if unsafe { ptr::read_volatile(&descriptor.status) } & DESC_DD == 0 {
+            return false;
+        }
+        let buffer = self.tx_buffers[index].as_mut_ptr::<u8>();
+        let mut length = 0;
+        for part in parts {
+            let n = part.len().min(BUFFER_SIZE - length);
+            unsafe { ptr::copy_nonoverlapping(part.as_ptr(), buffer.add(length), n) };
+            length += n;
+        }
+        unsafe {
+            ptr::write_volatile(&mut descriptor.length, length as u16);
+            ptr::write_volatile(&mut descriptor.cmd, CMD_EOP | CMD_IFCS | CMD_RS);
+            ptr::write_volatile(&mut descriptor.status, 0);
+        }
+        self.tx_next = (index + 1) % TX_RING_LEN;
+        write_reg(self.mmio, TDT, self.tx_next as u32);
+        true
+    }
+
+    fn quiesce(&mut self) {
+        write_reg(self.mmio, IMC, u32::MAX);
+        write_reg(self.mmio, RCTL, read_reg(self.mmio, RCTL) & !RCTL_EN);
+        write_reg(self.mmio, TCTL, read_reg(self.mmio, TCTL) & !TCTL_EN);
+        IRQ_MMIO.store(0, Ordering::Relaxed);
+    }
+}
+
+fn interrupt_handler() {
+    let mmio = VirtAddr::new(IRQ_MMIO.load(Ordering::Relaxed));
+    if mmio.is_null() {
+        return;
+    }
+    // Reading ICR acknowledges the interrupt; zero means another device fired.
+    let cause = read_reg(mmio, ICR);
+    if cause & ICR_LSC != 0 {
+        let up = read_reg(mmio, STATUS) & STATUS_LU != 0;
+        info!("e1000: link {}", if up { "up" } else { "down" });
+    }
+    if cause != 0 {
+        net::notify();
+    }
+}
+
+pub static DRIVER: E1000Driver = E1000Driver;
+
+pub struct E1000Driver;
+
+impl Driver for E1000Driver {
+    fn name(&self) -> &'static str {
+        "e1000"
+    }
+
+    fn api_version(&self) -> u32 {
+        driver::API_VERSION
+    }
+
+    fn ids(&self) -> &'static [DeviceId] {
+        &DEVICE_IDS
+    }
+
+    /// Attaches the NIC to the network stack.
+    fn init(&self, pci: PciDevice) -> Result<(), driver::Error> {
+        let e1000 = E1000::new(pci)?;
+        IRQ_MMIO.store(e1000.mmio.as_u64(), Ordering::Relaxed);
+        e1000.enable_interrupts();
+        kernel::services()
+            .devices()
+            .attach_net(Box::new(e1000))
+            .map_err(|_| driver::Error::Device("network stack busy"))
+    }
+
+    fn interrupt(&self) {
+        interrupt_handler()
+    }
+
+    fn suspend(&self) {
+        let mmio = VirtAddr::new(IRQ_MMIO.load(Ordering::Relaxed));
+        if !mmio.is_null() {
+            write_reg(mmio, IMC, u32::MAX);
+        }
+    }
+
+    fn remove(&self) {
+        let mmio = VirtAddr::new(IRQ_MMIO.swap(0, Ordering::Relaxed));
+        if !mmio.is_null() {
+            write_reg(mmio, IMC, u32::MAX);
+            write_reg(mmio, RCTL, read_reg(mmio, RCTL) & !RCTL_EN);
+            write_reg(mmio, TCTL, read_reg(mmio, TCTL) & !TCTL_EN);
+        }
+    }
+}
+
+impl From<Error> for driver::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::OutOfMemory => driver::Error::OutOfMemory,
+            Error::NoMemoryBar => driver::Error::Unsupported,
+            Error::ResetTimeout => driver::Error::Device("reset timed out"),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Who owns which I/O ports. A driver claims its range before touching
+//! it and gets its ports from the `PortRange` it is handed, so two drivers
+//! probing the same hardware fail loudly instead of corrupting each
+//! other's index registers.
+//!
+//! The platform devices were written against fixed port constants long
+//! before this, and some of them run where no lock can be taken (panic
+//! screen, monitor, early console); `init` records their ranges as
+//! reserved so nothing else claims them.
+
+use crate::{shell::ShellErr, sync::Mutex};
+use alloc::vec::Vec;
+use core::{fmt::Write, ops::RangeInclusive};
+use x86_64::instructions::port::{Port, PortReadOnly};
+
+/// The fixed ranges of the legacy devices, by owner.
+const PLATFORM: [(RangeInclusive<u16>, &str); 10] = [
+    (0x20..=0x21, "pic_8259"),
+    (0x40..=0x43, "pit"),
+    (0x60..=0x64, "keyboard"),
+    (0x70..=0x71, "cmos"),
+    (0x80..=0x80, "post delay"),
+    (0xa0..=0xa1, "pic_8259"),
+    (0x2f8..=0x2ff, "serial"),
+    (0x3c0..=0x3df, "vga"),
+    (0x3f8..=0x3ff, "serial"),
+    (0xcf8..=0xcff, "pci"),
+];
+
+struct Claim {
+    range: RangeInclusive<u16>,
+    owner: &'static str,
+    /// Reserved for good, with no `PortRange` to drop.
+    reserved: bool,
+}
+
+/// Sorted by first port; ranges never overlap.
+static CLAIMS: Mutex<Vec<Claim>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Overlaps ports this owner has.
+    Busy(&'static str),
+    Empty,
+}
+
+/// Claimed ports, released when dropped.
+#[derive(Debug)]
+pub struct PortRange {
+    base: u16,
+    last: u16,
+}
+
+impl PortRange {
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    pub fn last(&self) -> u16 {
+        self.last
+    }
+
+    /// The port `offset` bytes into the range, as wide as `T`.
+    ///
+    /// Panics if it reaches past the range.
+    pub fn port<T>(&self, offset: u16) -> Port<T> {
+        Port::new(self.checked(offset, core::mem::size_of::<T>()))
+    }
+
+    /// Like `port`, for registers that must not be written.
+    pub fn read_only<T>(&self, offset: u16) -> PortReadOnly<T> {
+        PortReadOnly::new(self.checked(offset, core::mem::size_of::<T>()))
+    }
+
+    fn checked(&self, offset: u16, width: usize) -> u16 {
+        assert!(
+            usize::from(offset) + width <= usize::from(self.last - self.base) + 1,
+            "port {:#x}+{} outside {:#x}..={:#x}",
+            self.base,
+            offset,
+            self.base,
+            self.last
+        );
+        self.base + offset
+    }
+}
+
+impl Drop for PortRange {
+    fn drop(&mut self) {
+        CLAIMS
+            .lock()
+            .retain(|claim| *claim.range.start() != self.base);
+    }
+}
+
+fn insert(range: RangeInclusive<u16>, owner: &'static str, reserved: bool) -> Result<(), Error> {
+    if range.is_empty() {
+        return Err(Error::Empty);
+    }
+    let mut claims = CLAIMS.lock();
+    if let Some(claim) = claims
+        .iter()
+        .find(|c| c.range.start() <= range.end() && range.start() <= c.range.end())
+    {
+        return Err(Error::Busy(claim.owner));
+    }
+    let at = claims
+        .iter()
+        .position(|c| c.range.start() > range.start())
+        .unwrap_or(claims.len());
+    claims.insert(
+        at,
+        Claim {
+            range,
+            owner,
+            reserved,
+        },
+    );
+    Ok(())
+}
+
+/// Gives `owner` the ports in `range` until the returned range drops.
+pub fn claim(range: RangeInclusive<u16>, owner: &'static str) -> Result<PortRange, Error> {
+    let (base, last) = (*range.start(), *range.end());
+    insert(range, owner, false)?;
+    Ok(PortRange { base, last })
+}
+
+/// Marks `range` as `owner`'s for good, for code that keeps using its
+/// own port constants.
+pub fn reserve(range: RangeInclusive<u16>, owner: &'static str) -> Result<(), Error> {
+    insert(range, owner, true)
+}
+
+/// Reserves the platform devices' ports.
+pub fn init() {
+    for (range, owner) in PLATFORM.iter().cloned() {
+        if let Err(err) = reserve(range.clone(), owner) {
+            warn!(
+                "ioport: {:#x}..={:#x} for {}: {:?}",
+                range.start(),
+                range.end(),
+                owner,
+                err
+            );
+        }
+    }
+}
+
+/// `ioports`
+pub fn command(_args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let claims: Vec<_> = CLAIMS
+        .lock()
+        .iter()
+        .map(|c| (c.range.clone(), c.owner, c.reserved))
+        .collect();
+    for (range, owner, reserved) in claims {
+        writeln!(
+            out,
+            "{:04x}-{:04x}  {}{}",
+            range.start(),
+            range.end(),
+            owner,
+            if reserved { " (reserved)" } else { "" }
+        )?;
+    }
+    Ok(())
+}
@@ -1,32 +1,201 @@
-use crate::{print, println};
-use conquer_once::spin::OnceCell;
+//! The PS/2 keyboard's scancodes, queued by its interrupt handler, and the
+//! key events every keyboard produces.
+//!
+//! Raw scancodes stay available for `/dev/kbd`; `KeyEventStream` decodes
+//! them in whichever set the keyboard speaks and merges in the events USB
+//! keyboards add with `add_key_event`. `recorder` captures and replays
+//! the events.
+
+use super::scancode::{Decoder, KeyCode, KeyEvent, KeyState, Set1, Set2};
+use crate::{
+    config::keyboard::QUEUE_DEPTH,
+    print,
+    sync::{MpscQueue, MpscSlot, Mutex},
+};
 use core::{
     pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
     task::{Context, Poll},
 };
-use crossbeam_queue::ArrayQueue;
 use futures_util::{
     stream::{Stream, StreamExt},
     task::AtomicWaker,
 };
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use x86_64::instructions::port::Port;
+
+pub mod recorder;
 
-static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static SCANCODE_SLOTS: [MpscSlot<u8>; QUEUE_DEPTH] = [EMPTY_SLOT; QUEUE_DEPTH];
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: MpscSlot<u8> = MpscSlot::new();
+static SCANCODE_QUEUE: MpscQueue<u8> = MpscQueue::from_static(&SCANCODE_SLOTS);
+static EVENT_SLOTS: [MpscSlot<KeyEvent>; QUEUE_DEPTH] = [EMPTY_EVENT_SLOT; QUEUE_DEPTH];
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_EVENT_SLOT: MpscSlot<KeyEvent> = MpscSlot::new();
+/// Events from keyboards that are not on the PS/2 port.
+static EVENT_QUEUE: MpscQueue<KeyEvent> = MpscQueue::from_static(&EVENT_SLOTS);
 static WAKER: AtomicWaker = AtomicWaker::new();
+/// For `poll_scancode`, so reading `/dev/kbd` does not steal the stream's
+/// wakeups.
+static READER_WAKER: AtomicWaker = AtomicWaker::new();
+
+const PS2_DATA: u16 = 0x60;
+const PS2_STATUS: u16 = 0x64;
+const PS2_COMMAND: u16 = 0x64;
+const PS2_OUTPUT_FULL: u8 = 1 << 0;
+const PS2_INPUT_FULL: u8 = 1 << 1;
+const READ_CONFIG: u8 = 0x20;
+/// The controller turns set 2 into set 1 on the way in.
+const CONFIG_TRANSLATE: u8 = 1 << 6;
+/// Status polls before giving up on the controller.
+const PS2_POLLS: usize = 100_000;
+
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+/// The keyboard's scancodes reach the kernel untranslated, in set 2.
+static SET2: AtomicBool = AtomicBool::new(false);
+static CTRL_DOWN: AtomicBool = AtomicBool::new(false);
+static ALT_DOWN: AtomicBool = AtomicBool::new(false);
+static MONITOR_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Follows the interrupt handler's scancodes for the hotkeys.
+static HOTKEY_DECODER: Mutex<Ps2Decoder> = Mutex::new(Ps2Decoder::new());
+
+/// Decodes the PS/2 keyboard in the set `init` found it in.
+pub struct Ps2Decoder {
+    set1: Set1,
+    set2: Set2,
+}
+
+impl Ps2Decoder {
+    pub const fn new() -> Self {
+        Ps2Decoder {
+            set1: Set1::new(),
+            set2: Set2::new(),
+        }
+    }
+}
+
+impl Decoder for Ps2Decoder {
+    fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        if SET2.load(Ordering::Relaxed) {
+            self.set2.feed(byte)
+        } else {
+            self.set1.feed(byte)
+        }
+    }
+
+    fn reset(&mut self) {
+        self.set1.reset();
+        self.set2.reset();
+    }
+}
+
+/// Asks the PS/2 controller whether it translates to set 1. Runs with
+/// interrupts off, so the handler cannot take the reply.
+pub fn init() {
+    let mut status = Port::<u8>::new(PS2_STATUS);
+    let mut wait =
+        |mask: u8, set: bool| (0..PS2_POLLS).any(|_| (unsafe { status.read() } & mask != 0) == set);
+    if !wait(PS2_INPUT_FULL, false) {
+        return;
+    }
+    unsafe { Port::<u8>::new(PS2_COMMAND).write(READ_CONFIG) };
+    if !wait(PS2_OUTPUT_FULL, true) {
+        return;
+    }
+    let config = unsafe { Port::<u8>::new(PS2_DATA).read() };
+    if config & CONFIG_TRANSLATE == 0 {
+        SET2.store(true, Ordering::Relaxed);
+        info!("keyboard: scancode set 2");
+    }
+}
 
 /// Called by the keyboard interrupt handler
 ///
 /// Must not block or allocate.
 pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropping keyboard input");
-        } else {
-            WAKER.wake();
-        }
+    if let Some(event) = HOTKEY_DECODER
+        .try_lock()
+        .and_then(|mut decoder| decoder.feed(scancode))
+    {
+        check_hotkeys(&event);
+    }
+    if SCANCODE_QUEUE.push(scancode).is_err() {
+        warn!("scancode queue full; dropping keyboard input");
     } else {
-        println!("WARNING: scancode queue uninitialized");
+        WAKER.wake();
+        READER_WAKER.wake();
+    }
+}
+
+/// Queues a key event from a keyboard other than the PS/2 one.
+pub fn add_key_event(event: KeyEvent) {
+    check_hotkeys(&event);
+    if push_key_event(event).is_err() {
+        warn!("key event queue full; dropping keyboard input");
+    }
+}
+
+/// Queues `event`, handing it back if the queue is full.
+fn push_key_event(event: KeyEvent) -> Result<(), KeyEvent> {
+    EVENT_QUEUE.push(event)?;
+    WAKER.wake();
+    Ok(())
+}
+
+/// Requests a reboot on Ctrl+Alt+Del and the monitor on Ctrl+Alt+M.
+/// Checked as the keys arrive, so both work when every task is stuck.
+fn check_hotkeys(event: &KeyEvent) {
+    let pressed = event.state == KeyState::Down;
+    let chord = || pressed && CTRL_DOWN.load(Ordering::Relaxed) && ALT_DOWN.load(Ordering::Relaxed);
+    match event.code {
+        KeyCode::ControlLeft | KeyCode::ControlRight => CTRL_DOWN.store(pressed, Ordering::Relaxed),
+        KeyCode::AltLeft | KeyCode::AltRight => ALT_DOWN.store(pressed, Ordering::Relaxed),
+        KeyCode::Delete | KeyCode::NumpadPeriod if chord() => {
+            crate::power::request(crate::power::Action::Reboot)
+        }
+        KeyCode::M if chord() => MONITOR_REQUESTED.store(true, Ordering::Relaxed),
+        _ => {}
+    }
+}
+
+/// Whether Ctrl+Alt+M was pressed since the last call. The monitor reads
+/// the keyboard itself, so the modifiers count as released afterwards.
+pub(crate) fn take_monitor_request() -> bool {
+    if !MONITOR_REQUESTED.swap(false, Ordering::Relaxed) {
+        return false;
     }
+    CTRL_DOWN.store(false, Ordering::Relaxed);
+    ALT_DOWN.store(false, Ordering::Relaxed);
+    if let Some(mut decoder) = HOTKEY_DECODER.try_lock() {
+        decoder.reset();
+    }
+    true
+}
+
+/// Takes a queued scancode without waiting.
+pub fn pop_scancode() -> Option<u8> {
+    SCANCODE_QUEUE.pop()
+}
+
+/// Takes a queued scancode, or wakes `cx` once the next one arrives.
+pub fn poll_scancode(cx: &mut Context) -> Poll<u8> {
+    if let Some(scancode) = SCANCODE_QUEUE.pop() {
+        return Poll::Ready(scancode);
+    }
+    READER_WAKER.register(&cx.waker());
+    match SCANCODE_QUEUE.pop() {
+        Some(scancode) => {
+            READER_WAKER.take();
+            Poll::Ready(scancode)
+        }
+        None => Poll::Pending,
+    }
+}
+
+/// Scancodes dropped because the queue was full, since boot.
+pub fn dropped_scancodes() -> u64 {
+    SCANCODE_QUEUE.overflows()
 }
 
 pub struct ScancodeStream {
@@ -35,48 +204,111 @@ pub struct ScancodeStream {
 
 impl ScancodeStream {
     pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new should only be called once");
+        assert!(
+            !STREAM_TAKEN.swap(true, Ordering::Relaxed),
+            "ScancodeStream::new should only be called once"
+        );
         ScancodeStream { _private: () }
     }
 }
 
+/// Lets a restarted console take the stream again.
+impl Drop for ScancodeStream {
+    fn drop(&mut self) {
+        STREAM_TAKEN.store(false, Ordering::Relaxed);
+    }
+}
+
 impl Stream for ScancodeStream {
     type Item = u8;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
-        let queue = SCANCODE_QUEUE
-            .try_get()
-            .expect("scancode queue not initialized");
-
         // fast path
-        if let Ok(scancode) = queue.pop() {
+        if let Some(scancode) = SCANCODE_QUEUE.pop() {
             return Poll::Ready(Some(scancode));
         }
 
         WAKER.register(&cx.waker());
-        match queue.pop() {
-            Ok(scancode) => {
+        match SCANCODE_QUEUE.pop() {
+            Some(scancode) => {
                 WAKER.take();
                 Poll::Ready(Some(scancode))
             }
-            Err(crossbeam_queue::PopError) => Poll::Pending,
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Every keyboard's key events, in the order they arrived within each
+/// source.
+pub struct KeyEventStream {
+    scancodes: ScancodeStream,
+    decoder: Ps2Decoder,
+    /// Scancodes dropped when last looked; the decoder starts over after
+    /// more are lost.
+    dropped: u64,
+}
+
+impl KeyEventStream {
+    pub fn new() -> Self {
+        KeyEventStream {
+            scancodes: ScancodeStream::new(),
+            decoder: Ps2Decoder::new(),
+            dropped: dropped_scancodes(),
+        }
+    }
+
+    fn pop(&mut self) -> Option<KeyEvent> {
+        let event = self.next_event()?;
+        recorder::record(&event);
+        Some(event)
+    }
+
+    fn next_event(&mut self) -> Option<KeyEvent> {
+        if let Some(event) = EVENT_QUEUE.pop() {
+            return Some(event);
+        }
+        let dropped = dropped_scancodes();
+        if dropped != self.dropped {
+            self.dropped = dropped;
+            self.decoder.reset();
+        }
+        while let Some(scancode) = SCANCODE_QUEUE.pop() {
+            if let Some(event) = self.decoder.feed(scancode) {
+                return Some(event);
+            }
+        }
+        None
+    }
+}
+
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        if let Some(event) = self.pop() {
+            return Poll::Ready(Some(event));
+        }
+        WAKER.register(&cx.waker());
+        match self.pop() {
+            Some(event) => {
+                WAKER.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
         }
     }
 }
 
 pub async fn print_keypresses() {
-    let mut scancodes = ScancodeStream::new();
+    let mut events = KeyEventStream::new();
     let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
 
-    while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => print!("{}", character),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
-                }
+    while let Some(event) = events.next().await {
+        if let Some(key) = keyboard.process_keyevent(event) {
+            match key {
+                DecodedKey::Unicode(character) => print!("{}", character),
+                DecodedKey::RawKey(key) => print!("{:?}", key),
             }
         }
     }
@@ -0,0 +1,178 @@
+//! Recording the key events the console reads and playing them back, so
+//! the shell, the editor and the hotkeys can be driven the same way every
+//! run, by a test or by hand.
+//!
+//! Recording captures events as `KeyEventStream` hands them out, from every
+//! keyboard. Playback queues events as a USB keyboard would, so they go
+//! through the hotkey checks and reach whoever reads the stream; it waits
+//! whenever the queue is full rather than dropping any.
+
+use super::{check_hotkeys, push_key_event, KeyCode, KeyEvent, KeyState};
+use crate::{
+    kernel,
+    shell::ShellErr,
+    sync::Mutex,
+    task::{self, Priority},
+};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// The events recorded so far, while recording.
+static RECORDING: Mutex<Option<Vec<KeyEvent>>> = Mutex::new(None);
+/// What the last recording captured, for `keymacro play`.
+static LAST: Mutex<Vec<KeyEvent>> = Mutex::new(Vec::new());
+
+/// Starts recording, dropping what a recording in progress had.
+pub fn start() {
+    *RECORDING.lock() = Some(Vec::new());
+}
+
+/// Stops recording and returns the events, none when nothing was being
+/// recorded.
+pub fn stop() -> Vec<KeyEvent> {
+    RECORDING.lock().take().unwrap_or_default()
+}
+
+pub fn is_recording() -> bool {
+    RECORDING.lock().is_some()
+}
+
+/// Called by `KeyEventStream` for each event it hands out.
+pub(super) fn record(event: &KeyEvent) {
+    if let Some(events) = RECORDING.lock().as_mut() {
+        events.push(event.clone());
+    }
+}
+
+/// Queues `events` in order, as if typed.
+pub async fn replay(events: Vec<KeyEvent>) {
+    for mut event in events {
+        check_hotkeys(&event);
+        while let Err(full) = push_key_event(event) {
+            event = full;
+            task::yield_init().await;
+        }
+    }
+}
+
+/// The presses and releases that type `text` on a US layout; characters
+/// it has no key for are skipped. Lets a test type a command line.
+pub fn type_text(text: &str) -> Vec<KeyEvent> {
+    let mut events = Vec::new();
+    for c in text.chars() {
+        let (code, shift) = match key_for(c) {
+            Some(key) => key,
+            None => continue,
+        };
+        if shift {
+            events.push(KeyEvent::new(KeyCode::ShiftLeft, KeyState::Down));
+        }
+        events.push(KeyEvent::new(code, KeyState::Down));
+        events.push(KeyEvent::new(code, KeyState::Up));
+        if shift {
+            events.push(KeyEvent::new(KeyCode::ShiftLeft, KeyState::Up));
+        }
+    }
+    events
+}
+
+/// The key that types `c`, and whether it needs Shift.
+fn key_for(c: char) -> Option<(KeyCode, bool)> {
+    use KeyCode::*;
+    const LETTERS: [KeyCode; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    const DIGITS: [KeyCode; 10] = [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    // the digit row shifted, from 0
+    const DIGIT_SYMBOLS: &str = ")!@#$%^&*(";
+    const PUNCTUATION: [(char, char, KeyCode); 11] = [
+        ('-', '_', Minus),
+        ('=', '+', Equals),
+        ('[', '{', BracketSquareLeft),
+        (']', '}', BracketSquareRight),
+        ('\\', '|', BackSlash),
+        (';', ':', SemiColon),
+        ('\'', '"', Quote),
+        ('`', '~', BackTick),
+        (',', '<', Comma),
+        ('.', '>', Fullstop),
+        ('/', '?', Slash),
+    ];
+
+    Some(match c {
+        'a'..='z' => (LETTERS[(c as u8 - b'a') as usize], false),
+        'A'..='Z' => (LETTERS[(c as u8 - b'A') as usize], true),
+        '0'..='9' => (DIGITS[(c as u8 - b'0') as usize], false),
+        ' ' => (Spacebar, false),
+        '\n' => (Enter, false),
+        '\t' => (Tab, false),
+        '\x08' => (Backspace, false),
+        _ => {
+            if let Some(digit) = DIGIT_SYMBOLS.find(c) {
+                return Some((DIGITS[digit], true));
+            }
+            let &(_, shifted, code) = PUNCTUATION
+                .iter()
+                .find(|&&(plain, shifted, _)| c == plain || c == shifted)?;
+            (code, c == shifted)
+        }
+    })
+}
+
+/// Drops what a recording made from the shell caught of the commands around
+/// it: the release of the Enter that ran `keymacro record`, and the line
+/// typing `keymacro stop`, which runs on the press of its Enter.
+fn trim_commands(mut events: Vec<KeyEvent>) -> Vec<KeyEvent> {
+    let enter = |state: KeyState| {
+        move |event: &KeyEvent| event.code == KeyCode::Enter && event.state == state
+    };
+    if events.first().map_or(false, enter(KeyState::Up)) {
+        events.remove(0);
+    }
+    if events.last().map_or(false, enter(KeyState::Down)) {
+        events.pop();
+        let end = events
+            .iter()
+            .rposition(enter(KeyState::Up))
+            .map_or(0, |up| up + 1);
+        events.truncate(end);
+    }
+    events
+}
+
+/// `keymacro record|stop|play|show|type <text>`
+pub fn command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let play = |events: Vec<KeyEvent>| {
+        kernel::services()
+            .spawner()
+            .spawn(Priority::High, replay(events));
+    };
+    match args {
+        ["record"] => start(),
+        ["stop"] => {
+            let events = trim_commands(stop());
+            writeln!(out, "{} events", events.len())?;
+            *LAST.lock() = events;
+        }
+        ["play"] => play(LAST.lock().clone()),
+        ["show"] => {
+            for event in LAST.lock().iter() {
+                writeln!(out, "{:?} {:?}", event.code, event.state)?;
+            }
+            if is_recording() {
+                writeln!(out, "(recording)")?;
+            }
+        }
+        ["type", text @ ..] if !text.is_empty() => {
+            let mut text = text.join(" ");
+            text.push('\n');
+            play(type_text(&text));
+        }
+        _ => {
+            return Err(ShellErr::new(
+                "usage: keymacro record|stop|play|show|type <text>",
+            ))
+        }
+    }
+    Ok(())
+}
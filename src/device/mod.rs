@@ -1,2 +1,108 @@
+use crate::{
+    fs::devfs,
+    shell::{self, ShellErr},
+};
+use core::fmt::Write;
+
+pub mod cmos;
+pub mod driver;
+#[cfg(feature = "driver-e1000")]
+pub mod e1000;
+pub mod ioport;
 pub mod keyboard;
+pub mod parallel;
+pub mod pci;
 pub mod pic_8259;
+pub mod pit;
+pub mod scancode;
+#[cfg(feature = "driver-xhci")]
+pub mod usb_keyboard;
+#[cfg(any(feature = "driver-virtio-console", feature = "driver-virtio-9p"))]
+pub mod virtio;
+#[cfg(feature = "driver-virtio-9p")]
+pub mod virtio_9p;
+#[cfg(feature = "driver-virtio-console")]
+pub mod virtio_console;
+#[cfg(feature = "driver-xhci")]
+pub mod xhci;
+
+/// The legacy devices every PC has, which are found at fixed ports rather
+/// than enumerated, and the module that drives each.
+const PLATFORM: [(&str, &str); 6] = [
+    ("8259 interrupt controllers", "pic_8259"),
+    ("8254 timer", "pit"),
+    ("PS/2 keyboard", "keyboard"),
+    ("16550 serial ports", "serial"),
+    ("parallel port", "parallel"),
+    ("CMOS RAM", "cmos"),
+];
+
+/// A device on fixed legacy ports that moves a byte at a time, such as a
+/// UART or a parallel port; debug output can use whichever one there is.
+pub trait CharDevice: Send {
+    /// Sends `byte` as is, waiting while the device is busy.
+    fn put(&mut self, byte: u8);
+
+    /// A byte the device received, if one is waiting; output-only devices
+    /// never have one.
+    fn get(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn put_all(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.put(byte);
+        }
+    }
+}
+
+/// Probes the platform devices that need it and registers the device
+/// listing commands.
+pub fn init() {
+    ioport::init();
+    keyboard::init();
+    parallel::init();
+    shell::register(
+        "lspci",
+        "lspci [-v]\nLists the PCI functions with their class, IRQ line and driver; -v adds the BARs.",
+        pci::lspci_command,
+    );
+    shell::register(
+        "devices",
+        "devices\nShows the devices by bus, with the driver bound to each.",
+        devices_command,
+    );
+    shell::register(
+        "cmos",
+        "cmos [<reg> [value]]\nDumps the CMOS RAM, or reads or writes one register; writes keep the checksum.",
+        cmos::command,
+    );
+    shell::register(
+        "keymacro",
+        "keymacro record|stop|play|show|type <text>\nRecords the keys typed until `keymacro stop`, replays them, or types <text> and Enter.",
+        keyboard::recorder::command,
+    );
+    shell::register(
+        "ioports",
+        "ioports\nLists the claimed I/O port ranges and their owners.",
+        ioport::command,
+    );
+}
+
+/// `devices`
+fn devices_command(_args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    writeln!(out, "pci")?;
+    for dev in pci::devices() {
+        let driver = dev.driver().unwrap_or("(no driver)");
+        writeln!(out, "  {} {:<24} {}", dev, dev.class_name(), driver)?;
+    }
+    writeln!(out, "platform")?;
+    for &(name, driver) in PLATFORM.iter() {
+        writeln!(out, "  {:<31} {}", name, driver)?;
+    }
+    writeln!(out, "dev")?;
+    for name in devfs::names() {
+        writeln!(out, "  /dev/{}", name)?;
+    }
+    Ok(())
+}
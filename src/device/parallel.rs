@@ -0,0 +1,218 @@
+//! The standard parallel port on LPT1, as output only: `/dev/lp0` and a
+//! log sink, `lp0`, which starts off and is turned on with
+//! `loglevel -s lp0 <level>`.
+//!
+//! The port is polled. Its IRQ 7 is also where the master 8259 sends
+//! spurious interrupts, and a printer that is slow to take a byte is
+//! better waited on than interrupted for.
+
+use super::{
+    ioport::{self, PortRange},
+    CharDevice,
+};
+use crate::{
+    fs::{devfs, Error as FsError},
+    kobject::Kref,
+    logs::{self, Context as LogContext, Sink},
+    sync::Mutex,
+};
+use bitflags::bitflags;
+use core::fmt::{self, Write};
+use log::{LevelFilter, Record};
+use x86_64::instructions::port::{Port, PortReadOnly};
+
+pub const LPT1: u16 = 0x378;
+
+/// Status reads spent waiting for the printer before it is taken to be
+/// offline; each read takes about a microsecond on the ISA bus.
+const BUSY_POLLS: u32 = 100_000;
+
+bitflags! {
+    struct Status: u8 {
+        /// Low while the printer reports an error.
+        const NO_ERROR = 1 << 3;
+        const SELECTED = 1 << 4;
+        const PAPER_OUT = 1 << 5;
+        const ACK = 1 << 6;
+        /// Low while the printer is busy.
+        const NOT_BUSY = 1 << 7;
+    }
+}
+
+bitflags! {
+    struct Control: u8 {
+        const STROBE = 1;
+        const AUTO_LINEFEED = 1 << 1;
+        /// Low resets the printer.
+        const NOT_INIT = 1 << 2;
+        const SELECT = 1 << 3;
+        const IRQ_ENABLE = 1 << 4;
+    }
+}
+
+/// A standard (SPP) parallel port.
+pub struct ParallelPort {
+    data: Port<u8>,
+    status: PortReadOnly<u8>,
+    control: Port<u8>,
+    /// Set when the printer stopped taking bytes; later ones are dropped
+    /// rather than waited for.
+    offline: bool,
+    _ports: PortRange,
+}
+
+impl ParallelPort {
+    /// Drives the parallel port at the start of `ports`.
+    pub fn new(ports: PortRange) -> Self {
+        ParallelPort {
+            data: ports.port(0),
+            status: ports.read_only(1),
+            control: ports.port(2),
+            offline: false,
+            _ports: ports,
+        }
+    }
+
+    /// Whether a port answers: its data latch reads back what was written,
+    /// where an empty bus reads 0xff.
+    pub fn probe(&mut self) -> bool {
+        [0xaa, 0x55].iter().all(|&pattern| unsafe {
+            self.data.write(pattern);
+            self.data.read() == pattern
+        })
+    }
+
+    /// Resets the printer and selects it, with the port's interrupt off.
+    pub fn init(&mut self) {
+        unsafe {
+            self.control.write(Control::SELECT.bits());
+            self.delay();
+            self.control
+                .write((Control::SELECT | Control::NOT_INIT).bits());
+        }
+        self.offline = false;
+    }
+
+    fn status(&mut self) -> Status {
+        Status::from_bits_truncate(unsafe { self.status.read() })
+    }
+
+    /// A few microseconds, long enough for a strobe or reset pulse.
+    fn delay(&mut self) {
+        for _ in 0..4 {
+            self.status();
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+}
+
+impl CharDevice for ParallelPort {
+    /// Latches `byte` and strobes it out once the printer is ready.
+    fn put(&mut self, byte: u8) {
+        if self.offline {
+            return;
+        }
+        let mut polls = 0;
+        while !self.status().contains(Status::NOT_BUSY) {
+            polls += 1;
+            if polls == BUSY_POLLS {
+                self.offline = true;
+                return;
+            }
+        }
+        let control = Control::SELECT | Control::NOT_INIT;
+        unsafe {
+            self.data.write(byte);
+            self.control.write((control | Control::STROBE).bits());
+            self.delay();
+            self.control.write(control.bits());
+        }
+    }
+}
+
+impl fmt::Write for ParallelPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.put(b'\r');
+            }
+            self.put(byte);
+        }
+        Ok(())
+    }
+}
+
+/// LPT1, if the probe found it.
+pub static PORT: Mutex<Option<ParallelPort>> = Mutex::new(None);
+
+struct Lp;
+
+impl devfs::Device for Lp {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        let mut port = PORT.lock();
+        let port = port.as_mut().ok_or(FsError::Io)?;
+        port.put_all(buf);
+        if port.is_offline() {
+            return Err(FsError::Io);
+        }
+        Ok(buf.len())
+    }
+}
+
+struct LpSink;
+
+static LP_SINK: LpSink = LpSink;
+
+impl Sink for LpSink {
+    fn name(&self) -> &'static str {
+        "lp0"
+    }
+
+    fn write(&self, record: &Record, context: &LogContext) {
+        if let Some(mut port) = PORT.try_lock() {
+            if let Some(port) = port.as_mut() {
+                let _ = writeln!(
+                    port,
+                    "{} [{}] {}: {}{}",
+                    context,
+                    record.level(),
+                    record.target(),
+                    record.args(),
+                    context.fields
+                );
+            }
+        }
+    }
+}
+
+/// Looks for LPT1 and, if it is there, publishes `/dev/lp0` and the
+/// `lp0` sink.
+pub fn init() {
+    let ports = match ioport::claim(LPT1..=LPT1 + 2, "parallel") {
+        Ok(ports) => ports,
+        Err(err) => {
+            warn!("parallel: LPT1 ports taken: {:?}", err);
+            return;
+        }
+    };
+    let mut port = ParallelPort::new(ports);
+    if !port.probe() {
+        return;
+    }
+    port.init();
+    *PORT.lock() = Some(port);
+    if let Err(err) = devfs::register("lp0", Kref::new(Lp)) {
+        warn!("parallel: /dev/lp0 not registered: {:?}", err);
+    }
+    if let Err(err) = logs::register_sink(&LP_SINK, LevelFilter::Off) {
+        warn!("parallel: log sink not registered: {:?}", err);
+    }
+    info!("parallel: LPT1 at {:#x}", LPT1);
+}
@@ -0,0 +1,269 @@
+use crate::{
+    shell::{Args, ShellErr},
+    sync::Mutex,
+};
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+const COMMAND: u8 = 0x04;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+struct ConfigSpace {
+    address: Port<u32>,
+    data: Port<u32>,
+}
+
+/// Which driver claimed each device, by bus, device and function.
+static BOUND: Mutex<Vec<((u8, u8, u8), &'static str)>> = Mutex::new(Vec::new());
+
+static CONFIG: Mutex<ConfigSpace> = Mutex::new(ConfigSpace {
+    address: Port::new(CONFIG_ADDRESS),
+    data: Port::new(CONFIG_DATA),
+});
+
+fn config_address(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    1 << 31
+        | (bus as u32) << 16
+        | (device as u32) << 11
+        | (function as u32) << 8
+        | (offset as u32 & 0xfc)
+}
+
+/// Reads a dword of configuration space through the legacy 0xCF8 mechanism.
+pub fn read_config(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    without_interrupts(|| {
+        let mut config = CONFIG.lock();
+        unsafe {
+            config
+                .address
+                .write(config_address(bus, device, function, offset));
+            config.data.read()
+        }
+    })
+}
+
+pub fn write_config(bus: u8, device: u8, function: u8, offset: u8, value: u32) {
+    without_interrupts(|| {
+        let mut config = CONFIG.lock();
+        unsafe {
+            config
+                .address
+                .write(config_address(bus, device, function, offset));
+            config.data.write(value);
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub header_type: u8,
+}
+
+impl PciDevice {
+    fn probe(bus: u8, device: u8, function: u8) -> Option<Self> {
+        let id = read_config(bus, device, function, 0x00);
+        if id & 0xffff == 0xffff {
+            return None;
+        }
+        let class = read_config(bus, device, function, 0x08);
+        let header = read_config(bus, device, function, 0x0c);
+        Some(PciDevice {
+            bus,
+            device,
+            function,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            header_type: (header >> 16) as u8,
+        })
+    }
+
+    pub fn read(&self, offset: u8) -> u32 {
+        read_config(self.bus, self.device, self.function, offset)
+    }
+
+    pub fn write(&self, offset: u8, value: u32) {
+        write_config(self.bus, self.device, self.function, offset, value)
+    }
+
+    pub fn bar(&self, index: u8) -> u32 {
+        self.read(0x10 + index * 4)
+    }
+
+    /// Physical address of a memory BAR, combining both halves of 64-bit ones.
+    pub fn memory_bar(&self, index: u8) -> Option<u64> {
+        let bar = self.bar(index);
+        if bar & 1 != 0 {
+            return None;
+        }
+        let low = (bar & !0xf) as u64;
+        match (bar >> 1) & 0b11 {
+            0b10 => Some(low | (self.bar(index + 1) as u64) << 32),
+            _ => Some(low),
+        }
+    }
+
+    /// Port base of an I/O BAR.
+    pub fn io_bar(&self, index: u8) -> Option<u16> {
+        let bar = self.bar(index);
+        if bar & 1 == 0 {
+            return None;
+        }
+        Some((bar & !0b11) as u16)
+    }
+
+    /// The legacy PIC line firmware routed this device's INTx to.
+    pub fn interrupt_line(&self) -> u8 {
+        self.read(0x3c) as u8
+    }
+
+    pub fn enable_bus_mastering(&self) {
+        let command = self.read(COMMAND);
+        let enabled = (COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER) as u32;
+        self.write(COMMAND, command | enabled);
+    }
+
+    pub fn is_multifunction(&self) -> bool {
+        self.header_type & 0x80 != 0
+    }
+
+    /// Only ordinary devices have six BARs; bridges have two and other
+    /// registers after them.
+    fn bar_count(&self) -> u8 {
+        match self.header_type & 0x7f {
+            0 => 6,
+            1 => 2,
+            _ => 0,
+        }
+    }
+
+    /// What the class code says the device is.
+    pub fn class_name(&self) -> &'static str {
+        match (self.class, self.subclass) {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "storage controller",
+            (0x02, 0x00) => "ethernet controller",
+            (0x02, _) => "network controller",
+            (0x03, _) => "display controller",
+            (0x04, _) => "multimedia controller",
+            (0x05, _) => "memory controller",
+            (0x06, 0x00) => "host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "bridge",
+            (0x07, _) => "communication controller",
+            (0x08, _) => "system peripheral",
+            (0x0c, 0x03) => "USB controller",
+            (0x0c, 0x05) => "SMBus controller",
+            (0x0c, _) => "serial bus controller",
+            _ => "unknown device",
+        }
+    }
+
+    /// The driver that claimed this device, if one did.
+    pub fn driver(&self) -> Option<&'static str> {
+        let address = (self.bus, self.device, self.function);
+        without_interrupts(|| {
+            BOUND
+                .lock()
+                .iter()
+                .find(|&&(bound, _)| bound == address)
+                .map(|&(_, driver)| driver)
+        })
+    }
+
+    /// Records that `driver` now runs this device, for `lspci` and
+    /// `devices`.
+    pub fn bind(&self, driver: &'static str) {
+        let address = (self.bus, self.device, self.function);
+        without_interrupts(|| BOUND.lock().push((address, driver)));
+    }
+}
+
+/// `bus:device.function`, as `lspci` writes it.
+impl fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// Scans every bus, device and function.
+pub fn devices() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+    for bus in 0..=255u8 {
+        for device in 0..32 {
+            let first = match PciDevice::probe(bus, device, 0) {
+                Some(first) => first,
+                None => continue,
+            };
+            devices.push(first);
+            if first.is_multifunction() {
+                devices
+                    .extend((1..8).filter_map(|function| PciDevice::probe(bus, device, function)));
+            }
+        }
+    }
+    devices
+}
+
+/// The first device from `vendor_id` whose id is in `device_ids`.
+pub fn find(vendor_id: u16, device_ids: &[u16]) -> Option<PciDevice> {
+    devices()
+        .into_iter()
+        .find(|dev| dev.vendor_id == vendor_id && device_ids.contains(&dev.device_id))
+}
+
+/// `lspci [-v]`
+pub fn lspci_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    let verbose = args.flag("-v");
+    args.finish()?;
+    for dev in devices() {
+        writeln!(
+            out,
+            "{} {:04x}:{:04x} {:<24} irq {:<3} {}",
+            dev,
+            dev.vendor_id,
+            dev.device_id,
+            dev.class_name(),
+            dev.interrupt_line(),
+            dev.driver().unwrap_or("-")
+        )?;
+        if verbose {
+            write_bars(&dev, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_bars(dev: &PciDevice, out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut index = 0;
+    while index < dev.bar_count() {
+        let bar = dev.bar(index);
+        if let Some(port) = dev.io_bar(index).filter(|&port| port != 0) {
+            writeln!(out, "    BAR{} io  {:#06x}", index, port)?;
+        } else if let Some(addr) = dev.memory_bar(index).filter(|&addr| addr != 0) {
+            writeln!(out, "    BAR{} mem {:#x}", index, addr)?;
+        }
+        // a 64-bit memory BAR takes the next slot for its upper half
+        index += if bar & 0b111 == 0b100 { 2 } else { 1 };
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use spin::Mutex;
+use crate::sync::Mutex;
 use x86_64::instructions::port::Port;
 
 pub static MAIN: Mutex<Pic> = Mutex::new(Pic::new(0x20));
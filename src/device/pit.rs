@@ -0,0 +1,25 @@
+use x86_64::instructions::port::Port;
+
+/// Input clock of the 8253/8254 programmable interval timer.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+/// Channel 0, lobyte/hibyte access, mode 2 (rate generator), binary.
+const RATE_GENERATOR: u8 = 0b0011_0100;
+
+/// Programs channel 0 to fire IRQ 0 `frequency` times per second.
+pub fn init(frequency: u32) {
+    let divisor = (BASE_FREQUENCY / frequency).max(1).min(u16::MAX as u32) as u16;
+
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut channel_0: Port<u8> = Port::new(CHANNEL_0);
+    unsafe {
+        command.write(RATE_GENERATOR);
+        channel_0.write((divisor & 0xff) as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+
+    info!("PIT Driver Initialized at {} Hz", frequency);
+}
@@ -0,0 +1,419 @@
+//! Turning keyboard bytes into key events, whatever scancode set the
+//! keyboard speaks.
+//!
+//! Both sets prefix the keys the 101-key layout added with 0xe0 and send
+//! Pause as a single 0xe1 sequence with no release. Around the extended
+//! keys the keyboard may also send fake shift presses, to keep old
+//! software that ignored the prefix from seeing shifted keys; they are
+//! dropped. The events are `pc_keyboard`'s, so a layout can turn them into
+//! characters whatever produced them.
+
+pub use pc_keyboard::{KeyCode, KeyEvent, KeyState};
+
+const EXTENDED: u8 = 0xe0;
+const PAUSE: u8 = 0xe1;
+/// Set 1: the top bit of a code marks a release.
+const SET1_RELEASED: u8 = 0x80;
+/// Set 2: a release is the code after 0xf0.
+const SET2_RELEASE: u8 = 0xf0;
+/// Bytes after 0xe1 in each set's Pause sequence.
+const SET1_PAUSE_LEN: u8 = 5;
+const SET2_PAUSE_LEN: u8 = 7;
+// the shift codes the fake shifts use
+const SET1_FAKE_SHIFTS: [u8; 2] = [0x2a, 0x36];
+const SET2_FAKE_SHIFTS: [u8; 2] = [0x12, 0x59];
+
+/// Decodes a keyboard's byte stream.
+pub trait Decoder: Send {
+    /// Takes the next byte and returns the key event it completes.
+    fn feed(&mut self, byte: u8) -> Option<KeyEvent>;
+
+    /// Forgets a sequence in progress, after bytes were lost.
+    fn reset(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    Extended,
+    /// Bytes of the Pause sequence still to come.
+    Pause(u8),
+}
+
+fn event(code: KeyCode, pressed: bool) -> KeyEvent {
+    let state = if pressed {
+        KeyState::Down
+    } else {
+        KeyState::Up
+    };
+    KeyEvent::new(code, state)
+}
+
+/// Scancode set 1, which the PS/2 controller translates set 2 into unless
+/// told not to; what the kernel has always assumed.
+#[derive(Debug)]
+pub struct Set1 {
+    state: State,
+}
+
+impl Set1 {
+    pub const fn new() -> Self {
+        Set1 {
+            state: State::Start,
+        }
+    }
+}
+
+impl Decoder for Set1 {
+    fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        match (self.state, byte) {
+            (State::Pause(1), _) => {
+                self.state = State::Start;
+                Some(event(KeyCode::PauseBreak, true))
+            }
+            (State::Pause(left), _) => {
+                self.state = State::Pause(left - 1);
+                None
+            }
+            (State::Start, EXTENDED) => {
+                self.state = State::Extended;
+                None
+            }
+            (State::Start, PAUSE) => {
+                self.state = State::Pause(SET1_PAUSE_LEN);
+                None
+            }
+            (State::Start, _) => {
+                set1(byte & !SET1_RELEASED).map(|code| event(code, byte & SET1_RELEASED == 0))
+            }
+            (State::Extended, _) => {
+                self.state = State::Start;
+                let code = byte & !SET1_RELEASED;
+                if SET1_FAKE_SHIFTS.contains(&code) {
+                    return None;
+                }
+                set1_extended(code).map(|code| event(code, byte & SET1_RELEASED == 0))
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = State::Start;
+    }
+}
+
+/// Scancode set 2, what PS/2 keyboards send natively.
+#[derive(Debug)]
+pub struct Set2 {
+    state: State,
+    release: bool,
+}
+
+impl Set2 {
+    pub const fn new() -> Self {
+        Set2 {
+            state: State::Start,
+            release: false,
+        }
+    }
+}
+
+impl Decoder for Set2 {
+    fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        match (self.state, byte) {
+            (State::Pause(1), _) => {
+                self.state = State::Start;
+                Some(event(KeyCode::PauseBreak, true))
+            }
+            (State::Pause(left), _) => {
+                self.state = State::Pause(left - 1);
+                None
+            }
+            (_, SET2_RELEASE) => {
+                self.release = true;
+                None
+            }
+            (State::Start, EXTENDED) => {
+                self.state = State::Extended;
+                None
+            }
+            (State::Start, PAUSE) => {
+                self.state = State::Pause(SET2_PAUSE_LEN);
+                None
+            }
+            (State::Start, _) => {
+                let pressed = !core::mem::replace(&mut self.release, false);
+                set2(byte).map(|code| event(code, pressed))
+            }
+            (State::Extended, _) => {
+                self.state = State::Start;
+                let pressed = !core::mem::replace(&mut self.release, false);
+                if SET2_FAKE_SHIFTS.contains(&byte) {
+                    return None;
+                }
+                set2_extended(byte).map(|code| event(code, pressed))
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = State::Start;
+        self.release = false;
+    }
+}
+
+fn set1(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Key1,
+        0x03 => Key2,
+        0x04 => Key3,
+        0x05 => Key4,
+        0x06 => Key5,
+        0x07 => Key6,
+        0x08 => Key7,
+        0x09 => Key8,
+        0x0a => Key9,
+        0x0b => Key0,
+        0x0c => Minus,
+        0x0d => Equals,
+        0x0e => Backspace,
+        0x0f => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1a => BracketSquareLeft,
+        0x1b => BracketSquareRight,
+        0x1c => Enter,
+        0x1d => ControlLeft,
+        0x1e => A,
+        0x1f => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => SemiColon,
+        0x28 => Quote,
+        0x29 => BackTick,
+        0x2a => ShiftLeft,
+        0x2b => BackSlash,
+        0x2c => Z,
+        0x2d => X,
+        0x2e => C,
+        0x2f => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Fullstop,
+        0x35 => Slash,
+        0x36 => ShiftRight,
+        0x37 => NumpadStar,
+        0x38 => AltLeft,
+        0x39 => Spacebar,
+        0x3a => CapsLock,
+        0x3b => F1,
+        0x3c => F2,
+        0x3d => F3,
+        0x3e => F4,
+        0x3f => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumpadLock,
+        0x46 => ScrollLock,
+        0x47 => Numpad7,
+        0x48 => Numpad8,
+        0x49 => Numpad9,
+        0x4a => NumpadMinus,
+        0x4b => Numpad4,
+        0x4c => Numpad5,
+        0x4d => Numpad6,
+        0x4e => NumpadPlus,
+        0x4f => Numpad1,
+        0x50 => Numpad2,
+        0x51 => Numpad3,
+        0x52 => Numpad0,
+        0x53 => NumpadPeriod,
+        0x57 => F11,
+        0x58 => F12,
+        _ => return None,
+    })
+}
+
+fn set1_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x10 => PrevTrack,
+        0x19 => NextTrack,
+        0x1c => NumpadEnter,
+        0x1d => ControlRight,
+        0x20 => Mute,
+        0x21 => Calculator,
+        0x22 => Play,
+        0x24 => Stop,
+        0x2e => VolumeDown,
+        0x30 => VolumeUp,
+        0x32 => WWWHome,
+        0x35 => NumpadSlash,
+        0x37 => PrintScreen,
+        0x38 => AltRight,
+        // Ctrl+Pause
+        0x46 => PauseBreak,
+        0x47 => Home,
+        0x48 => ArrowUp,
+        0x49 => PageUp,
+        0x4b => ArrowLeft,
+        0x4d => ArrowRight,
+        0x4f => End,
+        0x50 => ArrowDown,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5b => WindowsLeft,
+        0x5c => WindowsRight,
+        0x5d => Menus,
+        _ => return None,
+    })
+}
+
+fn set2(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0a => F8,
+        0x0b => F6,
+        0x0c => F4,
+        0x0d => Tab,
+        0x0e => BackTick,
+        0x11 => AltLeft,
+        0x12 => ShiftLeft,
+        0x14 => ControlLeft,
+        0x15 => Q,
+        0x16 => Key1,
+        0x1a => Z,
+        0x1b => S,
+        0x1c => A,
+        0x1d => W,
+        0x1e => Key2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Key4,
+        0x26 => Key3,
+        0x29 => Spacebar,
+        0x2a => V,
+        0x2b => F,
+        0x2c => T,
+        0x2d => R,
+        0x2e => Key5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Key6,
+        0x3a => M,
+        0x3b => J,
+        0x3c => U,
+        0x3d => Key7,
+        0x3e => Key8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Key0,
+        0x46 => Key9,
+        0x49 => Fullstop,
+        0x4a => Slash,
+        0x4b => L,
+        0x4c => SemiColon,
+        0x4d => P,
+        0x4e => Minus,
+        0x52 => Quote,
+        0x54 => BracketSquareLeft,
+        0x55 => Equals,
+        0x58 => CapsLock,
+        0x59 => ShiftRight,
+        0x5a => Enter,
+        0x5b => BracketSquareRight,
+        0x5d => BackSlash,
+        0x66 => Backspace,
+        0x69 => Numpad1,
+        0x6b => Numpad4,
+        0x6c => Numpad7,
+        0x70 => Numpad0,
+        0x71 => NumpadPeriod,
+        0x72 => Numpad2,
+        0x73 => Numpad5,
+        0x74 => Numpad6,
+        0x75 => Numpad8,
+        0x76 => Escape,
+        0x77 => NumpadLock,
+        0x78 => F11,
+        0x79 => NumpadPlus,
+        0x7a => Numpad3,
+        0x7b => NumpadMinus,
+        0x7c => NumpadStar,
+        0x7d => Numpad9,
+        0x7e => ScrollLock,
+        0x83 => F7,
+        _ => return None,
+    })
+}
+
+fn set2_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x11 => AltRight,
+        0x14 => ControlRight,
+        0x15 => PrevTrack,
+        0x1f => WindowsLeft,
+        0x21 => VolumeDown,
+        0x23 => Mute,
+        0x27 => WindowsRight,
+        0x2b => Calculator,
+        0x2f => Menus,
+        0x32 => VolumeUp,
+        0x34 => Play,
+        0x3a => WWWHome,
+        0x3b => Stop,
+        0x4a => NumpadSlash,
+        0x4d => NextTrack,
+        0x5a => NumpadEnter,
+        0x69 => End,
+        0x6b => ArrowLeft,
+        0x6c => Home,
+        0x70 => Insert,
+        0x71 => Delete,
+        0x72 => ArrowDown,
+        0x74 => ArrowRight,
+        0x75 => ArrowUp,
+        0x7a => PageDown,
+        0x7c => PrintScreen,
+        0x7d => PageUp,
+        // Ctrl+Pause
+        0x7e => PauseBreak,
+        _ => return None,
+    })
+}
@@ -0,0 +1,133 @@
+//! USB HID boot-protocol keyboards, turned into the same key events as
+//! the keyboard on port 0x60.
+//!
+//! A boot report holds the modifier keys as bits and up to six other keys
+//! held down; comparing each report with the last gives the presses and
+//! releases.
+
+use super::{
+    keyboard,
+    scancode::{KeyCode, KeyEvent, KeyState},
+};
+
+/// Modifier bits in the first byte of a report.
+const MODIFIERS: [KeyCode; 8] = [
+    KeyCode::ControlLeft,
+    KeyCode::ShiftLeft,
+    KeyCode::AltLeft,
+    KeyCode::WindowsLeft,
+    KeyCode::ControlRight,
+    KeyCode::ShiftRight,
+    KeyCode::AltRight,
+    KeyCode::WindowsRight,
+];
+
+/// Size of a boot report.
+pub const REPORT_LEN: usize = 8;
+
+/// What one keyboard last reported.
+#[derive(Default)]
+pub struct BootKeyboard {
+    last: [u8; REPORT_LEN],
+}
+
+impl BootKeyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the key events for what changed since the last report.
+    pub fn report(&mut self, report: &[u8; REPORT_LEN]) {
+        // every slot set to 1 means more keys are down than a report holds
+        if report[2..].iter().all(|&usage| usage == 1) {
+            return;
+        }
+        let (old, new) = (self.last[0], report[0]);
+        for (bit, &code) in MODIFIERS.iter().enumerate() {
+            match (old & 1 << bit != 0, new & 1 << bit != 0) {
+                (false, true) => send(code, false),
+                (true, false) => send(code, true),
+                _ => {}
+            }
+        }
+        for &usage in self.last[2..].iter().filter(|&&u| u != 0) {
+            if let (false, Some(code)) = (report[2..].contains(&usage), key(usage)) {
+                send(code, true);
+            }
+        }
+        for &usage in report[2..].iter().filter(|&&u| u != 0) {
+            if let (false, Some(code)) = (self.last[2..].contains(&usage), key(usage)) {
+                send(code, false);
+            }
+        }
+        self.last = *report;
+    }
+}
+
+/// The key with HID usage `usage`, from page 7.
+fn key(usage: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    const LETTERS: [KeyCode; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    const DIGITS: [KeyCode; 10] = [Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0];
+    const FUNCTION: [KeyCode; 12] = [F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12];
+    const NUMPAD: [KeyCode; 10] = [
+        Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9, Numpad0,
+    ];
+    Some(match usage {
+        0x04..=0x1d => LETTERS[usize::from(usage - 0x04)],
+        0x1e..=0x27 => DIGITS[usize::from(usage - 0x1e)],
+        0x28 => Enter,
+        0x29 => Escape,
+        0x2a => Backspace,
+        0x2b => Tab,
+        0x2c => Spacebar,
+        0x2d => Minus,
+        0x2e => Equals,
+        0x2f => BracketSquareLeft,
+        0x30 => BracketSquareRight,
+        // non-US # sits where US keyboards have \
+        0x31 | 0x32 => BackSlash,
+        0x33 => SemiColon,
+        0x34 => Quote,
+        0x35 => BackTick,
+        0x36 => Comma,
+        0x37 => Fullstop,
+        0x38 => Slash,
+        0x39 => CapsLock,
+        0x3a..=0x45 => FUNCTION[usize::from(usage - 0x3a)],
+        0x46 => PrintScreen,
+        0x47 => ScrollLock,
+        0x48 => PauseBreak,
+        0x49 => Insert,
+        0x4a => Home,
+        0x4b => PageUp,
+        0x4c => Delete,
+        0x4d => End,
+        0x4e => PageDown,
+        0x4f => ArrowRight,
+        0x50 => ArrowLeft,
+        0x51 => ArrowDown,
+        0x52 => ArrowUp,
+        0x53 => NumpadLock,
+        0x54 => NumpadSlash,
+        0x55 => NumpadStar,
+        0x56 => NumpadMinus,
+        0x57 => NumpadPlus,
+        0x58 => NumpadEnter,
+        0x59..=0x62 => NUMPAD[usize::from(usage - 0x59)],
+        0x63 => NumpadPeriod,
+        0x65 => Menus,
+        _ => return None,
+    })
+}
+
+fn send(code: KeyCode, release: bool) {
+    let state = if release {
+        KeyState::Up
+    } else {
+        KeyState::Down
+    };
+    keyboard::add_key_event(KeyEvent::new(code, state));
+}
@@ -0,0 +1,252 @@
+//! What the virtio drivers share: the legacy PCI transport, driven through
+//! I/O BAR 0, and split virtqueues.
+//!
+//! A driver resets the device with `begin`, builds its queues, then calls
+//! `ready`; on an error in between, `fail` tells the device to give up.
+
+use super::{driver, pci::PciDevice};
+use crate::kernel;
+use alloc::vec::Vec;
+use core::{
+    ptr,
+    sync::atomic::{fence, Ordering},
+};
+use x86_64::{instructions::port::Port, PhysAddr, VirtAddr};
+
+pub const VENDOR: u16 = 0x1af4;
+
+// legacy register offsets from BAR 0
+const GUEST_FEATURES: u16 = 0x04;
+const QUEUE_PFN: u16 = 0x08;
+const QUEUE_SIZE: u16 = 0x0c;
+const QUEUE_SELECT: u16 = 0x0e;
+const QUEUE_NOTIFY: u16 = 0x10;
+const DEVICE_STATUS: u16 = 0x12;
+const ISR_STATUS: u16 = 0x13;
+/// The device-specific configuration, without MSI-X.
+const CONFIG: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 0x80;
+
+pub const DESC_F_NEXT: u16 = 1;
+pub const DESC_F_WRITE: u16 = 2;
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    NoIoBar,
+    /// The device lacks a queue the driver needs.
+    NoQueue(u16),
+    OutOfMemory,
+}
+
+impl From<Error> for driver::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoIoBar => driver::Error::Unsupported,
+            Error::NoQueue(_) => driver::Error::Device("missing virtqueue"),
+            Error::OutOfMemory => driver::Error::OutOfMemory,
+        }
+    }
+}
+
+fn read8(io: u16, reg: u16) -> u8 {
+    unsafe { Port::new(io + reg).read() }
+}
+
+fn write8(io: u16, reg: u16, value: u8) {
+    unsafe { Port::new(io + reg).write(value) }
+}
+
+fn read16(io: u16, reg: u16) -> u16 {
+    unsafe { Port::new(io + reg).read() }
+}
+
+fn write16(io: u16, reg: u16, value: u16) {
+    unsafe { Port::new(io + reg).write(value) }
+}
+
+fn write32(io: u16, reg: u16, value: u32) {
+    unsafe { Port::new(io + reg).write(value) }
+}
+
+/// Resets the device behind `pci` and acknowledges it with no optional
+/// features, returning its I/O base.
+pub fn begin(pci: PciDevice) -> Result<u16, Error> {
+    let io = pci.io_bar(0).ok_or(Error::NoIoBar)?;
+    pci.enable_bus_mastering();
+    write8(io, DEVICE_STATUS, 0);
+    write8(io, DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+    write32(io, GUEST_FEATURES, 0);
+    Ok(io)
+}
+
+/// Lets the device start using the queues.
+pub fn ready(io: u16) {
+    write8(
+        io,
+        DEVICE_STATUS,
+        read8(io, DEVICE_STATUS) | STATUS_DRIVER_OK,
+    );
+}
+
+pub fn fail(io: u16) {
+    write8(io, DEVICE_STATUS, STATUS_FAILED);
+}
+
+/// Stops the device for good; it forgets its queues.
+pub fn reset(io: u16) {
+    write8(io, DEVICE_STATUS, 0);
+}
+
+/// Reads and so acknowledges the interrupt status; zero means the device
+/// did not interrupt.
+pub fn interrupt_status(io: u16) -> u8 {
+    read8(io, ISR_STATUS)
+}
+
+pub fn read_config8(io: u16, offset: u16) -> u8 {
+    read8(io, CONFIG + offset)
+}
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// A split virtqueue in the legacy layout: descriptors and the available
+/// ring, then the used ring on the next page boundary.
+pub struct Virtqueue {
+    io: u16,
+    index: u16,
+    size: u16,
+    desc: *mut Descriptor,
+    avail: *mut u16,
+    used: *const u8,
+    last_used: u16,
+}
+
+// Drivers keep their queues behind a lock.
+unsafe impl Send for Virtqueue {}
+
+impl Virtqueue {
+    /// Sets up queue `index` at the size the device asks for.
+    pub fn new(io: u16, index: u16) -> Result<Self, Error> {
+        write16(io, QUEUE_SELECT, index);
+        let size = read16(io, QUEUE_SIZE);
+        if size == 0 {
+            return Err(Error::NoQueue(index));
+        }
+        let n = size as usize;
+        let used_offset = align_page(n * 16 + 6 + 2 * n);
+        let pages = (used_offset + align_page(6 + 8 * n)) / 4096;
+        let (phys, virt) = kernel::services()
+            .memory()
+            .alloc_dma_frames(pages)
+            .ok_or(Error::OutOfMemory)?;
+        write32(io, QUEUE_PFN, (phys.as_u64() / 4096) as u32);
+        Ok(Virtqueue {
+            io,
+            index,
+            size,
+            desc: virt.as_mut_ptr(),
+            avail: (virt + n as u64 * 16).as_mut_ptr(),
+            used: (virt + used_offset as u64).as_ptr(),
+            last_used: 0,
+        })
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Points descriptor `id` at `len` bytes at `addr`, chained to `next`
+    /// if `flags` has `DESC_F_NEXT`.
+    pub fn set(&mut self, id: u16, addr: PhysAddr, len: usize, flags: u16, next: u16) {
+        assert!(id < self.size, "virtqueue descriptor {} out of range", id);
+        let descriptor = Descriptor {
+            addr: addr.as_u64(),
+            len: len as u32,
+            flags,
+            next,
+        };
+        unsafe { ptr::write_volatile(self.desc.add(id as usize), descriptor) };
+    }
+
+    pub fn set_len(&mut self, id: u16, len: usize) {
+        assert!(id < self.size, "virtqueue descriptor {} out of range", id);
+        unsafe { ptr::write_volatile(&mut (*self.desc.add(id as usize)).len, len as u32) };
+    }
+
+    /// Hands the chain starting at descriptor `id` to the device; it only
+    /// looks once notified.
+    pub fn push(&mut self, id: u16) {
+        unsafe {
+            let idx = ptr::read_volatile(self.avail.add(1));
+            ptr::write_volatile(self.avail.add(2 + (idx % self.size) as usize), id);
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(self.avail.add(1), idx.wrapping_add(1));
+        }
+    }
+
+    pub fn notify(&self) {
+        fence(Ordering::SeqCst);
+        write16(self.io, QUEUE_NOTIFY, self.index);
+    }
+
+    /// The next chain the device is done with, and the bytes it wrote.
+    pub fn pop_used(&mut self) -> Option<(u16, usize)> {
+        unsafe {
+            let idx = ptr::read_volatile(self.used.add(2) as *const u16);
+            if idx == self.last_used {
+                return None;
+            }
+            fence(Ordering::SeqCst);
+            let elem = self.used.add(4 + 8 * (self.last_used % self.size) as usize) as *const u32;
+            self.last_used = self.last_used.wrapping_add(1);
+            Some((
+                ptr::read_volatile(elem) as u16,
+                ptr::read_volatile(elem.add(1)) as usize,
+            ))
+        }
+    }
+
+    /// Asks the device not to interrupt when it uses a chain.
+    pub fn mask_interrupts(&mut self) {
+        unsafe { ptr::write_volatile(self.avail, AVAIL_F_NO_INTERRUPT) };
+    }
+}
+
+/// Gives the first `count` descriptors of `queue` a `size`-byte buffer
+/// each, writable by the device if `flags` says so, and returns where the
+/// kernel sees them. `size` must divide 4096.
+pub fn alloc_buffers(
+    queue: &mut Virtqueue,
+    count: usize,
+    size: usize,
+    flags: u16,
+) -> Result<Vec<VirtAddr>, Error> {
+    let mut buffers = Vec::with_capacity(count);
+    while buffers.len() < count {
+        let (phys, virt) = kernel::services()
+            .memory()
+            .alloc_dma_frame()
+            .ok_or(Error::OutOfMemory)?;
+        for i in 0..(4096 / size).min(count - buffers.len()) {
+            let offset = (i * size) as u64;
+            queue.set(buffers.len() as u16, phys + offset, size, flags, 0);
+            buffers.push(virt + offset);
+        }
+    }
+    Ok(buffers)
+}
+
+fn align_page(len: usize) -> usize {
+    (len + 4095) & !4095
+}
@@ -0,0 +1,199 @@
+//! virtio 9P transport (QEMU `-virtfs local,path=<dir>,mount_tag=<tag>,
+//! security_model=none`), mounting the shared directory at `/mnt`.
+//!
+//! Only the first share is mounted; later ones find `/mnt` busy. A request
+//! and its reply each take one buffer, so the message size is what one
+//! buffer holds. Replies are polled for, with the queue's interrupts off.
+
+use super::{
+    driver::{self, DeviceId, Driver},
+    pci::PciDevice,
+    virtio::{self, Virtqueue, DESC_F_NEXT, DESC_F_WRITE},
+};
+use crate::{
+    fs::{
+        self,
+        p9::{P9Fs, Transport},
+        Error as FsError,
+    },
+    interrupts, kernel,
+    sync::Mutex,
+    time,
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::{
+    ptr, slice,
+    sync::atomic::{AtomicU16, Ordering},
+};
+use x86_64::VirtAddr;
+
+const DEVICE_IDS: [DeviceId; 1] = [DeviceId::new(virtio::VENDOR, 0x1009)];
+
+pub const MOUNT_POINT: &str = "/mnt";
+
+const REQUEST_QUEUE: u16 = 0;
+/// Pages in each of the request and reply buffers.
+const BUFFER_PAGES: usize = 2;
+const BUFFER_SIZE: usize = BUFFER_PAGES * 4096;
+/// How long the server has to answer before the request fails.
+const TIMEOUT_MS: u64 = 5000;
+
+const REQUEST: u16 = 0;
+const REPLY: u16 = 1;
+
+/// I/O base of the mounted device, for acknowledging its interrupts.
+static IRQ_IO: AtomicU16 = AtomicU16::new(0);
+
+/// The device and its buffers, one request at a time.
+struct Channel {
+    queue: Virtqueue,
+    request: VirtAddr,
+    reply: VirtAddr,
+    /// Set once a request timed out; its buffers stay the device's.
+    stuck: bool,
+}
+
+struct Virtio9p {
+    io: u16,
+    channel: Mutex<Channel>,
+}
+
+impl Virtio9p {
+    fn new(pci: PciDevice) -> Result<Self, virtio::Error> {
+        let io = virtio::begin(pci)?;
+        let channel = Virtio9p::channel(io).map_err(|err| {
+            virtio::fail(io);
+            err
+        })?;
+        virtio::ready(io);
+        Ok(Virtio9p {
+            io,
+            channel: Mutex::new(channel),
+        })
+    }
+
+    fn channel(io: u16) -> Result<Channel, virtio::Error> {
+        let mut queue = Virtqueue::new(io, REQUEST_QUEUE)?;
+        let memory = kernel::services().memory();
+        let (request_phys, request) = memory
+            .alloc_dma_frames(BUFFER_PAGES)
+            .ok_or(virtio::Error::OutOfMemory)?;
+        let (reply_phys, reply) = memory
+            .alloc_dma_frames(BUFFER_PAGES)
+            .ok_or(virtio::Error::OutOfMemory)?;
+        queue.set(REQUEST, request_phys, BUFFER_SIZE, DESC_F_NEXT, REPLY);
+        queue.set(REPLY, reply_phys, BUFFER_SIZE, DESC_F_WRITE, 0);
+        queue.mask_interrupts();
+        Ok(Channel {
+            queue,
+            request,
+            reply,
+            stuck: false,
+        })
+    }
+
+    /// The share's name, from the device configuration.
+    fn mount_tag(&self) -> String {
+        let len = u16::from_le_bytes([
+            virtio::read_config8(self.io, 0),
+            virtio::read_config8(self.io, 1),
+        ]);
+        let tag: Vec<u8> = (0..len)
+            .map(|i| virtio::read_config8(self.io, 2 + i))
+            .collect();
+        String::from_utf8_lossy(&tag).into_owned()
+    }
+}
+
+impl Transport for Virtio9p {
+    fn max_message(&self) -> usize {
+        BUFFER_SIZE
+    }
+
+    /// Waits for the reply by polling the used ring; the server answers
+    /// from another host thread, usually within microseconds.
+    fn rpc(&self, request: &[u8]) -> Result<Vec<u8>, FsError> {
+        if request.len() > BUFFER_SIZE {
+            return Err(FsError::InvalidArgument);
+        }
+        let mut channel = self.channel.lock();
+        if channel.stuck {
+            return Err(FsError::Io);
+        }
+        unsafe {
+            let dst = channel.request.as_mut_ptr::<u8>();
+            ptr::copy_nonoverlapping(request.as_ptr(), dst, request.len());
+        }
+        channel.queue.set_len(REQUEST, request.len());
+        channel.queue.push(REQUEST);
+        channel.queue.notify();
+        let deadline = time::uptime_ms() + TIMEOUT_MS;
+        let len = loop {
+            if let Some((_, len)) = channel.queue.pop_used() {
+                break len.min(BUFFER_SIZE);
+            }
+            if time::uptime_ms() > deadline {
+                channel.stuck = true;
+                error!("virtio-9p: no reply in {} ms", TIMEOUT_MS);
+                return Err(FsError::Io);
+            }
+            interrupts::pause();
+        };
+        let reply = unsafe { slice::from_raw_parts(channel.reply.as_ptr::<u8>(), len) };
+        Ok(reply.to_vec())
+    }
+}
+
+pub static DRIVER: Virtio9pDriver = Virtio9pDriver;
+
+pub struct Virtio9pDriver;
+
+impl Driver for Virtio9pDriver {
+    fn name(&self) -> &'static str {
+        "virtio-9p"
+    }
+
+    fn api_version(&self) -> u32 {
+        driver::API_VERSION
+    }
+
+    fn ids(&self) -> &'static [DeviceId] {
+        &DEVICE_IDS
+    }
+
+    /// Attaches to the share and mounts it at `MOUNT_POINT`.
+    fn init(&self, pci: PciDevice) -> Result<(), driver::Error> {
+        let transport = Arc::new(Virtio9p::new(pci)?);
+        let io = transport.io;
+        let tag = transport.mount_tag();
+        let mounted =
+            P9Fs::attach(transport, "").and_then(|p9| fs::mount(MOUNT_POINT, Arc::new(p9)));
+        match mounted {
+            Ok(()) => {
+                IRQ_IO.store(io, Ordering::Relaxed);
+                info!("virtio-9p: share {:?} at {}", tag, MOUNT_POINT);
+                Ok(())
+            }
+            Err(err) => {
+                virtio::reset(io);
+                warn!("virtio-9p: share {:?} not mounted: {:?}", tag, err);
+                Err(driver::Error::Device("cannot mount the share"))
+            }
+        }
+    }
+
+    /// Only configuration changes get through, and are ignored.
+    fn interrupt(&self) {
+        let io = IRQ_IO.load(Ordering::Relaxed);
+        if io != 0 {
+            virtio::interrupt_status(io);
+        }
+    }
+
+    fn remove(&self) {
+        let io = IRQ_IO.swap(0, Ordering::Relaxed);
+        if io != 0 {
+            virtio::reset(io);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! virtio console (QEMU `-device virtio-serial -device virtconsole`), as
+//! `/dev/hvc0`, a log sink and a shell.
+//!
+//! Bytes cross as whole buffers rather than one port write each, so this
+//! is far faster than the emulated 16550 and never drops output because a
+//! FIFO was full. Only the legacy PCI interface is driven, and only port 0:
+//! multiport is not negotiated, so further `virtserialport`s stay closed.
+
+use super::{
+    driver::{self, DeviceId, Driver},
+    pci::PciDevice,
+    virtio::{self, Virtqueue, DESC_F_WRITE},
+};
+use crate::{
+    console::Input,
+    fs::{devfs, Error as FsError},
+    kernel,
+    kobject::Kref,
+    logs::{self, Context as LogContext, Sink},
+    shell::{self, LineEditor},
+    sync::Mutex,
+    task::Priority,
+};
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    future::Future,
+    pin::Pin,
+    ptr,
+    sync::atomic::{AtomicU16, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use log::Record;
+use x86_64::VirtAddr;
+
+const DEVICE_IDS: [DeviceId; 1] = [DeviceId::new(virtio::VENDOR, 0x1003)];
+
+/// Port 0's queues; the control queues only exist with multiport.
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+
+const BUFFERS: usize = 16;
+const BUFFER_SIZE: usize = 2048;
+/// Times to look for a transmit buffer coming back before giving up on
+/// the rest of a write.
+const TRANSMIT_SPINS: usize = 100_000;
+
+/// I/O base used by the interrupt handler; zero while no device runs.
+static IRQ_IO: AtomicU16 = AtomicU16::new(0);
+static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+struct Console {
+    io: u16,
+    rx: Virtqueue,
+    rx_buffers: Vec<VirtAddr>,
+    tx: Virtqueue,
+    tx_buffers: Vec<VirtAddr>,
+    /// A receive buffer partly read: its descriptor, how far and its length.
+    partial: Option<(u16, usize, usize)>,
+    tx_free: Vec<u16>,
+}
+
+impl Console {
+    fn new(pci: PciDevice) -> Result<Self, virtio::Error> {
+        let io = virtio::begin(pci)?;
+        Console::with_queues(io).map_err(|err| {
+            virtio::fail(io);
+            err
+        })
+    }
+
+    fn with_queues(io: u16) -> Result<Self, virtio::Error> {
+        let mut rx = Virtqueue::new(io, RECEIVE_QUEUE)?;
+        let count = BUFFERS.min(rx.size() as usize);
+        let rx_buffers = virtio::alloc_buffers(&mut rx, count, BUFFER_SIZE, DESC_F_WRITE)?;
+        let mut tx = Virtqueue::new(io, TRANSMIT_QUEUE)?;
+        let count = BUFFERS.min(tx.size() as usize);
+        let tx_buffers = virtio::alloc_buffers(&mut tx, count, BUFFER_SIZE, 0)?;
+
+        for id in 0..rx_buffers.len() as u16 {
+            rx.push(id);
+        }
+        let tx_free = (0..tx_buffers.len() as u16).collect();
+        virtio::ready(io);
+        rx.notify();
+        Ok(Console {
+            io,
+            rx,
+            rx_buffers,
+            tx,
+            tx_buffers,
+            partial: None,
+            tx_free,
+        })
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut read = 0;
+        let mut recycled = false;
+        while read < buf.len() {
+            let (id, offset, len) = match self.partial.take() {
+                Some(partial) => partial,
+                None => match self.rx.pop_used() {
+                    Some((id, len)) => (id, 0, len.min(BUFFER_SIZE)),
+                    None => break,
+                },
+            };
+            let n = (len - offset).min(buf.len() - read);
+            let src = self.rx_buffers[id as usize].as_ptr::<u8>();
+            unsafe { ptr::copy_nonoverlapping(src.add(offset), buf[read..].as_mut_ptr(), n) };
+            read += n;
+            if offset + n < len {
+                self.partial = Some((id, offset + n, len));
+            } else {
+                self.rx.push(id);
+                recycled = true;
+            }
+        }
+        if recycled {
+            self.rx.notify();
+        }
+        read
+    }
+
+    /// Queues as much of `buf` as buffers come free for, returning how much.
+    fn write(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+        for chunk in buf.chunks(BUFFER_SIZE) {
+            let id = match self.free_tx_buffer() {
+                Some(id) => id,
+                None => break,
+            };
+            let dst = self.tx_buffers[id as usize].as_mut_ptr::<u8>();
+            unsafe { ptr::copy_nonoverlapping(chunk.as_ptr(), dst, chunk.len()) };
+            self.tx.set_len(id, chunk.len());
+            self.tx.push(id);
+            self.tx.notify();
+            written += chunk.len();
+        }
+        written
+    }
+
+    fn free_tx_buffer(&mut self) -> Option<u16> {
+        for _ in 0..TRANSMIT_SPINS {
+            while let Some((id, _)) = self.tx.pop_used() {
+                self.tx_free.push(id);
+            }
+            if let Some(id) = self.tx_free.pop() {
+                return Some(id);
+            }
+            crate::interrupts::pause();
+        }
+        None
+    }
+}
+
+/// `/dev/hvc0`.
+struct Hvc;
+
+impl devfs::Device for Hvc {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        match CONSOLE.lock().as_mut() {
+            Some(console) => Ok(console.read(buf)),
+            None => Err(FsError::Io),
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        match CONSOLE.lock().as_mut() {
+            Some(console) => Ok(console.write(buf)),
+            None => Err(FsError::Io),
+        }
+    }
+
+    fn poll_read(&self, cx: &mut Context, buf: &mut [u8]) -> Poll<Result<usize, FsError>> {
+        match self.read(buf) {
+            Ok(0) if !buf.is_empty() => {}
+            result => return Poll::Ready(result),
+        }
+        WAKER.register(cx.waker());
+        match self.read(buf) {
+            Ok(0) if !buf.is_empty() => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+struct HvcSink;
+
+static HVC_SINK: HvcSink = HvcSink;
+
+impl Sink for HvcSink {
+    fn name(&self) -> &'static str {
+        "hvc0"
+    }
+
+    fn write(&self, record: &Record, context: &LogContext) {
+        if let Some(mut console) = CONSOLE.try_lock() {
+            if let Some(console) = console.as_mut() {
+                let mut line = Crlf(String::new());
+                let _ = writeln!(
+                    line,
+                    "{} [{}] {}: {}{}",
+                    context,
+                    record.level(),
+                    record.target(),
+                    record.args(),
+                    context.fields
+                );
+                console.write(line.0.as_bytes());
+            }
+        }
+    }
+}
+
+/// Output for a terminal, with `\n` sent as `\r\n`.
+struct Crlf(String);
+
+impl Write for Crlf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if c == '\n' {
+                self.0.push('\r');
+            }
+            self.0.push(c);
+        }
+        Ok(())
+    }
+}
+
+/// Reads what `/dev/hvc0` has, waiting for at least one byte.
+struct Read<'a>(&'a mut [u8]);
+
+impl Future for Read<'_> {
+    type Output = Result<usize, FsError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        devfs::Device::poll_read(&Hvc, cx, &mut self.0)
+    }
+}
+
+/// Serves the kernel shell on the console for as long as the device runs.
+async fn shell_task() {
+    let mut output = Crlf(String::new());
+    let mut editor = LineEditor::new();
+    let mut buf = [0; 256];
+
+    let _ = write!(output, "microkernel on hvc0\n{}", shell::PROMPT);
+    loop {
+        if !output.0.is_empty() {
+            let _ = devfs::Device::write(&Hvc, output.0.as_bytes());
+            output.0.clear();
+        }
+        let read = match Read(&mut buf).await {
+            Ok(read) => read,
+            Err(_) => return,
+        };
+        for &byte in &buf[..read] {
+            match editor.push(byte as char, &mut output) {
+                Some(Input::Line(line)) => shell::execute(&line, &mut output),
+                // the console stays for as long as the device does
+                Some(Input::Interrupt) | Some(Input::EndOfFile) => {}
+                None => continue,
+            }
+            let _ = output.write_str(shell::PROMPT);
+        }
+    }
+}
+
+pub static DRIVER: VirtioConsoleDriver = VirtioConsoleDriver;
+
+pub struct VirtioConsoleDriver;
+
+impl Driver for VirtioConsoleDriver {
+    fn name(&self) -> &'static str {
+        "virtio-console"
+    }
+
+    fn api_version(&self) -> u32 {
+        driver::API_VERSION
+    }
+
+    fn ids(&self) -> &'static [DeviceId] {
+        &DEVICE_IDS
+    }
+
+    /// Publishes `/dev/hvc0`, then logs and runs a shell on it.
+    fn init(&self, pci: PciDevice) -> Result<(), driver::Error> {
+        let mut console = CONSOLE.lock();
+        if console.is_some() {
+            return Err(driver::Error::Device("only one console is driven"));
+        }
+        let new = Console::new(pci)?;
+        IRQ_IO.store(new.io, Ordering::Relaxed);
+        *console = Some(new);
+        drop(console);
+
+        devfs::register("hvc0", Kref::new(Hvc))
+            .map_err(|_| driver::Error::Device("/dev/hvc0 taken"))?;
+        if let Err(err) = logs::register_sink(&HVC_SINK, logs::SERIAL_LOG_LEVEL) {
+            warn!("virtio-console: no log sink: {:?}", err);
+        }
+        kernel::services()
+            .spawner()
+            .spawn(Priority::Medium, shell_task());
+        Ok(())
+    }
+
+    fn interrupt(&self) {
+        let io = IRQ_IO.load(Ordering::Relaxed);
+        if io != 0 && virtio::interrupt_status(io) != 0 {
+            WAKER.wake();
+        }
+    }
+
+    fn suspend(&self) {
+        if let Some(mut console) = CONSOLE.try_lock() {
+            if let Some(console) = console.as_mut() {
+                console.rx.mask_interrupts();
+                console.tx.mask_interrupts();
+            }
+        }
+    }
+
+    fn remove(&self) {
+        let _ = logs::remove_sink(HVC_SINK.name());
+        let io = IRQ_IO.swap(0, Ordering::Relaxed);
+        if io != 0 {
+            virtio::reset(io);
+        }
+        if let Some(mut console) = CONSOLE.try_lock() {
+            *console = None;
+        }
+    }
+}
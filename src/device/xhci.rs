@@ -0,0 +1,854 @@
+//! xHCI USB host controllers, enough to run a boot-protocol keyboard on
+//! machines with no PS/2 controller.
+//!
+//! At boot the controller is reset and every connected root port is reset
+//! and addressed: each device gets a slot, its descriptors are read over
+//! control transfers on endpoint 0 and it is logged. A HID boot keyboard
+//! is configured and its interrupt endpoint polled by `keyboard_task`,
+//! which feeds the reports to `usb_keyboard`.
+//!
+//! The event ring is polled rather than interrupt driven, commands and
+//! control transfers wait for their completion, and hubs, mice and mass
+//! storage are not handled yet. Only the first controller is driven.
+
+use super::{
+    driver::{self, DeviceClass, DeviceId, Driver},
+    pci::PciDevice,
+    usb_keyboard::{BootKeyboard, REPORT_LEN},
+};
+use crate::{interrupts, kernel, sync::Mutex, task::Priority, time};
+use alloc::vec::Vec;
+use core::ptr;
+use x86_64::{PhysAddr, VirtAddr};
+
+const CLASSES: [DeviceClass; 1] = [DeviceClass::new(0x0c, 0x03, 0x30)];
+
+// capability registers
+const CAPLENGTH: u64 = 0x00;
+const HCSPARAMS1: u64 = 0x04;
+const HCSPARAMS2: u64 = 0x08;
+const HCCPARAMS1: u64 = 0x10;
+const DBOFF: u64 = 0x14;
+const RTSOFF: u64 = 0x18;
+
+// operational registers
+const USBCMD: u64 = 0x00;
+const USBSTS: u64 = 0x04;
+const CRCR: u64 = 0x18;
+const DCBAAP: u64 = 0x30;
+const CONFIG: u64 = 0x38;
+const PORTSC: u64 = 0x400;
+
+// interrupter 0, from the runtime registers
+const IMAN: u64 = 0x20;
+const ERSTSZ: u64 = 0x28;
+const ERSTBA: u64 = 0x30;
+const ERDP: u64 = 0x38;
+
+const USBCMD_RUN: u32 = 1 << 0;
+const USBCMD_RESET: u32 = 1 << 1;
+const USBSTS_HALTED: u32 = 1 << 0;
+const USBSTS_NOT_READY: u32 = 1 << 11;
+const ERDP_BUSY: u64 = 1 << 3;
+
+const PORTSC_CONNECTED: u32 = 1 << 0;
+const PORTSC_ENABLED: u32 = 1 << 1;
+const PORTSC_RESET: u32 = 1 << 4;
+const PORTSC_POWER: u32 = 1 << 9;
+const PORTSC_RESET_CHANGE: u32 = 1 << 21;
+/// The bits a write clears by setting, enabled included; kept clear when
+/// writing so as not to disturb them.
+const PORTSC_WRITE_CLEAR: u32 = PORTSC_ENABLED | 0x7f << 17;
+
+/// USB legacy support, in the extended capabilities: BIOS and OS ownership.
+const LEGACY_SUPPORT: u32 = 1;
+const LEGACY_BIOS_OWNED: u32 = 1 << 16;
+const LEGACY_OS_OWNED: u32 = 1 << 24;
+
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+
+const TRB_CYCLE: u32 = 1 << 0;
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+const TRB_IOC: u32 = 1 << 5;
+const TRB_IDT: u32 = 1 << 6;
+const TRB_DIR_IN: u32 = 1 << 16;
+const SETUP_NO_DATA: u32 = 0 << 16;
+const SETUP_IN_DATA: u32 = 3 << 16;
+
+const COMPLETION_SUCCESS: u8 = 1;
+const COMPLETION_SHORT_PACKET: u8 = 13;
+
+const EP_CONTROL: u32 = 4;
+const EP_INTERRUPT_IN: u32 = 7;
+
+const SPEED_FULL: u8 = 1;
+const SPEED_LOW: u8 = 2;
+const SPEED_HIGH: u8 = 3;
+
+const DESCRIPTOR_DEVICE: u16 = 1;
+const DESCRIPTOR_CONFIGURATION: u16 = 2;
+const DESCRIPTOR_INTERFACE: u8 = 4;
+const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+const GET_DESCRIPTOR: u8 = 6;
+const SET_CONFIGURATION: u8 = 9;
+const HID_SET_IDLE: u8 = 0x0a;
+const HID_SET_PROTOCOL: u8 = 0x0b;
+
+/// TRBs in each ring, a page's worth; the last of a transfer ring links
+/// back to the first.
+const RING_TRBS: usize = 256;
+const TIMEOUT_MS: u64 = 1000;
+const POLL_MS: u64 = 10;
+
+#[derive(Debug)]
+pub enum Error {
+    NoMemoryBar,
+    OutOfMemory,
+    Timeout,
+    /// A command failed with this completion code.
+    Command(u8),
+    /// A transfer failed with this completion code.
+    Transfer(u8),
+}
+
+impl From<Error> for driver::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoMemoryBar => driver::Error::Unsupported,
+            Error::OutOfMemory => driver::Error::OutOfMemory,
+            Error::Timeout => driver::Error::Device("controller timed out"),
+            Error::Command(_) | Error::Transfer(_) => driver::Error::Device("command failed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+struct Trb {
+    param: u64,
+    status: u32,
+    control: u32,
+}
+
+impl Trb {
+    fn kind(&self) -> u32 {
+        (self.control >> 10) & 0x3f
+    }
+
+    fn completion(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+
+    fn slot(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    fn endpoint(&self) -> u8 {
+        ((self.control >> 16) & 0x1f) as u8
+    }
+}
+
+fn read32(addr: VirtAddr) -> u32 {
+    unsafe { ptr::read_volatile(addr.as_ptr()) }
+}
+
+fn write32(addr: VirtAddr, value: u32) {
+    unsafe { ptr::write_volatile(addr.as_mut_ptr(), value) }
+}
+
+/// Low half first, as the controller latches 64-bit registers on the high.
+fn write64(addr: VirtAddr, value: u64) {
+    write32(addr, value as u32);
+    write32(addr + 4u64, (value >> 32) as u32);
+}
+
+/// Spins until `done`, or `TIMEOUT_MS` has passed.
+fn wait(mut done: impl FnMut() -> bool) -> Result<(), Error> {
+    let deadline = time::uptime_ms() + TIMEOUT_MS;
+    while !done() {
+        if time::uptime_ms() > deadline {
+            return Err(Error::Timeout);
+        }
+        interrupts::pause();
+    }
+    Ok(())
+}
+
+fn alloc_page() -> Result<(PhysAddr, VirtAddr), Error> {
+    kernel::services()
+        .memory()
+        .alloc_dma_frame()
+        .ok_or(Error::OutOfMemory)
+}
+
+/// A command or transfer ring: one page of TRBs, the last a link back.
+struct Ring {
+    trbs: *mut Trb,
+    phys: PhysAddr,
+    enqueue: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    fn new() -> Result<Self, Error> {
+        let (phys, virt) = alloc_page()?;
+        let trbs: *mut Trb = virt.as_mut_ptr();
+        let link = Trb {
+            param: phys.as_u64(),
+            status: 0,
+            control: TRB_LINK << 10 | TRB_TOGGLE_CYCLE,
+        };
+        unsafe { ptr::write_volatile(trbs.add(RING_TRBS - 1), link) };
+        Ok(Ring {
+            trbs,
+            phys,
+            enqueue: 0,
+            cycle: true,
+        })
+    }
+
+    /// Queues `trb`, returning its physical address.
+    fn push(&mut self, mut trb: Trb) -> PhysAddr {
+        let cycle = if self.cycle { TRB_CYCLE } else { 0 };
+        trb.control = trb.control & !TRB_CYCLE | cycle;
+        let addr = self.phys + (self.enqueue * 16) as u64;
+        unsafe { ptr::write_volatile(self.trbs.add(self.enqueue), trb) };
+        self.enqueue += 1;
+        if self.enqueue == RING_TRBS - 1 {
+            // hand the link over too, then start again on the other cycle
+            unsafe {
+                let link = self.trbs.add(RING_TRBS - 1);
+                let control = ptr::read_volatile(&(*link).control);
+                ptr::write_volatile(&mut (*link).control, control & !TRB_CYCLE | cycle);
+            }
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+        addr
+    }
+}
+
+/// The one-segment event ring of interrupter 0.
+struct EventRing {
+    trbs: *const Trb,
+    phys: PhysAddr,
+    dequeue: usize,
+    cycle: bool,
+}
+
+/// A device the controller addressed.
+struct Device {
+    slot: u8,
+    port: u8,
+    speed: u8,
+    input: VirtAddr,
+    input_phys: PhysAddr,
+    ep0: Ring,
+    /// For the data stage of control transfers.
+    buffer: VirtAddr,
+    buffer_phys: PhysAddr,
+}
+
+/// A boot keyboard's interrupt endpoint and what it last reported.
+struct Keyboard {
+    slot: u8,
+    dci: u8,
+    ring: Ring,
+    report: VirtAddr,
+    report_phys: PhysAddr,
+    state: BootKeyboard,
+}
+
+struct Controller {
+    op: VirtAddr,
+    runtime: VirtAddr,
+    doorbells: VirtAddr,
+    ports: u8,
+    context_size: u64,
+    dcbaa: *mut u64,
+    commands: Ring,
+    events: EventRing,
+    keyboards: Vec<Keyboard>,
+}
+
+// Only touched under `CONTROLLER`.
+unsafe impl Send for Controller {}
+
+static CONTROLLER: Mutex<Option<Controller>> = Mutex::new(None);
+
+impl Controller {
+    fn new(pci: PciDevice) -> Result<Self, Error> {
+        let bar = PhysAddr::new(pci.memory_bar(0).ok_or(Error::NoMemoryBar)?);
+        pci.enable_bus_mastering();
+        let memory = kernel::services().memory();
+        let caps = memory.map_mmio(bar, 4096).map_err(|_| Error::OutOfMemory)?;
+        let cap_length = read32(caps + CAPLENGTH) as u8 as u64;
+        let params1 = read32(caps + HCSPARAMS1);
+        let params2 = read32(caps + HCSPARAMS2);
+        let cparams1 = read32(caps + HCCPARAMS1);
+        let db_offset = (read32(caps + DBOFF) & !0x3) as u64;
+        let rt_offset = (read32(caps + RTSOFF) & !0x1f) as u64;
+        let extended = (cparams1 >> 16) as u64 * 4;
+        let slots = params1 as u8;
+        let ports = (params1 >> 24) as u8;
+        let size = (db_offset + 4 * 256)
+            .max(rt_offset + 0x40)
+            .max(cap_length + PORTSC + 0x10 * ports as u64)
+            .max(extended + 4096);
+        let base = memory.map_mmio(bar, size).map_err(|_| Error::OutOfMemory)?;
+
+        let mut controller = Controller {
+            op: base + cap_length,
+            runtime: base + rt_offset,
+            doorbells: base + db_offset,
+            ports,
+            context_size: if cparams1 & 1 << 2 != 0 { 64 } else { 32 },
+            dcbaa: ptr::null_mut(),
+            commands: Ring::new()?,
+            events: EventRing {
+                trbs: ptr::null(),
+                phys: PhysAddr::zero(),
+                dequeue: 0,
+                cycle: true,
+            },
+            keyboards: Vec::new(),
+        };
+        take_from_bios(base, extended)?;
+        controller.reset()?;
+        write32(controller.op + CONFIG, slots as u32);
+
+        let (dcbaa_phys, dcbaa) = alloc_page()?;
+        controller.dcbaa = dcbaa.as_mut_ptr();
+        let scratchpads = ((params2 >> 27) & 0x1f | ((params2 >> 21) & 0x1f) << 5) as usize;
+        if scratchpads > 0 {
+            let (array_phys, array) = alloc_page()?;
+            let array: *mut u64 = array.as_mut_ptr();
+            for i in 0..scratchpads.min(512) {
+                let (page, _) = alloc_page()?;
+                unsafe { ptr::write_volatile(array.add(i), page.as_u64()) };
+            }
+            unsafe { ptr::write_volatile(controller.dcbaa, array_phys.as_u64()) };
+        }
+        write64(controller.op + DCBAAP, dcbaa_phys.as_u64());
+        write64(controller.op + CRCR, controller.commands.phys.as_u64() | 1);
+
+        let (segment_phys, segment) = alloc_page()?;
+        let (erst_phys, erst) = alloc_page()?;
+        unsafe {
+            let entry: *mut u64 = erst.as_mut_ptr();
+            ptr::write_volatile(entry, segment_phys.as_u64());
+            ptr::write_volatile(entry.add(1), RING_TRBS as u64);
+        }
+        controller.events.trbs = segment.as_ptr();
+        controller.events.phys = segment_phys;
+        write32(controller.runtime + ERSTSZ, 1);
+        write64(controller.runtime + ERDP, segment_phys.as_u64());
+        write64(controller.runtime + ERSTBA, erst_phys.as_u64());
+        // polled: the interrupter stays off
+        write32(controller.runtime + IMAN, 1);
+
+        write32(controller.op + USBCMD, USBCMD_RUN);
+        let op = controller.op;
+        wait(|| read32(op + USBSTS) & USBSTS_HALTED == 0)?;
+        Ok(controller)
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        let op = self.op;
+        write32(op + USBCMD, read32(op + USBCMD) & !USBCMD_RUN);
+        wait(|| read32(op + USBSTS) & USBSTS_HALTED != 0)?;
+        write32(op + USBCMD, USBCMD_RESET);
+        wait(|| {
+            read32(op + USBCMD) & USBCMD_RESET == 0 && read32(op + USBSTS) & USBSTS_NOT_READY == 0
+        })
+    }
+
+    fn portsc(&self, port: u8) -> VirtAddr {
+        self.op + PORTSC + 0x10 * (port as u64 - 1)
+    }
+
+    fn ring_doorbell(&self, slot: u8, target: u8) {
+        write32(self.doorbells + 4 * slot as u64, target as u32);
+    }
+
+    /// The next event, if the controller has posted one.
+    fn next_event(&mut self) -> Option<Trb> {
+        let events = &mut self.events;
+        let trb = unsafe { ptr::read_volatile(events.trbs.add(events.dequeue)) };
+        if (trb.control & TRB_CYCLE != 0) != events.cycle {
+            return None;
+        }
+        events.dequeue += 1;
+        if events.dequeue == RING_TRBS {
+            events.dequeue = 0;
+            events.cycle = !events.cycle;
+        }
+        let dequeue = events.phys + (events.dequeue * 16) as u64;
+        write64(self.runtime + ERDP, dequeue.as_u64() | ERDP_BUSY);
+        Some(trb)
+    }
+
+    /// Waits for the event `matches` picks; others are dropped, as nothing
+    /// else is under way while enumerating.
+    fn wait_event(&mut self, matches: impl Fn(&Trb) -> bool) -> Result<Trb, Error> {
+        let deadline = time::uptime_ms() + TIMEOUT_MS;
+        loop {
+            match self.next_event() {
+                Some(event) if matches(&event) => return Ok(event),
+                Some(_) => {}
+                None if time::uptime_ms() > deadline => return Err(Error::Timeout),
+                None => interrupts::pause(),
+            }
+        }
+    }
+
+    fn command(&mut self, trb: Trb) -> Result<Trb, Error> {
+        let addr = self.commands.push(trb).as_u64();
+        self.ring_doorbell(0, 0);
+        let event =
+            self.wait_event(|event| event.kind() == TRB_COMMAND_COMPLETION && event.param == addr)?;
+        match event.completion() {
+            COMPLETION_SUCCESS => Ok(event),
+            code => Err(Error::Command(code)),
+        }
+    }
+
+    /// Resets `port` and returns the speed of the device on it.
+    fn reset_port(&mut self, port: u8) -> Result<u8, Error> {
+        let portsc = self.portsc(port);
+        let status = read32(portsc);
+        write32(portsc, status & !PORTSC_WRITE_CLEAR | PORTSC_RESET);
+        wait(|| read32(portsc) & PORTSC_RESET_CHANGE != 0)?;
+        let status = read32(portsc);
+        write32(portsc, status & !PORTSC_WRITE_CLEAR | PORTSC_RESET_CHANGE);
+        if status & PORTSC_ENABLED == 0 {
+            return Err(Error::Timeout);
+        }
+        Ok(((status >> 10) & 0xf) as u8)
+    }
+
+    /// Pointer to dword `dword` of context `index` in the input context,
+    /// where 0 is the input control context and 1 the slot's.
+    fn input(&self, device: &Device, index: u64, dword: u64) -> *mut u32 {
+        (device.input + index * self.context_size + dword * 4).as_mut_ptr()
+    }
+
+    fn address(&mut self, port: u8, speed: u8) -> Result<Device, Error> {
+        let slot = self
+            .command(Trb {
+                control: TRB_ENABLE_SLOT << 10,
+                ..Trb::default()
+            })?
+            .slot();
+        let (input_phys, input) = alloc_page()?;
+        let (output_phys, _) = alloc_page()?;
+        let (buffer_phys, buffer) = alloc_page()?;
+        let mut device = Device {
+            slot,
+            port,
+            speed,
+            input,
+            input_phys,
+            ep0: Ring::new()?,
+            buffer,
+            buffer_phys,
+        };
+        unsafe { ptr::write_volatile(self.dcbaa.add(slot as usize), output_phys.as_u64()) };
+
+        let packet_size = match speed {
+            SPEED_LOW | SPEED_FULL => 8,
+            SPEED_HIGH => 64,
+            _ => 512,
+        };
+        unsafe {
+            ptr::write_volatile(self.input(&device, 0, 1), 0b11);
+            self.write_slot(&device, 1);
+            ptr::write_volatile(
+                self.input(&device, 2, 1),
+                3 << 1 | EP_CONTROL << 3 | packet_size << 16,
+            );
+            let dequeue = device.ep0.phys.as_u64() | 1;
+            ptr::write_volatile(self.input(&device, 2, 2), dequeue as u32);
+            ptr::write_volatile(self.input(&device, 2, 3), (dequeue >> 32) as u32);
+            ptr::write_volatile(self.input(&device, 2, 4), 8);
+        }
+        self.command(Trb {
+            param: input_phys.as_u64(),
+            control: TRB_ADDRESS_DEVICE << 10 | (slot as u32) << 24,
+            ..Trb::default()
+        })?;
+
+        // the real packet size is in the first 8 bytes of the descriptor
+        self.get_descriptor(&mut device, DESCRIPTOR_DEVICE, 8)?;
+        let reported = unsafe { *device.buffer.as_ptr::<u8>().add(7) } as u32;
+        let reported = if speed > SPEED_HIGH {
+            1 << reported
+        } else {
+            reported
+        };
+        if reported != packet_size && reported != 0 {
+            unsafe {
+                ptr::write_volatile(self.input(&device, 0, 1), 0b10);
+                ptr::write_volatile(
+                    self.input(&device, 2, 1),
+                    3 << 1 | EP_CONTROL << 3 | reported << 16,
+                );
+            }
+            self.command(Trb {
+                param: input_phys.as_u64(),
+                control: TRB_EVALUATE_CONTEXT << 10 | (slot as u32) << 24,
+                ..Trb::default()
+            })?;
+        }
+        Ok(device)
+    }
+
+    /// Fills in the slot context with the last endpoint context in use.
+    unsafe fn write_slot(&self, device: &Device, last_dci: u32) {
+        let dword0 = last_dci << 27 | (device.speed as u32) << 20;
+        ptr::write_volatile(self.input(device, 1, 0), dword0);
+        ptr::write_volatile(self.input(device, 1, 1), (device.port as u32) << 16);
+    }
+
+    /// Runs a control transfer on endpoint 0; an IN data stage lands in
+    /// the device's buffer.
+    fn control(
+        &mut self,
+        device: &mut Device,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> Result<(), Error> {
+        let setup = request_type as u64
+            | (request as u64) << 8
+            | (value as u64) << 16
+            | (index as u64) << 32
+            | (length as u64) << 48;
+        let has_data = length > 0;
+        device.ep0.push(Trb {
+            param: setup,
+            status: 8,
+            control: TRB_SETUP << 10
+                | TRB_IDT
+                | if has_data {
+                    SETUP_IN_DATA
+                } else {
+                    SETUP_NO_DATA
+                },
+        });
+        if has_data {
+            device.ep0.push(Trb {
+                param: device.buffer_phys.as_u64(),
+                status: length as u32,
+                control: TRB_DATA << 10 | TRB_DIR_IN,
+            });
+        }
+        let status_dir = if has_data { 0 } else { TRB_DIR_IN };
+        device.ep0.push(Trb {
+            control: TRB_STATUS << 10 | TRB_IOC | status_dir,
+            ..Trb::default()
+        });
+        self.ring_doorbell(device.slot, 1);
+        let slot = device.slot;
+        let event = self.wait_event(|event| {
+            event.kind() == TRB_TRANSFER_EVENT && event.slot() == slot && event.endpoint() == 1
+        })?;
+        match event.completion() {
+            COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET => Ok(()),
+            code => Err(Error::Transfer(code)),
+        }
+    }
+
+    fn get_descriptor(&mut self, device: &mut Device, kind: u16, length: u16) -> Result<(), Error> {
+        self.control(device, 0x80, GET_DESCRIPTOR, kind << 8, 0, length)
+    }
+
+    /// Reads `device`'s descriptors and takes it on if it is a keyboard.
+    fn enumerate(&mut self, mut device: Device) -> Result<(), Error> {
+        self.get_descriptor(&mut device, DESCRIPTOR_DEVICE, 18)?;
+        let descriptor = unsafe { core::slice::from_raw_parts(device.buffer.as_ptr::<u8>(), 18) };
+        let vendor = u16::from_le_bytes([descriptor[8], descriptor[9]]);
+        let product = u16::from_le_bytes([descriptor[10], descriptor[11]]);
+        info!(
+            "xhci: port {} slot {}: {} class {:#04x}",
+            device.port,
+            device.slot,
+            DeviceId::new(vendor, product),
+            descriptor[4]
+        );
+
+        self.get_descriptor(&mut device, DESCRIPTOR_CONFIGURATION, 9)?;
+        let total = unsafe { ptr::read_unaligned(device.buffer.as_ptr::<u16>().add(1)) };
+        let total = total.min(4096);
+        self.get_descriptor(&mut device, DESCRIPTOR_CONFIGURATION, total)?;
+        let config =
+            unsafe { core::slice::from_raw_parts(device.buffer.as_ptr::<u8>(), total as usize) };
+        let configuration = config[5];
+        let keyboard = match find_boot_keyboard(config) {
+            Some(keyboard) => keyboard,
+            None => return Ok(()),
+        };
+
+        self.control(
+            &mut device,
+            0x00,
+            SET_CONFIGURATION,
+            configuration as u16,
+            0,
+            0,
+        )?;
+        self.control(
+            &mut device,
+            0x21,
+            HID_SET_PROTOCOL,
+            0,
+            keyboard.interface as u16,
+            0,
+        )?;
+        // some keyboards stall this; reports then come only on change anyway
+        let _ = self.control(
+            &mut device,
+            0x21,
+            HID_SET_IDLE,
+            0,
+            keyboard.interface as u16,
+            0,
+        );
+        self.configure_keyboard(&device, keyboard)?;
+        info!("xhci: slot {} is a boot keyboard", device.slot);
+        Ok(())
+    }
+
+    fn configure_keyboard(&mut self, device: &Device, endpoint: BootEndpoint) -> Result<(), Error> {
+        let dci = (endpoint.address & 0xf) as u32 * 2 + 1;
+        // full and low speed count the interval in frames, faster ones as
+        // a power of two of 125 us
+        let interval = match device.speed {
+            SPEED_LOW | SPEED_FULL => 31 - (endpoint.interval.max(1) as u32 * 8).leading_zeros(),
+            _ => endpoint.interval.max(1).min(16) as u32 - 1,
+        };
+        let ring = Ring::new()?;
+        let packet_size = endpoint.max_packet as u32;
+        unsafe {
+            for dword in 0..8 {
+                ptr::write_volatile(self.input(device, 0, dword), 0);
+            }
+            ptr::write_volatile(self.input(device, 0, 1), 1 | 1 << dci);
+            self.write_slot(device, dci);
+            let context = dci as u64 + 1;
+            ptr::write_volatile(self.input(device, context, 0), interval << 16);
+            ptr::write_volatile(
+                self.input(device, context, 1),
+                3 << 1 | EP_INTERRUPT_IN << 3 | packet_size << 16,
+            );
+            let dequeue = ring.phys.as_u64() | 1;
+            ptr::write_volatile(self.input(device, context, 2), dequeue as u32);
+            ptr::write_volatile(self.input(device, context, 3), (dequeue >> 32) as u32);
+            ptr::write_volatile(
+                self.input(device, context, 4),
+                packet_size | packet_size << 16,
+            );
+        }
+        self.command(Trb {
+            param: device.input_phys.as_u64(),
+            control: TRB_CONFIGURE_ENDPOINT << 10 | (device.slot as u32) << 24,
+            ..Trb::default()
+        })?;
+
+        let (report_phys, report) = alloc_page()?;
+        let mut keyboard = Keyboard {
+            slot: device.slot,
+            dci: dci as u8,
+            ring,
+            report,
+            report_phys,
+            state: BootKeyboard::new(),
+        };
+        self.queue_report(&mut keyboard);
+        self.keyboards.push(keyboard);
+        Ok(())
+    }
+
+    fn queue_report(&self, keyboard: &mut Keyboard) {
+        keyboard.ring.push(Trb {
+            param: keyboard.report_phys.as_u64(),
+            status: REPORT_LEN as u32,
+            control: TRB_NORMAL << 10 | TRB_IOC,
+        });
+        self.ring_doorbell(keyboard.slot, keyboard.dci);
+    }
+
+    /// Handles the reports that came in since the last call.
+    fn poll_keyboards(&mut self) {
+        while let Some(event) = self.next_event() {
+            if event.kind() != TRB_TRANSFER_EVENT {
+                continue;
+            }
+            let index = self
+                .keyboards
+                .iter()
+                .position(|k| k.slot == event.slot() && k.dci == event.endpoint());
+            let mut keyboard = match index {
+                Some(index) => self.keyboards.swap_remove(index),
+                None => continue,
+            };
+            if let COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET = event.completion() {
+                let report =
+                    unsafe { ptr::read_volatile(keyboard.report.as_ptr::<[u8; REPORT_LEN]>()) };
+                keyboard.state.report(&report);
+            }
+            self.queue_report(&mut keyboard);
+            self.keyboards.push(keyboard);
+        }
+    }
+}
+
+/// Takes the controller over from firmware still using it to emulate PS/2.
+fn take_from_bios(base: VirtAddr, first: u64) -> Result<(), Error> {
+    let mut offset = first;
+    while offset != 0 {
+        let capability = base + offset;
+        let header = read32(capability);
+        if header & 0xff == LEGACY_SUPPORT {
+            write32(capability, header | LEGACY_OS_OWNED);
+            return wait(|| read32(capability) & LEGACY_BIOS_OWNED == 0);
+        }
+        offset = match (header >> 8) & 0xff {
+            0 => 0,
+            next => offset + next as u64 * 4,
+        };
+    }
+    Ok(())
+}
+
+/// A HID boot keyboard interface and its interrupt IN endpoint.
+struct BootEndpoint {
+    interface: u8,
+    address: u8,
+    max_packet: u16,
+    interval: u8,
+}
+
+/// Walks a configuration descriptor for a boot keyboard interface.
+fn find_boot_keyboard(config: &[u8]) -> Option<BootEndpoint> {
+    let mut interface = None;
+    let mut offset = 0;
+    while offset + 2 <= config.len() {
+        let len = config[offset] as usize;
+        if len < 2 || offset + len > config.len() {
+            break;
+        }
+        let descriptor = &config[offset..offset + len];
+        match descriptor[1] {
+            DESCRIPTOR_INTERFACE if len >= 9 => {
+                // class HID, subclass boot, protocol keyboard
+                interface = match (descriptor[5], descriptor[6], descriptor[7]) {
+                    (3, 1, 1) => Some(descriptor[2]),
+                    _ => None,
+                };
+            }
+            DESCRIPTOR_ENDPOINT if len >= 7 => {
+                // an interrupt IN endpoint
+                if let (Some(interface), true, 3) =
+                    (interface, descriptor[2] & 0x80 != 0, descriptor[3] & 3)
+                {
+                    return Some(BootEndpoint {
+                        interface,
+                        address: descriptor[2],
+                        max_packet: u16::from_le_bytes([descriptor[4], descriptor[5]]) & 0x7ff,
+                        interval: descriptor[6],
+                    });
+                }
+            }
+            _ => {}
+        }
+        offset += len;
+    }
+    None
+}
+
+/// Feeds keystrokes from the USB keyboards for as long as the kernel runs.
+async fn keyboard_task() {
+    loop {
+        time::sleep(POLL_MS).await;
+        if let Some(controller) = CONTROLLER.lock().as_mut() {
+            controller.poll_keyboards();
+        }
+    }
+}
+
+pub static DRIVER: XhciDriver = XhciDriver;
+
+pub struct XhciDriver;
+
+impl Driver for XhciDriver {
+    fn name(&self) -> &'static str {
+        "xhci"
+    }
+
+    fn api_version(&self) -> u32 {
+        driver::API_VERSION
+    }
+
+    fn ids(&self) -> &'static [DeviceId] {
+        &[]
+    }
+
+    fn classes(&self) -> &'static [DeviceClass] {
+        &CLASSES
+    }
+
+    /// Enumerates the root ports and starts polling any keyboards found.
+    fn init(&self, pci: PciDevice) -> Result<(), driver::Error> {
+        let mut slot = CONTROLLER.lock();
+        if slot.is_some() {
+            return Err(driver::Error::Device("only one controller is driven"));
+        }
+        let mut controller = Controller::new(pci)?;
+        for port in 1..=controller.ports {
+            let portsc = controller.portsc(port);
+            if read32(portsc) & PORTSC_POWER == 0 {
+                write32(portsc, PORTSC_POWER);
+            }
+            if read32(portsc) & PORTSC_CONNECTED == 0 {
+                continue;
+            }
+            let enumerated = controller
+                .reset_port(port)
+                .and_then(|speed| controller.address(port, speed))
+                .and_then(|device| controller.enumerate(device));
+            if let Err(err) = enumerated {
+                warn!("xhci: port {}: {:?}", port, err);
+            }
+        }
+        let keyboards = !controller.keyboards.is_empty();
+        *slot = Some(controller);
+        if keyboards {
+            kernel::services()
+                .spawner()
+                .spawn(Priority::High, keyboard_task());
+        }
+        Ok(())
+    }
+
+    fn remove(&self) {
+        if let Some(mut controller) = CONTROLLER.try_lock() {
+            if let Some(controller) = controller.take() {
+                write32(controller.op + USBCMD, 0);
+            }
+        }
+    }
+}
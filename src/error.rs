@@ -0,0 +1,168 @@
+//! One error type for code that reports failures to someone rather than
+//! handling them: the shell and the syscall layer.
+//!
+//! Subsystems keep their own `Error` enums, which say precisely what went
+//! wrong for the code that can do something about it. Each converts into a
+//! `KernelError`, so a caller that can only pass the failure on writes `?`
+//! and gets a message or an errno out of it.
+
+use crate::{
+    device::{driver, ioport},
+    memory::{protect, user},
+    task::scheduler,
+    time,
+};
+use core::fmt;
+use x86_64::structures::paging::{mapper::MapToError, Size4KiB};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelError {
+    /// No frames, heap or DMA memory left.
+    OutOfMemory,
+    /// A device did not answer in time.
+    DeviceTimeout,
+    /// A device answered, with a failure.
+    DeviceFailed,
+    InvalidArgument,
+    /// A user address outside user space or not backed by a page.
+    BadAddress,
+    NotMapped,
+    /// A fixed-size queue or table has no room.
+    QueueFull,
+    NotFound,
+    AlreadyExists,
+    Busy,
+    /// The subsystem has not been set up yet.
+    NotReady,
+    Unsupported,
+}
+
+impl KernelError {
+    pub fn message(self) -> &'static str {
+        match self {
+            KernelError::OutOfMemory => "out of memory",
+            KernelError::DeviceTimeout => "device timed out",
+            KernelError::DeviceFailed => "device failed",
+            KernelError::InvalidArgument => "invalid argument",
+            KernelError::BadAddress => "bad address",
+            KernelError::NotMapped => "not mapped",
+            KernelError::QueueFull => "queue full",
+            KernelError::NotFound => "not found",
+            KernelError::AlreadyExists => "already exists",
+            KernelError::Busy => "busy",
+            KernelError::NotReady => "not set up yet",
+            KernelError::Unsupported => "not supported",
+        }
+    }
+}
+
+impl fmt::Display for KernelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl From<scheduler::Error> for KernelError {
+    fn from(err: scheduler::Error) -> Self {
+        match err {
+            scheduler::Error::DuplicateId => KernelError::AlreadyExists,
+            scheduler::Error::TaskQueueFull | scheduler::Error::TooManyHooks => {
+                KernelError::QueueFull
+            }
+            scheduler::Error::UnknownId => KernelError::NotFound,
+            scheduler::Error::NoPriorities => KernelError::Unsupported,
+        }
+    }
+}
+
+impl From<MapToError<Size4KiB>> for KernelError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        match err {
+            MapToError::FrameAllocationFailed => KernelError::OutOfMemory,
+            MapToError::ParentEntryHugePage | MapToError::PageAlreadyMapped(_) => {
+                KernelError::AlreadyExists
+            }
+        }
+    }
+}
+
+impl From<protect::Error> for KernelError {
+    fn from(err: protect::Error) -> Self {
+        match err {
+            protect::Error::MapperNotInstalled => KernelError::NotReady,
+            protect::Error::NotMapped => KernelError::NotMapped,
+            protect::Error::HugePage => KernelError::Unsupported,
+        }
+    }
+}
+
+impl From<user::Error> for KernelError {
+    fn from(_: user::Error) -> Self {
+        KernelError::BadAddress
+    }
+}
+
+impl From<time::page::Error> for KernelError {
+    fn from(err: time::page::Error) -> Self {
+        match err {
+            time::page::Error::MapperNotInstalled => KernelError::NotReady,
+            time::page::Error::NotMapped => KernelError::NotMapped,
+            time::page::Error::Map(err) => err.into(),
+        }
+    }
+}
+
+impl From<driver::Error> for KernelError {
+    fn from(err: driver::Error) -> Self {
+        match err {
+            driver::Error::Unsupported => KernelError::Unsupported,
+            driver::Error::OutOfMemory => KernelError::OutOfMemory,
+            driver::Error::Device(_) => KernelError::DeviceFailed,
+        }
+    }
+}
+
+impl From<ioport::Error> for KernelError {
+    fn from(err: ioport::Error) -> Self {
+        match err {
+            ioport::Error::Busy(_) => KernelError::Busy,
+            ioport::Error::Empty => KernelError::InvalidArgument,
+        }
+    }
+}
+
+#[cfg(feature = "driver-e1000")]
+impl From<crate::device::e1000::Error> for KernelError {
+    fn from(err: crate::device::e1000::Error) -> Self {
+        use crate::device::e1000::Error;
+        match err {
+            Error::NoMemoryBar => KernelError::Unsupported,
+            Error::OutOfMemory => KernelError::OutOfMemory,
+            Error::ResetTimeout => KernelError::DeviceTimeout,
+        }
+    }
+}
+
+#[cfg(any(feature = "driver-virtio-console", feature = "driver-virtio-9p"))]
+impl From<crate::device::virtio::Error> for KernelError {
+    fn from(err: crate::device::virtio::Error) -> Self {
+        use crate::device::virtio::Error;
+        match err {
+            Error::NoIoBar | Error::NoQueue(_) => KernelError::Unsupported,
+            Error::OutOfMemory => KernelError::OutOfMemory,
+        }
+    }
+}
+
+#[cfg(feature = "driver-xhci")]
+impl From<crate::device::xhci::Error> for KernelError {
+    fn from(err: crate::device::xhci::Error) -> Self {
+        use crate::device::xhci::Error;
+        match err {
+            Error::NoMemoryBar => KernelError::Unsupported,
+            Error::OutOfMemory => KernelError::OutOfMemory,
+            Error::Timeout => KernelError::DeviceTimeout,
+            Error::Command(_) | Error::Transfer(_) => KernelError::DeviceFailed,
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use crate::sync::RwLock;
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::task::{Context, Poll};
+
+pub mod devfs;
+pub mod fd;
+pub mod initramfs;
+pub mod p9;
+pub mod path;
+pub mod tmpfs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    AlreadyExists,
+    BadDescriptor,
+    Busy,
+    CrossDevice,
+    InvalidArgument,
+    InvalidPath,
+    /// The device failed the transfer.
+    Io,
+    IsADirectory,
+    NotADirectory,
+    NotEmpty,
+    /// The heap cannot hold the file's new size.
+    NoSpace,
+    NotFound,
+    NotMounted,
+    ReadOnly,
+    TooManyLinks,
+    TooManyOpenFiles,
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    File,
+    Directory,
+    Symlink,
+    Device,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    pub kind: NodeKind,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: NodeKind,
+}
+
+/// A file, directory, symlink or device inside a mounted filesystem.
+///
+/// Every operation has a default that reports it as unsupported, so
+/// read-only filesystems only implement what they have.
+pub trait Node: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, Error> {
+        Err(Error::IsADirectory)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Reads into each buffer in turn, as one read from `offset` would,
+    /// stopping at the first short read.
+    fn read_vectored_at(&self, offset: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Error> {
+        let mut read = 0;
+        for buf in bufs.iter_mut() {
+            let n = self.read_at(offset + read, buf)?;
+            read += n;
+            if n < buf.len() {
+                break;
+            }
+        }
+        Ok(read)
+    }
+
+    /// Writes each buffer in turn from `offset`, so headers and payloads
+    /// need not be joined first. Nodes that can take them in one go, under
+    /// one lock, should.
+    fn write_vectored_at(&self, offset: usize, bufs: &[&[u8]]) -> Result<usize, Error> {
+        let mut written = 0;
+        for buf in bufs {
+            let n = self.write_at(offset + written, buf)?;
+            written += n;
+            if n < buf.len() {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Like `read_at`, but nodes backed by slow hardware return `Pending`
+    /// and wake `cx` once the transfer completes instead of spinning.
+    fn poll_read_at(
+        &self,
+        _cx: &mut Context,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        Poll::Ready(self.read_at(offset, buf))
+    }
+
+    fn poll_write_at(
+        &self,
+        _cx: &mut Context,
+        offset: usize,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Poll::Ready(self.write_at(offset, buf))
+    }
+
+    fn truncate(&self, _size: usize) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Node>, Error> {
+        Err(Error::NotADirectory)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, Error> {
+        Err(Error::NotADirectory)
+    }
+
+    fn create(&self, _name: &str, _kind: NodeKind) -> Result<Arc<dyn Node>, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Adds an existing node of the same filesystem under `name`.
+    fn link(&self, _name: &str, _node: Arc<dyn Node>) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Removes `name`; directories must be empty.
+    fn unlink(&self, _name: &str) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Removes `name` whatever it holds, for moving it elsewhere.
+    fn detach(&self, _name: &str) -> Result<Arc<dyn Node>, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn read_link(&self) -> Result<String, Error> {
+        Err(Error::InvalidPath)
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn root(&self) -> Arc<dyn Node>;
+}
+
+/// A filesystem attached at an absolute path.
+pub struct Mount {
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+impl Mount {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn fs(&self) -> &Arc<dyn FileSystem> {
+        &self.fs
+    }
+
+    /// The rest of `path` below this mount, if it lies inside it.
+    fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        if self.path == "/" {
+            return Some(path);
+        }
+        let rest = path.strip_prefix(self.path.as_str())?;
+        if rest.is_empty() || rest.starts_with('/') {
+            Some(rest)
+        } else {
+            None
+        }
+    }
+}
+
+static MOUNTS: RwLock<Vec<Arc<Mount>>> = RwLock::new(Vec::new());
+
+/// Makes `fs` the root of the namespace.
+pub fn mount_root(fs: Arc<dyn FileSystem>) -> Result<(), Error> {
+    mount("/", fs)
+}
+
+/// Attaches `fs` at `path`, which must be an existing directory unless it is `/`.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), Error> {
+    let path = path::absolute(path)?;
+    if path != "/" && lookup(&path)?.metadata().kind != NodeKind::Directory {
+        return Err(Error::NotADirectory);
+    }
+    let mut mounts = MOUNTS.write();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(Error::Busy);
+    }
+    info!("mounted {} at {}", fs.name(), path);
+    mounts.push(Arc::new(Mount { path, fs }));
+    Ok(())
+}
+
+/// Detaches the filesystem at `path`.
+///
+/// Fails with `Busy` while other mounts sit below it or files on it are open.
+pub fn umount(path: &str) -> Result<(), Error> {
+    let path = path::absolute(path)?;
+    let mut mounts = MOUNTS.write();
+    let index = mounts
+        .iter()
+        .position(|mount| mount.path == path)
+        .ok_or(Error::NotMounted)?;
+    let target = &mounts[index];
+    let nested = mounts
+        .iter()
+        .any(|mount| mount.path != path && target.strip(&mount.path).is_some());
+    if nested || Arc::strong_count(target) > 1 {
+        return Err(Error::Busy);
+    }
+    let mount = mounts.remove(index);
+    info!("unmounted {} from {}", mount.fs.name(), path);
+    Ok(())
+}
+
+/// The active mounts, in the order they were made.
+pub fn mounts() -> Vec<Arc<Mount>> {
+    MOUNTS.read().clone()
+}
+
+/// Resolves a path to its node and the mount holding it, following symlinks.
+pub fn resolve(path: &str) -> Result<(Arc<Mount>, Arc<dyn Node>), Error> {
+    resolve_with(path, true)
+}
+
+/// Like `resolve`, but a symlink in the last component is returned as is.
+pub fn resolve_nofollow(path: &str) -> Result<(Arc<Mount>, Arc<dyn Node>), Error> {
+    resolve_with(path, false)
+}
+
+fn resolve_with(path: &str, follow_last: bool) -> Result<(Arc<Mount>, Arc<dyn Node>), Error> {
+    let mut path = path::absolute(path)?;
+    for _ in 0..=path::MAX_SYMLINK_DEPTH {
+        match walk(&path, follow_last)? {
+            Walk::Found(mount, node) => return Ok((mount, node)),
+            Walk::Symlink(target) => path = target,
+        }
+    }
+    Err(Error::TooManyLinks)
+}
+
+enum Walk {
+    Found(Arc<Mount>, Arc<dyn Node>),
+    /// The path to restart from after substituting a symlink.
+    Symlink(String),
+}
+
+/// Walks a normalized absolute path from the innermost mount covering it.
+fn walk(path: &str, follow_last: bool) -> Result<Walk, Error> {
+    let (mount, rest) = MOUNTS
+        .read()
+        .iter()
+        .filter_map(|mount| mount.strip(path).map(|rest| (mount, rest)))
+        .min_by_key(|(_, rest)| rest.len())
+        .map(|(mount, rest)| (mount.clone(), rest))
+        .ok_or(Error::NotMounted)?;
+    let mut node = mount.fs.root();
+    let mut end = path.len() - rest.len();
+    for name in rest.split('/').skip(1).filter(|c| !c.is_empty()) {
+        let parent = match &path[..end] {
+            "" => "/",
+            parent => parent,
+        };
+        end += 1 + name.len();
+        node = node.lookup(name)?;
+        let last = end == path.len();
+        if node.metadata().kind == NodeKind::Symlink && (follow_last || !last) {
+            let mut target = path::join(parent, &node.read_link()?);
+            target.push_str(&path[end..]);
+            return Ok(Walk::Symlink(path::normalize(&target)));
+        }
+    }
+    Ok(Walk::Found(mount, node))
+}
+
+pub fn lookup(path: &str) -> Result<Arc<dyn Node>, Error> {
+    resolve(path).map(|(_, node)| node)
+}
+
+pub fn read_to_end(path: &str) -> Result<Vec<u8>, Error> {
+    let node = lookup(path)?;
+    let mut data = vec![0; node.metadata().size];
+    let mut read = 0;
+    while read < data.len() {
+        match node.read_at(read, &mut data[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    data.truncate(read);
+    Ok(data)
+}
+
+pub fn readdir(path: &str) -> Result<Vec<DirEntry>, Error> {
+    lookup(path)?.readdir()
+}
+
+/// Resolves the directory holding the last component of `path`.
+fn resolve_parent(path: &str) -> Result<(Arc<Mount>, Arc<dyn Node>, String), Error> {
+    let path = path::absolute(path)?;
+    let (parent, name) = path::split(&path).ok_or(Error::InvalidPath)?;
+    let (mount, parent) = resolve(parent)?;
+    Ok((mount, parent, String::from(name)))
+}
+
+pub fn create(path: &str, kind: NodeKind) -> Result<Arc<dyn Node>, Error> {
+    let (_, parent, name) = resolve_parent(path)?;
+    parent.create(&name, kind)
+}
+
+pub fn mkdir(path: &str) -> Result<(), Error> {
+    create(path, NodeKind::Directory).map(|_| ())
+}
+
+pub fn symlink(target: &str, path: &str) -> Result<(), Error> {
+    let (_, parent, name) = resolve_parent(path)?;
+    parent.symlink(&name, target)
+}
+
+/// Replaces the contents of `path`, creating the file if needed.
+pub fn write(path: &str, data: &[u8]) -> Result<(), Error> {
+    let node = match lookup(path) {
+        Ok(node) => node,
+        Err(Error::NotFound) => create(path, NodeKind::File)?,
+        Err(err) => return Err(err),
+    };
+    node.truncate(0)?;
+    node.write_at(0, data)?;
+    Ok(())
+}
+
+pub fn truncate(path: &str, size: usize) -> Result<(), Error> {
+    lookup(path)?.truncate(size)
+}
+
+pub fn remove(path: &str) -> Result<(), Error> {
+    let (_, parent, name) = resolve_parent(path)?;
+    parent.unlink(&name)
+}
+
+/// Moves `from` to `to`, replacing whatever `to` held unless it is a
+/// non-empty directory. If the move fails, `to` is left as it was.
+pub fn rename(from: &str, to: &str) -> Result<(), Error> {
+    let (old_mount, old_parent, old_name) = resolve_parent(from)?;
+    let (new_mount, new_parent, new_name) = resolve_parent(to)?;
+    if !Arc::ptr_eq(&old_mount, &new_mount) {
+        return Err(Error::CrossDevice);
+    }
+    let node = old_parent.lookup(&old_name)?;
+    if node.metadata().kind == NodeKind::Directory && is_above(&node, to)? {
+        return Err(Error::InvalidArgument);
+    }
+    let replaced = match new_parent.lookup(&new_name) {
+        Ok(existing) if same_node(&existing, &node) => return Ok(()),
+        Ok(existing) => {
+            let metadata = existing.metadata();
+            if metadata.kind == NodeKind::Directory && metadata.size > 0 {
+                return Err(Error::NotEmpty);
+            }
+            Some(new_parent.detach(&new_name)?)
+        }
+        Err(Error::NotFound) => None,
+        Err(err) => return Err(err),
+    };
+    if let Err(err) = new_parent.link(&new_name, node) {
+        if let Some(replaced) = replaced {
+            let _ = new_parent.link(&new_name, replaced);
+        }
+        return Err(err);
+    }
+    old_parent.detach(&old_name).map(|_| ())
+}
+
+fn same_node(a: &Arc<dyn Node>, b: &Arc<dyn Node>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
+/// Whether `dir` is one of the directories `path` goes through, so moving
+/// it there would put it inside itself. Nodes do not know their parents,
+/// so every prefix is resolved in turn.
+fn is_above(dir: &Arc<dyn Node>, path: &str) -> Result<bool, Error> {
+    let path = path::absolute(path)?;
+    let mut prefix = path.as_str();
+    while let Some((parent, _)) = path::split(prefix) {
+        if same_node(&resolve(parent)?.1, dir) {
+            return Ok(true);
+        }
+        prefix = parent;
+    }
+    Ok(false)
+}
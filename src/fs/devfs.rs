@@ -0,0 +1,237 @@
+use super::{DirEntry, Error, FileSystem, Metadata, Node, NodeKind};
+use crate::{
+    kobject::{KObject, Kind, Kref},
+    sync::{Lazy, Rcu},
+};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::task::{Context, Poll};
+
+/// A character device reachable as `/dev/<name>`.
+pub trait Device: KObject {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Error>;
+    fn write(&self, buf: &[u8]) -> Result<usize, Error>;
+
+    /// Devices that wait on an interrupt return `Pending` here and wake `cx`.
+    fn poll_read(&self, _cx: &mut Context, buf: &mut [u8]) -> Poll<Result<usize, Error>> {
+        Poll::Ready(self.read(buf))
+    }
+
+    /// Likewise for devices that wait to take more.
+    fn poll_write(&self, _cx: &mut Context, buf: &[u8]) -> Poll<Result<usize, Error>> {
+        Poll::Ready(self.write(buf))
+    }
+}
+
+/// Looked up on every open; devices come and go rarely, so changes copy
+/// the map.
+impl<T: Device + ?Sized> KObject for T {
+    fn kind(&self) -> Kind {
+        Kind::Device
+    }
+}
+
+static DEVICES: Lazy<Rcu<BTreeMap<String, Kref<dyn Device>>>> =
+    Lazy::new(|| Rcu::new(BTreeMap::new()));
+
+/// Publishes `device` as `/dev/<name>`.
+pub fn register(name: &str, device: Kref<dyn Device>) -> Result<(), Error> {
+    DEVICES.try_update(|devices| {
+        if devices.contains_key(name) {
+            return Err(Error::AlreadyExists);
+        }
+        let mut devices = devices.clone();
+        devices.insert(String::from(name), device);
+        Ok(devices)
+    })
+}
+
+/// Names of the devices under `/dev`.
+pub fn names() -> Vec<String> {
+    DEVICES.read().keys().cloned().collect()
+}
+
+pub fn unregister(name: &str) -> Result<(), Error> {
+    DEVICES.try_update(|devices| {
+        let mut devices = devices.clone();
+        devices.remove(name).ok_or(Error::NotFound)?;
+        Ok(devices)
+    })
+}
+
+/// Registers the devices every kernel has.
+pub fn init() {
+    register("null", Kref::new(Null)).unwrap();
+    register("zero", Kref::new(Zero)).unwrap();
+    register("random", Kref::new(Random)).unwrap();
+    register("console", Kref::new(Console)).unwrap();
+    register("kbd", Kref::new(Keyboard)).unwrap();
+}
+
+pub struct Devfs;
+
+impl FileSystem for Devfs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        Arc::new(Root)
+    }
+}
+
+struct Root;
+
+impl Node for Root {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Directory,
+            size: DEVICES.read().len(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, Error> {
+        let device = DEVICES.read().get(name).cloned().ok_or(Error::NotFound)?;
+        Ok(Arc::new(DeviceNode(device)))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, Error> {
+        Ok(DEVICES
+            .read()
+            .keys()
+            .map(|name| DirEntry {
+                name: name.clone(),
+                kind: NodeKind::Device,
+            })
+            .collect())
+    }
+}
+
+/// Character devices have no position, so offsets are ignored.
+struct DeviceNode(Kref<dyn Device>);
+
+impl Node for DeviceNode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Device,
+            size: 0,
+        }
+    }
+
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        self.0.read(buf)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize, Error> {
+        self.0.write(buf)
+    }
+
+    fn poll_read_at(
+        &self,
+        cx: &mut Context,
+        _offset: usize,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.0.poll_read(cx, buf)
+    }
+
+    fn poll_write_at(
+        &self,
+        cx: &mut Context,
+        _offset: usize,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.0.poll_write(cx, buf)
+    }
+
+    fn truncate(&self, _size: usize) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+struct Null;
+
+impl Device for Null {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        Ok(buf.len())
+    }
+}
+
+struct Zero;
+
+impl Device for Zero {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        buf.iter_mut().for_each(|b| *b = 0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        Ok(buf.len())
+    }
+}
+
+/// Bytes from `cpu::random`; not cryptographically secure.
+struct Random;
+
+impl Device for Random {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        crate::cpu::random::fill(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        Ok(buf.len())
+    }
+}
+
+/// Writes go to the VGA console; there is no line input yet.
+struct Console;
+
+impl Device for Console {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        print!("{}", String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+}
+
+/// Raw scancodes, competing with any `ScancodeStream` consumer.
+struct Keyboard;
+
+impl Device for Keyboard {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut read = 0;
+        while read < buf.len() {
+            match crate::device::keyboard::pop_scancode() {
+                Some(scancode) => buf[read] = scancode,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    /// Waits for the first scancode, then takes whatever else is queued.
+    fn poll_read(&self, cx: &mut Context, buf: &mut [u8]) -> Poll<Result<usize, Error>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        match crate::device::keyboard::poll_scancode(cx) {
+            Poll::Ready(scancode) => {
+                buf[0] = scancode;
+                Poll::Ready(self.read(&mut buf[1..]).map(|read| read + 1))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, Error> {
+        Err(Error::Unsupported)
+    }
+}
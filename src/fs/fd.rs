@@ -0,0 +1,287 @@
+use super::{DirEntry, Error, Metadata, Mount, Node, NodeKind};
+use crate::{
+    debug::fault,
+    kobject::{KObject, Kind, Kref},
+    shell::{Args, ShellErr},
+    sync::{Lazy, Mutex},
+    task::TaskId,
+};
+use alloc::{collections::BTreeMap, format, string::String, sync::Arc, vec::Vec};
+use bitflags::bitflags;
+use core::fmt::Write;
+use futures_util::future::poll_fn;
+
+/// Descriptors a single task may hold open at once.
+pub const MAX_FDS: usize = 32;
+
+pub type Fd = usize;
+
+bitflags! {
+    pub struct OpenFlags: u32 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const CREATE = 1 << 2;
+        const TRUNCATE = 1 << 3;
+        const APPEND = 1 << 4;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
+
+/// An open file description, shared by duplicated descriptors.
+pub struct OpenFile {
+    /// Keeps the filesystem from being unmounted under the file.
+    mount: Arc<Mount>,
+    node: Arc<dyn Node>,
+    flags: OpenFlags,
+    offset: Mutex<usize>,
+}
+
+impl OpenFile {
+    pub fn mount(&self) -> &Arc<Mount> {
+        &self.mount
+    }
+
+    pub fn node(&self) -> &Arc<dyn Node> {
+        &self.node
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+}
+
+impl KObject for OpenFile {
+    fn kind(&self) -> Kind {
+        Kind::File
+    }
+}
+
+struct FdTable {
+    files: [Option<Kref<OpenFile>>; MAX_FDS],
+    /// Normalized absolute path that relative paths are resolved against.
+    cwd: String,
+}
+
+impl FdTable {
+    fn new() -> Self {
+        FdTable {
+            files: Default::default(),
+            cwd: String::from("/"),
+        }
+    }
+
+    fn insert(&mut self, file: Kref<OpenFile>) -> Result<Fd, Error> {
+        let fd = self
+            .files
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::TooManyOpenFiles)?;
+        self.files[fd] = Some(file);
+        Ok(fd)
+    }
+}
+
+/// Tables by owning task; code running outside any task uses `None`.
+static TABLES: Lazy<Mutex<BTreeMap<Option<TaskId>, FdTable>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+fn with_table<R>(f: impl FnOnce(&mut FdTable) -> R) -> R {
+    let mut tables = TABLES.lock();
+    f(tables.entry(TaskId::current()).or_insert_with(FdTable::new))
+}
+
+/// The open file behind `fd`; the table lock is not held while it is used.
+pub fn get(fd: Fd) -> Result<Kref<OpenFile>, Error> {
+    with_table(|table| table.files.get(fd).cloned().flatten()).ok_or(Error::BadDescriptor)
+}
+
+/// Closes every descriptor of a finished task.
+pub fn release(task_id: TaskId) {
+    TABLES.lock().remove(&Some(task_id));
+}
+
+pub fn cwd() -> String {
+    with_table(|table| table.cwd.clone())
+}
+
+pub fn chdir(path: &str) -> Result<(), Error> {
+    let path = super::path::absolute(path)?;
+    if super::lookup(&path)?.metadata().kind != NodeKind::Directory {
+        return Err(Error::NotADirectory);
+    }
+    with_table(|table| table.cwd = path);
+    Ok(())
+}
+
+/// `cd [path]`: to `/` without one. Each console's task has its own.
+pub fn cd_command(args: &[&str], _out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    let path = args.word().unwrap_or("/");
+    args.finish()?;
+    chdir(path).map_err(|err| format!("{}: {:?}", path, err).into())
+}
+
+/// `pwd`
+pub fn pwd_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    Args::new(args).finish()?;
+    writeln!(out, "{}", cwd())?;
+    Ok(())
+}
+
+pub fn open(path: &str, flags: OpenFlags) -> Result<Fd, Error> {
+    let (mount, node) = match super::resolve(path) {
+        Err(Error::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            super::create(path, NodeKind::File)?;
+            super::resolve(path)?
+        }
+        result => result?,
+    };
+    if flags.contains(OpenFlags::TRUNCATE) && flags.contains(OpenFlags::WRITE) {
+        node.truncate(0)?;
+    }
+    let file = Kref::new(OpenFile {
+        mount,
+        node,
+        flags,
+        offset: Mutex::new(0),
+    });
+    with_table(|table| table.insert(file))
+}
+
+pub fn close(fd: Fd) -> Result<(), Error> {
+    with_table(|table| table.files.get_mut(fd).and_then(Option::take))
+        .map(|_| ())
+        .ok_or(Error::BadDescriptor)
+}
+
+/// Points a new descriptor at the same open file description.
+pub fn dup(fd: Fd) -> Result<Fd, Error> {
+    let file = get(fd)?;
+    with_table(|table| table.insert(file))
+}
+
+pub fn read(fd: Fd, buf: &mut [u8]) -> Result<usize, Error> {
+    let file = get(fd)?;
+    if !file.flags.contains(OpenFlags::READ) {
+        return Err(Error::BadDescriptor);
+    }
+    if fault::should_fail(fault::Site::FileIo) {
+        return Err(Error::Io);
+    }
+    let mut offset = file.offset.lock();
+    let read = file.node.read_at(*offset, buf)?;
+    *offset += read;
+    Ok(read)
+}
+
+pub fn write(fd: Fd, buf: &[u8]) -> Result<usize, Error> {
+    let file = get(fd)?;
+    if !file.flags.contains(OpenFlags::WRITE) {
+        return Err(Error::BadDescriptor);
+    }
+    if fault::should_fail(fault::Site::FileIo) {
+        return Err(Error::Io);
+    }
+    let mut offset = file.offset.lock();
+    if file.flags.contains(OpenFlags::APPEND) {
+        *offset = file.node.metadata().size;
+    }
+    let written = file.node.write_at(*offset, buf)?;
+    *offset += written;
+    Ok(written)
+}
+
+/// `read`, scattered over `bufs` in order.
+pub fn readv(fd: Fd, bufs: &mut [&mut [u8]]) -> Result<usize, Error> {
+    let file = get(fd)?;
+    if !file.flags.contains(OpenFlags::READ) {
+        return Err(Error::BadDescriptor);
+    }
+    if fault::should_fail(fault::Site::FileIo) {
+        return Err(Error::Io);
+    }
+    let mut offset = file.offset.lock();
+    let read = file.node.read_vectored_at(*offset, bufs)?;
+    *offset += read;
+    Ok(read)
+}
+
+/// `write`, gathered from `bufs` in order.
+pub fn writev(fd: Fd, bufs: &[&[u8]]) -> Result<usize, Error> {
+    let file = get(fd)?;
+    if !file.flags.contains(OpenFlags::WRITE) {
+        return Err(Error::BadDescriptor);
+    }
+    if fault::should_fail(fault::Site::FileIo) {
+        return Err(Error::Io);
+    }
+    let mut offset = file.offset.lock();
+    if file.flags.contains(OpenFlags::APPEND) {
+        *offset = file.node.metadata().size;
+    }
+    let written = file.node.write_vectored_at(*offset, bufs)?;
+    *offset += written;
+    Ok(written)
+}
+
+/// Moves the offset and returns its new value.
+pub fn lseek(fd: Fd, pos: SeekFrom) -> Result<usize, Error> {
+    let file = get(fd)?;
+    let mut offset = file.offset.lock();
+    let (base, delta) = match pos {
+        SeekFrom::Start(start) => (start, 0),
+        SeekFrom::Current(delta) => (*offset, delta),
+        SeekFrom::End(delta) => (file.node.metadata().size, delta),
+    };
+    let new = if delta < 0 {
+        base.checked_sub(delta.wrapping_neg() as usize)
+    } else {
+        base.checked_add(delta as usize)
+    };
+    *offset = new.ok_or(Error::InvalidArgument)?;
+    Ok(*offset)
+}
+
+pub fn stat(fd: Fd) -> Result<Metadata, Error> {
+    Ok(get(fd)?.node.metadata())
+}
+
+pub fn readdir(fd: Fd) -> Result<Vec<DirEntry>, Error> {
+    get(fd)?.node.readdir()
+}
+
+/// Reads without stalling the executor while the node waits for hardware.
+///
+/// The offset is only advanced once the read completes, so concurrent
+/// reads through one description may see the same data.
+pub async fn read_async(fd: Fd, buf: &mut [u8]) -> Result<usize, Error> {
+    let file = get(fd)?;
+    if !file.flags.contains(OpenFlags::READ) {
+        return Err(Error::BadDescriptor);
+    }
+    let offset = *file.offset.lock();
+    let read = poll_fn(|cx| file.node.poll_read_at(cx, offset, buf)).await?;
+    *file.offset.lock() += read;
+    Ok(read)
+}
+
+pub async fn write_async(fd: Fd, buf: &[u8]) -> Result<usize, Error> {
+    let file = get(fd)?;
+    if !file.flags.contains(OpenFlags::WRITE) {
+        return Err(Error::BadDescriptor);
+    }
+    let offset = if file.flags.contains(OpenFlags::APPEND) {
+        file.node.metadata().size
+    } else {
+        *file.offset.lock()
+    };
+    let written = poll_fn(|cx| file.node.poll_write_at(cx, offset, buf)).await?;
+    *file.offset.lock() = offset + written;
+    Ok(written)
+}
@@ -0,0 +1,349 @@
+use super::{DirEntry, Error, FileSystem, Metadata, Node, NodeKind};
+use crate::{
+    boot::cmdline::{self, Integrity},
+    compress::lz4,
+    crypto::{ct_eq, sha256},
+    sync::RwLock,
+};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+/// The archive packed from `initramfs/` and compressed by `build.rs`, and
+/// the SHA-256 of what was compressed.
+static ARCHIVE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initramfs.tar.lz4"));
+static ARCHIVE_SHA256: &[u8; 32] = include_bytes!(concat!(env!("OUT_DIR"), "/initramfs.sha256"));
+
+const BLOCK: usize = 512;
+
+/// A read-only filesystem backed by a ustar archive.
+pub struct Initramfs {
+    root: Arc<Directory>,
+}
+
+impl Initramfs {
+    /// The archive the loader passed as a module named `initramfs`,
+    /// `initramfs.tar` or `initramfs.tar.lz4`, falling back to the embedded
+    /// one. Either is checked against its SHA-256 first, if one is known,
+    /// and decompressed if it is an LZ4 frame.
+    pub fn load() -> Result<Self, Error> {
+        let module = crate::boot::find_module("initramfs")
+            .or_else(|| crate::boot::find_module("initramfs.tar"))
+            .or_else(|| crate::boot::find_module("initramfs.tar.lz4"));
+        match module {
+            Some(module) => {
+                info!("initramfs: using boot module {}", module.path());
+                let data = module.data().map_err(|err| {
+                    error!("initramfs: cannot read {}: {}", module.path(), err);
+                    Error::Io
+                })?;
+                match &cmdline::options().initramfs_sha256 {
+                    Some(expected) => verify(data, expected),
+                    None => info!("initramfs: no initramfs_sha256= to check the module against"),
+                }
+                Self::parse(decompress(data)?)
+            }
+            None => {
+                verify(ARCHIVE, ARCHIVE_SHA256);
+                Self::embedded()
+            }
+        }
+    }
+
+    pub fn embedded() -> Result<Self, Error> {
+        Self::parse(decompress(ARCHIVE)?)
+    }
+
+    pub fn parse(archive: &'static [u8]) -> Result<Self, Error> {
+        let mut dirs = Directories::new();
+        let mut offset = 0;
+
+        while offset + BLOCK <= archive.len() {
+            let header = Header(&archive[offset..offset + BLOCK]);
+            if header.is_end() {
+                break;
+            }
+            if !header.is_ustar() || !header.checksum_ok() {
+                warn!("initramfs: bad header at offset {}", offset);
+                return Err(Error::InvalidPath);
+            }
+
+            let size = header.size();
+            let data_start = offset + BLOCK;
+            let data_end = data_start + size;
+            if data_end > archive.len() {
+                return Err(Error::InvalidPath);
+            }
+
+            let path = header.path();
+            let path = path.trim_matches('/');
+            match header.typeflag() {
+                b'0' | b'\0' => {
+                    dirs.insert(path, Arc::new(File(&archive[data_start..data_end])))?
+                }
+                b'5' => {
+                    dirs.directory(path)?;
+                }
+                b'2' => dirs.insert(path, Arc::new(Symlink(String::from(header.link_name()))))?,
+                other => debug!("initramfs: skipping {} (type {})", path, other as char),
+            }
+
+            offset = data_start + (size + BLOCK - 1) / BLOCK * BLOCK;
+        }
+
+        Ok(Initramfs {
+            root: dirs.directory("")?,
+        })
+    }
+}
+
+/// The tar archive in `image`, decompressed onto the heap for good if it
+/// is an LZ4 frame; the files point into it for as long as they exist.
+fn decompress(image: &'static [u8]) -> Result<&'static [u8], Error> {
+    if !lz4::is_frame(image) {
+        return Ok(image);
+    }
+    // the archive stays on the heap for good; one wanting more than half
+    // of it is taken as corrupt
+    match lz4::decompress_frame(image, crate::allocators::heap_size() / 2) {
+        Ok(archive) => {
+            info!(
+                "initramfs: {} bytes decompressed to {}",
+                image.len(),
+                archive.len()
+            );
+            Ok(Box::leak(archive.into_boxed_slice()))
+        }
+        Err(err) => {
+            warn!("initramfs: cannot decompress: {:?}", err);
+            Err(Error::InvalidPath)
+        }
+    }
+}
+
+/// Checks `archive` against `expected`, so a corrupted image fails here
+/// rather than as a missing file later. A mismatch panics under
+/// `integrity=enforce` and is logged as an error otherwise.
+fn verify(archive: &[u8], expected: &[u8; 32]) {
+    let actual = sha256::digest(archive);
+    if ct_eq(&actual, expected) {
+        info!("initramfs: SHA-256 verified ({} bytes)", archive.len());
+        return;
+    }
+    let (actual, expected) = (Hex(&actual), Hex(expected));
+    match cmdline::options().integrity {
+        Integrity::Enforce => panic!(
+            "initramfs: SHA-256 mismatch: {} expected, {} found",
+            expected, actual
+        ),
+        Integrity::Warn => error!(
+            "initramfs: SHA-256 mismatch: {} expected, {} found; booting anyway",
+            expected, actual
+        ),
+    }
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl core::fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Directories created so far while unpacking, keyed by path without
+/// leading or trailing slashes ("" is the root).
+struct Directories(BTreeMap<String, Arc<Directory>>);
+
+impl Directories {
+    fn new() -> Self {
+        let mut dirs = BTreeMap::new();
+        dirs.insert(String::new(), Arc::new(Directory::new()));
+        Directories(dirs)
+    }
+
+    /// Returns the directory at `path`, creating it and its parents.
+    fn directory(&mut self, path: &str) -> Result<Arc<Directory>, Error> {
+        if let Some(dir) = self.0.get(path) {
+            return Ok(dir.clone());
+        }
+        let (parent, name) = split_parent(path);
+        let parent = self.directory(parent)?;
+        if parent.lookup(name).is_ok() {
+            return Err(Error::NotADirectory);
+        }
+        let dir = Arc::new(Directory::new());
+        parent.insert(name, dir.clone());
+        self.0.insert(String::from(path), dir.clone());
+        Ok(dir)
+    }
+
+    fn insert(&mut self, path: &str, node: Arc<dyn Node>) -> Result<(), Error> {
+        let (parent, name) = split_parent(path);
+        if name.is_empty() {
+            return Err(Error::InvalidPath);
+        }
+        self.directory(parent)?.insert(name, node);
+        Ok(())
+    }
+}
+
+fn split_parent(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    }
+}
+
+impl FileSystem for Initramfs {
+    fn name(&self) -> &'static str {
+        "initramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        self.root.clone()
+    }
+}
+
+struct Header<'a>(&'a [u8]);
+
+impl<'a> Header<'a> {
+    fn is_end(&self) -> bool {
+        self.0.iter().all(|&b| b == 0)
+    }
+
+    fn is_ustar(&self) -> bool {
+        &self.0[257..262] == b"ustar"
+    }
+
+    fn checksum_ok(&self) -> bool {
+        let sum: u32 = self
+            .0
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if (148..156).contains(&i) {
+                    b' ' as u32
+                } else {
+                    b as u32
+                }
+            })
+            .sum();
+        parse_octal(&self.0[148..156]) == sum as usize
+    }
+
+    fn typeflag(&self) -> u8 {
+        self.0[156]
+    }
+
+    fn size(&self) -> usize {
+        parse_octal(&self.0[124..136])
+    }
+
+    fn path(&self) -> String {
+        let prefix = field_str(&self.0[345..500]);
+        let name = field_str(&self.0[0..100]);
+        let mut path = String::from(prefix);
+        if !path.is_empty() {
+            path.push('/');
+        }
+        path.push_str(name);
+        path
+    }
+
+    fn link_name(&self) -> &'a str {
+        field_str(&self.0[157..257])
+    }
+}
+
+fn field_str(field: &[u8]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("")
+}
+
+fn parse_octal(field: &[u8]) -> usize {
+    field
+        .iter()
+        .skip_while(|&&b| b == b' ')
+        .take_while(|&&b| (b'0'..=b'7').contains(&b))
+        .fold(0, |n, &b| n * 8 + (b - b'0') as usize)
+}
+
+struct File(&'static [u8]);
+
+impl Node for File {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::File,
+            size: self.0.len(),
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        let data = self.0.get(offset..).unwrap_or(&[]);
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+}
+
+struct Symlink(String);
+
+impl Node for Symlink {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Symlink,
+            size: self.0.len(),
+        }
+    }
+
+    fn read_link(&self) -> Result<String, Error> {
+        Ok(self.0.clone())
+    }
+}
+
+struct Directory {
+    entries: RwLock<BTreeMap<String, Arc<dyn Node>>>,
+}
+
+impl Directory {
+    fn new() -> Self {
+        Directory {
+            entries: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    fn insert(&self, name: &str, node: Arc<dyn Node>) {
+        self.entries.write().insert(String::from(name), node);
+    }
+}
+
+impl Node for Directory {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Directory,
+            size: self.entries.read().len(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, Error> {
+        self.entries
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, Error> {
+        Ok(self
+            .entries
+            .read()
+            .iter()
+            .map(|(name, node)| DirEntry {
+                name: name.clone(),
+                kind: node.metadata().kind,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,586 @@
+//! A 9P2000.L client, for mounting a directory the host shares.
+//!
+//! Each node holds a fid walked to it and clunked when the node is
+//! dropped; reads and writes go through a second fid, opened on first use.
+//! Requests are sent one at a time and block until the server answers, so
+//! this suits development, not heavy I/O.
+
+use super::{DirEntry, Error, FileSystem, Metadata, Node, NodeKind};
+use crate::sync::Mutex;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub const VERSION: &str = "9P2000.L";
+
+const NOTAG: u16 = !0;
+const NOFID: u32 = !0;
+/// Requests never overlap, so one tag does.
+const TAG: u16 = 1;
+/// Message header: size, type and tag.
+const HEADER: usize = 7;
+/// Header room in `Tread`/`Twrite` and their replies, besides the data.
+const IO_HEADER: usize = 24;
+
+const RLERROR: u8 = 7;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TSYMLINK: u8 = 16;
+const TREADLINK: u8 = 22;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TREADDIR: u8 = 40;
+const TMKDIR: u8 = 72;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+
+const QID_DIR: u8 = 0x80;
+const QID_SYMLINK: u8 = 0x02;
+
+// Linux values, which 9P2000.L uses as they are
+const O_RDONLY: u32 = 0;
+const O_RDWR: u32 = 2;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+const O_DIRECTORY: u32 = 0o200000;
+const AT_REMOVEDIR: u32 = 0x200;
+const DT_CHR: u8 = 2;
+const DT_DIR: u8 = 4;
+const DT_BLK: u8 = 6;
+const DT_LNK: u8 = 10;
+
+const GETATTR_BASIC: u64 = 0x7ff;
+const SETATTR_SIZE: u32 = 0x8;
+
+/// Carries 9P messages to the server.
+pub trait Transport: Send + Sync {
+    /// The largest message the transport carries either way.
+    fn max_message(&self) -> usize;
+
+    /// Sends one request and returns the reply.
+    fn rpc(&self, request: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Qid {
+    kind: u8,
+}
+
+impl Qid {
+    fn node_kind(&self) -> NodeKind {
+        if self.kind & QID_DIR != 0 {
+            NodeKind::Directory
+        } else if self.kind & QID_SYMLINK != 0 {
+            NodeKind::Symlink
+        } else {
+            NodeKind::File
+        }
+    }
+}
+
+/// A request being built.
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new(kind: u8) -> Self {
+        Message::with_tag(kind, TAG)
+    }
+
+    fn with_tag(kind: u8, tag: u16) -> Self {
+        let mut message = Message(Vec::with_capacity(64));
+        message.u32(0).u8(kind).u16(tag);
+        message
+    }
+
+    fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    fn u16(&mut self, value: u16) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn str(&mut self, value: &str) -> &mut Self {
+        self.u16(value.len() as u16);
+        self.0.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+}
+
+/// A reply being taken apart; running short is an I/O error.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() < len {
+            return Err(Error::Io);
+        }
+        let (taken, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn str(&mut self) -> Result<String, Error> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn qid(&mut self) -> Result<Qid, Error> {
+        let kind = self.u8()?;
+        self.take(4 + 8)?; // version, path
+        Ok(Qid { kind })
+    }
+}
+
+/// The errno in an `Rlerror`, as near as `Error` gets.
+fn errno(errno: u32) -> Error {
+    match errno {
+        1 | 13 | 30 => Error::ReadOnly,
+        2 => Error::NotFound,
+        9 => Error::BadDescriptor,
+        16 => Error::Busy,
+        17 => Error::AlreadyExists,
+        18 => Error::CrossDevice,
+        20 => Error::NotADirectory,
+        21 => Error::IsADirectory,
+        22 => Error::InvalidArgument,
+        36 => Error::InvalidPath,
+        38 | 95 => Error::Unsupported,
+        39 => Error::NotEmpty,
+        40 => Error::TooManyLinks,
+        _ => Error::Io,
+    }
+}
+
+struct Client {
+    transport: Arc<dyn Transport>,
+    msize: usize,
+    next_fid: AtomicU32,
+}
+
+impl Client {
+    /// Sends `message` and returns the body of the matching reply.
+    fn rpc(&self, message: &mut Message) -> Result<Vec<u8>, Error> {
+        let kind = message.0[4];
+        let len = message.0.len() as u32;
+        message.0[..4].copy_from_slice(&len.to_le_bytes());
+        let mut reply = self.transport.rpc(&message.0)?;
+        let mut header = Reader { buf: &reply };
+        let size = header.u32()? as usize;
+        let reply_kind = header.u8()?;
+        header.u16()?;
+        if size < HEADER || size > reply.len() {
+            return Err(Error::Io);
+        }
+        reply.truncate(size);
+        if reply_kind == RLERROR {
+            return Err(errno(
+                Reader {
+                    buf: &reply[HEADER..],
+                }
+                .u32()?,
+            ));
+        }
+        if reply_kind != kind + 1 {
+            return Err(Error::Io);
+        }
+        Ok(reply.split_off(HEADER))
+    }
+
+    fn alloc_fid(&self) -> u32 {
+        self.next_fid.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Walks `fid` down `names` into a new fid; no names clones it.
+    fn walk(&self, fid: u32, names: &[&str]) -> Result<(u32, Option<Qid>), Error> {
+        let newfid = self.alloc_fid();
+        let mut message = Message::new(TWALK);
+        message.u32(fid).u32(newfid).u16(names.len() as u16);
+        for name in names {
+            message.str(name);
+        }
+        let reply = self.rpc(&mut message)?;
+        let mut reader = Reader { buf: &reply };
+        let count = reader.u16()? as usize;
+        // a partial walk leaves newfid unused
+        if count != names.len() {
+            return Err(Error::NotFound);
+        }
+        let mut qid = None;
+        for _ in 0..count {
+            qid = Some(reader.qid()?);
+        }
+        Ok((newfid, qid))
+    }
+
+    fn open(&self, fid: u32, flags: u32) -> Result<(), Error> {
+        self.rpc(Message::new(TLOPEN).u32(fid).u32(flags))
+            .map(|_| ())
+    }
+
+    fn clunk(&self, fid: u32) {
+        let _ = self.rpc(Message::new(TCLUNK).u32(fid));
+    }
+
+    /// The data a `Tread` or `Twrite` may carry.
+    fn io_size(&self) -> usize {
+        self.msize - IO_HEADER
+    }
+}
+
+/// A directory the server shares, mounted as a filesystem.
+pub struct P9Fs {
+    root: Arc<P9Node>,
+}
+
+impl P9Fs {
+    /// Negotiates the protocol over `transport` and attaches to `aname`,
+    /// the server's export, or its default one if empty.
+    pub fn attach(transport: Arc<dyn Transport>, aname: &str) -> Result<Self, Error> {
+        let mut message = Message::with_tag(TVERSION, NOTAG);
+        message.u32(transport.max_message() as u32).str(VERSION);
+        let mut client = Client {
+            msize: transport.max_message(),
+            transport,
+            next_fid: AtomicU32::new(1),
+        };
+        let reply = client.rpc(&mut message)?;
+        let mut reader = Reader { buf: &reply };
+        let msize = reader.u32()? as usize;
+        if reader.str()? != VERSION || msize <= IO_HEADER {
+            return Err(Error::Unsupported);
+        }
+        client.msize = client.msize.min(msize);
+
+        let root_fid = 0;
+        let reply = client.rpc(
+            Message::new(TATTACH)
+                .u32(root_fid)
+                .u32(NOFID)
+                .str("root")
+                .str(aname)
+                .u32(0),
+        )?;
+        let qid = Reader { buf: &reply }.qid()?;
+        Ok(P9Fs {
+            root: Arc::new(P9Node::new(Arc::new(client), root_fid, qid)),
+        })
+    }
+}
+
+impl FileSystem for P9Fs {
+    fn name(&self) -> &'static str {
+        "9p"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        self.root.clone()
+    }
+}
+
+struct P9Node {
+    client: Arc<Client>,
+    fid: u32,
+    qid: Qid,
+    /// The fid reads and writes go through, and whether it was opened
+    /// for writing.
+    io: Mutex<Option<(u32, bool)>>,
+}
+
+impl P9Node {
+    fn new(client: Arc<Client>, fid: u32, qid: Qid) -> Self {
+        P9Node {
+            client,
+            fid,
+            qid,
+            io: Mutex::new(None),
+        }
+    }
+
+    /// The opened fid, read-write if the server allows, else read-only.
+    fn io_fid(&self) -> Result<(u32, bool), Error> {
+        let mut io = self.io.lock();
+        if let Some(io) = *io {
+            return Ok(io);
+        }
+        let (fid, _) = self.client.walk(self.fid, &[])?;
+        let opened = match self.client.open(fid, O_RDWR) {
+            Ok(()) => Ok((fid, true)),
+            Err(_) => self.client.open(fid, O_RDONLY).map(|_| (fid, false)),
+        };
+        match opened {
+            Ok(opened) => Ok(*io.get_or_insert(opened)),
+            Err(err) => {
+                self.client.clunk(fid);
+                Err(err)
+            }
+        }
+    }
+
+    fn child(&self, name: &str) -> Result<P9Node, Error> {
+        let (fid, qid) = self.client.walk(self.fid, &[name])?;
+        let qid = qid.ok_or(Error::Io)?;
+        Ok(P9Node::new(self.client.clone(), fid, qid))
+    }
+
+    fn size(&self) -> Result<usize, Error> {
+        let reply = self
+            .client
+            .rpc(Message::new(TGETATTR).u32(self.fid).u64(GETATTR_BASIC))?;
+        let mut reader = Reader { buf: &reply };
+        reader.u64()?; // valid
+        reader.qid()?;
+        reader.u32()?; // mode
+        reader.u32()?; // uid
+        reader.u32()?; // gid
+        reader.u64()?; // nlink
+        reader.u64()?; // rdev
+        Ok(reader.u64()? as usize)
+    }
+}
+
+impl Drop for P9Node {
+    fn drop(&mut self) {
+        if let Some((fid, _)) = self.io.lock().take() {
+            self.client.clunk(fid);
+        }
+        self.client.clunk(self.fid);
+    }
+}
+
+impl Node for P9Node {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: self.qid.node_kind(),
+            size: self.size().unwrap_or(0),
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.qid.node_kind() == NodeKind::Directory {
+            return Err(Error::IsADirectory);
+        }
+        let (fid, _) = self.io_fid()?;
+        let count = buf.len().min(self.client.io_size());
+        let reply = self.client.rpc(
+            Message::new(TREAD)
+                .u32(fid)
+                .u64(offset as u64)
+                .u32(count as u32),
+        )?;
+        let mut reader = Reader { buf: &reply };
+        let read = (reader.u32()? as usize).min(count);
+        buf[..read].copy_from_slice(reader.take(read)?);
+        Ok(read)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Error> {
+        if self.qid.node_kind() == NodeKind::Directory {
+            return Err(Error::IsADirectory);
+        }
+        let (fid, writable) = self.io_fid()?;
+        if !writable {
+            return Err(Error::ReadOnly);
+        }
+        let mut written = 0;
+        for chunk in buf.chunks(self.client.io_size()) {
+            let reply = self.client.rpc(
+                Message::new(TWRITE)
+                    .u32(fid)
+                    .u64((offset + written) as u64)
+                    .u32(chunk.len() as u32)
+                    .bytes(chunk),
+            )?;
+            let count = Reader { buf: &reply }.u32()? as usize;
+            written += count;
+            if count < chunk.len() {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), Error> {
+        self.client
+            .rpc(
+                Message::new(TSETATTR)
+                    .u32(self.fid)
+                    .u32(SETATTR_SIZE)
+                    .u32(0) // mode
+                    .u32(0) // uid
+                    .u32(0) // gid
+                    .u64(size as u64)
+                    .u64(0) // atime
+                    .u64(0)
+                    .u64(0) // mtime
+                    .u64(0),
+            )
+            .map(|_| ())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, Error> {
+        if self.qid.node_kind() != NodeKind::Directory {
+            return Err(Error::NotADirectory);
+        }
+        Ok(Arc::new(self.child(name)?))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, Error> {
+        let (fid, _) = self.client.walk(self.fid, &[])?;
+        let entries = self
+            .client
+            .open(fid, O_RDONLY | O_DIRECTORY)
+            .and_then(|_| read_entries(&self.client, fid));
+        self.client.clunk(fid);
+        entries
+    }
+
+    fn create(&self, name: &str, kind: NodeKind) -> Result<Arc<dyn Node>, Error> {
+        match kind {
+            NodeKind::Directory => {
+                self.client.rpc(
+                    Message::new(TMKDIR)
+                        .u32(self.fid)
+                        .str(name)
+                        .u32(0o755)
+                        .u32(0),
+                )?;
+                Ok(Arc::new(self.child(name)?))
+            }
+            NodeKind::File => {
+                // Tlcreate turns the fid it is sent into the new file, open
+                let (io, _) = self.client.walk(self.fid, &[])?;
+                let created = self.client.rpc(
+                    Message::new(TLCREATE)
+                        .u32(io)
+                        .str(name)
+                        .u32(O_RDWR | O_CREAT | O_EXCL)
+                        .u32(0o644)
+                        .u32(0),
+                );
+                if let Err(err) = created {
+                    self.client.clunk(io);
+                    return Err(err);
+                }
+                let child = self.child(name);
+                let child = child.map_err(|err| {
+                    self.client.clunk(io);
+                    err
+                })?;
+                *child.io.lock() = Some((io, true));
+                Ok(Arc::new(child))
+            }
+            NodeKind::Symlink | NodeKind::Device => Err(Error::Unsupported),
+        }
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<(), Error> {
+        self.client
+            .rpc(
+                Message::new(TSYMLINK)
+                    .u32(self.fid)
+                    .str(name)
+                    .str(target)
+                    .u32(0),
+            )
+            .map(|_| ())
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Error> {
+        let flags = match self.child(name)?.qid.node_kind() {
+            NodeKind::Directory => AT_REMOVEDIR,
+            _ => 0,
+        };
+        self.client
+            .rpc(Message::new(TUNLINKAT).u32(self.fid).str(name).u32(flags))
+            .map(|_| ())
+    }
+
+    fn read_link(&self) -> Result<String, Error> {
+        if self.qid.node_kind() != NodeKind::Symlink {
+            return Err(Error::InvalidPath);
+        }
+        let reply = self.client.rpc(Message::new(TREADLINK).u32(self.fid))?;
+        Reader { buf: &reply }.str()
+    }
+}
+
+/// Reads a directory opened as `fid` to the end.
+fn read_entries(client: &Client, fid: u32) -> Result<Vec<DirEntry>, Error> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    loop {
+        let reply = client.rpc(
+            Message::new(TREADDIR)
+                .u32(fid)
+                .u64(offset)
+                .u32(client.io_size() as u32),
+        )?;
+        let mut reader = Reader { buf: &reply };
+        let count = reader.u32()? as usize;
+        if count == 0 {
+            return Ok(entries);
+        }
+        let mut reader = Reader {
+            buf: reader.take(count)?,
+        };
+        while !reader.buf.is_empty() {
+            let qid = reader.qid()?;
+            offset = reader.u64()?;
+            let kind = match reader.u8()? {
+                DT_DIR => NodeKind::Directory,
+                DT_LNK => NodeKind::Symlink,
+                DT_CHR | DT_BLK => NodeKind::Device,
+                _ => qid.node_kind(),
+            };
+            let name = reader.str()?;
+            if name != "." && name != ".." {
+                entries.push(DirEntry { name, kind });
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use super::Error;
+use alloc::{string::String, vec::Vec};
+
+/// Symlinks followed while resolving one path before giving up.
+pub const MAX_SYMLINK_DEPTH: usize = 8;
+
+/// Collapses `.`, `..` and repeated slashes in an absolute path.
+///
+/// This is purely lexical: `..` after a symlink goes back to the
+/// directory holding the link, not the target's parent.
+pub fn normalize(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    let mut normalized = String::with_capacity(path.len());
+    for component in components {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Joins `path` to `base` unless it is already absolute.
+pub fn join(base: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        normalize(path)
+    } else {
+        let mut joined = String::from(base);
+        joined.push('/');
+        joined.push_str(path);
+        normalize(&joined)
+    }
+}
+
+/// Resolves `path` against the current task's working directory.
+pub fn absolute(path: &str) -> Result<String, Error> {
+    if path.is_empty() {
+        return Err(Error::InvalidPath);
+    }
+    if path.starts_with('/') {
+        return Ok(normalize(path));
+    }
+    Ok(join(&super::fd::cwd(), path))
+}
+
+/// Splits a normalized path into its parent and final component.
+pub fn split(path: &str) -> Option<(&str, &str)> {
+    let index = path.rfind('/')?;
+    let name = &path[index + 1..];
+    if name.is_empty() {
+        return None;
+    }
+    Some((if index == 0 { "/" } else { &path[..index] }, name))
+}
+
+#[test_case]
+fn normalize_collapses_dots() {
+    assert_eq!(normalize("/a/./b/../c//d/"), "/a/c/d");
+    assert_eq!(normalize("/.."), "/");
+    assert_eq!(normalize(""), "/");
+}
+
+#[test_case]
+fn join_keeps_absolute_paths() {
+    assert_eq!(join("/usr", "bin/../lib"), "/usr/lib");
+    assert_eq!(join("/usr", "/etc"), "/etc");
+}
+
+#[test_case]
+fn split_last_component() {
+    assert_eq!(split("/a/b"), Some(("/a", "b")));
+    assert_eq!(split("/a"), Some(("/", "a")));
+    assert_eq!(split("/"), None);
+}
@@ -0,0 +1,236 @@
+use super::{DirEntry, Error, FileSystem, Metadata, Node, NodeKind};
+use crate::sync::RwLock;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+/// A heap-backed read/write filesystem.
+pub struct Tmpfs {
+    root: Arc<Directory>,
+}
+
+impl Tmpfs {
+    pub fn new() -> Self {
+        Tmpfs {
+            root: Arc::new(Directory::new()),
+        }
+    }
+}
+
+impl FileSystem for Tmpfs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Node> {
+        self.root.clone()
+    }
+}
+
+struct File {
+    data: RwLock<Vec<u8>>,
+}
+
+impl Node for File {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::File,
+            size: self.data.read().len(),
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        let data = self.data.read();
+        let data = data.get(offset..).unwrap_or(&[]);
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Error> {
+        let mut data = self.data.write();
+        let end = offset
+            .checked_add(buf.len())
+            .ok_or(Error::InvalidArgument)?;
+        grow(&mut data, end)?;
+        data[offset..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Grows the file once and copies every buffer under the one lock, so
+    /// readers never see half the parts.
+    fn write_vectored_at(&self, offset: usize, bufs: &[&[u8]]) -> Result<usize, Error> {
+        let mut data = self.data.write();
+        let end = offset
+            .checked_add(bufs.iter().map(|buf| buf.len()).sum::<usize>())
+            .ok_or(Error::InvalidArgument)?;
+        grow(&mut data, end)?;
+        let mut at = offset;
+        for buf in bufs {
+            data[at..at + buf.len()].copy_from_slice(buf);
+            at += buf.len();
+        }
+        Ok(end - offset)
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), Error> {
+        let mut data = self.data.write();
+        data.truncate(size);
+        grow(&mut data, size)
+    }
+}
+
+/// Zero-fills `data` out to `len`, failing instead of taking the kernel
+/// down when the heap cannot hold that much.
+fn grow(data: &mut Vec<u8>, len: usize) -> Result<(), Error> {
+    if data.len() < len {
+        data.try_reserve(len - data.len())
+            .map_err(|_| Error::NoSpace)?;
+        data.resize(len, 0);
+    }
+    Ok(())
+}
+
+struct Symlink(String);
+
+impl Node for Symlink {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Symlink,
+            size: self.0.len(),
+        }
+    }
+
+    fn read_link(&self) -> Result<String, Error> {
+        Ok(self.0.clone())
+    }
+}
+
+struct Directory {
+    entries: RwLock<BTreeMap<String, Arc<dyn Node>>>,
+}
+
+impl Directory {
+    fn new() -> Self {
+        Directory {
+            entries: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Node for Directory {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Directory,
+            size: self.entries.read().len(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Node>, Error> {
+        self.entries
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, Error> {
+        Ok(self
+            .entries
+            .read()
+            .iter()
+            .map(|(name, node)| DirEntry {
+                name: name.clone(),
+                kind: node.metadata().kind,
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, kind: NodeKind) -> Result<Arc<dyn Node>, Error> {
+        let node: Arc<dyn Node> = match kind {
+            NodeKind::File => Arc::new(File {
+                data: RwLock::new(Vec::new()),
+            }),
+            NodeKind::Directory => Arc::new(Directory::new()),
+            NodeKind::Symlink | NodeKind::Device => return Err(Error::Unsupported),
+        };
+        self.link(name, node.clone())?;
+        Ok(node)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<(), Error> {
+        self.link(name, Arc::new(Symlink(String::from(target))))
+    }
+
+    fn link(&self, name: &str, node: Arc<dyn Node>) -> Result<(), Error> {
+        let mut entries = self.entries.write();
+        if entries.contains_key(name) {
+            return Err(Error::AlreadyExists);
+        }
+        entries.insert(String::from(name), node);
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Error> {
+        let mut entries = self.entries.write();
+        let node = entries.get(name).ok_or(Error::NotFound)?;
+        let metadata = node.metadata();
+        if metadata.kind == NodeKind::Directory && metadata.size > 0 {
+            return Err(Error::NotEmpty);
+        }
+        entries.remove(name);
+        Ok(())
+    }
+
+    fn detach(&self, name: &str) -> Result<Arc<dyn Node>, Error> {
+        self.entries.write().remove(name).ok_or(Error::NotFound)
+    }
+}
+
+#[test_case]
+fn files_read_back_what_was_written() {
+    let fs = Tmpfs::new();
+    let file = fs.root().create("file", NodeKind::File).unwrap();
+    assert_eq!(file.write_at(0, b"hello").unwrap(), 5);
+    // a write past the end zero-fills the gap
+    assert_eq!(file.write_at(7, b"world").unwrap(), 5);
+    assert_eq!(file.metadata().size, 12);
+    let mut buf = [0xff; 16];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 12);
+    assert_eq!(&buf[..12], b"hello\0\0world");
+    assert_eq!(file.read_at(12, &mut buf).unwrap(), 0);
+    assert_eq!(
+        fs.root().create("file", NodeKind::File).err(),
+        Some(Error::AlreadyExists)
+    );
+}
+
+#[test_case]
+fn growth_is_bounded_by_the_heap() {
+    let file = Tmpfs::new().root().create("file", NodeKind::File).unwrap();
+    file.write_at(0, b"kept").unwrap();
+    assert_eq!(file.write_at(usize::MAX / 2, b"x"), Err(Error::NoSpace));
+    assert_eq!(file.write_at(usize::MAX, b"x"), Err(Error::InvalidArgument));
+    assert_eq!(file.truncate(usize::MAX / 2), Err(Error::NoSpace));
+    let mut buf = [0; 8];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], b"kept");
+}
+
+#[test_case]
+fn rename_replaces_an_existing_node() {
+    // the first test to need the namespace gets a tmpfs root
+    let _ = super::mount_root(Arc::new(Tmpfs::new()));
+    super::mkdir("/rename").unwrap();
+    super::write("/rename/from", b"new").unwrap();
+    super::write("/rename/to", b"old").unwrap();
+    super::rename("/rename/from", "/rename/to").unwrap();
+    assert_eq!(super::lookup("/rename/from").err(), Some(Error::NotFound));
+    assert_eq!(super::read_to_end("/rename/to").unwrap(), b"new");
+
+    super::mkdir("/rename/full").unwrap();
+    super::write("/rename/full/file", b"").unwrap();
+    assert_eq!(
+        super::rename("/rename/to", "/rename/full"),
+        Err(Error::NotEmpty)
+    );
+    assert_eq!(super::read_to_end("/rename/to").unwrap(), b"new");
+}
@@ -1,19 +1,27 @@
-use lazy_static::lazy_static;
+use crate::sync::{Lazy, Mutex, Rcu};
+use core::{
+    panic::Location,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
 use pic8259_simple::ChainedPics;
-use spin::Mutex;
 use x86_64::{
-    instructions::{interrupts::without_interrupts, port::Port},
+    instructions::port::Port,
     registers::control::{Cr2, Cr3},
     registers::rflags::{self, RFlags},
     structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+    PrivilegeLevel,
 };
 
 use crate::device::pic_8259::{MAIN, WORKER};
+use crate::memory::protect::{self, PageAligned};
 
 pub mod gdt;
+pub mod latency;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+/// `int 0x80`, the syscall gate.
+pub const SYSCALL_VECTOR: usize = 0x80;
 
 #[inline(always)]
 pub fn disable() {
@@ -34,10 +42,50 @@ pub fn enable_and_hlt() {
     x86_64::instructions::interrupts::enable_and_hlt();
 }
 
+static INTERRUPT_DEPTH: AtomicUsize = AtomicUsize::new(0);
+/// Hardware interrupts handled since boot.
+static INTERRUPT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Marks the current code as running inside an interrupt handler until the
+/// returned guard is dropped.
+pub struct InterruptGuard {
+    _private: (),
+}
+
+impl InterruptGuard {
+    pub fn enter() -> Self {
+        INTERRUPT_DEPTH.fetch_add(1, Ordering::Relaxed);
+        INTERRUPT_COUNT.fetch_add(1, Ordering::Relaxed);
+        latency::enter();
+        InterruptGuard { _private: () }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        INTERRUPT_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// How many interrupt handlers are currently nested.
+pub fn depth() -> usize {
+    INTERRUPT_DEPTH.load(Ordering::Relaxed)
+}
+
+/// Hardware interrupts handled since boot, counted as handlers enter.
+pub fn count() -> u64 {
+    INTERRUPT_COUNT.load(Ordering::Relaxed)
+}
+
+pub fn in_interrupt() -> bool {
+    INTERRUPT_DEPTH.load(Ordering::Relaxed) > 0
+}
+
 pub fn enabled() -> bool {
     rflags::read().contains(RFlags::INTERRUPT_FLAG)
 }
 
+#[track_caller]
 pub fn disable_then_execute<F, T>(uninterrupted_fn: F) -> T
 where
     F: FnOnce() -> T,
@@ -46,22 +94,27 @@ where
     if interrupts_enabled == true {
         disable();
     }
+    let start = latency::off_start();
 
     let result: T = uninterrupted_fn();
 
     if interrupts_enabled == true {
+        latency::off_end(start, Location::caller());
         enable();
     }
 
     result
 }
 
+#[track_caller]
 pub fn mask_then_restore<F, T>(uninterrupted_fn: F) -> T
 where
     F: FnOnce() -> T,
 {
     let saved_masks: (u8, u8) = mask();
+    let start = latency::off_start();
     let result: T = uninterrupted_fn();
+    latency::off_end(start, Location::caller());
     restore_mask(saved_masks);
     result
 }
@@ -144,6 +197,59 @@ impl InterruptIndex {
     }
 }
 
+#[derive(Debug)]
+pub enum Error {
+    UnroutableIrq,
+    IrqHandlersFull,
+}
+
+/// Handlers sharing one line; each must check whether its device fired.
+const HANDLERS_PER_IRQ: usize = 4;
+
+/// Lines firmware hands out to PCI devices, which get dispatched handlers.
+const PCI_IRQS: [InterruptIndex; 3] = [
+    InterruptIndex::Acpi,
+    InterruptIndex::Available1,
+    InterruptIndex::Available2,
+];
+
+type IrqHandlers = [[Option<fn()>; HANDLERS_PER_IRQ]; 16];
+
+static NO_IRQ_HANDLERS: IrqHandlers = [[None; HANDLERS_PER_IRQ]; 16];
+/// Read on every interrupt without a lock; registering copies the table.
+static IRQ_HANDLERS: Rcu<IrqHandlers> = Rcu::from_static(&NO_IRQ_HANDLERS);
+
+/// Calls `handler` whenever PIC line `line` fires.
+pub fn register_irq(line: u8, handler: fn()) -> Result<(), Error> {
+    if !PCI_IRQS
+        .iter()
+        .any(|index| index.as_u8() - PIC_1_OFFSET == line)
+    {
+        return Err(Error::UnroutableIrq);
+    }
+    IRQ_HANDLERS.try_update(|handlers| {
+        let mut handlers = *handlers;
+        let slot = handlers[line as usize]
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(Error::IrqHandlersFull)?;
+        *slot = Some(handler);
+        Ok(handlers)
+    })
+}
+
+fn dispatch_irq(index: InterruptIndex) {
+    let _guard = InterruptGuard::enter();
+    trace_event!(irq, vector = index.as_u8());
+    let handlers = IRQ_HANDLERS.read();
+    handlers[(index.as_u8() - PIC_1_OFFSET) as usize]
+        .iter()
+        .flatten()
+        .for_each(|handler| handler());
+    drop(handlers);
+    unsafe { PICS.lock().notify_end_of_interrupt(index.as_u8()) }
+}
+
 pub static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
@@ -151,60 +257,76 @@ pub fn init() {
     IDT.load();
 }
 
-lazy_static! {
-    static ref IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-            idt.divide_error.set_handler_fn(divide_error_handler);
-            idt.debug.set_handler_fn(debug_handler);
-            idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler);
-            idt.breakpoint.set_handler_fn(breakpoint_handler);
-            idt.overflow.set_handler_fn(overflow_handler);
-            idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
-            idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
-            idt.device_not_available.set_handler_fn(device_not_available_handler);
-            unsafe {
-                idt.double_fault
-                    .set_handler_fn(double_fault_handler)
-                    .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-            }
-            idt.invalid_tss.set_handler_fn(invalid_tss_handler);
-            idt.segment_not_present.set_handler_fn(segment_not_present_handler);
-            idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
-            // idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
-            idt.page_fault.set_handler_fn(page_fault_handler);
-            // idt.reserved_1.set_handler_fn();
-            idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
-            idt.alignment_check.set_handler_fn(alignment_check_handler);
-            // idt.machine_check.set_handler_fn(machine_check_handler);
-            idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
-            idt.virtualization.set_handler_fn(virtualization_handler);
-            // idt.reserved_2.set_handler_fn();
-            idt.security_exception.set_handler_fn(security_exception_handler);
-            // idt.reserved_3.set_handler_fn();
-            idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
-            idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
-            /*
-            idt[Cascade.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[SerialPort2.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[SerialPort1.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[ParallelPort2_3.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[FloppyDisk.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[ParallelPort1.as_usize()].set_handler_fn(_interrupt_handler);
-            */
-            // idt[InterruptIndex::RealTimeClock.as_usize()].set_handler_fn(real_time_clock_interrupt_handler);
-            /*
-            idt[Acpi.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[Available1.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[Available2.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[Mouse.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[CoProcessor.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[PrimaryAta.as_usize()].set_handler_fn(_interrupt_handler);
-            idt[SecondaryAta.as_usize()].set_handler_fn(_interrupt_handler);
-            */
-
-        idt
-    };
-}
+/// Makes the IDT and GDT read-only; nothing changes them after `init`.
+pub fn protect_tables() -> Result<(), protect::Error> {
+    protect::protect(IDT.addr(), IDT.size())?;
+    gdt::protect()
+}
+
+static IDT: Lazy<PageAligned<InterruptDescriptorTable>> = Lazy::new(|| {
+    let mut idt = InterruptDescriptorTable::new();
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(crate::debug::gdb::debug_entry());
+    idt.non_maskable_interrupt
+        .set_handler_fn(non_maskable_interrupt_handler);
+    idt.breakpoint
+        .set_handler_fn(crate::debug::gdb::breakpoint_entry());
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded
+        .set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available
+        .set_handler_fn(device_not_available_handler);
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present
+        .set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault
+        .set_handler_fn(stack_segment_fault_handler);
+    // idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    // idt.reserved_1.set_handler_fn();
+    idt.x87_floating_point
+        .set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    // idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point
+        .set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    // idt.reserved_2.set_handler_fn();
+    idt.security_exception
+        .set_handler_fn(security_exception_handler);
+    // idt.reserved_3.set_handler_fn();
+    idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
+    idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+    idt[InterruptIndex::SerialPort1.as_usize()].set_handler_fn(serial_interrupt_handler);
+    idt[InterruptIndex::SerialPort2.as_usize()].set_handler_fn(serial2_interrupt_handler);
+    idt[InterruptIndex::Acpi.as_usize()].set_handler_fn(irq9_handler);
+    idt[InterruptIndex::Available1.as_usize()].set_handler_fn(irq10_handler);
+    idt[InterruptIndex::Available2.as_usize()].set_handler_fn(irq11_handler);
+    idt[SYSCALL_VECTOR]
+        .set_handler_fn(crate::syscall::entry())
+        .set_privilege_level(PrivilegeLevel::Ring3);
+    /*
+    idt[Cascade.as_usize()].set_handler_fn(_interrupt_handler);
+    idt[ParallelPort2_3.as_usize()].set_handler_fn(_interrupt_handler);
+    idt[FloppyDisk.as_usize()].set_handler_fn(_interrupt_handler);
+    idt[ParallelPort1.as_usize()].set_handler_fn(_interrupt_handler);
+    */
+    // idt[InterruptIndex::RealTimeClock.as_usize()].set_handler_fn(real_time_clock_interrupt_handler);
+    /*
+    idt[Mouse.as_usize()].set_handler_fn(_interrupt_handler);
+    idt[CoProcessor.as_usize()].set_handler_fn(_interrupt_handler);
+    idt[PrimaryAta.as_usize()].set_handler_fn(_interrupt_handler);
+    idt[SecondaryAta.as_usize()].set_handler_fn(_interrupt_handler);
+    */
+
+    PageAligned(idt)
+});
 
 // CPU exceptions
 
@@ -213,20 +335,16 @@ extern "x86-interrupt" fn divide_error_handler(_stack_frame: &mut InterruptStack
     panic!("DIVISION BY ZERO {:#?}", _stack_frame);
 }
 
-extern "x86-interrupt" fn debug_handler(_stack_frame: &mut InterruptStackFrame) {
-    error!("debug");
-    panic!("DEBUG");
-}
-
-extern "x86-interrupt" fn non_maskable_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
+    // both may have overflowed at once, so ask each
+    let watchdog = crate::debug::watchdog::nmi(stack_frame);
+    if crate::cpu::pmu::nmi(stack_frame) || watchdog {
+        return;
+    }
     error!("non maskable");
     panic!("Non maskable Stack Frame");
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut InterruptStackFrame) {
-    error!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
-}
-
 extern "x86-interrupt" fn overflow_handler(_stack_frame: &mut InterruptStackFrame) {
     error!("overflow");
     panic!("OVERFLOW");
@@ -294,7 +412,7 @@ extern "x86-interrupt" fn general_protection_fault_handler(
     println!("TRIED TO READ : {:#?}", Cr2::read());
     println!("CR3 : {:#?}", Cr3::read());
     println!("ERROR : {:#?}", _error_code);
-    shutdown();
+    crate::power::shutdown();
 }
 
 extern "x86-interrupt" fn x87_floating_point_handler(_stack_frame: &mut InterruptStackFrame) {
@@ -341,23 +459,36 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-
-    x86_64::instructions::hlt()
+    let addr = Cr2::read();
+    kv_log!(error, "EXCEPTION: PAGE FAULT"; addr = addr, code = error_code);
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && !error_code.contains(PageFaultErrorCode::USER_MODE)
+        && crate::memory::user::is_user_address(addr)
+    {
+        // SMEP or SMAP: the kernel followed a user pointer outside usercopy
+        panic!(
+            "EXCEPTION: PAGE FAULT on user address {:?} from the kernel\n{:#?}",
+            addr, stack_frame
+        );
+    }
+    // nothing is demand-paged, so returning would only fault again
+    panic!("EXCEPTION: PAGE FAULT\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
-    // print!(".");
+    let _guard = InterruptGuard::enter();
+    latency::timer_tick();
+    trace_event!(irq, vector = InterruptIndex::Timer.as_u8());
+    crate::time::tick();
+    crate::debug::canary::tick(crate::time::ticks());
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8())
     }
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
+    let _guard = InterruptGuard::enter();
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
 
@@ -367,6 +498,50 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut Interrup
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
     }
+    if crate::device::keyboard::take_monitor_request() {
+        crate::debug::monitor::enter_from_interrupt(stack_frame);
+    }
+}
+
+extern "x86-interrupt" fn serial_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
+    let _guard = InterruptGuard::enter();
+    let monitor = crate::serial::interrupt();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::SerialPort1.as_u8());
+    }
+    if monitor {
+        crate::debug::monitor::enter_from_interrupt(stack_frame);
+    }
+}
+
+/// COM2 belongs to the gdb stub, which may stop the kernel right here.
+extern "x86-interrupt" fn serial2_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+    let stop = {
+        let _guard = InterruptGuard::enter();
+        let stop = crate::debug::gdb::receive_interrupt();
+        unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(InterruptIndex::SerialPort2.as_u8());
+        }
+        stop
+    };
+    if stop {
+        crate::debug::gdb::breakpoint();
+    }
+}
+
+extern "x86-interrupt" fn irq9_handler(_stack_frame: &mut InterruptStackFrame) {
+    dispatch_irq(InterruptIndex::Acpi);
+}
+
+extern "x86-interrupt" fn irq10_handler(_stack_frame: &mut InterruptStackFrame) {
+    dispatch_irq(InterruptIndex::Available1);
+}
+
+extern "x86-interrupt" fn irq11_handler(_stack_frame: &mut InterruptStackFrame) {
+    dispatch_irq(InterruptIndex::Available2);
 }
 
 // extern "x86-interrupt" fn real_time_clock_interrupt_handler(
@@ -413,33 +588,3 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut Interrup
 //         }
 //     });
 // }
-
-pub fn shutdown() -> ! {
-    unsafe {
-        warn!("Sending shutdown signal to QEMU.");
-        let mut shutdown = Port::new(0x604);
-        shutdown.write(0x2000_u16);
-    }
-    unsafe {
-        asm!(
-            "push rax",
-            "push rbx",
-            "push rcx",
-            "push rsp",
-            "mov ax, 0x1000",
-            "mov ax, ss",
-            "mov sp, 0xf000",
-            "mov ax, 0x5307",
-            "mov bx, 0x0001",
-            "mov cx, 0x0003",
-            "int 0x15",
-            "pop rsp",
-            "pop rcx",
-            "pop rbx",
-            "pop rax",
-            "call failed_shutdown",
-            "ret",
-            options(noreturn,),
-        )
-    }
-}
@@ -1,39 +1,36 @@
-use lazy_static::lazy_static;
+use crate::memory::protect::{self, PageAligned};
+use crate::sync::Lazy;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
-}
+const STACK_SIZE: usize = 4096 * 5;
+static mut DOUBLE_FAULT_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
-lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                tss_selector,
-            },
-        )
+static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        let stack_start = VirtAddr::from_ptr(unsafe { &DOUBLE_FAULT_STACK });
+        let stack_end = stack_start + STACK_SIZE;
+        stack_end
     };
-}
+    tss
+});
+
+static GDT: Lazy<(PageAligned<GlobalDescriptorTable>, Selectors)> = Lazy::new(|| {
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+    (
+        PageAligned(gdt),
+        Selectors {
+            code_selector,
+            tss_selector,
+        },
+    )
+});
 
 struct Selectors {
     code_selector: SegmentSelector,
@@ -49,4 +46,14 @@ pub fn init() {
         set_cs(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
+    crate::debug::canary::register(
+        "double fault",
+        VirtAddr::from_ptr(unsafe { &DOUBLE_FAULT_STACK }),
+    );
+}
+
+/// Done after `init`: loading the TSS marks its descriptor busy, and the
+/// first `set_cs` sets the accessed bit, both writes by the CPU.
+pub(super) fn protect() -> Result<(), protect::Error> {
+    protect::protect(GDT.0.addr(), GDT.0.size())
 }
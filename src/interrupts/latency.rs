@@ -0,0 +1,256 @@
+//! How late interrupts are handled, built with the `irq-latency` feature.
+//!
+//! `InterruptGuard::enter` stamps every handler's entry with the TSC. The
+//! PIT's entries should come one tick apart, so however much longer an
+//! interval takes is how late that tick was handled, be it behind a `cli`,
+//! a masked PIC or another handler. The tick's length in cycles is the
+//! average interval since measuring began, which needs no calibration of
+//! its own. A stall longer than a tick loses the ticks in between and
+//! shows as a single late one.
+//!
+//! `disable_then_execute` and `mask_then_restore` also time how long they
+//! keep interrupts off, and remember where the longest such window was
+//! opened: the place to look when the latency is bad.
+//!
+//! Without the feature the hooks compile away and `stats` is `None`.
+
+use crate::{
+    shell::{self, Args, ShellErr},
+    sync::Mutex,
+    time::TICK_RATE,
+};
+use alloc::format;
+use core::{
+    arch::x86_64::_rdtsc,
+    fmt::Write,
+    panic::Location,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+const ENABLED: bool = cfg!(feature = "irq-latency");
+const TICK_NS: u64 = 1_000_000_000 / TICK_RATE as u64;
+/// Ticks left out at first, while the timer settles.
+const WARMUP_TICKS: u64 = 16;
+/// Histogram buckets, a microsecond wide; anything later goes in the last.
+const BUCKETS: usize = 512;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU32 = AtomicU32::new(0);
+
+/// The TSC when the innermost handler was entered.
+static ENTRY_TSC: AtomicU64 = AtomicU64::new(0);
+/// Timer entries since the last reset, warm-up included.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static FIRST_TSC: AtomicU64 = AtomicU64::new(0);
+static LAST_TSC: AtomicU64 = AtomicU64::new(0);
+
+static MIN_NS: AtomicU64 = AtomicU64::new(u64::MAX);
+static MAX_NS: AtomicU64 = AtomicU64::new(0);
+static HISTOGRAM: [AtomicU32; BUCKETS] = [ZERO; BUCKETS];
+
+static OFF_WINDOWS: AtomicU64 = AtomicU64::new(0);
+static OFF_TOTAL_CYCLES: AtomicU64 = AtomicU64::new(0);
+static OFF_MAX_CYCLES: AtomicU64 = AtomicU64::new(0);
+static OFF_MAX_SITE: Mutex<Option<&'static Location<'static>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    /// Timer ticks measured.
+    pub samples: u64,
+    /// How late the timer was handled, in nanoseconds.
+    pub min_ns: u64,
+    /// To the microsecond above.
+    pub p99_ns: u64,
+    pub max_ns: u64,
+    /// Windows `disable_then_execute` and `mask_then_restore` kept
+    /// interrupts off, and for how long in nanoseconds.
+    pub off_windows: u64,
+    pub off_total_ns: u64,
+    pub off_max_ns: u64,
+    /// Where the longest window was opened.
+    pub off_max_site: Option<&'static Location<'static>>,
+}
+
+/// Called by `InterruptGuard::enter`.
+#[inline(always)]
+pub(super) fn enter() {
+    if ENABLED {
+        ENTRY_TSC.store(unsafe { _rdtsc() }, Ordering::Relaxed);
+    }
+}
+
+/// Called by the timer handler, which runs with interrupts off, so the
+/// entry stamp is still its own.
+pub(super) fn timer_tick() {
+    if !ENABLED {
+        return;
+    }
+    let now = ENTRY_TSC.load(Ordering::Relaxed);
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    if ticks <= WARMUP_TICKS {
+        FIRST_TSC.store(now, Ordering::Relaxed);
+        LAST_TSC.store(now, Ordering::Relaxed);
+        return;
+    }
+    let last = LAST_TSC.swap(now, Ordering::Relaxed);
+    let period = now.wrapping_sub(FIRST_TSC.load(Ordering::Relaxed)) / (ticks - WARMUP_TICKS);
+    if period == 0 {
+        return;
+    }
+    let late = now.wrapping_sub(last).saturating_sub(period);
+    record(to_ns(late, period));
+}
+
+fn record(ns: u64) {
+    MIN_NS.fetch_min(ns, Ordering::Relaxed);
+    MAX_NS.fetch_max(ns, Ordering::Relaxed);
+    let bucket = ((ns / 1000) as usize).min(BUCKETS - 1);
+    HISTOGRAM[bucket].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Starts timing a window with interrupts off; hand the result to
+/// `off_end` before turning them back on.
+#[inline(always)]
+pub(super) fn off_start() -> u64 {
+    if ENABLED {
+        unsafe { _rdtsc() }
+    } else {
+        0
+    }
+}
+
+pub(super) fn off_end(start: u64, site: &'static Location<'static>) {
+    if !ENABLED {
+        return;
+    }
+    let cycles = unsafe { _rdtsc() }.wrapping_sub(start);
+    OFF_WINDOWS.fetch_add(1, Ordering::Relaxed);
+    OFF_TOTAL_CYCLES.fetch_add(cycles, Ordering::Relaxed);
+    if cycles > OFF_MAX_CYCLES.fetch_max(cycles, Ordering::Relaxed) {
+        // interrupts are still off, so no handler holds this
+        if let Some(mut max_site) = OFF_MAX_SITE.try_lock() {
+            *max_site = Some(site);
+        }
+    }
+}
+
+fn to_ns(cycles: u64, period: u64) -> u64 {
+    (u128::from(cycles) * u128::from(TICK_NS) / u128::from(period)) as u64
+}
+
+/// What has been measured since boot or the last `reset`; `None` without
+/// the feature or until the timer has ticked past the warm-up.
+pub fn stats() -> Option<Stats> {
+    let ticks = TICKS.load(Ordering::Relaxed);
+    if !ENABLED || ticks <= WARMUP_TICKS {
+        return None;
+    }
+    let period = LAST_TSC
+        .load(Ordering::Relaxed)
+        .wrapping_sub(FIRST_TSC.load(Ordering::Relaxed))
+        / (ticks - WARMUP_TICKS);
+    if period == 0 {
+        return None;
+    }
+    let max_ns = MAX_NS.load(Ordering::Relaxed);
+    Some(Stats {
+        samples: ticks - WARMUP_TICKS,
+        min_ns: MIN_NS.load(Ordering::Relaxed).min(max_ns),
+        p99_ns: p99_ns().min(max_ns),
+        max_ns,
+        off_windows: OFF_WINDOWS.load(Ordering::Relaxed),
+        off_total_ns: to_ns(OFF_TOTAL_CYCLES.load(Ordering::Relaxed), period),
+        off_max_ns: to_ns(OFF_MAX_CYCLES.load(Ordering::Relaxed), period),
+        off_max_site: *OFF_MAX_SITE.lock(),
+    })
+}
+
+/// The upper edge of the bucket the 99th percentile falls in, unbounded
+/// for the last.
+fn p99_ns() -> u64 {
+    let total: u64 = HISTOGRAM
+        .iter()
+        .map(|bucket| u64::from(bucket.load(Ordering::Relaxed)))
+        .sum();
+    let target = (total * 99 + 99) / 100;
+    let mut seen = 0;
+    for (i, bucket) in HISTOGRAM.iter().enumerate() {
+        seen += u64::from(bucket.load(Ordering::Relaxed));
+        if seen >= target && i < BUCKETS - 1 {
+            return (i as u64 + 1) * 1000;
+        }
+    }
+    u64::MAX
+}
+
+/// Starts measuring afresh, the tick's length included.
+pub fn reset() {
+    crate::interrupts::disable_then_execute(|| {
+        TICKS.store(0, Ordering::Relaxed);
+        MIN_NS.store(u64::MAX, Ordering::Relaxed);
+        MAX_NS.store(0, Ordering::Relaxed);
+        HISTOGRAM
+            .iter()
+            .for_each(|bucket| bucket.store(0, Ordering::Relaxed));
+        OFF_WINDOWS.store(0, Ordering::Relaxed);
+        OFF_TOTAL_CYCLES.store(0, Ordering::Relaxed);
+        OFF_MAX_CYCLES.store(0, Ordering::Relaxed);
+        *OFF_MAX_SITE.lock() = None;
+    });
+}
+
+/// Registers `irqlat`.
+pub fn init() {
+    shell::register(
+        "irqlat",
+        "irqlat [reset]\nShows how late the timer interrupt ran and how long interrupts were off.",
+        command,
+    );
+}
+
+/// Nanoseconds as microseconds with three decimals.
+struct Micros(u64);
+
+impl core::fmt::Display for Micros {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}.{:03} us", self.0 / 1000, self.0 % 1000)
+    }
+}
+
+/// `irqlat [reset]`
+fn command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    if !ENABLED {
+        return Err(ShellErr::new("built without the irq-latency feature"));
+    }
+    let mut args = Args::new(args);
+    match args.word() {
+        Some("reset") => {
+            args.finish()?;
+            reset();
+            return Ok(());
+        }
+        Some(word) => return Err(ShellErr::from(format!("bad argument: {}", word))),
+        None => args.finish()?,
+    }
+    let stats = stats().ok_or_else(|| ShellErr::new("still warming up"))?;
+    writeln!(
+        out,
+        "timer over {} ticks: min {}, p99 {}, max {} late",
+        stats.samples,
+        Micros(stats.min_ns),
+        Micros(stats.p99_ns),
+        Micros(stats.max_ns)
+    )?;
+    write!(
+        out,
+        "interrupts off {} times for {}, longest {}",
+        stats.off_windows,
+        Micros(stats.off_total_ns),
+        Micros(stats.off_max_ns)
+    )?;
+    match stats.off_max_site {
+        Some(site) => writeln!(out, " at {}:{}", site.file(), site.line())?,
+        None => writeln!(out)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! The kernel's shared services, assembled once at the end of `init_from`.
+//!
+//! Modules that need memory, time, devices or new tasks go through
+//! `services()` instead of the statics behind them, so what depends on
+//! what shows up in one place. Assembling checks that each service's own
+//! initialization has run and panics naming the first one that has not,
+//! which keeps the boot order in `init_from` honest.
+//!
+//! The console and the spawner are trait objects, so another
+//! implementation can be registered before assembly and every caller gets
+//! it, with no `#[cfg]` at the call sites. Without one, the console follows
+//! `console=` on the command line: the VGA screen, or COM1 for a headless
+//! machine, which also drops the VGA log sink. Log sinks and NICs were
+//! trait objects already; they register with `logs` and through
+//! `Devices::attach_net`.
+
+use crate::{
+    boot::cmdline::{self, ConsoleKind},
+    device::{
+        driver::{self, Driver},
+        pci::{self, PciDevice},
+    },
+    error::KernelError,
+    logs,
+    memory::{self, BootInfoFrameAllocator, FRAME_ALLOCATOR, MAPPER},
+    net::{self, NetDevice},
+    serial::SERIAL_CONSOLE,
+    sync::{InitCell, Mutex, Once},
+    task::{scheduler::Spawner, Priority, PriorityTask, TaskId},
+    time::{self, Sleep},
+    vga_buffer::VGA_CONSOLE,
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::{fmt, future::Future};
+use x86_64::{
+    structures::paging::{mapper::MapToError, OffsetPageTable, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+static SERVICES: InitCell<Services> = InitCell::new("kernel services");
+static SPAWNER: Spawner = Spawner::new();
+/// Implementations registered in place of the defaults.
+static CONSOLE: Once<&'static dyn Console> = Once::new();
+static SPAWN: Once<&'static dyn Spawn> = Once::new();
+
+pub struct Services {
+    memory: Memory,
+    timer: Timer,
+    devices: Devices,
+    spawner: &'static dyn Spawn,
+    console: &'static dyn Console,
+}
+
+/// Where `print!` and the shell write.
+pub trait Console: Sync {
+    fn name(&self) -> &'static str;
+    /// Called with interrupts in any state; must not log.
+    fn print(&self, args: fmt::Arguments);
+}
+
+/// Starts and stops tasks on the running scheduler.
+pub trait Spawn: Sync {
+    /// Queues `task`, returning its id. Not for interrupt handlers, which
+    /// could find the queue locked.
+    fn spawn_task(&self, task: PriorityTask) -> TaskId;
+    fn kill(&self, task_id: TaskId);
+}
+
+impl dyn Spawn {
+    /// Queues `future` as a task, returning its id.
+    pub fn spawn(&self, priority: Priority, future: impl Future<Output = ()> + 'static) -> TaskId {
+        self.spawn_task(PriorityTask::new(priority, future))
+    }
+}
+
+/// Uses `console` instead of the one `console=` picks. Only takes effect
+/// before assembly, and only the first time.
+pub fn register_console(console: &'static dyn Console) {
+    CONSOLE.call_once(|| console);
+}
+
+/// Uses `spawner` instead of the scheduler's queue; as `register_console`.
+pub fn register_spawner(spawner: &'static dyn Spawn) {
+    SPAWN.call_once(|| spawner);
+}
+
+/// The services; panics before `init_from` has assembled them.
+pub fn services() -> &'static Services {
+    SERVICES.get()
+}
+
+/// Whether `services()` may be called yet, for code that also runs early.
+pub fn is_ready() -> bool {
+    SERVICES.is_initialized()
+}
+
+/// Gathers the services, once memory, ACPI, devices and the timer are up.
+pub(crate) fn assemble() {
+    assert!(time::is_running(), "kernel services need the timer");
+    let console = *CONSOLE.call_once(|| match cmdline::options().console {
+        ConsoleKind::Vga => &VGA_CONSOLE,
+        ConsoleKind::Serial => &SERIAL_CONSOLE,
+    });
+    if cmdline::options().console == ConsoleKind::Serial {
+        // headless: nobody is looking at the screen
+        let _ = logs::remove_sink("vga");
+    }
+    SERVICES.init(Services {
+        memory: Memory {
+            mapper: MAPPER.get(),
+            frames: FRAME_ALLOCATOR.get(),
+        },
+        timer: Timer { _private: () },
+        devices: Devices { _private: () },
+        spawner: *SPAWN.call_once(|| &SPAWNER),
+        console,
+    });
+}
+
+impl Services {
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn timer(&self) -> &Timer {
+        &self.timer
+    }
+
+    pub fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    pub fn spawner(&self) -> &'static dyn Spawn {
+        self.spawner
+    }
+
+    pub fn console(&self) -> &'static dyn Console {
+        self.console
+    }
+}
+
+/// Physical frames and the kernel's page tables.
+pub struct Memory {
+    mapper: &'static Mutex<OffsetPageTable<'static>>,
+    frames: &'static Mutex<BootInfoFrameAllocator>,
+}
+
+impl Memory {
+    pub fn mapper(&self) -> &'static Mutex<OffsetPageTable<'static>> {
+        self.mapper
+    }
+
+    pub fn frames(&self) -> &'static Mutex<BootInfoFrameAllocator> {
+        self.frames
+    }
+
+    pub fn phys_to_virt(&self, addr: PhysAddr) -> Result<VirtAddr, KernelError> {
+        memory::phys_to_virt(addr)
+    }
+
+    /// A zeroed frame for device DMA, by physical and virtual address.
+    pub fn alloc_dma_frame(&self) -> Option<(PhysAddr, VirtAddr)> {
+        memory::alloc_dma_frame()
+    }
+
+    /// `count` zeroed frames in one physically contiguous block.
+    pub fn alloc_dma_frames(&self, count: usize) -> Option<(PhysAddr, VirtAddr)> {
+        memory::alloc_dma_frames(count)
+    }
+
+    /// Maps `size` bytes of device registers at `phys` uncached.
+    pub fn map_mmio(&self, phys: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+        memory::map_mmio(phys, size)
+    }
+}
+
+/// The tick clock and sleeping.
+pub struct Timer {
+    _private: (),
+}
+
+impl Timer {
+    pub fn ticks(&self) -> u64 {
+        time::ticks()
+    }
+
+    pub fn uptime_ms(&self) -> u64 {
+        time::uptime_ms()
+    }
+
+    pub fn sleep(&self, ms: u64) -> Sleep {
+        time::sleep(ms)
+    }
+}
+
+/// The devices found on the buses.
+pub struct Devices {
+    _private: (),
+}
+
+impl Devices {
+    pub fn pci(&self) -> Vec<PciDevice> {
+        pci::devices()
+    }
+
+    /// The devices a driver took over, with the driver.
+    pub fn bound(&self) -> &'static [(PciDevice, &'static dyn Driver)] {
+        driver::bound()
+    }
+
+    /// The first PCI function from `vendor_id` with one of `device_ids`.
+    pub fn find_pci(&self, vendor_id: u16, device_ids: &[u16]) -> Option<PciDevice> {
+        pci::find(vendor_id, device_ids)
+    }
+
+    /// Hands `device` to the network stack.
+    pub fn attach_net(&self, device: Box<dyn NetDevice>) -> Result<(), net::Error> {
+        net::attach(device)
+    }
+}
@@ -0,0 +1,238 @@
+//! Kernel objects: the things a task can hold a reference to, such as open
+//! files and devices, and later IPC endpoints and memory objects.
+//!
+//! `Kref` is a reference-counted pointer like `Arc`, with the counts kept
+//! in the object's allocation; `Kweak` refers to an object without keeping
+//! it alive. Every object has a kernel-wide id and a `Kind`, and its
+//! `destroy` hook runs when the last `Kref` goes, while weak references
+//! still find the allocation but can no longer upgrade. A `Kref` coerces
+//! to `Kref<dyn KObject>`, so one handle table can hold every kind.
+
+use crate::shell::ShellErr;
+use alloc::boxed::Box;
+use core::{
+    alloc::Layout,
+    fmt::{self, Write},
+    marker::{PhantomData, Unsize},
+    ops::{CoerceUnsized, Deref},
+    ptr::{self, NonNull},
+    sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Endpoint,
+    Memory,
+    Device,
+    File,
+}
+
+const KINDS: [Kind; 4] = [Kind::Endpoint, Kind::Memory, Kind::Device, Kind::File];
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Endpoint => "endpoint",
+            Kind::Memory => "memory",
+            Kind::Device => "device",
+            Kind::File => "file",
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+/// Live objects by kind.
+static LIVE: [AtomicUsize; KINDS.len()] = [ZERO; KINDS.len()];
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Live objects of each kind.
+pub fn live_counts() -> impl Iterator<Item = (Kind, usize)> {
+    KINDS
+        .iter()
+        .map(|&kind| (kind, LIVE[kind as usize].load(Ordering::Relaxed)))
+}
+
+pub fn kobjects_command(_args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    for (kind, count) in live_counts() {
+        writeln!(out, "{:<10} {}", kind.name(), count)?;
+    }
+    Ok(())
+}
+
+pub trait KObject: Send + Sync {
+    fn kind(&self) -> Kind;
+
+    /// Runs once, when the last strong reference is dropped and before the
+    /// object itself is.
+    fn destroy(&self) {}
+}
+
+struct Inner<T: ?Sized> {
+    strong: AtomicUsize,
+    /// Weak references, plus one held by all strong ones together.
+    weak: AtomicUsize,
+    id: u64,
+    value: T,
+}
+
+/// A strong reference to a kernel object.
+pub struct Kref<T: ?Sized + KObject> {
+    inner: NonNull<Inner<T>>,
+    _owns: PhantomData<Inner<T>>,
+}
+
+unsafe impl<T: ?Sized + KObject> Send for Kref<T> {}
+unsafe impl<T: ?Sized + KObject> Sync for Kref<T> {}
+
+impl<T: ?Sized + Unsize<U> + KObject, U: ?Sized + KObject> CoerceUnsized<Kref<U>> for Kref<T> {}
+
+impl<T: KObject> Kref<T> {
+    pub fn new(value: T) -> Self {
+        LIVE[value.kind() as usize].fetch_add(1, Ordering::Relaxed);
+        let inner = Box::new(Inner {
+            strong: AtomicUsize::new(1),
+            weak: AtomicUsize::new(1),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            value,
+        });
+        Kref {
+            inner: NonNull::from(Box::leak(inner)),
+            _owns: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized + KObject> Kref<T> {
+    fn inner(&self) -> &Inner<T> {
+        unsafe { self.inner.as_ref() }
+    }
+
+    pub fn id(this: &Self) -> u64 {
+        this.inner().id
+    }
+
+    pub fn downgrade(this: &Self) -> Kweak<T> {
+        this.inner().weak.fetch_add(1, Ordering::Relaxed);
+        Kweak {
+            inner: this.inner,
+            _owns: PhantomData,
+        }
+    }
+
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.load(Ordering::Relaxed)
+    }
+
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        ptr::eq(a.inner.as_ptr() as *const u8, b.inner.as_ptr() as *const u8)
+    }
+}
+
+impl<T: ?Sized + KObject> Clone for Kref<T> {
+    fn clone(&self) -> Self {
+        let old = self.inner().strong.fetch_add(1, Ordering::Relaxed);
+        assert!(old <= isize::MAX as usize, "Kref count overflow");
+        Kref {
+            inner: self.inner,
+            _owns: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized + KObject> Deref for Kref<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T: ?Sized + KObject> Drop for Kref<T> {
+    fn drop(&mut self) {
+        if self.inner().strong.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        fence(Ordering::Acquire);
+        let inner = self.inner();
+        let kind = inner.value.kind();
+        trace_event!(kobject_destroy, kind = kind as u8, id = inner.id);
+        inner.value.destroy();
+        unsafe { ptr::drop_in_place(&mut (*self.inner.as_ptr()).value) };
+        LIVE[kind as usize].fetch_sub(1, Ordering::Relaxed);
+        unsafe { release_weak(self.inner) };
+    }
+}
+
+impl<T: ?Sized + KObject + fmt::Debug> fmt::Debug for Kref<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A reference that does not keep its object alive.
+pub struct Kweak<T: ?Sized + KObject> {
+    inner: NonNull<Inner<T>>,
+    _owns: PhantomData<Inner<T>>,
+}
+
+unsafe impl<T: ?Sized + KObject> Send for Kweak<T> {}
+unsafe impl<T: ?Sized + KObject> Sync for Kweak<T> {}
+
+impl<T: ?Sized + Unsize<U> + KObject, U: ?Sized + KObject> CoerceUnsized<Kweak<U>> for Kweak<T> {}
+
+impl<T: ?Sized + KObject> Kweak<T> {
+    /// A strong reference, unless the object has been destroyed.
+    pub fn upgrade(&self) -> Option<Kref<T>> {
+        let strong = unsafe { &self.inner.as_ref().strong };
+        let mut count = strong.load(Ordering::Relaxed);
+        loop {
+            if count == 0 {
+                return None;
+            }
+            match strong.compare_exchange_weak(
+                count,
+                count + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(Kref {
+                        inner: self.inner,
+                        _owns: PhantomData,
+                    })
+                }
+                Err(current) => count = current,
+            }
+        }
+    }
+}
+
+impl<T: ?Sized + KObject> Clone for Kweak<T> {
+    fn clone(&self) -> Self {
+        unsafe { self.inner.as_ref() }
+            .weak
+            .fetch_add(1, Ordering::Relaxed);
+        Kweak {
+            inner: self.inner,
+            _owns: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized + KObject> Drop for Kweak<T> {
+    fn drop(&mut self) {
+        unsafe { release_weak(self.inner) };
+    }
+}
+
+/// Drops one weak count, freeing the allocation with the last. The value
+/// must already be gone by then.
+unsafe fn release_weak<T: ?Sized>(inner: NonNull<Inner<T>>) {
+    if inner.as_ref().weak.fetch_sub(1, Ordering::Release) != 1 {
+        return;
+    }
+    fence(Ordering::Acquire);
+    let layout = Layout::for_value(inner.as_ref());
+    alloc::alloc::dealloc(inner.as_ptr() as *mut u8, layout);
+}
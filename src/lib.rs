@@ -0,0 +1,157 @@
+#![no_std]
+#![cfg_attr(test, no_main)]
+#![feature(abi_x86_interrupt)]
+#![feature(default_alloc_error_handler)]
+#![feature(alloc_error_handler)]
+#![feature(const_mut_refs)]
+#![feature(asm)]
+#![feature(llvm_asm)]
+#![feature(wake_trait)]
+#![feature(try_reserve)]
+#![feature(naked_functions)]
+#![feature(get_mut_unchecked)]
+#![feature(coerce_unsized)]
+#![feature(unsize)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::debug::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+#[macro_use]
+extern crate log;
+extern crate alloc;
+
+use bootloader::BootInfo;
+use memory::BootInfoFrameAllocator;
+use x86_64::VirtAddr;
+
+#[macro_use]
+pub mod logs;
+#[macro_use]
+pub mod serial;
+#[macro_use]
+pub mod trace;
+#[macro_use]
+pub mod vga_buffer;
+pub mod acpi;
+pub mod allocators;
+pub mod bench;
+pub mod boot;
+pub mod compress;
+pub mod config;
+pub mod console;
+pub mod cpu;
+pub mod crypto;
+pub mod debug;
+pub mod device;
+pub mod error;
+pub mod fs;
+pub mod interrupts;
+pub mod kernel;
+pub mod kobject;
+pub mod memory;
+pub mod net;
+pub mod power;
+pub mod selftest;
+pub mod shell;
+pub mod sync;
+pub mod syscall;
+pub mod task;
+pub mod time;
+
+/// Brings up logging, CPU feature detection, paging, the heap and
+/// interrupts, which everything else (and every test binary) depends on,
+/// then hands them out through `kernel::services()`.
+pub fn init(boot_info: &'static BootInfo) {
+    init_from(boot::BootInfo::from(boot_info))
+}
+
+/// `init` for any loader, once its information is in the common form.
+pub fn init_from(boot_info: boot::BootInfo) {
+    let boot_info = boot::install(boot_info);
+    early_println!(
+        "boot: {:?}, {} memory regions, {} modules",
+        boot_info.loader,
+        boot_info.regions().len(),
+        boot_info.modules().len()
+    );
+    log_init();
+    boot::cmdline::init();
+    cpu::init();
+    memory_init(boot_info);
+    vga_buffer::mode::init();
+    if let Err(err) = acpi::init() {
+        warn!("no ACPI: {:?}", err);
+    }
+    device::init();
+    logs::register_commands();
+    memory::inspect::init();
+    task::scheduler::init();
+    interrupts::latency::init();
+    trace::init();
+    debug::canary::register_boot_stack();
+    logs::deferred::init();
+    interrupt_init();
+    kernel::assemble();
+}
+
+fn log_init() {
+    match debug::early_console::retire() {
+        0 => vga_buffer::WRITER.lock().clear_screen(),
+        row => vga_buffer::WRITER.lock().set_position(row, 0),
+    }
+    logs::init().expect("LOGGER FAILED TO LAUNCH!");
+    info!("Log Initialized!")
+}
+
+fn interrupt_init() {
+    interrupts::gdt::init();
+    interrupts::init();
+    memory::protect::init();
+    device::pic_8259::init();
+    time::init();
+    unsafe { interrupts::PICS.lock().initialize() };
+    serial::init();
+    if let Err(err) = power::buttons::init() {
+        info!("no ACPI buttons: {:?}", err);
+    }
+    if boot::cmdline::options().gdb {
+        debug::gdb::init();
+    }
+    x86_64::instructions::interrupts::enable();
+    info!("Interrupt Initialized!")
+}
+
+fn memory_init(boot_info: &'static boot::BootInfo) {
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(boot_info.regions()) };
+    allocators::init_heap(&mut mapper, &mut frame_allocator).expect("Heap initialization failed");
+    if let Some(frame) = frame_allocator.reserved_frame() {
+        logs::persist::init(phys_mem_offset + frame.start_address().as_u64());
+    }
+    memory::install(mapper, frame_allocator, phys_mem_offset);
+    info!("Memory Manager Initialized!");
+    // memory::print_l4_table(phys_mem_offset, mapper)
+}
+
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[cfg(test)]
+bootloader::entry_point!(test_kernel_main);
+
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    hlt_loop()
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    debug::test_panic_handler(info)
+}
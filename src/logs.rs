@@ -1,67 +1,350 @@
+use crate::{
+    interrupts::in_interrupt,
+    shell::{self, Args, ShellErr},
+    sync::Mutex,
+    task::TaskId,
+    time,
+};
+use alloc::{string::ToString, vec::Vec};
+use core::fmt::{self, Write};
 use log::{self, Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
 use x86_64::instructions::interrupts;
 
-pub const LOG_LEVEL: log::Level = log::Level::Debug;
+pub mod deferred;
+#[macro_use]
+pub mod fields;
+pub mod filter;
+pub mod limit;
+pub mod persist;
+pub mod ring;
+pub mod sink;
+pub mod syslog;
+
+use self::fields::Fields;
+use self::filter::FILTER;
+use self::limit::{Verdict, LIMITER};
+use self::ring::LogEntry;
+
+pub use self::ring::RING_SINK;
+pub use self::sink::{Sink, SERIAL_SINK, VGA_SINK};
+
+pub const RING_LOG_LEVEL: LevelFilter = LevelFilter::Debug;
+pub const SERIAL_LOG_LEVEL: LevelFilter = LevelFilter::Trace;
+pub const VGA_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+pub const NET_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+const MAX_SINKS: usize = 8;
 
 static LOGGER: Logger = Logger;
+static SINKS: Mutex<[Option<SinkEntry>; MAX_SINKS]> = Mutex::new([None; MAX_SINKS]);
+
+#[derive(Debug)]
+pub enum Error {
+    DuplicateSink,
+    InvalidDirective,
+    TooManyDirectives,
+    TooManySinks,
+    UnknownSink,
+}
+
+/// Per-record information captured once by the logger and rendered by
+/// every sink in the same way.
+#[derive(Clone, Copy)]
+pub struct Context {
+    pub uptime_ms: u64,
+    pub task: Option<TaskId>,
+    pub fields: Fields,
+}
+
+impl Context {
+    fn capture() -> Self {
+        Context {
+            uptime_ms: time::uptime_ms(),
+            task: TaskId::current(),
+            fields: Fields::new(),
+        }
+    }
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:>8}ms]", self.uptime_ms)?;
+        match self.task {
+            Some(task) => write!(f, " [task {}]", task),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SinkEntry {
+    sink: &'static dyn Sink,
+    filter: LevelFilter,
+}
 
 pub fn init() -> Result<(), SetLoggerError> {
     log::set_logger(&LOGGER)?;
-    log::set_max_level(LOGGER.filter());
+    register_sink(&SERIAL_SINK, SERIAL_LOG_LEVEL).expect("serial sink registered twice");
+    register_sink(&VGA_SINK, VGA_LOG_LEVEL).expect("vga sink registered twice");
+    register_sink(&RING_SINK, RING_LOG_LEVEL).expect("ring sink registered twice");
+    Ok(())
+}
+
+/// Adds a sink receiving every record at or above `filter`.
+pub fn register_sink(sink: &'static dyn Sink, filter: LevelFilter) -> Result<(), Error> {
+    interrupts::without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        if sinks.iter().flatten().any(|e| e.sink.name() == sink.name()) {
+            return Err(Error::DuplicateSink);
+        }
+        let slot = sinks
+            .iter_mut()
+            .find(|e| e.is_none())
+            .ok_or(Error::TooManySinks)?;
+        *slot = Some(SinkEntry { sink, filter });
+        update_max_level(&sinks);
+        Ok(())
+    })
+}
+
+pub fn remove_sink(name: &str) -> Result<(), Error> {
+    interrupts::without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        let slot = sinks
+            .iter_mut()
+            .find(|e| e.map_or(false, |e| e.sink.name() == name))
+            .ok_or(Error::UnknownSink)?;
+        *slot = None;
+        update_max_level(&sinks);
+        Ok(())
+    })
+}
+
+pub fn set_sink_level(name: &str, filter: LevelFilter) -> Result<(), Error> {
+    interrupts::without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        let entry = sinks
+            .iter_mut()
+            .flatten()
+            .find(|e| e.sink.name() == name)
+            .ok_or(Error::UnknownSink)?;
+        entry.filter = filter;
+        update_max_level(&sinks);
+        Ok(())
+    })
+}
+
+/// Sets the level for every module without a more specific override.
+pub fn set_level(filter: LevelFilter) {
+    interrupts::without_interrupts(|| {
+        FILTER.lock().set_global(filter);
+        update_max_level(&SINKS.lock());
+    })
+}
+
+/// Overrides the level for a module path prefix, e.g. `task::scheduler`.
+pub fn set_module_level(module: &str, filter: LevelFilter) -> Result<(), Error> {
+    interrupts::without_interrupts(|| {
+        FILTER.lock().set_module(module, filter)?;
+        update_max_level(&SINKS.lock());
+        Ok(())
+    })
+}
+
+pub fn clear_module_level(module: &str) {
+    interrupts::without_interrupts(|| {
+        FILTER.lock().clear_module(module);
+        update_max_level(&SINKS.lock());
+    })
+}
+
+/// Applies directives such as `info,task::scheduler=trace`.
+pub fn parse_directives(spec: &str) -> Result<(), Error> {
+    interrupts::without_interrupts(|| {
+        FILTER.lock().parse(spec)?;
+        update_max_level(&SINKS.lock());
+        Ok(())
+    })
+}
+
+/// Registers `dmesg` and `loglevel`; the logger starts before the heap
+/// the shell needs, so this runs later than `init`.
+pub fn register_commands() {
+    shell::register(
+        "dmesg",
+        "dmesg [-c] [level] [module]\nShows the log ring, at least as severe as level and from modules under module; -c clears it after.",
+        ring::dmesg_command,
+    );
+    shell::register(
+        "loglevel",
+        "loglevel [directives | -s <sink> <level> | -c <module>]\nShows the log levels, applies directives such as info,net=trace, sets a sink's level or drops a module's override.",
+        loglevel_command,
+    );
+}
+
+/// `loglevel [directives | -s <sink> <level> | -c <module>]`
+fn loglevel_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    if args.flag("-s") {
+        let sink = args.required("sink")?;
+        let level = args.parse("level")?;
+        args.finish()?;
+        return set_sink_level(sink, level).map_err(|_| ShellErr::new("no such sink"));
+    }
+    if args.flag("-c") {
+        let module = args.required("module")?;
+        args.finish()?;
+        clear_module_level(module);
+        return Ok(());
+    }
+    if let Some(spec) = args.word() {
+        args.finish()?;
+        return parse_directives(spec).map_err(|err| match err {
+            Error::TooManyDirectives => ShellErr::new("too many module overrides"),
+            _ => ShellErr::new("directives look like info,net=trace"),
+        });
+    }
+    let (filter, sinks) = interrupts::without_interrupts(|| {
+        let sinks: Vec<_> = SINKS.lock().iter().flatten().copied().collect();
+        (FILTER.lock().to_string(), sinks)
+    });
+    writeln!(out, "filter: {}", filter)?;
+    for entry in sinks {
+        writeln!(
+            out,
+            "sink {:<8} {}",
+            entry.sink.name(),
+            filter::level_name(entry.filter)
+        )?;
+    }
     Ok(())
 }
 
+/// The global max level is the most verbose level both a module filter
+/// and a sink accept, so records nobody wants are rejected by the `log`
+/// macros up front.
+fn update_max_level(sinks: &[Option<SinkEntry>; MAX_SINKS]) {
+    let sink_max = sinks
+        .iter()
+        .flatten()
+        .map(|e| e.filter)
+        .max()
+        .unwrap_or(LevelFilter::Off);
+    let filter_max = FILTER.lock().max_level();
+    log::set_max_level(sink_max.min(filter_max));
+}
+
+/// Hands a record to every sink whose filter accepts it.
+fn dispatch(record: &Record, context: &Context) {
+    interrupts::without_interrupts(|| {
+        let sinks = *SINKS.lock();
+        for entry in sinks.iter().flatten() {
+            if record.level() <= entry.filter {
+                entry.sink.write(record, context);
+            }
+        }
+    });
+}
+
+/// Replays a record captured in interrupt context.
+fn dispatch_entry(entry: &LogEntry) {
+    dispatch(
+        &Record::builder()
+            .level(entry.level())
+            .target(entry.module())
+            .module_path_static(Some(entry.module()))
+            .args(format_args!("{}", entry.message()))
+            .build(),
+        entry.context(),
+    );
+}
+
+/// Backs `kv_log!`: logs `args` with `fields` attached.
+pub fn log_with_fields(
+    level: Level,
+    module: &'static str,
+    file: &'static str,
+    line: u32,
+    args: fmt::Arguments,
+    fields: Fields,
+) {
+    let record = Record::builder()
+        .level(level)
+        .target(module)
+        .module_path_static(Some(module))
+        .file_static(Some(file))
+        .line(Some(line))
+        .args(args)
+        .build();
+    if LOGGER.enabled(record.metadata()) {
+        let mut context = Context::capture();
+        context.fields = fields;
+        LOGGER.log_with_context(&record, &context);
+    }
+}
+
 struct Logger;
 
 impl Logger {
-    fn filter(&self) -> LevelFilter {
-        LOG_LEVEL.to_level_filter()
+    fn log_with_context(&self, record: &Record, context: &Context) {
+        let verdict = interrupts::without_interrupts(|| LIMITER.lock().check(record));
+        match verdict {
+            Verdict::Drop => {}
+            Verdict::Pass {
+                repeated,
+                suppressed,
+            } => {
+                if let Some((level, count)) = repeated {
+                    self.emit(
+                        &Record::builder()
+                            .level(level)
+                            .target(module_path!())
+                            .args(format_args!("last message repeated {} times", count))
+                            .build(),
+                        context,
+                    );
+                }
+                if suppressed > 0 {
+                    self.emit(
+                        &Record::builder()
+                            .level(Level::Warn)
+                            .target(record.target())
+                            .args(format_args!(
+                                "{} messages from {}:{} suppressed",
+                                suppressed,
+                                record.file().unwrap_or("?"),
+                                record.line().unwrap_or(0)
+                            ))
+                            .build(),
+                        context,
+                    );
+                }
+                self.emit(record, context);
+            }
+        }
+    }
+
+    fn emit(&self, record: &Record, context: &Context) {
+        // interrupt handlers must never wait on the sink locks
+        if in_interrupt() {
+            deferred::push(record, context);
+        } else {
+            dispatch(record, context);
+        }
     }
 }
 
 impl Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= LOG_LEVEL
+        metadata.level() <= log::max_level()
+            && interrupts::without_interrupts(|| {
+                metadata.level() <= FILTER.lock().level_for(metadata.target())
+            })
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            // #[cfg(feature = "logging-serial")]
-            // {
-            // use core::fmt::Write;
-            // interrupts::without_interrupts(|| {
-            //     writeln!(
-            //         crate::serial::COM1.write(),
-            //         "[{}]: {}",
-            //         record.level(),
-            //         record.args()
-            //     )
-            //     .unwrap()
-            // });
-            // }
-            // #[cfg(feature = "logging-console")]
-            // {
-            use crate::vga_buffer::{Color, ColorCode, WRITER};
-            use core::fmt::Write;
-
-            let color = ColorCode::new(
-                match record.level() {
-                    Level::Error => Color::Red,
-                    Level::Warn => Color::Magenta,
-                    Level::Info => Color::Green,
-                    Level::Debug => Color::Cyan,
-                    Level::Trace => Color::White,
-                },
-                Color::Black,
-            );
-
-            interrupts::without_interrupts(|| {
-                let mut wtr = WRITER.lock();
-                write!(wtr.return_color().set_color(color), "{:>5}", record.level()).unwrap();
-
-                writeln!(wtr, ": {}", record.args()).unwrap();
-            });
-            // }
+            self.log_with_context(record, &Context::capture());
         }
     }
 
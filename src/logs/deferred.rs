@@ -0,0 +1,87 @@
+use super::{ring::LogEntry, Context};
+use crate::sync::Once;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context as TaskContext, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::task::AtomicWaker;
+use log::Record;
+
+const QUEUE_CAPACITY: usize = 64;
+
+static QUEUE: Once<ArrayQueue<LogEntry>> = Once::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Must be called once the heap is up; interrupt-context records logged
+/// before that are counted as dropped.
+pub fn init() {
+    assert!(
+        QUEUE.set(ArrayQueue::new(QUEUE_CAPACITY)).is_ok(),
+        "deferred log queue initialized twice"
+    );
+    crate::power::register_hook("logs", |_| flush());
+}
+
+/// Called by the logger from interrupt context
+///
+/// Must not block or allocate.
+pub(super) fn push(record: &Record, context: &Context) {
+    let queued = QUEUE.get().map_or(false, |queue| {
+        queue.push(LogEntry::from_record(record, context)).is_ok()
+    });
+    if queued {
+        WAKER.wake();
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Number of records lost because the queue was full or not yet set up.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Formats whatever is queued right now, for the shutdown path.
+pub fn flush() {
+    if let Some(queue) = QUEUE.get() {
+        while let Ok(entry) = queue.pop() {
+            super::dispatch_entry(&entry);
+        }
+    }
+}
+
+/// Formats queued interrupt-context records through the normal sinks.
+pub async fn drain_deferred() {
+    loop {
+        let entry = NextEntry.await;
+        super::dispatch_entry(&entry);
+    }
+}
+
+struct NextEntry;
+
+impl Future for NextEntry {
+    type Output = LogEntry;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<LogEntry> {
+        let queue = QUEUE.get().expect("deferred log queue not initialized");
+
+        // fast path
+        if let Ok(entry) = queue.pop() {
+            return Poll::Ready(entry);
+        }
+
+        WAKER.register(&cx.waker());
+        match queue.pop() {
+            Ok(entry) => {
+                WAKER.take();
+                Poll::Ready(entry)
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
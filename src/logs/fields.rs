@@ -0,0 +1,119 @@
+use core::fmt::{self, Write};
+
+pub const MAX_FIELDS: usize = 4;
+pub const VALUE_LEN: usize = 24;
+
+#[derive(Clone, Copy)]
+pub struct Field {
+    key: &'static str,
+    value: [u8; VALUE_LEN],
+    len: usize,
+}
+
+impl Field {
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+
+    pub fn value(&self) -> &str {
+        // only whole characters are ever copied in, see `write_str`
+        unsafe { core::str::from_utf8_unchecked(&self.value[..self.len]) }
+    }
+}
+
+/// Formats into the value, silently truncating at `VALUE_LEN`.
+impl fmt::Write for Field {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let width = c.len_utf8();
+            if self.len + width > VALUE_LEN {
+                break;
+            }
+            c.encode_utf8(&mut self.value[self.len..]);
+            self.len += width;
+        }
+        Ok(())
+    }
+}
+
+/// Key-value pairs attached to a record by `kv_log!`.
+#[derive(Clone, Copy)]
+pub struct Fields {
+    fields: [Option<Field>; MAX_FIELDS],
+}
+
+impl Fields {
+    pub const fn new() -> Self {
+        Fields {
+            fields: [None; MAX_FIELDS],
+        }
+    }
+
+    /// Records `key`, dropping it when all slots are taken.
+    pub fn push(&mut self, key: &'static str, value: &dyn fmt::Debug) {
+        if let Some(slot) = self.fields.iter_mut().find(|f| f.is_none()) {
+            let mut field = Field {
+                key,
+                value: [0; VALUE_LEN],
+                len: 0,
+            };
+            let _ = write!(field, "{:?}", value);
+            *slot = Some(field);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|f| f.key == key).map(Field::value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().flatten()
+    }
+}
+
+impl fmt::Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for field in self.iter() {
+            write!(f, " {}={}", field.key, field.value())?;
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __kv_level {
+    (error) => {
+        log::Level::Error
+    };
+    (warn) => {
+        log::Level::Warn
+    };
+    (info) => {
+        log::Level::Info
+    };
+    (debug) => {
+        log::Level::Debug
+    };
+    (trace) => {
+        log::Level::Trace
+    };
+}
+
+/// Logs a message with structured fields, e.g.
+/// `kv_log!(info, "page fault"; addr = cr2, code = ec)`.
+#[macro_export]
+macro_rules! kv_log {
+    ($level:ident, $($msg:expr),+; $($key:ident = $value:expr),+ $(,)?) => {{
+        let mut fields = $crate::logs::fields::Fields::new();
+        $( fields.push(stringify!($key), &$value); )+
+        $crate::logs::log_with_fields(
+            $crate::__kv_level!($level),
+            module_path!(),
+            file!(),
+            line!(),
+            format_args!($($msg),+),
+            fields,
+        );
+    }};
+}
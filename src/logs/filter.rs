@@ -0,0 +1,163 @@
+use super::Error;
+use crate::sync::Mutex;
+use core::fmt;
+use log::LevelFilter;
+
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Debug;
+
+const MAX_DIRECTIVES: usize = 16;
+const PREFIX_LEN: usize = 48;
+
+pub(super) static FILTER: Mutex<Filter> = Mutex::new(Filter::new());
+
+/// A `module::path=level` override.
+#[derive(Clone, Copy)]
+struct Directive {
+    prefix: [u8; PREFIX_LEN],
+    len: usize,
+    filter: LevelFilter,
+}
+
+impl Directive {
+    fn prefix(&self) -> &str {
+        // built from a `&str` in `set_module`, so always valid UTF-8
+        unsafe { core::str::from_utf8_unchecked(&self.prefix[..self.len]) }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let prefix = self.prefix();
+        path.starts_with(prefix)
+            && (path.len() == prefix.len() || path[prefix.len()..].starts_with("::"))
+    }
+}
+
+pub struct Filter {
+    global: LevelFilter,
+    directives: [Option<Directive>; MAX_DIRECTIVES],
+}
+
+impl Filter {
+    const fn new() -> Self {
+        Filter {
+            global: DEFAULT_LOG_LEVEL,
+            directives: [None; MAX_DIRECTIVES],
+        }
+    }
+
+    pub fn set_global(&mut self, filter: LevelFilter) {
+        self.global = filter;
+    }
+
+    pub fn set_module(&mut self, prefix: &str, filter: LevelFilter) -> Result<(), Error> {
+        let prefix = in_crate(prefix);
+        if prefix.is_empty() || prefix.len() > PREFIX_LEN {
+            return Err(Error::InvalidDirective);
+        }
+        if let Some(directive) = self
+            .directives
+            .iter_mut()
+            .flatten()
+            .find(|d| d.prefix() == prefix)
+        {
+            directive.filter = filter;
+            return Ok(());
+        }
+        let slot = self
+            .directives
+            .iter_mut()
+            .find(|d| d.is_none())
+            .ok_or(Error::TooManyDirectives)?;
+        let mut directive = Directive {
+            prefix: [0; PREFIX_LEN],
+            len: prefix.len(),
+            filter,
+        };
+        directive.prefix[..prefix.len()].copy_from_slice(prefix.as_bytes());
+        *slot = Some(directive);
+        Ok(())
+    }
+
+    pub fn clear_module(&mut self, prefix: &str) {
+        let prefix = in_crate(prefix);
+        for slot in self.directives.iter_mut() {
+            if slot.map_or(false, |d| d.prefix() == prefix) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// The level for `target`, from the longest matching directive.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let path = in_crate(target);
+        self.directives
+            .iter()
+            .flatten()
+            .filter(|d| d.matches(path))
+            .max_by_key(|d| d.len)
+            .map_or(self.global, |d| d.filter)
+    }
+
+    /// The most verbose level any target can currently log at.
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .map(|d| d.filter)
+            .fold(self.global, LevelFilter::max)
+    }
+
+    /// Applies a comma separated list such as `info,task::scheduler=trace`.
+    pub fn parse(&mut self, spec: &str) -> Result<(), Error> {
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(level), None) => self.set_global(parse_level(level)?),
+                (Some(module), Some(level)) => self.set_module(module, parse_level(level)?)?,
+                _ => return Err(Error::InvalidDirective),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The filter as directives `parse` would accept, global level first.
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", level_name(self.global))?;
+        for directive in self.directives.iter().flatten() {
+            write!(
+                f,
+                ",{}={}",
+                directive.prefix(),
+                level_name(directive.filter)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// How directives spell `filter`.
+pub fn level_name(filter: LevelFilter) -> &'static str {
+    match filter {
+        LevelFilter::Off => "off",
+        LevelFilter::Error => "error",
+        LevelFilter::Warn => "warn",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug => "debug",
+        LevelFilter::Trace => "trace",
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, Error> {
+    level.trim().parse().map_err(|_| Error::InvalidDirective)
+}
+
+fn crate_name() -> &'static str {
+    module_path!().split("::").next().unwrap_or("")
+}
+
+/// `path` without this crate's name in front, as directives are kept.
+fn in_crate(path: &str) -> &str {
+    path.trim_start_matches(crate_name())
+        .trim_start_matches("::")
+}
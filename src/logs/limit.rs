@@ -0,0 +1,147 @@
+use crate::{sync::Mutex, time};
+use core::fmt::{self, Write};
+use log::{Level, Record};
+
+/// Records a single call site may emit back to back.
+pub const BURST: u32 = 10;
+/// Tokens returned to every call site per second.
+pub const RATE_PER_SEC: u64 = 10;
+
+const MAX_CALL_SITES: usize = 32;
+
+pub(super) static LIMITER: Mutex<Limiter> = Mutex::new(Limiter::new());
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    site: (usize, u32),
+    tokens: u32,
+    refilled_ms: u64,
+    suppressed: usize,
+}
+
+/// What the logger should do with a record.
+pub enum Verdict {
+    Drop,
+    Pass {
+        /// The previous message was folded this many times.
+        repeated: Option<(Level, usize)>,
+        /// This call site was throttled this many times since it last passed.
+        suppressed: usize,
+    },
+}
+
+pub struct Limiter {
+    buckets: [Option<Bucket>; MAX_CALL_SITES],
+    next_evict: usize,
+    last_hash: u64,
+    last_level: Level,
+    repeats: usize,
+}
+
+impl Limiter {
+    const fn new() -> Self {
+        Limiter {
+            buckets: [None; MAX_CALL_SITES],
+            next_evict: 0,
+            last_hash: 0,
+            last_level: Level::Trace,
+            repeats: 0,
+        }
+    }
+
+    pub fn check(&mut self, record: &Record) -> Verdict {
+        let hash = hash_record(record);
+        if hash == self.last_hash {
+            self.repeats += 1;
+            return Verdict::Drop;
+        }
+
+        let now = time::uptime_ms();
+        let bucket = self.bucket(call_site(record), now);
+        let refill = (now - bucket.refilled_ms) * RATE_PER_SEC / 1000;
+        if refill > 0 {
+            bucket.tokens = (bucket.tokens + refill as u32).min(BURST);
+            bucket.refilled_ms = now;
+        }
+        if bucket.tokens == 0 {
+            bucket.suppressed += 1;
+            return Verdict::Drop;
+        }
+        bucket.tokens -= 1;
+        let suppressed = core::mem::replace(&mut bucket.suppressed, 0);
+
+        let repeated = match core::mem::replace(&mut self.repeats, 0) {
+            0 => None,
+            n => Some((self.last_level, n)),
+        };
+        self.last_hash = hash;
+        self.last_level = record.level();
+        Verdict::Pass {
+            repeated,
+            suppressed,
+        }
+    }
+
+    fn bucket(&mut self, site: (usize, u32), now: u64) -> &mut Bucket {
+        let index = match self
+            .buckets
+            .iter()
+            .position(|b| b.map_or(false, |b| b.site == site))
+        {
+            Some(index) => index,
+            None => {
+                let index = self
+                    .buckets
+                    .iter()
+                    .position(Option::is_none)
+                    .unwrap_or_else(|| {
+                        self.next_evict = (self.next_evict + 1) % MAX_CALL_SITES;
+                        self.next_evict
+                    });
+                self.buckets[index] = Some(Bucket {
+                    site,
+                    tokens: BURST,
+                    refilled_ms: now,
+                    suppressed: 0,
+                });
+                index
+            }
+        };
+        self.buckets[index].as_mut().unwrap()
+    }
+}
+
+fn call_site(record: &Record) -> (usize, u32) {
+    (
+        record.file().map_or(0, |f| f.as_ptr() as usize),
+        record.line().unwrap_or(0),
+    )
+}
+
+/// FNV-1a over the level, target, call site and formatted message, so
+/// the same text from two places is not folded into one.
+fn hash_record(record: &Record) -> u64 {
+    struct Hasher(u64);
+
+    impl fmt::Write for Hasher {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for byte in s.bytes() {
+                self.0 ^= byte as u64;
+                self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+            }
+            Ok(())
+        }
+    }
+
+    let mut hasher = Hasher(0xcbf2_9ce4_8422_2325);
+    let _ = write!(
+        hasher,
+        "{}{}{}:{}{}",
+        record.level(),
+        record.target(),
+        record.file().unwrap_or(""),
+        record.line().unwrap_or(0),
+        record.args()
+    );
+    hasher.0
+}
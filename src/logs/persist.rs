@@ -0,0 +1,127 @@
+use super::ring::{LogEntry, RING};
+use core::{
+    fmt::{self, Write},
+    mem::size_of,
+    panic::PanicInfo,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+use x86_64::VirtAddr;
+
+const MAGIC: u64 = 0x5041_4e49_4352_4543; // "PANICREC"
+const LINE_LEN: usize = 160;
+
+/// Log lines kept alongside the panic message.
+pub const SAVED_LINES: usize = 16;
+
+static RECORD: AtomicPtr<PanicRecord> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Laid out in a physical frame the frame allocator never hands out.
+#[repr(C)]
+struct PanicRecord {
+    magic: u64,
+    checksum: u64,
+    message: Line,
+    lines: [Line; SAVED_LINES],
+    line_count: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Line {
+    bytes: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_str(&self) -> &str {
+        let len = self.len.min(LINE_LEN);
+        core::str::from_utf8(&self.bytes[..len]).unwrap_or("<corrupt>")
+    }
+}
+
+/// Appends, silently truncating at `LINE_LEN`.
+impl fmt::Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let width = c.len_utf8();
+            if self.len + width > LINE_LEN {
+                break;
+            }
+            c.encode_utf8(&mut self.bytes[self.len..]);
+            self.len += width;
+        }
+        Ok(())
+    }
+}
+
+impl PanicRecord {
+    fn compute_checksum(&self) -> u64 {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                (self as *const Self as *const u8).add(2 * size_of::<u64>()),
+                size_of::<Self>() - 2 * size_of::<u64>(),
+            )
+        };
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash: u64, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100_0000_01b3)
+        })
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC
+            && self.line_count <= SAVED_LINES
+            && self.checksum == self.compute_checksum()
+    }
+}
+
+/// Points the panic record at `addr` (the virtual address of the reserved
+/// frame) and reports a panic left there by the previous boot.
+pub fn init(addr: VirtAddr) {
+    assert!(size_of::<PanicRecord>() <= 4096);
+    let record = addr.as_mut_ptr::<PanicRecord>();
+    let previous = unsafe { &mut *record };
+
+    if previous.is_valid() {
+        warn!("previous boot panicked with: {}", previous.message.as_str());
+        for line in previous.lines[..previous.line_count].iter() {
+            warn!("  | {}", line.as_str());
+        }
+    }
+    previous.magic = 0;
+    RECORD.store(record, Ordering::SeqCst);
+}
+
+/// Stores the panic message and the tail of the log ring. Called from the
+/// panic handler, so it only ever try-locks.
+pub fn save(info: &PanicInfo) {
+    let record = match unsafe { RECORD.load(Ordering::SeqCst).as_mut() } {
+        Some(record) => record,
+        None => return,
+    };
+
+    record.magic = 0;
+    record.message.clear();
+    let _ = write!(record.message, "{}", info);
+    record.line_count = 0;
+
+    if let Some(ring) = RING.try_lock() {
+        let skip = ring.len().saturating_sub(SAVED_LINES);
+        for entry in ring.iter().skip(skip) {
+            save_line(record, entry);
+        }
+    }
+
+    record.checksum = record.compute_checksum();
+    record.magic = MAGIC;
+}
+
+fn save_line(record: &mut PanicRecord, entry: &LogEntry) {
+    let line = &mut record.lines[record.line_count];
+    line.clear();
+    let _ = write!(line, "{}", entry);
+    record.line_count += 1;
+}
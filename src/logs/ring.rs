@@ -0,0 +1,210 @@
+use super::{Context, Sink};
+use crate::shell::{Args, ShellErr};
+use crate::sync::Mutex;
+use crate::task::TaskId;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use log::{Level, Record};
+
+/// Number of records retained before the oldest are overwritten.
+pub const RING_CAPACITY: usize = 256;
+pub const MESSAGE_LEN: usize = 120;
+
+pub static RING: Mutex<LogRing> = Mutex::new(LogRing::new());
+pub static RING_SINK: RingSink = RingSink;
+
+#[derive(Clone, Copy)]
+pub struct LogEntry {
+    level: Level,
+    context: Context,
+    module: &'static str,
+    message: [u8; MESSAGE_LEN],
+    len: usize,
+}
+
+impl LogEntry {
+    pub fn from_record(record: &Record, context: &Context) -> Self {
+        let mut entry = LogEntry {
+            level: record.level(),
+            context: *context,
+            module: record.module_path_static().unwrap_or("?"),
+            message: [0; MESSAGE_LEN],
+            len: 0,
+        };
+        let _ = write!(entry, "{}", record.args());
+        entry
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn uptime_ms(&self) -> u64 {
+        self.context.uptime_ms
+    }
+
+    pub fn task(&self) -> Option<TaskId> {
+        self.context.task
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.context.fields.get(key)
+    }
+
+    pub fn module(&self) -> &'static str {
+        self.module
+    }
+
+    pub fn message(&self) -> &str {
+        // only whole characters are ever copied in, see `write_str`
+        unsafe { core::str::from_utf8_unchecked(&self.message[..self.len]) }
+    }
+}
+
+/// Appends to the message, silently truncating at `MESSAGE_LEN`.
+impl fmt::Write for LogEntry {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let width = c.len_utf8();
+            if self.len + width > MESSAGE_LEN {
+                break;
+            }
+            c.encode_utf8(&mut self.message[self.len..]);
+            self.len += width;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:>5} {}: {}{}",
+            self.context,
+            self.level,
+            self.module,
+            self.message(),
+            self.context.fields
+        )
+    }
+}
+
+pub struct LogRing {
+    entries: [Option<LogEntry>; RING_CAPACITY],
+    next: usize,
+    len: usize,
+}
+
+impl LogRing {
+    pub const fn new() -> Self {
+        LogRing {
+            entries: [None; RING_CAPACITY],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, entry: LogEntry) {
+        self.entries[self.next] = Some(entry);
+        self.next = (self.next + 1) % RING_CAPACITY;
+        self.len = (self.len + 1).min(RING_CAPACITY);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+
+    /// Iterates over the retained records, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &LogEntry> {
+        let start = (self.next + RING_CAPACITY - self.len) % RING_CAPACITY;
+        (0..self.len).filter_map(move |i| self.entries[(start + i) % RING_CAPACITY].as_ref())
+    }
+
+    pub fn dump(&self, w: &mut impl Write) -> fmt::Result {
+        for entry in self.iter() {
+            writeln!(w, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct RingSink;
+
+impl Sink for RingSink {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+
+    fn write(&self, record: &Record, context: &Context) {
+        RING.lock().push(LogEntry::from_record(record, context));
+    }
+}
+
+/// Calls `f` on every retained record, oldest first.
+pub fn for_each<F: FnMut(&LogEntry)>(mut f: F) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        RING.lock().iter().for_each(|e| f(e));
+    })
+}
+
+/// Calls `f` on every retained record whose field `key` equals `value`.
+pub fn for_each_with_field<F: FnMut(&LogEntry)>(key: &str, value: &str, mut f: F) {
+    for_each(|e| {
+        if e.field(key) == Some(value) {
+            f(e)
+        }
+    })
+}
+
+/// Writes the whole ring to the serial port. Only try-locks, so it is safe
+/// to call from the panic handler.
+pub fn dump_to_serial() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if let (Some(ring), Some(mut serial)) = (RING.try_lock(), crate::serial::SERIAL1.try_lock())
+        {
+            let _ = ring.dump(&mut *serial);
+        }
+    })
+}
+
+/// `dmesg [-c] [level] [module]`
+pub fn dmesg_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    let clear = args.flag("-c");
+    let mut level = Level::Trace;
+    let mut module = None;
+    while let Some(word) = args.word() {
+        match word.parse() {
+            Ok(parsed) => level = parsed,
+            Err(_) => module = Some(word),
+        }
+    }
+    args.finish()?;
+    let wanted = |entry: &LogEntry| {
+        let path = entry.module().splitn(2, "::").nth(1).unwrap_or("");
+        entry.level() <= level && module.map_or(true, |module| path.starts_with(module))
+    };
+    // copied out so the ring is not held, with interrupts off, while printing
+    let mut entries = Vec::new();
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut ring = RING.lock();
+        entries.extend(ring.iter().filter(|entry| wanted(entry)).copied());
+        if clear {
+            ring.clear();
+        }
+    });
+    for entry in entries.iter() {
+        writeln!(out, "{}", entry)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use super::Context;
+use crate::serial::SERIAL1;
+use crate::vga_buffer::{Color, ColorCode, WRITER};
+use core::fmt::Write;
+use log::{Level, Record};
+
+/// A destination for log records.
+///
+/// Sinks are called with interrupts disabled and must not log themselves.
+pub trait Sink: Sync {
+    fn name(&self) -> &'static str;
+    fn write(&self, record: &Record, context: &Context);
+}
+
+pub struct VgaSink;
+pub struct SerialSink;
+
+pub static VGA_SINK: VgaSink = VgaSink;
+pub static SERIAL_SINK: SerialSink = SerialSink;
+
+impl Sink for VgaSink {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn write(&self, record: &Record, context: &Context) {
+        let color = ColorCode::new(
+            match record.level() {
+                Level::Error => Color::Red,
+                Level::Warn => Color::Magenta,
+                Level::Info => Color::Green,
+                Level::Debug => Color::Cyan,
+                Level::Trace => Color::White,
+            },
+            Color::Black,
+        );
+
+        let mut wtr = WRITER.lock();
+        let cursor_visible = wtr.cursor_visible();
+        wtr.hide_cursor();
+        write!(wtr, "{} ", context).unwrap();
+        write!(wtr.return_color().set_color(color), "{:>5}", record.level()).unwrap();
+
+        writeln!(wtr, ": {}{}", record.args(), context.fields).unwrap();
+        if cursor_visible {
+            wtr.show_cursor();
+        }
+    }
+}
+
+impl Sink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write(&self, record: &Record, context: &Context) {
+        if crate::serial::is_raw() {
+            return;
+        }
+        if let Some(mut serial) = SERIAL1.try_lock() {
+            writeln!(
+                serial.buffered(),
+                "{} [{}] {}: {}{}",
+                context,
+                record.level(),
+                record.target(),
+                record.args(),
+                context.fields
+            )
+            .unwrap();
+        }
+    }
+}
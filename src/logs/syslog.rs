@@ -0,0 +1,169 @@
+use super::{ring::LogEntry, Context, Sink};
+use crate::{
+    net::{self, UdpSocket},
+    sync::Once,
+    time,
+};
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::Write,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context as TaskContext, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::task::AtomicWaker;
+use log::{Level, Record};
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+pub const SYSLOG_PORT: u16 = 514;
+
+/// The host as seen from QEMU's user-mode network.
+pub const DEFAULT_COLLECTOR: IpEndpoint = IpEndpoint {
+    addr: IpAddress::Ipv4(Ipv4Address([10, 0, 2, 2])),
+    port: SYSLOG_PORT,
+};
+
+const QUEUE_CAPACITY: usize = 128;
+const HOSTNAME: &str = "microkernel";
+
+static QUEUE: Once<ArrayQueue<LogEntry>> = Once::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+pub static SYSLOG_SINK: SyslogSink = SyslogSink;
+
+/// Queues records for `syslog_task`, which sends them as UDP datagrams.
+pub struct SyslogSink;
+
+/// Records from the network stack would be sent by the stack they describe.
+fn is_net_module(module: &str) -> bool {
+    module.split("::").nth(1) == Some("net")
+}
+
+impl Sink for SyslogSink {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn write(&self, record: &Record, context: &Context) {
+        let entry = LogEntry::from_record(record, context);
+        if is_net_module(entry.module()) {
+            return;
+        }
+        let queued = QUEUE.get().map_or(false, |queue| queue.push(entry).is_ok());
+        if queued {
+            WAKER.wake();
+        } else {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Records lost because the queue was full.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// RFC 5424 framing with the kernel facility; there is no wall clock, so
+/// the timestamp is nil and the uptime leads the message instead.
+fn format(entry: &LogEntry, line: &mut String) {
+    line.clear();
+    let _ = write!(
+        line,
+        "<{}>1 - {} {} ",
+        severity(entry.level()),
+        HOSTNAME,
+        entry.module()
+    );
+    let _ = match entry.task() {
+        Some(task) => write!(line, "{}", task),
+        None => write!(line, "-"),
+    };
+    let _ = write!(
+        line,
+        " - - [{}ms] {}{}",
+        entry.uptime_ms(),
+        entry.message(),
+        entry.context().fields
+    );
+}
+
+/// Streams log records to `collector` once the interface has an address.
+///
+/// Records logged earlier are replayed from the log ring first.
+pub async fn syslog_task(collector: IpEndpoint) {
+    if !net::is_attached() || net::loopback::is_active() {
+        return;
+    }
+    while net::ipv4_address().is_none() {
+        time::sleep(500).await;
+    }
+    let socket = match UdpSocket::bind(0) {
+        Ok(socket) => socket,
+        Err(err) => {
+            warn!("syslog: {:?}", err);
+            return;
+        }
+    };
+    assert!(
+        QUEUE.set(ArrayQueue::new(QUEUE_CAPACITY)).is_ok(),
+        "syslog_task started twice"
+    );
+
+    // Tasks do not preempt each other and interrupt-context records go
+    // through the deferred queue, so nothing lands between these two steps.
+    let mut backlog = Vec::new();
+    super::ring::for_each(|entry| {
+        if entry.level() <= super::NET_LOG_LEVEL && !is_net_module(entry.module()) {
+            backlog.push(*entry);
+        }
+    });
+    if let Err(err) = super::register_sink(&SYSLOG_SINK, super::NET_LOG_LEVEL) {
+        warn!("syslog: {:?}", err);
+        return;
+    }
+    info!("syslog: sending to {}", collector);
+
+    let mut line = String::new();
+    for entry in backlog {
+        format(&entry, &mut line);
+        let _ = socket.send_to(line.as_bytes(), collector).await;
+    }
+    loop {
+        let entry = NextEntry.await;
+        format(&entry, &mut line);
+        let _ = socket.send_to(line.as_bytes(), collector).await;
+    }
+}
+
+struct NextEntry;
+
+impl Future for NextEntry {
+    type Output = LogEntry;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<LogEntry> {
+        let queue = QUEUE.get().expect("syslog queue not initialized");
+        if let Ok(entry) = queue.pop() {
+            return Poll::Ready(entry);
+        }
+        WAKER.register(&cx.waker());
+        match queue.pop() {
+            Ok(entry) => {
+                WAKER.take();
+                Poll::Ready(entry)
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
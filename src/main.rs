@@ -1,81 +1,140 @@
 #![no_std]
 #![no_main]
-#![feature(abi_x86_interrupt)]
-#![feature(default_alloc_error_handler)]
-#![feature(alloc_error_handler)]
-#![feature(const_mut_refs)]
-#![feature(asm)]
-#![feature(llvm_asm)]
-#![feature(wake_trait)]
-#![feature(naked_functions)]
-#![feature(get_mut_unchecked)]
+#![feature(custom_test_frameworks)]
+#![test_runner(microkernel::debug::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate microkernel;
 extern crate alloc;
 
-use core::panic::PanicInfo;
+use alloc::{sync::Arc, vec::Vec};
+use core::{fmt::Write, panic::PanicInfo};
 
 use bootloader::{entry_point, BootInfo};
-use device::keyboard::print_keypresses;
-use log::info;
-use memory::BootInfoFrameAllocator;
-use task::{
-    scheduler::{priority::PriorityScheduler, Scheduler},
-    PriorityTask,
+use microkernel::{
+    boot::cmdline::{self, SchedulerKind},
+    cpu, debug, device, fs, interrupts, logs, net, power, serial, shell,
+    task::{
+        self,
+        scheduler::{
+            budget::BudgetScheduler, priority::PriorityScheduler, round_robin::RoundRobinScheduler,
+            Scheduler,
+        },
+        supervisor, PriorityTask,
+    },
 };
-use x86_64::VirtAddr;
-
-mod logs;
-#[macro_use]
-mod serial;
-#[macro_use]
-mod vga_buffer;
-mod allocators;
-mod device;
-mod interrupts;
-mod memory;
-mod task;
 
 entry_point!(kernel_main);
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
-    info!("KERNEL STARTING...");
-    log_init();
-    memory_init(boot_info);
-    interrupt_init();
+    early_println!("KERNEL STARTING...");
+    microkernel::init(boot_info);
+    fs_init();
     interrupts::clear_mask();
-    let mut executor = PriorityScheduler::new();
-    executor.spawn(PriorityTask::new(task::Priority::High, print_keypresses()));
-    executor.spawn(PriorityTask::new(task::Priority::Low, task_1()));
-    executor.spawn(PriorityTask::new(task::Priority::High, task_2()));
-    executor.spawn(PriorityTask::new(task::Priority::High, task_3()));
-    executor.run();
-    hlt_loop()
+    net_init();
+    if let Err(err) = debug::watchdog::init() {
+        info!("no NMI watchdog: {:?}", err);
+    }
+
+    #[cfg(test)]
+    test_main();
+
+    if cmdline::options().selftest {
+        microkernel::selftest::run();
+    }
+
+    if cfg!(feature = "bench") || cmdline::options().bench {
+        microkernel::bench::run_suite();
+    }
+
+    let mut tasks = alloc::vec![
+        PriorityTask::new(task::Priority::High, power::power_task()),
+        PriorityTask::new(task::Priority::High, power::buttons::button_task()),
+        PriorityTask::new(task::Priority::High, supervisor::supervisor_task()),
+        PriorityTask::new(task::Priority::High, shell::console_task()),
+        PriorityTask::new(task::Priority::High, serial::echo_serial_input()),
+        PriorityTask::new(task::Priority::Low, logs::deferred::drain_deferred()),
+        PriorityTask::new(task::Priority::Low, cpu::thermal::thermal_task()),
+        PriorityTask::new(task::Priority::Low, serial::report::report_task()),
+        PriorityTask::new(task::Priority::Medium, net::poll_task()),
+        PriorityTask::new(task::Priority::Low, net::dhcp::dhcp_task(static_ip())),
+        PriorityTask::new(
+            task::Priority::Low,
+            logs::syslog::syslog_task(logs::syslog::DEFAULT_COLLECTOR),
+        ),
+        PriorityTask::new(task::Priority::Low, net::selftest::selftest_task()),
+        PriorityTask::new(task::Priority::Low, shell::script_task()),
+        PriorityTask::new(task::Priority::Low, task_1()),
+        PriorityTask::new(task::Priority::High, task_2()),
+        PriorityTask::new(task::Priority::High, task_3()),
+    ];
+    // the shell has no login, so it goes on the network only when asked
+    if cmdline::options().telnet {
+        tasks.push(PriorityTask::new(
+            task::Priority::Medium,
+            net::remote_console(net::remote_console::REMOTE_CONSOLE_PORT),
+        ));
+    }
+    supervisor::watch(
+        "shell",
+        supervisor::CHECK_IN_MS,
+        Some(|| PriorityTask::new(task::Priority::High, shell::console_task())),
+    );
+    supervisor::watch(
+        "net",
+        supervisor::CHECK_IN_MS,
+        Some(|| PriorityTask::new(task::Priority::Medium, net::poll_task())),
+    );
+    match cmdline::options().scheduler {
+        SchedulerKind::Priority => run(PriorityScheduler::new(), tasks),
+        SchedulerKind::RoundRobin => run(RoundRobinScheduler::new(), tasks),
+        SchedulerKind::Budget => run(BudgetScheduler::new(), tasks),
+    }
 }
 
-fn log_init() {
-    vga_buffer::WRITER.lock().clear_screen();
-    logs::init().expect("LOGGER FAILED TO LAUNCH!");
-    info!("Log Initialized!")
+fn run(mut executor: impl Scheduler<PriorityTask>, tasks: Vec<PriorityTask>) -> ! {
+    for task in tasks {
+        if let Err(err) = executor.spawn(task) {
+            error!("failed to spawn a task: {:?}", err);
+        }
+    }
+    executor.run()
+}
+
+fn fs_init() {
+    fs::devfs::init();
+    let mounted = match fs::initramfs::Initramfs::load() {
+        Ok(initramfs) => fs::mount_root(Arc::new(initramfs)),
+        Err(err) => {
+            error!("initramfs unusable, using an empty tmpfs: {:?}", err);
+            fs::mount_root(Arc::new(fs::tmpfs::Tmpfs::new())).and_then(|_| fs::mkdir("/dev"))
+        }
+    };
+    if let Err(err) = mounted.and_then(|_| fs::mount("/dev", Arc::new(fs::devfs::Devfs))) {
+        error!("failed to set up the root filesystem: {:?}", err);
+    }
 }
 
-fn interrupt_init() {
-    interrupts::gdt::init();
-    interrupts::init();
-    device::pic_8259::init();
-    unsafe { interrupts::PICS.lock().initialize() };
-    x86_64::instructions::interrupts::enable();
-    info!("Interrupt Initialized!")
+fn net_init() {
+    device::driver::probe();
+    if !net::is_attached() {
+        info!("no NIC, using loopback");
+        if let Err(err) = net::loopback::attach() {
+            error!("loopback: {:?}", err);
+        }
+    }
 }
 
-fn memory_init(boot_info: &'static BootInfo) {
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-    allocators::init_heap(&mut mapper, &mut frame_allocator).expect("Heap initialization failed");
-    info!("Memory Manager Initialized!");
-    // memory::print_l4_table(phys_mem_offset, mapper)
+/// What `ip=` and `gw=` ask for, for DHCP to fall back on.
+fn static_ip() -> Option<net::dhcp::StaticConfig> {
+    let options = cmdline::options();
+    options.ip.map(|address| net::dhcp::StaticConfig {
+        address,
+        gateway: options.gateway,
+    })
 }
 
 async fn task_1() {
@@ -98,14 +157,39 @@ unsafe fn page_fault() {
     *(0xdeadbeef as *mut u64) = 42
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{:#?}", info);
-    loop {}
+    if debug::early_console::is_active() {
+        early_println!("KERNEL PANIC: {}", info);
+        microkernel::hlt_loop();
+    }
+    let regs = debug::panic_screen::Registers::capture();
+    // CI wants every panic to fail the run
+    if cmdline::options().panic_recover
+        && !cfg!(feature = "qemu-exit")
+        && !cmdline::options().test_mode
+    {
+        task::recover::recover(info);
+    }
+    if cmdline::options().crash_dump {
+        debug::crash_dump::stream(info, &regs);
+    } else {
+        debug::backtrace::dump_to_serial();
+        logs::ring::dump_to_serial();
+    }
+    logs::persist::save(info);
+    if cfg!(feature = "qemu-exit") || cmdline::options().test_mode {
+        if let Some(mut serial) = serial::SERIAL1.try_lock() {
+            let _ = writeln!(serial, "KERNEL PANIC: {}", info);
+        }
+        debug::exit_qemu(false);
+    }
+    debug::panic_screen::show(info, &regs)
 }
 
-pub fn hlt_loop() -> ! {
-    loop {
-        x86_64::instructions::hlt();
-    }
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    microkernel::debug::test_panic_handler(info)
 }
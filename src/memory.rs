@@ -1,12 +1,34 @@
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+pub use crate::cpu::pat::CacheMode;
+use crate::{
+    boot::{Region, RegionKind},
+    debug::fault,
+    error::KernelError,
+    sync::{InitCell, Mutex},
+};
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::{
     structures::paging::{
-        FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB, Translate,
+        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTable,
+        PageTableFlags, PhysFrame, Size4KiB, Translate,
     },
     PhysAddr, VirtAddr,
 };
 
+pub mod inspect;
+pub mod kaslr;
 pub mod page;
+pub mod protect;
+pub mod user;
+
+/// Lowest address of the device memory window; `install` slides it up.
+const MMIO_BASE: u64 = 0x_5555_0000_0000;
+
+static PHYSICAL_MEMORY_OFFSET: InitCell<VirtAddr> = InitCell::new("physical memory offset");
+/// The kernel's page tables; lock before `FRAME_ALLOCATOR` when taking both.
+pub static MAPPER: InitCell<Mutex<OffsetPageTable<'static>>> = InitCell::new("page mapper");
+pub static FRAME_ALLOCATOR: InitCell<Mutex<BootInfoFrameAllocator>> =
+    InitCell::new("frame allocator");
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_BASE);
 
 pub unsafe fn init(physical_memory_offset: x86_64::VirtAddr) -> OffsetPageTable<'static> {
     let level_4_table = active_level_4_table(physical_memory_offset);
@@ -28,29 +50,65 @@ unsafe fn active_level_4_table(
 }
 
 pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
+    regions: &'static [Region],
     next: usize,
+    reserved: Option<PhysFrame>,
 }
 
 impl BootInfoFrameAllocator {
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
+    pub unsafe fn init(regions: &'static [Region]) -> Self {
+        let mut allocator = BootInfoFrameAllocator {
+            regions,
             next: 0,
-        }
+            reserved: None,
+        };
+        allocator.reserved = allocator.all_usable_frames().last();
+        allocator
     }
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
+    /// The highest usable frame, never handed out so its contents survive
+    /// a warm reset (see `logs::persist`).
+    pub fn reserved_frame(&self) -> Option<PhysFrame> {
+        self.reserved
+    }
+
+    fn all_usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        let regions = self.regions.iter();
+        let usable_regions = regions.filter(|r| r.kind == RegionKind::Usable);
+        let addr_ranges = usable_regions.map(|r| align_up(r.start)..r.end & !4095);
         let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
         frame_addresses.map(|addr| PhysFrame::containing_address(x86_64::PhysAddr::new(addr)))
     }
+
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        let reserved = self.reserved;
+        self.all_usable_frames()
+            .filter(move |frame| Some(*frame) != reserved)
+    }
+
+    /// `count` physically consecutive frames, returning the first. Frames
+    /// passed over to find the run are not handed out again.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        let mut first = self.allocate_frame()?;
+        let mut run = 1;
+        while run < count {
+            let frame = self.allocate_frame()?;
+            if frame == first + run as u64 {
+                run += 1;
+            } else {
+                first = frame;
+                run = 1;
+            }
+        }
+        Some(first)
+    }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if fault::should_fail(fault::Site::FrameAlloc) {
+            return None;
+        }
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
@@ -87,6 +145,109 @@ pub fn print_l4_table(physical_memory_offset: x86_64::VirtAddr, mapper: OffsetPa
     // }
 }
 
+/// Keeps the boot-time mapper and frame allocator so drivers can map
+/// memory after initialization.
+pub fn install(
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BootInfoFrameAllocator,
+    physical_memory_offset: VirtAddr,
+) {
+    PHYSICAL_MEMORY_OFFSET.init(physical_memory_offset);
+    MAPPER.init(Mutex::new(mapper));
+    FRAME_ALLOCATOR.init(Mutex::new(frame_allocator));
+    NEXT_MMIO.store(kaslr::slide(MMIO_BASE), Ordering::Relaxed);
+}
+
+/// Where physical memory is visible in the kernel's address space;
+/// `NotReady` before `install`.
+pub fn phys_to_virt(addr: PhysAddr) -> Result<VirtAddr, KernelError> {
+    let offset = PHYSICAL_MEMORY_OFFSET
+        .try_get()
+        .ok_or(KernelError::NotReady)?;
+    Ok(translate_physical_to_virtual(addr, *offset))
+}
+
+/// Allocates a zeroed frame for device DMA, returning its physical and
+/// virtual addresses.
+pub fn alloc_dma_frame() -> Option<(PhysAddr, VirtAddr)> {
+    let frame = FRAME_ALLOCATOR.try_get()?.lock().allocate_frame()?;
+    let phys = frame.start_address();
+    let virt = phys_to_virt(phys).ok()?;
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, 4096) };
+    Some((phys, virt))
+}
+
+/// Like `alloc_dma_frame`, for `count` frames the device sees as one
+/// physically contiguous block.
+pub fn alloc_dma_frames(count: usize) -> Option<(PhysAddr, VirtAddr)> {
+    let frame = FRAME_ALLOCATOR
+        .try_get()?
+        .lock()
+        .allocate_contiguous(count)?;
+    let phys = frame.start_address();
+    let virt = phys_to_virt(phys).ok()?;
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, count * 4096) };
+    Some((phys, virt))
+}
+
+/// Maps `size` bytes of device registers at `phys` uncached.
+pub fn map_mmio(phys: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    map_mmio_with(phys, size, CacheMode::Uncacheable)
+}
+
+/// Maps `size` bytes of device memory at `phys` with caching `mode`, e.g.
+/// write-combining for a framebuffer.
+pub fn map_mmio_with(
+    phys: PhysAddr,
+    size: u64,
+    mode: CacheMode,
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + size - 1u64);
+    let pages = (last.start_address() - first.start_address()) / 4096 + 1;
+    let start = VirtAddr::new(NEXT_MMIO.fetch_add(pages * 4096, Ordering::Relaxed));
+
+    let (mapper, frame_allocator) = match (MAPPER.try_get(), FRAME_ALLOCATOR.try_get()) {
+        (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+        _ => return Err(MapToError::FrameAllocationFailed),
+    };
+    let mut mapper = mapper.lock();
+    let mut frame_allocator = frame_allocator.lock();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | mode.flags();
+    // `map_to` takes bit 7 for a huge page and refuses it, so the PAT bit
+    // of write-combining goes in once the page is mapped
+    let map_flags = flags - PageTableFlags::HUGE_PAGE;
+    for (i, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+        let page = Page::containing_address(start + i as u64 * 4096);
+        unsafe {
+            mapper
+                .map_to(page, frame, map_flags, &mut *frame_allocator)?
+                .flush();
+            if flags != map_flags {
+                mapper
+                    .update_flags(page, flags)
+                    .map_err(|_| MapToError::ParentEntryHugePage)?
+                    .flush();
+            }
+        }
+    }
+    Ok(start + (phys - first.start_address()))
+}
+
+/// Whether `addr` is backed by a page. Only try-locks the mapper, so it is
+/// safe in fault and panic paths; reports `false` when the mapper is busy.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    MAPPER
+        .try_get()
+        .and_then(|mapper| mapper.try_lock())
+        .map_or(false, |mapper| mapper.translate_addr(addr).is_some())
+}
+
+/// Multiboot2 regions need not start on a page boundary.
+fn align_up(addr: u64) -> u64 {
+    (addr + 4095) & !4095
+}
+
 fn translate_physical_to_virtual(
     physical_address: PhysAddr,
     physical_memory_offset: x86_64::VirtAddr,
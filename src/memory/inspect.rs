@@ -0,0 +1,278 @@
+//! Shell commands to look at and change memory by virtual address, for
+//! driver bring-up: `hexdump`, `peek`, `poke` and `pte`.
+//!
+//! Every address is first looked up in the page tables, walked from CR3
+//! without taking the mapper, so an unmapped one is reported instead of
+//! faulting. Device memory is read like any other, side effects included.
+
+use super::phys_to_virt;
+use crate::shell::{self, Args, ShellErr};
+use core::{fmt::Write, ptr};
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{PageTable, PageTableFlags},
+    PhysAddr, VirtAddr,
+};
+
+/// Longest `hexdump`, in bytes.
+const MAX_DUMP: u64 = 4096;
+const ROW: u64 = 16;
+
+/// One page table entry on the way to an address.
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    level: u8,
+    index: u16,
+    flags: PageTableFlags,
+    addr: PhysAddr,
+}
+
+impl Step {
+    /// Whether the walk ends here, at a missing entry or a huge page.
+    fn is_last(&self) -> bool {
+        !self.flags.contains(PageTableFlags::PRESENT)
+            || self.level == 1
+            || self.flags.contains(PageTableFlags::HUGE_PAGE)
+    }
+}
+
+/// The entries mapping `addr`, from the level 4 table down to the page or
+/// the first entry that is not present. Does not allocate, so the monitor
+/// can use it with the heap locked.
+fn walk(addr: VirtAddr) -> [Option<Step>; 4] {
+    let indices = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut table = Cr3::read().0.start_address();
+    let mut steps = [None; 4];
+    for ((level, &index), slot) in (1..=4).rev().zip(indices.iter()).zip(steps.iter_mut()) {
+        let entries = match phys_to_virt(table) {
+            Ok(virt) => unsafe { &*virt.as_ptr::<PageTable>() },
+            Err(_) => break,
+        };
+        let entry = &entries[index];
+        let step = Step {
+            level,
+            index: u16::from(index),
+            flags: entry.flags(),
+            addr: entry.addr(),
+        };
+        *slot = Some(step);
+        if step.is_last() {
+            break;
+        }
+        table = step.addr;
+    }
+    steps
+}
+
+struct Mapping {
+    phys: PhysAddr,
+    writable: bool,
+}
+
+fn translate(addr: VirtAddr) -> Option<Mapping> {
+    let steps = walk(addr);
+    let last = steps.iter().flatten().last()?;
+    if !last.flags.contains(PageTableFlags::PRESENT) {
+        return None;
+    }
+    let page_size: u64 = match last.level {
+        1 => 4 << 10,
+        2 => 2 << 20,
+        _ => 1 << 30,
+    };
+    Some(Mapping {
+        phys: last.addr + (addr.as_u64() & (page_size - 1)),
+        writable: steps
+            .iter()
+            .flatten()
+            .all(|step| step.flags.contains(PageTableFlags::WRITABLE)),
+    })
+}
+
+/// Whether `addr` is backed by a page, from the page tables alone. Unlike
+/// `memory::is_mapped` it works while the mapper is locked.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    translate(addr).is_some()
+}
+
+/// Registers the commands; needs the heap and the physical memory mapping.
+pub fn init() {
+    shell::register(
+        "hexdump",
+        "hexdump <addr> [len]\nDumps up to 4096 bytes of memory; unmapped bytes show as ??.",
+        hexdump_command,
+    );
+    shell::register(
+        "peek",
+        "peek <addr> [1|2|4|8]\nReads one value of the given width in bytes, 8 by default.",
+        peek_command,
+    );
+    shell::register(
+        "poke",
+        "poke <addr> <value> [1|2|4|8]\nWrites one value of the given width; debug builds only.",
+        poke_command,
+    );
+    shell::register(
+        "pte",
+        "pte <addr>\nShows the page table entries that map an address.",
+        pte_command,
+    );
+}
+
+fn address(args: &mut Args) -> Result<VirtAddr, ShellErr> {
+    VirtAddr::try_new(args.number("address")?).map_err(|_| ShellErr::new("not a canonical address"))
+}
+
+fn width(args: &mut Args) -> Result<u64, ShellErr> {
+    match args.optional_number()? {
+        None => Ok(8),
+        Some(width @ 1) | Some(width @ 2) | Some(width @ 4) | Some(width @ 8) => Ok(width),
+        Some(_) => Err(ShellErr::new("width is 1, 2, 4 or 8")),
+    }
+}
+
+/// Finds the mapping for a `width`-byte access at `addr`, which must be
+/// aligned so it stays within one page.
+fn checked(addr: VirtAddr, width: u64) -> Result<Mapping, ShellErr> {
+    if addr.as_u64() % width != 0 {
+        return Err(ShellErr::new("address not aligned to the width"));
+    }
+    translate(addr).ok_or_else(|| ShellErr::new("address not mapped"))
+}
+
+/// `hexdump <addr> [len]`
+fn hexdump_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    let start = address(&mut args)?;
+    let len = args.optional_number()?.unwrap_or(64).min(MAX_DUMP);
+    args.finish()?;
+
+    // the page checked last and whether it is mapped
+    let mut page = None;
+    let mut mapped = |addr: u64| match page {
+        Some((base, mapped)) if base == addr & !0xfff => mapped,
+        _ => {
+            let is_mapped = VirtAddr::try_new(addr).map_or(false, |a| translate(a).is_some());
+            page = Some((addr & !0xfff, is_mapped));
+            is_mapped
+        }
+    };
+    let start = start.as_u64();
+    let end = start.saturating_add(len);
+    let mut row = start;
+    while row < end {
+        let mut bytes = [None; ROW as usize];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let addr = row + i as u64;
+            if addr < end && mapped(addr) {
+                *byte = Some(unsafe { ptr::read_volatile(addr as *const u8) });
+            }
+        }
+        write!(out, "{:#018x}:", row)?;
+        for (i, byte) in bytes.iter().enumerate() {
+            match byte {
+                Some(byte) => write!(out, " {:02x}", byte)?,
+                None if row + (i as u64) < end => write!(out, " ??")?,
+                None => write!(out, "   ")?,
+            }
+        }
+        write!(out, "  ")?;
+        for byte in bytes.iter() {
+            let c = match byte {
+                Some(byte) if byte.is_ascii_graphic() || *byte == b' ' => *byte as char,
+                Some(_) => '.',
+                None => ' ',
+            };
+            out.write_char(c)?;
+        }
+        writeln!(out)?;
+        row = row.saturating_add(ROW);
+    }
+    Ok(())
+}
+
+/// `peek <addr> [width]`
+fn peek_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    let addr = address(&mut args)?;
+    let width = width(&mut args)?;
+    args.finish()?;
+    let mapping = checked(addr, width)?;
+    let value = unsafe {
+        match width {
+            1 => u64::from(ptr::read_volatile(addr.as_ptr::<u8>())),
+            2 => u64::from(ptr::read_volatile(addr.as_ptr::<u16>())),
+            4 => u64::from(ptr::read_volatile(addr.as_ptr::<u32>())),
+            _ => ptr::read_volatile(addr.as_ptr::<u64>()),
+        }
+    };
+    writeln!(
+        out,
+        "{:#018x} (phys {:#x}) = {:#0w$x}",
+        addr.as_u64(),
+        mapping.phys.as_u64(),
+        value,
+        w = 2 + 2 * width as usize
+    )?;
+    Ok(())
+}
+
+/// `poke <addr> <value> [width]`
+fn poke_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    if !cfg!(debug_assertions) {
+        return Err(ShellErr::new("only available in debug builds"));
+    }
+    let mut args = Args::new(args);
+    let addr = address(&mut args)?;
+    let value = args.number("value")?;
+    let width = width(&mut args)?;
+    args.finish()?;
+    if width < 8 && value >> (8 * width) != 0 {
+        return Err(ShellErr::new("value does not fit the width"));
+    }
+    if !checked(addr, width)?.writable {
+        return Err(ShellErr::new("address mapped read-only"));
+    }
+    unsafe {
+        match width {
+            1 => ptr::write_volatile(addr.as_mut_ptr::<u8>(), value as u8),
+            2 => ptr::write_volatile(addr.as_mut_ptr::<u16>(), value as u16),
+            4 => ptr::write_volatile(addr.as_mut_ptr::<u32>(), value as u32),
+            _ => ptr::write_volatile(addr.as_mut_ptr::<u64>(), value),
+        }
+    }
+    writeln!(out, "{:#018x} <- {:#x}", addr.as_u64(), value)?;
+    Ok(())
+}
+
+/// `pte <addr>`
+fn pte_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    let addr = address(&mut args)?;
+    args.finish()?;
+    for step in walk(addr).iter().flatten() {
+        writeln!(
+            out,
+            "L{} [{:>3}] {:#014x} {:?}",
+            step.level,
+            step.index,
+            step.addr.as_u64(),
+            step.flags
+        )?;
+    }
+    match translate(addr) {
+        Some(mapping) => writeln!(
+            out,
+            "-> phys {:#x}{}",
+            mapping.phys.as_u64(),
+            if mapping.writable { "" } else { ", read-only" }
+        )?,
+        None => writeln!(out, "-> not mapped")?,
+    }
+    Ok(())
+}
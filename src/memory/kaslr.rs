@@ -0,0 +1,24 @@
+//! Randomized placement of the heap and the MMIO window, so a leaked
+//! pointer says little about where anything else is. Tasks are futures on
+//! the one kernel stack, so there are no per-task stacks to move yet.
+//!
+//! Build with the `no-kaslr` feature, or boot with `nokaslr`, to get the
+//! same addresses every boot.
+
+use crate::cpu::random;
+
+/// How far a region may move from its base.
+pub const SLIDE_SPAN: u64 = 64 * 1024 * 1024 * 1024;
+const PAGE_SIZE: u64 = 4096;
+
+pub fn enabled() -> bool {
+    !cfg!(feature = "no-kaslr") && !crate::boot::cmdline::options().nokaslr
+}
+
+/// `base` moved up by a random whole number of pages below `SLIDE_SPAN`.
+pub fn slide(base: u64) -> u64 {
+    if !enabled() {
+        return base;
+    }
+    base + random::next_u64() % (SLIDE_SPAN / PAGE_SIZE) * PAGE_SIZE
+}
@@ -1,3 +1,4 @@
+use crate::error::KernelError;
 use alloc::boxed::Box;
 use core::fmt::Display;
 
@@ -49,40 +50,43 @@ impl PTEntry {
         PhysAddr::new(self.0 & (((1 << 40) - 1) * FRAME_SIZE))
     }
 
-    pub unsafe fn next_pt(&self) -> &'static mut PageTable {
-        self.phys_addr().to_virt().unwrap().to_ref::<PageTable>()
+    /// The table this entry points to; `NotMapped` if it lies beyond the
+    /// physical memory mapped at `VIRT_OFFSET`.
+    pub unsafe fn next_pt(&self) -> Result<&'static mut PageTable, KernelError> {
+        let virt = self.phys_addr().to_virt().ok_or(KernelError::NotMapped)?;
+        Ok(virt.to_ref::<PageTable>())
     }
 }
 
 impl Display for PTEntry {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         if self.get_bit(BIT_PRESENT) {
-            let res = write!(f, "{}", self.phys_addr());
+            write!(f, "{}", self.phys_addr())?;
             if self.get_bit(BIT_WRITABLE) {
-                write!(f, " writable").unwrap();
+                write!(f, " writable")?;
             }
             if self.get_bit(BIT_USER) {
-                write!(f, " user").unwrap();
+                write!(f, " user")?;
             }
             if self.get_bit(BIT_WRITE_THROUGH) {
-                write!(f, " write_through").unwrap();
+                write!(f, " write_through")?;
             }
             if self.get_bit(BIT_NO_CACHE) {
-                write!(f, " no_cache").unwrap();
+                write!(f, " no_cache")?;
             }
             if self.get_bit(BIT_ACCESSED) {
-                write!(f, " accessed").unwrap();
+                write!(f, " accessed")?;
             }
             if self.get_bit(BIT_DIRTY) {
-                write!(f, " dirty").unwrap();
+                write!(f, " dirty")?;
             }
             if self.get_bit(BIT_HUGE) {
-                write!(f, " huge").unwrap();
+                write!(f, " huge")?;
             }
             if self.get_bit(BIT_GLOBAL) {
-                write!(f, " global").unwrap();
+                write!(f, " global")?;
             }
-            res
+            Ok(())
         } else {
             write!(f, "<not present>")
         }
@@ -96,39 +100,46 @@ pub unsafe fn get_page_table() -> &'static mut PageTable {
 }
 
 impl PageTable {
-    pub unsafe fn new() -> Box<PageTable> {
+    pub unsafe fn new() -> Result<Box<PageTable>, KernelError> {
         let mut pt = Box::new(PageTable {
             entries: [PTEntry(0); 512],
         }); // allocate the master PT struct
-        pt.entries[0].set_phys_addr(Self::alloc_page()); // allocate page for the first child PT
+        pt.entries[0].set_phys_addr(Self::alloc_page()?); // allocate page for the first child PT
         pt.entries[0].set_bit(BIT_PRESENT, true);
         pt.entries[0].set_bit(BIT_WRITABLE, true);
         pt.entries[0].set_bit(BIT_USER, true); // entry is present, writable and accessible by user
-        let mut pt0 = pt.entries[0].next_pt(); // get the child PT we just allocated
-        let cur_pt0 = get_page_table().entries[0].next_pt();
+        let mut pt0 = pt.entries[0].next_pt()?; // get the child PT we just allocated
+        let cur_pt0 = get_page_table().entries[0].next_pt()?;
         pt0.entries[3] = cur_pt0.entries[3].clone(); // copy over the entries 3, 4, 5, 6 from the equivalent
         pt0.entries[4] = cur_pt0.entries[4].clone(); // child PT that is currently in use
         pt0.entries[5] = cur_pt0.entries[5].clone(); // these correspond to the addresses our kernel uses
         pt0.entries[6] = cur_pt0.entries[6].clone(); // plus some more, so that the entire physical memory is mapped
-        pt
+        Ok(pt)
     }
 
-    pub unsafe fn phys_addr(&self) -> PhysAddr {
+    pub unsafe fn phys_addr(&self) -> Result<PhysAddr, KernelError> {
         let virt = VirtAddr::new(self as *const _ as u64);
-        virt.to_phys().unwrap().0
+        virt.to_phys()
+            .map(|(phys, _)| phys)
+            .ok_or(KernelError::NotMapped)
     }
 
-    pub unsafe fn enable(&self) {
-        let phys_addr = self.phys_addr().addr();
+    pub unsafe fn enable(&self) -> Result<(), KernelError> {
+        let phys_addr = self.phys_addr()?.addr();
         asm!("mov cr3, rax", in("rax") phys_addr);
+        Ok(())
     }
 
-    unsafe fn alloc_page() -> PhysAddr {
+    unsafe fn alloc_page() -> Result<PhysAddr, KernelError> {
         let frame: Box<EmptyFrame> = Box::new([0; FRAME_SIZE as usize]);
-        VirtAddr::new(Box::into_raw(frame) as u64)
-            .to_phys()
-            .unwrap()
-            .0
+        let frame = Box::into_raw(frame);
+        match VirtAddr::new(frame as u64).to_phys() {
+            Some((phys, _)) => Ok(phys),
+            None => {
+                drop(Box::from_raw(frame));
+                Err(KernelError::NotMapped)
+            }
+        }
     }
 
     pub fn get_entry(&mut self, i: usize) -> &mut PTEntry {
@@ -139,12 +150,12 @@ impl PageTable {
         virt: VirtAddr,
         phys: PhysAddr,
         create_options: u16,
-    ) -> &'static PTEntry {
+    ) -> Result<&'static PTEntry, KernelError> {
         let create_huge = (create_options & BIT_HUGE) != 0;
         let p4_off = (virt.addr() >> 39) & 0b1_1111_1111;
         let pte = self.get_entry(p4_off as usize);
         if !pte.get_bit(BIT_PRESENT) {
-            let new_frame = Self::alloc_page();
+            let new_frame = Self::alloc_page()?;
             pte.set_phys_addr(new_frame);
             pte.set_bit(BIT_PRESENT, true);
         }
@@ -155,9 +166,9 @@ impl PageTable {
             pte.set_bit(BIT_USER, true);
         }
         let p3_off = (virt.addr() >> 30) & 0b1_1111_1111;
-        let pte = pte.next_pt().get_entry(p3_off as usize);
+        let pte = pte.next_pt()?.get_entry(p3_off as usize);
         if !pte.get_bit(BIT_PRESENT) || pte.get_bit(BIT_HUGE) {
-            let new_frame = Self::alloc_page();
+            let new_frame = Self::alloc_page()?;
             pte.set_phys_addr(new_frame);
             pte.set_bit(BIT_PRESENT, true);
         }
@@ -168,14 +179,14 @@ impl PageTable {
             pte.set_bit(BIT_USER, true);
         }
         let p2_off = (virt.addr() >> 21) & 0b1_1111_1111;
-        let pte = pte.next_pt().get_entry(p2_off as usize);
+        let pte = pte.next_pt()?.get_entry(p2_off as usize);
         if !pte.get_bit(BIT_PRESENT) || pte.get_bit(BIT_HUGE) {
             if create_huge {
                 pte.set_phys_addr(phys);
                 pte.set_opts(create_options);
-                return pte;
+                return Ok(pte);
             } else {
-                let new_frame = Self::alloc_page();
+                let new_frame = Self::alloc_page()?;
                 pte.set_phys_addr(new_frame);
                 pte.set_bit(BIT_PRESENT, true);
             }
@@ -187,10 +198,10 @@ impl PageTable {
             pte.set_bit(BIT_USER, true);
         }
         let p1_off = (virt.addr() / FRAME_SIZE) & 0b1_1111_1111;
-        let pte = pte.next_pt().get_entry(p1_off as usize);
+        let pte = pte.next_pt()?.get_entry(p1_off as usize);
         pte.set_phys_addr(phys);
         pte.set_opts(create_options);
-        return pte;
+        Ok(pte)
     }
 }
 
@@ -219,7 +230,7 @@ impl VirtAddr {
             return None;
         }
         let p3_off = (self.0 >> 30) & 0b1_1111_1111;
-        let pte = pte.next_pt().get_entry(p3_off as usize);
+        let pte = pte.next_pt().ok()?.get_entry(p3_off as usize);
         if !pte.get_bit(BIT_PRESENT) {
             return None;
         } else if pte.get_bit(BIT_HUGE) {
@@ -227,7 +238,7 @@ impl VirtAddr {
             return Some((pte.phys_addr().offset(page_off), &*pte));
         }
         let p2_off = (self.0 >> 21) & 0b1_1111_1111;
-        let pte = pte.next_pt().get_entry(p2_off as usize);
+        let pte = pte.next_pt().ok()?.get_entry(p2_off as usize);
         if !pte.get_bit(BIT_PRESENT) {
             return None;
         } else if pte.get_bit(BIT_HUGE) {
@@ -235,7 +246,7 @@ impl VirtAddr {
             return Some((pte.phys_addr().offset(page_off), &*pte));
         }
         let p1_off = (self.0 / FRAME_SIZE) & 0b1_1111_1111;
-        let pte = pte.next_pt().get_entry(p1_off as usize);
+        let pte = pte.next_pt().ok()?.get_entry(p1_off as usize);
         if !pte.get_bit(BIT_PRESENT) {
             return None;
         } else {
@@ -0,0 +1,155 @@
+//! Write protection for memory that must not change after boot: the IDT,
+//! the GDT and the kernel text. A stray write to them now page faults at
+//! the culprit instead of corrupting a descriptor that only bites later.
+//!
+//! `.rodata` is already mapped read-only by the bootloader, which follows
+//! the ELF segment flags; text is checked anyway since patching it is the
+//! one thing a debugger legitimately does.
+
+use super::MAPPER;
+use core::ops::{Deref, DerefMut};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags},
+        model_specific::EferFlags,
+    },
+    structures::paging::{
+        mapper::{MappedFrame, TranslateResult},
+        Mapper, Page, PageTableFlags, Size4KiB, Translate,
+    },
+    VirtAddr,
+};
+
+const PAGE_SIZE: u64 = 4096;
+/// Largest kernel text `init` walks, in pages.
+const MAX_TEXT_PAGES: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    MapperNotInstalled,
+    NotMapped,
+    /// Part of a 2 MiB or 1 GiB page, which would take its neighbours along.
+    HugePage,
+}
+
+/// Gives `T` pages of its own, so protecting it does not freeze whatever
+/// the linker would otherwise have placed next to it.
+#[repr(C, align(4096))]
+pub struct PageAligned<T>(pub T);
+
+impl<T> PageAligned<T> {
+    pub fn addr(&self) -> VirtAddr {
+        VirtAddr::from_ptr(self)
+    }
+
+    pub fn size(&self) -> u64 {
+        core::mem::size_of::<Self>() as u64
+    }
+}
+
+impl<T> Deref for PageAligned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for PageAligned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// Makes the kernel text read-only and enforces write protection for the
+/// kernel itself. Run once the descriptor tables are loaded.
+pub fn init() {
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+    let text = protect_text();
+    let tables = crate::interrupts::protect_tables();
+    match (text, tables) {
+        (Ok(pages), Ok(())) => info!("protect: text ({} pages), IDT and GDT read-only", pages),
+        (text, tables) => error!("protect: text {:?}, tables {:?}", text, tables),
+    }
+}
+
+/// Clears the writable bit on every page of `[start, start + len)`.
+pub fn protect(start: VirtAddr, len: u64) -> Result<(), Error> {
+    update(start, len, |flags| flags - PageTableFlags::WRITABLE)
+}
+
+/// Undoes `protect`. Prefer `with_writable`, which cannot forget to redo it.
+pub fn unprotect(start: VirtAddr, len: u64) -> Result<(), Error> {
+    update(start, len, |flags| flags | PageTableFlags::WRITABLE)
+}
+
+/// Runs `f` with `[start, start + len)` writable, for legitimate late
+/// patching of protected memory.
+pub fn with_writable<R>(start: VirtAddr, len: u64, f: impl FnOnce() -> R) -> Result<R, Error> {
+    unprotect(start, len)?;
+    let result = f();
+    protect(start, len)?;
+    Ok(result)
+}
+
+fn update(
+    start: VirtAddr,
+    len: u64,
+    f: impl Fn(PageTableFlags) -> PageTableFlags,
+) -> Result<(), Error> {
+    let mut mapper = MAPPER.try_get().ok_or(Error::MapperNotInstalled)?.lock();
+    let first = Page::<Size4KiB>::containing_address(start);
+    let last = Page::<Size4KiB>::containing_address(start + len.max(1) - 1u64);
+    for page in Page::range_inclusive(first, last) {
+        let flags = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(_),
+                flags,
+                ..
+            } => flags,
+            TranslateResult::Mapped { .. } => return Err(Error::HugePage),
+            _ => return Err(Error::NotMapped),
+        };
+        unsafe {
+            mapper
+                .update_flags(page, f(flags))
+                .map_err(|_| Error::NotMapped)?
+                .flush()
+        };
+    }
+    Ok(())
+}
+
+/// Walks the executable pages around this function both ways and makes
+/// each read-only, returning how many there were.
+fn protect_text() -> Result<u64, Error> {
+    if !crate::cpu::msr::efer().contains(EferFlags::NO_EXECUTE_ENABLE) {
+        // every page looks executable, so text cannot be told from data
+        return Ok(0);
+    }
+    let here = Page::<Size4KiB>::containing_address(VirtAddr::new(protect_text as usize as u64));
+    let executable = |page: Page| match MAPPER
+        .try_get()
+        .map(|mapper| mapper.lock().translate(page.start_address()))
+    {
+        Some(TranslateResult::Mapped { flags, .. }) => !flags.contains(PageTableFlags::NO_EXECUTE),
+        _ => false,
+    };
+    let mut first = here;
+    let mut last = here;
+    for _ in 0..MAX_TEXT_PAGES {
+        if first.start_address().as_u64() < PAGE_SIZE || !executable(first - 1) {
+            break;
+        }
+        first -= 1;
+    }
+    for _ in 0..MAX_TEXT_PAGES {
+        if !executable(last + 1) {
+            break;
+        }
+        last += 1;
+    }
+    let pages = last - first + 1;
+    protect(first.start_address(), pages * PAGE_SIZE)?;
+    Ok(pages)
+}
@@ -0,0 +1,65 @@
+//! Copies between kernel buffers and user memory. The only code that
+//! should dereference user pointers, so SMAP catches everything else.
+
+use super::is_mapped;
+use crate::cpu::protection::{user_access_begin, user_access_end};
+use x86_64::VirtAddr;
+
+/// User space gets the top quarter of the lower half. The rest holds the
+/// kernel image, the physical memory map, the heap and the MMIO window.
+pub const USER_START: u64 = 0x0000_6000_0000_0000;
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Outside user space, wrapping around, or not mapped.
+    BadAddress,
+}
+
+pub fn is_user_address(addr: VirtAddr) -> bool {
+    (USER_START..USER_END).contains(&addr.as_u64())
+}
+
+/// Whether `len` bytes at `addr` lie in user space and are mapped. Empty
+/// ranges always pass.
+pub fn check_range(addr: VirtAddr, len: usize) -> Result<(), Error> {
+    if len == 0 {
+        return Ok(());
+    }
+    let start = addr.as_u64();
+    let end = start.checked_add(len as u64).ok_or(Error::BadAddress)?;
+    if start < USER_START || end > USER_END {
+        return Err(Error::BadAddress);
+    }
+    let mut page = start & !0xfff;
+    while page < end {
+        if !is_mapped(VirtAddr::new(page)) {
+            return Err(Error::BadAddress);
+        }
+        page += 4096;
+    }
+    Ok(())
+}
+
+/// Fills `dst` from user memory at `src`.
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<(), Error> {
+    check_range(src, dst.len())?;
+    user_access_begin();
+    unsafe { core::ptr::copy_nonoverlapping(src.as_ptr::<u8>(), dst.as_mut_ptr(), dst.len()) };
+    user_access_end();
+    Ok(())
+}
+
+/// Writes `src` to user memory at `dst`. The time page is mapped but
+/// read-only, so writes touching it fail here rather than fault.
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<(), Error> {
+    check_range(dst, src.len())?;
+    // the time page ends user space
+    if !src.is_empty() && dst.as_u64() + src.len() as u64 > crate::time::page::ADDRESS {
+        return Err(Error::BadAddress);
+    }
+    user_access_begin();
+    unsafe { core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr::<u8>(), src.len()) };
+    user_access_end();
+    Ok(())
+}
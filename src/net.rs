@@ -0,0 +1,285 @@
+use crate::sync::{Event, Mutex};
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU16, Ordering},
+    task::{Context, Poll, Waker},
+};
+use smoltcp::{
+    iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache, Routes},
+    socket::{AnySocket, Socket, SocketHandle, SocketSet, TcpSocket},
+    time::Instant,
+    wire::{EthernetAddress, IpCidr, Ipv4Address, Ipv4Cidr},
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{task::supervisor, time};
+
+pub mod device;
+pub mod dhcp;
+pub mod icmp;
+pub mod loopback;
+pub mod remote_console;
+pub mod selftest;
+pub mod tcp;
+pub mod udp;
+
+pub use self::device::NetDevice;
+pub use self::icmp::ping;
+pub use self::remote_console::remote_console;
+pub use self::tcp::{TcpListener, TcpStream};
+pub use self::udp::UdpSocket;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    AlreadyAttached,
+    ConnectionRefused,
+    ConnectionReset,
+    NoInterface,
+    NotConnected,
+    Stack(smoltcp::Error),
+    TimedOut,
+}
+
+impl From<smoltcp::Error> for Error {
+    fn from(err: smoltcp::Error) -> Self {
+        Error::Stack(err)
+    }
+}
+
+struct Stack {
+    iface: EthernetInterface<'static, device::Adapter>,
+    sockets: SocketSet<'static>,
+}
+
+static STACK: Mutex<Option<Stack>> = Mutex::new(None);
+
+/// Signalled when the NIC or a socket user wants the stack polled.
+static PENDING: Event = Event::new();
+
+/// TCP sockets their owner closed, removed once the close handshake is done.
+static LINGERING: Mutex<Vec<SocketHandle>> = Mutex::new(Vec::new());
+
+/// Tasks waiting for socket state to change, woken after every poll.
+static SOCKET_WAKERS: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+
+/// Brings up the interface on `device`, unconfigured until `set_ipv4`.
+pub fn attach(device: Box<dyn NetDevice>) -> Result<(), Error> {
+    let mut stack = STACK.lock();
+    if stack.is_some() {
+        return Err(Error::AlreadyAttached);
+    }
+    let mac = device.mac_address();
+    info!(
+        "net: {} at {}, link {}",
+        device.name(),
+        EthernetAddress(mac),
+        if device.link_up() { "up" } else { "down" }
+    );
+    let iface = EthernetInterfaceBuilder::new(device::Adapter(device))
+        .ethernet_addr(EthernetAddress(mac))
+        .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+        .ip_addrs(vec![IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)])
+        .routes(Routes::new(BTreeMap::new()))
+        .finalize();
+    *stack = Some(Stack {
+        iface,
+        sockets: SocketSet::new(vec![]),
+    });
+    drop(stack);
+    crate::power::register_hook("net", |_| quiesce());
+    notify();
+    Ok(())
+}
+
+/// Stops the NIC; the stack stays attached but nothing moves any more.
+pub fn quiesce() {
+    if let Some(stack) = STACK.lock().as_mut() {
+        stack.iface.device_mut().0.quiesce();
+    }
+}
+
+pub fn is_attached() -> bool {
+    STACK.lock().is_some()
+}
+
+/// Sets the interface address and, optionally, the default route.
+pub fn set_ipv4(cidr: Ipv4Cidr, gateway: Option<Ipv4Address>) -> Result<(), Error> {
+    with_stack(|stack| configure(stack, cidr, gateway))
+}
+
+fn configure(stack: &mut Stack, cidr: Ipv4Cidr, gateway: Option<Ipv4Address>) {
+    stack.iface.update_ip_addrs(|addrs| {
+        if let Some(addr) = addrs.iter_mut().next() {
+            *addr = IpCidr::Ipv4(cidr);
+        }
+    });
+    let routes = stack.iface.routes_mut();
+    match gateway {
+        Some(gateway) => {
+            let _ = routes.add_default_ipv4_route(gateway);
+        }
+        None => {
+            routes.remove_default_ipv4_route();
+        }
+    }
+    info!("net: address {}, gateway {:?}", cidr, gateway);
+}
+
+/// Name servers learned from DHCP.
+static DNS_SERVERS: Mutex<[Option<Ipv4Address>; 3]> = Mutex::new([None; 3]);
+
+pub fn dns_servers() -> [Option<Ipv4Address>; 3] {
+    *DNS_SERVERS.lock()
+}
+
+pub fn ipv4_address() -> Option<Ipv4Cidr> {
+    let stack = STACK.lock();
+    let stack = stack.as_ref()?;
+    stack.iface.ip_addrs().iter().find_map(|addr| match addr {
+        IpCidr::Ipv4(cidr) if !cidr.address().is_unspecified() => Some(*cidr),
+        _ => None,
+    })
+}
+
+fn with_stack<R>(f: impl FnOnce(&mut Stack) -> R) -> Result<R, Error> {
+    STACK.lock().as_mut().map(f).ok_or(Error::NoInterface)
+}
+
+pub fn add_socket<S: Into<Socket<'static>>>(socket: S) -> Result<SocketHandle, Error> {
+    with_stack(|stack| stack.sockets.add(socket))
+}
+
+pub fn remove_socket(handle: SocketHandle) {
+    let _ = with_stack(|stack| stack.sockets.remove(handle));
+    notify();
+}
+
+/// Hands a closed TCP socket to the stack so its FIN still gets through.
+pub fn linger(handle: SocketHandle) {
+    LINGERING.lock().push(handle);
+    notify();
+}
+
+/// Runs `f` on a socket; panics if `handle` does not hold a `T`.
+pub fn with_socket<T, R>(handle: SocketHandle, f: impl FnOnce(&mut T) -> R) -> Result<R, Error>
+where
+    T: AnySocket<'static>,
+{
+    with_stack(|stack| f(&mut *stack.sockets.get::<T>(handle)))
+}
+
+const EPHEMERAL_PORTS: core::ops::Range<u16> = 49152..65535;
+
+static NEXT_EPHEMERAL_PORT: AtomicU16 = AtomicU16::new(EPHEMERAL_PORTS.start);
+
+/// A local port for sockets bound to port 0.
+pub fn ephemeral_port() -> u16 {
+    let port = NEXT_EPHEMERAL_PORT.fetch_add(1, Ordering::Relaxed);
+    if port >= EPHEMERAL_PORTS.end {
+        NEXT_EPHEMERAL_PORT.store(EPHEMERAL_PORTS.start + 1, Ordering::Relaxed);
+        EPHEMERAL_PORTS.start
+    } else {
+        port
+    }
+}
+
+/// A socket that is removed from the stack when dropped.
+pub struct OwnedSocket(SocketHandle);
+
+impl OwnedSocket {
+    pub fn new<S: Into<Socket<'static>>>(socket: S) -> Result<Self, Error> {
+        add_socket(socket).map(OwnedSocket)
+    }
+
+    pub fn handle(&self) -> SocketHandle {
+        self.0
+    }
+
+    pub fn with<T, R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, Error>
+    where
+        T: AnySocket<'static>,
+    {
+        with_socket(self.0, f)
+    }
+}
+
+impl Drop for OwnedSocket {
+    fn drop(&mut self) {
+        remove_socket(self.0);
+    }
+}
+
+/// Registers `cx` to be woken after the next poll of the stack.
+pub fn register_waker(cx: &Context) {
+    without_interrupts(|| SOCKET_WAKERS.lock().push(cx.waker().clone()));
+}
+
+/// Asks the poll task to run; called by NIC interrupt handlers and after
+/// socket operations that have something to send.
+pub fn notify() {
+    PENDING.signal();
+}
+
+/// Polls the interface once, returning how long it may sleep in ms.
+fn poll() -> Option<u64> {
+    let delay = {
+        let mut stack = STACK.lock();
+        let stack = stack.as_mut()?;
+        let now = Instant::from_millis(time::uptime_ms() as i64);
+        match stack.iface.poll(&mut stack.sockets, now) {
+            Ok(_) | Err(smoltcp::Error::Unrecognized) => {}
+            Err(err) => debug!("net: poll error: {}", err),
+        }
+        let sockets = &mut stack.sockets;
+        LINGERING.lock().retain(|&handle| {
+            let open = sockets.get::<TcpSocket>(handle).is_open();
+            if !open {
+                sockets.remove(handle);
+            }
+            open
+        });
+        stack
+            .iface
+            .poll_delay(&stack.sockets, now)
+            .map(|delay| delay.total_millis())
+    };
+    let wakers = without_interrupts(|| core::mem::replace(&mut *SOCKET_WAKERS.lock(), Vec::new()));
+    wakers.into_iter().for_each(Waker::wake);
+    delay
+}
+
+/// Drives the stack: polls whenever the NIC interrupts, a socket has work,
+/// or a protocol timer (ARP, TCP retransmission) expires.
+pub async fn poll_task() {
+    loop {
+        supervisor::check_in("net");
+        let delay = poll().map_or(supervisor::CHECK_IN_MS, |delay| {
+            delay.min(supervisor::CHECK_IN_MS)
+        });
+        let deadline = Some(time::uptime_ms() + delay);
+        Wakeup { deadline }.await;
+    }
+}
+
+struct Wakeup {
+    deadline: Option<u64>,
+}
+
+impl Future for Wakeup {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if PENDING.take() {
+            return Poll::Ready(());
+        }
+        if let Some(deadline) = self.deadline {
+            if time::uptime_ms() >= deadline {
+                return Poll::Ready(());
+            }
+            time::wake_at(deadline, cx.waker().clone());
+        }
+        PENDING.poll_wait(cx)
+    }
+}
@@ -0,0 +1,83 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use smoltcp::{
+    phy::{self, DeviceCapabilities},
+    time::Instant,
+};
+
+/// Largest Ethernet frame handed to or taken from a driver, without FCS.
+pub const MAX_FRAME_LEN: usize = 1514;
+
+/// What a NIC driver provides to the network stack.
+pub trait NetDevice: Send {
+    fn name(&self) -> &'static str;
+    fn mac_address(&self) -> [u8; 6];
+    fn link_up(&self) -> bool;
+
+    /// Takes the next received frame, if any.
+    fn receive(&mut self) -> Option<Vec<u8>>;
+
+    /// Queues a frame, returning false when the transmit ring is full.
+    fn transmit(&mut self, frame: &[u8]) -> bool;
+
+    /// Queues the frame made of `parts` in order, so a header built on the
+    /// stack and a payload held elsewhere need not be joined first. Drivers
+    /// should copy the parts straight into their DMA buffers.
+    fn transmit_vectored(&mut self, parts: &[&[u8]]) -> bool {
+        self.transmit(&parts.concat())
+    }
+
+    /// Stops receiving, transmitting and interrupting, before power off.
+    fn quiesce(&mut self) {}
+}
+
+/// Adapts a `NetDevice` to the interface smoltcp polls.
+pub struct Adapter(pub Box<dyn NetDevice>);
+
+impl<'a> phy::Device<'a> for Adapter {
+    type RxToken = RxToken;
+    type TxToken = TxToken<'a>;
+
+    fn receive(&'a mut self) -> Option<(RxToken, TxToken<'a>)> {
+        let frame = self.0.receive()?;
+        Some((RxToken(frame), TxToken(&mut self.0)))
+    }
+
+    fn transmit(&'a mut self) -> Option<TxToken<'a>> {
+        Some(TxToken(&mut self.0))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.max_transmission_unit = MAX_FRAME_LEN;
+        capabilities.max_burst_size = Some(1);
+        capabilities
+    }
+}
+
+pub struct RxToken(Vec<u8>);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        f(&mut self.0)
+    }
+}
+
+pub struct TxToken<'a>(&'a mut Box<dyn NetDevice>);
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        let mut frame = vec![0; len];
+        let result = f(&mut frame)?;
+        if self.0.transmit(&frame) {
+            Ok(result)
+        } else {
+            Err(smoltcp::Error::Exhausted)
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use super::{Stack, DNS_SERVERS};
+use crate::time;
+use alloc::vec;
+use core::task::Poll;
+use futures_util::future::poll_fn;
+use smoltcp::{
+    dhcp::{Config, Dhcpv4Client},
+    socket::{RawPacketMetadata, RawSocketBuffer},
+    time::Instant,
+    wire::{Ipv4Address, Ipv4Cidr},
+};
+
+/// How long to wait for a lease before applying the static fallback.
+pub const DHCP_TIMEOUT_MS: u64 = 10_000;
+
+/// Address to use when no DHCP server answers.
+#[derive(Debug, Clone, Copy)]
+pub struct StaticConfig {
+    pub address: Ipv4Cidr,
+    pub gateway: Option<Ipv4Address>,
+}
+
+fn now() -> Instant {
+    Instant::from_millis(time::uptime_ms() as i64)
+}
+
+fn apply(stack: &mut Stack, config: Config) {
+    if let Some(address) = config.address {
+        super::configure(stack, address, config.router);
+    }
+    if config.dns_servers.iter().any(Option::is_some) {
+        *DNS_SERVERS.lock() = config.dns_servers;
+    }
+}
+
+/// Acquires and renews a lease for the interface, if there is one.
+pub async fn dhcp_task(fallback: Option<StaticConfig>) {
+    if super::loopback::is_active() {
+        return;
+    }
+    let buffer = |size| RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; size]);
+    let client = super::with_stack(|stack| {
+        Dhcpv4Client::new(&mut stack.sockets, buffer(900), buffer(600), now())
+    });
+    let mut client = match client {
+        Ok(client) => client,
+        Err(_) => return,
+    };
+    let started = time::uptime_ms();
+    let mut leased = false;
+    let mut fallback = fallback;
+
+    loop {
+        let next_poll = super::with_stack(|stack| {
+            let now = now();
+            match client.poll(&mut stack.iface, &mut stack.sockets, now) {
+                Ok(Some(config)) => {
+                    leased |= config.address.is_some();
+                    apply(stack, config);
+                }
+                Ok(None) => {}
+                Err(err) => debug!("dhcp: {}", err),
+            }
+            if !leased && time::uptime_ms() - started >= DHCP_TIMEOUT_MS {
+                if let Some(config) = fallback.take() {
+                    warn!("dhcp: no lease, using static configuration");
+                    super::configure(stack, config.address, config.gateway);
+                }
+            }
+            client.next_poll(now).total_millis()
+        });
+        let next_poll = match next_poll {
+            Ok(next_poll) => next_poll,
+            Err(_) => return,
+        };
+        super::notify();
+
+        // Wake on the client's own timer or whenever the stack has polled,
+        // since replies land in the client's raw socket.
+        let deadline = time::uptime_ms() + next_poll;
+        let mut waiting = false;
+        poll_fn(|cx| {
+            if waiting || time::uptime_ms() >= deadline {
+                return Poll::Ready(());
+            }
+            waiting = true;
+            super::register_waker(cx);
+            time::wake_at(deadline, cx.waker().clone());
+            Poll::Pending
+        })
+        .await;
+    }
+}
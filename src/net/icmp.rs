@@ -0,0 +1,75 @@
+use super::{Error, OwnedSocket};
+use crate::time;
+use alloc::vec;
+use core::{
+    sync::atomic::{AtomicU16, Ordering},
+    task::Poll,
+};
+use futures_util::future::poll_fn;
+use smoltcp::{
+    phy::ChecksumCapabilities,
+    socket::{IcmpEndpoint, IcmpPacketMetadata, IcmpSocket, IcmpSocketBuffer},
+    wire::{Icmpv4Packet, Icmpv4Repr, Ipv4Address},
+};
+
+/// How long `ping` waits for the echo reply.
+pub const PING_TIMEOUT_MS: u64 = 1000;
+
+const PAYLOAD: &[u8] = b"microkernel ping";
+
+static NEXT_IDENT: AtomicU16 = AtomicU16::new(0x4d4b);
+
+/// Sends one ICMP echo request and returns the round trip time in ms.
+///
+/// ARP resolution happens inside the stack; replies to pings from other
+/// hosts are also sent by the stack without any socket.
+pub async fn ping(addr: Ipv4Address) -> Result<u64, Error> {
+    let ident = NEXT_IDENT.fetch_add(1, Ordering::Relaxed);
+    let buffer = || IcmpSocketBuffer::new(vec![IcmpPacketMetadata::EMPTY; 4], vec![0; 512]);
+    let mut socket = IcmpSocket::new(buffer(), buffer());
+    socket.bind(IcmpEndpoint::Ident(ident))?;
+    let socket = OwnedSocket::new(socket)?;
+
+    let checksum = ChecksumCapabilities::default();
+    let request = Icmpv4Repr::EchoRequest {
+        ident,
+        seq_no: 1,
+        data: PAYLOAD,
+    };
+    socket.with(|icmp: &mut IcmpSocket| -> Result<(), Error> {
+        let buf = icmp.send(request.buffer_len(), addr.into())?;
+        request.emit(&mut Icmpv4Packet::new_unchecked(buf), &checksum);
+        Ok(())
+    })??;
+    super::notify();
+
+    let sent = time::uptime_ms();
+    let deadline = sent + PING_TIMEOUT_MS;
+    poll_fn(|cx| {
+        let replied = socket.with(|icmp: &mut IcmpSocket| {
+            while let Ok((payload, _)) = icmp.recv() {
+                let packet = Icmpv4Packet::new_unchecked(payload);
+                if let Ok(Icmpv4Repr::EchoReply {
+                    ident: id, seq_no, ..
+                }) = Icmpv4Repr::parse(&packet, &checksum)
+                {
+                    if id == ident && seq_no == 1 {
+                        return true;
+                    }
+                }
+            }
+            false
+        });
+        match replied {
+            Err(err) => Poll::Ready(Err(err)),
+            Ok(true) => Poll::Ready(Ok(time::uptime_ms() - sent)),
+            Ok(false) if time::uptime_ms() >= deadline => Poll::Ready(Err(Error::TimedOut)),
+            Ok(false) => {
+                super::register_waker(cx);
+                time::wake_at(deadline, cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    })
+    .await
+}
@@ -0,0 +1,129 @@
+use super::{TcpListener, TcpStream};
+use crate::{
+    console::Input,
+    shell::{self, LineEditor},
+};
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+pub const REMOTE_CONSOLE_PORT: u16 = 23;
+
+const IAC: u8 = 255;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const WILL: u8 = 251;
+const DONT: u8 = 254;
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// We echo typed characters ourselves and never send go-ahead.
+const NEGOTIATION: [u8; 6] = [IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD];
+
+/// Buffers output with telnet line endings until it can be sent.
+struct Output(String);
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if c == '\n' {
+                self.0.push('\r');
+            }
+            self.0.push(c);
+        }
+        Ok(())
+    }
+}
+
+/// Skips telnet commands, which the client sends interleaved with input.
+enum Telnet {
+    Data,
+    Command,
+    Option,
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+impl Telnet {
+    /// Returns the byte if it is input rather than protocol.
+    fn feed(&mut self, byte: u8) -> Option<u8> {
+        let (next, data) = match (&*self, byte) {
+            (Telnet::Data, IAC) => (Telnet::Command, None),
+            (Telnet::Data, byte) => (Telnet::Data, Some(byte)),
+            (Telnet::Command, IAC) => (Telnet::Data, Some(IAC)),
+            (Telnet::Command, SB) => (Telnet::Subnegotiation, None),
+            (Telnet::Command, WILL..=DONT) => (Telnet::Option, None),
+            (Telnet::Command, _) | (Telnet::Option, _) => (Telnet::Data, None),
+            (Telnet::Subnegotiation, IAC) => (Telnet::SubnegotiationIac, None),
+            (Telnet::Subnegotiation, _) => (Telnet::Subnegotiation, None),
+            (Telnet::SubnegotiationIac, SE) => (Telnet::Data, None),
+            (Telnet::SubnegotiationIac, _) => (Telnet::Subnegotiation, None),
+        };
+        *self = next;
+        data
+    }
+}
+
+async fn session(stream: &mut TcpStream) -> Result<(), super::Error> {
+    let mut output = Output(String::new());
+    let mut editor = LineEditor::new();
+    let mut telnet = Telnet::Data;
+    let mut buf = [0; 256];
+
+    stream.write_all(&NEGOTIATION).await?;
+    let _ = write!(output, "microkernel remote console\n{}", shell::PROMPT);
+    loop {
+        if !output.0.is_empty() {
+            stream.write_all(output.0.as_bytes()).await?;
+            output.0.clear();
+        }
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        for &byte in &buf[..read] {
+            let byte = match telnet.feed(byte) {
+                Some(byte) => byte,
+                None => continue,
+            };
+            match editor.push(byte as char, &mut output) {
+                Some(Input::Line(line)) => shell::execute(&line, &mut output),
+                Some(Input::Interrupt) => {}
+                Some(Input::EndOfFile) => {
+                    stream.write_all(output.0.as_bytes()).await?;
+                    return Ok(());
+                }
+                None => continue,
+            }
+            let _ = output.write_str(shell::PROMPT);
+        }
+    }
+}
+
+/// Serves the kernel shell to one telnet client at a time on `port`.
+///
+/// Anyone who can reach the port gets the shell, unauthenticated; the
+/// kernel starts this only with `telnet` on the command line.
+pub async fn remote_console(port: u16) {
+    let mut listener = match TcpListener::bind(port, 1) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!("remote console: {:?}", err);
+            return;
+        }
+    };
+    loop {
+        match listener.accept().await {
+            Ok((mut stream, remote)) => {
+                info!("remote console: connection from {}", remote);
+                match session(&mut stream).await {
+                    Ok(()) => info!("remote console: {} disconnected", remote),
+                    Err(err) => info!("remote console: {} dropped: {:?}", remote, err),
+                }
+            }
+            Err(err) => {
+                warn!("remote console: {:?}", err);
+                return;
+            }
+        }
+    }
+}
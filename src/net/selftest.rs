@@ -0,0 +1,114 @@
+use super::{loopback, Error, TcpListener, TcpStream, UdpSocket};
+use crate::time;
+use futures_util::{
+    future::{join, select, Either},
+    pin_mut,
+};
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+const UDP_PORT: u16 = 7;
+const TCP_PORT: u16 = 7;
+const TIMEOUT_MS: u64 = 2000;
+const PAYLOAD: &[u8] = b"netstack selftest payload";
+
+#[derive(Debug)]
+pub enum Failure {
+    Net(Error),
+    Mismatch(&'static str),
+    TimedOut(&'static str),
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        Failure::Net(err)
+    }
+}
+
+fn localhost(port: u16) -> IpEndpoint {
+    IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::new(127, 0, 0, 1)), port)
+}
+
+async fn timeout<T>(
+    what: &'static str,
+    future: impl core::future::Future<Output = Result<T, Failure>>,
+) -> Result<T, Failure> {
+    let sleep = time::sleep(TIMEOUT_MS);
+    pin_mut!(future);
+    pin_mut!(sleep);
+    match select(future, sleep).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(Failure::TimedOut(what)),
+    }
+}
+
+async fn udp_echo() -> Result<(), Failure> {
+    let server = UdpSocket::bind(UDP_PORT)?;
+    let client = UdpSocket::bind(0)?;
+    let mut buf = [0; 64];
+
+    client.send_to(PAYLOAD, localhost(UDP_PORT)).await?;
+    let (len, from) = server.recv_from(&mut buf).await?;
+    if &buf[..len] != PAYLOAD {
+        return Err(Failure::Mismatch("udp request"));
+    }
+    server.send_to(&buf[..len], from).await?;
+    let (len, _) = client.recv_from(&mut buf).await?;
+    if &buf[..len] != PAYLOAD {
+        return Err(Failure::Mismatch("udp reply"));
+    }
+    Ok(())
+}
+
+async fn read_exact(stream: &mut TcpStream, buf: &mut [u8]) -> Result<(), Failure> {
+    let mut read = 0;
+    while read < buf.len() {
+        match stream.read(&mut buf[read..]).await? {
+            0 => return Err(Failure::Mismatch("tcp stream ended early")),
+            n => read += n,
+        }
+    }
+    Ok(())
+}
+
+async fn tcp_echo() -> Result<(), Failure> {
+    let mut listener = TcpListener::bind(TCP_PORT, 1)?;
+    let (client, accepted) = join(TcpStream::connect(localhost(TCP_PORT)), listener.accept()).await;
+    let (mut client, (mut server, _)) = (client?, accepted?);
+    let mut buf = [0; PAYLOAD.len()];
+
+    client.write_all(PAYLOAD).await?;
+    read_exact(&mut server, &mut buf).await?;
+    if &buf[..] != PAYLOAD {
+        return Err(Failure::Mismatch("tcp request"));
+    }
+    server.write_all(&buf).await?;
+    read_exact(&mut client, &mut buf).await?;
+    if &buf[..] != PAYLOAD {
+        return Err(Failure::Mismatch("tcp reply"));
+    }
+
+    client.close();
+    if server.read(&mut buf).await? != 0 {
+        return Err(Failure::Mismatch("tcp data after FIN"));
+    }
+    Ok(())
+}
+
+/// Runs UDP and TCP echoes through the loopback interface; the
+/// `net_loopback` integration test checks the result.
+pub async fn run() -> Result<(), Failure> {
+    timeout("udp echo", udp_echo()).await?;
+    timeout("tcp echo", tcp_echo()).await?;
+    Ok(())
+}
+
+/// Reports the result of `run` when the loopback device is the interface.
+pub async fn selftest_task() {
+    if !loopback::is_active() {
+        return;
+    }
+    match run().await {
+        Ok(()) => info!("net selftest passed"),
+        Err(failure) => error!("net selftest failed: {:?}", failure),
+    }
+}
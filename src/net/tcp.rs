@@ -0,0 +1,209 @@
+use super::Error;
+use alloc::{vec, vec::Vec};
+use core::task::Poll;
+use futures_util::future::poll_fn;
+use smoltcp::{
+    socket::{SocketHandle, TcpSocket, TcpSocketBuffer, TcpState},
+    wire::IpEndpoint,
+};
+
+/// Bytes each direction buffers; a full transmit buffer makes `write` wait.
+const BUFFER_SIZE: usize = 4096;
+
+fn new_socket() -> TcpSocket<'static> {
+    TcpSocket::new(
+        TcpSocketBuffer::new(vec![0; BUFFER_SIZE]),
+        TcpSocketBuffer::new(vec![0; BUFFER_SIZE]),
+    )
+}
+
+/// An established connection.
+///
+/// Dropping it closes the connection gracefully; use `abort` to reset it.
+pub struct TcpStream {
+    handle: SocketHandle,
+    closed: bool,
+}
+
+impl TcpStream {
+    pub async fn connect(remote: IpEndpoint) -> Result<TcpStream, Error> {
+        let mut socket = new_socket();
+        socket.connect(remote, super::ephemeral_port())?;
+        let stream = TcpStream {
+            handle: super::add_socket(socket)?,
+            closed: false,
+        };
+        super::notify();
+        poll_fn(|cx| {
+            let state = stream.with(|tcp| tcp.state())?;
+            match state {
+                TcpState::Established | TcpState::CloseWait => Poll::Ready(Ok(())),
+                // A RST in reply to our SYN puts the socket straight back here.
+                TcpState::Closed => Poll::Ready(Err(Error::ConnectionRefused)),
+                _ => {
+                    super::register_waker(cx);
+                    Poll::Pending
+                }
+            }
+        })
+        .await?;
+        Ok(stream)
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut TcpSocket) -> R) -> Result<R, Error> {
+        super::with_socket(self.handle, |tcp: &mut TcpSocket| f(tcp))
+    }
+
+    pub fn remote_endpoint(&self) -> Result<IpEndpoint, Error> {
+        self.with(|tcp| tcp.remote_endpoint())
+    }
+
+    /// Waits for data; returns 0 once the peer has sent FIN.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let closed = self.closed;
+        poll_fn(|cx| {
+            let read = self.with(|tcp| {
+                if tcp.can_recv() {
+                    Some(tcp.recv_slice(buf).map_err(Error::from))
+                } else if !tcp.may_recv() {
+                    match tcp.state() {
+                        TcpState::Closed if !closed => Some(Err(Error::ConnectionReset)),
+                        _ => Some(Ok(0)),
+                    }
+                } else {
+                    None
+                }
+            })?;
+            match read {
+                Some(read) => {
+                    // Reading frees window space the peer should hear about.
+                    super::notify();
+                    Poll::Ready(read)
+                }
+                None => {
+                    super::register_waker(cx);
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Writes as much of `buf` as fits in the transmit buffer, waiting
+    /// while it is full.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        poll_fn(|cx| {
+            let written = self.with(|tcp| {
+                if tcp.can_send() {
+                    Some(tcp.send_slice(buf).map_err(Error::from))
+                } else if !tcp.may_send() {
+                    match tcp.state() {
+                        TcpState::Closed => Some(Err(Error::ConnectionReset)),
+                        _ => Some(Err(Error::NotConnected)),
+                    }
+                } else {
+                    None
+                }
+            })?;
+            match written {
+                Some(written) => {
+                    super::notify();
+                    Poll::Ready(written)
+                }
+                None => {
+                    super::register_waker(cx);
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> Result<(), Error> {
+        while !buf.is_empty() {
+            let written = self.write(buf).await?;
+            buf = &buf[written..];
+        }
+        Ok(())
+    }
+
+    /// Sends FIN once queued data is out; reads still drain what is left.
+    pub fn close(&mut self) {
+        self.closed = true;
+        let _ = self.with(|tcp| tcp.close());
+        super::notify();
+    }
+
+    /// Resets the connection, discarding anything unsent.
+    pub fn abort(mut self) {
+        self.closed = true;
+        let _ = self.with(|tcp| tcp.abort());
+        super::notify();
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.with(|tcp| tcp.close());
+        }
+        super::linger(self.handle);
+    }
+}
+
+/// Accepts connections on a port with up to `backlog` handshakes in flight.
+pub struct TcpListener {
+    port: u16,
+    pending: Vec<SocketHandle>,
+}
+
+impl TcpListener {
+    pub fn bind(port: u16, backlog: usize) -> Result<Self, Error> {
+        let mut listener = TcpListener {
+            port,
+            pending: Vec::with_capacity(backlog),
+        };
+        for _ in 0..backlog.max(1) {
+            listener.pending.push(Self::listen(port)?);
+        }
+        Ok(listener)
+    }
+
+    fn listen(port: u16) -> Result<SocketHandle, Error> {
+        let mut socket = new_socket();
+        socket.listen(port)?;
+        super::add_socket(socket)
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Waits for a connection to finish its handshake.
+    pub async fn accept(&mut self) -> Result<(TcpStream, IpEndpoint), Error> {
+        let index = poll_fn(|cx| {
+            for (index, &handle) in self.pending.iter().enumerate() {
+                let state = super::with_socket(handle, |tcp: &mut TcpSocket| tcp.state())?;
+                if let TcpState::Established | TcpState::CloseWait = state {
+                    return Poll::Ready(Ok(index));
+                }
+            }
+            super::register_waker(cx);
+            Poll::Pending
+        })
+        .await?;
+        let handle = core::mem::replace(&mut self.pending[index], Self::listen(self.port)?);
+        let stream = TcpStream {
+            handle,
+            closed: false,
+        };
+        let remote = stream.remote_endpoint()?;
+        Ok((stream, remote))
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        self.pending.drain(..).for_each(super::remove_socket);
+    }
+}
@@ -0,0 +1,87 @@
+use super::{Error, OwnedSocket};
+use alloc::vec;
+use core::task::Poll;
+use futures_util::future::poll_fn;
+use smoltcp::{
+    socket::{UdpPacketMetadata, UdpSocket as StackSocket, UdpSocketBuffer},
+    wire::IpEndpoint,
+};
+
+/// Datagrams each direction can hold before `send_to` waits or incoming
+/// ones are dropped.
+const QUEUE_LEN: usize = 16;
+const BUFFER_SIZE: usize = 8192;
+
+/// A bound UDP socket, closed when dropped.
+pub struct UdpSocket {
+    socket: OwnedSocket,
+    local_port: u16,
+}
+
+impl UdpSocket {
+    /// Binds to `port` on every local address; 0 picks an ephemeral port.
+    pub fn bind(port: u16) -> Result<Self, Error> {
+        let port = if port == 0 {
+            super::ephemeral_port()
+        } else {
+            port
+        };
+        let buffer = || {
+            UdpSocketBuffer::new(
+                vec![UdpPacketMetadata::EMPTY; QUEUE_LEN],
+                vec![0; BUFFER_SIZE],
+            )
+        };
+        let mut socket = StackSocket::new(buffer(), buffer());
+        socket.bind(port)?;
+        Ok(UdpSocket {
+            socket: OwnedSocket::new(socket)?,
+            local_port: port,
+        })
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    /// Queues one datagram, waiting while the transmit buffer is full.
+    pub async fn send_to(&self, data: &[u8], endpoint: IpEndpoint) -> Result<usize, Error> {
+        poll_fn(|cx| {
+            let sent = self
+                .socket
+                .with(|udp: &mut StackSocket| udp.send_slice(data, endpoint));
+            match sent {
+                Ok(Ok(())) => {
+                    super::notify();
+                    Poll::Ready(Ok(data.len()))
+                }
+                Ok(Err(smoltcp::Error::Exhausted)) => {
+                    super::register_waker(cx);
+                    Poll::Pending
+                }
+                Ok(Err(err)) => Poll::Ready(Err(err.into())),
+                Err(err) => Poll::Ready(Err(err)),
+            }
+        })
+        .await
+    }
+
+    /// Waits for a datagram; anything past `buf.len()` is discarded.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), Error> {
+        poll_fn(|cx| {
+            let received = self
+                .socket
+                .with(|udp: &mut StackSocket| udp.recv_slice(buf));
+            match received {
+                Ok(Ok(received)) => Poll::Ready(Ok(received)),
+                Ok(Err(smoltcp::Error::Exhausted)) => {
+                    super::register_waker(cx);
+                    Poll::Pending
+                }
+                Ok(Err(err)) => Poll::Ready(Err(err.into())),
+                Err(err) => Poll::Ready(Err(err)),
+            }
+        })
+        .await
+    }
+}
@@ -0,0 +1,274 @@
+//! Powering the machine off and resetting it.
+//!
+//! `request` is the graceful path: `power_task` cancels every other task,
+//! waits up to `CANCEL_DEADLINE_MS` for the scheduler to drop them, runs
+//! the hooks subsystems registered (flushing logs, quiescing the NIC) and
+//! only then cuts power.
+//!
+//! There is no block cache to flush yet, since there is no block layer.
+//! Nor is the RTC written back: the kernel's real time is the RTC's at
+//! boot plus the uptime, and nothing sets it, so syncing would only store
+//! the timer's drift.
+//! `shutdown` and `reboot` act immediately, for fault paths. `buttons`
+//! turns ACPI power button presses into requests.
+
+use crate::{
+    acpi::{self, sleep},
+    shell::ShellErr,
+    sync::Mutex,
+    task, time,
+};
+use alloc::vec::Vec;
+use core::{
+    fmt::Write,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use x86_64::{
+    instructions::{interrupts, port::Port, tables::lidt},
+    structures::DescriptorTablePointer,
+    PhysAddr,
+};
+
+pub mod buttons;
+
+/// Set in PM1 control once the firmware has handed power management over.
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_EN: u16 = 1 << 13;
+/// Polls of PM1a control while waiting for the switch to ACPI mode.
+const ACPI_ENABLE_POLLS: usize = 1_000_000;
+const QEMU_SHUTDOWN_PORT: u16 = 0x604;
+const QEMU_SHUTDOWN_VALUE: u16 = 0x2000;
+const KBC_STATUS: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xfe;
+/// Polls given each reset method before trying the next.
+const RESET_POLLS: usize = 1_000_000;
+
+/// How long the scheduler gets to drop cancelled tasks before the hooks
+/// run anyway.
+pub const CANCEL_DEADLINE_MS: u64 = 1000;
+const CANCEL_POLL_MS: u64 = 10;
+
+const NO_REQUEST: u8 = 0;
+
+static REQUEST: AtomicU8 = AtomicU8::new(NO_REQUEST);
+static REQUEST_WAKER: AtomicWaker = AtomicWaker::new();
+static HOOKS: Mutex<Vec<(&'static str, fn(Action))>> = Mutex::new(Vec::new());
+
+/// What a graceful request ends in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Action {
+    PowerOff = 1,
+    Reboot = 2,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Acpi(acpi::Error),
+    /// The firmware never set `SCI_EN`.
+    AcpiModeTimeout,
+    Interrupt(crate::interrupts::Error),
+}
+
+impl From<acpi::Error> for Error {
+    fn from(err: acpi::Error) -> Self {
+        Error::Acpi(err)
+    }
+}
+
+impl From<crate::interrupts::Error> for Error {
+    fn from(err: crate::interrupts::Error) -> Self {
+        Error::Interrupt(err)
+    }
+}
+
+/// Runs `hook` before power goes, after tasks were cancelled. Hooks run
+/// in reverse registration order, so later subsystems go down first.
+pub fn register_hook(name: &'static str, hook: fn(Action)) {
+    HOOKS.lock().push((name, hook));
+}
+
+/// Starts a graceful shutdown or reboot. A second request while one is
+/// under way acts immediately, for when the graceful path hangs.
+pub fn request(action: Action) {
+    let queued = REQUEST
+        .compare_exchange(NO_REQUEST, action as u8, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok();
+    if queued {
+        REQUEST_WAKER.wake();
+        return;
+    }
+    match action {
+        Action::PowerOff => shutdown(),
+        Action::Reboot => reboot(),
+    }
+}
+
+/// Waits for a `request` and carries it out.
+pub async fn power_task() {
+    let action = NextRequest.await;
+    info!(
+        "power: {:?} requested, cancelling {} tasks",
+        action,
+        task::live_count() - 1
+    );
+    task::cancel_all();
+    let deadline = time::uptime_ms() + CANCEL_DEADLINE_MS;
+    while task::live_count() > 1 && time::uptime_ms() < deadline {
+        time::sleep(CANCEL_POLL_MS).await;
+    }
+    if task::live_count() > 1 {
+        warn!("power: {} tasks still running", task::live_count() - 1);
+    }
+    let hooks = HOOKS.lock().clone();
+    for &(name, hook) in hooks.iter().rev() {
+        let start = time::uptime_ms();
+        hook(action);
+        debug!("power: {} done in {} ms", name, time::uptime_ms() - start);
+    }
+    match action {
+        Action::PowerOff => shutdown(),
+        Action::Reboot => reboot(),
+    }
+}
+
+struct NextRequest;
+
+impl Future for NextRequest {
+    type Output = Action;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Action> {
+        REQUEST_WAKER.register(cx.waker());
+        match REQUEST.load(Ordering::SeqCst) {
+            NO_REQUEST => Poll::Pending,
+            action if action == Action::Reboot as u8 => Poll::Ready(Action::Reboot),
+            _ => Poll::Ready(Action::PowerOff),
+        }
+    }
+}
+
+/// Turns the machine off: ACPI S5 first, then QEMU's shutdown port. Halts
+/// if neither takes.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    warn!("shutting down");
+    if let Err(err) = enter_s5() {
+        warn!("ACPI shutdown failed ({:?}), trying the QEMU port", err);
+    }
+    unsafe { Port::new(QEMU_SHUTDOWN_PORT).write(QEMU_SHUTDOWN_VALUE) };
+    error!("shutdown failed, halting");
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Resets the machine: the ACPI reset register, then the keyboard
+/// controller's reset line, then a triple fault, which always works.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    warn!("rebooting");
+    match acpi::fadt().map(|fadt| (fadt.reset_register, fadt.reset_value)) {
+        Ok((Some(register), value)) => {
+            acpi_reset(register, value);
+            wait();
+        }
+        Ok((None, _)) => {}
+        Err(err) => warn!("no ACPI reset register: {:?}", err),
+    }
+    keyboard_controller_reset();
+    wait();
+    triple_fault()
+}
+
+fn acpi_reset(register: acpi::GenericAddress, value: u8) {
+    match register.space {
+        acpi::GenericAddress::SYSTEM_IO => unsafe {
+            Port::<u8>::new(register.address as u16).write(value)
+        },
+        acpi::GenericAddress::SYSTEM_MEMORY => {
+            match crate::memory::phys_to_virt(PhysAddr::new(register.address)) {
+                Ok(addr) => unsafe { core::ptr::write_volatile(addr.as_mut_ptr::<u8>(), value) },
+                Err(err) => warn!("ACPI reset register: {}", err),
+            }
+        }
+        space => warn!("ACPI reset register in address space {}", space),
+    }
+}
+
+fn keyboard_controller_reset() {
+    let mut status = Port::<u8>::new(KBC_STATUS);
+    for _ in 0..RESET_POLLS {
+        if unsafe { status.read() } & KBC_INPUT_FULL == 0 {
+            break;
+        }
+        crate::interrupts::pause();
+    }
+    unsafe { status.write(KBC_PULSE_RESET) };
+}
+
+/// With an empty IDT the breakpoint cannot be delivered, nor the double
+/// fault that follows, and the CPU resets.
+fn triple_fault() -> ! {
+    let empty = DescriptorTablePointer { limit: 0, base: 0 };
+    unsafe {
+        lidt(&empty);
+        asm!("int3", options(nomem, nostack));
+    }
+    unreachable!("survived a triple fault")
+}
+
+fn wait() {
+    for _ in 0..RESET_POLLS {
+        crate::interrupts::pause();
+    }
+}
+
+/// Writes `SLP_TYP` for S5 with `SLP_EN` to the PM1 control registers; on
+/// success this does not return.
+fn enter_s5() -> Result<(), Error> {
+    let fadt = acpi::fadt()?;
+    let types = sleep::sleep_types(5)?;
+    enable_acpi_mode(&fadt)?;
+    unsafe {
+        let mut pm1a = Port::<u16>::new(fadt.pm1a_control as u16);
+        pm1a.write(u16::from(types.a) << SLP_TYP_SHIFT | SLP_EN);
+        if fadt.pm1b_control != 0 {
+            let mut pm1b = Port::<u16>::new(fadt.pm1b_control as u16);
+            pm1b.write(u16::from(types.b) << SLP_TYP_SHIFT | SLP_EN);
+        }
+    }
+    Ok(())
+}
+
+fn enable_acpi_mode(fadt: &acpi::Fadt) -> Result<(), Error> {
+    let mut pm1a = Port::<u16>::new(fadt.pm1a_control as u16);
+    if unsafe { pm1a.read() } & SCI_EN != 0 || fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return Ok(());
+    }
+    unsafe { Port::<u8>::new(fadt.smi_command as u16).write(fadt.acpi_enable) };
+    for _ in 0..ACPI_ENABLE_POLLS {
+        if unsafe { pm1a.read() } & SCI_EN != 0 {
+            return Ok(());
+        }
+        crate::interrupts::pause();
+    }
+    Err(Error::AcpiModeTimeout)
+}
+
+/// `reboot`
+pub fn reboot_command(_args: &[&str], _out: &mut dyn Write) -> Result<(), ShellErr> {
+    request(Action::Reboot);
+    Ok(())
+}
+
+/// `shutdown`
+pub fn shutdown_command(_args: &[&str], _out: &mut dyn Write) -> Result<(), ShellErr> {
+    request(Action::PowerOff);
+    Ok(())
+}
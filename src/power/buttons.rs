@@ -0,0 +1,165 @@
+//! The ACPI fixed-feature power and sleep buttons, which raise an SCI with
+//! their bit set in the PM1 status registers; QEMU's `system_powerdown`
+//! presses the power button.
+//!
+//! The SCI handler clears the status and records the press, and
+//! `button_task` decides what a press means. Firmware that implements a
+//! button as a control-method device in the DSDT sets the matching FADT
+//! flag, and that button is left alone, since there is no AML interpreter.
+
+use super::{enable_acpi_mode, request, shutdown, Action, Error, NO_REQUEST, REQUEST};
+use crate::{acpi, interrupts, sync::Once};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU16, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use x86_64::instructions::port::Port;
+
+// PM1 status and enable bits
+const PWRBTN: u16 = 1 << 8;
+const SLPBTN: u16 = 1 << 9;
+// FADT flags: the button is a control-method device, or absent
+const PWR_BUTTON: u32 = 1 << 4;
+const SLP_BUTTON: u32 = 1 << 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Power,
+    Sleep,
+}
+
+/// The PM1a and PM1b event blocks; a status register of 0 means the
+/// block is absent.
+struct Pm1Events {
+    status: [u16; 2],
+    enable: [u16; 2],
+    /// The button bits enabled in each block.
+    buttons: u16,
+}
+
+static PM1: Once<Pm1Events> = Once::new();
+/// Button bits pressed but not yet taken by `next_press`.
+static PRESSED: AtomicU16 = AtomicU16::new(0);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Switches to ACPI mode and enables the fixed buttons the FADT has, with
+/// their presses delivered on the SCI.
+pub fn init() -> Result<(), Error> {
+    let fadt = acpi::fadt()?;
+    let mut buttons = 0;
+    if fadt.flags & PWR_BUTTON == 0 {
+        buttons |= PWRBTN;
+    }
+    if fadt.flags & SLP_BUTTON == 0 {
+        buttons |= SLPBTN;
+    }
+    if buttons == 0 || fadt.pm1a_event == 0 {
+        return Ok(());
+    }
+    // each block is a status register, then an enable register as long
+    let half = u32::from(fadt.pm1_event_len / 2);
+    let block = |base: u32| {
+        if base == 0 {
+            (0, 0)
+        } else {
+            (base as u16, (base + half) as u16)
+        }
+    };
+    let (a, b) = (block(fadt.pm1a_event), block(fadt.pm1b_event));
+    enable_acpi_mode(&fadt)?;
+    let pm1 = PM1.call_once(|| Pm1Events {
+        status: [a.0, b.0],
+        enable: [a.1, b.1],
+        buttons,
+    });
+    interrupts::register_irq(fadt.sci_interrupt as u8, sci)?;
+    for (&status, &enable) in pm1.status.iter().zip(pm1.enable.iter()) {
+        if status == 0 {
+            continue;
+        }
+        unsafe {
+            // presses from before boot are stale
+            Port::<u16>::new(status).write(buttons);
+            let mut enable = Port::<u16>::new(enable);
+            let bits = enable.read();
+            enable.write(bits | buttons);
+        }
+    }
+    info!(
+        "power: buttons on SCI {}{}{}",
+        fadt.sci_interrupt,
+        if buttons & PWRBTN != 0 { ", power" } else { "" },
+        if buttons & SLPBTN != 0 { ", sleep" } else { "" },
+    );
+    Ok(())
+}
+
+/// Runs on the shared SCI line; other ACPI events are not ours to clear.
+/// The task that would take a press is cancelled once a shutdown starts,
+/// so a power press during one cuts power here.
+fn sci() {
+    let pm1 = match PM1.get() {
+        Some(pm1) => pm1,
+        None => return,
+    };
+    for &status in pm1.status.iter().filter(|&&status| status != 0) {
+        let mut status = Port::<u16>::new(status);
+        let pressed = unsafe { status.read() } & pm1.buttons;
+        if pressed != 0 {
+            // write one to clear
+            unsafe { status.write(pressed) };
+            if pressed & PWRBTN != 0 && REQUEST.load(Ordering::SeqCst) != NO_REQUEST {
+                shutdown();
+            }
+            PRESSED.fetch_or(pressed, Ordering::SeqCst);
+            WAKER.wake();
+        }
+    }
+}
+
+/// Waits for the next press; a power press wins over a sleep press that
+/// came with it.
+pub fn next_press() -> NextPress {
+    NextPress
+}
+
+pub struct NextPress;
+
+impl Future for NextPress {
+    type Output = Button;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Button> {
+        WAKER.register(cx.waker());
+        let pressed = PRESSED.load(Ordering::SeqCst);
+        let button = if pressed & PWRBTN != 0 {
+            PWRBTN
+        } else if pressed & SLPBTN != 0 {
+            SLPBTN
+        } else {
+            return Poll::Pending;
+        };
+        PRESSED.fetch_and(!button, Ordering::SeqCst);
+        Poll::Ready(if button == PWRBTN {
+            Button::Power
+        } else {
+            Button::Sleep
+        })
+    }
+}
+
+/// The button policy: power shuts down gracefully. There is no sleep
+/// state to enter, so the sleep button is only logged.
+pub async fn button_task() {
+    loop {
+        match next_press().await {
+            Button::Power => {
+                info!("power: power button pressed");
+                request(Action::PowerOff);
+            }
+            Button::Sleep => info!("power: sleep button pressed, sleeping is not supported"),
+        }
+    }
+}
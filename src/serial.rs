@@ -1,20 +1,736 @@
-use lazy_static::lazy_static;
-use spin::Mutex;
-use uart_16550::SerialPort;
-
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
+use crate::{
+    console::{Input, LineDiscipline},
+    device::CharDevice,
+    shell::{self, parse_number, ShellErr},
+    sync::{Lazy, Once, TrackedMutex},
+};
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+use x86_64::instructions::{
+    interrupts::without_interrupts,
+    port::{Port, PortReadOnly},
+};
+
+pub mod report;
+pub mod xmodem;
+
+pub const COM1: u16 = 0x3F8;
+pub const COM2: u16 = 0x2F8;
+
+pub static SERIAL1: Lazy<TrackedMutex<SerialPort>> = Lazy::new(|| {
+    let mut serial_port = unsafe { SerialPort::new(COM1) };
+    serial_port.init(BaudRate::Baud115200);
+    TrackedMutex::new("SERIAL1", serial_port)
+});
+
+pub static SERIAL2: Lazy<TrackedMutex<SerialPort>> = Lazy::new(|| {
+    let mut serial_port = unsafe { SerialPort::new(COM2) };
+    serial_port.init(BaudRate::Baud115200);
+    TrackedMutex::new("SERIAL2", serial_port)
+});
+
+/// Divisor latch values for the 1.8432 MHz UART clock.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum BaudRate {
+    Baud115200 = 1,
+    Baud57600 = 2,
+    Baud38400 = 3,
+    Baud19200 = 6,
+    Baud9600 = 12,
+}
+
+/// The UART clock divided by 16.
+const BASE_BAUD: u32 = 115_200;
+
+impl BaudRate {
+    const ALL: [BaudRate; 5] = [
+        BaudRate::Baud115200,
+        BaudRate::Baud57600,
+        BaudRate::Baud38400,
+        BaudRate::Baud19200,
+        BaudRate::Baud9600,
+    ];
+
+    pub fn from_bps(bps: u32) -> Option<BaudRate> {
+        Self::ALL.iter().copied().find(|baud| baud.bps() == bps)
+    }
+
+    pub fn bps(self) -> u32 {
+        BASE_BAUD / self as u32
+    }
+}
+
+/// Bytes the transmit FIFO takes each time it empties.
+const TRANSMIT_FIFO: usize = 16;
+/// Polls of a low CTS before a direct send goes ahead anyway, about a
+/// second's worth: a panic must not hang on a peer that went away.
+const CTS_POLLS: u32 = 1_000_000;
+
+bitflags! {
+    /// Line control register: 8 data bits, no parity, one stop bit is `DATA_8`.
+    struct LineControl: u8 {
+        const DATA_5 = 0x00;
+        const DATA_6 = 0x01;
+        const DATA_7 = 0x02;
+        const DATA_8 = 0x03;
+        const TWO_STOP_BITS = 1 << 2;
+        const PARITY_ENABLE = 1 << 3;
+        const EVEN_PARITY = 1 << 4;
+        const DIVISOR_LATCH = 1 << 7;
+    }
+}
+
+bitflags! {
+    struct FifoControl: u8 {
+        const ENABLE = 1;
+        const CLEAR_RECEIVE = 1 << 1;
+        const CLEAR_TRANSMIT = 1 << 2;
+        const TRIGGER_14 = 0xC0;
+    }
+}
+
+bitflags! {
+    struct ModemControl: u8 {
+        const DATA_TERMINAL_READY = 1;
+        const REQUEST_TO_SEND = 1 << 1;
+        const AUX_OUTPUT_2 = 1 << 3;
+    }
+}
+
+bitflags! {
+    struct InterruptEnable: u8 {
+        const RECEIVED_DATA = 1;
+        const TRANSMIT_EMPTY = 1 << 1;
+        const LINE_STATUS = 1 << 2;
+        const MODEM_STATUS = 1 << 3;
+    }
+}
+
+bitflags! {
+    struct LineStatus: u8 {
+        const DATA_READY = 1;
+        const OVERRUN_ERROR = 1 << 1;
+        const OUTPUT_EMPTY = 1 << 5;
+    }
+}
+
+bitflags! {
+    struct ModemStatus: u8 {
+        const DELTA_CLEAR_TO_SEND = 1;
+        const CLEAR_TO_SEND = 1 << 4;
+    }
+}
+
+/// Interrupt identification: bit 0 is clear while one is pending.
+const NO_INTERRUPT_PENDING: u8 = 1;
+
+/// A 16550-compatible UART on the legacy I/O ports.
+///
+/// Sending blocks until the UART takes the byte, unless a transmit queue is
+/// attached: `write_buffered` then only queues, and the THR-empty interrupt
+/// drains the queue a FIFO load at a time. Anything sent directly first
+/// drains what is queued, so bytes never overtake each other.
+pub struct SerialPort {
+    data: Port<u8>,
+    int_enable: Port<u8>,
+    /// Interrupt identification when read.
+    fifo_ctrl: Port<u8>,
+    line_ctrl: Port<u8>,
+    modem_ctrl: Port<u8>,
+    line_status: PortReadOnly<u8>,
+    modem_status: PortReadOnly<u8>,
+    interrupts: InterruptEnable,
+    baud: BaudRate,
+    /// RTS/CTS: only send while the other end raises CTS.
+    flow_control: bool,
+    /// A direct send gave up waiting for CTS; the next ones do not wait
+    /// until it is seen raised again.
+    cts_lost: bool,
+    tx_queue: Option<&'static ArrayQueue<u8>>,
+}
+
+#[allow(dead_code)]
+impl SerialPort {
+    /// Creates a port handle for the UART at `base`.
+    ///
+    /// Unsafe because the caller must guarantee a UART lives at `base`.
+    pub const unsafe fn new(base: u16) -> Self {
+        SerialPort {
+            data: Port::new(base),
+            int_enable: Port::new(base + 1),
+            fifo_ctrl: Port::new(base + 2),
+            line_ctrl: Port::new(base + 3),
+            modem_ctrl: Port::new(base + 4),
+            line_status: PortReadOnly::new(base + 5),
+            modem_status: PortReadOnly::new(base + 6),
+            interrupts: InterruptEnable::empty(),
+            baud: BaudRate::Baud115200,
+            flow_control: false,
+            cts_lost: false,
+            tx_queue: None,
+        }
+    }
+
+    /// Programs the baud rate, 8N1 framing and the FIFOs, with interrupts off.
+    pub fn init(&mut self, baud: BaudRate) {
+        self.interrupts = InterruptEnable::empty();
+        unsafe { self.int_enable.write(0x00) };
+        self.set_baud(baud);
+        unsafe {
+            self.fifo_ctrl.write(
+                (FifoControl::ENABLE
+                    | FifoControl::CLEAR_RECEIVE
+                    | FifoControl::CLEAR_TRANSMIT
+                    | FifoControl::TRIGGER_14)
+                    .bits(),
+            );
+            self.modem_ctrl.write(
+                (ModemControl::DATA_TERMINAL_READY
+                    | ModemControl::REQUEST_TO_SEND
+                    | ModemControl::AUX_OUTPUT_2)
+                    .bits(),
+            );
+        }
+    }
+
+    /// Reprograms the divisor, after sending whatever is queued at the old
+    /// rate.
+    pub fn set_baud(&mut self, baud: BaudRate) {
+        self.flush();
+        let divisor = baud as u16;
+        unsafe {
+            self.line_ctrl.write(LineControl::DIVISOR_LATCH.bits());
+            self.data.write((divisor & 0xff) as u8);
+            self.int_enable.write((divisor >> 8) as u8);
+            self.line_ctrl.write(LineControl::DATA_8.bits());
+            // the divisor latch shadowed it
+            self.int_enable.write(self.interrupts.bits());
+        }
+        self.baud = baud;
+    }
+
+    pub fn baud(&self) -> BaudRate {
+        self.baud
+    }
+
+    /// Turns RTS/CTS flow control on or off. While it is on, nothing is
+    /// sent without CTS, though a direct send waits only so long for it.
+    pub fn set_flow_control(&mut self, on: bool) {
+        self.flow_control = on;
+        self.set_rts(true);
+        if self.interrupts.contains(InterruptEnable::RECEIVED_DATA) {
+            self.interrupts.set(InterruptEnable::MODEM_STATUS, on);
+            self.write_interrupts();
+        }
+    }
+
+    pub fn flow_control(&self) -> bool {
+        self.flow_control
+    }
+
+    /// Raises or drops RTS, telling the other end whether to send.
+    fn set_rts(&mut self, ready: bool) {
+        let mut control = ModemControl::DATA_TERMINAL_READY | ModemControl::AUX_OUTPUT_2;
+        control.set(ModemControl::REQUEST_TO_SEND, ready);
+        unsafe { self.modem_ctrl.write(control.bits()) }
+    }
+
+    fn clear_to_send(&mut self) -> bool {
+        !self.flow_control
+            || ModemStatus::from_bits_truncate(unsafe { self.modem_status.read() })
+                .contains(ModemStatus::CLEAR_TO_SEND)
+    }
+
+    fn write_interrupts(&mut self) {
+        unsafe { self.int_enable.write(self.interrupts.bits()) }
+    }
+
+    /// Raises IRQ 4/3 whenever a byte is received, and on CTS changes with
+    /// flow control on.
+    pub fn enable_receive_interrupt(&mut self) {
+        self.interrupts |= InterruptEnable::RECEIVED_DATA;
+        self.interrupts
+            .set(InterruptEnable::MODEM_STATUS, self.flow_control);
+        self.write_interrupts();
+    }
+
+    /// Queues bytes in `queue` for the transmit interrupt from now on.
+    /// Needs the receive interrupt on, since both share the handler.
+    pub fn attach_transmit_queue(&mut self, queue: &'static ArrayQueue<u8>) {
+        self.flush();
+        self.tx_queue = Some(queue);
+    }
+
+    /// Bytes queued and not yet handed to the UART.
+    pub fn queued(&self) -> usize {
+        self.tx_queue.map_or(0, |queue| queue.len())
+    }
+
+    fn line_status(&mut self) -> LineStatus {
+        LineStatus::from_bits_truncate(unsafe { self.line_status.read() })
+    }
+
+    /// Sends a byte as is, once the queued ones are out.
+    pub fn send_raw(&mut self, byte: u8) {
+        self.flush();
+        self.put(byte);
+    }
+
+    fn put(&mut self, byte: u8) {
+        let mut polls = 0;
+        loop {
+            if self.line_status().contains(LineStatus::OUTPUT_EMPTY) {
+                if self.clear_to_send() {
+                    self.cts_lost = false;
+                    break;
+                }
+                if self.cts_lost || polls == CTS_POLLS {
+                    self.cts_lost = true;
+                    break;
+                }
+                polls += 1;
+            }
+            crate::interrupts::pause();
+        }
+        unsafe { self.data.write(byte) }
+    }
+
+    /// Sends everything queued, polling; for when the interrupt cannot run.
+    pub fn flush(&mut self) {
+        if let Some(queue) = self.tx_queue {
+            while let Ok(byte) = queue.pop() {
+                self.put(byte);
+            }
+        }
+    }
+
+    /// Queues `bytes`, sending the oldest queued byte directly whenever the
+    /// queue is full. Sends directly without a queue.
+    pub fn write_buffered(&mut self, bytes: &[u8]) {
+        let queue = match self.tx_queue {
+            Some(queue) => queue,
+            None => {
+                bytes.iter().for_each(|&byte| self.put(byte));
+                return;
+            }
+        };
+        for &byte in bytes {
+            while queue.push(byte).is_err() {
+                if let Ok(oldest) = queue.pop() {
+                    self.put(oldest);
+                }
+            }
+        }
+        self.transmit();
+    }
+
+    /// Queues as much of `bytes` as fits, returning how much that was.
+    fn try_write_buffered(&mut self, bytes: &[u8]) -> usize {
+        let queue = match self.tx_queue {
+            Some(queue) => queue,
+            None => {
+                bytes.iter().for_each(|&byte| self.put(byte));
+                return bytes.len();
+            }
+        };
+        let queued = bytes
+            .iter()
+            .take_while(|&&byte| queue.push(byte).is_ok())
+            .count();
+        self.transmit();
+        queued
+    }
+
+    /// Moves queued bytes into the FIFO if it is empty, and keeps the
+    /// THR-empty interrupt on while more are waiting. Returns whether any
+    /// byte moved.
+    fn transmit(&mut self) -> bool {
+        let queue = match self.tx_queue {
+            Some(queue) => queue,
+            None => return false,
+        };
+        let mut moved = false;
+        let clear_to_send = self.clear_to_send();
+        if clear_to_send && self.line_status().contains(LineStatus::OUTPUT_EMPTY) {
+            for _ in 0..TRANSMIT_FIFO {
+                match queue.pop() {
+                    Ok(byte) => unsafe { self.data.write(byte) },
+                    Err(_) => break,
+                }
+                moved = true;
+            }
+        }
+        // without CTS the modem status interrupt restarts the queue
+        let waiting = !queue.is_empty() && clear_to_send;
+        if waiting != self.interrupts.contains(InterruptEnable::TRANSMIT_EMPTY) {
+            self.interrupts
+                .set(InterruptEnable::TRANSMIT_EMPTY, waiting);
+            self.write_interrupts();
+        }
+        moved
+    }
+
+    /// A writer that queues rather than waits, expanding `\n` like `send`.
+    pub fn buffered(&mut self) -> Buffered {
+        Buffered(self)
+    }
+
+    /// Sends a byte, expanding `\n` to `\r\n` for terminals.
+    pub fn send(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.send_raw(b'\r');
+        }
+        self.send_raw(byte);
+    }
+
+    /// Blocks until a byte has been received.
+    pub fn receive(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_receive() {
+                return byte;
+            }
+            crate::interrupts::pause();
+        }
+    }
+
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if self.line_status().contains(LineStatus::DATA_READY) {
+            Some(unsafe { self.data.read() })
+        } else {
+            None
+        }
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+    }
+}
+
+impl CharDevice for SerialPort {
+    fn put(&mut self, byte: u8) {
+        self.send_raw(byte);
+    }
+
+    fn get(&mut self) -> Option<u8> {
+        self.try_receive()
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        SerialPort::write_str(self, s);
+        Ok(())
+    }
+}
+
+/// See `SerialPort::buffered`.
+pub struct Buffered<'a>(&'a mut SerialPort);
+
+impl fmt::Write for Buffered<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.0.write_buffered(b"\r\n");
+            }
+            self.0.write_buffered(line.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+static RECEIVE_QUEUE: Once<ArrayQueue<u8>> = Once::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static TRANSMIT_QUEUE: Once<ArrayQueue<u8>> = Once::new();
+static TRANSMIT_WAKER: AtomicWaker = AtomicWaker::new();
+/// RTS is down because the receive queue is filling up.
+static THROTTLED: AtomicBool = AtomicBool::new(false);
+/// A binary transfer owns COM1; see `xmodem`.
+static RAW: AtomicBool = AtomicBool::new(false);
+
+fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::Relaxed);
+}
+
+/// Whether text output to COM1 is being dropped for a binary transfer.
+pub fn is_raw() -> bool {
+    RAW.load(Ordering::Relaxed)
+}
+
+/// Called by the serial interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn add_byte(byte: u8) {
+    if let Some(queue) = RECEIVE_QUEUE.get() {
+        if let Err(_) = queue.push(byte) {
+            warn!("serial receive queue full; dropping input");
+        } else {
+            WAKER.wake();
+        }
+    }
+}
+
+/// Whether the receive queue is three quarters full.
+fn receive_backlog() -> bool {
+    RECEIVE_QUEUE
+        .get()
+        .map_or(false, |queue| queue.len() * 4 >= queue.capacity() * 3)
+}
+
+/// COM1's interrupt: queues what arrived, refills the transmit FIFO and,
+/// with flow control, drops RTS while the reader falls behind. Returns
+/// whether the monitor hotkey came in.
+pub(crate) fn interrupt() -> bool {
+    let mut monitor = false;
+    let mut serial = SERIAL1.lock();
+    // edge-triggered, so every cause has to be handled before returning
+    while unsafe { serial.fifo_ctrl.read() } & NO_INTERRUPT_PENDING == 0 {
+        while let Some(byte) = serial.try_receive() {
+            if byte == crate::debug::monitor::SERIAL_HOTKEY {
+                monitor = true;
+            } else {
+                add_byte(byte);
+            }
+        }
+        if serial.transmit() {
+            TRANSMIT_WAKER.wake();
+        }
+    }
+    if serial.flow_control() && receive_backlog() && !THROTTLED.swap(true, Ordering::Relaxed) {
+        serial.set_rts(false);
+    }
+    monitor
+}
+
+/// Queues `bytes` on COM1, waiting for room rather than for the UART.
+pub async fn write_all(mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        let queued = without_interrupts(|| SERIAL1.lock().try_write_buffered(bytes));
+        bytes = &bytes[queued..];
+        if !bytes.is_empty() {
+            TransmitRoom.await;
+        }
+    }
+}
+
+/// Ready once COM1's transmit queue has room.
+struct TransmitRoom;
+
+impl Future for TransmitRoom {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let full = || TRANSMIT_QUEUE.get().map_or(false, |queue| queue.is_full());
+        if !full() {
+            return Poll::Ready(());
+        }
+        TRANSMIT_WAKER.register(cx.waker());
+        if full() {
+            Poll::Pending
+        } else {
+            TRANSMIT_WAKER.take();
+            Poll::Ready(())
+        }
+    }
+}
+
+/// Sets COM1 up as the command line says, gives it its transmit queue and
+/// turns its interrupt on. Runs with interrupts off, after the heap.
+pub fn init() {
+    let options = crate::boot::cmdline::options();
+    TRANSMIT_QUEUE.call_once(|| ArrayQueue::new(crate::config::serial::TRANSMIT_BUFFER));
+    {
+        let mut serial = SERIAL1.lock();
+        serial.set_baud(options.baud);
+        serial.set_flow_control(options.flow_control);
+        serial.enable_receive_interrupt();
+        if let Some(queue) = TRANSMIT_QUEUE.get() {
+            serial.attach_transmit_queue(queue);
+        }
+    }
+    shell::register(
+        "stty",
+        "stty [<baud>] [crtscts|-crtscts]\nShows or sets COM1's baud rate and RTS/CTS flow control.",
+        stty_command,
+    );
+}
+
+/// `stty [<baud>] [crtscts|-crtscts]`
+fn stty_command(args: &[&str], out: &mut dyn fmt::Write) -> Result<(), ShellErr> {
+    for &arg in args {
+        match arg {
+            "crtscts" => without_interrupts(|| SERIAL1.lock().set_flow_control(true)),
+            "-crtscts" => without_interrupts(|| SERIAL1.lock().set_flow_control(false)),
+            bps => {
+                let baud = BaudRate::from_bps(parse_number(bps)? as u32)
+                    .ok_or_else(|| ShellErr::new("baud rates: 115200 57600 38400 19200 9600"))?;
+                without_interrupts(|| SERIAL1.lock().set_baud(baud));
+            }
+        }
+    }
+    let (baud, flow_control, queued) = without_interrupts(|| {
+        let serial = SERIAL1.lock();
+        (serial.baud(), serial.flow_control(), serial.queued())
+    });
+    writeln!(
+        out,
+        "{} baud, {}crtscts, {} bytes queued",
+        baud.bps(),
+        if flow_control { "" } else { "-" },
+        queued
+    )?;
+    Ok(())
+}
+
+pub struct SerialStream {
+    _private: (),
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        assert!(
+            RECEIVE_QUEUE
+                .set(ArrayQueue::new(crate::config::serial::QUEUE_DEPTH))
+                .is_ok(),
+            "SerialStream::new should only be called once"
+        );
+        SerialStream { _private: () }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = RECEIVE_QUEUE
+            .get()
+            .expect("serial receive queue not initialized");
+
+        if THROTTLED.load(Ordering::Relaxed) && queue.len() * 4 < queue.capacity() {
+            THROTTLED.store(false, Ordering::Relaxed);
+            without_interrupts(|| SERIAL1.lock().set_rts(true));
+        }
+
+        // fast path
+        if let Ok(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKER.register(&cx.waker());
+        match queue.pop() {
+            Ok(byte) => {
+                WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
+
+const ESCAPE: u8 = 0x1b;
+
+/// Echoes lines typed on COM1 back to the host.
+///
+/// A line starting with ESC is not echoed but applied as log level
+/// directives, e.g. `ESC task::scheduler=trace`. `rx <path>` and
+/// `rb [dir]` start an XMODEM or YMODEM upload.
+pub async fn echo_serial_input() {
+    let mut bytes = SerialStream::new();
+    let mut discipline = LineDiscipline::new();
+
+    while let Some(byte) = bytes.next().await {
+        if byte == ESCAPE && discipline.line().is_empty() {
+            discipline.set_echo(false);
+            continue;
+        }
+        match discipline.push(byte as char, &mut Echo) {
+            Some(Input::Line(spec)) if !discipline.echo() => {
+                if let Err(err) = crate::logs::parse_directives(&spec) {
+                    warn!("invalid log directive {:?}: {:?}", spec, err);
+                }
+            }
+            Some(Input::Line(line)) => upload(&mut bytes, &line).await,
+            Some(_) => {}
+            None => continue,
+        }
+        discipline.set_echo(true);
+    }
+}
+
+/// Runs an upload if `line` asks for one.
+async fn upload(bytes: &mut SerialStream, line: &str) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["rx", path] => {
+            crate::serial_println!("rx: send {} with XMODEM", path);
+            match xmodem::receive(bytes, path).await {
+                Ok(size) => crate::serial_println!("rx: {} bytes to {}", size, path),
+                Err(err) => crate::serial_println!("rx: failed: {:?}", err),
+            }
+        }
+        ["rb"] | ["rb", _] => {
+            let dir = words.get(1).copied().unwrap_or("/tmp");
+            crate::serial_println!("rb: send the files with YMODEM");
+            match xmodem::receive_batch(bytes, dir).await {
+                Ok(files) => crate::serial_println!("rb: {} files to {}", files, dir),
+                Err(err) => crate::serial_println!("rb: failed: {:?}", err),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Echo for `echo_serial_input`, through `serial_print!`.
+struct Echo;
+
+impl fmt::Write for Echo {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::serial_print!("{}", s);
+        Ok(())
+    }
 }
 
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
-    SERIAL1
-        .try_lock()
-        .map(|mut lock| lock.write_fmt(args).expect("Printing to serial failed"));
+    use x86_64::instructions::interrupts;
+    if is_raw() {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        SERIAL1
+            .try_lock()
+            .map(|mut lock| lock.write_fmt(args).expect("Printing to serial failed"));
+    });
+}
+
+/// COM1, as the console of a headless machine.
+pub struct SerialConsole;
+
+pub static SERIAL_CONSOLE: SerialConsole = SerialConsole;
+
+impl crate::kernel::Console for SerialConsole {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn print(&self, args: fmt::Arguments) {
+        _print(args)
+    }
 }
 
 /// Prints to the host through the serial interface.
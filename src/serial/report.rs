@@ -0,0 +1,87 @@
+//! Statistics as CSV over COM1 every `report=<seconds>`, so a script on
+//! the host can graph a long soak run from the serial log alone.
+//!
+//! Every line starts with `stats,`, so `grep '^stats,'` pulls the table
+//! out of whatever else the port carries; the first line is the header:
+//!
+//! ```text
+//! stats,uptime_ms,unix_ms,tasks,ready,polls,runtime_us,heap_used,heap_free,interrupts
+//! ```
+//!
+//! `unix_ms` is 0 when the RTC could not be read. `polls` and `runtime_us`
+//! add up the live tasks' counters and `interrupts` counts from boot, so a
+//! rate is the difference between two lines. The heap columns are empty if
+//! the allocator was busy.
+
+use crate::{
+    allocators,
+    boot::cmdline,
+    interrupts,
+    task::scheduler::{self, TaskState},
+    time,
+};
+
+pub const HEADER: &str =
+    "stats,uptime_ms,unix_ms,tasks,ready,polls,runtime_us,heap_used,heap_free,interrupts";
+
+/// Writes a line every `report=` seconds; returns at once without one.
+pub async fn report_task() {
+    let period_ms = match cmdline::options()
+        .report_secs
+        .and_then(|secs| secs.checked_mul(1000))
+    {
+        Some(ms) => ms,
+        None => return,
+    };
+    crate::serial_println!("{}", HEADER);
+    let mut next = time::uptime_ms();
+    loop {
+        report();
+        // against a fixed schedule, so the lines do not drift apart
+        next = next.saturating_add(period_ms);
+        time::sleep(next.saturating_sub(time::uptime_ms())).await;
+    }
+}
+
+fn report() {
+    let tasks = scheduler::tasks();
+    let ready = tasks
+        .iter()
+        .filter(|task| task.state != TaskState::Pending)
+        .count();
+    let polls: u64 = tasks.iter().map(|task| task.polls).sum();
+    let runtime_us: u64 = tasks.iter().map(|task| task.runtime_us).sum();
+    let (heap_used, heap_free) = match allocators::try_usage() {
+        Some((used, free)) => (Some(used), Some(free)),
+        None => (None, None),
+    };
+    let (monotonic_ns, boot_unix_ns) = time::page::read(time::page::data());
+    let unix_ms = match boot_unix_ns {
+        0 => 0,
+        boot => (boot + monotonic_ns) / 1_000_000,
+    };
+    crate::serial_println!(
+        "stats,{},{},{},{},{},{},{},{},{}",
+        time::uptime_ms(),
+        unix_ms,
+        tasks.len(),
+        ready,
+        polls,
+        runtime_us,
+        Column(heap_used),
+        Column(heap_free),
+        interrupts::count()
+    );
+}
+
+/// A value, or nothing if it is unknown.
+struct Column(Option<usize>);
+
+impl core::fmt::Display for Column {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            Some(value) => write!(f, "{}", value),
+            None => Ok(()),
+        }
+    }
+}
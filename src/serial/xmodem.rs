@@ -0,0 +1,340 @@
+//! XMODEM and YMODEM receivers, for pushing files into the running
+//! kernel over COM1 with `sx`, `sb` or a terminal program's upload.
+//!
+//! Typed on COM1, `rx <path>` takes one file over XMODEM and `rb [dir]` a
+//! YMODEM batch into `dir`, `/tmp` by default, under the names the sender
+//! gives. Both ask for CRC-16 and take 1K blocks, falling back to the
+//! original checksum when the sender does not answer 'C'. Files are kept
+//! in memory until the transfer ends, then written in one go.
+//!
+//! Nothing else may reach COM1 while the protocol runs, so the port is in
+//! raw mode for the duration: prints and the serial log sink drop their
+//! output.
+
+use super::{set_raw, SerialStream, SERIAL1};
+use crate::{
+    fs::{self, path},
+    time,
+};
+use alloc::{string::String, vec::Vec};
+use core::str;
+use futures_util::{
+    future::{select, Either},
+    stream::StreamExt,
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Asks the sender for CRC-16 instead of the checksum.
+const CRC_REQUEST: u8 = b'C';
+/// XMODEM pads the last block with these.
+const PAD: u8 = 0x1a;
+
+/// Start requests sent before giving up, the first `CRC_TRIES` asking for
+/// CRC-16.
+const START_TRIES: usize = 10;
+const CRC_TRIES: usize = 3;
+const START_TIMEOUT_MS: u64 = 3000;
+const BYTE_TIMEOUT_MS: u64 = 1000;
+/// Bad or missing blocks in a row before the transfer is cancelled.
+const MAX_ERRORS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Timeout,
+    /// The sender sent CAN CAN.
+    Cancelled,
+    TooManyErrors,
+    /// A block came that was neither the next nor a repeat.
+    OutOfSequence,
+    /// The YMODEM header block did not hold a file name.
+    BadHeader,
+    Fs(fs::Error),
+}
+
+impl From<fs::Error> for Error {
+    fn from(err: fs::Error) -> Self {
+        Error::Fs(err)
+    }
+}
+
+enum Packet {
+    Block(u8, Vec<u8>),
+    EndOfFile,
+}
+
+/// Keeps COM1 raw while it lives.
+struct Raw;
+
+impl Raw {
+    fn enter() -> Self {
+        set_raw(true);
+        Raw
+    }
+}
+
+impl Drop for Raw {
+    fn drop(&mut self) {
+        set_raw(false);
+    }
+}
+
+struct Receiver<'a> {
+    bytes: &'a mut SerialStream,
+    crc: bool,
+    errors: usize,
+    _raw: Raw,
+}
+
+impl<'a> Receiver<'a> {
+    fn new(bytes: &'a mut SerialStream) -> Self {
+        Receiver {
+            bytes,
+            crc: true,
+            errors: 0,
+            _raw: Raw::enter(),
+        }
+    }
+
+    async fn byte(&mut self, timeout_ms: u64) -> Result<u8, Error> {
+        match select(self.bytes.next(), time::sleep(timeout_ms)).await {
+            Either::Left((Some(byte), _)) => Ok(byte),
+            Either::Left((None, _)) | Either::Right(_) => Err(Error::Timeout),
+        }
+    }
+
+    fn send(&self, byte: u8) {
+        without_interrupts(|| SERIAL1.lock().send_raw(byte));
+    }
+
+    fn cancel(&self) {
+        for _ in 0..3 {
+            self.send(CAN);
+        }
+    }
+
+    /// Counts a bad block, cancelling once there were too many.
+    fn error(&mut self) -> Result<(), Error> {
+        self.errors += 1;
+        if self.errors == MAX_ERRORS {
+            self.cancel();
+            return Err(Error::TooManyErrors);
+        }
+        Ok(())
+    }
+
+    /// Waits for the line to go quiet, so a NAK is not answered by the
+    /// rest of a broken block.
+    async fn purge(&mut self) {
+        while self.byte(BYTE_TIMEOUT_MS).await.is_ok() {}
+    }
+
+    /// Reads the packet `first` starts; `None` if it arrived damaged.
+    async fn packet(&mut self, first: u8) -> Result<Option<Packet>, Error> {
+        let len = match first {
+            SOH => 128,
+            STX => 1024,
+            EOT => return Ok(Some(Packet::EndOfFile)),
+            CAN => {
+                return match self.byte(BYTE_TIMEOUT_MS).await {
+                    Ok(CAN) => Err(Error::Cancelled),
+                    _ => Ok(None),
+                }
+            }
+            _ => {
+                self.purge().await;
+                return Ok(None);
+            }
+        };
+        let seq = self.byte(BYTE_TIMEOUT_MS).await?;
+        let complement = self.byte(BYTE_TIMEOUT_MS).await?;
+        let mut data = Vec::with_capacity(len);
+        for _ in 0..len {
+            data.push(self.byte(BYTE_TIMEOUT_MS).await?);
+        }
+        let valid = if self.crc {
+            let high = self.byte(BYTE_TIMEOUT_MS).await?;
+            let low = self.byte(BYTE_TIMEOUT_MS).await?;
+            crc16(&data) == u16::from_be_bytes([high, low])
+        } else {
+            let sum = self.byte(BYTE_TIMEOUT_MS).await?;
+            data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == sum
+        };
+        if seq != !complement || !valid {
+            self.purge().await;
+            return Ok(None);
+        }
+        Ok(Some(Packet::Block(seq, data)))
+    }
+
+    /// Asks the sender to start until it does, returning the first byte.
+    async fn start(&mut self) -> Result<u8, Error> {
+        for attempt in 0..START_TRIES {
+            self.crc = attempt < CRC_TRIES;
+            self.send(if self.crc { CRC_REQUEST } else { NAK });
+            match self.byte(START_TIMEOUT_MS).await {
+                Err(Error::Timeout) => continue,
+                result => return result,
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// After a YMODEM header, asks for the file's blocks.
+    fn start_data(&self) {
+        self.send(if self.crc { CRC_REQUEST } else { NAK });
+    }
+
+    /// Takes blocks from 1 up until EOT, appending them to `data`. YMODEM
+    /// senders expect the first EOT to be NAKed.
+    async fn blocks(
+        &mut self,
+        mut first: Option<u8>,
+        nak_first_eot: bool,
+        data: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let mut expected: u8 = 1;
+        let mut eot_seen = !nak_first_eot;
+        self.errors = 0;
+        loop {
+            let byte = match first.take() {
+                Some(byte) => byte,
+                None => match self.byte(START_TIMEOUT_MS).await {
+                    Ok(byte) => byte,
+                    Err(Error::Timeout) => {
+                        self.error()?;
+                        self.send(NAK);
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                },
+            };
+            match self.packet(byte).await? {
+                Some(Packet::EndOfFile) if eot_seen => {
+                    self.send(ACK);
+                    return Ok(());
+                }
+                Some(Packet::EndOfFile) => {
+                    eot_seen = true;
+                    self.send(NAK);
+                }
+                Some(Packet::Block(seq, block)) if seq == expected => {
+                    data.extend_from_slice(&block);
+                    expected = expected.wrapping_add(1);
+                    self.errors = 0;
+                    self.send(ACK);
+                }
+                // our ACK was lost
+                Some(Packet::Block(seq, _)) if seq == expected.wrapping_sub(1) => self.send(ACK),
+                Some(Packet::Block(..)) => {
+                    self.cancel();
+                    return Err(Error::OutOfSequence);
+                }
+                None => {
+                    self.error()?;
+                    self.send(NAK);
+                }
+            }
+        }
+    }
+
+    /// Reads a YMODEM header block: the file name and, maybe, its size.
+    /// `None` once the batch is over.
+    async fn header(&mut self) -> Result<Option<(String, Option<usize>)>, Error> {
+        self.errors = 0;
+        let mut first = Some(self.start().await?);
+        loop {
+            let byte = match first.take() {
+                Some(byte) => byte,
+                None => self.byte(START_TIMEOUT_MS).await?,
+            };
+            match self.packet(byte).await? {
+                Some(Packet::Block(0, block)) => {
+                    self.send(ACK);
+                    return parse_header(&block);
+                }
+                _ => {
+                    self.error()?;
+                    self.send(NAK);
+                }
+            }
+        }
+    }
+}
+
+/// `name NUL size ...`; an empty name ends the batch.
+fn parse_header(block: &[u8]) -> Result<Option<(String, Option<usize>)>, Error> {
+    let mut fields = block.split(|&b| b == 0);
+    let name = fields.next().unwrap_or(&[]);
+    if name.is_empty() {
+        return Ok(None);
+    }
+    let name = str::from_utf8(name).map_err(|_| Error::BadHeader)?;
+    // only the last component, so the sender cannot write elsewhere
+    let name = name
+        .rsplit('/')
+        .next()
+        .filter(|n| !["", ".", ".."].contains(n));
+    let name = name.ok_or(Error::BadHeader)?;
+    let size = fields
+        .next()
+        .and_then(|rest| rest.split(|&b| b == b' ').next())
+        .and_then(|size| str::from_utf8(size).ok())
+        .and_then(|size| size.parse().ok());
+    Ok(Some((String::from(name), size)))
+}
+
+/// CRC-16/XMODEM: polynomial 0x1021, starting from 0.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Receives one file over XMODEM into `path`, returning its size. The
+/// padding of the last block is stripped, so files ending in 0x1a lose it.
+pub async fn receive(bytes: &mut SerialStream, path: &str) -> Result<usize, Error> {
+    let mut data = Vec::new();
+    {
+        let mut receiver = Receiver::new(bytes);
+        let first = receiver.start().await?;
+        receiver.blocks(Some(first), false, &mut data).await?;
+    }
+    while data.last() == Some(&PAD) {
+        data.pop();
+    }
+    fs::write(path, &data)?;
+    Ok(data.len())
+}
+
+/// Receives a YMODEM batch into `dir`, returning how many files came.
+pub async fn receive_batch(bytes: &mut SerialStream, dir: &str) -> Result<usize, Error> {
+    let mut files = Vec::new();
+    {
+        let mut receiver = Receiver::new(bytes);
+        while let Some((name, size)) = receiver.header().await? {
+            let mut data = Vec::new();
+            receiver.start_data();
+            receiver.blocks(None, true, &mut data).await?;
+            if let Some(size) = size {
+                data.truncate(size);
+            }
+            files.push((name, data));
+        }
+    }
+    for (name, data) in files.iter() {
+        fs::write(&path::join(dir, name), data)?;
+    }
+    Ok(files.len())
+}
@@ -0,0 +1,85 @@
+use super::ShellErr;
+use alloc::{format, vec::Vec};
+use core::str::FromStr;
+
+/// Reads a number in decimal or, with a `0x` prefix, hexadecimal.
+pub fn parse_number(text: &str) -> Result<u64, ShellErr> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    }
+    .map_err(|_| ShellErr::from(format!("expected a number, got {}", text)))
+}
+
+/// A command's arguments, split into flags (`-v`, `--all`) and the words
+/// in between, which are taken in order. A command ends with `finish` to
+/// reject anything it did not ask for.
+pub struct Args<'a> {
+    words: Vec<&'a str>,
+    flags: Vec<&'a str>,
+    next: usize,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(args: &[&'a str]) -> Self {
+        let (flags, words) = args
+            .iter()
+            .copied()
+            .partition(|word: &&str| word.starts_with('-') && word.len() > 1);
+        Args {
+            words,
+            flags,
+            next: 0,
+        }
+    }
+
+    /// Whether `name` was given, consuming it.
+    pub fn flag(&mut self, name: &str) -> bool {
+        match self.flags.iter().position(|&flag| flag == name) {
+            Some(index) => {
+                self.flags.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn word(&mut self) -> Option<&'a str> {
+        let word = self.words.get(self.next).copied()?;
+        self.next += 1;
+        Some(word)
+    }
+
+    /// The next word, which the command cannot do without; `what` names it
+    /// in the error.
+    pub fn required(&mut self, what: &str) -> Result<&'a str, ShellErr> {
+        self.word()
+            .ok_or_else(|| ShellErr::from(format!("missing {}", what)))
+    }
+
+    pub fn number(&mut self, what: &str) -> Result<u64, ShellErr> {
+        parse_number(self.required(what)?)
+    }
+
+    pub fn optional_number(&mut self) -> Result<Option<u64>, ShellErr> {
+        self.word().map(parse_number).transpose()
+    }
+
+    /// The next word as any type that parses from a string.
+    pub fn parse<T: FromStr>(&mut self, what: &str) -> Result<T, ShellErr> {
+        let word = self.required(what)?;
+        word.parse()
+            .map_err(|_| ShellErr::from(format!("bad {}: {}", what, word)))
+    }
+
+    /// Fails if anything was given that the command did not ask for.
+    pub fn finish(self) -> Result<(), ShellErr> {
+        if let Some(flag) = self.flags.first() {
+            return Err(ShellErr::from(format!("unknown option {}", flag)));
+        }
+        if let Some(word) = self.words.get(self.next) {
+            return Err(ShellErr::from(format!("unexpected argument {}", word)));
+        }
+        Ok(())
+    }
+}
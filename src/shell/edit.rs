@@ -0,0 +1,363 @@
+//! `edit`, a full-screen editor in the style of nano, so config files and
+//! scripts in tmpfs or the initramfs can be changed during bring-up on a
+//! machine without a network.
+//!
+//! The command only checks the path and asks for the editor; the keyboard
+//! console opens it once the command returns, since the editor needs its
+//! keys, and puts the screen back after. Ctrl+S saves, Ctrl+X leaves (twice
+//! with unsaved changes), Ctrl+K cuts the line and Ctrl+U pastes it. Text
+//! is edited as bytes; typed characters outside ASCII are ignored.
+
+use super::{Keys, ShellErr};
+use crate::{
+    fs::{self, path},
+    sync::Mutex,
+    vga_buffer::{self, window::Window, Color, ColorCode, WRITER},
+};
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+use x86_64::instructions::interrupts::without_interrupts;
+
+const TAB_WIDTH: usize = 4;
+const HELP: &str = "^S save  ^X exit  ^K cut line  ^U paste  ^C position";
+const TEXT: ColorCode = ColorCode::new(Color::LightGray, Color::Black);
+const BAR: ColorCode = ColorCode::new(Color::Black, Color::LightGray);
+
+/// The file the console is to open once the current command returns.
+static REQUEST: Mutex<Option<String>> = Mutex::new(None);
+
+/// `edit <path>`
+pub fn command(args: &[&str], _out: &mut dyn Write) -> Result<(), ShellErr> {
+    let path = match args {
+        [path] => path::absolute(path).map_err(|err| format!("{:?}", err))?,
+        _ => return Err(ShellErr::new("usage: edit <path>")),
+    };
+    if crate::kernel::services().console().name() != "vga" {
+        return Err(ShellErr::new("the editor needs the screen"));
+    }
+    // fail here rather than on a blank screen
+    match fs::read_to_end(&path) {
+        Ok(_) | Err(fs::Error::NotFound) => {}
+        Err(err) => return Err(format!("{}: {:?}", path, err).into()),
+    }
+    *REQUEST.lock() = Some(path);
+    Ok(())
+}
+
+/// The path `edit` asked for, if the command just run did.
+pub(super) fn take_request() -> Option<String> {
+    REQUEST.lock().take()
+}
+
+/// Drops a request left by a command run where there is no editor, such
+/// as the remote console.
+pub(super) fn clear_request() {
+    REQUEST.lock().take();
+}
+
+/// Edits `path` on the whole screen until Ctrl+X.
+pub(super) async fn run(path: String, keys: &mut Keys) {
+    let saved = without_interrupts(|| Box::new(WRITER.lock().snapshot()));
+    let mut editor = Editor::open(path);
+    editor.draw();
+    while let Some(c) = keys.next().await {
+        if !editor.key(c) {
+            break;
+        }
+        editor.draw();
+    }
+    without_interrupts(|| WRITER.lock().restore(*saved));
+}
+
+#[derive(Clone, Copy)]
+enum Escape {
+    None,
+    /// After ESC.
+    Started,
+    /// After ESC [, with the number so far.
+    Csi(u32),
+}
+
+struct Editor {
+    path: String,
+    lines: Vec<Vec<u8>>,
+    /// Whether the file ends in a newline, kept when saving.
+    final_newline: bool,
+    /// The cursor, in bytes.
+    row: usize,
+    col: usize,
+    /// The first line and column on screen.
+    top: usize,
+    left: usize,
+    modified: bool,
+    /// Set by Ctrl+X on unsaved changes; a second one discards them.
+    quitting: bool,
+    cut: Option<Vec<u8>>,
+    status: String,
+    escape: Escape,
+    title: Window,
+    text: Window,
+    bar: Window,
+}
+
+impl Editor {
+    fn open(path: String) -> Self {
+        let (lines, final_newline, status) = match fs::read_to_end(&path) {
+            Ok(data) => {
+                let final_newline = data.last() == Some(&b'\n');
+                let mut lines: Vec<Vec<u8>> =
+                    data.split(|&byte| byte == b'\n').map(Vec::from).collect();
+                if final_newline {
+                    lines.pop();
+                }
+                if lines.is_empty() {
+                    lines.push(Vec::new());
+                }
+                let status = format!("{} lines", lines.len());
+                (lines, final_newline, status)
+            }
+            Err(_) => (vec![Vec::new()], true, String::from("new file")),
+        };
+        let height = vga_buffer::height();
+        let width = vga_buffer::BUFFER_WIDTH;
+        Editor {
+            path,
+            lines,
+            final_newline,
+            row: 0,
+            col: 0,
+            top: 0,
+            left: 0,
+            modified: false,
+            quitting: false,
+            cut: None,
+            status,
+            escape: Escape::None,
+            title: Window::new(0, 0, width, 1, BAR),
+            text: Window::new(1, 0, width, height - 2, TEXT),
+            bar: Window::new(height - 1, 0, width, 1, BAR),
+        }
+    }
+
+    /// Handles one typed character; false once the editor is to close.
+    fn key(&mut self, c: char) -> bool {
+        match (self.escape, c) {
+            (Escape::None, '\x1b') => {
+                self.escape = Escape::Started;
+                return true;
+            }
+            (Escape::None, _) => {}
+            (Escape::Started, '[') => {
+                self.escape = Escape::Csi(0);
+                return true;
+            }
+            (Escape::Csi(n), '0'..='9') => {
+                self.escape = Escape::Csi(n * 10 + c.to_digit(10).unwrap_or(0));
+                return true;
+            }
+            (Escape::Csi(n), c) => {
+                self.escape = Escape::None;
+                self.special(c, n);
+                return true;
+            }
+            (Escape::Started, _) => {
+                self.escape = Escape::None;
+                return true;
+            }
+        }
+        if c != '\x18' {
+            self.quitting = false;
+        }
+        match c {
+            '\x18' => {
+                if self.modified && !self.quitting {
+                    self.quitting = true;
+                    self.status = String::from("unsaved changes; ^X again to discard them");
+                    return true;
+                }
+                return false;
+            }
+            '\x13' => self.save(),
+            '\x0b' => self.cut_line(),
+            '\x15' => self.paste(),
+            '\x03' => {
+                self.status = format!(
+                    "line {}/{}, column {}",
+                    self.row + 1,
+                    self.lines.len(),
+                    self.col + 1
+                )
+            }
+            '\n' | '\r' => self.split_line(),
+            '\x08' => self.backspace(),
+            // what the layout makes of the Delete key
+            '\x7f' => self.delete(),
+            '\t' => {
+                let spaces = TAB_WIDTH - self.col % TAB_WIDTH;
+                for _ in 0..spaces {
+                    self.insert(b' ');
+                }
+            }
+            ' '..='~' => self.insert(c as u8),
+            _ => {}
+        }
+        true
+    }
+
+    /// Arrows, Home, End and the keys sent as `ESC [ n ~`.
+    fn special(&mut self, c: char, n: u32) {
+        let page = self.text.rows().saturating_sub(1).max(1);
+        match (c, n) {
+            ('A', _) => self.row = self.row.saturating_sub(1),
+            ('B', _) => self.row = (self.row + 1).min(self.lines.len() - 1),
+            ('C', _) if self.col < self.lines[self.row].len() => self.col += 1,
+            ('C', _) if self.row + 1 < self.lines.len() => {
+                self.row += 1;
+                self.col = 0;
+            }
+            ('D', _) if self.col > 0 => self.col -= 1,
+            ('D', _) if self.row > 0 => {
+                self.row -= 1;
+                self.col = self.lines[self.row].len();
+            }
+            ('H', _) => self.col = 0,
+            ('F', _) => self.col = self.lines[self.row].len(),
+            ('~', 3) => self.delete(),
+            ('~', 5) => self.row = self.row.saturating_sub(page),
+            ('~', 6) => self.row = (self.row + page).min(self.lines.len() - 1),
+            _ => {}
+        }
+        self.col = self.col.min(self.lines[self.row].len());
+    }
+
+    fn insert(&mut self, byte: u8) {
+        self.lines[self.row].insert(self.col, byte);
+        self.col += 1;
+        self.modified = true;
+    }
+
+    fn split_line(&mut self) {
+        let rest = self.lines[self.row].split_off(self.col);
+        self.row += 1;
+        self.col = 0;
+        self.lines.insert(self.row, rest);
+        self.modified = true;
+    }
+
+    fn backspace(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+            self.lines[self.row].remove(self.col);
+        } else if self.row > 0 {
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.col = self.lines[self.row].len();
+            self.lines[self.row].extend(line);
+        } else {
+            return;
+        }
+        self.modified = true;
+    }
+
+    fn delete(&mut self) {
+        if self.col < self.lines[self.row].len() {
+            self.lines[self.row].remove(self.col);
+        } else if self.row + 1 < self.lines.len() {
+            let line = self.lines.remove(self.row + 1);
+            self.lines[self.row].extend(line);
+        } else {
+            return;
+        }
+        self.modified = true;
+    }
+
+    fn cut_line(&mut self) {
+        let line = if self.lines.len() > 1 {
+            let line = self.lines.remove(self.row);
+            self.row = self.row.min(self.lines.len() - 1);
+            line
+        } else {
+            core::mem::replace(&mut self.lines[0], Vec::new())
+        };
+        self.col = 0;
+        self.cut = Some(line);
+        self.modified = true;
+    }
+
+    fn paste(&mut self) {
+        if let Some(line) = self.cut.clone() {
+            self.lines.insert(self.row, line);
+            self.row += 1;
+            self.col = 0;
+            self.modified = true;
+        }
+    }
+
+    fn save(&mut self) {
+        let mut data = self.lines.join(&b'\n');
+        if self.final_newline {
+            data.push(b'\n');
+        }
+        match fs::write(&self.path, &data) {
+            Ok(()) => {
+                self.modified = false;
+                self.status = format!("saved {} lines", self.lines.len());
+            }
+            Err(err) => self.status = format!("save failed: {:?}", err),
+        }
+    }
+
+    /// Redraws the screen with the cursor in view.
+    fn draw(&mut self) {
+        let (rows, columns) = (self.text.rows(), self.text.columns());
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + rows {
+            self.top = self.row + 1 - rows;
+        }
+        if self.col < self.left {
+            self.left = self.col;
+        } else if self.col >= self.left + columns {
+            self.left = self.col + 1 - columns;
+        }
+
+        let title = format!(
+            " edit {}{}",
+            self.path,
+            if self.modified { " (modified)" } else { "" }
+        );
+        let status = if self.status.is_empty() {
+            HELP
+        } else {
+            &self.status
+        };
+        without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            self.title.write_row(&mut writer, 0, title.as_bytes());
+            for screen_row in 0..rows {
+                let visible = self
+                    .lines
+                    .get(self.top + screen_row)
+                    .map(|line| visible_bytes(line, self.left))
+                    .unwrap_or_default();
+                self.text.write_row(&mut writer, screen_row, &visible);
+            }
+            self.bar.write_row(&mut writer, 0, status.as_bytes());
+            writer.flush();
+            writer.show_cursor();
+            self.text
+                .place_cursor(&mut writer, self.row - self.top, self.col - self.left);
+        });
+        // a message shows until the next key
+        self.status.clear();
+    }
+}
+
+/// The part of `line` from column `left`, with control bytes blanked so
+/// they cannot upset the screen.
+fn visible_bytes(line: &[u8], left: usize) -> Vec<u8> {
+    line.iter()
+        .skip(left)
+        .map(|&byte| if byte < b' ' { b' ' } else { byte })
+        .collect()
+}
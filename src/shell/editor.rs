@@ -0,0 +1,190 @@
+use super::{COMMANDS, PROMPT};
+use crate::{
+    console::{Input, LineDiscipline},
+    fs::{self, NodeKind},
+};
+use alloc::{collections::VecDeque, format, string::String, vec::Vec};
+use core::{fmt::Write, mem};
+
+/// Lines kept for recalling with up and down.
+const HISTORY: usize = 32;
+
+/// Where the editor is in an ANSI escape sequence, which is how arrow keys
+/// arrive from a terminal.
+#[derive(Clone, Copy)]
+enum Escape {
+    None,
+    /// After ESC.
+    Started,
+    /// After ESC [, until the final letter.
+    Csi,
+}
+
+/// The shell's line discipline: up and down through earlier lines and tab
+/// completion of command names and paths, on top of what every console
+/// does with typed characters.
+pub struct LineEditor {
+    discipline: LineDiscipline,
+    history: VecDeque<String>,
+    /// The history entry shown; `history.len()` for the line being typed.
+    browsing: usize,
+    /// The line being typed, put aside while browsing.
+    draft: String,
+    escape: Escape,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor {
+            discipline: LineDiscipline::new(),
+            history: VecDeque::new(),
+            browsing: 0,
+            draft: String::new(),
+            escape: Escape::None,
+        }
+    }
+
+    /// Returns what `c` finished: a line once it is a newline.
+    pub fn push(&mut self, c: char, echo: &mut dyn Write) -> Option<Input> {
+        match (self.escape, c) {
+            (Escape::None, _) => {}
+            (Escape::Started, '[') => {
+                self.escape = Escape::Csi;
+                return None;
+            }
+            (Escape::Csi, '0'..='9') | (Escape::Csi, ';') => return None,
+            (Escape::Csi, c) => {
+                self.escape = Escape::None;
+                match c {
+                    'A' => self.recall(true, echo),
+                    'B' => self.recall(false, echo),
+                    _ => {}
+                }
+                return None;
+            }
+            (Escape::Started, _) => {
+                self.escape = Escape::None;
+                return None;
+            }
+        }
+        match c {
+            '\x1b' => {
+                self.escape = Escape::Started;
+                None
+            }
+            '\t' => {
+                self.complete(echo);
+                None
+            }
+            c => {
+                let input = self.discipline.push(c, echo)?;
+                match &input {
+                    Input::Line(line) => self.remember(line),
+                    _ => self.remember(""),
+                }
+                Some(input)
+            }
+        }
+    }
+
+    fn remember(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.history.back().map(String::as_str) != Some(line) {
+            if self.history.len() == HISTORY {
+                self.history.pop_front();
+            }
+            self.history.push_back(String::from(line));
+        }
+        self.browsing = self.history.len();
+        self.draft.clear();
+    }
+
+    /// Shows the previous (`older`) or next history entry in place of the
+    /// line; past the newest comes back to the line being typed.
+    fn recall(&mut self, older: bool, echo: &mut dyn Write) {
+        let index = match older {
+            true if self.browsing > 0 => self.browsing - 1,
+            false if self.browsing < self.history.len() => self.browsing + 1,
+            _ => return,
+        };
+        if self.browsing == self.history.len() {
+            self.draft = String::from(self.discipline.line());
+        }
+        let line = match self.history.get(index) {
+            Some(line) => line.clone(),
+            None => mem::replace(&mut self.draft, String::new()),
+        };
+        self.browsing = index;
+        self.discipline.replace(line, echo);
+    }
+
+    /// Completes the last word as far as every candidate agrees: a command
+    /// name for the first word, a path for a later one starting with `/`.
+    /// When that adds nothing, lists the candidates.
+    fn complete(&mut self, echo: &mut dyn Write) {
+        let line = self.discipline.line();
+        let start = line.rfind(' ').map_or(0, |space| space + 1);
+        let word = &line[start..];
+        let candidates: Vec<String> = if start == 0 {
+            COMMANDS
+                .read()
+                .keys()
+                .filter(|keyword| keyword.starts_with(word))
+                .map(|&keyword| format!("{} ", keyword))
+                .collect()
+        } else if word.starts_with('/') {
+            paths(word)
+        } else {
+            return;
+        };
+        let common = match candidates.first() {
+            Some(first) => candidates.iter().fold(first.as_str(), |common, candidate| {
+                common_prefix(common, candidate)
+            }),
+            None => return,
+        };
+        if common.len() > word.len() {
+            let rest = String::from(&common[word.len()..]);
+            self.discipline.insert_str(&rest, echo);
+        } else if candidates.len() > 1 {
+            let _ = echo.write_char('\n');
+            for candidate in candidates.iter() {
+                let _ = write!(echo, "{}  ", candidate.trim_end());
+            }
+            let _ = write!(echo, "\n{}{}", PROMPT, self.discipline.line());
+        }
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Entries of the directory `word` is in that start like its last part,
+/// directories with a `/` after them.
+fn paths(word: &str) -> Vec<String> {
+    let split = word.rfind('/').map_or(0, |slash| slash + 1);
+    let (dir, prefix) = word.split_at(split);
+    let entries = match fs::readdir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .into_iter()
+        .filter(|entry| entry.name.starts_with(prefix))
+        .map(|entry| match entry.kind {
+            NodeKind::Directory => format!("{}{}/", dir, entry.name),
+            _ => format!("{}{} ", dir, entry.name),
+        })
+        .collect()
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|&((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((index, _), _)| index);
+    &a[..len]
+}
@@ -1,123 +1,404 @@
-// use core::str::SplitWhitespace;
+use crate::sync::{Lazy, RwLock};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use futures_util::{
+    future::{select, Either},
+    pin_mut,
+    stream::StreamExt,
+};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
+use x86_64::instructions::interrupts::without_interrupts;
 
-// use alloc::collections::BTreeMap;
-// use alloc::{string::String, vec::Vec};
-// use lazy_static::lazy_static;
-// use task::keyboard::keyboard_interaction;
+use crate::{
+    console::Input, device::keyboard::KeyEventStream, error::KernelError, task::supervisor, time,
+    vga_buffer,
+};
 
 // mod ascii_fluid;
+mod args;
+mod edit;
+mod editor;
+mod script;
 
-// #[derive(Debug, Clone)]
-// pub struct ShellErr {
-//     message: String,
-// }
-
-// /// ShellCommand is the wrapper around each command callable from the shell
-// ///
-// /// *Attributes*
-// /// - `keyword` keyword through which one can call the command
-// /// - `help` help message displayd when the execution of the command returns an error
-// /// - `function` main function of the command
-// #[derive(Clone, Debug)]
-// pub struct ShellCommand {
-//     pub keyword: String,
-//     pub help: String,
-//     pub function: fn(Vec<String>) -> Result<(), ShellErr>,
-// }
-
-// /// Test command
-// pub fn _test1(_a: Vec<String>) -> Result<(), ShellErr> {
-//     println!("test");
-//     Ok(())
-// }
-
-// #[allow(unreachable_code)]
-// pub fn ascii(_: Vec<String>) -> Result<(), ShellErr> {
-//     ascii_fluid::main();
-//     Ok(())
-// }
-
-// /// Help command
-// ///
-// /// `help [keyword]` prints the help string provided for the associated command.
-// /// Prints error messages if invalid input.
-// pub fn help(list: Vec<String>) -> Result<(), ShellErr> {
-//     match list.get(0) {
-//         Some(a) => match COMMANDS.get(a) {
-//             Some(command) => {
-//                 println!("{} : \n {}", command.keyword, command.help);
-//             }
-//             None => println!("No such command."),
-//         },
-//         None => println!("Expected command keyword."),
-//     }
-//     Ok(())
-// }
-
-// lazy_static! {
-//     /// Main BTreeMap. Contains the bindings `keyword` <=> `command`
-//     pub static ref COMMANDS: BTreeMap<String, ShellCommand> = {
-//         let mut commands = BTreeMap::new();
-//         let test_command = ShellCommand {
-//             keyword: String::from("test"),
-//             help: String::from("A simple test function."),
-//             function: _test1,
-//         };
-//         commands.insert(String::from("test"), test_command);
-//         let test_command = ShellCommand {
-//             keyword: String::from("ascii"),
-//             help: String::from("Launches the ASCIIfluid program."),
-//             function: ascii,
-//         };
-//         commands.insert(String::from("ascii"), test_command);
-//         let test_command = ShellCommand {
-//             keyword: String::from("help"),
-//             help: String::from("help [function_name]\nPrints the help indcations for any function."),
-//             function: help,
-//         };
-//         commands.insert(String::from("help"), test_command);
-//         commands
-//     };
-// }
-
-// /// Entry function of the shell
-// ///
-// /// TODO : clean it and make it more general
-// pub fn main_shell() {
-//     println!(":( :( :( :(");
-//     let _utilisateur = keyboard_interaction::get_input("pseudo : ", false);
-//     println!(":( :( :( :( :(");
-//     println!();
-//     let _mpd = keyboard_interaction::get_input("mdp : ", true);
-//     _main_loop();
-// }
-
-// /// Main Read-Evaluate-Print loop of the shell.
-// ///
-// /// The user can write comands.
-// /// The first word is the keywords, which indicates which (software-defined) programed is called
-// pub fn _main_loop() -> ! {
-//     loop {
-//         let a = keyboard_interaction::get_input(">> ", false);
-//         let mut it = _parse_input_into_vec(&a);
-//         match it.next() {
-//             Some(a) => match COMMANDS.get(a) {
-//                 Some(command) => {
-//                     let func = command.function;
-//                     match func(it.map(String::from).collect::<Vec<String>>()) {
-//                         Ok(()) => (),
-//                         _ => println!("{}", command.help),
-//                     }
-//                 }
-//                 None => println!("No such command."),
-//             },
-//             None => println!("Empty command."),
-//         }
-//     }
-// }
-
-// /// Temporary function.
-// /// Will be modified or removed in the future
-// pub fn _parse_input_into_vec(s: &str) -> SplitWhitespace {
-//     s.split_whitespace()
-// }
+pub use self::{
+    args::{parse_number, Args},
+    editor::LineEditor,
+    script::script_task,
+};
+
+pub const PROMPT: &str = ">> ";
+
+/// How soon the console is to run the current command again, in
+/// milliseconds; 0 when it is not.
+static REFRESH_MS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone)]
+pub struct ShellErr {
+    message: String,
+}
+
+impl ShellErr {
+    pub fn new(message: &str) -> Self {
+        ShellErr {
+            message: String::from(message),
+        }
+    }
+}
+
+impl fmt::Display for ShellErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<String> for ShellErr {
+    fn from(message: String) -> Self {
+        ShellErr { message }
+    }
+}
+
+impl From<KernelError> for ShellErr {
+    fn from(err: KernelError) -> Self {
+        ShellErr::new(err.message())
+    }
+}
+
+impl From<fmt::Error> for ShellErr {
+    fn from(_: fmt::Error) -> Self {
+        ShellErr::new("output failed")
+    }
+}
+
+/// Commands get their arguments and write their output to `out`, so the
+/// same command works on the VGA console and over the network.
+pub type CommandFn = fn(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr>;
+
+/// ShellCommand is the wrapper around each command callable from the shell
+///
+/// *Attributes*
+/// - `keyword` keyword through which one can call the command
+/// - `help` help message displayed when the execution of the command returns an error
+/// - `function` main function of the command
+#[derive(Clone, Copy)]
+pub struct ShellCommand {
+    pub keyword: &'static str,
+    pub help: &'static str,
+    pub function: CommandFn,
+}
+
+/// Help command
+///
+/// `help [keyword]` prints the help string provided for the associated command,
+/// or lists every command.
+fn help(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let commands = COMMANDS.read();
+    match args.get(0) {
+        Some(keyword) => match commands.get(keyword) {
+            Some(command) => writeln!(out, "{} :\n {}", command.keyword, command.help)?,
+            None => writeln!(out, "No such command.")?,
+        },
+        None => {
+            for keyword in commands.keys() {
+                writeln!(out, "{}", keyword)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn echo(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    writeln!(out, "{}", args.join(" "))?;
+    Ok(())
+}
+
+/// Main BTreeMap. Contains the bindings `keyword` <=> `command`
+#[rustfmt::skip]
+static COMMANDS: Lazy<RwLock<BTreeMap<&'static str, ShellCommand>>> = Lazy::new(|| {
+    let mut commands = BTreeMap::new();
+    commands.insert("help", ShellCommand {
+        keyword: "help",
+        help: "help [function_name]\nPrints the help indications for any function.",
+        function: help,
+    });
+    commands.insert("echo", ShellCommand {
+        keyword: "echo",
+        help: "echo [words...]\nPrints its arguments.",
+        function: echo,
+    });
+    commands.insert("cd", ShellCommand {
+        keyword: "cd",
+        help: "cd [path]\nChanges the working directory relative paths start from; / without a path.",
+        function: crate::fs::fd::cd_command,
+    });
+    commands.insert("pwd", ShellCommand {
+        keyword: "pwd",
+        help: "pwd\nPrints the working directory.",
+        function: crate::fs::fd::pwd_command,
+    });
+    commands.insert("edit", ShellCommand {
+        keyword: "edit",
+        help: "edit <path>\nEdits a file on the whole screen: ^S saves, ^X exits, ^K and ^U cut and paste lines.",
+        function: edit::command,
+    });
+    commands.insert("trace", ShellCommand {
+        keyword: "trace",
+        help: "trace [on|off|clear|dump]\nControls the tracepoints and dumps their records.",
+        function: crate::trace::command,
+    });
+    commands.insert("fault", ShellCommand {
+        keyword: "fault",
+        help: "fault [frame|heap|io off|every <n>|prob <permille>]\nInjects allocation and I/O failures; needs the fault-injection feature.",
+        function: crate::debug::fault::command,
+    });
+    commands.insert("msr", ShellCommand {
+        keyword: "msr",
+        help: "msr [<name>|<index> [value]]\nReads or writes allow-listed MSRs; debug builds only.",
+        function: crate::cpu::msr::command,
+    });
+    commands.insert("pmu", ShellCommand {
+        keyword: "pmu",
+        help: "pmu\nShows the performance counters and the fixed counters' values.",
+        function: crate::cpu::pmu::command,
+    });
+    commands.insert("idle", ShellCommand {
+        keyword: "idle",
+        help: "idle [latency|power]\nShows idle residency per C-state, or sets the idle policy.",
+        function: crate::cpu::idle::command,
+    });
+    commands.insert("thermal", ShellCommand {
+        keyword: "thermal",
+        help: "thermal\nShows the CPU temperature from the digital thermal sensors and the throttling seen.",
+        function: crate::cpu::thermal::command,
+    });
+    commands.insert("shutdown", ShellCommand {
+        keyword: "shutdown",
+        help: "shutdown\nPowers the machine off.",
+        function: crate::power::shutdown_command,
+    });
+    commands.insert("reboot", ShellCommand {
+        keyword: "reboot",
+        help: "reboot\nResets the machine.",
+        function: crate::power::reboot_command,
+    });
+    commands.insert("palette", ShellCommand {
+        keyword: "palette",
+        help: "palette [default|solarized|blink on|off|<color> <rrggbb>]\nShows or sets the sixteen text colors; with blink off, bright backgrounds work.",
+        function: crate::vga_buffer::palette::command,
+    });
+    commands.insert("vgamode", ShellCommand {
+        keyword: "vgamode",
+        help: "vgamode [25|50]\nShows the text mode, or switches to 80x25 or 80x50.",
+        function: crate::vga_buffer::mode::command,
+    });
+    commands.insert("modules", ShellCommand {
+        keyword: "modules",
+        help: "modules\nLists the modules the bootloader passed.",
+        function: crate::boot::modules_command,
+    });
+    commands.insert("kobjects", ShellCommand {
+        keyword: "kobjects",
+        help: "kobjects\nCounts the live kernel objects of each kind.",
+        function: crate::kobject::kobjects_command,
+    });
+    RwLock::new(commands)
+});
+
+/// Adds a command, so a subsystem can contribute its own diagnostics from
+/// its init function. Needs the heap; taking a keyword twice is a bug.
+pub fn register(keyword: &'static str, help: &'static str, function: CommandFn) {
+    let command = ShellCommand {
+        keyword,
+        help,
+        function,
+    };
+    if COMMANDS.write().insert(keyword, command).is_some() {
+        panic!("shell command {} registered twice", keyword);
+    }
+}
+
+/// Asks the console to run the current command again after `ms`, on a
+/// clear screen, until a key is pressed; for views such as `top`. Over the
+/// remote console the command runs once.
+pub fn refresh(ms: u64) {
+    REFRESH_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Runs one command line, writing everything it prints to `out`.
+///
+/// The first word is the keyword, which indicates which command is called.
+pub fn execute(line: &str, out: &mut dyn Write) {
+    REFRESH_MS.store(0, Ordering::Relaxed);
+    edit::clear_request();
+    let mut words = line.split_whitespace();
+    let keyword = match words.next() {
+        Some(keyword) => keyword,
+        None => return,
+    };
+    let args = words.collect::<Vec<&str>>();
+    let command = COMMANDS.read().get(keyword).copied();
+    let _ = match command {
+        Some(command) => match (command.function)(&args, out) {
+            Ok(()) => Ok(()),
+            Err(err) => writeln!(out, "{}: {}\n{}", keyword, err, command.help),
+        },
+        None => writeln!(out, "No such command."),
+    };
+}
+
+/// Writes to the VGA console.
+pub struct Console;
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+        Ok(())
+    }
+}
+
+/// Lines shown before paging waits, leaving a row for the prompt; a
+/// console other than the screen scrolls back on its own.
+fn page_lines() -> usize {
+    if crate::kernel::services().console().name() != "vga" {
+        return usize::MAX;
+    }
+    vga_buffer::height() - 1
+}
+
+/// Characters typed on the keyboard. Arrow keys come out as the escape
+/// sequences a terminal sends for them and Ctrl+letter as the control
+/// character, so `LineEditor` handles both alike.
+struct Keys {
+    events: KeyEventStream,
+    /// Only its layout is used; the events come decoded.
+    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
+    /// The rest of an escape sequence being returned.
+    pending: &'static str,
+}
+
+impl Keys {
+    fn new() -> Self {
+        Keys {
+            events: KeyEventStream::new(),
+            keyboard: Keyboard::new(
+                layouts::Us104Key,
+                ScancodeSet1,
+                HandleControl::MapLettersToUnicode,
+            ),
+            pending: "",
+        }
+    }
+
+    async fn next(&mut self) -> Option<char> {
+        if let Some(c) = self.pending.chars().next() {
+            self.pending = &self.pending[c.len_utf8()..];
+            return Some(c);
+        }
+        loop {
+            // checks in while waiting, so a quiet keyboard is not a hung shell
+            supervisor::check_in("shell");
+            let event = {
+                let next = self.events.next();
+                pin_mut!(next);
+                match select(next, time::sleep(supervisor::CHECK_IN_MS)).await {
+                    Either::Left((Some(event), _)) => event,
+                    Either::Left((None, _)) => return None,
+                    Either::Right(_) => continue,
+                }
+            };
+            match self.keyboard.process_keyevent(event) {
+                Some(DecodedKey::Unicode(c)) => return Some(c),
+                Some(DecodedKey::RawKey(KeyCode::ArrowUp)) => self.pending = "[A",
+                Some(DecodedKey::RawKey(KeyCode::ArrowDown)) => self.pending = "[B",
+                Some(DecodedKey::RawKey(KeyCode::ArrowRight)) => self.pending = "[C",
+                Some(DecodedKey::RawKey(KeyCode::ArrowLeft)) => self.pending = "[D",
+                Some(DecodedKey::RawKey(KeyCode::Home)) => self.pending = "[H",
+                Some(DecodedKey::RawKey(KeyCode::End)) => self.pending = "[F",
+                Some(DecodedKey::RawKey(KeyCode::PageUp)) => self.pending = "[5~",
+                Some(DecodedKey::RawKey(KeyCode::PageDown)) => self.pending = "[6~",
+                _ => continue,
+            }
+            return Some('\x1b');
+        }
+    }
+}
+
+/// Prints a command's output a screen at a time: space shows the next
+/// screen, enter the next line, and q or Ctrl+C drops the rest.
+async fn page(output: &str, keys: &mut Keys) {
+    let mut lines = output.lines().peekable();
+    let mut left = page_lines();
+    while let Some(line) = lines.next() {
+        println!("{}", line);
+        left -= 1;
+        if left > 0 || lines.peek().is_none() {
+            continue;
+        }
+        print!("-- more --");
+        left = loop {
+            match keys.next().await {
+                Some(' ') => break page_lines(),
+                Some('\n') | Some('\r') => break 1,
+                Some('q') | Some('\x03') | None => break 0,
+                Some(_) => {}
+            }
+        };
+        without_interrupts(|| vga_buffer::WRITER.lock().clear_line());
+        if left == 0 {
+            return;
+        }
+    }
+}
+
+/// Runs `line` again on a clear screen for as long as it asks to be
+/// refreshed and no key is pressed.
+async fn refresh_until_key(line: &str, keys: &mut Keys) {
+    loop {
+        let ms = REFRESH_MS.load(Ordering::Relaxed);
+        if ms == 0 {
+            return;
+        }
+        let key = keys.next();
+        pin_mut!(key);
+        if let Either::Left(_) = select(key, time::sleep(ms)).await {
+            return;
+        }
+        let mut output = String::new();
+        execute(line, &mut output);
+        without_interrupts(|| {
+            let mut writer = vga_buffer::WRITER.lock();
+            writer.clear_screen();
+            writer.set_position(0, 0);
+        });
+        print!("{}", output);
+    }
+}
+
+/// Main Read-Evaluate-Print loop of the shell on the keyboard and screen.
+pub async fn console_task() {
+    let mut keys = Keys::new();
+    let mut editor = LineEditor::new();
+
+    print!("{}", PROMPT);
+    while let Some(c) = keys.next().await {
+        match editor.push(c, &mut Console) {
+            Some(Input::Line(line)) => {
+                let mut output = String::new();
+                execute(&line, &mut output);
+                match edit::take_request() {
+                    Some(path) => edit::run(path, &mut keys).await,
+                    None => {
+                        page(&output, &mut keys).await;
+                        refresh_until_key(&line, &mut keys).await;
+                    }
+                }
+            }
+            // the keyboard cannot be closed
+            Some(Input::Interrupt) | Some(Input::EndOfFile) => {}
+            None => continue,
+        }
+        print!("{}", PROMPT);
+    }
+}
@@ -0,0 +1,71 @@
+//! Shell commands run at boot, so automated runs can set things up and
+//! start tests without a keyboard.
+//!
+//! The script comes from the file named by `script=` on the command line,
+//! usually in the initramfs, followed by the commands of `sh=`, which are
+//! separated by `;` with `,` standing for spaces: `sh=ps;hexdump,0x1000`.
+//! Blank lines and lines starting with `#` are skipped, and `sleep <ms>`
+//! pauses the script without holding up other tasks. Commands echo to the
+//! screen and the serial port.
+
+use super::{execute, PROMPT};
+use crate::{boot::cmdline, fs, task, time};
+use alloc::{string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+/// Writes to the screen and the first serial port, where a test harness
+/// reads it.
+struct Output;
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+        crate::serial_print!("{}", s);
+        Ok(())
+    }
+}
+
+/// The script's lines, from the file first.
+fn lines() -> Vec<String> {
+    let options = cmdline::options();
+    let mut lines = Vec::new();
+    if let Some(path) = options.script {
+        match fs::read_to_end(path) {
+            Ok(bytes) => match String::from_utf8(bytes) {
+                Ok(text) => lines.extend(text.lines().map(String::from)),
+                Err(_) => warn!("script {}: not UTF-8", path),
+            },
+            Err(err) => warn!("script {}: {:?}", path, err),
+        }
+    }
+    if let Some(commands) = options.sh {
+        lines.extend(commands.split(';').map(|line| line.replace(',', " ")));
+    }
+    lines
+}
+
+/// Runs the boot script, one command per poll.
+pub async fn script_task() {
+    for line in lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if task::cancelled() {
+            return;
+        }
+        let _ = writeln!(Output, "{}{}", PROMPT, line);
+        let mut words = line.split_whitespace();
+        if words.next() == Some("sleep") {
+            match words.next().and_then(|ms| ms.parse().ok()) {
+                Some(ms) => time::sleep(ms).await,
+                None => {
+                    let _ = writeln!(Output, "sleep: expected milliseconds");
+                }
+            }
+            continue;
+        }
+        execute(line, &mut Output);
+        task::yield_init().await;
+    }
+}
@@ -0,0 +1,53 @@
+//! Synchronization primitives, in one place so modules agree on them.
+//!
+//! - `Mutex`: a plain spinlock, for data shared with interrupt handlers
+//!   (take it with interrupts off) or private to a subsystem; import
+//!   `TryLockFor` to bound a wait on it
+//! - `TrackedMutex`: the `debug::lockdep` spinlock, which catches
+//!   self-deadlocks and lock-order inversions in debug builds, and whose
+//!   `try_lock_for` reports the holder when a wait runs too long
+//! - `RwLock`: many readers or one writer; writers go first once waiting
+//! - `SeqLock`: a small `Copy` value such as a clock, written by an
+//!   interrupt handler and read without taking anything
+//! - `Once`: a value set once, readable from interrupt handlers without
+//!   blocking
+//! - `InitCell`: a `Once` for globals that boot sets up, panicking with
+//!   the cell's name when used too early or set twice
+//! - `Lazy`: a `Once` that builds its value on first access, in place of
+//!   `lazy_static!`
+//! - `Rcu`: a value read without locks on hot paths, replaced by copying
+//!   it; old versions are freed at the schedulers' quiescent points
+//! - `Event`: a flag an interrupt handler signals and a task awaits, the
+//!   usual way for a driver to report that a command finished
+//! - `Barrier`, `Rendezvous`: let tasks wait for each other, a whole
+//!   group at a time or one sender and one receiver
+//! - `MpscQueue`: a bounded lock-free queue that interrupt handlers push to
+//!   and one task drains, counting what it had to refuse
+//!
+//! This kernel runs on one CPU, where spinning on a lock or an
+//! initialization the interrupted code holds can only hang; the primitives
+//! here panic in that case instead where they can tell.
+
+mod barrier;
+mod event;
+mod init_cell;
+mod mpsc;
+mod once;
+pub mod rcu;
+mod rwlock;
+mod seqlock;
+mod timeout;
+
+pub use self::{
+    barrier::{Barrier, BarrierWait, BarrierWaitResult, Rendezvous},
+    event::Event,
+    init_cell::InitCell,
+    mpsc::{MpscQueue, MpscSlot},
+    once::{Lazy, Once},
+    rcu::{Rcu, RcuReadGuard},
+    rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    seqlock::SeqLock,
+    timeout::TryLockFor,
+};
+pub use crate::debug::lockdep::{Mutex as TrackedMutex, MutexGuard as TrackedMutexGuard};
+pub use spin::{Mutex, MutexGuard};
@@ -0,0 +1,201 @@
+use super::Mutex;
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// Adds `waker` unless one that wakes the same task is there already, so a
+/// task polled many times is woken once.
+fn register(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|w| w.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
+
+fn wake_all(wakers: &mut Vec<Waker>) {
+    wakers.drain(..).for_each(Waker::wake);
+}
+
+struct BarrierState {
+    arrived: usize,
+    /// Counts completed rounds, so waiters can tell theirs has finished.
+    generation: u64,
+    wakers: Vec<Waker>,
+}
+
+/// Lets a fixed number of tasks wait until all of them have reached the
+/// same point, then releases them together. It can be used for any number
+/// of rounds.
+///
+/// A task that arrives and then drops its `wait` future still counts
+/// towards the round.
+pub struct Barrier {
+    parties: usize,
+    state: Mutex<BarrierState>,
+}
+
+impl Barrier {
+    pub const fn new(parties: usize) -> Self {
+        Barrier {
+            parties,
+            state: Mutex::new(BarrierState {
+                arrived: 0,
+                generation: 0,
+                wakers: Vec::new(),
+            }),
+        }
+    }
+
+    /// Completes once `parties` tasks have called `wait` this round. The
+    /// last to arrive is the leader, e.g. to do the round's follow-up once.
+    pub fn wait(&self) -> BarrierWait {
+        BarrierWait {
+            barrier: self,
+            generation: None,
+        }
+    }
+}
+
+pub struct BarrierWait<'a> {
+    barrier: &'a Barrier,
+    /// The round this task arrived in, once it has.
+    generation: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BarrierWaitResult {
+    pub is_leader: bool,
+}
+
+impl Future for BarrierWait<'_> {
+    type Output = BarrierWaitResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<BarrierWaitResult> {
+        let barrier = self.barrier;
+        let mut state = barrier.state.lock();
+        match self.generation {
+            None => {
+                state.arrived += 1;
+                if state.arrived >= barrier.parties {
+                    state.arrived = 0;
+                    state.generation += 1;
+                    wake_all(&mut state.wakers);
+                    return Poll::Ready(BarrierWaitResult { is_leader: true });
+                }
+                self.generation = Some(state.generation);
+            }
+            Some(generation) if generation != state.generation => {
+                return Poll::Ready(BarrierWaitResult { is_leader: false });
+            }
+            Some(_) => {}
+        }
+        register(&mut state.wakers, cx.waker());
+        Poll::Pending
+    }
+}
+
+struct RendezvousState<T> {
+    slot: Option<T>,
+    /// Values put in the slot and taken out of it, ever.
+    placed: u64,
+    taken: u64,
+    senders: Vec<Waker>,
+    receivers: Vec<Waker>,
+}
+
+/// A channel without a buffer: `send` completes only once a receiver has
+/// taken the value, so both sides know the handoff happened.
+///
+/// Senders queue up for the single slot; a send dropped after its value
+/// went into the slot still delivers it.
+pub struct Rendezvous<T> {
+    state: Mutex<RendezvousState<T>>,
+}
+
+impl<T> Rendezvous<T> {
+    pub const fn new() -> Self {
+        Rendezvous {
+            state: Mutex::new(RendezvousState {
+                slot: None,
+                placed: 0,
+                taken: 0,
+                senders: Vec::new(),
+                receivers: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn send(&self, value: T) -> RendezvousSend<T> {
+        RendezvousSend {
+            rendezvous: self,
+            value: Some(value),
+            ticket: None,
+        }
+    }
+
+    pub fn recv(&self) -> RendezvousRecv<T> {
+        RendezvousRecv { rendezvous: self }
+    }
+}
+
+impl<T> Default for Rendezvous<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct RendezvousSend<'a, T> {
+    rendezvous: &'a Rendezvous<T>,
+    value: Option<T>,
+    /// Which value placed this was, once it is in the slot.
+    ticket: Option<u64>,
+}
+
+// the value is only moved, never pinned
+impl<T> Unpin for RendezvousSend<'_, T> {}
+
+impl<T> Future for RendezvousSend<'_, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        let mut state = this.rendezvous.state.lock();
+        match this.ticket {
+            None if state.slot.is_none() => {
+                state.slot = this.value.take();
+                state.placed += 1;
+                this.ticket = Some(state.placed);
+                wake_all(&mut state.receivers);
+            }
+            Some(ticket) if state.taken >= ticket => return Poll::Ready(()),
+            _ => {}
+        }
+        register(&mut state.senders, cx.waker());
+        Poll::Pending
+    }
+}
+
+pub struct RendezvousRecv<'a, T> {
+    rendezvous: &'a Rendezvous<T>,
+}
+
+impl<T> Future for RendezvousRecv<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut state = self.rendezvous.state.lock();
+        match state.slot.take() {
+            Some(value) => {
+                state.taken += 1;
+                wake_all(&mut state.senders);
+                Poll::Ready(value)
+            }
+            None => {
+                register(&mut state.receivers, cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+
+/// A flag one side signals and the other waits for, such as a driver's
+/// interrupt handler reporting "command complete" to whoever issued it.
+///
+/// `signal` never blocks, so interrupt handlers can call it. A task waits
+/// with `wait().await`; code running before the executor, with no task to
+/// park, spins in `wait_spin`. A completed wait consumes the signal, so
+/// the next wait needs a new one. Only one task may wait at a time.
+pub struct Event {
+    set: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Event {
+            set: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Sets the flag and wakes the waiting task, if any.
+    pub fn signal(&self) {
+        self.set.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.set.load(Ordering::Acquire)
+    }
+
+    /// Drops a signal nobody waited for, e.g. before issuing a command.
+    pub fn reset(&self) {
+        self.set.store(false, Ordering::Release);
+    }
+
+    /// Consumes the signal if there is one, without waiting.
+    pub fn take(&self) -> bool {
+        self.set.swap(false, Ordering::AcqRel)
+    }
+
+    /// For futures that wait on an event among other things.
+    pub fn poll_wait(&self, cx: &mut Context) -> Poll<()> {
+        if self.take() {
+            return Poll::Ready(());
+        }
+        self.waker.register(cx.waker());
+        // a signal may have landed before the waker was in place
+        if self.take() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    pub fn wait(&self) -> Wait {
+        Wait { event: self }
+    }
+
+    /// Spins until signalled, for at most `polls` checks; returns whether
+    /// the signal came. Interrupts must be on if an interrupt handler is to
+    /// send it.
+    pub fn wait_spin(&self, polls: usize) -> bool {
+        for _ in 0..polls {
+            if self.take() {
+                return true;
+            }
+            crate::interrupts::pause();
+        }
+        self.take()
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Wait<'a> {
+    event: &'a Event,
+}
+
+impl Future for Wait<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        self.event.poll_wait(cx)
+    }
+}
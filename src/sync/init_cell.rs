@@ -0,0 +1,44 @@
+use super::Once;
+
+/// A global set exactly once during boot and used everywhere after.
+///
+/// Unlike a bare `Once`, misuse is a bug worth stopping for: `init` panics
+/// if the cell is already set and `get` panics if it is not yet, naming
+/// the cell either way. Neither blocks, so both are safe in interrupt
+/// handlers; `try_get` is for code that may run before boot gets there,
+/// such as fault handlers.
+pub struct InitCell<T> {
+    name: &'static str,
+    value: Once<T>,
+}
+
+impl<T> InitCell<T> {
+    pub const fn new(name: &'static str) -> Self {
+        InitCell {
+            name,
+            value: Once::new(),
+        }
+    }
+
+    pub fn init(&self, value: T) -> &T {
+        if self.value.set(value).is_err() {
+            panic!("{} initialized twice", self.name);
+        }
+        self.get()
+    }
+
+    pub fn get(&self) -> &T {
+        match self.value.get() {
+            Some(value) => value,
+            None => panic!("{} used before initialization", self.name),
+        }
+    }
+
+    pub fn try_get(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.value.is_completed()
+    }
+}
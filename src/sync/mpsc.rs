@@ -0,0 +1,206 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+/// One entry of an `MpscQueue`.
+///
+/// Its stamp tells producers and the consumer whose turn the slot is: the
+/// slot at `index` is free for the push at position `pos` when
+/// `stamp + index == pos`, and holds that push's value once
+/// `stamp + index == pos + 1`. Counting from `index` lets every slot start
+/// at zero, so a buffer can be built in a `static`.
+pub struct MpscSlot<T> {
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> MpscSlot<T> {
+    pub const fn new() -> Self {
+        MpscSlot {
+            stamp: AtomicUsize::new(0),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+}
+
+unsafe impl<T: Send> Sync for MpscSlot<T> {}
+
+enum Buffer<T: 'static> {
+    Static(&'static [MpscSlot<T>]),
+    Owned(Box<[MpscSlot<T>]>),
+}
+
+/// A bounded lock-free queue for many producers and one consumer, such as
+/// interrupt handlers feeding a task.
+///
+/// `push` never blocks or allocates, so it is safe in an interrupt
+/// handler. When the queue is full it hands the value back and counts the
+/// overflow rather than failing loudly; the consumer decides what a lost
+/// item means. `pop` is for the one consumer: a second caller arriving
+/// while a pop is under way, such as an interrupt handler, gets `None`.
+///
+/// The buffer is either allocated by `with_capacity` or a `static` array
+/// of `MpscSlot::new()` given to `from_static`, for queues needed before
+/// the heap is up. Either way it needs at least two slots.
+pub struct MpscQueue<T: 'static> {
+    buffer: Buffer<T>,
+    /// Position of the next push.
+    tail: AtomicUsize,
+    /// Position of the next pop.
+    head: AtomicUsize,
+    popping: AtomicBool,
+    overflows: AtomicU64,
+}
+
+unsafe impl<T: Send + 'static> Send for MpscQueue<T> {}
+unsafe impl<T: Send + 'static> Sync for MpscQueue<T> {}
+
+impl<T: 'static> MpscQueue<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity >= 2, "MpscQueue needs at least two slots");
+        let slots: Vec<_> = (0..capacity).map(|_| MpscSlot::new()).collect();
+        Self::from_buffer(Buffer::Owned(slots.into_boxed_slice()))
+    }
+
+    /// A queue over `slots`, which it then owns for good.
+    pub const fn from_static(slots: &'static [MpscSlot<T>]) -> Self {
+        Self::from_buffer(Buffer::Static(slots))
+    }
+
+    const fn from_buffer(buffer: Buffer<T>) -> Self {
+        MpscQueue {
+            buffer,
+            tail: AtomicUsize::new(0),
+            head: AtomicUsize::new(0),
+            popping: AtomicBool::new(false),
+            overflows: AtomicU64::new(0),
+        }
+    }
+
+    fn slots(&self) -> &[MpscSlot<T>] {
+        match &self.buffer {
+            Buffer::Static(slots) => slots,
+            Buffer::Owned(slots) => slots,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots().len()
+    }
+
+    /// Queues `value`, or hands it back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let slots = self.slots();
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let index = pos % slots.len();
+            let slot = &slots[index];
+            let seq = slot.stamp.load(Ordering::Acquire).wrapping_add(index);
+            match seq.wrapping_sub(pos) as isize {
+                0 => match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).as_mut_ptr().write(value) };
+                        slot.stamp
+                            .store(pos.wrapping_add(1).wrapping_sub(index), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                },
+                // the slot still holds the value from a lap ago
+                lag if lag < 0 => {
+                    self.overflows.fetch_add(1, Ordering::Relaxed);
+                    return Err(value);
+                }
+                // another producer took `pos` first
+                _ => pos = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Takes the oldest value, if there is one and no other pop is under
+    /// way.
+    pub fn pop(&self) -> Option<T> {
+        if self.popping.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        let slots = self.slots();
+        let pos = self.head.load(Ordering::Relaxed);
+        let index = pos % slots.len();
+        let slot = &slots[index];
+        let seq = slot.stamp.load(Ordering::Acquire).wrapping_add(index);
+        let value = if seq == pos.wrapping_add(1) {
+            let value = unsafe { (*slot.value.get()).as_ptr().read() };
+            slot.stamp.store(
+                pos.wrapping_add(slots.len()).wrapping_sub(index),
+                Ordering::Release,
+            );
+            self.head.store(pos.wrapping_add(1), Ordering::Relaxed);
+            Some(value)
+        } else {
+            None
+        };
+        self.popping.store(false, Ordering::Release);
+        value
+    }
+
+    /// Values queued, counting pushes still being written.
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Relaxed);
+        tail.wrapping_sub(head).min(self.capacity())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pushes refused because the queue was full, since it was made.
+    pub fn overflows(&self) -> u64 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: 'static> Drop for MpscQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[test_case]
+fn mpsc_counts_overflows() {
+    let queue = MpscQueue::with_capacity(2);
+    assert_eq!(queue.push(1), Ok(()));
+    assert_eq!(queue.push(2), Ok(()));
+    assert_eq!(queue.push(3), Err(3));
+    assert_eq!(queue.overflows(), 1);
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue.pop(), Some(1));
+    assert_eq!(queue.push(3), Ok(()));
+    assert_eq!(queue.pop(), Some(2));
+    assert_eq!(queue.pop(), Some(3));
+    assert_eq!(queue.pop(), None);
+    assert_eq!(queue.overflows(), 1);
+}
+
+#[test_case]
+fn mpsc_wraps_in_order() {
+    static SLOTS: [MpscSlot<usize>; 3] = [MpscSlot::new(), MpscSlot::new(), MpscSlot::new()];
+    let queue = MpscQueue::from_static(&SLOTS);
+    // many laps, the queue never quite empty
+    queue.push(0).unwrap();
+    for i in 1..20 {
+        queue.push(i).unwrap();
+        assert_eq!(queue.pop(), Some(i - 1));
+    }
+    assert_eq!(queue.pop(), Some(19));
+    assert!(queue.is_empty());
+    assert_eq!(queue.overflows(), 0);
+}
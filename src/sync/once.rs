@@ -0,0 +1,148 @@
+use core::{
+    cell::{Cell, UnsafeCell},
+    fmt,
+    mem::MaybeUninit,
+    ops::Deref,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+const EMPTY: u8 = 0;
+const RUNNING: u8 = 1;
+const READY: u8 = 2;
+
+/// A value written once and then only read.
+///
+/// `get` never blocks, so interrupt handlers can use it. Reaching
+/// `call_once` while another initialization of the same cell is under way
+/// means this CPU interrupted it or recursed into it, which would spin
+/// forever, so it panics instead.
+pub struct Once<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send> Send for Once<T> {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Once {
+            state: AtomicU8::new(EMPTY),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// The value, if it has been set.
+    pub fn get(&self) -> Option<&T> {
+        if self.is_completed() {
+            Some(unsafe { &*(*self.value.get()).as_ptr() })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == READY
+    }
+
+    /// The value, running `init` to produce it if nothing has yet.
+    pub fn call_once(&self, init: impl FnOnce() -> T) -> &T {
+        match self
+            .state
+            .compare_exchange(EMPTY, RUNNING, Ordering::Acquire, Ordering::Acquire)
+        {
+            Ok(_) => {
+                unsafe { (*self.value.get()).as_mut_ptr().write(init()) };
+                self.state.store(READY, Ordering::Release);
+            }
+            Err(READY) => {}
+            // nothing unwinds, so a panicking `init` leaves it running too
+            Err(_) => panic!("Once initialization re-entered, or it panicked earlier"),
+        }
+        self.get().expect("Once not ready after initialization")
+    }
+
+    /// Sets the value unless one is set already, in which case `value` is
+    /// handed back.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.call_once(|| value.take().expect("Once::set value taken twice"));
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == READY {
+            unsafe { core::ptr::drop_in_place((*self.value.get()).as_mut_ptr()) };
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Once<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("Once").field(value).finish(),
+            None => f.write_str("Once(<empty>)"),
+        }
+    }
+}
+
+/// A value built by `init` the first time it is dereferenced.
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: Cell<Option<F>>,
+}
+
+// `init` is only taken inside `call_once`, which runs at most once.
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Lazy {
+            once: Once::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    /// Builds the value now if it has not been built yet.
+    pub fn force(this: &Self) -> &T {
+        // `call_once` runs this at most once, so `init` is still there
+        this.once
+            .call_once(|| this.init.take().expect("Lazy initializer taken twice")())
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+#[test_case]
+fn set_hands_back_a_second_value() {
+    let once = Once::new();
+    assert_eq!(once.get(), None);
+    assert_eq!(once.set(1), Ok(()));
+    assert_eq!(once.set(2), Err(2));
+    assert_eq!(once.call_once(|| 3), &1);
+    assert_eq!(once.get(), Some(&1));
+}
+
+#[test_case]
+fn lazy_builds_once() {
+    use core::sync::atomic::AtomicUsize;
+
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    let lazy = Lazy::new(|| BUILDS.fetch_add(1, Ordering::Relaxed) + 10);
+    assert_eq!(*lazy, 10);
+    assert_eq!(*lazy, 10);
+    assert_eq!(BUILDS.load(Ordering::Relaxed), 1);
+}
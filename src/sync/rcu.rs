@@ -0,0 +1,154 @@
+//! Read-copy-update: readers follow a pointer without taking anything;
+//! writers build a new version from a copy, publish it with one store, and
+//! leave the old one to be freed once no reader can still see it.
+//!
+//! Readers hold an `RcuReadGuard` for the length of one lookup. The
+//! schedulers call `quiescent` between task polls: on this single CPU,
+//! no read section is open at that point, since interrupt handlers finish
+//! before the scheduler runs again and a task must not hold a guard across
+//! an `.await`. Every version retired before the last quiescent point is
+//! then freed. Each quiescent point starts a new epoch; versions are tagged
+//! with the epoch they were retired in.
+
+use crate::sync::Mutex;
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    marker::PhantomData,
+    ops::Deref,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+static EPOCH: AtomicU64 = AtomicU64::new(0);
+/// Read sections open right now.
+static READERS: AtomicUsize = AtomicUsize::new(0);
+/// Versions waiting for a quiescent point, with the epoch they were
+/// retired in.
+static RETIRED: Mutex<Vec<(u64, Box<dyn Send>)>> = Mutex::new(Vec::new());
+static RETIRED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A value read often and without locks, and replaced rarely.
+pub struct Rcu<T: 'static> {
+    current: AtomicPtr<T>,
+    /// Where the first version came from when it was not allocated; that
+    /// one is never freed.
+    initial: *const T,
+    writer: Mutex<()>,
+}
+
+unsafe impl<T: Send + Sync> Send for Rcu<T> {}
+unsafe impl<T: Send + Sync> Sync for Rcu<T> {}
+
+impl<T: Send + Sync + 'static> Rcu<T> {
+    pub fn new(value: T) -> Self {
+        Rcu {
+            current: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            initial: ptr::null(),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Starts from a `static` value, so the `Rcu` can itself be a `static`
+    /// and be read before the heap is up.
+    pub const fn from_static(initial: &'static T) -> Self {
+        Rcu {
+            current: AtomicPtr::new(initial as *const T as *mut T),
+            initial,
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// The current version, for one short read section.
+    pub fn read(&self) -> RcuReadGuard<T> {
+        READERS.fetch_add(1, Ordering::Acquire);
+        RcuReadGuard {
+            value: unsafe { &*self.current.load(Ordering::Acquire) },
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Publishes `f`'s new version built from the current one. Readers
+    /// already in see the old version until they finish.
+    pub fn update(&self, f: impl FnOnce(&T) -> T) {
+        let _ = self.try_update(|value| Ok::<T, ()>(f(value)));
+    }
+
+    /// Like `update`, but `f` may refuse, leaving the current version.
+    pub fn try_update<E>(&self, f: impl FnOnce(&T) -> Result<T, E>) -> Result<(), E> {
+        let _writer = self.writer.lock();
+        let old = self.current.load(Ordering::Acquire);
+        let new = Box::into_raw(Box::new(f(unsafe { &*old })?));
+        self.current.store(new, Ordering::Release);
+        if old as *const T != self.initial {
+            retire(unsafe { Box::from_raw(old) });
+        }
+        Ok(())
+    }
+}
+
+impl<T: 'static> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        let current = *self.current.get_mut();
+        if current as *const T != self.initial {
+            drop(unsafe { Box::from_raw(current) });
+        }
+    }
+}
+
+/// A version of an `Rcu` value, valid until the guard is dropped. Not to be
+/// held across an `.await`.
+pub struct RcuReadGuard<'a, T> {
+    value: &'a T,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for RcuReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T> Drop for RcuReadGuard<'_, T> {
+    fn drop(&mut self) {
+        READERS.fetch_sub(1, Ordering::Release);
+    }
+}
+
+fn retire(old: Box<dyn Send>) {
+    let epoch = EPOCH.load(Ordering::Relaxed);
+    without_interrupts(|| RETIRED.lock().push((epoch, old)));
+    RETIRED_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Declares that the caller holds no read section, and frees the versions
+/// retired before now if nobody else does either. Cheap when nothing is
+/// waiting.
+pub fn quiescent() {
+    if RETIRED_COUNT.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    if READERS.load(Ordering::Acquire) != 0 {
+        // a guard outlived its poll; keep everything until it is dropped
+        return;
+    }
+    let epoch = EPOCH.fetch_add(1, Ordering::AcqRel);
+    let freed: Vec<_> = without_interrupts(|| {
+        let mut retired = RETIRED.lock();
+        let (freed, kept): (Vec<_>, Vec<_>) = retired
+            .drain(..)
+            .partition(|&(retired_at, _)| retired_at <= epoch);
+        *retired = kept;
+        freed
+    });
+    RETIRED_COUNT.fetch_sub(freed.len(), Ordering::Relaxed);
+    // dropped outside the lock; destructors may retire more
+    drop(freed);
+}
+
+/// Versions retired and not yet freed.
+pub fn pending() -> usize {
+    RETIRED_COUNT.load(Ordering::Relaxed)
+}
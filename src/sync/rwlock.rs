@@ -0,0 +1,156 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{spin_loop_hint, AtomicUsize, Ordering},
+};
+
+const WRITER: usize = !(usize::MAX >> 1);
+/// A writer is spinning; new readers hold off so it gets its turn.
+const WRITER_WAITING: usize = WRITER >> 1;
+const READERS: usize = !(WRITER | WRITER_WAITING);
+
+/// A spinning reader-writer lock that favours writers: once one waits, new
+/// readers wait behind it, so a stream of readers cannot starve it.
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        RwLock {
+            state: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            spin_loop_hint();
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITER | WRITER_WAITING) != 0 || state & READERS == READERS {
+            return None;
+        }
+        self.state
+            .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockReadGuard { lock: self })
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            spin_loop_hint();
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITER | READERS) != 0 {
+            return None;
+        }
+        self.state
+            .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value.get() }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // also clears WRITER_WAITING; a writer still spinning sets it again
+        self.lock.state.store(0, Ordering::Release);
+    }
+}
+
+#[test_case]
+fn readers_share_writers_exclude() {
+    let lock = RwLock::new(0);
+    let first = lock.read();
+    let second = lock.try_read().expect("a second reader");
+    assert!(lock.try_write().is_none());
+    drop((first, second));
+    let mut writer = lock.try_write().expect("the writer");
+    *writer = 1;
+    assert!(lock.try_read().is_none());
+    drop(writer);
+    assert_eq!(*lock.read(), 1);
+}
+
+#[test_case]
+fn waiting_writer_holds_off_new_readers() {
+    let lock = RwLock::new(0);
+    let reader = lock.read();
+    // what `write` leaves behind while it spins on the reader
+    assert!(lock.try_write().is_none());
+    lock.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+    assert!(lock.try_read().is_none());
+    drop(reader);
+    drop(lock.try_write().expect("the waiting writer"));
+    assert!(lock.try_read().is_some());
+}
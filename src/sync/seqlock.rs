@@ -0,0 +1,91 @@
+use core::{
+    cell::UnsafeCell,
+    ptr,
+    sync::atomic::{fence, spin_loop_hint, AtomicUsize, Ordering},
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// A value that is written rarely, usually by an interrupt handler, and
+/// read often.
+///
+/// Writers bump a sequence number to odd, store, and bump it back to even;
+/// readers copy the value and retry if the number moved or was odd. Readers
+/// take nothing, so the writer never waits on them, and a reader only
+/// retries when a write landed in the middle of its copy. Writes run with
+/// interrupts off, so a reader in an interrupt handler never finds one
+/// half done; a write reached from inside another write panics.
+pub struct SeqLock<T: Copy> {
+    seq: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(value: T) -> Self {
+        SeqLock {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// A copy of the value as of one write.
+    pub fn read(&self) -> T {
+        loop {
+            if let Some(value) = self.try_read() {
+                return value;
+            }
+            spin_loop_hint();
+        }
+    }
+
+    /// One attempt at `read`, failing if a write was under way.
+    fn try_read(&self) -> Option<T> {
+        let seq = self.seq.load(Ordering::Acquire);
+        if seq & 1 != 0 {
+            return None;
+        }
+        // may tear against a write; the sequence check throws such a
+        // copy away
+        let value = unsafe { ptr::read_volatile(self.value.get()) };
+        fence(Ordering::Acquire);
+        if self.seq.load(Ordering::Relaxed) == seq {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    pub fn write(&self, value: T) {
+        self.update(|old| *old = value);
+    }
+
+    /// Changes the value in place, as one write.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        without_interrupts(|| {
+            let seq = self.seq.load(Ordering::Relaxed);
+            assert!(seq & 1 == 0, "SeqLock write re-entered");
+            self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+            fence(Ordering::Release);
+            let mut value = unsafe { ptr::read_volatile(self.value.get()) };
+            f(&mut value);
+            unsafe { ptr::write_volatile(self.value.get(), value) };
+            self.seq.store(seq.wrapping_add(2), Ordering::Release);
+        })
+    }
+}
+
+#[test_case]
+fn reads_retry_during_a_write() {
+    let lock = SeqLock::new((0u64, 0u64));
+    lock.update(|value| {
+        value.0 = 1;
+        // a reader here, as an interrupt handler could be, must not take
+        // the half-written pair
+        assert_eq!(lock.try_read(), None);
+        value.1 = 1;
+    });
+    assert_eq!(lock.try_read(), Some((1, 1)));
+    lock.write((2, 2));
+    assert_eq!(lock.read(), (2, 2));
+}
@@ -0,0 +1,38 @@
+use super::{Mutex, MutexGuard};
+use crate::time::Deadline;
+use core::{panic::Location, sync::atomic::spin_loop_hint, time::Duration};
+
+/// `try_lock_for` for the plain `Mutex`, which has no idea who holds it;
+/// on a timeout it logs where the waiter is instead. Use `TrackedMutex`
+/// when the holder matters.
+pub trait TryLockFor<T> {
+    /// Spins for the lock until `timeout` passes, then warns and gives up.
+    fn try_lock_for(&self, timeout: Duration) -> Option<MutexGuard<T>>;
+}
+
+impl<T> TryLockFor<T> for Mutex<T> {
+    #[track_caller]
+    fn try_lock_for(&self, timeout: Duration) -> Option<MutexGuard<T>> {
+        let caller = Location::caller();
+        let deadline = Deadline::after(timeout);
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+            if deadline.expired() {
+                warn!("lock not acquired within {:?} at {}", timeout, caller);
+                return None;
+            }
+            spin_loop_hint();
+        }
+    }
+}
+
+#[test_case]
+fn gives_up_on_a_held_lock() {
+    let lock = Mutex::new(0);
+    let guard = lock.lock();
+    assert!(lock.try_lock_for(Duration::from_millis(1)).is_none());
+    drop(guard);
+    assert!(lock.try_lock_for(Duration::from_millis(1)).is_some());
+}
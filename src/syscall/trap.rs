@@ -0,0 +1,138 @@
+//! `int 0x80`, the way into `dispatch`. The gate is open to ring 3, and
+//! takes the number in `rax` and the arguments in `rdi`, `rsi`, `rdx`,
+//! `r10`, `r8` and `r9` as Linux's `syscall` does; the result comes back
+//! in `rax` and every other register is preserved.
+
+use super::dispatch;
+use x86_64::structures::idt::HandlerFunc;
+
+/// Registers saved by `stub`, lowest address first; the CPU's interrupt
+/// frame follows. Only `trap` reads them, and not all of them.
+#[allow(dead_code)]
+#[repr(C)]
+struct Registers {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+}
+
+#[naked]
+unsafe extern "C" fn stub() -> ! {
+    asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        "call {trap}",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        "iretq",
+        trap = sym trap,
+        options(noreturn)
+    )
+}
+
+extern "C" fn trap(regs: &mut Registers) {
+    let args = [
+        regs.rdi as usize,
+        regs.rsi as usize,
+        regs.rdx as usize,
+        regs.r10 as usize,
+        regs.r8 as usize,
+        regs.r9 as usize,
+    ];
+    regs.rax = dispatch(regs.rax as usize, args) as u64;
+}
+
+/// IDT handler for vector 0x80. Not a real `x86-interrupt` function: the
+/// stub does its own save and `iretq`.
+pub fn entry() -> HandlerFunc {
+    unsafe { core::mem::transmute(stub as unsafe extern "C" fn() -> !) }
+}
+
+/// Makes a syscall through the gate, as user code will.
+#[cfg(test)]
+fn call(number: usize, args: [usize; 6]) -> isize {
+    let result: isize;
+    unsafe {
+        asm!(
+            "int 0x80",
+            inlateout("rax") number as isize => result,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            in("rdx") args[2],
+            in("r10") args[3],
+            in("r8") args[4],
+            in("r9") args[5],
+        );
+    }
+    result
+}
+
+#[test_case]
+fn unknown_numbers_fail_with_nosys() {
+    assert_eq!(call(9999, [0; 6]), -(super::Errno::NoSys as isize));
+}
+
+#[test_case]
+fn bad_descriptors_fail_with_badf() {
+    assert_eq!(
+        call(super::CLOSE, [9999, 0, 0, 0, 0, 0]),
+        -(super::Errno::BadF as isize)
+    );
+}
+
+#[test_case]
+fn kernel_buffers_fail_with_fault() {
+    let mut timespec = [0u64; 2];
+    let args = [
+        super::CLOCK_MONOTONIC,
+        timespec.as_mut_ptr() as usize,
+        0,
+        0,
+        0,
+        0,
+    ];
+    assert_eq!(
+        call(super::CLOCK_GETTIME, args),
+        -(super::Errno::Fault as isize)
+    );
+    assert_eq!(timespec, [0, 0]);
+}
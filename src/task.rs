@@ -41,28 +41,92 @@
 // }
 
 use alloc::boxed::Box;
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use core::task::{Context, Poll};
-use core::{future::Future, pin::Pin};
+use core::{fmt, future::Future, pin::Pin};
 
+pub mod pi_mutex;
+pub mod recover;
 pub mod scheduler;
+pub mod supervisor;
 pub mod yields;
 
-pub use self::yields::yield_init;
+pub use self::{
+    pi_mutex::{PiMutex, PiMutexGuard},
+    yields::yield_init,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(u64);
 
+const NO_TASK: u64 = u64::MAX;
+
+static CURRENT_TASK: AtomicU64 = AtomicU64::new(NO_TASK);
+static LIVE_TASKS: AtomicUsize = AtomicUsize::new(0);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Tasks created and not yet dropped.
+pub fn live_count() -> usize {
+    LIVE_TASKS.load(Ordering::Relaxed)
+}
+
+/// Has the scheduler drop every task but the caller on its next pass;
+/// tasks started after should check `cancelled` and return. For when the
+/// system is going down.
+pub fn cancel_all() {
+    CANCELLED.store(true, Ordering::Relaxed);
+    for task in scheduler::tasks() {
+        // skips the caller
+        scheduler::kill(task.id);
+    }
+}
+
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
 impl TaskId {
     fn new() -> Self {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+
+    /// The task being polled right now, if any.
+    pub fn current() -> Option<TaskId> {
+        match CURRENT_TASK.load(Ordering::Relaxed) {
+            NO_TASK => None,
+            id => Some(TaskId(id)),
+        }
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// Called by the schedulers around every poll.
+    pub(crate) fn set_current(task_id: Option<TaskId>) {
+        CURRENT_TASK.store(task_id.map_or(NO_TASK, |id| id.0), Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
 pub trait TaskFuture {
     fn id(&self) -> TaskId;
     fn poll(&mut self, context: &mut Context) -> Poll<()>;
+
+    /// Gets rid of a task that panicked without dropping its future, whose
+    /// state may be half updated.
+    fn abandon(self)
+    where
+        Self: Sized,
+    {
+        core::mem::forget(self)
+    }
 }
 
 pub struct Task {
@@ -72,6 +136,7 @@ pub struct Task {
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
+        LIVE_TASKS.fetch_add(1, Ordering::Relaxed);
         Task {
             id: TaskId::new(),
             future: Box::pin(future),
@@ -79,6 +144,12 @@ impl Task {
     }
 }
 
+impl Drop for Task {
+    fn drop(&mut self) {
+        LIVE_TASKS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 impl TaskFuture for Task {
     fn id(&self) -> TaskId {
         self.id
@@ -87,6 +158,11 @@ impl TaskFuture for Task {
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
+
+    fn abandon(self) {
+        LIVE_TASKS.fetch_sub(1, Ordering::Relaxed);
+        core::mem::forget(self)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -96,6 +172,32 @@ pub enum Priority {
     High,
 }
 
+const NO_PRIORITY: u8 = u8::MAX;
+
+static CURRENT_PRIORITY: AtomicU8 = AtomicU8::new(NO_PRIORITY);
+
+impl Priority {
+    /// The priority the running task was polled at, boosts included; `None`
+    /// outside a task or under a scheduler without priorities.
+    pub fn current() -> Option<Priority> {
+        Priority::from_u8(CURRENT_PRIORITY.load(Ordering::Relaxed))
+    }
+
+    /// Called by the schedulers with priorities around every poll.
+    pub(crate) fn set_current(priority: Option<Priority>) {
+        CURRENT_PRIORITY.store(priority.map_or(NO_PRIORITY, |p| p as u8), Ordering::Relaxed);
+    }
+
+    fn from_u8(value: u8) -> Option<Priority> {
+        match value {
+            0 => Some(Priority::Low),
+            1 => Some(Priority::Medium),
+            2 => Some(Priority::High),
+            _ => None,
+        }
+    }
+}
+
 pub struct PriorityTask {
     priority: Priority,
     inner: Task,
@@ -114,6 +216,13 @@ impl PriorityTask {
     }
 }
 
+/// Drops the priority, for schedulers without any.
+impl From<PriorityTask> for Task {
+    fn from(task: PriorityTask) -> Self {
+        task.inner
+    }
+}
+
 impl TaskFuture for PriorityTask {
     fn id(&self) -> TaskId {
         self.inner.id
@@ -122,4 +231,8 @@ impl TaskFuture for PriorityTask {
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.inner.future.as_mut().poll(context)
     }
+
+    fn abandon(self) {
+        self.inner.abandon()
+    }
 }
@@ -1,20 +1,24 @@
 use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use crate::sync::MpscQueue;
+use alloc::{collections::BTreeMap, sync::Arc, task::Wake, vec::Vec};
 use core::task::{Context, Poll, Waker};
-use crossbeam_queue::ArrayQueue;
 
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    task_queue: Arc<MpscQueue<TaskId>>,
     waker_cache: BTreeMap<TaskId, Waker>,
+    overflows_seen: u64,
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            task_queue: Arc::new(MpscQueue::with_capacity(
+                crate::config::scheduler::QUEUE_DEPTH,
+            )),
             waker_cache: BTreeMap::new(),
+            overflows_seen: 0,
         }
     }
 
@@ -34,37 +38,54 @@ impl Executor {
     }
 
     fn run_ready_tasks(&mut self) {
+        loop {
+            while let Some(task_id) = self.task_queue.pop() {
+                self.poll_task(task_id);
+            }
+            let overflows = self.task_queue.overflows();
+            if !super::scheduler::wakes_lost(overflows, &mut self.overflows_seen) {
+                break;
+            }
+            let task_ids: Vec<TaskId> = self.tasks.keys().copied().collect();
+            for task_id in task_ids {
+                self.poll_task(task_id);
+            }
+        }
+    }
+
+    fn poll_task(&mut self, task_id: TaskId) {
         let Self {
             tasks,
             task_queue,
             waker_cache,
+            ..
         } = self;
 
-        while let Ok(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue,
-            };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
+        let task = match tasks.get_mut(&task_id) {
+            Some(task) => task,
+            None => return,
+        };
+        let waker = waker_cache
+            .entry(task_id)
+            .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+        let mut context = Context::from_waker(waker);
+        let poll = task.poll(&mut context);
+        crate::sync::rcu::quiescent();
+        match poll {
+            Poll::Ready(()) => {
+                tasks.remove(&task_id);
+                waker_cache.remove(&task_id);
             }
+            Poll::Pending => {}
         }
     }
 
     fn sleep_if_idle(&self) {
-        use x86_64::instructions::interrupts::{self, enable_and_hlt};
+        use x86_64::instructions::interrupts;
 
         interrupts::disable();
         if self.task_queue.is_empty() {
-            enable_and_hlt();
+            crate::cpu::idle::wait();
         } else {
             interrupts::enable();
         }
@@ -79,11 +100,11 @@ impl Default for Executor {
 
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    task_queue: Arc<MpscQueue<TaskId>>,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    fn new(task_id: TaskId, task_queue: Arc<MpscQueue<TaskId>>) -> Waker {
         Waker::from(Arc::new(TaskWaker {
             task_id,
             task_queue,
@@ -91,7 +112,7 @@ impl TaskWaker {
     }
 
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        let _ = self.task_queue.push(self.task_id);
     }
 }
 
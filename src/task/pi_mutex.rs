@@ -0,0 +1,263 @@
+//! A mutex for tasks that lends its holder the priority of the most urgent
+//! task waiting for it.
+//!
+//! Without that, a Low task holding a lock that a High task wants runs
+//! only when nothing at Medium is ready, and the High task waits on every
+//! Medium one. While a `PiMutex` has waiters above its holder's priority,
+//! `PriorityScheduler` queues the holder as if it had theirs; the boost
+//! ends when the lock is released.
+//!
+//! Boosts are kept per task, not per lock: a task holding two contended
+//! `PiMutex`es drops back to its own priority when it releases either.
+//! Under `RoundRobinScheduler` tasks have no priority, and a `PiMutex`
+//! is a plain async mutex.
+
+use super::{Priority, TaskId};
+use crate::sync::Mutex;
+use alloc::vec::Vec;
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+/// Tasks that can be boosted at once; past that, holders keep their own
+/// priority.
+const BOOST_SLOTS: usize = 16;
+const NO_TASK: u64 = u64::MAX;
+
+struct Boost {
+    task: AtomicU64,
+    priority: AtomicU8,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_BOOST: Boost = Boost {
+    task: AtomicU64::new(NO_TASK),
+    priority: AtomicU8::new(0),
+};
+
+/// Written from tasks only, read by wakers, which may run in interrupt
+/// handlers; hence atomics rather than a lock.
+static BOOSTS: [Boost; BOOST_SLOTS] = [NO_BOOST; BOOST_SLOTS];
+
+/// The priority `task_id` is lent by the locks it holds, if any.
+pub fn boost(task_id: TaskId) -> Option<Priority> {
+    BOOSTS
+        .iter()
+        .find(|boost| boost.task.load(Ordering::Acquire) == task_id.as_u64())
+        .and_then(|boost| Priority::from_u8(boost.priority.load(Ordering::Relaxed)))
+}
+
+/// `base`, or the priority `task_id` is lent if that is higher.
+pub fn effective_priority(task_id: TaskId, base: Priority) -> Priority {
+    boost(task_id).map_or(base, |boost| boost.max(base))
+}
+
+fn set_boost(task_id: TaskId, priority: Option<Priority>) {
+    let id = task_id.as_u64();
+    let slot = BOOSTS
+        .iter()
+        .find(|boost| boost.task.load(Ordering::Relaxed) == id);
+    match (slot, priority) {
+        (Some(slot), Some(priority)) => slot.priority.store(priority as u8, Ordering::Relaxed),
+        (Some(slot), None) => slot.task.store(NO_TASK, Ordering::Release),
+        (None, Some(priority)) => {
+            let free = BOOSTS
+                .iter()
+                .find(|boost| boost.task.load(Ordering::Relaxed) == NO_TASK);
+            match free {
+                Some(slot) => {
+                    // priority first, so a waker never sees the task with
+                    // a stale one
+                    slot.priority.store(priority as u8, Ordering::Relaxed);
+                    slot.task.store(id, Ordering::Release);
+                }
+                None => warn!("no boost slot free for task {}", task_id),
+            }
+        }
+        (None, None) => {}
+    }
+}
+
+struct Waiter {
+    task_id: TaskId,
+    priority: Priority,
+    waker: Waker,
+}
+
+struct State {
+    holder: Option<TaskId>,
+    /// The holder's priority when it took the lock.
+    holder_priority: Priority,
+    /// Moves the holder to its boosted queue when a boost comes in.
+    holder_waker: Option<Waker>,
+    waiters: Vec<Waiter>,
+}
+
+impl State {
+    /// Lends the holder the priority of its most urgent waiter, if that is
+    /// higher than its own.
+    fn update_boost(&mut self) {
+        let holder = match self.holder {
+            Some(holder) => holder,
+            None => return,
+        };
+        let wanted = self
+            .waiters
+            .iter()
+            .map(|waiter| waiter.priority)
+            .max()
+            .filter(|&priority| priority > self.holder_priority);
+        let before = boost(holder);
+        if wanted == before {
+            return;
+        }
+        set_boost(holder, wanted);
+        if wanted > before {
+            trace_event!(
+                pi_boost,
+                task = holder,
+                priority = wanted.map_or(0, |p| p as u8)
+            );
+            if let Some(waker) = &self.holder_waker {
+                waker.wake_by_ref();
+            }
+        }
+    }
+
+    fn remove_waiter(&mut self, task_id: TaskId) {
+        self.waiters.retain(|waiter| waiter.task_id != task_id);
+    }
+}
+
+/// An async mutex with priority inheritance. Lock it only from tasks.
+pub struct PiMutex<T> {
+    state: Mutex<State>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for PiMutex<T> {}
+unsafe impl<T: Send> Sync for PiMutex<T> {}
+
+impl<T> PiMutex<T> {
+    pub const fn new(value: T) -> Self {
+        PiMutex {
+            state: Mutex::new(State {
+                holder: None,
+                holder_priority: Priority::Low,
+                holder_waker: None,
+                waiters: Vec::new(),
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Completes once the running task holds the lock.
+    pub fn lock(&self) -> Lock<T> {
+        Lock {
+            mutex: self,
+            task_id: None,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state.lock().holder.is_some()
+    }
+}
+
+pub struct Lock<'a, T> {
+    mutex: &'a PiMutex<T>,
+    /// The task polling, once it has; it may be on the waiter list.
+    task_id: Option<TaskId>,
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = PiMutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<PiMutexGuard<'a, T>> {
+        let task_id = TaskId::current().expect("PiMutex locked outside a task");
+        self.task_id = Some(task_id);
+        let priority = Priority::current().unwrap_or(Priority::Medium);
+        let mutex = self.mutex;
+        let mut state = mutex.state.lock();
+        match state.holder {
+            None => {
+                state.remove_waiter(task_id);
+                state.holder = Some(task_id);
+                state.holder_priority = priority;
+                state.holder_waker = Some(cx.waker().clone());
+                state.update_boost();
+                drop(state);
+                self.task_id = None;
+                Poll::Ready(PiMutexGuard { mutex, task_id })
+            }
+            Some(holder) if holder == task_id => {
+                panic!("PiMutex locked twice by task {}", task_id)
+            }
+            Some(_) => {
+                match state.waiters.iter_mut().find(|w| w.task_id == task_id) {
+                    Some(waiter) => {
+                        waiter.priority = priority;
+                        waiter.waker = cx.waker().clone();
+                    }
+                    None => state.waiters.push(Waiter {
+                        task_id,
+                        priority,
+                        waker: cx.waker().clone(),
+                    }),
+                }
+                state.update_boost();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Lock<'_, T> {
+    /// A task that stops waiting takes its claim on the holder's priority
+    /// with it.
+    fn drop(&mut self) {
+        if let Some(task_id) = self.task_id {
+            let mut state = self.mutex.state.lock();
+            state.remove_waiter(task_id);
+            state.update_boost();
+        }
+    }
+}
+
+pub struct PiMutexGuard<'a, T> {
+    mutex: &'a PiMutex<T>,
+    task_id: TaskId,
+}
+
+impl<T> Deref for PiMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for PiMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for PiMutexGuard<'_, T> {
+    /// Ends the holder's boost and wakes every waiter; the most urgent
+    /// runs first and takes the lock.
+    fn drop(&mut self) {
+        let mut state = self.mutex.state.lock();
+        state.holder = None;
+        state.holder_waker = None;
+        set_boost(self.task_id, None);
+        for waiter in state.waiters.iter() {
+            waiter.waker.wake_by_ref();
+        }
+    }
+}
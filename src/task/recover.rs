@@ -0,0 +1,155 @@
+//! Containing a panic to the task whose `poll` raised it, with
+//! `panic=recover` on the command line.
+//!
+//! The kernel is built with `panic-strategy: abort`, so nothing unwinds.
+//! Instead the schedulers poll through `catch`, which saves the registers
+//! a call preserves and RFLAGS, setjmp-style; the panic handler calls
+//! `recover`, which writes the panic and a backtrace to COM1 and jumps
+//! straight back, so the poll comes out as failed and the scheduler drops
+//! the task.
+//!
+//! The frames jumped over never run their destructors, and the task is
+//! leaked rather than dropped, as its state may be half updated. A lock
+//! they held would stay held for good, so `recover` declines when lockdep
+//! sees the task holding more locks than when it was polled. Lockdep only
+//! sees `TrackedMutex`: a plain `sync::Mutex`, which most subsystems use,
+//! is never counted, so a task that panics holding one is still recovered
+//! and whoever takes that lock next hangs. Release builds track no locks
+//! at all. A panic in an interrupt handler, or outside any task, stops the
+//! kernel as before.
+
+use crate::{
+    debug::{backtrace, lockdep},
+    interrupts,
+    serial::SERIAL1,
+};
+use core::{
+    fmt::{self, Write},
+    panic::PanicInfo,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// What `save` keeps and `restore` puts back: the registers the System V
+/// ABI has callees preserve, the stack pointer, where to return to and
+/// RFLAGS, so interrupts come back on if the panic struck with them off.
+#[derive(Default)]
+#[repr(C)]
+struct JumpBuffer {
+    rbx: u64,
+    rbp: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rsp: u64,
+    rip: u64,
+    rflags: u64,
+}
+
+/// The innermost `catch` under way, if any.
+static ARMED: AtomicPtr<JumpBuffer> = AtomicPtr::new(ptr::null_mut());
+/// The interrupt depth it was entered at.
+static ARMED_DEPTH: AtomicUsize = AtomicUsize::new(0);
+/// How many tracked locks were held when it was entered.
+static ARMED_HELD: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs `f`, returning `None` instead if it panicked.
+#[inline(never)]
+pub fn catch<R>(f: impl FnOnce() -> R) -> Option<R> {
+    let mut buffer = JumpBuffer::default();
+    let previous = ARMED.swap(&mut buffer, Ordering::SeqCst);
+    let depth = interrupts::depth();
+    let previous_depth = ARMED_DEPTH.swap(depth, Ordering::SeqCst);
+    let held = lockdep::held_at(depth).unwrap_or(0);
+    let previous_held = ARMED_HELD.swap(held, Ordering::SeqCst);
+    let disarm = || {
+        ARMED.store(previous, Ordering::SeqCst);
+        ARMED_DEPTH.store(previous_depth, Ordering::SeqCst);
+        ARMED_HELD.store(previous_held, Ordering::SeqCst);
+    };
+    // everything used after the second return is set before `save` and
+    // left alone after, so the jump back finds it intact
+    if unsafe { save(&mut buffer) } != 0 {
+        disarm();
+        return None;
+    }
+    let result = f();
+    disarm();
+    Some(result)
+}
+
+/// Called by the panic handler: if a `catch` can take this panic, reports
+/// it and resumes there, never returning.
+///
+/// The logger's locks may be what the task died holding, so the report
+/// goes straight to COM1, and only if it is free.
+pub fn recover(info: &PanicInfo) {
+    let buffer = ARMED.swap(ptr::null_mut(), Ordering::SeqCst);
+    let depth = interrupts::depth();
+    if buffer.is_null() || depth != ARMED_DEPTH.load(Ordering::SeqCst) {
+        ARMED.store(buffer, Ordering::SeqCst);
+        return;
+    }
+    if lockdep::held_at(depth) != Some(ARMED_HELD.load(Ordering::SeqCst)) {
+        ARMED.store(buffer, Ordering::SeqCst);
+        report(format_args!("not recovering: the panic left a lock held"));
+        return;
+    }
+    match super::TaskId::current() {
+        Some(task) => report(format_args!("task {} panicked: {}", task, info)),
+        None => report(format_args!("panicked: {}", info)),
+    }
+    backtrace::dump_to_serial();
+    unsafe { restore(buffer) }
+}
+
+fn report(what: fmt::Arguments) {
+    without_interrupts(|| {
+        if let Some(mut serial) = SERIAL1.try_lock() {
+            let _ = writeln!(serial, "{}", what);
+        }
+    })
+}
+
+/// Returns 0, and 1 a second time when `restore` jumps back.
+#[naked]
+unsafe extern "C" fn save(buffer: *mut JumpBuffer) -> u64 {
+    asm!(
+        "mov [rdi], rbx",
+        "mov [rdi + 8], rbp",
+        "mov [rdi + 16], r12",
+        "mov [rdi + 24], r13",
+        "mov [rdi + 32], r14",
+        "mov [rdi + 40], r15",
+        // the stack pointer and return address as the caller sees them
+        "lea rax, [rsp + 8]",
+        "mov [rdi + 48], rax",
+        "mov rax, [rsp]",
+        "mov [rdi + 56], rax",
+        "pushfq",
+        "pop qword ptr [rdi + 64]",
+        "xor eax, eax",
+        "ret",
+        options(noreturn)
+    )
+}
+
+#[naked]
+unsafe extern "C" fn restore(buffer: *const JumpBuffer) -> ! {
+    asm!(
+        "mov rbx, [rdi]",
+        "mov rbp, [rdi + 8]",
+        "mov r12, [rdi + 16]",
+        "mov r13, [rdi + 24]",
+        "mov r14, [rdi + 32]",
+        "mov r15, [rdi + 40]",
+        "mov rsp, [rdi + 48]",
+        "push qword ptr [rdi + 64]",
+        "popfq",
+        "mov eax, 1",
+        "jmp qword ptr [rdi + 56]",
+        options(noreturn)
+    )
+}
@@ -1,40 +1,103 @@
 use core::task::Waker;
 
-use alloc::{sync::Arc, task::Wake};
-use crossbeam_queue::ArrayQueue;
+use alloc::{sync::Arc, task::Wake, vec::Vec};
 
-use super::{TaskFuture, TaskId};
+use super::{Priority, TaskFuture, TaskId};
+use crate::sync::MpscQueue;
 
+pub mod budget;
+pub mod hooks;
 pub mod priority;
+pub mod round_robin;
+pub mod spawner;
+mod stats;
+
+pub(crate) use self::stats::TaskStats;
+pub use self::{
+    spawner::{kill, spawn, Spawner},
+    stats::{init, set_priority, tasks, try_for_each_task, TaskInfo, TaskState},
+};
 
 #[derive(Debug)]
 pub enum Error {
     DuplicateId,
     TaskQueueFull,
     UnknownId,
+    TooManyHooks,
+    /// Asked to change a priority under a scheduler without any.
+    NoPriorities,
 }
 
 pub trait Scheduler<T: TaskFuture> {
     fn run(&mut self) -> !;
     fn spawn(&mut self, task: T) -> Result<(), Error>;
     fn kill(&mut self, task_id: TaskId) -> Result<(), Error>;
+    /// The tasks spawned and not yet finished, by id.
+    fn tasks(&self) -> Vec<TaskInfo>;
+    /// Wakes dropped on a full run queue since the scheduler was made.
+    fn lost_wakes(&self) -> u64;
+
+    /// Called by the implementation once `spawn` has taken a task. Like the
+    /// other hooks, passes the event on to the registered `hooks::Hooks`
+    /// unless overridden.
+    fn on_spawn(&self, task_id: TaskId, priority: Option<Priority>) {
+        hooks::on_spawn(task_id, priority);
+    }
+
+    /// Called right before each poll.
+    fn before_poll(&self, task_id: TaskId, priority: Option<Priority>) {
+        hooks::before_poll(task_id, priority);
+    }
+
+    /// Called right after each poll.
+    fn after_poll(&self, task_id: TaskId, ready: bool) {
+        hooks::after_poll(task_id, ready);
+    }
+
+    /// Called once a task is gone, finished or killed.
+    fn on_complete(&self, task_id: TaskId) {
+        hooks::on_complete(task_id);
+    }
+}
+
+/// Whether wakes were dropped on a full queue since `seen` was last
+/// updated, which it then is. The scheduler should then poll every task
+/// once, since there is no telling whose the wakes were.
+pub(crate) fn wakes_lost(overflows: u64, seen: &mut u64) -> bool {
+    if overflows == *seen {
+        return false;
+    }
+    warn!(
+        "run queue full; {} wakes lost, polling every task",
+        overflows - *seen
+    );
+    *seen = overflows;
+    true
 }
 
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    task_queue: Arc<MpscQueue<TaskId>>,
+    stats: Arc<TaskStats>,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    fn new(task_id: TaskId, task_queue: Arc<MpscQueue<TaskId>>, stats: Arc<TaskStats>) -> Waker {
         Waker::from(Arc::new(TaskWaker {
             task_id,
             task_queue,
+            stats,
         }))
     }
 
+    /// Queues the task unless it is queued already, so a burst of wakes
+    /// costs one entry and one poll. A full queue counts the lost wake; the
+    /// scheduler notices the count going up and polls every task once to
+    /// make up for it, which also clears the task's ready flag.
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        if self.stats.woken() {
+            let _ = self.task_queue.push(self.task_id);
+        }
     }
 }
 
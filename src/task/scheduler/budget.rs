@@ -0,0 +1,216 @@
+//! A scheduler between the other two: each priority gets a budget of polls
+//! per round, high first, and a round ends once every class has used its
+//! budget or run out of ready tasks.
+//!
+//! With the default budgets of 8, 4 and 2, busy high priority tasks get
+//! eight polls for every four medium and two low ones, instead of all of
+//! them as under `PriorityScheduler`, and a class with nothing ready leaves
+//! its share to the others.
+
+use super::{priority::PriorityWaker, spawner, Error, Scheduler, TaskInfo, TaskStats};
+use crate::{
+    config::scheduler::QUEUE_DEPTH,
+    interrupts,
+    sync::MpscQueue,
+    task::{pi_mutex, Priority, PriorityTask, TaskFuture, TaskId},
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::task::{Context, Poll, Waker};
+
+/// The classes in the order a round visits them.
+const ROUND: [Priority; 3] = [Priority::High, Priority::Medium, Priority::Low];
+
+pub struct BudgetScheduler {
+    tasks: BTreeMap<TaskId, PriorityTask>,
+    /// Run queues, indexed by priority.
+    queues: [Arc<MpscQueue<TaskId>>; 3],
+    /// Polls per round, indexed by priority.
+    budgets: [usize; 3],
+    waker_cache: BTreeMap<TaskId, Waker>,
+    stats: BTreeMap<TaskId, Arc<TaskStats>>,
+    /// Queue overflows, over all three queues, already made up for.
+    overflows_seen: u64,
+}
+
+impl BudgetScheduler {
+    /// Uses the budgets of `budget=`, or the build's.
+    pub fn new() -> Self {
+        Self::with_budgets(crate::boot::cmdline::options().budgets)
+    }
+
+    /// `budgets` are polls per round, indexed by priority, low first; a
+    /// zero is taken as one so no class starves.
+    pub fn with_budgets(budgets: [usize; 3]) -> Self {
+        let queue = || Arc::new(MpscQueue::with_capacity(QUEUE_DEPTH));
+        BudgetScheduler {
+            tasks: BTreeMap::new(),
+            queues: [queue(), queue(), queue()],
+            budgets: [budgets[0].max(1), budgets[1].max(1), budgets[2].max(1)],
+            waker_cache: BTreeMap::new(),
+            stats: BTreeMap::new(),
+            overflows_seen: 0,
+        }
+    }
+
+    pub fn run_ready_tasks(&mut self) {
+        loop {
+            while self.run_round() {}
+            if !self.recover_lost_wakes() {
+                break;
+            }
+        }
+    }
+
+    /// Gives each class up to its budget of polls. Returns whether any
+    /// task ran.
+    fn run_round(&mut self) -> bool {
+        let mut ran = false;
+        for &priority in ROUND.iter() {
+            for _ in 0..self.budgets[priority as usize] {
+                let task_id = match self.queues[priority as usize].pop() {
+                    Some(task_id) => task_id,
+                    None => break,
+                };
+                self.poll_task(task_id);
+                ran = true;
+            }
+        }
+        ran
+    }
+
+    /// Polls every task once if wakes were dropped on a full queue, since
+    /// there is no telling whose they were. Returns whether it did.
+    fn recover_lost_wakes(&mut self) -> bool {
+        let overflows: u64 = self.queues.iter().map(|queue| queue.overflows()).sum();
+        if !super::wakes_lost(overflows, &mut self.overflows_seen) {
+            return false;
+        }
+        let task_ids: Vec<TaskId> = self.tasks.keys().copied().collect();
+        for task_id in task_ids {
+            self.poll_task(task_id);
+        }
+        true
+    }
+
+    fn poll_task(&mut self, task_id: TaskId) {
+        let priority = match self.stats.get(&task_id).and_then(|stats| stats.priority()) {
+            Some(base) => pi_mutex::effective_priority(task_id, base),
+            None => return,
+        };
+        self.before_poll(task_id, Some(priority));
+        let poll = {
+            let Self {
+                tasks,
+                queues,
+                waker_cache,
+                stats,
+                ..
+            } = self;
+
+            let (task, stats) = match (tasks.get_mut(&task_id), stats.get(&task_id)) {
+                (Some(task), Some(stats)) => (task, stats),
+                _ => return,
+            };
+            let waker = waker_cache
+                .entry(task_id)
+                .or_insert_with(|| PriorityWaker::new(task_id, queues.clone(), stats.clone()));
+            let mut context = Context::from_waker(waker);
+            TaskId::set_current(Some(task_id));
+            Priority::set_current(Some(priority));
+            let poll = stats.poll(task, &mut context);
+            Priority::set_current(None);
+            TaskId::set_current(None);
+            poll
+        };
+        crate::debug::canary::check(Some(task_id));
+        crate::sync::rcu::quiescent();
+        self.after_poll(task_id, poll != Some(Poll::Pending));
+        if poll == Some(Poll::Pending) {
+            return;
+        }
+        // done, or panicked and not to be dropped
+        if let Some(task) = self.tasks.remove(&task_id) {
+            if poll.is_none() {
+                task.abandon();
+            }
+        }
+        self.waker_cache.remove(&task_id);
+        if let Some(stats) = self.stats.remove(&task_id) {
+            stats.untrack();
+        }
+        crate::fs::fd::release(task_id);
+        self.on_complete(task_id);
+    }
+
+    fn sleep_if_idle(&self) {
+        interrupts::disable();
+        if self.queues.iter().all(|queue| queue.is_empty()) && spawner::is_empty() {
+            crate::cpu::idle::wait();
+        } else {
+            interrupts::enable();
+        }
+    }
+
+    /// Kills and starts the tasks queued through `spawner`.
+    fn start_spawned(&mut self) {
+        for task_id in spawner::take_kills() {
+            let _ = self.kill(task_id);
+        }
+        for task in spawner::take() {
+            if let Err(err) = self.spawn(task) {
+                error!("failed to spawn a task: {:?}", err);
+            }
+        }
+    }
+}
+
+impl Scheduler<PriorityTask> for BudgetScheduler {
+    fn run(&mut self) -> ! {
+        loop {
+            self.start_spawned();
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn spawn(&mut self, task: PriorityTask) -> Result<(), Error> {
+        let task_id = task.id();
+        let priority = task.priority();
+        if self.tasks.insert(task_id, task).is_some() {
+            return Err(Error::DuplicateId);
+        }
+        if self.queues[priority as usize].push(task_id).is_err() {
+            self.tasks.remove(&task_id);
+            return Err(Error::TaskQueueFull);
+        }
+        self.stats
+            .insert(task_id, TaskStats::track(task_id, Some(priority)));
+        self.on_spawn(task_id, Some(priority));
+        Ok(())
+    }
+
+    fn kill(&mut self, task_id: TaskId) -> Result<(), Error> {
+        self.tasks.remove(&task_id).ok_or(Error::UnknownId)?;
+        self.waker_cache.remove(&task_id);
+        if let Some(stats) = self.stats.remove(&task_id) {
+            stats.untrack();
+        }
+        crate::fs::fd::release(task_id);
+        self.on_complete(task_id);
+        Ok(())
+    }
+
+    fn tasks(&self) -> Vec<TaskInfo> {
+        self.stats.values().map(|stats| stats.info()).collect()
+    }
+
+    fn lost_wakes(&self) -> u64 {
+        self.queues.iter().map(|queue| queue.overflows()).sum()
+    }
+}
+
+impl Drop for BudgetScheduler {
+    fn drop(&mut self) {
+        self.stats.values().for_each(|stats| stats.untrack());
+    }
+}
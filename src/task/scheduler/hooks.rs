@@ -0,0 +1,73 @@
+//! Instrumentation that sees every scheduler's tasks come, run and go,
+//! without patching each scheduler.
+//!
+//! The schedulers call the `Scheduler` trait's hook methods at each point,
+//! and the default methods pass the call on to every `Hooks` registered
+//! here. Hooks are called from the scheduler loop, between polls, and stay
+//! registered for good.
+
+use super::Error;
+use crate::{
+    sync::Once,
+    task::{Priority, TaskId},
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Hooks that can be registered.
+const MAX_HOOKS: usize = 4;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Once<&'static dyn Hooks> = Once::new();
+static HOOKS: [Once<&'static dyn Hooks>; MAX_HOOKS] = [EMPTY; MAX_HOOKS];
+/// Slots taken so far.
+static TAKEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Callbacks around a task's life; each does nothing unless implemented.
+/// They run on the scheduler's time, so they should be quick.
+pub trait Hooks: Sync {
+    /// A task was handed to the scheduler; `priority` is `None` under a
+    /// scheduler without priorities.
+    fn on_spawn(&self, _task_id: TaskId, _priority: Option<Priority>) {}
+
+    /// The task is about to be polled, at `priority` with boosts included.
+    fn before_poll(&self, _task_id: TaskId, _priority: Option<Priority>) {}
+
+    /// The poll returned; `ready` if the task finished or panicked.
+    fn after_poll(&self, _task_id: TaskId, _ready: bool) {}
+
+    /// The task left the scheduler: it finished, panicked or was killed.
+    fn on_complete(&self, _task_id: TaskId) {}
+}
+
+/// Adds `hooks` to those every scheduler calls.
+pub fn register(hooks: &'static dyn Hooks) -> Result<(), Error> {
+    let slot = TAKEN.fetch_add(1, Ordering::Relaxed);
+    if slot >= MAX_HOOKS {
+        TAKEN.fetch_sub(1, Ordering::Relaxed);
+        return Err(Error::TooManyHooks);
+    }
+    HOOKS[slot].call_once(|| hooks);
+    Ok(())
+}
+
+fn each(f: impl Fn(&dyn Hooks)) {
+    for hooks in HOOKS.iter().filter_map(Once::get) {
+        f(*hooks);
+    }
+}
+
+pub(super) fn on_spawn(task_id: TaskId, priority: Option<Priority>) {
+    each(|hooks| hooks.on_spawn(task_id, priority));
+}
+
+pub(super) fn before_poll(task_id: TaskId, priority: Option<Priority>) {
+    each(|hooks| hooks.before_poll(task_id, priority));
+}
+
+pub(super) fn after_poll(task_id: TaskId, ready: bool) {
+    each(|hooks| hooks.after_poll(task_id, ready));
+}
+
+pub(super) fn on_complete(task_id: TaskId) {
+    each(|hooks| hooks.on_complete(task_id));
+}
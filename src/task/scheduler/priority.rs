@@ -1,69 +1,138 @@
 use crate::{
+    config::scheduler::QUEUE_DEPTH,
     interrupts,
-    task::{Priority, PriorityTask, TaskFuture, TaskId},
+    sync::MpscQueue,
+    task::{pi_mutex, Priority, PriorityTask, TaskFuture, TaskId},
 };
-use alloc::{collections::BTreeMap, sync::Arc};
+use alloc::{collections::BTreeMap, sync::Arc, task::Wake, vec::Vec};
 use core::task::{Context, Poll, Waker};
-use crossbeam_queue::ArrayQueue;
 
-use super::{Error, Scheduler, TaskWaker};
+use super::{spawner, Error, Scheduler, TaskInfo, TaskStats};
 
 pub struct PriorityScheduler {
     tasks: BTreeMap<TaskId, PriorityTask>,
-    high_queue: Arc<ArrayQueue<TaskId>>,
-    medium_queue: Arc<ArrayQueue<TaskId>>,
-    low_queue: Arc<ArrayQueue<TaskId>>,
+    high_queue: Arc<MpscQueue<TaskId>>,
+    medium_queue: Arc<MpscQueue<TaskId>>,
+    low_queue: Arc<MpscQueue<TaskId>>,
     waker_cache: BTreeMap<TaskId, Waker>,
+    stats: BTreeMap<TaskId, Arc<TaskStats>>,
+    /// Queue overflows, over all three queues, already made up for.
+    overflows_seen: u64,
 }
 
 impl PriorityScheduler {
     pub fn new() -> Self {
         PriorityScheduler {
             tasks: BTreeMap::new(),
-            high_queue: Arc::new(ArrayQueue::new(1024)),
-            medium_queue: Arc::new(ArrayQueue::new(1024)),
-            low_queue: Arc::new(ArrayQueue::new(1024)),
+            high_queue: Arc::new(MpscQueue::with_capacity(QUEUE_DEPTH)),
+            medium_queue: Arc::new(MpscQueue::with_capacity(QUEUE_DEPTH)),
+            low_queue: Arc::new(MpscQueue::with_capacity(QUEUE_DEPTH)),
             waker_cache: BTreeMap::new(),
+            stats: BTreeMap::new(),
+            overflows_seen: 0,
         }
     }
 
     pub fn run_ready_tasks(&mut self) {
-        while !self.is_idle() {
-            if let Ok(task_id) = self.high_queue.pop() {
-                self.execute_priority_task(task_id, self.high_queue.clone());
-            } else if let Ok(task_id) = self.medium_queue.pop() {
-                self.execute_priority_task(task_id, self.medium_queue.clone());
-            } else if let Ok(task_id) = self.low_queue.pop() {
-                self.execute_priority_task(task_id, self.low_queue.clone());
+        loop {
+            while let Some(task_id) = self.next_task() {
+                self.execute_priority_task(task_id);
+            }
+            if !self.recover_lost_wakes() {
+                break;
             }
         }
     }
 
-    fn execute_priority_task(&mut self, task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) {
-        let Self {
-            tasks, waker_cache, ..
-        } = self;
+    /// Polls every task once if wakes were dropped on a full queue, since
+    /// there is no telling whose they were. Returns whether it did.
+    fn recover_lost_wakes(&mut self) -> bool {
+        let overflows = self.high_queue.overflows()
+            + self.medium_queue.overflows()
+            + self.low_queue.overflows();
+        if !super::wakes_lost(overflows, &mut self.overflows_seen) {
+            return false;
+        }
+        let task_ids: Vec<TaskId> = self.tasks.keys().copied().collect();
+        for task_id in task_ids {
+            self.execute_priority_task(task_id);
+        }
+        true
+    }
+
+    fn queue(&self, priority: Priority) -> &Arc<MpscQueue<TaskId>> {
+        match priority {
+            Priority::High => &self.high_queue,
+            Priority::Medium => &self.medium_queue,
+            Priority::Low => &self.low_queue,
+        }
+    }
+
+    /// Pops the highest-priority ready task.
+    fn next_task(&self) -> Option<TaskId> {
+        self.high_queue
+            .pop()
+            .or_else(|| self.medium_queue.pop())
+            .or_else(|| self.low_queue.pop())
+    }
 
-        if let Some(task) = tasks.get_mut(&task_id) {
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+    fn execute_priority_task(&mut self, task_id: TaskId) {
+        let priority = match self.stats.get(&task_id).and_then(|stats| stats.priority()) {
+            Some(base) => pi_mutex::effective_priority(task_id, base),
+            None => return,
+        };
+        self.before_poll(task_id, Some(priority));
+        let poll = {
+            let Self {
+                tasks,
+                waker_cache,
+                high_queue,
+                medium_queue,
+                low_queue,
+                stats,
+                ..
+            } = self;
+
+            let (task, stats) = match (tasks.get_mut(&task_id), stats.get(&task_id)) {
+                (Some(task), Some(stats)) => (task, stats),
+                _ => return,
+            };
+            let waker = waker_cache.entry(task_id).or_insert_with(|| {
+                let queues = [low_queue.clone(), medium_queue.clone(), high_queue.clone()];
+                PriorityWaker::new(task_id, queues, stats.clone())
+            });
             let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
+            TaskId::set_current(Some(task_id));
+            Priority::set_current(Some(priority));
+            let poll = stats.poll(task, &mut context);
+            Priority::set_current(None);
+            TaskId::set_current(None);
+            poll
+        };
+        crate::debug::canary::check(Some(task_id));
+        crate::sync::rcu::quiescent();
+        self.after_poll(task_id, poll != Some(Poll::Pending));
+        if poll == Some(Poll::Pending) {
+            return;
+        }
+        // task done or panicked -> remove it and its cached waker
+        if let Some(task) = self.tasks.remove(&task_id) {
+            if poll.is_none() {
+                task.abandon();
             }
         }
+        self.waker_cache.remove(&task_id);
+        if let Some(stats) = self.stats.remove(&task_id) {
+            stats.untrack();
+        }
+        crate::fs::fd::release(task_id);
+        self.on_complete(task_id);
     }
 
     fn sleep_if_idle(&self) {
         interrupts::disable();
         if self.is_idle() {
-            interrupts::enable_and_hlt();
+            crate::cpu::idle::wait();
         } else {
             interrupts::enable();
         }
@@ -72,13 +141,27 @@ impl PriorityScheduler {
     fn is_idle(&self) -> bool {
         return self.high_queue.is_empty()
             && self.medium_queue.is_empty()
-            && self.low_queue.is_empty();
+            && self.low_queue.is_empty()
+            && spawner::is_empty();
+    }
+
+    /// Kills and starts the tasks queued through `spawner`.
+    fn start_spawned(&mut self) {
+        for task_id in spawner::take_kills() {
+            let _ = self.kill(task_id);
+        }
+        for task in spawner::take() {
+            if let Err(err) = self.spawn(task) {
+                error!("failed to spawn a task: {:?}", err);
+            }
+        }
     }
 }
 
 impl Scheduler<PriorityTask> for PriorityScheduler {
     fn run(&mut self) -> ! {
         loop {
+            self.start_spawned();
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
@@ -90,24 +173,82 @@ impl Scheduler<PriorityTask> for PriorityScheduler {
         if self.tasks.insert(task_id, task).is_some() {
             return Err(Error::DuplicateId);
         }
-        match priority {
-            Priority::High => self
-                .high_queue
-                .push(task_id)
-                .map_err(|_| Error::TaskQueueFull),
-            Priority::Medium => self
-                .medium_queue
-                .push(task_id)
-                .map_err(|_| Error::TaskQueueFull),
-            Priority::Low => self
-                .low_queue
-                .push(task_id)
-                .map_err(|_| Error::TaskQueueFull),
+        if self.queue(priority).push(task_id).is_err() {
+            self.tasks.remove(&task_id);
+            return Err(Error::TaskQueueFull);
         }
+        self.stats
+            .insert(task_id, TaskStats::track(task_id, Some(priority)));
+        self.on_spawn(task_id, Some(priority));
+        Ok(())
     }
 
     fn kill(&mut self, task_id: TaskId) -> Result<(), Error> {
         self.tasks.remove(&task_id).ok_or(Error::UnknownId)?;
+        if let Some(stats) = self.stats.remove(&task_id) {
+            stats.untrack();
+        }
+        crate::fs::fd::release(task_id);
+        self.on_complete(task_id);
         Ok(())
     }
+
+    fn tasks(&self) -> Vec<TaskInfo> {
+        self.stats.values().map(|stats| stats.info()).collect()
+    }
+
+    fn lost_wakes(&self) -> u64 {
+        self.high_queue.overflows() + self.medium_queue.overflows() + self.low_queue.overflows()
+    }
+}
+
+impl Drop for PriorityScheduler {
+    fn drop(&mut self) {
+        self.stats.values().for_each(|stats| stats.untrack());
+    }
+}
+
+/// Wakes a task into the queue for its priority at the time, so a boost
+/// lent by a `PiMutex` or a `set_priority` takes effect on its next wake.
+pub(super) struct PriorityWaker {
+    task_id: TaskId,
+    /// Run queues, indexed by priority.
+    queues: [Arc<MpscQueue<TaskId>>; 3],
+    stats: Arc<TaskStats>,
+}
+
+impl PriorityWaker {
+    pub(super) fn new(
+        task_id: TaskId,
+        queues: [Arc<MpscQueue<TaskId>>; 3],
+        stats: Arc<TaskStats>,
+    ) -> Waker {
+        Waker::from(Arc::new(PriorityWaker {
+            task_id,
+            queues,
+            stats,
+        }))
+    }
+
+    /// Skips tasks already queued and counts wakes lost to a full queue,
+    /// as `TaskWaker` does. A task boosted while queued keeps its place.
+    fn wake_task(&self) {
+        if !self.stats.woken() {
+            return;
+        }
+        // tracked with a priority, which `set_priority` only ever replaces
+        let base = self.stats.priority().unwrap_or(Priority::Medium);
+        let priority = pi_mutex::effective_priority(self.task_id, base);
+        let _ = self.queues[priority as usize].push(self.task_id);
+    }
+}
+
+impl Wake for PriorityWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
 }
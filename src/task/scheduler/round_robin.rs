@@ -1,65 +1,137 @@
-use super::{Error, Scheduler, TaskWaker};
-use crate::task::{TaskFuture, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc};
+use super::{spawner, Error, Scheduler, TaskInfo, TaskStats, TaskWaker};
+use crate::{
+    config::scheduler::QUEUE_DEPTH,
+    sync::MpscQueue,
+    task::{PriorityTask, TaskFuture, TaskId},
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
 use core::task::{Context, Poll, Waker};
-use crossbeam_queue::ArrayQueue;
 
 pub struct RoundRobinScheduler<T: TaskFuture> {
     tasks: BTreeMap<TaskId, T>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    task_queue: Arc<MpscQueue<TaskId>>,
     waker_cache: BTreeMap<TaskId, Waker>,
+    stats: BTreeMap<TaskId, Arc<TaskStats>>,
+    /// Queue overflows already made up for.
+    overflows_seen: u64,
 }
 
-impl<T: TaskFuture> RoundRobinScheduler<T> {
+impl<T: TaskFuture + From<PriorityTask>> RoundRobinScheduler<T> {
     pub fn new() -> Self {
         RoundRobinScheduler {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(1024)),
+            task_queue: Arc::new(MpscQueue::with_capacity(QUEUE_DEPTH)),
             waker_cache: BTreeMap::new(),
+            stats: BTreeMap::new(),
+            overflows_seen: 0,
         }
     }
 
     pub fn run_ready_tasks(&mut self) {
-        let Self {
-            tasks,
-            task_queue,
-            waker_cache,
-        } = self;
-
-        while let Ok(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue,
+        loop {
+            while let Some(task_id) = self.task_queue.pop() {
+                self.poll_task(task_id);
+            }
+            if !self.recover_lost_wakes() {
+                break;
+            }
+        }
+    }
+
+    /// Polls every task once if wakes were dropped on a full queue, since
+    /// there is no telling whose they were. Returns whether it did.
+    fn recover_lost_wakes(&mut self) -> bool {
+        let overflows = self.task_queue.overflows();
+        if !super::wakes_lost(overflows, &mut self.overflows_seen) {
+            return false;
+        }
+        let task_ids: Vec<TaskId> = self.tasks.keys().copied().collect();
+        for task_id in task_ids {
+            self.poll_task(task_id);
+        }
+        true
+    }
+
+    fn poll_task(&mut self, task_id: TaskId) {
+        if !self.tasks.contains_key(&task_id) {
+            return;
+        }
+        self.before_poll(task_id, None);
+        let poll = {
+            let Self {
+                tasks,
+                task_queue,
+                waker_cache,
+                stats,
+                ..
+            } = self;
+
+            let (task, stats) = match (tasks.get_mut(&task_id), stats.get(&task_id)) {
+                (Some(task), Some(stats)) => (task, stats),
+                _ => return,
             };
             let waker = waker_cache
                 .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone(), stats.clone()));
             let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
+            TaskId::set_current(Some(task_id));
+            let poll = stats.poll(task, &mut context);
+            TaskId::set_current(None);
+            poll
+        };
+        crate::debug::canary::check(Some(task_id));
+        crate::sync::rcu::quiescent();
+        self.after_poll(task_id, poll != Some(Poll::Pending));
+        if poll == Some(Poll::Pending) {
+            return;
+        }
+        // done, or panicked and not to be dropped
+        if let Some(task) = self.tasks.remove(&task_id) {
+            if poll.is_none() {
+                task.abandon();
             }
         }
+        self.waker_cache.remove(&task_id);
+        self.untrack(task_id);
+        crate::fs::fd::release(task_id);
+        self.on_complete(task_id);
+    }
+
+    fn untrack(&mut self, task_id: TaskId) {
+        if let Some(stats) = self.stats.remove(&task_id) {
+            stats.untrack();
+        }
     }
 
     fn sleep_if_idle(&self) {
-        use x86_64::instructions::interrupts::{self, enable_interrupts_and_hlt};
+        use x86_64::instructions::interrupts;
 
         interrupts::disable();
-        if self.task_queue.is_empty() {
-            enable_interrupts_and_hlt();
+        if self.task_queue.is_empty() && spawner::is_empty() {
+            crate::cpu::idle::wait();
         } else {
             interrupts::enable();
         }
     }
+
+    /// Kills and starts the tasks queued through `spawner`, the started
+    /// ones without their priority.
+    fn start_spawned(&mut self) {
+        for task_id in spawner::take_kills() {
+            let _ = self.kill(task_id);
+        }
+        for task in spawner::take() {
+            if let Err(err) = self.spawn(T::from(task)) {
+                error!("failed to spawn a task: {:?}", err);
+            }
+        }
+    }
 }
 
-impl<T: TaskFuture> Scheduler<T> for RoundRobinScheduler<T> {
+impl<T: TaskFuture + From<PriorityTask>> Scheduler<T> for RoundRobinScheduler<T> {
     fn run(&mut self) -> ! {
         loop {
+            self.start_spawned();
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
@@ -70,13 +142,34 @@ impl<T: TaskFuture> Scheduler<T> for RoundRobinScheduler<T> {
         if self.tasks.insert(task_id, task).is_some() {
             return Err(Error::DuplicateId);
         }
-        self.task_queue
-            .push(task_id)
-            .map_err(|_| Error::TaskQueueFull)
+        if self.task_queue.push(task_id).is_err() {
+            self.tasks.remove(&task_id);
+            return Err(Error::TaskQueueFull);
+        }
+        self.stats.insert(task_id, TaskStats::track(task_id, None));
+        self.on_spawn(task_id, None);
+        Ok(())
     }
 
     fn kill(&mut self, task_id: TaskId) -> Result<(), Error> {
         self.tasks.remove(&task_id).ok_or(Error::UnknownId)?;
+        self.untrack(task_id);
+        crate::fs::fd::release(task_id);
+        self.on_complete(task_id);
         Ok(())
     }
+
+    fn tasks(&self) -> Vec<TaskInfo> {
+        self.stats.values().map(|stats| stats.info()).collect()
+    }
+
+    fn lost_wakes(&self) -> u64 {
+        self.task_queue.overflows()
+    }
+}
+
+impl<T: TaskFuture> Drop for RoundRobinScheduler<T> {
+    fn drop(&mut self) {
+        self.stats.values().for_each(|stats| stats.untrack());
+    }
 }
@@ -0,0 +1,68 @@
+//! Starting and stopping tasks from code that has no handle on the
+//! scheduler, such as other tasks and shell commands. Requests wait here
+//! until the running scheduler's next pass takes them, kills first.
+
+use crate::{
+    kernel::Spawn,
+    sync::Mutex,
+    task::{PriorityTask, TaskFuture, TaskId},
+};
+use alloc::vec::Vec;
+
+struct Queued(PriorityTask);
+
+// One CPU: a queued task is only ever polled where it was spawned.
+unsafe impl Send for Queued {}
+
+static QUEUE: Mutex<Vec<Queued>> = Mutex::new(Vec::new());
+static KILLS: Mutex<Vec<TaskId>> = Mutex::new(Vec::new());
+
+/// The services' default spawner, queueing here.
+#[derive(Debug, Clone, Copy)]
+pub struct Spawner {
+    _private: (),
+}
+
+impl Spawner {
+    pub(crate) const fn new() -> Self {
+        Spawner { _private: () }
+    }
+}
+
+impl Spawn for Spawner {
+    fn spawn_task(&self, task: PriorityTask) -> TaskId {
+        spawn(task)
+    }
+
+    fn kill(&self, task_id: TaskId) {
+        kill(task_id)
+    }
+}
+
+pub fn spawn(task: PriorityTask) -> TaskId {
+    let task_id = task.id();
+    QUEUE.lock().push(Queued(task));
+    task_id
+}
+
+/// Has the scheduler drop `task_id` on its next pass, unless the task is
+/// the one running, which would drop itself.
+pub fn kill(task_id: TaskId) {
+    if TaskId::current() != Some(task_id) {
+        KILLS.lock().push(task_id);
+    }
+}
+
+/// Takes every queued task, for the scheduler to start.
+pub(crate) fn take() -> Vec<PriorityTask> {
+    QUEUE.lock().drain(..).map(|Queued(task)| task).collect()
+}
+
+/// Takes every task queued to be killed.
+pub(crate) fn take_kills() -> Vec<TaskId> {
+    KILLS.lock().drain(..).collect()
+}
+
+pub(crate) fn is_empty() -> bool {
+    QUEUE.lock().is_empty() && KILLS.lock().is_empty()
+}
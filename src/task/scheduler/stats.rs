@@ -0,0 +1,304 @@
+//! What the schedulers know about their tasks, for `ps` and `top`, and
+//! the `kill` and `renice` commands to deal with one that runs away.
+//!
+//! A shell command runs inside a task, so it cannot reach the scheduler
+//! polling it. Each task's counters are shared instead: the scheduler
+//! updates them around polls, the task's waker marks it ready, and
+//! `tasks` reads them all from a table the scheduler keeps current. The
+//! priority a task is queued at lives there too, so `set_priority` can
+//! change it without waiting for the scheduler.
+
+use super::{spawner, Error};
+use crate::{
+    error::KernelError,
+    shell::{self, Args, ShellErr},
+    sync::{Lazy, RwLock},
+    task::{pi_mutex, recover, Priority, TaskFuture, TaskId, NO_PRIORITY},
+    time,
+};
+use alloc::{collections::BTreeMap, format, sync::Arc, vec::Vec};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Being polled; only ever the task asking.
+    Running,
+    /// Woken and waiting for its turn.
+    Ready,
+    /// Waiting for a wake.
+    Pending,
+}
+
+impl TaskState {
+    pub fn name(self) -> &'static str {
+        match self {
+            TaskState::Running => "running",
+            TaskState::Ready => "ready",
+            TaskState::Pending => "pending",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    /// Boosts included; `None` under a scheduler without priorities.
+    pub priority: Option<Priority>,
+    pub state: TaskState,
+    pub polls: u64,
+    /// Time spent in `poll`, in microseconds.
+    pub runtime_us: u64,
+}
+
+/// One task's counters, shared by its scheduler and its waker.
+pub(crate) struct TaskStats {
+    id: TaskId,
+    /// Before boosts; `NO_PRIORITY` under a scheduler without priorities.
+    priority: AtomicU8,
+    polls: AtomicU64,
+    runtime_us: AtomicU64,
+    /// Woken and not polled since, so already in a run queue.
+    ready: AtomicBool,
+}
+
+static TABLE: Lazy<RwLock<BTreeMap<TaskId, Arc<TaskStats>>>> =
+    Lazy::new(|| RwLock::new(BTreeMap::new()));
+
+impl TaskStats {
+    /// Starts counting for a task being spawned, which is queued and so
+    /// starts out ready.
+    pub(crate) fn track(id: TaskId, priority: Option<Priority>) -> Arc<TaskStats> {
+        let stats = Arc::new(TaskStats {
+            id,
+            priority: AtomicU8::new(priority.map_or(NO_PRIORITY, |p| p as u8)),
+            polls: AtomicU64::new(0),
+            runtime_us: AtomicU64::new(0),
+            ready: AtomicBool::new(true),
+        });
+        TABLE.write().insert(id, stats.clone());
+        stats
+    }
+
+    /// Drops a task that finished or was killed from the table.
+    pub(crate) fn untrack(&self) {
+        TABLE.write().remove(&self.id);
+    }
+
+    /// The priority the task is queued at, before boosts.
+    pub(crate) fn priority(&self) -> Option<Priority> {
+        Priority::from_u8(self.priority.load(Ordering::Relaxed))
+    }
+
+    /// Marks the task ready. False if it already was: it is queued, and
+    /// queueing it again would only poll it twice for one wake.
+    pub(crate) fn woken(&self) -> bool {
+        !self.ready.swap(true, Ordering::Relaxed)
+    }
+
+    /// Polls `task`, counting the poll and the time it took. `None` if the
+    /// task panicked, see `task::recover`.
+    pub(crate) fn poll<T: TaskFuture>(
+        &self,
+        task: &mut T,
+        context: &mut Context,
+    ) -> Option<Poll<()>> {
+        // cleared first, so a task that wakes itself stays ready
+        self.ready.store(false, Ordering::Relaxed);
+        let start = time::uptime_us();
+        let poll = recover::catch(|| task.poll(context));
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.runtime_us
+            .fetch_add(time::uptime_us().saturating_sub(start), Ordering::Relaxed);
+        poll
+    }
+
+    pub(crate) fn info(&self) -> TaskInfo {
+        let state = if TaskId::current() == Some(self.id) {
+            TaskState::Running
+        } else if self.ready.load(Ordering::Relaxed) {
+            TaskState::Ready
+        } else {
+            TaskState::Pending
+        };
+        TaskInfo {
+            id: self.id,
+            priority: self
+                .priority()
+                .map(|priority| pi_mutex::effective_priority(self.id, priority)),
+            state,
+            polls: self.polls.load(Ordering::Relaxed),
+            runtime_us: self.runtime_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The running scheduler's tasks, by id. Unlike `Scheduler::tasks`, this
+/// works from inside a task.
+pub fn tasks() -> Vec<TaskInfo> {
+    TABLE.read().values().map(|stats| stats.info()).collect()
+}
+
+/// Has `task_id` queued at `priority` from its next wake on, boosts lent
+/// by a `PiMutex` still applying. Like `tasks`, works from inside a task.
+pub fn set_priority(task_id: TaskId, priority: Priority) -> Result<(), Error> {
+    let table = TABLE.read();
+    let stats = table.get(&task_id).ok_or(Error::UnknownId)?;
+    if stats.priority().is_none() {
+        return Err(Error::NoPriorities);
+    }
+    stats.priority.store(priority as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Calls `f` for every task without allocating or waiting, for the panic
+/// path. Returns false, having called nothing, if the table was locked.
+pub fn try_for_each_task(mut f: impl FnMut(TaskInfo)) -> bool {
+    match TABLE.try_read() {
+        Some(table) => {
+            table.values().for_each(|stats| f(stats.info()));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Registers `ps`, `top`, `kill` and `renice`.
+pub fn init() {
+    shell::register(
+        "ps",
+        "ps\nLists the scheduler's tasks with their state, polls and time spent running.",
+        ps_command,
+    );
+    shell::register(
+        "top",
+        "top [seconds]\nShows the busiest tasks, refreshing until a key is pressed.",
+        top_command,
+    );
+    shell::register(
+        "kill",
+        "kill <task>\nStops a task, as numbered by ps, before its next poll.",
+        kill_command,
+    );
+    shell::register(
+        "renice",
+        "renice <task> <high|medium|low|up|down>\nChanges the priority a task is queued at.",
+        renice_command,
+    );
+}
+
+fn priority_name(priority: Option<Priority>) -> &'static str {
+    match priority {
+        Some(Priority::High) => "high",
+        Some(Priority::Medium) => "medium",
+        Some(Priority::Low) => "low",
+        None => "-",
+    }
+}
+
+fn write_table(tasks: &[TaskInfo], out: &mut dyn Write) -> Result<(), ShellErr> {
+    writeln!(
+        out,
+        "{:>6} {:<8} {:<8} {:>10} {:>12}",
+        "TASK", "PRIO", "STATE", "POLLS", "RUNTIME ms"
+    )?;
+    for task in tasks {
+        writeln!(
+            out,
+            "{:>6} {:<8} {:<8} {:>10} {:>8}.{:03}",
+            task.id.as_u64(),
+            priority_name(task.priority),
+            task.state.name(),
+            task.polls,
+            task.runtime_us / 1000,
+            task.runtime_us % 1000
+        )?;
+    }
+    Ok(())
+}
+
+/// `ps`
+fn ps_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    Args::new(args).finish()?;
+    write_table(&tasks(), out)
+}
+
+/// Task rows that fit on the screen under `top`'s two header lines.
+const TOP_ROWS: usize = 20;
+
+/// `top [seconds]`
+fn top_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    let seconds = args.optional_number()?.unwrap_or(2).max(1);
+    args.finish()?;
+    let mut tasks = tasks();
+    tasks.sort_by(|a, b| b.runtime_us.cmp(&a.runtime_us));
+    tasks.truncate(TOP_ROWS);
+    writeln!(
+        out,
+        "up {} s, {} tasks, refreshing every {} s; any key quits",
+        time::uptime_ms() / 1000,
+        crate::task::live_count(),
+        seconds
+    )?;
+    write_table(&tasks, out)?;
+    shell::refresh(seconds * 1000);
+    Ok(())
+}
+
+/// The task `ps` lists as `id`.
+fn find(id: u64) -> Result<Arc<TaskStats>, ShellErr> {
+    TABLE
+        .read()
+        .values()
+        .find(|stats| stats.id.as_u64() == id)
+        .cloned()
+        .ok_or_else(|| ShellErr::from(format!("no task {}", id)))
+}
+
+/// `kill <task>`
+fn kill_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    let stats = find(args.number("task")?)?;
+    args.finish()?;
+    if TaskId::current() == Some(stats.id) {
+        return Err(ShellErr::new("cannot kill the shell's own task"));
+    }
+    spawner::kill(stats.id);
+    writeln!(out, "task {} killed", stats.id)?;
+    Ok(())
+}
+
+/// `renice <task> <high|medium|low|up|down>`
+fn renice_command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    let mut args = Args::new(args);
+    let stats = find(args.number("task")?)?;
+    let level = args.required("priority")?;
+    args.finish()?;
+    let old = stats
+        .priority()
+        .ok_or_else(|| ShellErr::new("the scheduler has no priorities"))?;
+    let new = match level {
+        "high" => Priority::High,
+        "medium" => Priority::Medium,
+        "low" => Priority::Low,
+        "up" => Priority::from_u8(old as u8 + 1).unwrap_or(old),
+        "down" => (old as u8)
+            .checked_sub(1)
+            .and_then(Priority::from_u8)
+            .unwrap_or(old),
+        _ => return Err(ShellErr::from(format!("bad priority: {}", level))),
+    };
+    set_priority(stats.id, new).map_err(KernelError::from)?;
+    writeln!(
+        out,
+        "task {}: {} -> {}",
+        stats.id,
+        priority_name(Some(old)),
+        priority_name(Some(new))
+    )?;
+    Ok(())
+}
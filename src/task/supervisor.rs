@@ -0,0 +1,219 @@
+//! Liveness checks for the tasks the kernel cannot do without.
+//!
+//! A critical task is registered with `watch`, saying how often it will
+//! call `check_in` and, optionally, giving a factory that builds a fresh
+//! copy of it. `supervisor_task` looks every `POLL_MS`: a task that has
+//! missed `MISSES` check-ins in a row is reported with its scheduler
+//! statistics and, given a factory, killed and started again.
+//!
+//! Only tasks stuck waiting are caught this way. One spinning inside
+//! `poll` keeps the supervisor from running at all; that is what the NMI
+//! watchdog is for.
+//!
+//! `spawn_supervised` covers tasks that end instead: it runs a task built
+//! by a factory and starts it again as its `Restart` policy says, so a
+//! supervised task can supervise its own children in turn. A task fails by
+//! returning an error or, with `panic=recover`, by panicking, which
+//! `task::recover` turns into an error here.
+
+use super::{
+    recover,
+    scheduler::{self, spawner},
+    Priority, PriorityTask, TaskId,
+};
+use crate::{sync::Mutex, time};
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_util::future::LocalBoxFuture;
+
+/// How often the kernel's own critical tasks check in.
+pub const CHECK_IN_MS: u64 = 1000;
+/// Check-ins a task may miss before it counts as hung.
+pub const MISSES: u64 = 3;
+const POLL_MS: u64 = 250;
+
+struct Watched {
+    name: &'static str,
+    interval_ms: u64,
+    factory: Option<fn() -> PriorityTask>,
+    /// The task that last checked in under this name.
+    task: Option<TaskId>,
+    last_check_in: u64,
+    restarts: u32,
+}
+
+static WATCHED: Mutex<Vec<Watched>> = Mutex::new(Vec::new());
+
+/// Starts watching the task that checks in as `name` at least every
+/// `interval_ms`. With a `factory`, a hung task is replaced by a new one.
+pub fn watch(name: &'static str, interval_ms: u64, factory: Option<fn() -> PriorityTask>) {
+    let mut watched = WATCHED.lock();
+    assert!(
+        watched.iter().all(|w| w.name != name),
+        "task {} watched twice",
+        name
+    );
+    watched.push(Watched {
+        name,
+        interval_ms,
+        factory,
+        task: None,
+        last_check_in: time::uptime_ms(),
+        restarts: 0,
+    });
+}
+
+/// Tells the supervisor the calling task is alive. Unwatched names are
+/// ignored, so a task may check in whether or not anyone watches it.
+pub fn check_in(name: &'static str) {
+    let now = time::uptime_ms();
+    if let Some(watched) = WATCHED.lock().iter_mut().find(|w| w.name == name) {
+        watched.last_check_in = now;
+        watched.task = TaskId::current();
+    }
+}
+
+/// Checks on the watched tasks for as long as the kernel runs.
+pub async fn supervisor_task() {
+    loop {
+        time::sleep(POLL_MS).await;
+        check_all();
+    }
+}
+
+fn check_all() {
+    let now = time::uptime_ms();
+    let mut watched = WATCHED.lock();
+    for w in watched.iter_mut() {
+        let silent_ms = now.saturating_sub(w.last_check_in);
+        if silent_ms <= w.interval_ms * MISSES {
+            continue;
+        }
+        report(w, silent_ms);
+        let factory = match w.factory {
+            Some(factory) => factory,
+            None => {
+                // report once per missed window, not on every look
+                w.last_check_in = now;
+                continue;
+            }
+        };
+        if let Some(task) = w.task.take() {
+            spawner::kill(task);
+        }
+        let task = spawner::spawn(factory());
+        w.restarts += 1;
+        w.last_check_in = now;
+        warn!(
+            "supervisor: restarted {} as task {} (restart {})",
+            w.name, task, w.restarts
+        );
+    }
+}
+
+fn report(w: &Watched, silent_ms: u64) {
+    error!(
+        "supervisor: {} has not checked in for {} ms (every {} ms expected)",
+        w.name, silent_ms, w.interval_ms
+    );
+    let task = match w.task {
+        Some(task) => task,
+        None => {
+            error!("supervisor: {} never checked in", w.name);
+            return;
+        }
+    };
+    match scheduler::tasks().into_iter().find(|info| info.id == task) {
+        Some(info) => error!(
+            "supervisor: {} is task {}, {}, {} polls, {} us run",
+            w.name,
+            task,
+            info.state.name(),
+            info.polls,
+            info.runtime_us
+        ),
+        None => error!("supervisor: {} was task {}, which has exited", w.name, task),
+    }
+}
+
+/// What to do when a supervised task returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    /// Let it end for good.
+    Never,
+    /// Start it again if it returned an error or panicked.
+    OnFailure,
+    /// Start it again however it returned.
+    Always,
+    /// Like `Always`, but wait `initial_ms` before the first restart and
+    /// twice as long before each one after, up to `max_ms`. A run that
+    /// lasted longer than `max_ms` resets the wait.
+    Backoff { initial_ms: u64, max_ms: u64 },
+}
+
+/// Builds a fresh copy of a supervised task.
+pub type Factory = fn() -> LocalBoxFuture<'static, Result<(), &'static str>>;
+
+/// Spawns the task `factory` builds, restarting it under `restart`.
+pub fn spawn_supervised(
+    name: &'static str,
+    priority: Priority,
+    restart: Restart,
+    factory: Factory,
+) -> TaskId {
+    spawner::spawn(PriorityTask::new(
+        priority,
+        supervised(name, restart, factory),
+    ))
+}
+
+async fn supervised(name: &'static str, restart: Restart, factory: Factory) {
+    let mut backoff_ms = match restart {
+        Restart::Backoff { initial_ms, .. } => initial_ms,
+        _ => 0,
+    };
+    for runs in 1u64.. {
+        let started = time::uptime_ms();
+        let result = Caught(Some(factory())).await;
+        if let Err(err) = result {
+            warn!("supervisor: {} failed: {}", name, err);
+        }
+        match restart {
+            Restart::Never => return,
+            Restart::OnFailure if result.is_ok() => return,
+            Restart::OnFailure | Restart::Always => {}
+            Restart::Backoff { initial_ms, max_ms } => {
+                if time::uptime_ms() - started > max_ms {
+                    backoff_ms = initial_ms;
+                }
+                time::sleep(backoff_ms).await;
+                backoff_ms = backoff_ms.saturating_mul(2).min(max_ms);
+            }
+        }
+        info!("supervisor: restarting {} (run {})", name, runs + 1);
+    }
+}
+
+/// A supervised task's future, with a panic turned into an error. The
+/// future that panicked is leaked, not dropped.
+struct Caught(Option<LocalBoxFuture<'static, Result<(), &'static str>>>);
+
+impl Future for Caught {
+    type Output = Result<(), &'static str>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let future = self.0.as_mut().expect("Caught polled after completion");
+        match recover::catch(|| future.as_mut().poll(cx)) {
+            Some(poll) => poll,
+            None => {
+                mem::forget(self.0.take());
+                Poll::Ready(Err("panicked"))
+            }
+        }
+    }
+}
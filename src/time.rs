@@ -0,0 +1,220 @@
+use crate::sync::{Mutex, SeqLock};
+use alloc::vec::Vec;
+use core::{
+    arch::x86_64::_rdtsc,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::device::{cmos, pit};
+
+pub mod page;
+
+/// Timer interrupts per second.
+pub const TICK_RATE: u32 = crate::config::time::TICK_RATE;
+
+/// The clock as of the last tick.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    pub ticks: u64,
+    /// TSC when `ticks` last went up.
+    pub tsc: u64,
+    /// TSC cycles between the last two ticks, 0 until there have been two.
+    pub cycles_per_tick: u64,
+}
+
+static CLOCK: SeqLock<Clock> = SeqLock::new(Clock {
+    ticks: 0,
+    tsc: 0,
+    cycles_per_tick: 0,
+});
+
+/// Wakers to fire once the uptime reaches their deadline, in milliseconds,
+/// one per task.
+///
+/// The interrupt handler only wakes them by reference and marks them
+/// `FIRED`: dropping the last reference to a waker can free its task, and
+/// the heap may be locked by the code the interrupt stopped. `wake_at`
+/// clears fired entries out from task context.
+static TIMERS: Mutex<Vec<(u64, Waker)>> = Mutex::new(Vec::new());
+const FIRED: u64 = u64::MAX;
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Nanoseconds per timer tick.
+const TICK_NS: u64 = 1_000_000_000 / TICK_RATE as u64;
+
+pub fn init() {
+    pit::init(TICK_RATE);
+    RUNNING.store(true, Ordering::Relaxed);
+    let boot_unix_ns = unix_seconds(&cmos::datetime()) * 1_000_000_000;
+    page::update(&clock(), TICK_NS, Some(boot_unix_ns));
+    if let Err(err) = page::map() {
+        error!("time: failed to map the time page: {:?}", err);
+    }
+}
+
+/// Whether the timer has been programmed; it ticks once interrupts are on.
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Called by the timer interrupt handler
+pub(crate) fn tick() {
+    let tsc = unsafe { _rdtsc() };
+    CLOCK.update(|clock| {
+        if clock.ticks > 0 {
+            clock.cycles_per_tick = tsc.wrapping_sub(clock.tsc);
+        }
+        clock.ticks += 1;
+        clock.tsc = tsc;
+    });
+    page::update(&clock(), TICK_NS, None);
+    if let Some(mut timers) = TIMERS.try_lock() {
+        let now = uptime_ms();
+        for (deadline, waker) in timers.iter_mut() {
+            if *deadline <= now {
+                *deadline = FIRED;
+                waker.wake_by_ref();
+            }
+        }
+    }
+}
+
+pub fn clock() -> Clock {
+    CLOCK.read()
+}
+
+pub fn ticks() -> u64 {
+    CLOCK.read().ticks
+}
+
+/// Milliseconds elapsed since the timer was started.
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TICK_RATE as u64
+}
+
+/// Microseconds elapsed since the timer was started, finer than a tick:
+/// the TSC measures how far into the current tick we are.
+pub fn uptime_us() -> u64 {
+    let clock = clock();
+    let base = clock.ticks * 1_000_000 / TICK_RATE as u64;
+    if clock.cycles_per_tick == 0 {
+        return base;
+    }
+    let tick_us = 1_000_000 / TICK_RATE as u64;
+    let into_tick = unsafe { _rdtsc() }.wrapping_sub(clock.tsc);
+    // a late tick leaves the TSC running past one tick's worth; cap it so
+    // time never runs ahead of the next tick
+    base + (into_tick * tick_us / clock.cycles_per_tick).min(tick_us - 1)
+}
+
+/// Nanoseconds since the timer started, read as user code reads the time
+/// page.
+pub fn monotonic_ns() -> u64 {
+    page::read(page::data()).0
+}
+
+/// Nanoseconds since the Unix epoch, by the RTC at boot plus the time
+/// since.
+pub fn realtime_ns() -> u64 {
+    let (monotonic, boot) = page::read(page::data());
+    boot + monotonic
+}
+
+/// Seconds from the Unix epoch to `date`, taken as UTC.
+pub fn unix_seconds(date: &cmos::DateTime) -> u64 {
+    // days from civil: count from a March 1st so leap days end the year
+    let (year, month) = if date.month <= 2 {
+        (date.year as i64 - 1, date.month as i64 + 9)
+    } else {
+        (date.year as i64, date.month as i64 - 3)
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + date.day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let seconds =
+        days * 86_400 + date.hour as i64 * 3600 + date.minute as i64 * 60 + date.second as i64;
+    seconds.max(0) as u64
+}
+
+/// Wakes `waker` from the timer interrupt once `uptime_ms()` reaches `deadline`.
+///
+/// A task registers again every time it polls, so a waker already waiting
+/// keeps a single entry, at the earlier of its deadlines; waking early only
+/// costs a spurious poll.
+pub fn wake_at(deadline: u64, waker: Waker) {
+    let deadline = deadline.min(FIRED - 1);
+    without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        timers.retain(|(deadline, _)| *deadline != FIRED);
+        match timers.iter_mut().find(|(_, w)| w.will_wake(&waker)) {
+            Some((at, _)) => *at = (*at).min(deadline),
+            None => timers.push((deadline, waker)),
+        }
+    })
+}
+
+/// Completes after at least `ms` milliseconds.
+pub fn sleep(ms: u64) -> Sleep {
+    Sleep {
+        deadline: uptime_ms() + ms,
+    }
+}
+
+pub struct Sleep {
+    deadline: u64,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if uptime_ms() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            wake_at(self.deadline, cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// A point to spin until, for waits that cannot sleep. Once the timer has
+/// ticked twice it is measured with the TSC, so it still runs out with
+/// interrupts off; before that it counts ticks.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    tsc: u64,
+    ticks: u64,
+    calibrated: bool,
+}
+
+impl Deadline {
+    pub fn after(duration: Duration) -> Self {
+        let clock = clock();
+        let tick_us = 1_000_000 / TICK_RATE as u64;
+        let us = duration.as_micros().min(u64::MAX as u128) as u64;
+        Deadline {
+            tsc: unsafe { _rdtsc() }
+                .saturating_add(us.saturating_mul(clock.cycles_per_tick) / tick_us),
+            ticks: clock
+                .ticks
+                .saturating_add(us.saturating_add(tick_us - 1) / tick_us),
+            calibrated: clock.cycles_per_tick != 0,
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        if self.calibrated {
+            (unsafe { _rdtsc() }) >= self.tsc
+        } else {
+            ticks() >= self.ticks
+        }
+    }
+}
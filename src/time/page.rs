@@ -0,0 +1,140 @@
+//! The time page: one read-only page at `ADDRESS` in user space holding
+//! what a clock read needs, so user code can tell the time without a
+//! syscall, as with Linux's vDSO.
+//!
+//! The kernel rewrites it on every tick, bracketed by a sequence number as
+//! `SeqLock` does. A reader loads the number, then the fields, then the
+//! number again, and starts over if it was odd or has moved. `read` is that
+//! reader; `clock_gettime` uses it too, so the two never disagree.
+//!
+//! There is one address space, so mapping the page once at boot puts it in
+//! every process there will be.
+
+use super::Clock;
+use crate::memory::{protect::PageAligned, user::USER_END, FRAME_ALLOCATOR, MAPPER};
+use core::{
+    arch::x86_64::_rdtsc,
+    sync::atomic::{fence, spin_loop_hint, AtomicU64, Ordering},
+};
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    structures::paging::{
+        mapper::{MapToError, MappedFrame, TranslateResult},
+        Mapper, Page, PageTableFlags, Size4KiB, Translate,
+    },
+    VirtAddr,
+};
+
+/// Where user code finds the page: the last page of user space.
+pub const ADDRESS: u64 = USER_END - 4096;
+
+/// The page's layout, which user code relies on; fields are only ever
+/// appended.
+#[repr(C)]
+pub struct TimeData {
+    /// Odd while the kernel is updating the page.
+    pub seq: AtomicU64,
+    /// Timer ticks since boot, and the TSC at the last one.
+    pub ticks: AtomicU64,
+    pub tsc: AtomicU64,
+    /// TSC cycles per tick, 0 until there have been two ticks.
+    pub cycles_per_tick: AtomicU64,
+    pub tick_ns: AtomicU64,
+    /// Unix time when the timer started, in nanoseconds; 0 if unknown.
+    pub boot_unix_ns: AtomicU64,
+}
+
+static PAGE: PageAligned<TimeData> = PageAligned(TimeData {
+    seq: AtomicU64::new(0),
+    ticks: AtomicU64::new(0),
+    tsc: AtomicU64::new(0),
+    cycles_per_tick: AtomicU64::new(0),
+    tick_ns: AtomicU64::new(0),
+    boot_unix_ns: AtomicU64::new(0),
+});
+
+#[derive(Debug)]
+pub enum Error {
+    MapperNotInstalled,
+    /// The kernel's copy is not on a 4 KiB page of its own.
+    NotMapped,
+    Map(MapToError<Size4KiB>),
+}
+
+/// The kernel's view of the page.
+pub fn data() -> &'static TimeData {
+    &PAGE
+}
+
+/// Maps the page read-only at `ADDRESS`.
+pub fn map() -> Result<(), Error> {
+    let (mapper, frame_allocator) = match (MAPPER.try_get(), FRAME_ALLOCATOR.try_get()) {
+        (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+        _ => return Err(Error::MapperNotInstalled),
+    };
+    let mut mapper = mapper.lock();
+    let frame = match mapper.translate(PAGE.addr()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            ..
+        } => frame,
+        _ => return Err(Error::NotMapped),
+    };
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(ADDRESS));
+    let flags =
+        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+    unsafe {
+        mapper
+            .map_to(page, frame, flags, &mut *frame_allocator.lock())
+            .map_err(Error::Map)?
+            .flush()
+    };
+    Ok(())
+}
+
+/// Rewrites the page with `clock` and, if given, the Unix time at boot.
+pub(super) fn update(clock: &Clock, tick_ns: u64, boot_unix_ns: Option<u64>) {
+    without_interrupts(|| {
+        let seq = PAGE.seq.load(Ordering::Relaxed);
+        PAGE.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        PAGE.ticks.store(clock.ticks, Ordering::Relaxed);
+        PAGE.tsc.store(clock.tsc, Ordering::Relaxed);
+        PAGE.cycles_per_tick
+            .store(clock.cycles_per_tick, Ordering::Relaxed);
+        PAGE.tick_ns.store(tick_ns, Ordering::Relaxed);
+        if let Some(ns) = boot_unix_ns {
+            PAGE.boot_unix_ns.store(ns, Ordering::Relaxed);
+        }
+        PAGE.seq.store(seq.wrapping_add(2), Ordering::Release);
+    });
+}
+
+/// Nanoseconds since the timer started, finer than a tick, and the Unix
+/// time in nanoseconds at that start; what user code computes from its
+/// mapping of `data`.
+pub fn read(data: &TimeData) -> (u64, u64) {
+    loop {
+        let seq = data.seq.load(Ordering::Acquire);
+        if seq & 1 == 0 {
+            let ticks = data.ticks.load(Ordering::Relaxed);
+            let tsc = data.tsc.load(Ordering::Relaxed);
+            let cycles_per_tick = data.cycles_per_tick.load(Ordering::Relaxed);
+            let tick_ns = data.tick_ns.load(Ordering::Relaxed);
+            let boot_unix_ns = data.boot_unix_ns.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+            if data.seq.load(Ordering::Relaxed) == seq {
+                let mut ns = ticks * tick_ns;
+                if cycles_per_tick != 0 {
+                    let into_tick = unsafe { _rdtsc() }.wrapping_sub(tsc) as u128;
+                    // capped as in `uptime_us`, so a late tick cannot run
+                    // the clock ahead
+                    ns += (into_tick * tick_ns as u128 / cycles_per_tick as u128)
+                        .min(tick_ns.saturating_sub(1) as u128) as u64;
+                }
+                return (ns, boot_unix_ns);
+            }
+        }
+        spin_loop_hint();
+    }
+}
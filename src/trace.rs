@@ -0,0 +1,246 @@
+//! Tracepoints: fixed-size binary records with TSC timestamps, cheap
+//! enough to leave in the scheduler and interrupt paths.
+//!
+//! `trace_event!(scheduler_poll, task = id)` records nothing until tracing
+//! is switched on with `enable` (or the `trace on` shell command). `init`
+//! subscribes to the scheduler hooks, so every scheduler's spawns, polls
+//! and exits are recorded.
+
+use crate::{
+    shell::ShellErr,
+    sync::Mutex,
+    task::{
+        scheduler::hooks::{self, Hooks},
+        Priority, TaskId,
+    },
+};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+/// Records kept per CPU before the oldest are overwritten.
+pub const RING_CAPACITY: usize = 1024;
+/// Values a single tracepoint can carry.
+pub const MAX_ARGS: usize = 3;
+const MAX_CPUS: usize = 1;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Records lost because the ring was busy, e.g. an interrupt arriving
+/// while a task was recording.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static RINGS: [Mutex<TraceRing>; MAX_CPUS] = [Mutex::new(TraceRing::new())];
+
+/// A tracepoint, created once per `trace_event!` call site.
+pub struct Event {
+    pub name: &'static str,
+    pub fields: &'static [&'static str],
+}
+
+#[derive(Clone, Copy)]
+pub struct Record {
+    pub tsc: u64,
+    pub event: &'static Event,
+    pub args: [u64; MAX_ARGS],
+}
+
+struct TraceRing {
+    records: [Option<Record>; RING_CAPACITY],
+    next: usize,
+    len: usize,
+}
+
+impl TraceRing {
+    const fn new() -> Self {
+        TraceRing {
+            records: [None; RING_CAPACITY],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        self.records[self.next] = Some(record);
+        self.next = (self.next + 1) % RING_CAPACITY;
+        self.len = (self.len + 1).min(RING_CAPACITY);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Record> {
+        let start = (self.next + RING_CAPACITY - self.len) % RING_CAPACITY;
+        (0..self.len).filter_map(move |i| self.records[(start + i) % RING_CAPACITY].as_ref())
+    }
+}
+
+/// Converts tracepoint arguments to the raw `u64` stored in a record.
+pub trait TraceValue {
+    fn to_u64(self) -> u64;
+}
+
+macro_rules! impl_trace_value {
+    ($($ty:ty),*) => {
+        $(impl TraceValue for $ty {
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+        })*
+    };
+}
+
+impl_trace_value!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, bool);
+
+impl TraceValue for TaskId {
+    fn to_u64(self) -> u64 {
+        self.as_u64()
+    }
+}
+
+/// Records `$name` with up to `MAX_ARGS` named values.
+#[macro_export]
+macro_rules! trace_event {
+    ($name:ident $(, $key:ident = $value:expr)* $(,)?) => {{
+        static EVENT: $crate::trace::Event = $crate::trace::Event {
+            name: stringify!($name),
+            fields: &[$(stringify!($key)),*],
+        };
+        if $crate::trace::is_enabled() {
+            $crate::trace::record(
+                &EVENT,
+                &[$($crate::trace::TraceValue::to_u64($value)),*],
+            );
+        }
+    }};
+}
+
+/// Records the scheduler's events; priorities are 255 under a scheduler
+/// without them.
+struct SchedulerTrace;
+
+static SCHEDULER_TRACE: SchedulerTrace = SchedulerTrace;
+
+fn priority_value(priority: Option<Priority>) -> u8 {
+    priority.map_or(u8::MAX, |priority| priority as u8)
+}
+
+impl Hooks for SchedulerTrace {
+    fn on_spawn(&self, task_id: TaskId, priority: Option<Priority>) {
+        trace_event!(
+            scheduler_spawn,
+            task = task_id,
+            priority = priority_value(priority)
+        );
+    }
+
+    fn before_poll(&self, task_id: TaskId, priority: Option<Priority>) {
+        trace_event!(
+            scheduler_poll,
+            task = task_id,
+            priority = priority_value(priority)
+        );
+    }
+
+    fn after_poll(&self, task_id: TaskId, ready: bool) {
+        trace_event!(scheduler_return, task = task_id, ready = ready);
+    }
+
+    fn on_complete(&self, task_id: TaskId) {
+        trace_event!(scheduler_exit, task = task_id);
+    }
+}
+
+/// Subscribes to the scheduler hooks.
+pub fn init() {
+    if let Err(err) = hooks::register(&SCHEDULER_TRACE) {
+        warn!("trace: no scheduler events: {:?}", err);
+    }
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Called by `trace_event!`; extra arguments are cut off at `MAX_ARGS`.
+pub fn record(event: &'static Event, values: &[u64]) {
+    let mut args = [0; MAX_ARGS];
+    for (arg, value) in args.iter_mut().zip(values) {
+        *arg = *value;
+    }
+    let record = Record {
+        tsc: unsafe { core::arch::x86_64::_rdtsc() },
+        event,
+        args,
+    };
+    // never spin: the holder may be the code this interrupted
+    match RINGS[cpu()].try_lock() {
+        Some(mut ring) => ring.push(record),
+        None => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn cpu() -> usize {
+    0
+}
+
+/// Calls `f` on every retained record of every CPU, oldest first per CPU.
+pub fn for_each<F: FnMut(usize, &Record)>(mut f: F) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        for (cpu, ring) in RINGS.iter().enumerate() {
+            ring.lock().iter().for_each(|record| f(cpu, record));
+        }
+    })
+}
+
+pub fn clear() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        for ring in RINGS.iter() {
+            let mut ring = ring.lock();
+            ring.next = 0;
+            ring.len = 0;
+        }
+    });
+    DROPPED.store(0, Ordering::Relaxed);
+}
+
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// One line per record: `tsc cpu event key=value...`, for offline analysis.
+pub fn dump(out: &mut dyn Write) -> core::fmt::Result {
+    let mut result = Ok(());
+    for_each(|cpu, record| {
+        if result.is_err() {
+            return;
+        }
+        result = (|| {
+            write!(out, "{} {} {}", record.tsc, cpu, record.event.name)?;
+            for (key, value) in record.event.fields.iter().zip(record.args.iter()) {
+                write!(out, " {}={}", key, value)?;
+            }
+            writeln!(out)
+        })();
+    });
+    result?;
+    writeln!(out, "# dropped {}", dropped())
+}
+
+/// `trace on|off|clear|dump`
+pub fn command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    match args {
+        ["on"] => enable(),
+        ["off"] => disable(),
+        ["clear"] => clear(),
+        ["dump"] | [] => dump(out)?,
+        _ => return Err(ShellErr::new("unknown subcommand")),
+    }
+    Ok(())
+}
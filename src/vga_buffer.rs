@@ -1,17 +1,20 @@
-use core::fmt::{self, Write};
-use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::{
+    kernel::Console,
+    sync::{Lazy, TrackedMutex},
+};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use volatile::Volatile;
 use x86_64::instructions::port::Port;
 
-lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        row_position: 0,
-        column_position: 0,
-        color_code: ColorCode::new(Color::White, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
-}
+pub mod mode;
+pub mod palette;
+pub mod window;
+
+pub static WRITER: Lazy<TrackedMutex<Writer>> =
+    Lazy::new(|| TrackedMutex::new("WRITER", Writer::new()));
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -43,8 +46,40 @@ impl ColorCode {
     pub const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    /// Sets bit 7, which blinks the character while `palette::blink` is on
+    /// and otherwise gives the background its bright variant; a bright
+    /// background passed to `new` sets the same bit.
+    pub const fn blinking(self) -> ColorCode {
+        ColorCode(self.0 | 0x80)
+    }
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Underline,
+    Block,
+}
+
+impl CursorShape {
+    /// Returns the (start, end) scanlines programmed into the CRTC, for
+    /// the font of the current mode.
+    fn scanlines(self) -> (u8, u8) {
+        let last = (SCANLINES / height() - 1) as u8;
+        match self {
+            CursorShape::Underline => (last - 1, last),
+            CursorShape::Block => (0, last),
+        }
+    }
+}
+
+const CRTC_ADDR: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_END: u8 = 0x0b;
+const CURSOR_DISABLE: u8 = 1 << 5;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 struct ScreenChar {
@@ -52,25 +87,110 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+/// Rows in the tallest text mode; `height` is how many are on screen.
+pub const MAX_HEIGHT: usize = 50;
+const DEFAULT_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
+/// Scanlines the text modes fill, whatever the font height.
+const SCANLINES: usize = 400;
+
+static HEIGHT: AtomicUsize = AtomicUsize::new(DEFAULT_HEIGHT);
+
+/// Rows on the screen in the current mode.
+pub fn height() -> usize {
+    HEIGHT.load(Ordering::Relaxed)
+}
 
 #[repr(transparent)]
 struct Buffer {
-    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; MAX_HEIGHT],
+}
+
+pub struct ScreenState {
+    chars: [[ScreenChar; BUFFER_WIDTH]; MAX_HEIGHT],
+    row_position: usize,
+    column_position: usize,
+    color_code: ColorCode,
+    cursor_shape: CursorShape,
+    cursor_visible: bool,
 }
+
+/// Draws into a copy of the screen, which `flush` copies out cell by cell
+/// where it changed. Writing a string flushes once at the end; anything
+/// drawn with `write_byte_at` or cleared stays off screen until the next
+/// flush, so a redraw never shows half done.
 pub struct Writer {
     row_position: usize,
     column_position: usize,
     color_code: ColorCode,
+    cursor_shape: CursorShape,
+    cursor_visible: bool,
+    /// Rows on screen; the shadow always has room for `MAX_HEIGHT`.
+    height: usize,
+    /// What the screen shows after the next `flush`.
+    shadow: [[ScreenChar; BUFFER_WIDTH]; MAX_HEIGHT],
+    /// Cells of `shadow` the screen does not have yet, a bit per column.
+    dirty: [u128; MAX_HEIGHT],
     buffer: &'static mut Buffer,
 }
 
 #[allow(dead_code)]
 impl Writer {
+    /// Takes over the screen with what the bootloader left on it.
+    fn new() -> Self {
+        let buffer = unsafe { &mut *(0xb8000 as *mut Buffer) };
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: ColorCode::new(Color::White, Color::Black),
+        };
+        let mut shadow = [[blank; BUFFER_WIDTH]; MAX_HEIGHT];
+        for (row, cells) in shadow.iter_mut().enumerate() {
+            for (col, cell) in cells.iter_mut().enumerate() {
+                *cell = buffer.chars[row][col].read();
+            }
+        }
+        Writer {
+            row_position: 0,
+            column_position: 0,
+            color_code: ColorCode::new(Color::White, Color::Black),
+            cursor_shape: CursorShape::Underline,
+            cursor_visible: true,
+            height: DEFAULT_HEIGHT,
+            shadow,
+            dirty: [0; MAX_HEIGHT],
+            buffer,
+        }
+    }
+
+    fn cell(&self, row: usize, col: usize) -> ScreenChar {
+        self.shadow[row][col]
+    }
+
+    fn set_cell(&mut self, row: usize, col: usize, c: ScreenChar) {
+        if self.shadow[row][col] != c {
+            self.shadow[row][col] = c;
+            self.dirty[row] |= 1 << col;
+        }
+    }
+
+    /// Copies the cells changed since the last flush to the screen.
+    pub fn flush(&mut self) {
+        for row in 0..MAX_HEIGHT {
+            let mut dirty = self.dirty[row];
+            while dirty != 0 {
+                let col = dirty.trailing_zeros() as usize;
+                dirty &= dirty - 1;
+                self.buffer.chars[row][col].write(self.shadow[row][col]);
+            }
+            self.dirty[row] = 0;
+        }
+    }
+
     pub fn write_byte(&mut self, byte: u8, style: ColorCode) {
         match byte {
             b'\n' => self.new_line(),
+            // backspace moves back so the next character overwrites
+            0x08 => self.column_position = self.column_position.saturating_sub(1),
             byte => {
                 let row = self.row_position;
                 let col = self.column_position;
@@ -86,32 +206,42 @@ impl Writer {
     }
 
     pub fn write_byte_at(&mut self, byte: u8, row: usize, col: usize, style: ColorCode) {
-        self.buffer.chars[row][col].write(ScreenChar {
-            ascii_character: byte,
-            color_code: style,
-        });
+        self.set_cell(
+            row,
+            col,
+            ScreenChar {
+                ascii_character: byte,
+                color_code: style,
+            },
+        );
     }
 
     fn new_line(&mut self) {
         self.column_position = 0;
         self.row_position += 1;
 
-        if self.row_position >= BUFFER_HEIGHT {
+        if self.row_position >= self.height {
             self.scroll();
         }
     }
 
     fn scroll(&mut self) {
-        for row in 0..(BUFFER_HEIGHT - 1) {
+        self.scroll_by(1);
+        self.column_position = 0;
+        self.row_position = self.height - 1;
+    }
+
+    /// Moves the rows on screen up by `lines`, blanking the bottom ones.
+    fn scroll_by(&mut self, lines: usize) {
+        for row in 0..self.height.saturating_sub(lines) {
             for col in 0..BUFFER_WIDTH {
-                let c = self.buffer.chars[row + 1][col].read();
-                self.buffer.chars[row][col].write(c);
+                let c = self.cell(row + lines, col);
+                self.set_cell(row, col, c);
             }
         }
-
-        self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
-        self.row_position = BUFFER_HEIGHT - 1;
+        for row in self.height.saturating_sub(lines)..self.height {
+            self.clear_row(row);
+        }
     }
 
     fn clear_row(&mut self, row: usize) {
@@ -120,7 +250,7 @@ impl Writer {
             color_code: self.color_code,
         };
         for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(clear_style);
+            self.set_cell(row, col, clear_style);
         }
     }
 
@@ -131,6 +261,7 @@ impl Writer {
 
         let row = self.row_position;
         let col = self.column_position;
+        self.flush();
         self.move_cursor(row, col);
     }
 
@@ -151,7 +282,7 @@ impl Writer {
 
     fn move_cursor(&mut self, row: usize, col: usize) {
         assert!(
-            row < BUFFER_HEIGHT,
+            row < self.height,
             "attempted out-of-bounds (row) cursor move"
         );
         assert!(
@@ -161,24 +292,136 @@ impl Writer {
 
         let pos: u16 = ((row * 80) + col) as u16;
         unsafe {
-            Port::new(0x3d4).write(0x0Fu8);
-            Port::new(0x3d5).write((pos & 0xff) as u8);
+            Port::new(CRTC_ADDR).write(0x0Fu8);
+            Port::new(CRTC_DATA).write((pos & 0xff) as u8);
 
-            Port::new(0x3d4).write(0x0eu8);
-            Port::new(0x3d5).write(((pos >> 8) & 0xff) as u8);
+            Port::new(CRTC_ADDR).write(0x0eu8);
+            Port::new(CRTC_DATA).write(((pos >> 8) & 0xff) as u8);
         }
     }
 
-    pub fn clear_screen(&mut self) {
-        let clear_style = ScreenChar {
-            ascii_character: b' ',
+    pub fn show_cursor(&mut self) {
+        let (start, end) = self.cursor_shape.scanlines();
+        unsafe {
+            let mut addr: Port<u8> = Port::new(CRTC_ADDR);
+            let mut data: Port<u8> = Port::new(CRTC_DATA);
+
+            addr.write(CRTC_CURSOR_START);
+            let prev = data.read();
+            data.write((prev & 0xc0) | start);
+
+            addr.write(CRTC_CURSOR_END);
+            let prev = data.read();
+            data.write((prev & 0xe0) | end);
+        }
+        self.cursor_visible = true;
+    }
+
+    pub fn hide_cursor(&mut self) {
+        unsafe {
+            Port::new(CRTC_ADDR).write(CRTC_CURSOR_START);
+            Port::new(CRTC_DATA).write(CURSOR_DISABLE);
+        }
+        self.cursor_visible = false;
+    }
+
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) {
+        self.cursor_shape = shape;
+        if self.cursor_visible {
+            self.show_cursor();
+        }
+    }
+
+    pub fn cursor_shape(&self) -> CursorShape {
+        self.cursor_shape
+    }
+
+    pub fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    /// The screen as drawn so far, flushed or not.
+    pub fn snapshot(&self) -> ScreenState {
+        ScreenState {
+            chars: self.shadow,
+            row_position: self.row_position,
+            column_position: self.column_position,
             color_code: self.color_code,
-        };
-        for row in 0..BUFFER_HEIGHT {
+            cursor_shape: self.cursor_shape,
+            cursor_visible: self.cursor_visible,
+        }
+    }
+
+    /// Puts `state` back on screen, rewriting only the cells that differ.
+    pub fn restore(&mut self, state: ScreenState) {
+        for row in 0..self.height {
             for col in 0..BUFFER_WIDTH {
-                self.buffer.chars[row][col].write(clear_style);
+                self.set_cell(row, col, state.chars[row][col]);
             }
         }
+        self.flush();
+        self.row_position = state.row_position.min(self.height - 1);
+        self.column_position = state.column_position;
+        self.color_code = state.color_code;
+        self.cursor_shape = state.cursor_shape;
+        if state.cursor_visible {
+            self.show_cursor();
+        } else {
+            self.hide_cursor();
+        }
+        self.move_cursor(self.row_position, self.column_position);
+    }
+
+    /// Moves where the next character is written.
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.row_position = row.min(self.height - 1);
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+    }
+
+    /// Blanks the row being written and goes back to its start.
+    pub fn clear_line(&mut self) {
+        let row = self.row_position;
+        self.clear_row(row);
+        self.column_position = 0;
+        self.flush();
+        self.move_cursor(row, 0);
+    }
+
+    /// Blanks the screen; the blank only shows if nothing is drawn over it
+    /// before the next flush.
+    pub fn clear_screen(&mut self) {
+        for row in 0..self.height {
+            self.clear_row(row);
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Follows a switch to a mode with `height` rows. Shrinking scrolls the
+    /// cursor's row onto the screen; the rows gained or lost are blanked.
+    pub(crate) fn set_height(&mut self, height: usize) {
+        assert!(
+            height > 0 && height <= MAX_HEIGHT,
+            "unsupported text mode height"
+        );
+        if self.row_position >= height {
+            let excess = self.row_position + 1 - height;
+            self.scroll_by(excess);
+            self.row_position -= excess;
+        }
+        for row in height.min(self.height)..height.max(self.height) {
+            self.clear_row(row);
+        }
+        self.height = height;
+        HEIGHT.store(height, Ordering::Relaxed);
+        self.flush();
+        if self.cursor_visible {
+            self.show_cursor();
+        }
+        let (row, col) = (self.row_position, self.column_position);
+        self.move_cursor(row, col);
     }
 }
 
@@ -221,10 +464,31 @@ macro_rules! println {
     ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
 }
 
+/// Prints through the services' console, or to the screen until they are
+/// assembled.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    use x86_64::instructions::interrupts;
-    interrupts::without_interrupts(|| {
-        let _ = WRITER.lock().write_fmt(args).unwrap();
-    })
+    if crate::kernel::is_ready() {
+        crate::kernel::services().console().print(args);
+    } else {
+        VGA_CONSOLE.print(args);
+    }
+}
+
+/// The screen, as the console.
+pub struct VgaConsole;
+
+pub static VGA_CONSOLE: VgaConsole = VgaConsole;
+
+impl Console for VgaConsole {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn print(&self, args: fmt::Arguments) {
+        use x86_64::instructions::interrupts;
+        interrupts::without_interrupts(|| {
+            let _ = WRITER.lock().write_fmt(args).unwrap();
+        })
+    }
 }
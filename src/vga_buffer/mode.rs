@@ -0,0 +1,189 @@
+//! Switching between 80x25 and 80x50 text.
+//!
+//! Both modes draw the same 400 scanlines, so only the font height changes:
+//! the CRTC's maximum scanline register says how tall a row is. The BIOS
+//! loaded an 8x16 font into plane 2 and there is no BIOS to ask for its
+//! 8x8 one, so the 8-line font is made from it, each pair of rows ORed
+//! into one, and kept in the second font slot. Going back to 25 rows only
+//! selects the first slot again.
+
+use super::{height, CRTC_ADDR, SCANLINES, WRITER};
+use crate::{error::KernelError, memory, shell::ShellErr};
+use core::{
+    fmt::Write,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::{
+    instructions::{interrupts::without_interrupts, port::Port},
+    PhysAddr,
+};
+
+/// Index ports, each with its data port right after it.
+const SEQ_ADDR: u16 = 0x3c4;
+const GC_ADDR: u16 = 0x3ce;
+
+const SEQ_MAP_MASK: u8 = 0x02;
+const SEQ_CHAR_MAP: u8 = 0x03;
+const SEQ_MEMORY_MODE: u8 = 0x04;
+const GC_READ_MAP: u8 = 0x04;
+const GC_MODE: u8 = 0x05;
+const GC_MISC: u8 = 0x06;
+const CRTC_MAX_SCANLINE: u8 = 0x09;
+const MAX_SCANLINE_MASK: u8 = 0x1f;
+
+/// Where plane 2 shows up while it is mapped for font access.
+const FONT_WINDOW: u64 = 0xa0000;
+/// Bytes per glyph in a font slot, whatever the font height.
+const GLYPH_STRIDE: usize = 32;
+const GLYPHS: usize = 256;
+/// Offset of font slot 1 in plane 2.
+const SLOT_1: usize = 0x4000;
+
+static SMALL_FONT_LOADED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextMode {
+    /// 8x16 characters, as the BIOS leaves it.
+    Rows25,
+    /// 8x8 characters.
+    Rows50,
+}
+
+impl TextMode {
+    pub fn rows(self) -> usize {
+        match self {
+            TextMode::Rows25 => 25,
+            TextMode::Rows50 => 50,
+        }
+    }
+
+    fn font_height(self) -> u8 {
+        (SCANLINES / self.rows()) as u8
+    }
+
+    /// Character map select value using the same slot for both halves of
+    /// the character set.
+    fn char_map(self) -> u8 {
+        match self {
+            TextMode::Rows25 => 0x00,
+            TextMode::Rows50 => 0x05,
+        }
+    }
+}
+
+pub fn current() -> TextMode {
+    if height() == TextMode::Rows50.rows() {
+        TextMode::Rows50
+    } else {
+        TextMode::Rows25
+    }
+}
+
+unsafe fn read_reg(addr: u16, index: u8) -> u8 {
+    Port::new(addr).write(index);
+    Port::new(addr + 1).read()
+}
+
+unsafe fn write_reg(addr: u16, index: u8, value: u8) {
+    Port::new(addr).write(index);
+    Port::new(addr + 1).write(value);
+}
+
+/// Maps plane 2 at `FONT_WINDOW` for `f`, then puts text mode's memory
+/// setup back.
+unsafe fn with_font_plane(f: impl FnOnce(*mut u8)) -> Result<(), KernelError> {
+    let window = memory::phys_to_virt(PhysAddr::new(FONT_WINDOW))?;
+    let saved = [
+        (SEQ_ADDR, SEQ_MAP_MASK, read_reg(SEQ_ADDR, SEQ_MAP_MASK)),
+        (
+            SEQ_ADDR,
+            SEQ_MEMORY_MODE,
+            read_reg(SEQ_ADDR, SEQ_MEMORY_MODE),
+        ),
+        (GC_ADDR, GC_READ_MAP, read_reg(GC_ADDR, GC_READ_MAP)),
+        (GC_ADDR, GC_MODE, read_reg(GC_ADDR, GC_MODE)),
+        (GC_ADDR, GC_MISC, read_reg(GC_ADDR, GC_MISC)),
+    ];
+    write_reg(SEQ_ADDR, SEQ_MAP_MASK, 1 << 2);
+    // sequential addressing, no odd/even
+    write_reg(SEQ_ADDR, SEQ_MEMORY_MODE, 0x06);
+    write_reg(GC_ADDR, GC_READ_MAP, 2);
+    write_reg(GC_ADDR, GC_MODE, 0x00);
+    // 64K at 0xa0000, no odd/even
+    write_reg(GC_ADDR, GC_MISC, 0x04);
+    f(window.as_mut_ptr());
+    for &(addr, index, value) in saved.iter() {
+        write_reg(addr, index, value);
+    }
+    Ok(())
+}
+
+/// Builds the 8-line font in slot 1 from the 16-line one in slot 0.
+fn load_small_font() -> Result<(), KernelError> {
+    if SMALL_FONT_LOADED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    unsafe {
+        with_font_plane(|plane| {
+            for glyph in 0..GLYPHS {
+                let src = plane.add(glyph * GLYPH_STRIDE);
+                let dst = plane.add(SLOT_1 + glyph * GLYPH_STRIDE);
+                for row in 0..GLYPH_STRIDE {
+                    let line = if row < 8 {
+                        ptr::read_volatile(src.add(2 * row))
+                            | ptr::read_volatile(src.add(2 * row + 1))
+                    } else {
+                        0
+                    };
+                    ptr::write_volatile(dst.add(row), line);
+                }
+            }
+        })?;
+    }
+    SMALL_FONT_LOADED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Switches the screen to `mode`, keeping what is on it.
+pub fn set(mode: TextMode) -> Result<(), KernelError> {
+    without_interrupts(|| {
+        // held while plane 2 is mapped, when the text is not
+        let mut writer = WRITER.lock();
+        if mode == TextMode::Rows50 {
+            load_small_font()?;
+        }
+        unsafe {
+            write_reg(SEQ_ADDR, SEQ_CHAR_MAP, mode.char_map());
+            let scanline = read_reg(CRTC_ADDR, CRTC_MAX_SCANLINE) & !MAX_SCANLINE_MASK;
+            write_reg(
+                CRTC_ADDR,
+                CRTC_MAX_SCANLINE,
+                scanline | (mode.font_height() - 1),
+            );
+        }
+        writer.set_height(mode.rows());
+        Ok(())
+    })
+}
+
+/// Applies `vga=` from the command line. Needs physical memory mapped.
+pub fn init() {
+    let mode = crate::boot::cmdline::options().text_mode;
+    if mode != current() {
+        if let Err(err) = set(mode) {
+            error!("vga: cannot switch to {} rows: {}", mode.rows(), err);
+        }
+    }
+}
+
+/// `vgamode [25|50]`
+pub fn command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    match args {
+        [] => writeln!(out, "80x{}", current().rows())?,
+        ["25"] => set(TextMode::Rows25)?,
+        ["50"] => set(TextMode::Rows50)?,
+        _ => return Err(ShellErr::new("usage: vgamode [25|50]")),
+    }
+    Ok(())
+}
@@ -0,0 +1,252 @@
+//! The colors behind the sixteen text attributes, and what bit 7 of an
+//! attribute means.
+//!
+//! An attribute's color index goes through the attribute controller's
+//! palette registers to one of the DAC's 256 entries, which holds the
+//! actual color. The BIOS leaves the palette registers pointing at the
+//! EGA-compatible entries (brown is entry 0x14, the bright colors 0x38 and
+//! up), so colors are changed at the DAC entry they map to and the
+//! mapping itself is left alone.
+//!
+//! With blinking on, bit 7 of an attribute blinks the character and only
+//! eight backgrounds exist; with it off, bit 7 makes the background bright.
+
+use super::Color;
+use crate::{
+    shell::{parse_number, ShellErr},
+    sync::Mutex,
+};
+use core::fmt::Write;
+use x86_64::instructions::{
+    interrupts::without_interrupts,
+    port::{Port, PortReadOnly},
+};
+
+/// Index and data writes alternate on the one port.
+const ATTR_WRITE: u16 = 0x3c0;
+const ATTR_READ: u16 = 0x3c1;
+/// Reading input status 1 sends `ATTR_WRITE` back to expecting an index.
+const INPUT_STATUS_1: u16 = 0x3da;
+const DAC_READ_INDEX: u16 = 0x3c7;
+const DAC_WRITE_INDEX: u16 = 0x3c8;
+const DAC_DATA: u16 = 0x3c9;
+
+/// Keeps the display on while a register is addressed; writes to the
+/// palette registers are ignored with it set, reads are not.
+const PALETTE_ADDRESS_SOURCE: u8 = 1 << 5;
+const ATTR_MODE_CONTROL: u8 = 0x10;
+const MODE_BLINK: u8 = 1 << 3;
+
+/// A color, eight bits per channel; the DAC keeps the top six.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    /// Parses `rrggbb`, as colors are usually written.
+    pub fn parse(text: &str) -> Option<Rgb> {
+        let text = text.strip_prefix('#').unwrap_or(text);
+        if text.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(text, 16).ok()?;
+        Some(Rgb::new(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ))
+    }
+}
+
+/// The CGA colors the BIOS programs.
+pub const DEFAULT: [Rgb; 16] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0x00, 0x00, 0xaa),
+    Rgb::new(0x00, 0xaa, 0x00),
+    Rgb::new(0x00, 0xaa, 0xaa),
+    Rgb::new(0xaa, 0x00, 0x00),
+    Rgb::new(0xaa, 0x00, 0xaa),
+    Rgb::new(0xaa, 0x55, 0x00),
+    Rgb::new(0xaa, 0xaa, 0xaa),
+    Rgb::new(0x55, 0x55, 0x55),
+    Rgb::new(0x55, 0x55, 0xff),
+    Rgb::new(0x55, 0xff, 0x55),
+    Rgb::new(0x55, 0xff, 0xff),
+    Rgb::new(0xff, 0x55, 0x55),
+    Rgb::new(0xff, 0x55, 0xff),
+    Rgb::new(0xff, 0xff, 0x55),
+    Rgb::new(0xff, 0xff, 0xff),
+];
+
+/// Solarized dark, laid out as terminals map it onto the sixteen colors:
+/// black is the background and the bright slots hold the base tones.
+pub const SOLARIZED: [Rgb; 16] = [
+    Rgb::new(0x00, 0x2b, 0x36), // base03
+    Rgb::new(0x26, 0x8b, 0xd2), // blue
+    Rgb::new(0x85, 0x99, 0x00), // green
+    Rgb::new(0x2a, 0xa1, 0x98), // cyan
+    Rgb::new(0xdc, 0x32, 0x2f), // red
+    Rgb::new(0xd3, 0x36, 0x82), // magenta
+    Rgb::new(0xb5, 0x89, 0x00), // yellow
+    Rgb::new(0x83, 0x94, 0x96), // base0
+    Rgb::new(0x07, 0x36, 0x42), // base02
+    Rgb::new(0x83, 0x94, 0x96), // base0
+    Rgb::new(0x58, 0x6e, 0x75), // base01
+    Rgb::new(0x93, 0xa1, 0xa1), // base1
+    Rgb::new(0xcb, 0x4b, 0x16), // orange
+    Rgb::new(0x6c, 0x71, 0xc4), // violet
+    Rgb::new(0x65, 0x7b, 0x83), // base00
+    Rgb::new(0xfd, 0xf6, 0xe3), // base3
+];
+
+struct Ports {
+    attr_write: Port<u8>,
+    attr_read: PortReadOnly<u8>,
+    input_status: PortReadOnly<u8>,
+    dac_read_index: Port<u8>,
+    dac_write_index: Port<u8>,
+    dac_data: Port<u8>,
+}
+
+static PORTS: Mutex<Ports> = Mutex::new(Ports {
+    attr_write: Port::new(ATTR_WRITE),
+    attr_read: PortReadOnly::new(ATTR_READ),
+    input_status: PortReadOnly::new(INPUT_STATUS_1),
+    dac_read_index: Port::new(DAC_READ_INDEX),
+    dac_write_index: Port::new(DAC_WRITE_INDEX),
+    dac_data: Port::new(DAC_DATA),
+});
+
+impl Ports {
+    fn attr_read(&mut self, index: u8) -> u8 {
+        unsafe {
+            self.input_status.read();
+            self.attr_write.write(index | PALETTE_ADDRESS_SOURCE);
+            let value = self.attr_read.read();
+            self.input_status.read();
+            value
+        }
+    }
+
+    fn attr_write(&mut self, index: u8, value: u8) {
+        unsafe {
+            self.input_status.read();
+            self.attr_write.write(index | PALETTE_ADDRESS_SOURCE);
+            self.attr_write.write(value);
+        }
+    }
+
+    fn dac_read(&mut self, index: u8) -> Rgb {
+        unsafe {
+            self.dac_read_index.write(index);
+            let r = self.dac_data.read() << 2;
+            let g = self.dac_data.read() << 2;
+            let b = self.dac_data.read() << 2;
+            // repeats the top bits, so 0x3f reads back as 0xff
+            Rgb::new(r | r >> 6, g | g >> 6, b | b >> 6)
+        }
+    }
+
+    fn dac_write(&mut self, index: u8, rgb: Rgb) {
+        unsafe {
+            self.dac_write_index.write(index);
+            self.dac_data.write(rgb.r >> 2);
+            self.dac_data.write(rgb.g >> 2);
+            self.dac_data.write(rgb.b >> 2);
+        }
+    }
+}
+
+fn with_ports<R>(f: impl FnOnce(&mut Ports) -> R) -> R {
+    without_interrupts(|| f(&mut PORTS.lock()))
+}
+
+/// What `color` currently looks like.
+pub fn color(color: Color) -> Rgb {
+    with_ports(|ports| {
+        let index = ports.attr_read(color as u8);
+        ports.dac_read(index)
+    })
+}
+
+/// Changes what `color` looks like, everywhere it is on screen.
+pub fn set_color(color: Color, rgb: Rgb) {
+    set_entry(color as u8, rgb);
+}
+
+/// Reprograms the DAC entry that color `value` is drawn with.
+fn set_entry(value: u8, rgb: Rgb) {
+    with_ports(|ports| {
+        let index = ports.attr_read(value);
+        ports.dac_write(index, rgb);
+    })
+}
+
+/// The sixteen colors, by `Color` value.
+pub fn palette() -> [Rgb; 16] {
+    let mut palette = [Rgb::new(0, 0, 0); 16];
+    with_ports(|ports| {
+        for (value, rgb) in palette.iter_mut().enumerate() {
+            let index = ports.attr_read(value as u8);
+            *rgb = ports.dac_read(index);
+        }
+    });
+    palette
+}
+
+pub fn set_palette(palette: &[Rgb; 16]) {
+    for (value, &rgb) in palette.iter().enumerate() {
+        set_entry(value as u8, rgb);
+    }
+}
+
+/// Whether bit 7 of an attribute blinks rather than brightens the
+/// background.
+pub fn blink() -> bool {
+    with_ports(|ports| ports.attr_read(ATTR_MODE_CONTROL) & MODE_BLINK != 0)
+}
+
+pub fn set_blink(blink: bool) {
+    with_ports(|ports| {
+        let mode = ports.attr_read(ATTR_MODE_CONTROL) & !MODE_BLINK;
+        let mode = if blink { mode | MODE_BLINK } else { mode };
+        ports.attr_write(ATTR_MODE_CONTROL, mode);
+    })
+}
+
+/// `palette [default|solarized|blink on|off|<color> <rrggbb>]`
+pub fn command(args: &[&str], out: &mut dyn Write) -> Result<(), ShellErr> {
+    match args {
+        [] => {
+            for (value, rgb) in palette().iter().enumerate() {
+                writeln!(out, "{:2}  {:02x}{:02x}{:02x}", value, rgb.r, rgb.g, rgb.b)?;
+            }
+            writeln!(out, "blink {}", if blink() { "on" } else { "off" })?;
+        }
+        ["default"] => set_palette(&DEFAULT),
+        ["solarized"] => set_palette(&SOLARIZED),
+        ["blink", "on"] => set_blink(true),
+        ["blink", "off"] => set_blink(false),
+        [value, rgb] => {
+            let value = parse_number(value)?;
+            if value > 15 {
+                return Err(ShellErr::new("colors go from 0 to 15"));
+            }
+            let rgb = Rgb::parse(rgb).ok_or_else(|| ShellErr::new("expected rrggbb"))?;
+            set_entry(value as u8, rgb);
+        }
+        _ => {
+            return Err(ShellErr::new(
+                "usage: palette [default|solarized|blink on|off|<color> <rrggbb>]",
+            ))
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use super::{height, ColorCode, ScreenChar, Writer, BUFFER_WIDTH, WRITER};
+use core::fmt;
+use x86_64::instructions::interrupts;
+
+const BORDER_HORIZONTAL: u8 = 0xcd;
+const BORDER_VERTICAL: u8 = 0xba;
+const BORDER_TOP_LEFT: u8 = 0xc9;
+const BORDER_TOP_RIGHT: u8 = 0xbb;
+const BORDER_BOTTOM_LEFT: u8 = 0xc8;
+const BORDER_BOTTOM_RIGHT: u8 = 0xbc;
+
+/// A rectangular pane of the VGA text buffer with its own cursor and scrolling.
+///
+/// Coordinates are absolute screen cells. When a border is set, the outermost
+/// ring of cells is reserved for it and text is written inside.
+pub struct Window {
+    top: usize,
+    left: usize,
+    width: usize,
+    height: usize,
+    row_position: usize,
+    column_position: usize,
+    color_code: ColorCode,
+    border: Option<ColorCode>,
+}
+
+#[allow(dead_code)]
+impl Window {
+    pub fn new(top: usize, left: usize, width: usize, height: usize, color: ColorCode) -> Self {
+        assert!(
+            top + height <= self::height() && left + width <= BUFFER_WIDTH,
+            "window does not fit on screen"
+        );
+        Window {
+            top,
+            left,
+            width,
+            height,
+            row_position: 0,
+            column_position: 0,
+            color_code: color,
+            border: None,
+        }
+    }
+
+    pub fn with_border(mut self, color: ColorCode) -> Self {
+        assert!(
+            self.width > 2 && self.height > 2,
+            "window too small for a border"
+        );
+        self.border = Some(color);
+        self
+    }
+
+    pub fn set_color(&mut self, color: ColorCode) {
+        self.color_code = color
+    }
+
+    fn inset(&self) -> usize {
+        if self.border.is_some() {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn inner_width(&self) -> usize {
+        self.width - 2 * self.inset()
+    }
+
+    fn inner_height(&self) -> usize {
+        self.height - 2 * self.inset()
+    }
+
+    fn screen_position(&self, row: usize, col: usize) -> (usize, usize) {
+        (
+            self.top + self.inset() + row,
+            self.left + self.inset() + col,
+        )
+    }
+
+    /// Clears the pane, redraws its border and moves the cursor home.
+    pub fn clear(&mut self, writer: &mut Writer) {
+        for row in 0..self.inner_height() {
+            self.clear_row(writer, row);
+        }
+        self.draw_border(writer);
+        self.row_position = 0;
+        self.column_position = 0;
+    }
+
+    pub fn draw_border(&self, writer: &mut Writer) {
+        let style = match self.border {
+            Some(style) => style,
+            None => return,
+        };
+        let bottom = self.top + self.height - 1;
+        let right = self.left + self.width - 1;
+
+        for col in self.left + 1..right {
+            writer.write_byte_at(BORDER_HORIZONTAL, self.top, col, style);
+            writer.write_byte_at(BORDER_HORIZONTAL, bottom, col, style);
+        }
+        for row in self.top + 1..bottom {
+            writer.write_byte_at(BORDER_VERTICAL, row, self.left, style);
+            writer.write_byte_at(BORDER_VERTICAL, row, right, style);
+        }
+        writer.write_byte_at(BORDER_TOP_LEFT, self.top, self.left, style);
+        writer.write_byte_at(BORDER_TOP_RIGHT, self.top, right, style);
+        writer.write_byte_at(BORDER_BOTTOM_LEFT, bottom, self.left, style);
+        writer.write_byte_at(BORDER_BOTTOM_RIGHT, bottom, right, style);
+    }
+
+    pub fn write_byte(&mut self, writer: &mut Writer, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(writer),
+            byte => {
+                let (row, col) = self.screen_position(self.row_position, self.column_position);
+                writer.write_byte_at(byte, row, col, self.color_code);
+
+                self.column_position += 1;
+                if self.column_position >= self.inner_width() {
+                    self.new_line(writer);
+                }
+            }
+        }
+    }
+
+    pub fn write_str(&mut self, writer: &mut Writer, s: &str) {
+        for byte in s.bytes() {
+            self.write_byte(writer, byte);
+        }
+    }
+
+    /// Writes `bytes` on `row` of the pane from its left edge, cut off at
+    /// the pane's width, and blanks the rest of the row. Unlike
+    /// `write_str`, it leaves the pane's cursor alone and never scrolls,
+    /// for panes redrawn whole such as an editor's.
+    pub fn write_row(&self, writer: &mut Writer, row: usize, bytes: &[u8]) {
+        self.clear_row(writer, row);
+        for (col, &byte) in bytes.iter().take(self.inner_width()).enumerate() {
+            let (row, col) = self.screen_position(row, col);
+            writer.write_byte_at(byte, row, col, self.color_code);
+        }
+    }
+
+    /// Puts the hardware cursor on a cell of the pane.
+    pub fn place_cursor(&self, writer: &mut Writer, row: usize, col: usize) {
+        let (row, col) = self.screen_position(row, col);
+        writer.move_cursor(row, col);
+    }
+
+    pub fn rows(&self) -> usize {
+        self.inner_height()
+    }
+
+    pub fn columns(&self) -> usize {
+        self.inner_width()
+    }
+
+    fn new_line(&mut self, writer: &mut Writer) {
+        self.column_position = 0;
+        self.row_position += 1;
+
+        if self.row_position >= self.inner_height() {
+            self.scroll(writer);
+        }
+    }
+
+    fn scroll(&mut self, writer: &mut Writer) {
+        for row in 0..(self.inner_height() - 1) {
+            for col in 0..self.inner_width() {
+                let (src_row, src_col) = self.screen_position(row + 1, col);
+                let (dst_row, dst_col) = self.screen_position(row, col);
+                let c = writer.cell(src_row, src_col);
+                writer.set_cell(dst_row, dst_col, c);
+            }
+        }
+
+        self.clear_row(writer, self.inner_height() - 1);
+        self.column_position = 0;
+        self.row_position = self.inner_height() - 1;
+    }
+
+    fn clear_row(&self, writer: &mut Writer, row: usize) {
+        let clear_style = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        for col in 0..self.inner_width() {
+            let (row, col) = self.screen_position(row, col);
+            writer.set_cell(row, col, clear_style);
+        }
+    }
+}
+
+/// Writes through the global `WRITER`, so a task can simply `write!` into its pane.
+impl fmt::Write for Window {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            Window::write_str(self, &mut writer, s);
+            writer.flush();
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use microkernel::{allocators, debug, serial_print, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    microkernel::init(boot_info);
+    serial_print!("heap_oom::allocation_failure_panics...\t");
+    let huge: Vec<u8> = Vec::with_capacity(allocators::heap_size() * 2);
+    serial_println!("[failed]\n");
+    serial_println!(
+        "Error: allocated {} bytes from a smaller heap",
+        huge.capacity()
+    );
+    debug::exit_qemu(false)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    if debug::panic_message_contains(info, "memory allocation of") {
+        serial_println!("[ok]");
+        debug::exit_qemu(true)
+    }
+    debug::test_panic_handler(info)
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use microkernel::{
+    debug, net, serial_print, serial_println,
+    task::{
+        scheduler::{priority::PriorityScheduler, Scheduler},
+        Priority, PriorityTask,
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    microkernel::init(boot_info);
+    serial_print!("net_loopback::udp_and_tcp_echo...\t");
+    // no NIC is probed here, so loopback is the interface
+    net::loopback::attach().expect("loopback attach failed");
+    let mut executor = PriorityScheduler::new();
+    executor
+        .spawn(PriorityTask::new(Priority::Medium, net::poll_task()))
+        .expect("spawn failed");
+    executor
+        .spawn(PriorityTask::new(Priority::Low, check()))
+        .expect("spawn failed");
+    executor.run()
+}
+
+async fn check() {
+    match net::selftest::run().await {
+        Ok(()) => {
+            serial_println!("[ok]");
+            debug::exit_qemu(true)
+        }
+        Err(failure) => {
+            serial_println!("[failed]\n");
+            serial_println!("Error: {:?}", failure);
+            debug::exit_qemu(false)
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    debug::test_panic_handler(info)
+}
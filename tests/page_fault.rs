@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use microkernel::{debug, serial_print, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    microkernel::init(boot_info);
+    serial_print!("page_fault::unmapped_write_panics...\t");
+    unsafe { core::ptr::write_volatile(0xdead_bee8 as *mut u64, 42) };
+    serial_println!("[failed]\n");
+    serial_println!("Error: write to an unmapped page returned");
+    debug::exit_qemu(false)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    if debug::panic_message_contains(info, "EXCEPTION: PAGE FAULT") {
+        serial_println!("[ok]");
+        debug::exit_qemu(true)
+    }
+    debug::test_panic_handler(info)
+}
@@ -0,0 +1,259 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{collections::BTreeSet, rc::Rc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    cell::{Cell, RefCell},
+    future::Future,
+    panic::PanicInfo,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use microkernel::{
+    config::scheduler::QUEUE_DEPTH,
+    debug, serial_print, serial_println,
+    sync::{Lazy, Mutex},
+    task::{
+        self,
+        scheduler::{
+            budget::BudgetScheduler,
+            hooks::{self, Hooks},
+            priority::PriorityScheduler,
+            round_robin::RoundRobinScheduler,
+            Scheduler,
+        },
+        Priority, PriorityTask, TaskId,
+    },
+    time,
+};
+
+/// Tasks spawned up front; each may spawn more.
+const ROOT_TASKS: usize = 200;
+/// Tasks alive at once, well under a run queue's depth.
+const LIVE_LIMIT: usize = QUEUE_DEPTH / 2;
+/// How deep spawn-from-task chains go.
+const MAX_DEPTH: u32 = 4;
+const MAX_STEPS: u64 = 8;
+/// Self-wakes a task may send in one poll; without coalescing a few of
+/// these would fill the queue.
+const MAX_BURST: u64 = 64;
+/// How long every task may take before the run fails.
+const TIMEOUT_MS: u64 = 10_000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    microkernel::init(boot_info);
+    hooks::register(&CHECKER).expect("no room for the checker's hooks");
+    let seed = unsafe { core::arch::x86_64::_rdtsc() } | 1;
+    serial_println!("scheduler_fuzz: seed {:#x}", seed);
+
+    serial_print!("scheduler_fuzz::priority...\t");
+    fuzz(
+        seed,
+        PriorityScheduler::new(),
+        PriorityScheduler::run_ready_tasks,
+    );
+    serial_println!("[ok]");
+    serial_print!("scheduler_fuzz::round_robin...\t");
+    fuzz(
+        seed,
+        RoundRobinScheduler::new(),
+        RoundRobinScheduler::<PriorityTask>::run_ready_tasks,
+    );
+    serial_println!("[ok]");
+    serial_print!("scheduler_fuzz::budget...\t");
+    fuzz(
+        seed,
+        BudgetScheduler::new(),
+        BudgetScheduler::run_ready_tasks,
+    );
+    serial_println!("[ok]");
+    debug::exit_qemu(true)
+}
+
+fn fail(message: &str) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}", message);
+    debug::exit_qemu(false)
+}
+
+/// xorshift64, so a failing seed can be replayed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// What the hooks saw of the tasks, checked as they are called.
+struct Tracker {
+    live: BTreeSet<TaskId>,
+    finished: BTreeSet<TaskId>,
+}
+
+static TRACKER: Lazy<Mutex<Tracker>> = Lazy::new(|| {
+    Mutex::new(Tracker {
+        live: BTreeSet::new(),
+        finished: BTreeSet::new(),
+    })
+});
+
+struct Checker;
+
+static CHECKER: Checker = Checker;
+
+impl Hooks for Checker {
+    fn on_spawn(&self, task_id: TaskId, _priority: Option<Priority>) {
+        let mut tracker = TRACKER.lock();
+        if !tracker.live.insert(task_id) {
+            fail("task spawned twice");
+        }
+    }
+
+    fn before_poll(&self, task_id: TaskId, _priority: Option<Priority>) {
+        let tracker = TRACKER.lock();
+        if tracker.finished.contains(&task_id) {
+            fail("task polled after it returned Ready");
+        }
+        if !tracker.live.contains(&task_id) {
+            fail("task polled before it was spawned");
+        }
+    }
+
+    fn after_poll(&self, task_id: TaskId, ready: bool) {
+        if ready {
+            TRACKER.lock().finished.insert(task_id);
+        }
+    }
+
+    fn on_complete(&self, task_id: TaskId) {
+        if !TRACKER.lock().live.remove(&task_id) {
+            fail("task completed twice");
+        }
+    }
+}
+
+/// Shared by the driver and every task of one run.
+struct Run {
+    rng: RefCell<Rng>,
+    /// Spawned by tasks, waiting for the driver to hand them over.
+    pending: RefCell<Vec<PriorityTask>>,
+    created: Cell<usize>,
+    done: Cell<usize>,
+}
+
+impl Run {
+    fn new_task(self: &Rc<Self>, depth: u32) -> PriorityTask {
+        let (priority, seed) = {
+            let mut rng = self.rng.borrow_mut();
+            let priority = match rng.below(3) {
+                0 => Priority::Low,
+                1 => Priority::Medium,
+                _ => Priority::High,
+            };
+            (priority, rng.next() | 1)
+        };
+        self.created.set(self.created.get() + 1);
+        PriorityTask::new(priority, body(self.clone(), Rng(seed), depth))
+    }
+}
+
+/// A random life: yields, bursts of self-wakes, short sleeps and children.
+async fn body(run: Rc<Run>, mut rng: Rng, depth: u32) {
+    for _ in 0..rng.below(MAX_STEPS) {
+        match rng.below(10) {
+            0..=4 => task::yield_init().await,
+            5 | 6 => Burst(rng.below(MAX_BURST) + 1).await,
+            7 => time::sleep(rng.below(3)).await,
+            _ if depth < MAX_DEPTH => {
+                let child = run.new_task(depth + 1);
+                run.pending.borrow_mut().push(child);
+            }
+            _ => {}
+        }
+    }
+    run.done.set(run.done.get() + 1);
+}
+
+/// Wakes itself this many times in one poll, then finishes on the next.
+struct Burst(u64);
+
+impl Future for Burst {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0 == 0 {
+            return Poll::Ready(());
+        }
+        for _ in 0..self.0 {
+            cx.waker().wake_by_ref();
+        }
+        self.0 = 0;
+        Poll::Pending
+    }
+}
+
+/// Runs one generation of tasks from `seed` to the end under `scheduler`.
+fn fuzz<S: Scheduler<PriorityTask>>(seed: u64, mut scheduler: S, run_ready_tasks: fn(&mut S)) {
+    let run = Rc::new(Run {
+        rng: RefCell::new(Rng(seed)),
+        pending: RefCell::new(Vec::new()),
+        created: Cell::new(0),
+        done: Cell::new(0),
+    });
+    let roots: Vec<PriorityTask> = (0..ROOT_TASKS).map(|_| run.new_task(0)).collect();
+    run.pending.borrow_mut().extend(roots);
+
+    let deadline = time::uptime_ms() + TIMEOUT_MS;
+    loop {
+        while TRACKER.lock().live.len() < LIVE_LIMIT {
+            let task = match run.pending.borrow_mut().pop() {
+                Some(task) => task,
+                None => break,
+            };
+            if scheduler.spawn(task).is_err() {
+                fail("spawn refused");
+            }
+        }
+        run_ready_tasks(&mut scheduler);
+        if scheduler.lost_wakes() != 0 {
+            fail("run queue overflowed");
+        }
+        if run.pending.borrow().is_empty() && run.done.get() == run.created.get() {
+            break;
+        }
+        if time::uptime_ms() > deadline {
+            serial_println!("[failed]\n");
+            serial_println!(
+                "Error: {} of {} tasks finished, {} waiting to start",
+                run.done.get(),
+                run.created.get(),
+                run.pending.borrow().len()
+            );
+            debug::exit_qemu(false);
+        }
+    }
+    if !TRACKER.lock().live.is_empty() {
+        fail("finished tasks still known to the scheduler");
+    }
+    if !scheduler.tasks().is_empty() {
+        fail("scheduler still lists tasks");
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    debug::test_panic_handler(info)
+}
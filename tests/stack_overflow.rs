@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use microkernel::{debug, serial_print, serial_println};
+use volatile::Volatile;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    microkernel::init(boot_info);
+    serial_print!("stack_overflow::double_fault_on_ist_stack...\t");
+    overflow();
+    serial_println!("[failed]\n");
+    serial_println!("Error: execution continued after a stack overflow");
+    debug::exit_qemu(false)
+}
+
+#[allow(unconditional_recursion)]
+fn overflow() {
+    overflow();
+    // keeps the recursion from being turned into a loop
+    Volatile::new(0).read();
+}
+
+/// Only reachable if the double fault handler got a working stack from the TSS.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    if debug::panic_message_contains(info, "EXCEPTION: DOUBLE FAULT") {
+        serial_println!("[ok]");
+        debug::exit_qemu(true)
+    }
+    debug::test_panic_handler(info)
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use microkernel::{
+    debug, serial_print, serial_println,
+    task::{
+        self,
+        scheduler::{budget::BudgetScheduler, Scheduler},
+        Priority, PriorityTask,
+    },
+    time,
+};
+
+/// How long the low priority task may wait before the test fails.
+const TIMEOUT_MS: u64 = 2000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    microkernel::init(boot_info);
+    serial_print!("starvation::low_priority_task_runs...\t");
+    // the budget scheduler promises every class a share of each round
+    let mut executor = BudgetScheduler::new();
+    for priority in [Priority::High, Priority::High, Priority::Medium].iter() {
+        executor
+            .spawn(PriorityTask::new(*priority, busy()))
+            .expect("spawn failed");
+    }
+    executor
+        .spawn(PriorityTask::new(Priority::Low, finish()))
+        .expect("spawn failed");
+    executor.run()
+}
+
+/// Always ready again: under strict priority nothing below it would run.
+async fn busy() {
+    let deadline = time::uptime_ms() + TIMEOUT_MS;
+    loop {
+        if time::uptime_ms() > deadline {
+            serial_println!("[failed]\n");
+            serial_println!("Error: low priority task starved for {} ms", TIMEOUT_MS);
+            debug::exit_qemu(false);
+        }
+        task::yield_init().await;
+    }
+}
+
+async fn finish() {
+    serial_println!("[ok]");
+    debug::exit_qemu(true);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    debug::test_panic_handler(info)
+}
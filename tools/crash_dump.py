@@ -0,0 +1,86 @@
+#!/usr/bin/env python3
+"""Finds the crash dumps in a serial log, checks them and prints them.
+
+Usage: tools/crash_dump.py serial.log
+
+The format is described in src/debug/crash_dump.rs. Dumps that fail their
+length or checksum are reported and skipped. Exits non-zero if none was good.
+"""
+
+import re
+import sys
+
+BEGIN = re.compile(rb"-----BEGIN CRASH DUMP v(\d+)-----\n")
+END = re.compile(rb"-----END CRASH DUMP (\d+) ([0-9a-f]{16})-----\n")
+VERSION = 1  # debug::crash_dump::VERSION
+FNV_OFFSET = 0xCBF29CE484222325
+FNV_PRIME = 0x100000001B3
+
+
+def fnv1a(data):
+    hash = FNV_OFFSET
+    for byte in data:
+        hash = ((hash ^ byte) * FNV_PRIME) & 0xFFFFFFFFFFFFFFFF
+    return hash
+
+
+def parse(body):
+    """Top-level items and the lines of each [section], by name."""
+    dump, section = {"sections": {}}, None
+    for line in body.decode(errors="replace").split("\n")[:-1]:
+        if line.startswith("[") and line.endswith("]"):
+            section = dump["sections"].setdefault(line[1:-1], [])
+        elif section is not None:
+            section.append(line)
+        else:
+            key, _, value = line.partition(" ")
+            dump[key] = value
+    return dump
+
+
+def dumps(log):
+    log = log.replace(b"\r\n", b"\n")
+    at = 0
+    while True:
+        begin = BEGIN.search(log, at)
+        if not begin:
+            return
+        end = END.search(log, begin.end())
+        if not end:
+            yield None, "dump at byte {} never ends".format(begin.start())
+            return
+        at = end.end()
+        body = log[begin.end():end.start()]
+        version, length, checksum = int(begin.group(1)), int(end.group(1)), int(end.group(2), 16)
+        if version != VERSION:
+            yield None, "dump v{} at byte {}: only v{} is known".format(version, begin.start(), VERSION)
+        elif len(body) != length or fnv1a(body) != checksum:
+            yield None, "dump at byte {} is corrupt".format(begin.start())
+        else:
+            yield parse(body), None
+
+
+def main(path):
+    with open(path, "rb") as f:
+        log = f.read()
+    good = 0
+    for dump, error in dumps(log):
+        if error:
+            print("skipped: " + error, file=sys.stderr)
+            continue
+        good += 1
+        print("panic {}".format(dump.get("panic", "?")))
+        print("uptime {} ms, task {}".format(dump.get("uptime_ms", "?"), dump.get("task", "?")))
+        for name, lines in dump["sections"].items():
+            print("\n[{}]".format(name))
+            for line in lines:
+                print("  " + line)
+        print()
+    if not good:
+        sys.exit("no good crash dump in {}".format(path))
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+    main(sys.argv[1])
@@ -0,0 +1,111 @@
+#!/usr/bin/env python3
+"""Fills the symbol table reserved by `debug::symbols` in a linked kernel.
+
+Usage: tools/embed_symbols.py target/x86_64/debug/Microkernel
+
+`cargo run` and `cargo test` do this through tools/runner.sh; run it by hand
+after `cargo build` and before `bootimage build`. Patching keeps every
+address unchanged, so the image stays consistent, and running it twice is
+harmless.
+"""
+
+import re
+import struct
+import sys
+
+TABLE_SYMBOL = b"KERNEL_SYMBOL_TABLE"
+MAGIC = b"KSYMTAB\0"
+CAPACITY = 192 * 1024  # debug::symbols::CAPACITY
+SHT_SYMTAB = 2
+STT_FUNC = 2
+
+ESCAPES = {
+    "$SP$": "@", "$BP$": "*", "$RF$": "&", "$LT$": "<", "$GT$": ">",
+    "$LP$": "(", "$RP$": ")", "$C$": ",",
+}
+
+
+def demangle(name):
+    """Legacy Rust mangling: _ZN<len><ident>...<len>h<hash>E."""
+    if not (name.startswith("_ZN") and name.endswith("E")):
+        return name
+    rest, parts = name[3:-1], []
+    while rest:
+        match = re.match(r"(\d+)", rest)
+        if not match:
+            return name
+        length = int(match.group(1))
+        start = len(match.group(1))
+        part = rest[start:start + length]
+        parts.append(part[1:] if part.startswith("_$") else part)
+        rest = rest[start + length:]
+    if parts and re.fullmatch(r"h[0-9a-f]{16}", parts[-1]):
+        parts.pop()
+    text = "::".join(parts)
+    for escape, char in ESCAPES.items():
+        text = text.replace(escape, char)
+    text = re.sub(r"\$u([0-9a-f]+)\$", lambda m: chr(int(m.group(1), 16)), text)
+    return text.replace("..", "::")
+
+
+def sections(elf):
+    shoff, = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum = struct.unpack_from("<HH", elf, 0x3A)
+    return [
+        struct.unpack_from("<IIQQQQIIQQ", elf, shoff + i * shentsize)
+        for i in range(shnum)
+    ]
+
+
+def symbols(elf, secs):
+    for _, kind, _, _, offset, size, link, _, _, entsize in secs:
+        if kind != SHT_SYMTAB:
+            continue
+        strtab = secs[link][4]
+        for at in range(offset, offset + size, entsize):
+            name, info, _, shndx, value, sym_size = struct.unpack_from("<IBBHQQ", elf, at)
+            end = elf.index(b"\0", strtab + name)
+            yield elf[strtab + name:end], info & 0xF, shndx, value, sym_size
+
+
+def build_table(functions):
+    entries, names = b"", b""
+    for address, size, name in functions:
+        encoded = name.encode()
+        entries += struct.pack("<QIIII", address, min(size, 0xFFFFFFFF), len(names), len(encoded), 0)
+        names += encoded
+    return MAGIC + struct.pack("<II", len(functions), 0) + entries + names
+
+
+def main(path):
+    with open(path, "rb") as f:
+        elf = bytearray(f.read())
+    if elf[:4] != b"\x7fELF" or elf[4] != 2:
+        sys.exit("{}: not a 64-bit ELF".format(path))
+    secs = sections(elf)
+
+    functions, table_offset = {}, None
+    for name, kind, shndx, value, size in symbols(elf, secs):
+        if name == TABLE_SYMBOL:
+            sec = secs[shndx]
+            table_offset = sec[4] + value - sec[3]
+        elif kind == STT_FUNC and value:
+            functions.setdefault(value, (value, size, demangle(name.decode(errors="replace"))))
+    if table_offset is None:
+        sys.exit("{}: no {} symbol".format(path, TABLE_SYMBOL.decode()))
+
+    table = build_table(sorted(functions.values()))
+    if len(table) > CAPACITY:
+        sys.exit("symbol table needs {} bytes, only {} reserved".format(len(table), CAPACITY))
+    if elf[table_offset:table_offset + len(MAGIC)] != MAGIC:
+        sys.exit("{}: table at {:#x} has no magic".format(path, table_offset))
+    elf[table_offset:table_offset + CAPACITY] = table.ljust(CAPACITY, b"\0")
+    with open(path, "wb") as f:
+        f.write(elf)
+    print("embedded {} symbols ({} bytes)".format(len(functions), len(table)))
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+    main(sys.argv[1])
@@ -0,0 +1,6 @@
+#!/bin/sh
+# The cargo runner: fills in the kernel's symbol table, then hands the
+# binary to bootimage, so `cargo run` and `cargo test` boot with names.
+set -e
+python3 "$(dirname "$0")/embed_symbols.py" "$1"
+exec bootimage runner "$@"